@@ -0,0 +1,509 @@
+//! `polirag backup` and `polirag restore`: the app state (index, config, usage ledger, last
+//! sync report) in one timestamped `.tar.zst`, and back. The scraped files are only included
+//! on request since they can take gigabytes and a sync downloads them again.
+
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::{config, rag, scrapper};
+
+/// Layout of the archive; restore refuses archives written in a newer one
+const BACKUP_FORMAT: u32 = 1;
+/// First entry of every archive
+const MANIFEST_NAME: &str = "polirag-backup.json";
+/// The state files live under this prefix, relative to the app data directory
+const STATE_DIR: &str = "state";
+/// Scraped data folder inside the app data directory
+const DATA_DIR: &str = "data";
+/// Config keys `--no-secrets` leaves out: PoliformaT login, OpenRouter key and custom headers
+const SECRET_KEYS: [&str; 3] = ["cached_credentials", "openrouter_api_key", "provider_headers"];
+/// Fast, and the embeddings barely compress at higher levels anyway
+const ZSTD_LEVEL: i32 = 3;
+
+/// What `polirag backup` puts in the archive
+pub struct BackupOptions {
+    /// Archive path, or a directory to write it in; defaults to `backups/` in the app data dir
+    pub out: Option<PathBuf>,
+    /// Keep the credentials, API key and request headers in `config.json`
+    pub secrets: bool,
+    /// Also archive the scraped files in `data/`
+    pub include_data: bool,
+}
+
+/// First entry of the archive, describing the rest
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    format: u32,
+    polirag_version: String,
+    created_at: u64,
+    /// Format version of the index inside, when it has a header
+    index_format: Option<u32>,
+    secrets: bool,
+    data: bool,
+    /// Paths relative to the app data directory, with their sizes
+    files: Vec<(String, u64)>,
+}
+
+enum Source {
+    File(PathBuf),
+    Bytes(Vec<u8>),
+}
+
+/// `polirag backup`: archive the state and print where it went
+pub fn run_backup(options: BackupOptions) -> anyhow::Result<()> {
+    backup(&config::Config::get_app_data_dir(), options).map(|_| ())
+}
+
+/// Archive the state in `app_dir`, returning the archive path
+fn backup(app_dir: &Path, options: BackupOptions) -> anyhow::Result<PathBuf> {
+    let files = state_files(app_dir, options.include_data);
+    if files.is_empty() {
+        anyhow::bail!("Nothing to back up in {}", app_dir.display());
+    }
+
+    let config_name = config_file_name();
+    let mut entries: Vec<(String, Source, u64)> = Vec::new();
+    for name in files {
+        let path = app_dir.join(&name);
+        if name == config_name && !options.secrets {
+            let bytes = without_secrets(&std::fs::read(&path)?)?;
+            let size = bytes.len() as u64;
+            entries.push((name, Source::Bytes(bytes), size));
+        } else {
+            let size = std::fs::metadata(&path)?.len();
+            entries.push((name, Source::File(path), size));
+        }
+    }
+
+    let created_at = now();
+    let manifest = Manifest {
+        format: BACKUP_FORMAT,
+        polirag_version: env!("CARGO_PKG_VERSION").to_string(),
+        created_at,
+        index_format: rag::format::read_header(&app_dir.join(index_data_name()))
+            .ok()
+            .flatten()
+            .map(|header| header.version),
+        secrets: options.secrets,
+        data: options.include_data,
+        files: entries.iter().map(|(name, _, size)| (name.clone(), *size)).collect(),
+    };
+
+    let file_name = format!("polirag-backup-{}.tar.zst", timestamp(created_at));
+    let out = match options.out {
+        Some(path) if path.is_dir() => path.join(file_name),
+        Some(path) => path,
+        None => app_dir.join("backups").join(file_name),
+    };
+    if let Some(parent) = out.parent().filter(|p| !p.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent).with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+
+    // A half-written archive never takes the final name
+    let mut partial = out.clone().into_os_string();
+    partial.push(".partial");
+    let partial = PathBuf::from(partial);
+    let written = write_archive(&partial, &manifest, entries);
+    if let Err(e) = written {
+        let _ = std::fs::remove_file(&partial);
+        return Err(e);
+    }
+    std::fs::rename(&partial, &out).with_context(|| format!("Failed to write {}", out.display()))?;
+
+    let bytes: u64 = manifest.files.iter().map(|(_, size)| size).sum();
+    println!("Backed up {} files ({:.1} MB) to {}", manifest.files.len(), bytes as f64 / 1_048_576.0, out.display());
+    if !options.secrets {
+        println!("Credentials, the OpenRouter key and request headers were left out (--no-secrets).");
+    }
+    if !options.include_data {
+        println!("Scraped files were not included; a sync downloads them again, or pass --include-data.");
+    }
+    Ok(out)
+}
+
+fn write_archive(path: &Path, manifest: &Manifest, entries: Vec<(String, Source, u64)>) -> anyhow::Result<()> {
+    let file = File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+    let mut builder = tar::Builder::new(zstd::Encoder::new(file, ZSTD_LEVEL)?);
+    append_bytes(&mut builder, MANIFEST_NAME, &serde_json::to_vec_pretty(manifest)?)?;
+    for (name, source, _) in entries {
+        let archived = format!("{}/{}", STATE_DIR, name);
+        match source {
+            Source::File(path) => builder.append_path_with_name(&path, &archived)
+                .with_context(|| format!("Failed to archive {}", path.display()))?,
+            Source::Bytes(bytes) => append_bytes(&mut builder, &archived, &bytes)?,
+        }
+    }
+    builder.into_inner()?.finish()?;
+    Ok(())
+}
+
+fn append_bytes<W: std::io::Write>(builder: &mut tar::Builder<W>, name: &str, bytes: &[u8]) -> anyhow::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(bytes.len() as u64);
+    header.set_mode(0o600);
+    header.set_mtime(now());
+    header.set_cksum();
+    builder.append_data(&mut header, name, bytes)?;
+    Ok(())
+}
+
+/// `polirag restore`: check the archive, list what changes and, unless `dry_run`, swap the
+/// restored files in. Everything is unpacked into a staging directory first, so a damaged
+/// archive leaves the current state untouched.
+pub fn run_restore(archive: &Path, dry_run: bool) -> anyhow::Result<()> {
+    restore(&config::Config::get_app_data_dir(), archive, dry_run)
+}
+
+fn restore(app_dir: &Path, archive: &Path, dry_run: bool) -> anyhow::Result<()> {
+    // Inside the app data dir so the swap is a rename on the same filesystem
+    let staging = tempfile::Builder::new().prefix(".restore-").tempdir_in(app_dir)?;
+    let manifest = unpack(archive, staging.path())?;
+    for (name, size) in &manifest.files {
+        let staged = std::fs::metadata(staging.path().join(name)).map(|m| m.len()).ok();
+        if staged != Some(*size) {
+            anyhow::bail!("The archive is incomplete: {} is missing or truncated", name);
+        }
+    }
+
+    let restored: BTreeSet<String> = manifest.files.iter().map(|(name, _)| name.clone()).collect();
+    let current: BTreeSet<String> = state_files(app_dir, manifest.data).into_iter().collect();
+    let replaced: Vec<&String> = restored.intersection(&current).collect();
+    let added: Vec<&String> = restored.difference(&current).collect();
+    let removed: Vec<&String> = current.difference(&restored).collect();
+
+    println!(
+        "Backup of {} UTC by polirag {}: {} files{}{}",
+        format_time(manifest.created_at),
+        manifest.polirag_version,
+        manifest.files.len(),
+        if manifest.secrets { "" } else { ", without secrets (the current ones are kept)" },
+        if manifest.data { ", with scraped data" } else { "" },
+    );
+    print_changes("Overwritten", &replaced);
+    print_changes("Added", &added);
+    print_changes("Removed", &removed);
+    if !manifest.data {
+        println!("Scraped data in {}/ is left as it is.", DATA_DIR);
+    }
+    if dry_run {
+        println!("Dry run: nothing was changed.");
+        return Ok(());
+    }
+
+    let config_name = config_file_name();
+    if !manifest.secrets && restored.contains(&config_name) {
+        keep_current_secrets(&staging.path().join(&config_name), &app_dir.join(&config_name))?;
+    }
+
+    // Swapped as whole units: each top-level file, and the data folder when the archive has it
+    let top_level = |names: &[&String]| -> BTreeSet<String> {
+        names.iter()
+            .map(|name| if name.starts_with(&format!("{}/", DATA_DIR)) { DATA_DIR.to_string() } else { name.to_string() })
+            .collect()
+    };
+    let incoming = top_level(&restored.iter().collect::<Vec<_>>());
+    let stale = top_level(&removed);
+    swap(app_dir, staging.path(), &incoming, &stale)?;
+    println!("Restored {} files. Restart polirag if it is running.", manifest.files.len());
+    Ok(())
+}
+
+/// Move the current `incoming` and `stale` entries aside, then the staged `incoming` ones in.
+/// On failure everything moved so far is put back.
+fn swap(app_dir: &Path, staged: &Path, incoming: &BTreeSet<String>, stale: &BTreeSet<String>) -> anyhow::Result<()> {
+    let previous = tempfile::Builder::new().prefix(".restore-previous-").tempdir_in(app_dir)?;
+    let mut moved_aside: Vec<&String> = Vec::new();
+    let mut moved_in: Vec<&String> = Vec::new();
+    let result = (|| -> anyhow::Result<()> {
+        for name in incoming.iter().chain(stale) {
+            let live = app_dir.join(name);
+            if live.exists() {
+                std::fs::rename(&live, previous.path().join(name)).with_context(|| format!("Failed to move {} aside", live.display()))?;
+                moved_aside.push(name);
+            }
+        }
+        for name in incoming {
+            std::fs::rename(staged.join(name), app_dir.join(name)).with_context(|| format!("Failed to restore {}", name))?;
+            moved_in.push(name);
+        }
+        Ok(())
+    })();
+
+    if let Err(e) = result {
+        for name in moved_in {
+            let _ = std::fs::rename(app_dir.join(name), staged.join(name));
+        }
+        for name in moved_aside {
+            let _ = std::fs::rename(previous.path().join(name), app_dir.join(name));
+        }
+        return Err(e.context("Restore failed, the previous state was put back"));
+    }
+    Ok(())
+}
+
+/// Unpack `archive` into `into`, checking the manifest before anything else is extracted
+fn unpack(archive: &Path, into: &Path) -> anyhow::Result<Manifest> {
+    let file = File::open(archive).with_context(|| format!("Failed to open {}", archive.display()))?;
+    let mut tar = tar::Archive::new(zstd::Decoder::new(file)?);
+    let mut manifest: Option<Manifest> = None;
+    for entry in tar.entries().context("Not a polirag backup (expected a .tar.zst archive)")? {
+        let mut entry = entry.context("The archive is damaged")?;
+        let path = entry.path()?.into_owned();
+
+        let Some(manifest) = &manifest else {
+            if path != Path::new(MANIFEST_NAME) {
+                anyhow::bail!("Not a polirag backup: it doesn't start with {}", MANIFEST_NAME);
+            }
+            let mut text = String::new();
+            entry.read_to_string(&mut text)?;
+            let read: Manifest = serde_json::from_str(&text).context("The backup manifest is unreadable")?;
+            check_compatible(&read)?;
+            if let Some((name, _)) = read.files.iter().find(|(name, _)| !is_safe(Path::new(name))) {
+                anyhow::bail!("Refusing to restore {}: it points outside the app data directory", name);
+            }
+            manifest = Some(read);
+            continue;
+        };
+
+        let relative = path.strip_prefix(STATE_DIR).ok().filter(|p| is_safe(p));
+        let Some(relative) = relative.filter(|p| manifest.files.iter().any(|(name, _)| Path::new(name) == *p)) else {
+            anyhow::bail!("Unexpected entry {} in the archive", path.display());
+        };
+        if !entry.header().entry_type().is_file() {
+            anyhow::bail!("Unexpected entry {} in the archive: not a regular file", path.display());
+        }
+        let target = into.join(relative);
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        entry.unpack(&target).with_context(|| format!("Failed to unpack {}", path.display()))?;
+    }
+    manifest.ok_or_else(|| anyhow::anyhow!("Not a polirag backup: the archive is empty"))
+}
+
+fn check_compatible(manifest: &Manifest) -> anyhow::Result<()> {
+    if manifest.format > BACKUP_FORMAT {
+        anyhow::bail!(
+            "This backup was written by polirag {} in backup format {}, and this version reads up to format {}. Update polirag to restore it.",
+            manifest.polirag_version, manifest.format, BACKUP_FORMAT
+        );
+    }
+    if let Some(version) = manifest.index_format.filter(|v| *v > rag::format::FORMAT_VERSION) {
+        anyhow::bail!(
+            "The index in this backup uses format v{}, and this version reads up to v{}. Update polirag to restore it.",
+            version, rag::format::FORMAT_VERSION
+        );
+    }
+    Ok(())
+}
+
+/// Only plain relative components, so no entry lands outside the staging directory
+fn is_safe(path: &Path) -> bool {
+    path.components().next().is_some() && path.components().all(|c| matches!(c, Component::Normal(_)))
+}
+
+/// Files making up the state, relative to `app_dir` with `/` separators: the top-level files
+/// except the log, temporary files and moved-aside copies, plus `data/` with `include_data`
+fn state_files(app_dir: &Path, include_data: bool) -> Vec<String> {
+    let mut files: Vec<String> = std::fs::read_dir(app_dir).into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| entry.path().is_file())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .filter(|name| {
+            name != "debug.log" && !name.starts_with('.')
+                && !name.ends_with(".tmp") && !name.ends_with(".partial") && !name.ends_with(".bak") && !name.contains(".broken-")
+        })
+        .collect();
+
+    let data_dir = app_dir.join(DATA_DIR);
+    if include_data && data_dir.is_dir() {
+        for entry in walkdir::WalkDir::new(&data_dir).into_iter().filter_map(|e| e.ok()) {
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            if let Ok(relative) = entry.path().strip_prefix(app_dir) {
+                let parts: Vec<String> = relative.components().map(|c| c.as_os_str().to_string_lossy().to_string()).collect();
+                files.push(parts.join("/"));
+            }
+        }
+    }
+    files.sort();
+    files
+}
+
+/// File name of the index's document data, which carries the format header
+fn index_data_name() -> PathBuf {
+    config::Config::get_index_path().with_extension("data").file_name().map(PathBuf::from).unwrap_or_default()
+}
+
+fn config_file_name() -> String {
+    config::Config::config_path()
+        .and_then(|path| path.file_name().map(|name| name.to_string_lossy().to_string()))
+        .unwrap_or_else(|| "config.json".to_string())
+}
+
+fn without_secrets(config: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut value: serde_json::Value = serde_json::from_slice(config).context("config.json is not valid JSON")?;
+    if let Some(object) = value.as_object_mut() {
+        for key in SECRET_KEYS {
+            object.remove(key);
+        }
+    }
+    Ok(serde_json::to_vec_pretty(&value)?)
+}
+
+/// A backup made with `--no-secrets` keeps the credentials of the config it replaces
+fn keep_current_secrets(restored: &Path, current: &Path) -> anyhow::Result<()> {
+    let Some(current) = std::fs::read(current).ok().and_then(|bytes| serde_json::from_slice::<serde_json::Value>(&bytes).ok()) else {
+        return Ok(());
+    };
+    let mut value: serde_json::Value = serde_json::from_slice(&std::fs::read(restored)?).context("The restored config.json is not valid JSON")?;
+    if let Some(object) = value.as_object_mut() {
+        for key in SECRET_KEYS {
+            if let Some(secret) = current.get(key) {
+                object.insert(key.to_string(), secret.clone());
+            }
+        }
+    }
+    std::fs::write(restored, serde_json::to_vec_pretty(&value)?)?;
+    Ok(())
+}
+
+fn print_changes(label: &str, names: &[&String]) {
+    if names.is_empty() {
+        return;
+    }
+    let (data, top): (Vec<&&String>, Vec<&&String>) = names.iter().partition(|name| name.starts_with(&format!("{}/", DATA_DIR)));
+    println!("{}:", label);
+    for name in top {
+        println!("  {}", name);
+    }
+    if !data.is_empty() {
+        println!("  {} files in {}/", data.len(), DATA_DIR);
+    }
+}
+
+fn now() -> u64 {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// "2025-03-14 09:30"
+fn format_time(at: u64) -> String {
+    format!("{} {:02}:{:02}", scrapper::processing::format_date(at / 86_400), at % 86_400 / 3600, at % 3600 / 60)
+}
+
+/// "2025-03-14-093000" for file names
+fn timestamp(at: u64) -> String {
+    format!("{}-{:02}{:02}{:02}", scrapper::processing::format_date(at / 86_400), at % 86_400 / 3600, at % 3600 / 60, at % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"{"openrouter_api_key":"sk-old","top_k":7}"#;
+
+    /// An app data directory with a config, an index, the ledger, a log and scraped files
+    fn fixture(app_dir: &Path) {
+        let write = |name: &str, contents: &str| {
+            let path = app_dir.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        };
+        write("config.json", CONFIG);
+        write("polirag.data", "documents");
+        write("polirag.hnsw.graph", "graph");
+        write("usage.json", "{}");
+        write("debug.log", "log lines");
+        write("polirag.data.v0.bak", "old index");
+        write("data/Algebra/resources/Tema 1.pdf", "%PDF-1");
+    }
+
+    fn read(app_dir: &Path, name: &str) -> Option<String> {
+        std::fs::read_to_string(app_dir.join(name)).ok()
+    }
+
+    fn json(app_dir: &Path, name: &str) -> serde_json::Value {
+        serde_json::from_str(&read(app_dir, name).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn state_files_skip_logs_backups_and_data() {
+        let dir = tempfile::tempdir().unwrap();
+        fixture(dir.path());
+        assert_eq!(state_files(dir.path(), false), ["config.json", "polirag.data", "polirag.hnsw.graph", "usage.json"]);
+        assert!(state_files(dir.path(), true).contains(&"data/Algebra/resources/Tema 1.pdf".to_string()));
+    }
+
+    #[test]
+    fn restore_brings_back_the_backed_up_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let app_dir = dir.path().join("polirag");
+        let out = dir.path().join("backups");
+        std::fs::create_dir_all(&out).unwrap();
+        fixture(&app_dir);
+        let archive = backup(&app_dir, BackupOptions { out: Some(out), secrets: false, include_data: true }).unwrap();
+
+        // The state moves on after the backup
+        std::fs::write(app_dir.join("config.json"), r#"{"openrouter_api_key":"sk-new","top_k":3}"#).unwrap();
+        std::fs::write(app_dir.join("polirag.data"), "reindexed").unwrap();
+        std::fs::remove_file(app_dir.join("usage.json")).unwrap();
+        std::fs::write(app_dir.join("sync_report.json"), "{}").unwrap();
+        std::fs::write(app_dir.join("data/Algebra/resources/Tema 2.pdf"), "%PDF-2").unwrap();
+
+        restore(&app_dir, &archive, true).unwrap();
+        assert_eq!(read(&app_dir, "polirag.data").as_deref(), Some("reindexed"));
+
+        restore(&app_dir, &archive, false).unwrap();
+        assert_eq!(read(&app_dir, "polirag.data").as_deref(), Some("documents"));
+        assert_eq!(read(&app_dir, "polirag.hnsw.graph").as_deref(), Some("graph"));
+        assert_eq!(read(&app_dir, "usage.json").as_deref(), Some("{}"));
+        assert_eq!(read(&app_dir, "data/Algebra/resources/Tema 1.pdf").as_deref(), Some("%PDF-1"));
+        // Files the backup didn't have are gone, the ones it never covers are left alone
+        assert_eq!(read(&app_dir, "sync_report.json"), None);
+        assert_eq!(read(&app_dir, "data/Algebra/resources/Tema 2.pdf"), None);
+        assert_eq!(read(&app_dir, "debug.log").as_deref(), Some("log lines"));
+        assert_eq!(read(&app_dir, "polirag.data.v0.bak").as_deref(), Some("old index"));
+        // Settings come from the backup, the key it left out from the replaced config
+        let config = json(&app_dir, "config.json");
+        assert_eq!(config["top_k"], 7);
+        assert_eq!(config["openrouter_api_key"], "sk-new");
+        // No staging directories are left behind
+        assert!(std::fs::read_dir(&app_dir).unwrap().flatten().all(|e| !e.file_name().to_string_lossy().starts_with(".restore")));
+    }
+
+    #[test]
+    fn backups_with_secrets_restore_them() {
+        let dir = tempfile::tempdir().unwrap();
+        fixture(dir.path());
+        let archive = backup(dir.path(), BackupOptions { out: Some(dir.path().join("all.tar.zst")), secrets: true, include_data: false }).unwrap();
+        std::fs::write(dir.path().join("config.json"), r#"{"openrouter_api_key":"sk-new"}"#).unwrap();
+
+        restore(dir.path(), &archive, false).unwrap();
+        assert_eq!(json(dir.path(), "config.json")["openrouter_api_key"], "sk-old");
+        // Without --include-data the scraped files are kept
+        assert_eq!(read(dir.path(), "data/Algebra/resources/Tema 1.pdf").as_deref(), Some("%PDF-1"));
+    }
+
+    #[test]
+    fn newer_backup_formats_are_refused() {
+        let manifest = Manifest {
+            format: BACKUP_FORMAT + 1,
+            polirag_version: "9.0.0".to_string(),
+            created_at: 0,
+            index_format: None,
+            secrets: false,
+            data: false,
+            files: Vec::new(),
+        };
+        assert!(check_compatible(&manifest).is_err());
+        assert!(!is_safe(Path::new("../config.json")));
+        assert!(!is_safe(Path::new("/etc/passwd")));
+    }
+}
@@ -0,0 +1,562 @@
+//! `polirag clean`: reclaim disk space in the scraped data directory. Lists what each target
+//! would free and only deletes with `--apply`. Files the index still refers to are only deleted
+//! together with their documents (`--prune-index`); otherwise they are left in place.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::config::Config;
+use crate::rag::RagSystem;
+use crate::scrapper::{changes::SyncReport, format_bytes, layout};
+
+/// Browser download leftovers, as `wait_for_downloads` recognizes them
+const PARTIAL_SUFFIXES: [&str; 3] = [".crdownload", ".part", ".tmp"];
+/// Page the scraper prints `summary.pdf` from, left behind when the print fails midway
+const TEMP_SUMMARY: &str = "temp_summary.html";
+/// Age of the extraction caches `CleanTarget::Extracted` removes when none is given
+pub const DEFAULT_OLDER_THAN_DAYS: u64 = 90;
+/// Extensions the indexer reads from the resources, see `processing::process_resources`
+const INDEXED_EXTENSIONS: [&str; 3] = ["pdf", "srt", "vtt"];
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CleanTarget {
+    /// Downloaded zips whose contents are extracted and indexed
+    Zips,
+    /// `resources/extracted/<zip>` folders not changed for `older_than_days`, with their zip
+    Extracted,
+    /// Unfinished downloads and the temporary summary page
+    Leftovers,
+    /// Folders of subjects the last sync no longer listed
+    Orphans,
+}
+
+impl CleanTarget {
+    pub const ALL: [CleanTarget; 4] = [CleanTarget::Orphans, CleanTarget::Extracted, CleanTarget::Zips, CleanTarget::Leftovers];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            CleanTarget::Zips => "Extracted zips",
+            CleanTarget::Extracted => "Old extraction caches",
+            CleanTarget::Leftovers => "Download leftovers",
+            CleanTarget::Orphans => "Orphaned subjects",
+        }
+    }
+}
+
+/// What `polirag clean` looks at and whether it deletes
+pub struct CleanOptions {
+    /// Empty for every target
+    pub targets: Vec<CleanTarget>,
+    /// Age of the extraction caches `CleanTarget::Extracted` removes
+    pub older_than_days: u64,
+    pub apply: bool,
+    /// Also remove the indexed documents of deleted files; without it those files are kept
+    pub prune_index: bool,
+}
+
+/// Something `polirag clean` can delete
+pub struct Candidate {
+    pub target: CleanTarget,
+    /// Subject name
+    pub subject: String,
+    /// Subject folder
+    pub dir: PathBuf,
+    /// Files and folders to delete
+    pub paths: Vec<PathBuf>,
+    pub bytes: u64,
+    /// Base ids of the indexed documents read from these files
+    pub indexed: Vec<String>,
+    pub reason: String,
+}
+
+impl Candidate {
+    /// "Bases de Datos: resources/Recursos.zip (12.3 MB), contents extracted"
+    pub fn describe(&self) -> String {
+        let paths: Vec<String> = self.paths.iter().map(|p| layout::relative_id(p, &self.dir)).collect();
+        let what = match paths.as_slice() {
+            [path] if path.is_empty() => "whole folder".to_string(),
+            [path] => path.clone(),
+            paths => format!("{} files", paths.len()),
+        };
+        format!("{}: {} ({}), {}", self.subject, what, format_bytes(self.bytes), self.reason)
+    }
+}
+
+/// Candidates of every selected target, and what could not be checked
+#[derive(Default)]
+pub struct CleanPlan {
+    pub candidates: Vec<Candidate>,
+    pub notes: Vec<String>,
+}
+
+impl CleanPlan {
+    pub fn bytes(&self, target: CleanTarget) -> u64 {
+        self.candidates.iter().filter(|c| c.target == target).map(|c| c.bytes).sum()
+    }
+
+    pub fn total_bytes(&self) -> u64 {
+        self.candidates.iter().map(|c| c.bytes).sum()
+    }
+}
+
+/// What `apply` did
+#[derive(Default)]
+pub struct CleanOutcome {
+    pub bytes: u64,
+    pub deleted: usize,
+    /// Candidates left in place because the index refers to them and pruning was off
+    pub kept_indexed: usize,
+    pub pruned_documents: usize,
+    pub errors: Vec<String>,
+}
+
+impl CleanOutcome {
+    pub fn describe(&self) -> String {
+        let mut out = format!("Freed {} ({} items)", format_bytes(self.bytes), self.deleted);
+        if self.pruned_documents > 0 {
+            out.push_str(&format!(", removed {} documents from the index", self.pruned_documents));
+        }
+        if self.kept_indexed > 0 {
+            out.push_str(&format!(", kept {} still indexed", self.kept_indexed));
+        }
+        if !self.errors.is_empty() {
+            out.push_str(&format!(", {} failed", self.errors.len()));
+        }
+        out
+    }
+}
+
+/// Work out what the selected targets would delete, without touching anything
+pub fn plan(rag: &RagSystem, options: &CleanOptions) -> CleanPlan {
+    let enrolled: Option<HashSet<String>> = SyncReport::load_last()
+        .map(|report| report.enrolled.into_iter().collect::<HashSet<_>>())
+        .filter(|ids| !ids.is_empty());
+    plan_in(&Config::get_scraped_data_dir(), enrolled, options, &|id| (rag.indexed_files(id), rag.contains(id)))
+}
+
+/// `plan` over the subject folders of `data_dir`. `enrolled` are the site ids of the last
+/// sync; `index` gives a subject's indexed files and whether its summary is indexed.
+fn plan_in(data_dir: &Path, enrolled: Option<HashSet<String>>, options: &CleanOptions, index: &dyn Fn(&str) -> (HashSet<String>, bool)) -> CleanPlan {
+    let targets = selected(&options.targets);
+    let mut plan = CleanPlan::default();
+    let Ok(entries) = std::fs::read_dir(data_dir) else { return plan };
+
+    if targets.contains(&CleanTarget::Orphans) && enrolled.is_none() {
+        plan.notes.push("No sync has recorded the enrolled subjects yet; sync once to find orphaned folders".to_string());
+    }
+
+    let mut legacy = 0;
+    let mut dirs: Vec<PathBuf> = entries.flatten()
+        .filter(|e| e.path().is_dir() && !e.file_name().to_string_lossy().starts_with('.'))
+        .map(|e| e.path())
+        .collect();
+    dirs.sort();
+    for dir in dirs {
+        // Without the site id the documents of the folder can't be told apart
+        let Some(sub) = layout::read_meta(&dir) else {
+            legacy += 1;
+            continue;
+        };
+        let (indexed, summary_indexed) = index(&sub.id);
+
+        if targets.contains(&CleanTarget::Orphans) && enrolled.as_ref().is_some_and(|ids| !ids.contains(&sub.id)) {
+            let mut documents: Vec<String> = indexed.into_iter().collect();
+            documents.sort();
+            if summary_indexed {
+                documents.insert(0, sub.id.clone());
+            }
+            plan.candidates.push(Candidate {
+                target: CleanTarget::Orphans,
+                subject: sub.name,
+                bytes: size_of(&dir),
+                paths: vec![dir.clone()],
+                dir,
+                indexed: documents,
+                reason: "not enrolled in the last sync".to_string(),
+            });
+            continue;
+        }
+
+        let resources = dir.join("resources");
+        let mut covered_zips = HashSet::new();
+        if targets.contains(&CleanTarget::Extracted) {
+            let max_age = Duration::from_secs(options.older_than_days * 86_400);
+            for cache in subdirs(&resources.join("extracted")) {
+                let Some(age) = newest_change(&cache).and_then(|t| t.elapsed().ok()) else { continue };
+                if age < max_age {
+                    continue;
+                }
+                let stem = cache.file_name().unwrap_or_default().to_string_lossy().to_string();
+                let mut paths = vec![cache.clone()];
+                let zip = resources.join(format!("{}.zip", stem));
+                if zip.is_file() {
+                    // The next sync would extract it again
+                    paths.push(zip);
+                    covered_zips.insert(stem);
+                }
+                plan.candidates.push(Candidate {
+                    target: CleanTarget::Extracted,
+                    subject: sub.name.clone(),
+                    bytes: paths.iter().map(|p| size_of(p)).sum(),
+                    indexed: indexed_under(&cache, &dir, &sub.id, &indexed),
+                    paths,
+                    dir: dir.clone(),
+                    reason: format!("unchanged for {} days", age.as_secs() / 86_400),
+                });
+            }
+        }
+
+        if targets.contains(&CleanTarget::Zips) {
+            for zip in files_with_extension(&resources, "zip") {
+                let stem = zip.file_stem().unwrap_or_default().to_string_lossy().to_string();
+                if covered_zips.contains(&stem) {
+                    continue;
+                }
+                let extracted = resources.join("extracted").join(&stem);
+                if !fully_extracted(&zip, &extracted) {
+                    continue;
+                }
+                let readable: Vec<String> = walkdir::WalkDir::new(&extracted).into_iter()
+                    .filter_map(|e| e.ok())
+                    .filter(|e| e.file_type().is_file() && is_indexed_type(e.path()))
+                    .map(|e| format!("{}/{}", sub.id, layout::relative_id(e.path(), &dir)))
+                    .collect();
+                // Not indexed yet: the zip stays until a sync has read what came out of it
+                if !readable.is_empty() && !readable.iter().any(|id| indexed.contains(id)) {
+                    continue;
+                }
+                plan.candidates.push(Candidate {
+                    target: CleanTarget::Zips,
+                    subject: sub.name.clone(),
+                    bytes: size_of(&zip),
+                    paths: vec![zip],
+                    dir: dir.clone(),
+                    indexed: Vec::new(),
+                    reason: "contents extracted and indexed".to_string(),
+                });
+            }
+        }
+
+        if targets.contains(&CleanTarget::Leftovers) {
+            let paths: Vec<PathBuf> = walkdir::WalkDir::new(&dir).into_iter()
+                .filter_map(|e| e.ok())
+                .filter(|e| e.file_type().is_file())
+                .filter(|e| {
+                    let name = e.file_name().to_string_lossy();
+                    (e.depth() == 1 && name == TEMP_SUMMARY) || PARTIAL_SUFFIXES.iter().any(|s| name.ends_with(s))
+                })
+                .map(|e| e.into_path())
+                .collect();
+            if !paths.is_empty() {
+                plan.candidates.push(Candidate {
+                    target: CleanTarget::Leftovers,
+                    subject: sub.name.clone(),
+                    bytes: paths.iter().map(|p| size_of(p)).sum(),
+                    paths,
+                    dir: dir.clone(),
+                    indexed: Vec::new(),
+                    reason: "unfinished downloads".to_string(),
+                });
+            }
+        }
+    }
+    if legacy > 0 {
+        plan.notes.push(format!("Skipped {} folders in the old name-based layout; a sync migrates them", legacy));
+    }
+    plan
+}
+
+/// Delete the candidates of `plan`. Ones the index refers to are only deleted with
+/// `prune_index`, after their documents are removed. Zips and caches are recorded in the
+/// subject's `CleanedRecord` so the next sync doesn't download them again.
+pub fn apply(rag: &RagSystem, plan: &CleanPlan, prune_index: bool) -> anyhow::Result<CleanOutcome> {
+    let batch = rag.begin_batch();
+    let outcome = apply_with(plan, prune_index, &mut |id| rag.remove_document_family(id))?;
+    batch.commit()?;
+    Ok(outcome)
+}
+
+/// `apply`, removing indexed documents with `remove_family`
+fn apply_with(plan: &CleanPlan, prune_index: bool, remove_family: &mut dyn FnMut(&str) -> anyhow::Result<usize>) -> anyhow::Result<CleanOutcome> {
+    let mut outcome = CleanOutcome::default();
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+
+    for candidate in &plan.candidates {
+        if !candidate.indexed.is_empty() {
+            if !prune_index {
+                outcome.kept_indexed += 1;
+                continue;
+            }
+            for id in &candidate.indexed {
+                outcome.pruned_documents += remove_family(id)?;
+            }
+        }
+
+        let mut removed = Vec::new();
+        for path in &candidate.paths {
+            let size = size_of(path);
+            let result = if path.is_dir() { std::fs::remove_dir_all(path) } else { std::fs::remove_file(path) };
+            match result {
+                Ok(()) => {
+                    outcome.bytes += size;
+                    removed.push((layout::relative_id(path, &candidate.dir), size));
+                }
+                Err(e) => outcome.errors.push(format!("{}: {}", path.display(), e)),
+            }
+        }
+        if !removed.is_empty() {
+            outcome.deleted += 1;
+        }
+        if matches!(candidate.target, CleanTarget::Zips | CleanTarget::Extracted) && !removed.is_empty() {
+            if let Err(e) = layout::record_cleaned(&candidate.dir, removed, now) {
+                outcome.errors.push(format!("{}: {}", candidate.dir.join(layout::CLEANED_FILE).display(), e));
+            }
+        }
+    }
+    Ok(outcome)
+}
+
+/// `polirag clean`: print what each target would free, and delete it with `--apply`
+pub fn run_clean(rag: &RagSystem, options: CleanOptions) -> anyhow::Result<()> {
+    let targets = selected(&options.targets);
+    let plan = plan(rag, &options);
+
+    for target in &targets {
+        let candidates: Vec<&Candidate> = plan.candidates.iter().filter(|c| c.target == *target).collect();
+        println!("{} ({}):", target.label(), format_bytes(plan.bytes(*target)));
+        if candidates.is_empty() {
+            println!("  nothing to clean");
+        }
+        for candidate in candidates {
+            let indexed = match candidate.indexed.len() {
+                0 => String::new(),
+                n => format!(" [{} indexed files]", n),
+            };
+            println!("  - {}{}", candidate.describe(), indexed);
+        }
+    }
+    for note in &plan.notes {
+        println!("Note: {}", note);
+    }
+    println!("Reclaimable: {}", format_bytes(plan.total_bytes()));
+
+    let indexed = plan.candidates.iter().filter(|c| !c.indexed.is_empty()).count();
+    if !options.apply {
+        println!("Dry run, nothing was deleted. Pass --apply to delete.");
+        if indexed > 0 {
+            println!("{} of them hold indexed files: they are kept unless --prune-index also removes their documents.", indexed);
+        }
+        return Ok(());
+    }
+
+    let outcome = apply(rag, &plan, options.prune_index)?;
+    for error in &outcome.errors {
+        eprintln!("Failed to delete {}", error);
+    }
+    println!("{}.", outcome.describe());
+    if outcome.kept_indexed > 0 {
+        println!("Pass --prune-index to also delete them and remove their documents from the index.");
+    }
+    Ok(())
+}
+
+/// The targets to look at, every one when none was chosen
+pub fn selected(targets: &[CleanTarget]) -> Vec<CleanTarget> {
+    CleanTarget::ALL.into_iter().filter(|t| targets.is_empty() || targets.contains(t)).collect()
+}
+
+/// Bytes of a file, or of every file under a folder
+fn size_of(path: &Path) -> u64 {
+    walkdir::WalkDir::new(path).into_iter()
+        .filter_map(|e| e.ok())
+        .filter_map(|e| e.metadata().ok())
+        .filter(|m| m.is_file())
+        .map(|m| m.len())
+        .sum()
+}
+
+fn subdirs(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else { return Vec::new() };
+    entries.flatten().map(|e| e.path()).filter(|p| p.is_dir()).collect()
+}
+
+fn files_with_extension(dir: &Path, extension: &str) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else { return Vec::new() };
+    entries.flatten().map(|e| e.path()).filter(|p| p.is_file() && p.extension().is_some_and(|e| e == extension)).collect()
+}
+
+/// Latest modification time of the files under `dir`
+fn newest_change(dir: &Path) -> Option<SystemTime> {
+    walkdir::WalkDir::new(dir).into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .filter_map(|e| e.metadata().ok()?.modified().ok())
+        .max()
+}
+
+fn is_indexed_type(path: &Path) -> bool {
+    path.extension().is_some_and(|e| INDEXED_EXTENSIONS.iter().any(|x| e.eq_ignore_ascii_case(x)))
+}
+
+/// Base ids of the indexed documents read from files under `folder`
+fn indexed_under(folder: &Path, subject_dir: &Path, site_id: &str, indexed: &HashSet<String>) -> Vec<String> {
+    let prefix = format!("{}/{}/", site_id, layout::relative_id(folder, subject_dir));
+    let mut ids: Vec<String> = indexed.iter().filter(|id| id.starts_with(&prefix)).cloned().collect();
+    ids.sort();
+    ids
+}
+
+/// Every file of `zip` is in `extracted` with its size. Files the download limits left out
+/// are missing, so such a zip is kept: a later sync with other limits still needs it.
+fn fully_extracted(zip: &Path, extracted: &Path) -> bool {
+    let Ok(file) = std::fs::File::open(zip) else { return false };
+    let Ok(mut archive) = zip::ZipArchive::new(file) else { return false };
+    (0..archive.len()).all(|i| {
+        let Ok(entry) = archive.by_index(i) else { return false };
+        let Some(name) = entry.enclosed_name() else { return true };
+        entry.is_dir() || std::fs::metadata(extracted.join(name)).is_ok_and(|m| m.len() == entry.size())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+    use std::io::Write;
+    use crate::scrapper::Subject;
+
+    fn write(path: &Path, contents: &str) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+
+    fn write_zip(path: &Path, files: &[(&str, &str)]) {
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(path).unwrap());
+        for (name, contents) in files {
+            zip.start_file(*name, zip::write::FileOptions::default()).unwrap();
+            zip.write_all(contents.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    fn subject(data_dir: &Path, id: &str, name: &str) -> PathBuf {
+        let dir = data_dir.join(id);
+        std::fs::create_dir_all(&dir).unwrap();
+        layout::write_meta(&dir, &Subject { id: id.to_string(), name: name.to_string(), url: String::new() }).unwrap();
+        dir
+    }
+
+    /// Two subjects, one no longer enrolled, and a folder in the old layout
+    fn fixture(data_dir: &Path) {
+        let algebra = subject(data_dir, "GRA_1", "Algebra");
+        let resources = algebra.join("resources");
+        write(&algebra.join("summary.md"), "# Algebra");
+        write(&algebra.join(TEMP_SUMMARY), "<html>");
+        write(&resources.join("video.mp4.part"), "half a video");
+        write(&resources.join("notes").join(TEMP_SUMMARY), "not the scraper's");
+        // Extracted and indexed
+        write(&resources.join("extracted/Recursos/a.pdf"), "pdf-a");
+        write_zip(&resources.join("Recursos.zip"), &[("a.pdf", "pdf-a")]);
+        // The download limits left a file out
+        write(&resources.join("extracted/Parcial/b.pdf"), "pdf-b");
+        write_zip(&resources.join("Parcial.zip"), &[("b.pdf", "pdf-b"), ("c.pdf", "pdf-c")]);
+        // Extracted but not indexed yet
+        write(&resources.join("extracted/Nuevo/d.pdf"), "pdf-d");
+        write_zip(&resources.join("Nuevo.zip"), &[("d.pdf", "pdf-d")]);
+        // An old cache and a recent one
+        write(&resources.join("extracted/Antiguo/e.srt"), "subtitles");
+        write_zip(&resources.join("Antiguo.zip"), &[("e.srt", "subtitles")]);
+        let old = SystemTime::now() - Duration::from_secs(200 * 86_400);
+        std::fs::File::options().write(true).open(resources.join("extracted/Antiguo/e.srt")).unwrap().set_modified(old).unwrap();
+        write(&resources.join("extracted/Reciente/f.pdf"), "pdf-f");
+
+        let dropped = subject(data_dir, "GRA_2", "Historia");
+        write(&dropped.join("resources/x.pdf"), "pdf-x");
+
+        write(&data_dir.join("Old Subject/resources/y.pdf"), "pdf-y");
+    }
+
+    fn index(id: &str) -> (HashSet<String>, bool) {
+        let files: &[&str] = match id {
+            "GRA_1" => &["GRA_1/resources/extracted/Recursos/a.pdf", "GRA_1/resources/extracted/Reciente/f.pdf"],
+            "GRA_2" => &["GRA_2/resources/x.pdf"],
+            _ => &[],
+        };
+        (files.iter().map(|f| f.to_string()).collect(), id == "GRA_2")
+    }
+
+    fn options(prune_index: bool) -> CleanOptions {
+        CleanOptions { targets: Vec::new(), older_than_days: DEFAULT_OLDER_THAN_DAYS, apply: true, prune_index }
+    }
+
+    fn enrolled() -> Option<HashSet<String>> {
+        Some(HashSet::from(["GRA_1".to_string()]))
+    }
+
+    fn files(data_dir: &Path) -> BTreeSet<String> {
+        walkdir::WalkDir::new(data_dir).into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+            .map(|e| layout::relative_id(e.path(), data_dir))
+            .collect()
+    }
+
+    #[test]
+    fn clean_deletes_exactly_the_reclaimable_files() {
+        let dir = tempfile::tempdir().unwrap();
+        fixture(dir.path());
+        let before = files(dir.path());
+
+        let plan = plan_in(dir.path(), enrolled(), &options(false), &index);
+        assert_eq!(plan.notes.len(), 1, "{:?}", plan.notes);
+        let outcome = apply_with(&plan, false, &mut |id| panic!("{} was pruned without --prune-index", id)).unwrap();
+        assert_eq!(outcome.kept_indexed, 1);
+        assert!(outcome.errors.is_empty(), "{:?}", outcome.errors);
+
+        let after = files(dir.path());
+        let deleted: BTreeSet<&str> = before.difference(&after).map(String::as_str).collect();
+        assert_eq!(deleted, BTreeSet::from([
+            "GRA_1/temp_summary.html",
+            "GRA_1/resources/video.mp4.part",
+            "GRA_1/resources/Recursos.zip",
+            "GRA_1/resources/Antiguo.zip",
+            "GRA_1/resources/extracted/Antiguo/e.srt",
+        ]));
+        let added: Vec<&String> = after.difference(&before).collect();
+        assert_eq!(added, ["GRA_1/cleaned.json"]);
+
+        // The next sync takes the removed archives as already downloaded
+        let cleaned = layout::read_cleaned(&dir.path().join("GRA_1"));
+        let recorded: Vec<&str> = cleaned.files.keys().map(String::as_str).collect();
+        assert_eq!(recorded, ["resources/Antiguo.zip", "resources/Recursos.zip", "resources/extracted/Antiguo"]);
+    }
+
+    #[test]
+    fn prune_index_deletes_indexed_folders_with_their_documents() {
+        let dir = tempfile::tempdir().unwrap();
+        fixture(dir.path());
+        let options = CleanOptions { targets: vec![CleanTarget::Orphans], ..options(true) };
+
+        let plan = plan_in(dir.path(), enrolled(), &options, &index);
+        let mut pruned = Vec::new();
+        let outcome = apply_with(&plan, true, &mut |id| {
+            pruned.push(id.to_string());
+            Ok(1)
+        }).unwrap();
+        assert_eq!(pruned, ["GRA_2", "GRA_2/resources/x.pdf"]);
+        assert_eq!(outcome.pruned_documents, 2);
+        assert!(!dir.path().join("GRA_2").exists());
+        assert!(dir.path().join("GRA_1/resources/Recursos.zip").exists());
+    }
+
+    #[test]
+    fn without_a_sync_no_folder_is_an_orphan() {
+        let dir = tempfile::tempdir().unwrap();
+        fixture(dir.path());
+
+        let plan = plan_in(dir.path(), None, &options(true), &index);
+        assert!(plan.candidates.iter().all(|c| c.target != CleanTarget::Orphans));
+        assert!(plan.notes.iter().any(|n| n.contains("sync once")));
+    }
+}
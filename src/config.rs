@@ -1,15 +1,38 @@
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
 use anyhow::Result;
 
 const CONFIG_FILE: &str = ".polirag.json";
 const ENCRYPTION_KEY: &[u8] = b"PoliRag2026SecretKey!@#$%";
+/// Free space the write probe needs, so a nearly full disk fails up front
+const WRITE_PROBE_BYTES: usize = 1024 * 1024;
+/// Default `rag.max_document_chars`, roughly a 300-page PDF
+const DEFAULT_MAX_DOCUMENT_CHARS: usize = 1_000_000;
+/// Default `rag.top_k`: snippets retrieved per question
+const DEFAULT_TOP_K: usize = 20;
+/// Relevance against novelty when diversifying retrieved snippets, see `rag::diversity`
+const DEFAULT_MMR_LAMBDA: f32 = 0.7;
+/// Default `model_loading_retries`: two minutes at one retry every 3 seconds
+const DEFAULT_MODEL_LOADING_RETRIES: u32 = 40;
+/// Bumped by every `Config::save` in this process, so the TUI can tell its own writes from outside edits
+static SAVE_GENERATION: AtomicU64 = AtomicU64::new(0);
+/// Held while `Config::load` migrates or recovers a file
+static LOAD_LOCK: Mutex<()> = Mutex::new(());
+/// What the last recovering load did, until it's reported
+static RECOVERY: Mutex<Option<ConfigRecovery>> = Mutex::new(None);
+/// Default `downloads.max_subject_mb`, so a course full of recordings can't fill the disk
+const DEFAULT_MAX_SUBJECT_MB: u64 = 1024;
 
-#[derive(Serialize, Deserialize, Clone, Default, PartialEq)]
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Eq, Hash)]
 pub enum LlmProvider {
     #[default]
     LmStudio,
     OpenRouter,
+    Ollama,
 }
 
 impl LlmProvider {
@@ -17,10 +40,467 @@ impl LlmProvider {
         match self {
             LlmProvider::LmStudio => "http://localhost:1234/v1",
             LlmProvider::OpenRouter => "https://openrouter.ai/api/v1",
+            LlmProvider::Ollama => "http://localhost:11434/v1",
         }
     }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            LlmProvider::LmStudio => "LM Studio",
+            LlmProvider::OpenRouter => "OpenRouter",
+            LlmProvider::Ollama => "Ollama",
+        }
+    }
+
+    /// The provider after this one in the Settings toggle, local servers first
+    pub fn next(&self) -> Self {
+        match self {
+            LlmProvider::LmStudio => LlmProvider::Ollama,
+            LlmProvider::Ollama => LlmProvider::OpenRouter,
+            LlmProvider::OpenRouter => LlmProvider::LmStudio,
+        }
+    }
+
+    /// A server the user runs, whose models are picked from its `/models` list and whose
+    /// requests cost nothing
+    pub fn is_local(&self) -> bool {
+        matches!(self, LlmProvider::LmStudio | LlmProvider::Ollama)
+    }
+}
+
+/// Similarity metric used to compare embeddings
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+pub enum SimilarityMetric {
+    #[default]
+    Cosine,
+    Dot,
+    Euclidean,
+}
+
+impl SimilarityMetric {
+    pub fn label(&self) -> &'static str {
+        match self {
+            SimilarityMetric::Cosine => "cosine",
+            SimilarityMetric::Dot => "dot product",
+            SimilarityMetric::Euclidean => "euclidean",
+        }
+    }
+}
+
+/// Vector store backend of the index. Each keeps different files next to the index path,
+/// so switching starts from whatever that backend last saved, usually nothing. SQLite is the
+/// exception: a new database imports the existing HNSW or linear index.
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum StoreType {
+    /// Approximate nearest neighbours over an HNSW graph: `<index>.data` and `<index>.hnsw.*`
+    #[default]
+    Hnsw,
+    /// Exact scan over every embedding: the index file itself
+    Linear,
+    /// Exact scan, with each document a row of `<index>.sqlite`; every change is written as it is made
+    Sqlite,
+}
+
+impl StoreType {
+    pub fn label(&self) -> &'static str {
+        match self {
+            StoreType::Hnsw => "HNSW",
+            StoreType::Linear => "linear",
+            StoreType::Sqlite => "SQLite",
+        }
+    }
+
+    /// The backend after this one, for cycling through them in the settings
+    pub fn next(&self) -> Self {
+        match self {
+            StoreType::Hnsw => StoreType::Linear,
+            StoreType::Linear => StoreType::Sqlite,
+            StoreType::Sqlite => StoreType::Hnsw,
+        }
+    }
+}
+
+/// HNSW graph build parameters
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub struct HnswParams {
+    /// Neighbours kept per node and layer (M)
+    pub max_nb_connection: usize,
+    /// Candidate list size while inserting
+    pub ef_construction: usize,
+    pub max_layer: usize,
+    /// Allocation hint, not a hard cap
+    pub max_elements: usize,
+}
+
+impl Default for HnswParams {
+    fn default() -> Self {
+        Self {
+            max_nb_connection: 24,
+            ef_construction: 200,
+            max_layer: 16,
+            max_elements: 10000,
+        }
+    }
+}
+
+/// Which retrieved snippets are close enough to the question to enter the prompt
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub struct RelevanceParams {
+    /// Lowest similarity a snippet needs; when even the best match is below it,
+    /// only that match is kept and marked as low-confidence. 0 turns the cutoff off.
+    pub floor: f32,
+    /// Snippets scoring more than this below the best match are dropped
+    pub delta: f32,
+}
+
+impl RelevanceParams {
+    /// Whether any retrieved snippet can be dropped for its score
+    pub fn filters(&self) -> bool {
+        self.floor > 0.0
+    }
+}
+
+impl Default for RelevanceParams {
+    fn default() -> Self {
+        Self {
+            floor: 0.22,
+            delta: 0.12,
+        }
+    }
+}
+
+/// Score boost for the subjects the recent answers drew on, see `rag::affinity`
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub struct AffinityParams {
+    /// Added to the similarity of a document from the subject being discussed; 0 disables it.
+    /// Capped at `rag::affinity::MAX_BOOST` so it can't beat a clearly better match.
+    pub boost: f32,
+    /// How much of a subject's weight carries over to the next question (0-1)
+    pub decay: f32,
+}
+
+impl Default for AffinityParams {
+    fn default() -> Self {
+        Self {
+            boost: 0.04,
+            decay: 0.6,
+        }
+    }
+}
+
+/// How keyword and vector results are combined
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Fusion {
+    /// Weighted reciprocal rank fusion: only the order of each list counts
+    #[default]
+    Rank,
+    /// `alpha * similarity + (1 - alpha) * bm25`, the BM25 score divided by the best one
+    Linear,
+}
+
+impl Fusion {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Fusion::Rank => "rank",
+            Fusion::Linear => "linear",
+        }
+    }
+}
+
+/// Keyword (BM25) search fused with the vector search, see `rag::keyword`
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(default)]
+pub struct HybridParams {
+    /// Share of the keyword ranking in the fused order (0-1); the vector ranking gets the
+    /// rest. 0 disables keyword search, whatever the fusion.
+    pub keyword_weight: f32,
+    /// Rank offset of reciprocal rank fusion: higher values flatten the difference between
+    /// the first places
+    pub rrf_k: f32,
+    pub fusion: Fusion,
+    /// Weight of the similarity in `Fusion::Linear` (0-1), the normalized BM25 score gets the rest
+    pub alpha: f32,
+}
+
+impl Default for HybridParams {
+    fn default() -> Self {
+        Self {
+            keyword_weight: 0.5,
+            rrf_k: 60.0,
+            fusion: Fusion::Rank,
+            alpha: 0.7,
+        }
+    }
+}
+
+/// How retrieved snippets are laid out in the prompt
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+pub enum ContextProfile {
+    /// Short snippets grouped per file, no scores
+    Compact,
+    /// One block per snippet with score and page
+    #[default]
+    Detailed,
+}
+
+impl ContextProfile {
+    pub fn label(&self) -> &'static str {
+        match self {
+            ContextProfile::Compact => "compact",
+            ContextProfile::Detailed => "detailed",
+        }
+    }
+
+    /// Characters kept around the best match of each snippet
+    pub fn snippet_chars(&self) -> usize {
+        match self {
+            ContextProfile::Compact => 600,
+            ContextProfile::Detailed => 1500,
+        }
+    }
+}
+
+/// How answers built from retrieved context are structured
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+pub enum AnswerMode {
+    #[default]
+    Standard,
+    /// List the sources used first, then answer citing them inline
+    SummaryFirst,
+}
+
+impl AnswerMode {
+    pub fn label(&self) -> &'static str {
+        match self {
+            AnswerMode::Standard => "standard",
+            AnswerMode::SummaryFirst => "summary-first",
+        }
+    }
+}
+
+/// Retrieval pipeline settings
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct RagConfig {
+    /// Also read as `store_backend`
+    #[serde(default, alias = "store_backend")]
+    pub store_type: StoreType,
+    #[serde(default)]
+    pub similarity_metric: SimilarityMetric,
+    #[serde(default)]
+    pub hnsw: HnswParams,
+    /// Fixed HNSW query breadth; None means max(64, 4 * top_k)
+    #[serde(default)]
+    pub ef_search: Option<usize>,
+    /// Write embeddings to the index files as f16 instead of f32, halving their size. The HNSW
+    /// graph dump keeps f32 vectors, so there only its document map shrinks.
+    #[serde(default)]
+    pub half_precision: bool,
+    /// Number the context sources and ask the model to cite them as [1], [2]...
+    #[serde(default)]
+    pub numbered_citations: bool,
+    #[serde(default)]
+    pub context_profile: ContextProfile,
+    /// Summary-first numbers the sources like `numbered_citations`
+    #[serde(default)]
+    pub answer_mode: AnswerMode,
+    /// Summarize sources far larger than their share of the context instead of dropping them
+    #[serde(default)]
+    pub condense_long_sources: bool,
+    /// Also condense with OpenRouter, where every summary is a paid request
+    #[serde(default)]
+    pub condense_remote: bool,
+    /// Let the chat model reorder the retrieved snippets by relevance before the best are kept
+    #[serde(default)]
+    pub rerank: bool,
+    /// Also rerank with OpenRouter, where every ranking is a paid request
+    #[serde(default)]
+    pub rerank_remote: bool,
+    /// Add a "Last modified: <date>" line to the header of indexed file chunks
+    #[serde(default)]
+    pub index_modified_dates: bool,
+    /// Longest document text indexed, in characters; longer documents are truncated with a warning
+    #[serde(default)]
+    pub max_document_chars: Option<usize>,
+    /// Retrieve fewer snippets for long, specific questions and more for short, vague ones
+    #[serde(default)]
+    pub adaptive_k: bool,
+    /// Add "[updated <date>]" to source labels in the prompt so the model can prefer newer material
+    #[serde(default)]
+    pub freshness_hints: bool,
+    /// Score cutoff relative to the best match, see `RelevanceParams`
+    #[serde(default)]
+    pub relevance: RelevanceParams,
+    /// Prefer the subjects of the last answers for follow-up questions, see `AffinityParams`
+    #[serde(default)]
+    pub affinity: AffinityParams,
+    /// Fuse keyword matches into the vector search, see `HybridParams`
+    #[serde(default)]
+    pub hybrid: HybridParams,
+    /// Snippets retrieved per question, the most `adaptive_k` goes up to; 0 means no limit
+    #[serde(default)]
+    pub top_k: Option<usize>,
+    /// Relevance against novelty of the retrieved snippets (0-1); 1 turns diversification off
+    #[serde(default)]
+    pub mmr_lambda: Option<f32>,
+}
+
+impl RagConfig {
+    pub fn document_char_limit(&self) -> usize {
+        self.max_document_chars.unwrap_or(DEFAULT_MAX_DOCUMENT_CHARS)
+    }
+
+    /// `top_k`, 0 for no limit
+    pub fn top_k(&self) -> usize {
+        self.top_k.unwrap_or(DEFAULT_TOP_K)
+    }
+
+    pub fn mmr_lambda(&self) -> f32 {
+        self.mmr_lambda.unwrap_or(DEFAULT_MMR_LAMBDA).clamp(0.0, 1.0)
+    }
+}
+
+/// Window the spending budget covers
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+pub enum BudgetPeriod {
+    Daily,
+    #[default]
+    Monthly,
+}
+
+impl BudgetPeriod {
+    pub fn label(&self) -> &'static str {
+        match self {
+            BudgetPeriod::Daily => "today",
+            BudgetPeriod::Monthly => "this month",
+        }
+    }
+}
+
+/// Spending limits for OpenRouter requests; a limit left unset is not checked
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct BudgetConfig {
+    #[serde(default)]
+    pub period: BudgetPeriod,
+    /// Prompt plus completion tokens per period
+    #[serde(default)]
+    pub max_tokens: Option<u64>,
+    /// US dollars per period, priced with the model's OpenRouter rates
+    #[serde(default)]
+    pub max_usd: Option<f64>,
+}
+
+impl BudgetConfig {
+    pub fn is_set(&self) -> bool {
+        self.max_tokens.is_some() || self.max_usd.is_some()
+    }
+}
+
+/// What a sync downloads and unpacks from each subject's resources
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+pub struct DownloadLimits {
+    /// Resource megabytes per subject; 0 disables the cap
+    #[serde(default)]
+    pub max_subject_mb: Option<u64>,
+    /// Resource files per subject; unset means no cap
+    #[serde(default)]
+    pub max_files: Option<usize>,
+    /// Extensions to keep, e.g. `["pdf", "docx", "pptx"]`; empty keeps every type
+    #[serde(default)]
+    pub extensions: Vec<String>,
+}
+
+impl DownloadLimits {
+    pub fn max_subject_bytes(&self) -> Option<u64> {
+        match self.max_subject_mb.unwrap_or(DEFAULT_MAX_SUBJECT_MB) {
+            0 => None,
+            mb => Some(mb * 1024 * 1024),
+        }
+    }
+
+    pub fn allows_extension(&self, file_name: &str) -> bool {
+        if self.extensions.is_empty() {
+            return true;
+        }
+        let extension = Path::new(file_name).extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+        self.extensions.iter().any(|allowed| allowed.trim_start_matches('.').eq_ignore_ascii_case(&extension))
+    }
+}
+
+/// What the user chose for one subject; subjects without an entry are synced and searched
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(default)]
+pub struct SubjectPrefs {
+    /// Scrape and index the subject in syncs
+    pub sync: bool,
+    /// Use its documents as chat context
+    pub retrieve: bool,
+    /// Shorter name shown in the subject lists
+    pub alias: Option<String>,
+}
+
+impl Default for SubjectPrefs {
+    fn default() -> Self {
+        Self { sync: true, retrieve: true, alias: None }
+    }
+}
+
+/// Pace of the scraper's page navigations, so a sync doesn't trip PoliformaT's rate limiting
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(default)]
+pub struct ThrottleConfig {
+    /// Least time between two page navigations of a tab
+    pub min_delay_ms: u64,
+    /// Up to this much is added at random to every wait
+    pub jitter_ms: u64,
+    /// A subject's delay doubles after each error page, up to this
+    pub max_delay_ms: u64,
+    /// Retries of a navigation that lands on an error page
+    pub retries: u32,
+    /// Page navigations in flight at once across all tabs
+    pub max_concurrent: usize,
+}
+
+impl Default for ThrottleConfig {
+    fn default() -> Self {
+        Self { min_delay_ms: 2500, jitter_ms: 1000, max_delay_ms: 30_000, retries: 2, max_concurrent: 2 }
+    }
+}
+
+/// When a sync needs approval before re-indexing a subject, and how long replaced documents are kept
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(default)]
+pub struct ChurnConfig {
+    /// Fraction (0-1) of a subject's indexed files a sync may replace or remove without asking
+    pub threshold: f32,
+    /// Days replaced and removed documents stay in `archive/` before they are deleted
+    pub archive_days: u64,
+}
+
+impl Default for ChurnConfig {
+    fn default() -> Self {
+        Self { threshold: 0.4, archive_days: 30 }
+    }
 }
 
+/// Desktop notifications when a sync, re-embed or answer finishes in the background
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(default)]
+pub struct NotificationConfig {
+    pub enabled: bool,
+    /// A finished answer is announced only after this long without a key press, when the
+    /// terminal is likely not the focused window
+    pub idle_secs: u64,
+}
+
+impl Default for NotificationConfig {
+    fn default() -> Self {
+        Self { enabled: false, idle_secs: 30 }
+    }
+}
+
+/// User settings, stored as `config.json` in the app data directory (see `Config::load`)
 #[derive(Serialize, Deserialize, Default)]
 pub struct Config {
     #[serde(default)]
@@ -33,6 +513,58 @@ pub struct Config {
     pub openrouter_api_key: Option<String>,
     #[serde(default)]
     pub openrouter_model: Option<String>,
+    #[serde(default)]
+    pub rag: RagConfig,
+    /// Last model used with each provider, restored when switching back
+    #[serde(default)]
+    pub models_by_provider: HashMap<LlmProvider, String>,
+    /// LM Studio server, e.g. "http://192.168.1.10:1234/v1" (its default when unset)
+    #[serde(default)]
+    pub chat_base_url: Option<String>,
+    /// Ollama server, e.g. "http://192.168.1.10:11434/v1" (its default when unset)
+    #[serde(default)]
+    pub ollama_base_url: Option<String>,
+    /// Server answering `/embeddings` when it isn't the chat server
+    #[serde(default)]
+    pub embeddings_base_url: Option<String>,
+    /// Extra HTTP headers sent with every request to a provider (org id, project...)
+    #[serde(default)]
+    pub provider_headers: HashMap<LlmProvider, HashMap<String, String>>,
+    /// Attribution shown in the OpenRouter dashboard (`HTTP-Referer` / `X-Title`)
+    #[serde(default)]
+    pub openrouter_referer: Option<String>,
+    #[serde(default)]
+    pub openrouter_title: Option<String>,
+    #[serde(default)]
+    pub budget: BudgetConfig,
+    /// Retries of a chat request while LM Studio is still loading the model
+    #[serde(default)]
+    pub model_loading_retries: Option<u32>,
+    /// Ask again once, a little more randomly, when the model returns an empty answer
+    #[serde(default)]
+    pub retry_empty_answers: bool,
+    /// Caps and extension allowlist for resource downloads
+    #[serde(default)]
+    pub downloads: DownloadLimits,
+    /// Minutes between background syncs while the interface is open and connected; off when unset
+    #[serde(default)]
+    pub auto_sync_minutes: Option<u64>,
+    /// Per-subject preferences by site id; also records which subjects have been seen
+    #[serde(default)]
+    pub subjects: HashMap<String, SubjectPrefs>,
+    /// Guard against syncs that replace most of a subject, see `ChurnConfig`
+    #[serde(default)]
+    pub churn: ChurnConfig,
+    /// Delays between the scraper's page navigations; `polirag sync --fast` skips them
+    #[serde(default)]
+    pub throttle: ThrottleConfig,
+    /// Desktop notifications, see `NotificationConfig`
+    #[serde(default)]
+    pub notifications: NotificationConfig,
+    /// Extra global hotkeys of the interface: key ("f5", "ctrl+k", "alt+s") -> action id.
+    /// An empty action id unbinds a default key.
+    #[serde(default)]
+    pub keybindings: HashMap<String, String>,
 }
 
 /// Encrypted credentials stored in config
@@ -49,6 +581,213 @@ pub struct CachedCredentials {
     pub pin: String,
 }
 
+/// Create `dir` if needed and write (then remove) a small probe file in it.
+/// Fails with "Data directory not writable" or "disk full" instead of the raw IO error.
+pub fn check_writable(dir: &Path) -> Result<()> {
+    let probe = dir.join(".polirag-write-test");
+    let result = std::fs::create_dir_all(dir)
+        .and_then(|_| std::fs::File::create(&probe))
+        .and_then(|mut file| {
+            file.write_all(&vec![0u8; WRITE_PROBE_BYTES])?;
+            file.sync_all()
+        });
+    let _ = std::fs::remove_file(&probe);
+
+    result.map_err(|e| match e.kind() {
+        std::io::ErrorKind::StorageFull | std::io::ErrorKind::QuotaExceeded => {
+            anyhow::anyhow!("Disk full: cannot write to data directory {:?}", dir)
+        }
+        std::io::ErrorKind::PermissionDenied | std::io::ErrorKind::ReadOnlyFilesystem => {
+            anyhow::anyhow!("Data directory not writable: {:?} ({})", dir, e)
+        }
+        _ => anyhow::anyhow!("Data directory not writable / disk full: {:?} ({})", dir, e),
+    })
+}
+
+/// What `Config::load` did to get usable settings out of a damaged or legacy config
+#[derive(Clone, Debug, Default)]
+pub struct ConfigRecovery {
+    /// Legacy config moved into the data directory
+    pub migrated_from: Option<PathBuf>,
+    /// Why the config couldn't be read; defaults are in use and the file was left alone
+    pub unreadable: Option<String>,
+    /// Where a config that didn't parse was moved
+    pub moved_to: Option<PathBuf>,
+    /// Settings taken from the damaged file
+    pub recovered: Vec<String>,
+    /// Settings in the damaged file that were unusable and reverted to their defaults
+    pub reverted: Vec<String>,
+    /// The file breaks off partway, so settings after that point use their defaults
+    pub cut_short: bool,
+}
+
+impl ConfigRecovery {
+    fn is_empty(&self) -> bool {
+        self.migrated_from.is_none() && self.unreadable.is_none() && self.moved_to.is_none()
+    }
+
+    /// One line per thing that happened, for the terminal or the TUI
+    pub fn warnings(&self) -> Vec<String> {
+        let path = Config::config_path().unwrap_or_default();
+        let mut lines = Vec::new();
+        if let Some(legacy) = &self.migrated_from {
+            lines.push(format!("Moved settings from {} to {} (the old file is kept as {})", legacy.display(), path.display(), with_suffix(legacy, MIGRATED_SUFFIX).display()));
+        }
+        if let Some(error) = &self.unreadable {
+            lines.push(format!("Could not read {} ({}); using default settings until it is fixed", path.display(), error));
+        }
+        if let Some(moved) = &self.moved_to {
+            lines.push(format!("{} was damaged and moved to {}", path.display(), moved.display()));
+            let names = |keys: &[String]| if keys.is_empty() { "none".to_string() } else { keys.join(", ") };
+            lines.push(format!("Recovered settings: {}", names(&self.recovered)));
+            if !self.reverted.is_empty() {
+                lines.push(format!("Reverted to defaults: {}", names(&self.reverted)));
+            }
+            if self.cut_short {
+                lines.push("The file breaks off partway, so any settings after that point use their defaults".to_string());
+            }
+        }
+        lines
+    }
+}
+
+/// Suffix the legacy config gets once moved into the data directory
+const MIGRATED_SUFFIX: &str = "migrated";
+
+/// `path` with `.suffix` appended to its file name
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+/// Write `contents` next to `path` and rename it into place, so a crash never leaves half a file
+fn write_replacing(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let partial = with_suffix(path, "partial");
+    std::fs::write(&partial, contents)?;
+    std::fs::rename(&partial, path)
+}
+
+/// Move the legacy home-dir config to `path`. A config already at `path` was shadowed by the
+/// legacy one, so it's kept aside rather than preferred.
+fn migrate_legacy(legacy: &Path, path: &Path, recovery: &mut ConfigRecovery) {
+    let contents = match std::fs::read(legacy) {
+        Ok(contents) => contents,
+        Err(e) => {
+            tracing::warn!("Could not read legacy config {:?}: {}", legacy, e);
+            return;
+        }
+    };
+    // Renamed first, so a failure here can't migrate it again over later saves
+    if let Err(e) = std::fs::rename(legacy, with_suffix(legacy, MIGRATED_SUFFIX)) {
+        tracing::warn!("Could not rename legacy config {:?}: {}", legacy, e);
+        return;
+    }
+    if path.exists() {
+        if let Err(e) = std::fs::rename(path, with_suffix(path, "before-migration")) {
+            tracing::warn!("Could not keep {:?} aside: {}", path, e);
+        }
+    }
+    match write_replacing(path, &contents) {
+        Ok(()) => {
+            tracing::info!("Migrated config from {:?} to {:?}", legacy, path);
+            recovery.migrated_from = Some(legacy.to_path_buf());
+        }
+        Err(e) => tracing::warn!("Could not migrate legacy config to {:?}: {}", path, e),
+    }
+}
+
+/// Settings out of a config that doesn't parse: whatever JSON precedes the damage, each known
+/// field kept if it's valid on its own. The file is moved aside for the caller to save the result in its place.
+fn recover(path: &Path, contents: &[u8], recovery: &mut ConfigRecovery) -> Config {
+    let text = String::from_utf8_lossy(contents);
+    // A crash mid-write can leave the tail zero-filled
+    let text = text.trim_end_matches(|c: char| c == '\0' || c.is_whitespace());
+    let value = match serde_json::from_str::<serde_json::Value>(text) {
+        Ok(value) => Some(value),
+        Err(_) => {
+            recovery.cut_short = true;
+            longest_valid_prefix(text)
+        }
+    };
+    let config = match value {
+        Some(serde_json::Value::Object(fields)) => salvage(fields, recovery),
+        _ => Config::default(),
+    };
+
+    let stamp = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let moved = with_suffix(path, &format!("corrupt-{}", stamp));
+    match std::fs::rename(path, &moved) {
+        Ok(()) => recovery.moved_to = Some(moved),
+        Err(e) => {
+            // Left in place, defaults it is; the next save replaces it
+            tracing::warn!("Could not move damaged config {:?} aside: {}", path, e);
+            recovery.unreadable = Some(format!("it does not parse and could not be moved aside: {}", e));
+        }
+    }
+    config
+}
+
+/// The JSON in `text` up to the last complete member before it breaks off, with the brackets
+/// still open at that point closed
+fn longest_valid_prefix(text: &str) -> Option<serde_json::Value> {
+    // Byte offsets where a member or container just ended, with the brackets open there
+    let mut cuts = Vec::new();
+    let mut open = Vec::new();
+    let (mut in_string, mut escaped) = (false, false);
+    for (i, c) in text.char_indices() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '{' | '[' => {
+                open.push(if c == '{' { '}' } else { ']' });
+                cuts.push((i + 1, open.clone()));
+            }
+            '}' | ']' => {
+                open.pop();
+                cuts.push((i + 1, open.clone()));
+            }
+            ',' => cuts.push((i, open.clone())),
+            _ => {}
+        }
+    }
+    cuts.into_iter().rev().find_map(|(end, open)| {
+        let mut candidate = text[..end].to_string();
+        candidate.extend(open.iter().rev());
+        serde_json::from_str(&candidate).ok()
+    })
+}
+
+/// A config from the known `fields` that deserialize, the rest left at their defaults
+fn salvage(fields: serde_json::Map<String, serde_json::Value>, recovery: &mut ConfigRecovery) -> Config {
+    let Ok(serde_json::Value::Object(mut merged)) = serde_json::to_value(Config::default()) else {
+        return Config::default();
+    };
+    for (key, value) in fields {
+        // Unknown keys are ignored by a normal load too
+        let Some(default) = merged.insert(key.clone(), value) else {
+            merged.remove(&key);
+            continue;
+        };
+        if serde_json::from_value::<Config>(serde_json::Value::Object(merged.clone())).is_ok() {
+            recovery.recovered.push(key);
+        } else {
+            merged.insert(key.clone(), default);
+            recovery.reverted.push(key);
+        }
+    }
+    serde_json::from_value(serde_json::Value::Object(merged)).unwrap_or_default()
+}
+
 // Simple XOR encryption with base64 encoding
 fn encrypt(data: &str) -> String {
     let encrypted: Vec<u8> = data
@@ -70,7 +809,7 @@ fn decrypt(encrypted: &str) -> Option<String> {
 }
 
 // Simple base64 encoding (no external dependency)
-fn base64_encode(data: &[u8]) -> String {
+pub fn base64_encode(data: &[u8]) -> String {
     const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
     let mut result = String::new();
     
@@ -130,7 +869,7 @@ impl Config {
         path
     }
 
-    fn config_path() -> Option<PathBuf> {
+    pub fn config_path() -> Option<PathBuf> {
         let path = Self::get_app_data_dir().join("config.json");
         Some(path)
     }
@@ -143,47 +882,179 @@ impl Config {
         Self::get_app_data_dir().join("data")
     }
 
+    /// Check that the app data directory can be written before a sync touches it
+    pub fn check_data_dir_writable() -> Result<()> {
+        check_writable(&Self::get_app_data_dir())
+    }
+
+    /// Settings from the config file. A legacy `~/.polirag.json` is moved into the data
+    /// directory first; a file that exists but doesn't parse is recovered field by field and
+    /// moved aside, and what happened is kept for `take_recovery`.
     pub fn load() -> Config {
-        // Check legacy path first (home dir)
-        if let Some(home) = dirs::home_dir() {
-            let legacy_path = home.join(CONFIG_FILE);
-            if legacy_path.exists() {
-                if let Ok(contents) = std::fs::read_to_string(&legacy_path) {
-                    if let Ok(config) = serde_json::from_str(&contents) {
-                        return config;
-                    }
+        let Some(path) = Self::config_path() else { return Config::default() };
+        let legacy = Self::legacy_path().filter(|legacy| legacy.exists());
+        if legacy.is_none() {
+            if let Ok(bytes) = std::fs::read(&path) {
+                if let Ok(config) = serde_json::from_slice(&bytes) {
+                    return config;
                 }
             }
         }
-        
-        if let Some(path) = Self::config_path() {
-            if path.exists() {
-                if let Ok(contents) = std::fs::read_to_string(&path) {
-                    if let Ok(config) = serde_json::from_str(&contents) {
-                        return config;
+
+        // Only one thread migrates or moves a damaged file aside
+        let _guard = LOAD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let mut recovery = ConfigRecovery::default();
+        if let Some(legacy) = legacy.filter(|legacy| legacy.exists()) {
+            migrate_legacy(&legacy, &path, &mut recovery);
+        }
+        let config = match std::fs::read(&path) {
+            Ok(bytes) => match serde_json::from_slice(&bytes) {
+                Ok(config) => config,
+                Err(e) => {
+                    tracing::warn!("Config {:?} does not parse: {}", path, e);
+                    let config = recover(&path, &bytes, &mut recovery);
+                    if recovery.moved_to.is_some() {
+                        if let Err(e) = config.save() {
+                            tracing::warn!("Could not save the recovered config: {}", e);
+                        }
                     }
+                    config
                 }
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Config::default(),
+            Err(e) => {
+                tracing::warn!("Could not read config {:?}: {}", path, e);
+                recovery.unreadable = Some(e.to_string());
+                Config::default()
+            }
+        };
+        if !recovery.is_empty() {
+            *RECOVERY.lock().unwrap_or_else(|e| e.into_inner()) = Some(recovery);
+        }
+        config
+    }
+
+    /// What the last load had to recover or migrate, once
+    pub fn take_recovery() -> Option<ConfigRecovery> {
+        RECOVERY.lock().unwrap_or_else(|e| e.into_inner()).take()
+    }
+
+    fn legacy_path() -> Option<PathBuf> {
+        dirs::home_dir().map(|home| home.join(CONFIG_FILE))
+    }
+
+    pub fn model_loading_retry_limit(&self) -> u32 {
+        self.model_loading_retries.unwrap_or(DEFAULT_MODEL_LOADING_RETRIES)
+    }
+
+    /// Same settings with another provider selected, to resolve its URLs before saving
+    pub fn with_provider(mut self, provider: LlmProvider) -> Self {
+        self.llm_provider = provider;
+        self
+    }
+
+    /// Base URL for chat, `/models` and context length detection
+    pub fn chat_url(&self) -> String {
+        let configured = match self.llm_provider {
+            LlmProvider::LmStudio => self.chat_base_url.as_deref(),
+            LlmProvider::Ollama => self.ollama_base_url.as_deref(),
+            LlmProvider::OpenRouter => None,
+        };
+        configured
+            .filter(|url| !url.trim().is_empty())
+            .map(|url| url.trim_end_matches('/').to_string())
+            .unwrap_or_else(|| self.llm_provider.base_url().to_string())
+    }
+
+    /// Headers for requests to `provider`: OpenRouter attribution, then the configured extras
+    pub fn request_headers(&self, provider: &LlmProvider) -> Vec<(String, String)> {
+        let mut headers = Vec::new();
+        if *provider == LlmProvider::OpenRouter {
+            let referer = self.openrouter_referer.clone().unwrap_or_else(|| "http://localhost:8080".to_string());
+            let title = self.openrouter_title.clone().unwrap_or_else(|| "PoliRag".to_string());
+            headers.push(("HTTP-Referer".to_string(), referer));
+            headers.push(("X-Title".to_string(), title));
+        }
+        if let Some(extra) = self.provider_headers.get(provider) {
+            for (name, value) in extra {
+                // A configured header replaces a default one with the same name
+                headers.retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+                headers.push((name.clone(), value.clone()));
             }
         }
-        Config::default()
+        headers
+    }
+
+    /// Base URL for `/embeddings`, the chat URL unless configured separately
+    pub fn embeddings_url(&self) -> String {
+        self.embeddings_base_url.clone()
+            .filter(|url| !url.trim().is_empty())
+            .map(|url| url.trim_end_matches('/').to_string())
+            .unwrap_or_else(|| self.chat_url())
     }
 
     pub fn save(&self) -> Result<()> {
         if let Some(path) = Self::config_path() {
             let contents = serde_json::to_string_pretty(self)?;
-            std::fs::write(&path, contents)?;
+            write_replacing(&path, contents.as_bytes())?;
+            SAVE_GENERATION.fetch_add(1, Ordering::SeqCst);
         }
         Ok(())
     }
 
+    /// Number of saves made by this process so far
+    pub fn save_generation() -> u64 {
+        SAVE_GENERATION.load(Ordering::SeqCst)
+    }
+
+    /// Modification time of the config file
+    pub fn modified_at() -> Option<std::time::SystemTime> {
+        Self::config_path()
+            .and_then(|path| std::fs::metadata(path).and_then(|m| m.modified()).ok())
+    }
+
+    /// Save the model for the active provider
     pub fn save_model(model: &str) -> Result<()> {
         let mut config = Config::load();
         config.last_model = Some(model.to_string());
+        let provider = config.llm_provider.clone();
+        config.models_by_provider.insert(provider, model.to_string());
         config.save()
     }
 
+    /// Save the snippets retrieved per question (0 for no limit) and the relevance floor
+    /// (0 for no cutoff), used from the next question
+    pub fn save_retrieval_limits(top_k: usize, min_score: f32) -> Result<()> {
+        let mut config = Config::load();
+        config.rag.top_k = Some(top_k);
+        config.rag.relevance.floor = min_score.clamp(0.0, 1.0);
+        config.save()
+    }
+
+    /// Save the vector store backend, used from the next start
+    pub fn save_store_type(store_type: StoreType) -> Result<()> {
+        let mut config = Config::load();
+        config.rag.store_type = store_type;
+        config.save()
+    }
+
+    /// Last model used with the active provider
     pub fn get_last_model() -> Option<String> {
-        Config::load().last_model
+        let config = Config::load();
+        config.model_for(&config.llm_provider)
+    }
+
+    /// Last model used with `provider`, falling back to the fields written by older versions
+    pub fn model_for(&self, provider: &LlmProvider) -> Option<String> {
+        if let Some(model) = self.models_by_provider.get(provider) {
+            return Some(model.clone());
+        }
+        match provider {
+            LlmProvider::LmStudio => self.last_model.clone(),
+            LlmProvider::OpenRouter => self.openrouter_model.clone(),
+            // Added after `models_by_provider`, so there is nothing older to fall back to
+            LlmProvider::Ollama => None,
+        }
     }
 
     /// Save credentials (encrypted)
@@ -207,6 +1078,23 @@ impl Config {
         Some(CachedCredentials { username, pin })
     }
 
+    pub fn subject_prefs(&self, id: &str) -> SubjectPrefs {
+        self.subjects.get(id).cloned().unwrap_or_default()
+    }
+
+    /// Site ids of the subjects left out of retrieval, sorted
+    pub fn retrieval_excluded_subjects(&self) -> Vec<String> {
+        let mut excluded: Vec<String> = self.subjects.iter().filter(|(_, p)| !p.retrieve).map(|(id, _)| id.clone()).collect();
+        excluded.sort();
+        excluded
+    }
+
+    pub fn save_subject_prefs(id: &str, prefs: SubjectPrefs) -> Result<()> {
+        let mut config = Config::load();
+        config.subjects.insert(id.to_string(), prefs);
+        config.save()
+    }
+
     pub fn clear_credentials() -> Result<()> {
         let mut config = Config::load();
         config.cached_credentials = None;
@@ -220,8 +1108,103 @@ impl Config {
             config.openrouter_api_key = Some(key);
         }
         if let Some(m) = model {
+            if !m.is_empty() && config.llm_provider == LlmProvider::OpenRouter {
+                config.models_by_provider.insert(LlmProvider::OpenRouter, m.clone());
+            }
             config.openrouter_model = Some(m);
         }
         config.save()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn store_backend_is_read_as_store_type() {
+        let config: RagConfig = serde_json::from_str(r#"{"store_backend": "linear"}"#).unwrap();
+        assert_eq!(config.store_type, StoreType::Linear);
+        let config: RagConfig = serde_json::from_str(r#"{"store_type": "sqlite"}"#).unwrap();
+        assert_eq!(config.store_type, StoreType::Sqlite);
+        let config: RagConfig = serde_json::from_str("{}").unwrap();
+        assert_eq!(config.store_type, StoreType::Hnsw);
+    }
+
+    const SETTINGS: &str = r#"{"last_model":"qwen3","openrouter_model":"openai/gpt-4o","retry_empty_answers":true,"rag":{"store_type":"linear","ef_search":64}}"#;
+
+    /// The damaged config at `<dir>/config.json`, recovered
+    fn recovered(dir: &Path, contents: &[u8]) -> (Config, ConfigRecovery) {
+        let path = dir.join("config.json");
+        std::fs::write(&path, contents).unwrap();
+        let mut recovery = ConfigRecovery::default();
+        let config = recover(&path, contents, &mut recovery);
+        (config, recovery)
+    }
+
+    #[test]
+    fn truncated_config_keeps_the_complete_settings() {
+        let dir = tempfile::tempdir().unwrap();
+        // Broken off inside the "rag" object
+        let cut = SETTINGS.find("\"ef_search").unwrap() + 5;
+        let (config, recovery) = recovered(dir.path(), &SETTINGS.as_bytes()[..cut]);
+
+        assert!(recovery.cut_short);
+        assert_eq!(config.last_model.as_deref(), Some("qwen3"));
+        assert_eq!(config.openrouter_model.as_deref(), Some("openai/gpt-4o"));
+        assert!(config.retry_empty_answers);
+        assert_eq!(config.rag.store_type, StoreType::Linear);
+        assert_eq!(config.rag.ef_search, None);
+        // The damaged file is kept aside for the user
+        let moved = recovery.moved_to.unwrap();
+        assert_eq!(std::fs::read(moved).unwrap(), &SETTINGS.as_bytes()[..cut]);
+        assert!(!dir.path().join("config.json").exists());
+    }
+
+    #[test]
+    fn zero_filled_tail_is_ignored() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut contents = SETTINGS.as_bytes().to_vec();
+        contents.extend([0u8; 64]);
+        let (config, recovery) = recovered(dir.path(), &contents);
+        assert!(!recovery.cut_short);
+        assert_eq!(config.rag.ef_search, Some(64));
+        assert!(recovery.reverted.is_empty());
+    }
+
+    #[test]
+    fn settings_of_other_versions_revert_one_by_one() {
+        let dir = tempfile::tempdir().unwrap();
+        // A value of the wrong type, one an older version wrote and a key a newer one added
+        let contents = br#"{"last_model":"qwen3","retry_empty_answers":"yes","llm_provider":"Gemini","added_later":{"x":1},"openrouter_model":"openai/gpt-4o"}"#;
+        let (config, recovery) = recovered(dir.path(), contents);
+
+        assert_eq!(config.last_model.as_deref(), Some("qwen3"));
+        assert_eq!(config.openrouter_model.as_deref(), Some("openai/gpt-4o"));
+        assert!(!config.retry_empty_answers);
+        assert!(config.llm_provider == LlmProvider::default());
+        let mut reverted = recovery.reverted.clone();
+        reverted.sort();
+        assert_eq!(reverted, ["llm_provider", "retry_empty_answers"]);
+        assert!(!recovery.recovered.contains(&"added_later".to_string()));
+    }
+
+    #[test]
+    fn legacy_config_moves_into_the_data_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let legacy = dir.path().join(".polirag.json");
+        let path = dir.path().join("data").join("config.json");
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&legacy, SETTINGS).unwrap();
+        std::fs::write(&path, "{}").unwrap();
+
+        let mut recovery = ConfigRecovery::default();
+        migrate_legacy(&legacy, &path, &mut recovery);
+        assert_eq!(recovery.migrated_from.as_deref(), Some(legacy.as_path()));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), SETTINGS);
+        assert!(!legacy.exists());
+        assert!(with_suffix(&legacy, MIGRATED_SUFFIX).exists());
+        // The config it shadowed is kept aside
+        assert_eq!(std::fs::read_to_string(with_suffix(&path, "before-migration")).unwrap(), "{}");
+    }
+}
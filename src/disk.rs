@@ -0,0 +1,110 @@
+//! Free space checks before work that writes a lot: a sync refuses to start on a nearly full
+//! volume, and an index save fails up front instead of leaving a truncated file behind.
+
+use std::path::{Path, PathBuf};
+
+use crate::scrapper::{changes::SyncReport, format_bytes};
+
+/// Least free space a sync starts with, and the estimate when no sync was recorded yet
+pub const MIN_SYNC_BYTES: u64 = 512 * 1024 * 1024;
+/// Zips are extracted next to the download, so a sync can take about twice what it downloads
+const SYNC_GROWTH_FACTOR: u64 = 2;
+/// Free space an index save wants, as a multiple of the index's size: the files are
+/// rewritten in place, and the index may have grown since they were last written
+const SAVE_GROWTH_FACTOR: u64 = 2;
+
+/// Not enough free space for `action`
+#[derive(Debug, thiserror::Error)]
+#[error("not enough disk space to {action}: {} free on {}, about {} needed", format_bytes(*free), path.display(), format_bytes(*needed))]
+pub struct LowSpace {
+    pub action: &'static str,
+    pub path: PathBuf,
+    pub free: u64,
+    pub needed: u64,
+}
+
+/// Free bytes on the volume holding `path`, which may not exist yet
+pub fn available_space(path: &Path) -> std::io::Result<u64> {
+    let existing = path.ancestors().find(|p| p.exists()).unwrap_or(Path::new("."));
+    fs2::available_space(existing)
+}
+
+/// Error when the volume holding `path` has less than `needed` bytes free. A volume that
+/// can't be probed passes; the write itself reports the failure then.
+pub fn ensure_free(path: &Path, needed: u64, action: &'static str) -> Result<(), LowSpace> {
+    match available_space(path) {
+        Ok(free) if free < needed => Err(LowSpace { action, path: path.to_path_buf(), free, needed }),
+        Ok(_) => Ok(()),
+        Err(e) => {
+            tracing::warn!("Could not check the free space on {}: {}", path.display(), e);
+            Ok(())
+        }
+    }
+}
+
+/// Space a sync is expected to need, from what the previous one downloaded
+pub fn sync_estimate(last: Option<&SyncReport>) -> u64 {
+    let downloaded = last.map_or(0, SyncReport::downloaded_bytes);
+    downloaded.saturating_mul(SYNC_GROWTH_FACTOR).max(MIN_SYNC_BYTES)
+}
+
+/// Error when the scraped data directory's volume looks too full for a sync
+pub fn check_sync(data_dir: &Path) -> Result<(), LowSpace> {
+    ensure_free(data_dir, sync_estimate(SyncReport::load_last().as_ref()), "sync")
+}
+
+/// Error when `dir`, about to receive new versions of `files`, has less than twice their size free
+pub fn check_save(dir: &Path, files: &[PathBuf]) -> Result<(), LowSpace> {
+    let current: u64 = files.iter().filter_map(|f| std::fs::metadata(f).ok()).map(|m| m.len()).sum();
+    ensure_free(dir, current.saturating_mul(SAVE_GROWTH_FACTOR), "save the index")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scrapper::changes::{FileChange, SubjectChanges};
+
+    const MB: u64 = 1024 * 1024;
+
+    fn change(new_size: Option<u64>) -> FileChange {
+        FileChange { path: "tema1.pdf".to_string(), old_size: Some(MB), new_size }
+    }
+
+    #[test]
+    fn a_sync_expects_twice_what_the_last_one_downloaded() {
+        assert_eq!(sync_estimate(None), MIN_SYNC_BYTES);
+        let report = SyncReport {
+            subjects: vec![SubjectChanges {
+                subject: "GRA_1".to_string(),
+                added: vec![change(Some(300 * MB))],
+                updated: vec![change(Some(100 * MB))],
+                removed: vec![change(None)],
+            }],
+            ..SyncReport::default()
+        };
+        assert_eq!(sync_estimate(Some(&report)), 800 * MB);
+        // A small sync still wants the minimum
+        let small = SyncReport { subjects: vec![SubjectChanges { added: vec![change(Some(MB))], ..SubjectChanges::default() }], ..SyncReport::default() };
+        assert_eq!(sync_estimate(Some(&small)), MIN_SYNC_BYTES);
+    }
+
+    #[test]
+    fn a_path_that_does_not_exist_yet_is_checked_on_its_volume() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("index").join("polirag.index");
+        assert_eq!(available_space(&missing).unwrap(), available_space(dir.path()).unwrap());
+        ensure_free(&missing, 0, "sync").unwrap();
+
+        let err = ensure_free(&missing, u64::MAX, "sync").unwrap_err();
+        assert_eq!(err.needed, u64::MAX);
+        assert!(err.to_string().starts_with("not enough disk space to sync: "), "{}", err);
+    }
+
+    #[test]
+    fn a_save_counts_only_the_files_already_written() {
+        let dir = tempfile::tempdir().unwrap();
+        let index = dir.path().join("polirag.index");
+        std::fs::write(&index, vec![0u8; 4096]).unwrap();
+        check_save(dir.path(), &[index, dir.path().join("polirag.data")]).unwrap();
+    }
+}
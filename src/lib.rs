@@ -0,0 +1,49 @@
+//! polirag's core: the PoliformaT scraper, the document index and the LLM client.
+//! The `polirag` binary puts the terminal interface and the CLI on top of it; build the
+//! library alone with `--no-default-features` to leave their dependencies out.
+//!
+//! - [`RagSystem`] embeds documents with [`EmbeddingModel`] into a [`VectorStore`] and
+//!   retrieves context for questions.
+//! - [`PoliformatClient`] logs in to PoliformaT and downloads subjects; [`sync::run`]
+//!   scrapes and indexes everything, reporting progress as [`SyncEvent`]s.
+//! - [`LlmClient`] talks to an OpenAI-compatible chat API.
+//! - [`Config`] holds the user's settings.
+//!
+//! A store can be used directly, e.g. with embeddings computed elsewhere:
+//!
+//! ```
+//! use polirag::config::SimilarityMetric;
+//! use polirag::rag::{store::LinearVectorStore, Document};
+//! use polirag::VectorStore;
+//!
+//! let mut store = LinearVectorStore::in_memory(SimilarityMetric::Cosine);
+//! for (id, embedding) in [("algebra.pdf#0", vec![1.0, 0.0]), ("history.pdf#0", vec![0.0, 1.0])] {
+//!     store.add_document(Document {
+//!         id: id.to_string(),
+//!         content: format!("Contents of {}", id),
+//!         embedding,
+//!         metadata: [("filename".to_string(), id.to_string())].into(),
+//!         user_id: "user".to_string(),
+//!     })?;
+//! }
+//!
+//! let results = store.search(&[0.9, 0.1], "user", 1, 0.0, None)?;
+//! assert_eq!(results[0].0.id, "algebra.pdf#0");
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+pub mod config;
+pub mod disk;
+pub mod llm;
+pub mod rag;
+pub mod scrapper;
+pub mod study;
+pub mod subjects;
+pub mod sync;
+pub mod usage;
+
+pub use config::Config;
+pub use llm::LlmClient;
+pub use rag::{embeddings::EmbeddingModel, store::VectorStore, RagSystem};
+pub use scrapper::PoliformatClient;
+pub use sync::SyncEvent;
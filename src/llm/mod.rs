@@ -3,14 +3,17 @@ use reqwest::Client;
 use anyhow::Result;
 use futures::Stream;
 use std::pin::Pin;
-use ratatui::text::Line;
 
+/// Client for an OpenAI-compatible chat API (LM Studio, OpenRouter...), with streaming
 #[derive(Clone)]
 pub struct LlmClient {
     client: Client,
     base_url: String,
     pub model: String,
     pub api_key: Option<String>,
+    /// Extra headers sent with every request, see `Config::request_headers`
+    headers: Vec<(String, String)>,
+    temperature: f32,
 }
 
 #[derive(Deserialize, Debug)]
@@ -23,8 +26,22 @@ pub struct ModelInfo {
     pub id: String,
     #[serde(default)]
     pub context_length: Option<usize>,
+    /// Only reported by OpenRouter
+    #[serde(default)]
+    pub pricing: Option<ModelPricing>,
+}
+
+/// USD per token, as decimal strings
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct ModelPricing {
+    #[serde(default)]
+    pub prompt: String,
+    #[serde(default)]
+    pub completion: String,
 }
 
+pub const DEFAULT_TEMPERATURE: f32 = 0.7;
+
 #[derive(Serialize)]
 pub struct ChatRequest {
     pub model: String,
@@ -32,18 +49,20 @@ pub struct ChatRequest {
     pub temperature: f32,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub stream: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<usize>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct ChatMessage {
     pub role: String,
     pub content: String,
-    #[serde(skip)]
-    #[serde(default)]
-    pub thinking_collapsed: bool,
-    
-    #[serde(skip)]
-    pub render_cache: RenderCache,
+}
+
+impl ChatMessage {
+    pub fn new(role: &str, content: impl Into<String>) -> Self {
+        Self { role: role.to_string(), content: content.into() }
+    }
 }
 
 #[derive(Deserialize)]
@@ -73,6 +92,59 @@ pub struct ChatStreamDelta {
     pub content: Option<String>,
 }
 
+/// Chat failures the UI handles specifically; they travel inside `anyhow::Error`
+#[derive(Debug, thiserror::Error)]
+pub enum LlmError {
+    /// LM Studio lists the model but is still loading it (or has unloaded it)
+    #[error("Model is loading on the server: {0}")]
+    ModelLoading(String),
+    #[error("Chat request failed: {0}")]
+    Request(String),
+}
+
+impl LlmError {
+    fn from_response(status: reqwest::StatusCode, body: String) -> Self {
+        // LM Studio answers with a JSON error such as "Model is loading", "Model ... is not loaded"
+        // or "No models loaded"; OpenAI-style servers use 503 for the same state
+        let lower = body.to_lowercase();
+        let loading = ["loading", "not loaded", "unloaded", "no models loaded"].iter().any(|m| lower.contains(m));
+        if loading || status == reqwest::StatusCode::SERVICE_UNAVAILABLE {
+            LlmError::ModelLoading(body)
+        } else {
+            LlmError::Request(body)
+        }
+    }
+}
+
+/// Rough token count of `text`, by the same ratio the embedder chunks with
+pub fn estimate_tokens(text: &str) -> usize {
+    text.len().div_ceil(crate::rag::embeddings::CHARS_PER_TOKEN)
+}
+
+/// Drop the oldest turns of `messages` until their estimated tokens fit in `max_tokens`,
+/// a user message with the answer after it at a time. The system message and the last
+/// message are always kept. Returns how many messages were dropped.
+pub fn trim_to_fit(messages: &mut Vec<ChatMessage>, max_tokens: usize) -> usize {
+    let mut total: usize = messages.iter().map(|m| estimate_tokens(&m.content)).sum();
+    let first = usize::from(messages.first().is_some_and(|m| m.role == "system"));
+    let mut dropped = 0;
+    while total > max_tokens && messages.len() > first + 1 {
+        total -= estimate_tokens(&messages.remove(first).content);
+        dropped += 1;
+        // An answer without its question would confuse the model
+        if messages.len() > first + 1 && messages[first].role == "assistant" {
+            total -= estimate_tokens(&messages.remove(first).content);
+            dropped += 1;
+        }
+    }
+    dropped
+}
+
+/// Whether `err` means the model is still loading and the request can be retried shortly
+pub fn is_model_loading(err: &anyhow::Error) -> bool {
+    matches!(err.downcast_ref::<LlmError>(), Some(LlmError::ModelLoading(_)))
+}
+
 #[derive(Deserialize, Debug, Default, Clone)]
 pub struct Usage {
     pub prompt_tokens: usize,
@@ -87,6 +159,8 @@ impl LlmClient {
             base_url: base_url.unwrap_or_else(|| "http://localhost:1234/v1".to_string()),
             model: model.unwrap_or_else(|| "local-model".to_string()),
             api_key,
+            headers: Vec::new(),
+            temperature: DEFAULT_TEMPERATURE,
         }
     }
 
@@ -99,13 +173,32 @@ impl LlmClient {
         self.api_key = api_key;
     }
 
-    pub async fn fetch_models(&self) -> Result<Vec<String>> {
-        let url = format!("{}/models", self.base_url);
-        let mut builder = self.client.get(&url);
-        
+    pub fn set_temperature(&mut self, temperature: f32) {
+        self.temperature = temperature;
+    }
+
+    pub fn set_headers(&mut self, headers: Vec<(String, String)>) {
+        self.headers = headers;
+    }
+
+    /// Add the API key and configured headers to a request
+    fn authorize(&self, mut builder: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
         if let Some(key) = &self.api_key {
             builder = builder.header("Authorization", format!("Bearer {}", key));
         }
+        for (name, value) in &self.headers {
+            builder = builder.header(name.as_str(), value.as_str());
+        }
+        builder
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    pub async fn fetch_models(&self) -> Result<Vec<String>> {
+        let url = format!("{}/models", self.base_url);
+        let builder = self.authorize(self.client.get(&url));
         
         let resp = builder.send().await?;
         
@@ -117,61 +210,62 @@ impl LlmClient {
         Ok(body.data.into_iter().map(|m| m.id).collect())
     }
     
+    /// Whether anything answers at `base_url`, used for the Settings connectivity indicators
+    pub async fn ping(base_url: &str) -> bool {
+        let client = match Client::builder().timeout(std::time::Duration::from_secs(3)).build() {
+            Ok(client) => client,
+            Err(_) => return false,
+        };
+        // Any HTTP response counts: OpenAI-compatible servers may answer 401/404 on /models
+        client.get(format!("{}/models", base_url)).send().await.is_ok()
+    }
+
     /// Fetch context length for the current model
     pub async fn fetch_context_length(&self) -> Result<usize> {
+        Ok(self.fetch_model_info().await?
+            .and_then(|model| model.context_length)
+            .unwrap_or(32768)) // Default fallback
+    }
+
+    /// The `/models` entry of the current model, None when the server doesn't list it
+    pub async fn fetch_model_info(&self) -> Result<Option<ModelInfo>> {
         let url = format!("{}/models", self.base_url);
-        let mut builder = self.client.get(&url);
-        
-        if let Some(key) = &self.api_key {
-            builder = builder.header("Authorization", format!("Bearer {}", key));
-        }
+        let builder = self.authorize(self.client.get(&url));
         
         let resp = builder.send().await?;
         
         if !resp.status().is_success() {
-            return Ok(32768); // Default fallback
+            return Ok(None);
         }
 
         let body: ModelListResponse = resp.json().await?;
-        
-        // Find current model and get its context length
-        for model in body.data {
-            if model.id == self.model {
-                if let Some(ctx_len) = model.context_length {
-                    return Ok(ctx_len);
-                }
-            }
-        }
-        
-        Ok(32768) // Default fallback
+        Ok(body.data.into_iter().find(|model| model.id == self.model))
     }
 
     pub async fn chat(&self, messages: &[ChatMessage]) -> Result<(String, Option<Usage>)> {
+        self.chat_limited(messages, None).await
+    }
+
+    /// Non-streaming chat with an optional cap on generated tokens
+    pub async fn chat_limited(&self, messages: &[ChatMessage], max_tokens: Option<usize>) -> Result<(String, Option<Usage>)> {
         let url = format!("{}/chat/completions", self.base_url);
         
         let req = ChatRequest {
             model: self.model.clone(),
             messages: messages.to_vec(),
-            temperature: 0.7,
+            temperature: self.temperature,
             stream: None,
+            max_tokens,
         };
 
-        let mut builder = self.client.post(&url).json(&req);
-        
-        if let Some(key) = &self.api_key {
-            builder = builder.header("Authorization", format!("Bearer {}", key));
-            // OpenRouter specific headers
-            if self.base_url.contains("openrouter") {
-                builder = builder.header("HTTP-Referer", "http://localhost:8080")
-                               .header("X-Title", "PoliRag");
-            }
-        }
+        let builder = self.authorize(self.client.post(&url).json(&req));
 
         let resp = builder.send().await?;
 
         if !resp.status().is_success() {
+             let status = resp.status();
              let err_text = resp.text().await.unwrap_or_default();
-             anyhow::bail!("Chat request failed: {}", err_text);
+             return Err(LlmError::from_response(status, err_text).into());
         }
 
         let body: ChatResponse = resp.json().await?;
@@ -190,25 +284,19 @@ impl LlmClient {
         let req = ChatRequest {
             model: self.model.clone(),
             messages: messages.to_vec(),
-            temperature: 0.7,
+            temperature: self.temperature,
             stream: Some(true),
+            max_tokens: None,
         };
 
-        let mut builder = self.client.post(&url).json(&req);
-        
-        if let Some(key) = &self.api_key {
-            builder = builder.header("Authorization", format!("Bearer {}", key));
-            if self.base_url.contains("openrouter") {
-                builder = builder.header("HTTP-Referer", "http://localhost:8080")
-                               .header("X-Title", "PoliRag");
-            }
-        }
+        let builder = self.authorize(self.client.post(&url).json(&req));
 
         let resp = builder.send().await?;
 
         if !resp.status().is_success() {
+             let status = resp.status();
              let err_text = resp.text().await.unwrap_or_default();
-             anyhow::bail!("Chat request failed: {}", err_text);
+             return Err(LlmError::from_response(status, err_text).into());
         }
 
         // Create stream
@@ -231,8 +319,8 @@ impl LlmClient {
                         buffer.clear();
                     }
                     
-                    if line.starts_with("data: ") {
-                        let data = line[6..].trim();
+                    if let Some(data) = line.strip_prefix("data: ") {
+                        let data = data.trim();
                         if data == "[DONE]" {
                             break;
                         }
@@ -262,25 +350,23 @@ pub enum StreamEvent {
     Usage(Usage),
 }
 
-#[derive(Clone, Default, Debug)]
-pub struct RenderCache {
-    pub inner: Option<(usize, Vec<Line<'static>>, usize)>,
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::StatusCode;
 
-impl Serialize for RenderCache {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: serde::Serializer,
-    {
-        serializer.serialize_none()
+    fn loading(status: StatusCode, body: &str) -> bool {
+        is_model_loading(&LlmError::from_response(status, body.to_string()).into())
     }
-}
 
-impl<'de> Deserialize<'de> for RenderCache {
-    fn deserialize<D>(_deserializer: D) -> Result<Self, D::Error>
-    where
-        D: serde::Deserializer<'de>,
-    {
-        Ok(RenderCache::default())
+    #[test]
+    fn a_model_still_loading_is_told_apart_from_other_failures() {
+        assert!(loading(StatusCode::BAD_REQUEST, r#"{"error":"Model is loading"}"#));
+        assert!(loading(StatusCode::NOT_FOUND, r#"{"error":"Model qwen3-8b is not loaded"}"#));
+        assert!(loading(StatusCode::BAD_REQUEST, r#"{"error":"No models loaded. Please load a model."}"#));
+        assert!(loading(StatusCode::SERVICE_UNAVAILABLE, ""));
+        assert!(!loading(StatusCode::UNAUTHORIZED, r#"{"error":"Invalid API key"}"#));
+        assert!(!loading(StatusCode::BAD_REQUEST, r#"{"error":"Context length exceeded"}"#));
+        assert!(!is_model_loading(&anyhow::anyhow!("Model is loading")));
     }
 }
@@ -2,13 +2,14 @@ use std::sync::{Arc, Mutex};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, Layer};
 use clap::{Parser, Subcommand};
 
-mod rag;
-mod scrapper;
-mod llm;
 mod tui;
-mod config;
 mod ops;
+mod report;
+mod backup;
+mod clean;
+mod startup;
 
+use polirag::{config, disk, llm, rag, scrapper, study, subjects, sync, usage};
 use llm::LlmClient;
 
 #[derive(Parser)]
@@ -18,21 +19,264 @@ use llm::LlmClient;
 struct Cli {
     #[command(subcommand)]
     command: Option<Commands>,
+    /// Rebuild the HNSW graph from the stored documents and exit
+    #[arg(long)]
+    rebuild_hnsw: bool,
+    /// Move an index that can't be opened aside (after confirmation) and start with an empty one
+    #[arg(long)]
+    force_fresh_index: bool,
+}
+
+#[derive(Subcommand, Clone)]
+enum IndexCommand {
+    /// Print the index header (format version, store, embedder) and document count
+    Info,
+}
+
+#[derive(Subcommand, Clone)]
+enum SubjectsCommand {
+    /// List the known subjects with their sync and retrieval settings
+    List,
+    /// Sync and search a subject again (both unless `--sync` or `--retrieve` is given)
+    Enable {
+        /// Site id, name or a unique part of the name
+        subject: String,
+        #[arg(long)]
+        sync: bool,
+        #[arg(long)]
+        retrieve: bool,
+    },
+    /// Stop syncing and searching a subject (both unless `--sync` or `--retrieve` is given)
+    Disable {
+        /// Site id, name or a unique part of the name
+        subject: String,
+        #[arg(long)]
+        sync: bool,
+        #[arg(long)]
+        retrieve: bool,
+    },
+    /// Show a subject under a shorter name in the lists; an empty alias removes it
+    Alias {
+        subject: String,
+        alias: String,
+    },
+}
+
+#[derive(Subcommand, Clone)]
+enum ArchiveCommand {
+    /// List the archived documents of replaced and removed files, newest first
+    List,
+    /// Put archived documents back in the index, replacing the current versions of their files
+    Restore {
+        /// Archive file (path or name from `archive list`), or a site id for its newest archive
+        archive: String,
+    },
 }
 
 #[derive(Subcommand, Clone)]
 enum Commands {
     /// Run synchronization (headless scrape & index)
-    Sync,
+    Sync {
+        /// Only download and index resources; skip announcements, lessons and the guia docent
+        #[arg(long)]
+        resources_only: bool,
+        /// Most resource files downloaded per subject (overrides `downloads.max_files`)
+        #[arg(long)]
+        limit: Option<usize>,
+        /// Most resource megabytes per subject, 0 for no cap (overrides `downloads.max_subject_mb`)
+        #[arg(long)]
+        max_mb: Option<u64>,
+        /// Only keep these file types, e.g. `--extensions pdf,docx,pptx` (overrides `downloads.extensions`)
+        #[arg(long, value_delimiter = ',')]
+        extensions: Vec<String>,
+        /// Re-index subjects even when most of their indexed files changed or disappeared
+        #[arg(long)]
+        accept_churn: bool,
+        /// Don't wait between page navigations (`throttle` in the config); risks PoliformaT's rate limiting
+        #[arg(long)]
+        fast: bool,
+        /// Sync even when the data volume has less free space than the sync is expected to need
+        #[arg(long)]
+        ignore_low_space: bool,
+    },
     /// Open the Interactive Menu (Default)
     Menu,
+    /// Measure HNSW recall against an exact scan of the index
+    Bench {
+        /// Number of indexed chunks to use as queries
+        #[arg(long, default_value_t = 50)]
+        queries: usize,
+        /// Results compared per query
+        #[arg(long, default_value_t = 5)]
+        top_k: usize,
+    },
+    /// Show what the last sync changed per subject
+    Status,
+    /// Print the OpenRouter usage ledger, one row per day
+    Usage,
+    /// Log in to PoliformaT from the terminal and save the credentials
+    Login {
+        /// Username/DNI; prompted for when missing
+        #[arg(long)]
+        username: Option<String>,
+        /// Read the PIN from the first line of stdin instead of prompting
+        #[arg(long)]
+        pin_stdin: bool,
+        /// Check the credentials without saving them
+        #[arg(long)]
+        no_save: bool,
+    },
+    /// Remove the saved credentials
+    Logout,
+    /// Write a print-friendly report for a subject (guia docent, professors, announcements, deadlines, files)
+    Report {
+        /// Subject name or site id, or a unique part of the name
+        subject: String,
+        /// Output file or directory; defaults to `reports/` in the app data dir
+        #[arg(long)]
+        out: Option<std::path::PathBuf>,
+        /// Add an LLM-written study guide section
+        #[arg(long)]
+        with_summary: bool,
+        /// Also print the report to PDF through headless Chrome
+        #[arg(long)]
+        pdf: bool,
+    },
+    /// Inspect the on-disk index
+    Index {
+        #[command(subcommand)]
+        command: IndexCommand,
+    },
+    /// Print the context a chat question would send, without calling the LLM
+    Retrieve {
+        query: String,
+        /// Only retrieve these document types, e.g. `--types pdf,subject`
+        #[arg(long, value_delimiter = ',')]
+        types: Vec<String>,
+        /// Only retrieve documents with this tag, like `/scope tag:<name>`
+        #[arg(long)]
+        tag: Option<String>,
+        /// Only retrieve documents of this subject (name, site id or a unique part of the name)
+        #[arg(long)]
+        subject: Option<String>,
+    },
+    /// Recalculate all embeddings in batches, resuming an interrupted run
+    Reembed,
+    /// Write every indexed document to a file as newline-delimited JSON
+    Export {
+        file: std::path::PathBuf,
+        /// Leave the embeddings out; `import` computes them again
+        #[arg(long)]
+        no_embeddings: bool,
+    },
+    /// Add the documents of a file written by `export`, replacing those with the same id
+    Import {
+        file: std::path::PathBuf,
+    },
+    /// Remove a subject's summary and files from the index; its downloaded files are kept
+    Remove {
+        /// Site id, name or alias of the subject
+        subject: String,
+    },
+    /// Choose which subjects are synced and searched
+    Subjects {
+        #[command(subcommand)]
+        command: SubjectsCommand,
+    },
+    /// Documents a sync replaced or removed, kept for `churn.archive_days`
+    Archive {
+        #[command(subcommand)]
+        command: ArchiveCommand,
+    },
+    /// Archive the index, config, usage ledger and sync report into a timestamped .tar.zst
+    Backup {
+        /// Archive path or directory; defaults to `backups/` in the app data dir
+        #[arg(long)]
+        out: Option<std::path::PathBuf>,
+        /// Leave the saved credentials, OpenRouter key and request headers out of the config
+        #[arg(long)]
+        no_secrets: bool,
+        /// Also archive the scraped files (can be several GB)
+        #[arg(long)]
+        include_data: bool,
+    },
+    /// Restore a backup made with `polirag backup`, replacing the current state
+    Restore {
+        file: std::path::PathBuf,
+        /// Check the archive and list what would change, without changing anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Free disk space in the scraped data; lists what it would delete unless --apply is passed
+    Clean {
+        /// Delete what the listing shows
+        #[arg(long)]
+        apply: bool,
+        /// Also delete files the index still refers to, removing their documents from the index
+        #[arg(long)]
+        prune_index: bool,
+        /// Zips whose contents are extracted and indexed
+        #[arg(long)]
+        zips: bool,
+        /// Extraction caches unchanged for --older-than days, with their zip
+        #[arg(long)]
+        extracted: bool,
+        /// Unfinished downloads and temporary pages
+        #[arg(long)]
+        leftovers: bool,
+        /// Folders of subjects the last sync no longer listed
+        #[arg(long)]
+        orphans: bool,
+        /// Days an extraction cache must be unchanged for --extracted
+        #[arg(long, default_value_t = clean::DEFAULT_OLDER_THAN_DAYS)]
+        older_than: u64,
+    },
     /// Internal: Extract PDF text (hidden)
     #[command(hide = true)]
     ExtractPdf {
-        path: String,
+        /// PDF inside the scraped data directory (or `POLIRAG_EXTRACT_ROOT`)
+        path: Option<String>,
+        /// Read the PDF bytes from stdin instead of a path
+        #[arg(long)]
+        stdin: bool,
     },
 }
 
+/// Env var naming an extra directory `extract-pdf` may read from
+const EXTRACT_ROOT_ENV: &str = "POLIRAG_EXTRACT_ROOT";
+
+/// PDF bytes for `extract-pdf`: from stdin, or from a path that must resolve inside the
+/// scraped data directory (or `POLIRAG_EXTRACT_ROOT`), so it can't be used to read arbitrary files.
+fn read_pdf_input(path: Option<&str>, stdin: bool) -> anyhow::Result<Vec<u8>> {
+    use std::io::Read;
+
+    if stdin {
+        let mut bytes = Vec::new();
+        std::io::stdin().read_to_end(&mut bytes)?;
+        return Ok(bytes);
+    }
+
+    let path = path.ok_or_else(|| anyhow::anyhow!("Pass a PDF path or --stdin"))?;
+    let mut roots = vec![config::Config::get_scraped_data_dir()];
+    if let Ok(root) = std::env::var(EXTRACT_ROOT_ENV) {
+        roots.push(std::path::PathBuf::from(root));
+    }
+    Ok(std::fs::read(resolve_within(std::path::Path::new(path), &roots)?)?)
+}
+
+/// `path` resolved, if it lies inside one of `roots`
+fn resolve_within(path: &std::path::Path, roots: &[std::path::PathBuf]) -> anyhow::Result<std::path::PathBuf> {
+    // canonicalize resolves "..", symlinks and relative paths before the check
+    let resolved = std::fs::canonicalize(path)?;
+    let allowed = roots.iter()
+        .filter_map(|root| std::fs::canonicalize(root).ok())
+        .any(|root| resolved.starts_with(root));
+    if !allowed {
+        anyhow::bail!("{:?} is outside the data directory", resolved);
+    }
+    Ok(resolved)
+}
+
 pub struct AppState {
     pub rag: Arc<rag::RagSystem>,
     pub poliformat: Arc<scrapper::PoliformatClient>,
@@ -45,10 +289,16 @@ async fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
     
     // Check for internal commands to skip full setup
-    if let Some(Commands::ExtractPdf { path }) = &cli.command {
+    if let Some(Commands::ExtractPdf { path, stdin }) = &cli.command {
         // Run extraction and exit immediately
-        let path = std::path::PathBuf::from(path);
-        match std::panic::catch_unwind(|| pdf_extract::extract_text(&path)) {
+        let bytes = match read_pdf_input(path.as_deref(), *stdin) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        };
+        match std::panic::catch_unwind(|| pdf_extract::extract_text_from_mem(&bytes)) {
             Ok(Ok(text)) => {
                 // Print with delimiters to separate from potential library noise
                 println!("<<<START_CONTENT>>>{}<<<END_CONTENT>>>", text);
@@ -88,13 +338,75 @@ async fn main() -> anyhow::Result<()> {
         )
         .init();
 
+    // Read the config before anything else does, so a damaged or legacy file is dealt with
+    // once; the TUI shows what happened itself
+    config::Config::load();
+    if !matches!(cli.command, None | Some(Commands::Menu)) {
+        for warning in config::Config::take_recovery().map(|r| r.warnings()).unwrap_or_default() {
+            eprintln!("Warning: {}", warning);
+        }
+    }
+
     // Initialize Systems using Global Path
     let index_path = config::Config::get_index_path();
     let index_path_str = index_path.to_string_lossy();
+
+    if cli.force_fresh_index && !startup::force_fresh_index(&index_path)? {
+        println!("Index left in place.");
+        return Ok(());
+    }
+
+    // Maintenance only needs the stored embeddings, skip loading the model and LLM
+    if cli.rebuild_hnsw {
+        return ops::run_rebuild_hnsw(&index_path_str);
+    }
+    if let Some(Commands::Status) = &cli.command {
+        return ops::run_status();
+    }
+    if let Some(Commands::Index { command: IndexCommand::Info }) = &cli.command {
+        return ops::run_index_info(&index_path_str);
+    }
+    if let Some(Commands::Login { username, pin_stdin, no_save }) = &cli.command {
+        let poliformat = Arc::new(scrapper::PoliformatClient::new());
+        if let Err(e) = ops::run_login(poliformat, username.clone(), *pin_stdin, !*no_save).await {
+            eprintln!("Login failed: {:#}", e);
+            std::process::exit(scrapper::auth::login_exit_code(&e));
+        }
+        return Ok(());
+    }
+    if let Some(Commands::Logout) = &cli.command {
+        return ops::run_logout();
+    }
+    if let Some(Commands::Report { subject, out, with_summary, pdf }) = &cli.command {
+        let options = report::ReportOptions { out: out.clone(), with_summary: *with_summary, pdf: *pdf };
+        return report::run_report(subject, options).await;
+    }
+    if let Some(Commands::Usage) = &cli.command {
+        usage::print_ledger();
+        return Ok(());
+    }
+    // Before the index is opened, so a restore can replace its files
+    if let Some(Commands::Backup { out, no_secrets, include_data }) = &cli.command {
+        return backup::run_backup(backup::BackupOptions { out: out.clone(), secrets: !*no_secrets, include_data: *include_data });
+    }
+    if let Some(Commands::Restore { file, dry_run }) = &cli.command {
+        return backup::run_restore(file, *dry_run);
+    }
+    if let Some(Commands::Bench { queries, top_k }) = &cli.command {
+        return ops::run_recall_bench(&index_path_str, *queries, *top_k);
+    }
     
-    let rag = Arc::new(rag::RagSystem::new(&index_path_str)?);
+    if let Err(cause) = config::Config::check_data_dir_writable() {
+        startup::exit(startup::StartupError::Permissions { path: config::Config::get_app_data_dir(), cause });
+    }
+    let rag = match rag::RagSystem::new(&index_path_str) {
+        Ok(rag) => Arc::new(rag),
+        Err(e) => startup::exit(startup::StartupError::from_rag(e)),
+    };
     let poliformat = Arc::new(scrapper::PoliformatClient::new());
-    let mut llm_client = LlmClient::new(None, None, None); // Defaults to localhost:1234
+    let startup_config = config::Config::load();
+    let mut llm_client = LlmClient::new(Some(startup_config.chat_url()), None, None); // Defaults to localhost:1234
+    llm_client.set_headers(startup_config.request_headers(&startup_config.llm_provider));
     
     // Try to load saved model from config first
     if let Some(saved_model) = config::Config::get_last_model() {
@@ -122,24 +434,113 @@ async fn main() -> anyhow::Result<()> {
     let command = cli.command.unwrap_or(Commands::Menu);
 
     match command {
-        Commands::Sync => {
+        Commands::Sync { resources_only, limit, max_mb, extensions, accept_churn, fast, ignore_low_space } => {
              println!("Starting Sync (Detailed logs in debug.log)...");
-             ops::run_sync(rag, poliformat).await?;
+             let mode = if resources_only { scrapper::SyncMode::ResourcesOnly } else { scrapper::SyncMode::Full };
+             let mut limits = startup_config.downloads.clone();
+             limits.max_files = limit.or(limits.max_files);
+             limits.max_subject_mb = max_mb.or(limits.max_subject_mb);
+             if !extensions.is_empty() {
+                 limits.extensions = extensions;
+             }
+             // Subjects over the churn threshold keep their indexed versions unless told otherwise
+             let churn = if accept_churn { sync::ChurnPolicy::Accept } else { sync::ChurnPolicy::Keep };
+             let options = sync::SyncOptions { mode, limits, fresh: false, churn, fast, ignore_low_space };
+             ops::run_sync(rag, poliformat, options).await?;
         },
         Commands::Menu => {
-             tui::run_app(state).await?;
+             if let Err(e) = tui::run_app(state).await {
+                 match e.downcast::<startup::StartupError>() {
+                     Ok(startup_error) => startup::exit(startup_error),
+                     Err(e) => return Err(e),
+                 }
+             }
+        },
+        Commands::Reembed => {
+             ops::run_reembed(rag).await?;
+        },
+        Commands::Export { file, no_embeddings } => ops::run_export(&rag, &file, !no_embeddings)?,
+        Commands::Import { file } => ops::run_import(&rag, &file).await?,
+        Commands::Remove { subject } => ops::run_remove(&rag, &subject)?,
+        Commands::Subjects { command } => match command {
+            SubjectsCommand::List => ops::run_subjects_list(&rag),
+            SubjectsCommand::Enable { subject, sync, retrieve } => {
+                ops::run_subjects_set(&rag, &subject, true, sync || !retrieve, retrieve || !sync)?;
+            }
+            SubjectsCommand::Disable { subject, sync, retrieve } => {
+                ops::run_subjects_set(&rag, &subject, false, sync || !retrieve, retrieve || !sync)?;
+            }
+            SubjectsCommand::Alias { subject, alias } => ops::run_subjects_alias(&rag, &subject, &alias)?,
+        },
+        Commands::Archive { command } => match command {
+            ArchiveCommand::List => ops::run_archive_list(),
+            ArchiveCommand::Restore { archive } => ops::run_archive_restore(&rag, &archive)?,
+        },
+        Commands::Clean { apply, prune_index, zips, extracted, leftovers, orphans, older_than } => {
+            let targets = [(zips, clean::CleanTarget::Zips), (extracted, clean::CleanTarget::Extracted), (leftovers, clean::CleanTarget::Leftovers), (orphans, clean::CleanTarget::Orphans)]
+                .into_iter()
+                .filter_map(|(chosen, target)| chosen.then_some(target))
+                .collect();
+            clean::run_clean(&rag, clean::CleanOptions { targets, older_than_days: older_than, apply, prune_index })?;
+        },
+        Commands::Retrieve { query, types, tag, subject } => {
+             let subject = match subject {
+                 Some(name) => Some(subjects::find(&subjects::known(&rag), &name)?.id.clone()),
+                 None => None,
+             };
+             let filter = rag::SearchFilter { subject, ..Default::default() };
+             let scope = rag::Scope { types: (!types.is_empty()).then_some(types), tag: tag.map(|t| t.to_lowercase()), filter, ..Default::default() };
+             ops::run_retrieve(rag, &query, scope).await?;
         },
-        Commands::ExtractPdf { .. } => unreachable!(), // Handled above
+        Commands::ExtractPdf { .. } | Commands::Bench { .. } | Commands::Status | Commands::Usage | Commands::Index { .. } | Commands::Login { .. } | Commands::Logout | Commands::Report { .. } | Commands::Backup { .. } | Commands::Restore { .. } => unreachable!(), // Handled above
     }
 
     // Drop guard to flush and close the log file
     drop(_guard);
     
-    // Clean up debug log on clean exit
+    // Clean up debug log on clean exit. The appender's worker may still hold the file for a
+    // moment, which makes the removal fail on Windows, so retry briefly before leaving it
     let log_file = config::Config::get_app_data_dir().join("debug.log");
-    if log_file.exists() {
-        let _ = std::fs::remove_file(log_file);
+    for _ in 0..5 {
+        if !log_file.exists() || std::fs::remove_file(&log_file).is_ok() {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extract_pdf_rejects_paths_outside_the_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("data");
+        std::fs::create_dir(&root).unwrap();
+        std::fs::write(dir.path().join("secret.pdf"), b"%PDF").unwrap();
+
+        assert!(resolve_within(&dir.path().join("secret.pdf"), std::slice::from_ref(&root)).is_err());
+        // ".." is resolved before the check
+        assert!(resolve_within(&root.join("../secret.pdf"), std::slice::from_ref(&root)).is_err());
+        // A root that's a prefix of the name, not of the path, doesn't count
+        std::fs::create_dir(dir.path().join("data-other")).unwrap();
+        std::fs::write(dir.path().join("data-other/notes.pdf"), b"%PDF").unwrap();
+        assert!(resolve_within(&dir.path().join("data-other/notes.pdf"), &[root]).is_err());
+    }
+
+    #[test]
+    fn extract_pdf_accepts_non_ascii_names_inside_the_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let subject = dir.path().join("Diseño de Sistemas").join("resources");
+        std::fs::create_dir_all(&subject).unwrap();
+        let pdf = subject.join("Tema 1 - Introducción a la señal.pdf");
+        std::fs::write(&pdf, b"%PDF").unwrap();
+
+        let resolved = resolve_within(&pdf, &[dir.path().to_path_buf()]).unwrap();
+        assert_eq!(resolved.file_name().unwrap(), "Tema 1 - Introducción a la señal.pdf");
+        assert_eq!(std::fs::read(resolved).unwrap(), b"%PDF");
+    }
+}
@@ -1,305 +1,441 @@
 use std::sync::Arc;
-use crate::{rag, scrapper, config};
-use text_splitter::TextSplitter;
-
-
-pub async fn run_sync(rag: Arc<rag::RagSystem>, poliformat: Arc<scrapper::PoliformatClient>) -> anyhow::Result<()> {
-    tracing::info!("Starting Sync...");
-
-    // Check connection first
-    if !poliformat.check_connection().await.unwrap_or(false) {
-        tracing::warn!("Not authenticated. Checking for credentials...");
-        
-        // First, try cached credentials from config
-        let cached_creds = config::Config::get_credentials().map(|c| (c.username, c.pin));
-        
-        // Then try environment variables
-        let env_creds = {
-            let username = std::env::var("POLIFORMAT_USER").or_else(|_| std::env::var("POLIFORMAT_DNI"));
-            let pin = std::env::var("POLIFORMAT_PIN").or_else(|_| std::env::var("POLIFORMAT_PASSWORD"));
-            if let (Ok(u), Ok(p)) = (username, pin) {
-                Some((u, p))
-            } else {
-                None
-            }
-        };
-        
-        // Prefer cached credentials, fallback to env
-        if let Some((u, p)) = cached_creds.or(env_creds) {
-            tracing::info!("Credentials found. Attempting automatic login for user: {}", u);
-            let creds = scrapper::auth::AuthCredentials {
-                username: u.clone(),
-                pin: p.clone(),
-            };
-            
-            // Perform login in blocking task since headless_chrome is sync
-            let client = poliformat.clone();
-            match tokio::task::spawn_blocking(move || {
-                client.login_headless(&creds)
-            }).await? {
-                Ok(_) => {
-                    tracing::info!("Login successful!");
-                    // Save credentials to config for future use
-                    if let Err(e) = config::Config::save_credentials(&u, &p) {
-                        tracing::warn!("Failed to cache credentials: {}", e);
-                    }
-                },
-                Err(e) => {
-                    tracing::error!("Auto-login failed: {}", e);
-                    // Clear bad cached credentials
-                    let _ = config::Config::clear_credentials();
-                    anyhow::bail!("Login failed. Please login via the Menu first.");
-                }
-            }
-        } else {
-             tracing::error!("No credentials found in config or .env.");
-             tracing::warn!("Please login via the Menu first.");
-             anyhow::bail!("No credentials available. Please login via the Menu first.");
+use crate::{rag, scrapper, config, disk, subjects, sync};
+
+
+/// `polirag sync`: progress goes to debug.log, warnings and the change summary to stdout.
+pub async fn run_sync(rag: Arc<rag::RagSystem>, poliformat: Arc<scrapper::PoliformatClient>, options: sync::SyncOptions) -> anyhow::Result<()> {
+    let report = sync::run(rag, poliformat, options, |event| match event {
+        sync::SyncEvent::Warning(msg) => println!("⚠ {}", msg),
+        event => tracing::info!("{}", event),
+    }).await?;
+    print_sync_report(&report);
+    Ok(())
+}
+
+/// `polirag reembed`: index files downloaded but not indexed yet, then recalculate every
+/// embedding like the RAG Info screen does. Progress goes to stderr, one line per batch.
+/// An interrupted run resumes from its last checkpoint.
+pub async fn run_reembed(rag: Arc<rag::RagSystem>) -> anyhow::Result<()> {
+    let indexed = sync::scan_local_data(rag.clone(), |event| match event {
+        sync::SyncEvent::Warning(msg) => eprintln!("⚠ {}", msg),
+        event => tracing::info!("{}", event),
+    }).await?;
+    if !indexed.is_empty() {
+        eprintln!("Indexed {} new chunks.", indexed.len());
+    }
+
+    let start = std::time::Instant::now();
+    let skip_ids = indexed.into_iter().collect();
+    let count = rag.reembed_all(&skip_ids, |progress| {
+        if progress.batch == 1 && progress.resumed > 0 {
+            eprintln!("Resuming: {} documents were done by an interrupted run.", progress.resumed);
         }
+        eprintln!("Batch {}/{}: {}/{} documents", progress.batch, progress.batches, progress.done, progress.total);
+    }).await?;
+    println!("Recalculated {} embeddings in {:.1}s.", count, start.elapsed().as_secs_f64());
+    Ok(())
+}
+
+/// `polirag export <file>`
+pub fn run_export(rag: &rag::RagSystem, file: &std::path::Path, embeddings: bool) -> anyhow::Result<()> {
+    let count = rag.export(file, embeddings)?;
+    let without = if embeddings { "" } else { " without embeddings" };
+    println!("Exported {} documents{} to {}.", count, without, file.display());
+    Ok(())
+}
+
+/// `polirag import <file>`
+pub async fn run_import(rag: &rag::RagSystem, file: &std::path::Path) -> anyhow::Result<()> {
+    let start = std::time::Instant::now();
+    let report = rag.import(file).await?;
+    println!(
+        "Imported {} documents in {:.1}s: {} added, {} replaced, {} re-embedded.",
+        report.added + report.replaced, start.elapsed().as_secs_f64(), report.added, report.replaced, report.reembedded
+    );
+    Ok(())
+}
+
+/// `polirag remove <subject>`
+pub fn run_remove(rag: &rag::RagSystem, query: &str) -> anyhow::Result<()> {
+    let known = subjects::known(rag);
+    let subject = subjects::find(&known, query)?;
+    let removed = rag.remove_subject(&subject.id)?;
+    println!("Removed {} documents of {} ({}) from the index.", removed, subject.name, subject.id);
+    if subject.prefs.sync {
+        println!("The next sync indexes it again; `polirag subjects disable {} --sync` stops that.", subject.id);
     }
-    
-    // Clear old RAG data before syncing - NO! We want incremental sync now.
-    // tracing::info!("Clearing old RAG index...");
-    // rag.clear()?;
-    
-    // Clear old scraped data logic using global path - NO! We want to keep it.
-    let data_dir = config::Config::get_scraped_data_dir();
-    
-    if !data_dir.exists() {
-        tracing::info!("Creating data directory: {:?}", data_dir);
-        std::fs::create_dir_all(&data_dir)?;
+    Ok(())
+}
+
+/// `polirag subjects list`
+pub fn run_subjects_list(rag: &rag::RagSystem) {
+    let known = subjects::known(rag);
+    if known.is_empty() {
+        println!("No subjects yet. Run a sync first.");
+        return;
     }
-    
-    // 1. Fetch Subjects
-    tracing::info!("Fetching subjects...");
-    let subjects = poliformat.get_subjects().await?;
-    tracing::info!("Found {} subjects. Starting content scrape...", subjects.len());
-    
-    // 2. Fetch Deep Content
-    let detailed_subjects = poliformat.scrape_subject_content(subjects).await?;
-    
-    for (sub, dir_path) in detailed_subjects {
-        tracing::info!("Indexing subject: {} (Path: {})", sub.name, dir_path);
-        
-        let summary_path = std::path::Path::new(&dir_path).join("summary.md");
-        let mut content = if summary_path.exists() {
-             std::fs::read_to_string(&summary_path).unwrap_or_default()
-        } else {
-             tracing::warn!("No summary.md found for {}", sub.name);
-             continue; 
+    println!("{:<5} {:<9} {:>6}  {:<20} Name", "Sync", "Retrieve", "Docs", "Site id");
+    for subject in &known {
+        let yes_no = |on: bool| if on { "yes" } else { "no" };
+        let name = match &subject.prefs.alias {
+            Some(alias) => format!("{} ({})", alias, subject.name),
+            None => subject.name.clone(),
         };
-        
-        // Append list of found resources
-        let resources_path = std::path::Path::new(&dir_path).join("resources");
-        if resources_path.exists() {
-             use std::fmt::Write;
-             let mut file_list = String::new();
-             writeln!(&mut file_list, "\n\n[Local Files]:").unwrap();
-             if let Ok(entries) = std::fs::read_dir(&resources_path) {
-                 for entry in entries.flatten() {
-                      if let Ok(name) = entry.file_name().into_string() {
-                          writeln!(&mut file_list, "- {}", name).unwrap();
-                      }
-                 }
-             }
-             content.push_str(&file_list);
+        println!("{:<5} {:<9} {:>6}  {:<20} {}", yes_no(subject.prefs.sync), yes_no(subject.prefs.retrieve), subject.documents, subject.id, name);
+    }
+}
+
+/// `polirag subjects enable/disable`: set the `sync` and/or `retrieve` preference to `on`
+pub fn run_subjects_set(rag: &rag::RagSystem, query: &str, on: bool, sync: bool, retrieve: bool) -> anyhow::Result<()> {
+    let known = subjects::known(rag);
+    let subject = subjects::find(&known, query)?;
+    let mut prefs = subject.prefs.clone();
+    if sync {
+        prefs.sync = on;
+    }
+    if retrieve {
+        prefs.retrieve = on;
+    }
+    config::Config::save_subject_prefs(&subject.id, prefs.clone())?;
+    println!("{} ({}): sync {}, retrieve {}", subject.name, subject.id, if prefs.sync { "on" } else { "off" }, if prefs.retrieve { "on" } else { "off" });
+    Ok(())
+}
+
+/// `polirag subjects alias`
+pub fn run_subjects_alias(rag: &rag::RagSystem, query: &str, alias: &str) -> anyhow::Result<()> {
+    let known = subjects::known(rag);
+    let subject = subjects::find(&known, query)?;
+    let mut prefs = subject.prefs.clone();
+    let alias = alias.trim();
+    prefs.alias = (!alias.is_empty()).then(|| alias.to_string());
+    config::Config::save_subject_prefs(&subject.id, prefs)?;
+    match alias {
+        "" => println!("Removed the alias of {}", subject.name),
+        alias => println!("{} is now shown as {}", subject.name, alias),
+    }
+    Ok(())
+}
+
+/// `polirag retrieve`: embed and search like a chat question, then print the user message the
+/// model would get to stdout (a summary goes to stderr). Long sources are never condensed and
+/// snippets never reranked here, since that needs the LLM. `scope` restricts retrieval like `/types` and `/scope` in the chat.
+pub async fn run_retrieve(rag: Arc<rag::RagSystem>, query: &str, scope: rag::Scope) -> anyhow::Result<()> {
+    let rag_config = config::Config::load().rag;
+    let summary_first = rag_config.answer_mode == config::AnswerMode::SummaryFirst;
+    let options = rag::context::PromptOptions {
+        profile: rag_config.context_profile,
+        numbered_citations: rag_config.numbered_citations || summary_first,
+        summary_first,
+        scope,
+        top_k: rag_config.top_k(),
+        adaptive_k: rag_config.adaptive_k,
+        expanded: false,
+        freshness_hints: rag_config.freshness_hints,
+        relevance: rag_config.relevance,
+        // No session, so nothing to lean towards
+        boosts: Default::default(),
+        // No model either, so only the usual limit
+        max_chars: rag::context::MAX_CONTEXT_CHARS,
+    };
+    let profile = options.profile;
+
+    let built = rag::context::build_prompt(&rag, query, options, None, None).await;
+    println!("{}", built.text);
+
+    eprintln!(
+        "\n{} chars │ {} profile │ {} snippets │ {} full files",
+        built.text.len(), profile.label(), built.snippets, built.full_files.len()
+    );
+    for source in &built.sources {
+        eprintln!("  {}", source);
+    }
+    if !built.has_context {
+        eprintln!("No context retrieved; the question would be sent on its own.");
+    } else if built.low_confidence {
+        eprintln!("Only a weak match was found; the prompt asks the model to hedge.");
+    }
+    Ok(())
+}
+
+/// Print the per-subject changes of a sync
+pub fn print_sync_report(report: &scrapper::changes::SyncReport) {
+    // Reports from before timings were recorded have none
+    if report.timing.scrape_secs > 0 {
+        println!("⏱ {}", report.timing.describe());
+    }
+    for decision in &report.churn {
+        println!("♻ {}: {}", decision.subject, decision.describe());
+    }
+    if report.subjects.is_empty() {
+        println!("No changes.");
+        return;
+    }
+    for subject in &report.subjects {
+        println!("{}  {}", subject.subject, subject.counts());
+        for change in &subject.added {
+            println!("  + {}", change.describe());
         }
-        
-        // --- Process Resources (Unzip & PDF Extract) ---
-        tracing::info!("Processing resources for {}...", sub.name);
-        
-        // Only process resources if we haven't indexed them yet? 
-        // Not trivial to know, but we can check if documents exist in RAG.
-        // But processing resources is cheap if PDFs are already extracted.
-        // See: scrapper::processing::process_resources.
-        // For now, let's run processing, it usually just scans PDFs.
-        
-        let extracted_docs = match scrapper::processing::process_resources(std::path::Path::new(&dir_path)) {
-            Ok(d) => d,
-            Err(e) => {
-                tracing::error!("Error processing resources for {}: {}", sub.name, e);
-                Vec::new()
-            }
-        };
-        
-        let full_text = format!("Subject: {}\nURL: {}\n\n{}", sub.name, sub.url, content);
-        
-        // Add Summary Doc
-        if !rag.contains(&sub.id) {
-            tracing::info!("Adding NEW subject summary: {}", sub.name);
-            rag.add_document(
-                &sub.id,
-                &full_text,
-                "user",
-                [
-                    ("type".to_string(), "subject".to_string()),
-                    ("name".to_string(), sub.name.clone())
-                ].into()
-            ).await?;
-        } else {
-            tracing::debug!("Skipping existing subject summary: {}", sub.name);
+        for change in &subject.updated {
+            println!("  ~ {}", change.describe());
         }
-        
-        // Add PDF Docs
-        for (rel_path, text) in extracted_docs {
-            let doc_id = format!("{}/{}", sub.id, rel_path);
-            
-            // Chunking Strategy
-            let chunk_0_id = format!("{}#0", doc_id);
-            
-            if !rag.contains(&chunk_0_id) {
-                tracing::info!("Indexing NEW PDF (chunked): {} (Length: {})", rel_path, text.len());
-                
-                let splitter = TextSplitter::new(1000);
-                let chunks: Vec<&str> = splitter.chunks(&text).collect();
-                
-                let filename = std::path::Path::new(&rel_path).file_name().and_then(|n| n.to_str()).unwrap_or(&rel_path);
-                
-                if chunks.is_empty() {
-                    let pdf_text = format!("### DOC: {}\nSubject: {}\n\n{}", filename, sub.name, text);
-                    let final_id = format!("{}#0", doc_id);
-                    rag.add_document(
-                        &final_id,
-                        &pdf_text,
-                        "user",
-                        [("type".to_string(), "pdf".to_string()), ("filename".to_string(), rel_path.clone())].into()
-                    ).await?;
-                } else {
-                    for (i, chunk) in chunks.iter().enumerate() {
-                        let chunk_id = format!("{}#{}", doc_id, i);
-                        let pdf_text = format!("### DOC: {} (Part {}/{})\nCourse: {}\n\n{}", filename, i+1, chunks.len(), sub.name, chunk);
-                        
-                         rag.add_document(
-                            &chunk_id,
-                            &pdf_text,
-                            "user",
-                            [("type".to_string(), "pdf".to_string()), ("filename".to_string(), rel_path.clone())].into()
-                        ).await?;
-                    }
-                }
-            } else {
-                tracing::debug!("Skipping existing PDF: {}", rel_path);
-            }
+        for change in &subject.removed {
+            println!("  - {}", change.describe());
         }
-        
-        // Save intermittently (good for large scrapes)
-        let _ = rag.save();
     }
-    
-    tracing::info!("Saving RAG index...");
-    rag.save()?;
-    
-    tracing::info!("Sync Complete.");
+}
+
+/// `polirag archive list`
+pub fn run_archive_list() {
+    let files = rag::archive::list();
+    if files.is_empty() {
+        println!("No archived documents.");
+        return;
+    }
+    println!("{:<10} {:>6}  {:<20} File", "Archived", "Docs", "Site id");
+    for file in &files {
+        let name = file.path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        println!("{:<10} {:>6}  {:<20} {}", scrapper::processing::format_date(file.created_at / 86_400), file.documents, file.subject, name);
+    }
+}
+
+/// `polirag archive restore`: the archived documents replace the current chunks of their files
+/// until a sync changes those files again
+pub fn run_archive_restore(rag: &rag::RagSystem, name: &str) -> anyhow::Result<()> {
+    let path = rag::archive::find(name)?;
+    let docs = rag::archive::read(&path)?;
+    let count = rag.restore_documents(docs)?;
+    println!("Restored {} documents from {}.", count, path.display());
     Ok(())
 }
 
+/// `polirag login`: log in with a headless browser, check the session with an authenticated
+/// request and save the credentials. Failures carry a `LoginError` when the user can fix them.
+pub async fn run_login(poliformat: Arc<scrapper::PoliformatClient>, username: Option<String>, pin_stdin: bool, save: bool) -> anyhow::Result<()> {
+    let username = match username {
+        Some(username) => username,
+        None => dialoguer::Input::<String>::new().with_prompt("Username/DNI").interact_text()?,
+    };
+    let pin = if pin_stdin {
+        let mut line = String::new();
+        std::io::stdin().read_line(&mut line)?;
+        line.trim_end_matches(['\r', '\n']).to_string()
+    } else {
+        dialoguer::Password::new().with_prompt("PIN/Password").interact()?
+    };
 
-// Similar to run_sync but only scans local files, no network
-pub async fn scan_local_data(rag: Arc<rag::RagSystem>, log_callback: impl Fn(String)) -> anyhow::Result<Vec<String>> {
-    log_callback("🔍 Scanning local data directory...".to_string());
-    
-    let data_dir = config::Config::get_scraped_data_dir();
-    if !data_dir.exists() {
-        log_callback("⚠️  Data directory not found.".to_string());
-        return Ok(Vec::new());
+    let creds = scrapper::auth::AuthCredentials { username: username.clone(), pin: pin.clone() };
+    scrapper::auth::check_format(&creds)?;
+
+    eprint!("Logging in to {} ", poliformat.base_url());
+    let client = poliformat.clone();
+    let mut login = tokio::task::spawn_blocking(move || client.login_headless(&creds));
+    let mut dots = tokio::time::interval(std::time::Duration::from_secs(1));
+    let result = loop {
+        tokio::select! {
+            result = &mut login => break result?,
+            _ = dots.tick() => eprint!("."),
+        }
+    };
+    eprintln!();
+    result?;
+
+    match poliformat.check_connection().await {
+        Ok(true) => println!("Logged in as {}; the session works.", username),
+        Ok(false) => return Err(scrapper::auth::LoginError::Rejected("the portal still redirects to the login page".to_string()).into()),
+        Err(e) => return Err(e.context("Could not check the session")),
     }
-    
-    let mut added_ids = Vec::new();
-    
-    // Iterate over subject directories
-    let entries = std::fs::read_dir(&data_dir)?;
-    for entry in entries.flatten() {
-        if !entry.path().is_dir() { continue; }
-        
-        let path = entry.path();
-        let dir_name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
-        
-        // Skip hidden folders
-        if dir_name.starts_with('.') { continue; }
-        
-        log_callback(format!("Checking subject: {}", dir_name));
-        
-        // 1. Process Resources
-        let extracted_docs = match scrapper::processing::process_resources(&path) {
-            Ok(d) => d,
-            Err(e) => {
-                tracing::error!("Error processing resources for {}: {}", dir_name, e);
-                Vec::new()
-            }
-        };
-        
-        // 2. Index PDFs
-        for (rel_path, text) in extracted_docs {
-            let summary_path = path.join("summary.md");
-            let subject_id = if summary_path.exists() {
-                let content = std::fs::read_to_string(&summary_path).unwrap_or_default();
-                if let Some(url_line) = content.lines().find(|l| l.starts_with("URL:")) {
-                    // Extract ID from URL
-                    // URL: https://poliformat.upv.es/portal/site/GRA_11673_2025
-                    // ID is GRA_11673_2025
-                    if let Some(pos) = url_line.rfind('/') {
-                         url_line[pos+1..].trim().to_string()
-                    } else {
-                        dir_name.clone()
-                    }
-                } else {
-                    dir_name.clone()
-                }
+
+    let config_path = config::Config::config_path().unwrap_or_default();
+    if save {
+        config::Config::save_credentials(&username, &pin)?;
+        println!("Saved the username and PIN (encrypted) to {}", config_path.display());
+    } else {
+        println!("Nothing saved (--no-save).");
+    }
+    println!("Session cookies are kept in memory only and were not stored.");
+    Ok(())
+}
+
+/// `polirag logout`: remove the saved credentials
+pub fn run_logout() -> anyhow::Result<()> {
+    let config_path = config::Config::config_path().unwrap_or_default();
+    if config::Config::load().cached_credentials.is_none() {
+        println!("No saved credentials in {}", config_path.display());
+    } else {
+        config::Config::clear_credentials()?;
+        println!("Removed the saved username and PIN from {}", config_path.display());
+    }
+    println!("Session cookies are never stored, so there were none to remove.");
+    if std::env::var("POLIFORMAT_USER").is_ok() || std::env::var("POLIFORMAT_DNI").is_ok() {
+        println!("POLIFORMAT_USER/POLIFORMAT_DNI is still set in the environment and will be used to log in.");
+    }
+    Ok(())
+}
+
+/// `polirag index info`: the index header and document count. Reading never migrates the file.
+pub fn run_index_info(index_path: &str) -> anyhow::Result<()> {
+    let (inspected, path) = match config::Config::load().rag.store_type {
+        config::StoreType::Hnsw => (
+            rag::hnsw_store::HnswVectorStore::inspect(index_path)?,
+            std::path::Path::new(index_path).with_extension("data"),
+        ),
+        config::StoreType::Linear => (rag::store::LinearVectorStore::inspect(index_path)?, index_path.into()),
+        config::StoreType::Sqlite => {
+            let path = std::path::Path::new(index_path).with_extension("sqlite");
+            (rag::sqlite_store::SqliteVectorStore::inspect(&path)?, path)
+        }
+    };
+    let Some((header, documents)) = inspected else {
+        println!("No index at {} yet. Run a sync first.", path.display());
+        return Ok(());
+    };
+
+    println!("Path:      {}", path.display());
+    match header {
+        Some(header) => {
+            let current = if header.version == rag::format::FORMAT_VERSION {
+                " (current)".to_string()
             } else {
-                dir_name.clone()
+                format!(" (migrated to v{} on the next start)", rag::format::FORMAT_VERSION)
             };
-            
-            let doc_id = format!("{}/{}", subject_id, rel_path);
-            
-            // Chunking Strategy:
-            // Check if chunk 0 exists to determine if we need to index
-            let chunk_0_id = format!("{}#0", doc_id);
-            
-            if !rag.contains(&chunk_0_id) {
-                // Check if an OLD unchunked version exists and remove it
-                if rag.contains(&doc_id) {
-                    let _ = rag.remove_document(&doc_id);
-                    log_callback(format!("  🗑️  Removing old unchunked entry for: {}", rel_path));
-                }
-
-                log_callback(format!("  ➕ Indexing new file (chunked): {}/{}", dir_name, rel_path));
-                
-                let splitter = TextSplitter::new(1000);
-                let chunks: Vec<&str> = splitter.chunks(&text).collect();
-                
-                let filename = std::path::Path::new(&rel_path).file_name().and_then(|n| n.to_str()).unwrap_or(&rel_path);
-                
-                if chunks.is_empty() {
-                    let pdf_text = format!("### DOC: {}\nSubject: {}\n\n{}", filename, dir_name, text);
-                    let final_id = format!("{}#0", doc_id); 
-                    rag.add_document(
-                        &final_id,
-                        &pdf_text,
-                        "user",
-                        [("type".to_string(), "pdf".to_string()), ("filename".to_string(), rel_path)].into()
-                    ).await?;
-                    added_ids.push(final_id);
-                } else {
-                    for (i, chunk) in chunks.iter().enumerate() {
-                        let chunk_id = format!("{}#{}", doc_id, i);
-                        let pdf_text = format!("### DOC: {} (Part {}/{})\nCourse: {}\n\n{}", filename, i+1, chunks.len(), dir_name, chunk);
-                        
-                        rag.add_document(
-                           &chunk_id,
-                           &pdf_text,
-                           "user",
-                           [("type".to_string(), "pdf".to_string()), ("filename".to_string(), rel_path.clone())].into()
-                       ).await?;
-                       added_ids.push(chunk_id);
-                    }
-                }
+            println!("Format:    v{}{}", header.version, current);
+            println!("Store:     {}", header.store);
+            let model = rag::embeddings::model_id();
+            if header.embedder == model {
+                println!("Embedder:  {}", header.embedder);
+            } else {
+                println!("Embedder:  {} (the loaded model is {})", header.embedder, model);
             }
+            println!(
+                "Written:   {} {:02}:{:02} UTC",
+                scrapper::processing::format_date(header.written_at / 86_400),
+                header.written_at % 86_400 / 3600,
+                header.written_at % 3600 / 60
+            );
+        }
+        None => println!("Format:    v0 (unversioned, migrated to v{} on the next start)", rag::format::FORMAT_VERSION),
+    }
+    println!("Documents: {}", documents);
+    Ok(())
+}
+
+/// `polirag status`: what the last sync changed
+pub fn run_status() -> anyhow::Result<()> {
+    match scrapper::changes::SyncReport::load_last() {
+        Some(report) => {
+            let ago = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs().saturating_sub(report.finished_at))
+                .unwrap_or(0);
+            println!("Last sync: {} h ago", ago / 3600);
+            print_sync_report(&report);
+        }
+        None => println!("No sync recorded yet."),
+    }
+    // The next sync refuses to start on a volume this full
+    let data_dir = config::Config::get_scraped_data_dir();
+    match disk::check_sync(&data_dir) {
+        Err(low) => println!("⚠ {}. Free some with `polirag clean`, or sync with --ignore-low-space.", low),
+        Ok(()) => if let Ok(free) = disk::available_space(&data_dir) {
+            println!("Free space: {} on {}", scrapper::format_bytes(free), data_dir.display());
+        },
+    }
+    Ok(())
+}
+
+/// Rebuild the HNSW graph from scratch so points left behind by re-inserted or removed
+/// documents stop degrading search, then save it.
+pub fn run_rebuild_hnsw(index_path: &str) -> anyhow::Result<()> {
+    use rag::store::VectorStore;
+
+    let rag_config = config::Config::load().rag;
+    if rag_config.store_type != config::StoreType::Hnsw {
+        anyhow::bail!("rag.store_type is {}: there is no HNSW graph to rebuild", rag_config.store_type.label());
+    }
+    let mut store = rag::hnsw_store::HnswVectorStore::new(index_path, rag_config.similarity_metric, rag_config.hnsw, rag_config.ef_search)?
+        .with_precision(rag::precision::Precision::from_half(rag_config.half_precision));
+
+    let count = store.count();
+    if count == 0 {
+        println!("Index is empty, nothing to rebuild.");
+        return Ok(());
+    }
+
+    println!("Rebuilding HNSW graph over {} documents...", count);
+    let start = std::time::Instant::now();
+    store.rebuild()?;
+    store.save()?;
+    println!("Done in {:.1}s.", start.elapsed().as_secs_f64());
+
+    Ok(())
+}
+
+/// Compare HNSW search results against an exact scan over the same embeddings.
+/// Indexed chunks are used as queries, so no embedding model is needed.
+pub fn run_recall_bench(index_path: &str, queries: usize, top_k: usize) -> anyhow::Result<()> {
+    use rag::store::VectorStore;
+    use std::collections::HashSet;
+
+    let rag_config = config::Config::load().rag;
+    if rag_config.store_type != config::StoreType::Hnsw {
+        anyhow::bail!("rag.store_type is {}: searches are already exact, there is no recall to measure", rag_config.store_type.label());
+    }
+    let metric = rag_config.similarity_metric;
+    let mut store = rag::hnsw_store::HnswVectorStore::new(index_path, metric, rag_config.hnsw, rag_config.ef_search)?;
+
+    let docs = store.get_all()?;
+    if docs.is_empty() {
+        println!("Index is empty, nothing to benchmark. Run a sync first.");
+        return Ok(());
+    }
+
+    let top_k = top_k.max(1);
+    let step = (docs.len() / queries.max(1)).max(1);
+    let sample: Vec<&rag::Document> = docs.iter().step_by(step).take(queries.max(1)).collect();
+
+    // Ground truth from a brute force scan
+    let exact: Vec<HashSet<&str>> = sample.iter()
+        .map(|q| {
+            let mut scored: Vec<(&str, f32)> = docs.iter()
+                .filter(|d| d.user_id == q.user_id)
+                .map(|d| (d.id.as_str(), rag::store::similarity(metric, &q.embedding, &d.embedding)))
+                .collect();
+            scored.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+            scored.into_iter().take(top_k).map(|(id, _)| id).collect()
+        })
+        .collect();
+
+    // Previous fixed value (2 * top_k), the automatic floor and a few wider settings
+    let mut ef_values = vec![top_k * 2, 64, 128, 256];
+    if let Some(ef) = rag_config.ef_search {
+        ef_values.push(ef);
+    }
+    ef_values.sort_unstable();
+    ef_values.dedup();
+
+    println!("{}: {} documents, {} queries, recall@{}", store.store_type(), docs.len(), sample.len(), top_k);
+    println!("{:>10}  {:>8}  {:>12}", "ef_search", "recall", "ms/query");
+
+    for ef in ef_values {
+        store.set_ef_search(Some(ef));
+
+        let mut hits = 0;
+        let mut expected = 0;
+        let start = std::time::Instant::now();
+        for (q, truth) in sample.iter().zip(&exact) {
+            let results = store.search(&q.embedding, &q.user_id, top_k, f32::MIN, None)?;
+            hits += results.iter().filter(|(d, _)| truth.contains(d.id.as_str())).count();
+            expected += truth.len();
         }
+        let per_query = start.elapsed().as_secs_f64() * 1000.0 / sample.len() as f64;
+        let recall = if expected > 0 { hits as f64 / expected as f64 } else { 1.0 };
+
+        println!("{:>10}  {:>7.1}%  {:>12.3}", ef, recall * 100.0, per_query);
     }
-    
-    if !added_ids.is_empty() {
-        rag.save()?;
+
+    if store.rebuild_pending() {
+        println!("\nThe graph was built with different parameters than configured; rebuild it from RAG Info to apply them.");
     }
-    
-    Ok(added_ids)
+
+    Ok(())
 }
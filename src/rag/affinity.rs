@@ -0,0 +1,141 @@
+//! Topic affinity of a chat session: the subjects recent answers drew on get a small score
+//! boost, so a vague follow-up ("and the second normal form?") stays on the subject being
+//! discussed without an explicit filter.
+
+use std::collections::BTreeMap;
+
+use crate::config::AffinityParams;
+
+/// Largest boost whatever the config says: a match more than this much better still wins
+pub const MAX_BOOST: f32 = 0.08;
+/// Subjects whose weight decays below this are forgotten
+const MIN_WEIGHT: f32 = 0.05;
+
+/// Weight (0-1) of each subject (site id) in the recent answers
+#[derive(Clone, Default, Debug)]
+pub struct SubjectAffinity {
+    weights: BTreeMap<String, f32>,
+}
+
+impl SubjectAffinity {
+    /// An answer drew on `subjects`: older weights decay and these move towards 1
+    pub fn record(&mut self, subjects: &[String], decay: f32) {
+        let decay = decay.clamp(0.0, 1.0);
+        for weight in self.weights.values_mut() {
+            *weight *= decay;
+        }
+        // Each subject once, however many of its sources were cited
+        let mut subjects = subjects.to_vec();
+        subjects.sort();
+        subjects.dedup();
+        for subject in subjects {
+            *self.weights.entry(subject).or_insert(0.0) += 1.0 - decay;
+        }
+        self.weights.retain(|_, weight| *weight >= MIN_WEIGHT);
+    }
+
+    pub fn clear(&mut self) {
+        self.weights.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.weights.is_empty()
+    }
+
+    /// Subjects by weight, heaviest first
+    pub fn weights(&self) -> Vec<(&str, f32)> {
+        let mut weights: Vec<(&str, f32)> = self.weights.iter().map(|(s, w)| (s.as_str(), *w)).collect();
+        weights.sort_by(|a, b| b.1.total_cmp(&a.1));
+        weights
+    }
+
+    /// Score added to each subject's documents for the next question
+    pub fn boosts(&self, params: AffinityParams) -> Boosts {
+        let max = params.boost.clamp(0.0, MAX_BOOST);
+        if max == 0.0 {
+            return Boosts::default();
+        }
+        Boosts(self.weights.iter().map(|(subject, weight)| (subject.clone(), max * weight.min(1.0))).collect())
+    }
+}
+
+/// Score added to the documents of each subject in `search_snippets`
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct Boosts(BTreeMap<String, f32>);
+
+impl Boosts {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn get(&self, subject: &str) -> f32 {
+        self.0.get(subject).copied().unwrap_or(0.0)
+    }
+
+    /// Add the boosts to `scored` (best first) and sort it again
+    pub fn apply<T>(&self, scored: &mut [(T, f32)], subject_of: impl Fn(&T) -> &str) {
+        if self.is_empty() {
+            return;
+        }
+        for (item, score) in scored.iter_mut() {
+            *score += self.get(subject_of(item));
+        }
+        scored.sort_by(|a, b| b.1.total_cmp(&a.1));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PARAMS: AffinityParams = AffinityParams { boost: 0.04, decay: 0.5 };
+
+    fn subjects(names: &[&str]) -> Vec<String> {
+        names.iter().map(|s| s.to_string()).collect()
+    }
+
+    /// Ids of `scored` (named `<subject>/<file>`) after the boosts, best first
+    fn rerank(boosts: &Boosts, scored: &[(&'static str, f32)]) -> Vec<&'static str> {
+        let mut scored = scored.to_vec();
+        boosts.apply(&mut scored, |id| id.split('/').next().unwrap());
+        scored.into_iter().map(|(id, _)| id).collect()
+    }
+
+    #[test]
+    fn a_close_match_from_the_discussed_subject_moves_up() {
+        let mut affinity = SubjectAffinity::default();
+        affinity.record(&subjects(&["BDA", "BDA"]), PARAMS.decay);
+        let boosts = affinity.boosts(PARAMS);
+        assert!((boosts.get("BDA") - 0.02).abs() < 1e-6);
+        assert_eq!(boosts.get("ETC"), 0.0);
+
+        let scored = [("ETC/normal-forms.pdf", 0.71), ("BDA/normalisation.pdf", 0.70)];
+        assert_eq!(rerank(&boosts, &scored), ["BDA/normalisation.pdf", "ETC/normal-forms.pdf"]);
+        // A clearly better match from another subject keeps its place
+        let scored = [("ETC/normal-forms.pdf", 0.80), ("BDA/normalisation.pdf", 0.70)];
+        assert_eq!(rerank(&boosts, &scored), ["ETC/normal-forms.pdf", "BDA/normalisation.pdf"]);
+    }
+
+    #[test]
+    fn older_subjects_fade_and_are_forgotten() {
+        let mut affinity = SubjectAffinity::default();
+        affinity.record(&subjects(&["BDA"]), PARAMS.decay);
+        affinity.record(&subjects(&["ETC"]), PARAMS.decay);
+        assert_eq!(affinity.weights(), [("ETC", 0.5), ("BDA", 0.25)]);
+
+        for _ in 0..3 {
+            affinity.record(&subjects(&["ETC"]), PARAMS.decay);
+        }
+        assert_eq!(affinity.weights().iter().map(|(s, _)| *s).collect::<Vec<_>>(), ["ETC"]);
+        affinity.clear();
+        assert!(affinity.is_empty());
+    }
+
+    #[test]
+    fn the_boost_is_capped_and_zero_disables_it() {
+        let mut affinity = SubjectAffinity::default();
+        affinity.record(&subjects(&["BDA"]), 0.0);
+        assert_eq!(affinity.boosts(AffinityParams { boost: 1.0, decay: 0.0 }).get("BDA"), MAX_BOOST);
+        assert!(affinity.boosts(AffinityParams { boost: 0.0, decay: 0.0 }).is_empty());
+    }
+}
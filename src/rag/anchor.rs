@@ -0,0 +1,118 @@
+//! Where each chunk sits in the extracted text of its file. The sync records the character
+//! range of every chunk (`CHAR_START_KEY`..`CHAR_END_KEY`, counted in the normalized text that
+//! was split), and since the chunks cover that text except for the whitespace between them, the
+//! text can be put back together from the index with every range still pointing at its chunk.
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+use super::Document;
+
+/// Metadata key with the character offset where a chunk starts in the extracted text
+pub const CHAR_START_KEY: &str = "char_start";
+/// Metadata key with the character offset where a chunk ends, exclusive
+pub const CHAR_END_KEY: &str = "char_end";
+
+/// Metadata entries recording that a chunk covers `range` (characters) of its file's text
+pub fn range_metadata(range: Range<usize>) -> [(String, String); 2] {
+    [(CHAR_START_KEY.to_string(), range.start.to_string()), (CHAR_END_KEY.to_string(), range.end.to_string())]
+}
+
+/// The character range a chunk recorded, `None` for chunks indexed before ranges were
+pub fn chunk_range(metadata: &HashMap<String, String>) -> Option<Range<usize>> {
+    let start = metadata.get(CHAR_START_KEY)?.parse().ok()?;
+    let end = metadata.get(CHAR_END_KEY)?.parse().ok()?;
+    (start <= end).then_some(start..end)
+}
+
+/// The chunk's own text: the last `len` characters of its content, after the header the
+/// indexer put in front
+fn chunk_body(content: &str, len: usize) -> Option<&str> {
+    let total = content.chars().count();
+    let skip = total.checked_sub(len)?;
+    let start = content.char_indices().nth(skip).map_or(content.len(), |(i, _)| i);
+    Some(&content[start..])
+}
+
+/// The chunk's own text without its header; the whole content for chunks indexed before
+/// ranges were recorded
+pub fn chunk_text(doc: &Document) -> &str {
+    chunk_range(&doc.metadata)
+        .and_then(|range| chunk_body(&doc.content, range.len()))
+        .unwrap_or(&doc.content)
+}
+
+/// The extracted text of a file, rebuilt from its chunks
+pub struct DocumentText {
+    pub text: String,
+    /// Id and character range in `text` of every chunk, in order
+    pub chunks: Vec<(String, Range<usize>)>,
+}
+
+impl DocumentText {
+    /// Range of the chunk `id`
+    pub fn range_of(&self, id: &str) -> Option<Range<usize>> {
+        self.chunks.iter().find(|(chunk, _)| chunk == id).map(|(_, range)| range.clone())
+    }
+}
+
+/// Put a file's text back together from its `chunks` (any order). The whitespace the splitter
+/// trimmed between two chunks comes back as line breaks, so offsets stay exact. `None` when a
+/// chunk has no recorded range or its content doesn't match it.
+pub fn reassemble(chunks: &[Document]) -> Option<DocumentText> {
+    let mut placed: Vec<(&Document, Range<usize>)> = chunks.iter()
+        .map(|doc| Some((doc, chunk_range(&doc.metadata)?)))
+        .collect::<Option<_>>()?;
+    placed.sort_by_key(|(_, range)| range.start);
+
+    let mut text = String::new();
+    let mut length = 0;
+    let mut ranges = Vec::with_capacity(placed.len());
+    for (doc, range) in placed {
+        // Overlapping chunks would shift everything after them
+        let gap = range.start.checked_sub(length)?;
+        let body = chunk_body(&doc.content, range.len())?;
+        text.extend(std::iter::repeat_n('\n', gap));
+        text.push_str(body);
+        length = range.end;
+        ranges.push((doc.id.clone(), range));
+    }
+    Some(DocumentText { text, chunks: ranges })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(id: &str, content: &str, range: Option<Range<usize>>) -> Document {
+        Document {
+            id: id.to_string(),
+            content: content.to_string(),
+            embedding: Vec::new(),
+            metadata: range.map(range_metadata).into_iter().flatten().collect(),
+            user_id: "user".to_string(),
+        }
+    }
+
+    #[test]
+    fn chunks_are_put_back_in_order_with_their_gaps() {
+        let chunks = [
+            chunk("f#1", "### DOC: f (Part 2/2)\nCourse: BDA\n\nsegunda", Some(9..16)),
+            chunk("f#0", "### DOC: f (Part 1/2)\nCourse: BDA\n\nprimera", Some(0..7)),
+        ];
+        let document = reassemble(&chunks).unwrap();
+        assert_eq!(document.text, "primera\n\nsegunda");
+        assert_eq!(document.range_of("f#1"), Some(9..16));
+        assert_eq!(chunk_text(&chunks[0]), "segunda");
+    }
+
+    #[test]
+    fn unrecorded_or_overlapping_chunks_cannot_be_placed() {
+        let legacy = chunk("f#0", "### DOC: f\nold text", None);
+        assert_eq!(chunk_text(&legacy), legacy.content);
+        assert!(reassemble(std::slice::from_ref(&legacy)).is_none());
+
+        let overlapping = [chunk("f#0", "abcdef", Some(0..6)), chunk("f#1", "efgh", Some(4..8))];
+        assert!(reassemble(&overlapping).is_none());
+    }
+}
@@ -0,0 +1,101 @@
+//! Documents a sync replaced or removed, kept as JSONL in `archive/` of the app data
+//! directory for `ChurnConfig::archive_days`, so an answer that relied on them can be
+//! reproduced and the old versions put back with `polirag archive restore`.
+
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+
+use super::Document;
+use crate::config::Config;
+
+const ARCHIVE_DIR: &str = "archive";
+
+/// An archive file: the documents one subject lost in one sync
+pub struct ArchiveFile {
+    pub path: PathBuf,
+    /// Unix time the documents were archived
+    pub created_at: u64,
+    pub subject: String,
+    pub documents: usize,
+}
+
+pub fn archive_dir() -> PathBuf {
+    Config::get_app_data_dir().join(ARCHIVE_DIR)
+}
+
+/// Write `docs` of `subject` (site id) to a new archive file, one JSON document per line
+pub fn write(subject: &str, docs: &[Document]) -> anyhow::Result<PathBuf> {
+    let dir = archive_dir();
+    std::fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    let path = dir.join(format!("{}-{}.jsonl", now(), subject));
+    let mut out = BufWriter::new(std::fs::File::create(&path).with_context(|| format!("Failed to create {}", path.display()))?);
+    for doc in docs {
+        serde_json::to_writer(&mut out, doc)?;
+        out.write_all(b"\n")?;
+    }
+    out.flush()?;
+    Ok(path)
+}
+
+pub fn read(path: &Path) -> anyhow::Result<Vec<Document>> {
+    let file = std::fs::File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    BufReader::new(file).lines()
+        .enumerate()
+        .filter(|(_, line)| line.as_ref().map_or(true, |l| !l.trim().is_empty()))
+        .map(|(n, line)| {
+            let line = line?;
+            serde_json::from_str(&line).with_context(|| format!("{}: line {} is not an archived document", path.display(), n + 1))
+        })
+        .collect()
+}
+
+/// Archive files, newest first
+pub fn list() -> Vec<ArchiveFile> {
+    let Ok(entries) = std::fs::read_dir(archive_dir()) else { return Vec::new() };
+    let mut files: Vec<ArchiveFile> = entries.flatten()
+        .filter_map(|entry| {
+            let path = entry.path();
+            let stem = path.file_stem()?.to_string_lossy().to_string();
+            if path.extension()? != "jsonl" {
+                return None;
+            }
+            let (created_at, subject) = stem.split_once('-')?;
+            let documents = std::fs::read_to_string(&path).ok()?.lines().filter(|l| !l.trim().is_empty()).count();
+            Some(ArchiveFile { created_at: created_at.parse().ok()?, subject: subject.to_string(), documents, path })
+        })
+        .collect();
+    files.sort_by_key(|f| std::cmp::Reverse(f.created_at));
+    files
+}
+
+/// Delete archive files older than `days`. Returns how many were deleted.
+pub fn prune(days: u64) -> usize {
+    let cutoff = now().saturating_sub(days * 86_400);
+    let mut removed = 0;
+    for file in list().into_iter().filter(|f| f.created_at < cutoff) {
+        match std::fs::remove_file(&file.path) {
+            Ok(()) => removed += 1,
+            Err(e) => tracing::warn!("Could not remove expired archive {:?}: {}", file.path, e),
+        }
+    }
+    removed
+}
+
+/// The archive file `name` names: a path, a file name in `archive/`, or a subject id for its newest archive
+pub fn find(name: &str) -> anyhow::Result<PathBuf> {
+    let path = Path::new(name);
+    if path.is_file() {
+        return Ok(path.to_path_buf());
+    }
+    let files = list();
+    files.iter()
+        .find(|f| f.path.file_name().is_some_and(|n| n == name) || f.subject == name)
+        .map(|f| f.path.clone())
+        .ok_or_else(|| anyhow::anyhow!("No archive '{}'. Run `polirag archive list` to see them.", name))
+}
+
+fn now() -> u64 {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
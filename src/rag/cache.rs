@@ -0,0 +1,85 @@
+//! Retrieval results of recent queries, so asking the same question again skips embedding and search
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use super::{affinity::Boosts, Scope, SearchTuning, Snippet};
+use crate::config::RelevanceParams;
+
+/// Queries kept; the least recently used one is dropped first
+const CAPACITY: usize = 32;
+
+/// Everything besides the index contents that shapes a `search_snippets` result
+#[derive(Clone, PartialEq)]
+pub struct QueryKey {
+    query: String,
+    user_id: String,
+    top_k: usize,
+    max_chars: usize,
+    relevance: RelevanceParams,
+    tuning: SearchTuning,
+    scope: Scope,
+    boosts: Boosts,
+}
+
+impl QueryKey {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(query: &str, user_id: &str, top_k: usize, max_chars: usize, relevance: RelevanceParams, tuning: SearchTuning, scope: &Scope, boosts: &Boosts) -> Self {
+        Self {
+            query: normalize_query(query),
+            user_id: user_id.to_string(),
+            top_k,
+            max_chars,
+            relevance,
+            tuning,
+            scope: scope.clone(),
+            boosts: boosts.clone(),
+        }
+    }
+}
+
+/// Case and spacing don't change retrieval: snippet matching lowercases the query words anyway
+fn normalize_query(query: &str) -> String {
+    query.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+}
+
+/// Small LRU of retrieval results for one index version
+#[derive(Default)]
+pub struct RetrievalCache {
+    inner: Mutex<Entries>,
+}
+
+#[derive(Default)]
+struct Entries {
+    index_version: u64,
+    /// Most recently used first
+    results: VecDeque<(QueryKey, Vec<Snippet>)>,
+}
+
+impl RetrievalCache {
+    /// Cached results for `key`, unless the index changed since they were stored
+    pub fn get(&self, key: &QueryKey, index_version: u64) -> Option<Vec<Snippet>> {
+        let mut entries = self.inner.lock().unwrap();
+        if entries.index_version != index_version {
+            entries.results.clear();
+            entries.index_version = index_version;
+            return None;
+        }
+        let position = entries.results.iter().position(|(k, _)| k == key)?;
+        let hit = entries.results.remove(position)?;
+        let snippets = hit.1.clone();
+        entries.results.push_front(hit);
+        Some(snippets)
+    }
+
+    pub fn insert(&self, key: QueryKey, snippets: Vec<Snippet>, index_version: u64) {
+        let mut entries = self.inner.lock().unwrap();
+        if entries.index_version != index_version {
+            entries.results.clear();
+            entries.index_version = index_version;
+        }
+        entries.results.retain(|(k, _)| *k != key);
+        entries.results.push_front((key, snippets));
+        entries.results.truncate(CAPACITY);
+    }
+}
@@ -0,0 +1,230 @@
+use std::ops::Range;
+
+/// Longest marker body we accept, e.g. "1, 2, 3-5"
+const MAX_MARKER_LEN: usize = 24;
+
+/// A citation marker found in an answer, e.g. `[1]`, `[1,2]`, `[2-4]` or `(3)`
+#[derive(Clone, Debug, PartialEq)]
+pub struct CitationMarker {
+    /// Byte range of the whole marker, brackets included
+    pub range: Range<usize>,
+    pub numbers: Vec<usize>,
+    /// Written as `(n)` rather than `[n]`
+    pub parenthesized: bool,
+}
+
+/// Numbered sources sent with a question and the citations found in the answer
+#[derive(Clone, Debug, Default)]
+pub struct CitationMap {
+    /// Source labels, `sources[0]` is citation number 1
+    pub sources: Vec<String>,
+    /// Footer text of each source (kind and chunk), empty when only the label is known
+    pub details: Vec<String>,
+    /// Valid citation numbers in order of first appearance
+    pub cited: Vec<usize>,
+    /// Numbers cited with square brackets that don't match any source
+    pub invalid: Vec<usize>,
+    /// Set once the answer is complete and has been scanned
+    pub resolved: bool,
+}
+
+impl CitationMap {
+    pub fn new(sources: Vec<String>, details: Vec<String>) -> Self {
+        Self { sources, details, ..Default::default() }
+    }
+
+    /// Scan the finished answer and record which sources it cites
+    pub fn resolve(&mut self, answer: &str) {
+        self.cited.clear();
+        self.invalid.clear();
+
+        for marker in valid_markers(answer, self.sources.len()) {
+            for n in marker.numbers {
+                if !self.cited.contains(&n) {
+                    self.cited.push(n);
+                }
+            }
+        }
+
+        // Only bracketed markers are reported: "(3)" is too often a list item or an equation
+        for marker in parse_markers(answer).into_iter().filter(|m| !m.parenthesized) {
+            for n in marker.numbers {
+                if (n == 0 || n > self.sources.len()) && !self.invalid.contains(&n) {
+                    self.invalid.push(n);
+                }
+            }
+        }
+
+        self.resolved = true;
+    }
+
+    /// Sources ordered by citation number (their details when known), with whether the answer cited each one
+    pub fn footer(&self) -> Vec<(usize, &str, bool)> {
+        self.sources.iter()
+            .enumerate()
+            .map(|(i, s)| {
+                let text = self.details.get(i).filter(|d| !d.is_empty()).unwrap_or(s);
+                (i + 1, text.as_str(), self.cited.contains(&(i + 1)))
+            })
+            .collect()
+    }
+}
+
+/// Markers whose numbers all refer to one of `source_count` sources.
+/// Parenthesized markers only count when every number is in range.
+pub fn valid_markers(text: &str, source_count: usize) -> Vec<CitationMarker> {
+    parse_markers(text)
+        .into_iter()
+        .filter(|m| m.numbers.iter().all(|n| *n >= 1 && *n <= source_count))
+        .collect()
+}
+
+/// Find citation markers: `[1]`, `[1,2]`, `[1, 2]`, `[1-3]`, `[1][2]` and `(1)`.
+/// Markdown links (`[1](url)`) and text inside backticks are skipped.
+pub fn parse_markers(text: &str) -> Vec<CitationMarker> {
+    let bytes = text.as_bytes();
+    let mut markers = Vec::new();
+    let mut in_code = false;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let c = bytes[i];
+        if c == b'`' {
+            in_code = !in_code;
+            i += 1;
+            continue;
+        }
+        if in_code || (c != b'[' && c != b'(') {
+            i += 1;
+            continue;
+        }
+
+        let close = if c == b'[' { b']' } else { b')' };
+        let body_start = i + 1;
+        let body_end = match bytes[body_start..].iter().take(MAX_MARKER_LEN + 1).position(|b| *b == close) {
+            Some(offset) => body_start + offset,
+            None => {
+                i += 1;
+                continue;
+            }
+        };
+
+        // "[1](http://...)" is a link, not a citation
+        let is_link = c == b'[' && bytes.get(body_end + 1) == Some(&b'(');
+
+        match parse_marker_body(&text[body_start..body_end]) {
+            Some(numbers) if !is_link => {
+                markers.push(CitationMarker {
+                    range: i..body_end + 1,
+                    numbers,
+                    parenthesized: c == b'(',
+                });
+                i = body_end + 1;
+            }
+            _ => i += 1,
+        }
+    }
+
+    markers
+}
+
+/// Parse "1", "1,2", "1, 2", "1-3" or "1–3"; anything else is not a citation
+fn parse_marker_body(body: &str) -> Option<Vec<usize>> {
+    let mut numbers = Vec::new();
+
+    for part in body.split(',') {
+        let part = part.trim();
+        if part.is_empty() {
+            return None;
+        }
+
+        if let Some((from, to)) = part.split_once(['-', '–']) {
+            let from: usize = parse_number(from.trim())?;
+            let to: usize = parse_number(to.trim())?;
+            if to < from || to - from > 20 {
+                return None;
+            }
+            numbers.extend(from..=to);
+        } else {
+            numbers.push(parse_number(part)?);
+        }
+    }
+
+    Some(numbers)
+}
+
+fn parse_number(s: &str) -> Option<usize> {
+    // Plain digits only; four or more digits is a year ("(2024)"), not a source number
+    if s.is_empty() || s.len() > 3 || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    s.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numbers(text: &str) -> Vec<Vec<usize>> {
+        parse_markers(text).into_iter().map(|m| m.numbers).collect()
+    }
+
+    fn map(count: usize) -> CitationMap {
+        let sources = (1..=count).map(|n| format!("tema{}.pdf", n)).collect();
+        CitationMap::new(sources, Vec::new())
+    }
+
+    #[test]
+    fn markers_list_their_numbers_and_ranges() {
+        assert_eq!(numbers("See [1], [1,2], [1, 3] and [2-4]."), [vec![1], vec![1, 2], vec![1, 3], vec![2, 3, 4]]);
+        assert_eq!(numbers("Both[1][2] agree (3)."), [vec![1], vec![2], vec![3]]);
+        let markers = parse_markers("Both[1][2]");
+        assert_eq!(markers[0].range, 4..7);
+        assert_eq!(markers[1].range, 7..10);
+        assert!(parse_markers("(3)")[0].parenthesized);
+    }
+
+    #[test]
+    fn links_code_years_and_prose_are_not_markers() {
+        assert!(numbers("[1](https://poliformat.upv.es)").is_empty());
+        assert!(numbers("Index with `v[1]` in code").is_empty());
+        assert!(numbers("Published (2024) in [a note]").is_empty());
+        assert!(numbers("[4-1] and [1-30]").is_empty());
+    }
+
+    #[test]
+    fn cited_sources_are_numbered_in_order_of_first_citation() {
+        let mut citations = map(3);
+        citations.resolve("Eigenvalues [3] come from the characteristic polynomial [1,3], see also [3].");
+        assert_eq!(citations.cited, [3, 1]);
+        assert!(citations.invalid.is_empty());
+        assert!(citations.resolved);
+    }
+
+    #[test]
+    fn out_of_range_numbers_are_invalid_only_in_brackets() {
+        let mut citations = map(2);
+        citations.resolve("As shown [2] and [5], with steps (1) to (7) and [0].");
+        // "(7)" reads as a list item and is not reported; "(1)" still cites a source
+        assert_eq!(citations.cited, [2, 1]);
+        assert_eq!(citations.invalid, [5, 0]);
+        // A marker with one number out of range cites nothing
+        citations.resolve("Both [1-3] say so.");
+        assert!(citations.cited.is_empty());
+        assert_eq!(citations.invalid, [3]);
+    }
+
+    #[test]
+    fn the_footer_lists_every_source_with_its_details_and_whether_it_was_cited() {
+        let mut citations = CitationMap::new(
+            vec!["tema1.pdf".to_string(), "tema2.pdf".to_string(), "GRA_11673_2025".to_string()],
+            vec!["tema1.pdf (pdf, chunk 2/5)".to_string(), String::new()],
+        );
+        citations.resolve("Gauss elimination [2].");
+        assert_eq!(citations.footer(), [
+            (1, "tema1.pdf (pdf, chunk 2/5)", false),
+            (2, "tema2.pdf", true),
+            (3, "GRA_11673_2025", false),
+        ]);
+    }
+}
@@ -0,0 +1,128 @@
+use anyhow::Result;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::llm::{ChatMessage, LlmClient};
+
+/// Most text sent to the summarizer; longer sources are sampled evenly so the tail is covered too
+const CONDENSE_INPUT_CHARS: usize = 48_000;
+const CONDENSE_MAX_TOKENS: usize = 600;
+const CONDENSE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Summaries of condensed sources, keyed by a hash of their content
+#[derive(Default)]
+pub struct SummaryCache {
+    entries: Mutex<HashMap<u64, String>>,
+}
+
+impl SummaryCache {
+    pub fn get(&self, content_hash: u64) -> Option<String> {
+        self.entries.lock().unwrap().get(&content_hash).cloned()
+    }
+
+    pub fn insert(&self, content_hash: u64, summary: String) {
+        self.entries.lock().unwrap().insert(content_hash, summary);
+    }
+}
+
+/// A source is condensed when its content is more than twice its share of the context budget
+pub fn needs_condensing(content_chars: usize, budget_share: usize) -> bool {
+    content_chars > budget_share.saturating_mul(2)
+}
+
+pub fn content_hash(chunks: &[String]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    chunks.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Join chunks up to `max_chars`, taking every n-th chunk when they don't all fit
+fn sample_chunks(chunks: &[String], max_chars: usize) -> String {
+    let total: usize = chunks.iter().map(|c| c.len()).sum();
+    let stride = total.div_ceil(max_chars.max(1)).max(1);
+
+    let mut out = String::new();
+    for chunk in chunks.iter().step_by(stride) {
+        if out.len() + chunk.len() > max_chars {
+            break;
+        }
+        out.push_str(chunk);
+        out.push('\n');
+    }
+    out
+}
+
+/// Summarize the chunks of `source` with the chat model, reusing a cached summary of the same content
+pub async fn condense(llm: &LlmClient, cache: &SummaryCache, source: &str, chunks: &[String]) -> Result<String> {
+    let hash = content_hash(chunks);
+    if let Some(summary) = cache.get(hash) {
+        tracing::debug!("Condensed summary of '{}' served from cache", source);
+        return Ok(summary);
+    }
+
+    let prompt = vec![
+        ChatMessage::new("system", "Summarize the document below for a student. Keep every date, number, definition, rule and requirement; drop examples and repetition. Write in the document's language. Reply with the summary only."),
+        ChatMessage::new("user", format!("Document: {}\n\n{}", source, sample_chunks(chunks, CONDENSE_INPUT_CHARS))),
+    ];
+
+    let (content, _) = tokio::time::timeout(CONDENSE_TIMEOUT, llm.chat_limited(&prompt, Some(CONDENSE_MAX_TOKENS)))
+        .await
+        .map_err(|_| anyhow::anyhow!("Summarizing '{}' timed out", source))??;
+
+    // Reasoning models prefix the summary with their thinking
+    let summary = content.rsplit("</think>").next().unwrap_or("").trim().to_string();
+    if summary.is_empty() {
+        anyhow::bail!("Empty summary for '{}'", source);
+    }
+
+    cache.insert(hash, summary.clone());
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunks(count: usize, chars: usize) -> Vec<String> {
+        (0..count).map(|i| format!("{:0width$}", i, width = chars)).collect()
+    }
+
+    #[test]
+    fn only_sources_over_twice_their_share_are_condensed() {
+        assert!(!needs_condensing(2_000, 1_000));
+        assert!(needs_condensing(2_001, 1_000));
+        assert!(needs_condensing(1, 0));
+    }
+
+    #[test]
+    fn sampling_spreads_over_the_whole_source_within_the_limit() {
+        let all = chunks(10, 100);
+        // Everything fits: nothing is skipped
+        assert_eq!(sample_chunks(&all, 10_000).lines().count(), 10);
+
+        // Less than half of it fits: every third chunk, the last one included
+        let sampled = sample_chunks(&all, 420);
+        let lines: Vec<&str> = sampled.lines().collect();
+        assert_eq!(lines, [all[0].as_str(), &all[3], &all[6], &all[9]]);
+        assert!(sampled.len() <= 420);
+        // Chunks that would overflow the limit are left out
+        assert_eq!(sample_chunks(&all, 250).lines().count(), 2);
+    }
+
+    #[tokio::test]
+    async fn a_cached_summary_is_served_without_asking_the_model() {
+        let cache = SummaryCache::default();
+        let source = chunks(3, 50);
+        cache.insert(content_hash(&source), "Exam on June 3".to_string());
+        // Nothing listens there: reaching the model would fail
+        let llm = LlmClient::new(Some("http://127.0.0.1:9".to_string()), None, None);
+
+        assert_eq!(condense(&llm, &cache, "guide.pdf", &source).await.unwrap(), "Exam on June 3");
+        let edited = chunks(4, 50);
+        assert_ne!(content_hash(&edited), content_hash(&source));
+        assert!(condense(&llm, &cache, "guide.pdf", &edited).await.is_err());
+    }
+}
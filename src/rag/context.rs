@@ -0,0 +1,584 @@
+use std::collections::HashMap;
+
+use crate::config::{ContextProfile, RelevanceParams};
+use crate::llm::LlmClient;
+
+use super::{affinity::Boosts, RagSystem, Scope, Snippet};
+
+/// Context size limit: ~200k chars ≈ 50k tokens to stay safely under most LLM limits
+pub const MAX_CONTEXT_CHARS: usize = 200_000;
+/// Fewest snippets `adaptive_k` retrieves, for long questions
+pub(crate) const MIN_ADAPTIVE_SNIPPETS: usize = 6;
+/// Questions up to this many words get `top_k` snippets, from twice as many `MIN_ADAPTIVE_SNIPPETS`
+const SHORT_QUERY_WORDS: usize = 12;
+/// Best matching files included whole
+pub(crate) const FULL_FILES: usize = 3;
+/// How much lower the relevance floor is, and how much wider the cutoff, for an expanded context
+const EXPANDED_SCORE_DROP: f32 = 0.1;
+/// Added to the context when only a weak match was found
+const LOW_CONFIDENCE_NOTE: &str = "Note: none of these documents matched the question closely. If they don't answer it, say so instead of guessing, and make clear how uncertain the answer is.";
+
+// Appended to the prompt in summary-first mode, after the numbered context
+pub const SUMMARY_FIRST_INSTRUCTIONS: &str = "Answer format:\n1. Start with a line \"Sources:\" listing the numbers and names of the sources you will use, e.g. \"Sources: [1] guide.pdf, [3] lecture2.pdf\".\n2. Then answer the question, citing the sources inline by number, e.g. \"The exam is on Friday [1].\"\n3. Only use numbers that appear in the context.";
+
+/// Lay out retrieved snippets for the prompt according to `profile`.
+/// `label` turns a snippet label into its header, e.g. "[2] notes.pdf" when sources are numbered.
+pub fn format_snippets(snippets: &[Snippet], profile: ContextProfile, label: &mut dyn FnMut(&str) -> String) -> String {
+    let mut out = String::new();
+
+    match profile {
+        ContextProfile::Detailed => {
+            for snippet in snippets {
+                let mut details = format!("score {:.2}", snippet.score);
+                if let Some(page) = &snippet.page {
+                    details.push_str(&format!(", page {}", page));
+                }
+                out.push_str(&format!("\n{} ({}):\n{}\n", label(&snippet.label), details, snippet.text));
+            }
+        }
+        ContextProfile::Compact => {
+            // Group by label, keeping the order in which each one first appears
+            let mut groups: Vec<(&str, Vec<&str>)> = Vec::new();
+            for snippet in snippets {
+                match groups.iter_mut().find(|(l, _)| *l == snippet.label) {
+                    Some((_, texts)) => texts.push(&snippet.text),
+                    None => groups.push((&snippet.label, vec![&snippet.text])),
+                }
+            }
+            for (group_label, texts) in groups {
+                out.push_str(&format!("\n{}:\n{}\n", label(group_label), texts.join("\n...\n")));
+            }
+        }
+    }
+
+    out
+}
+
+/// How `build_prompt` lays out and labels the context
+#[derive(Clone)]
+pub struct PromptOptions {
+    pub profile: ContextProfile,
+    /// Number the sources as [1], [2]... in the order they enter the context
+    pub numbered_citations: bool,
+    /// Append `SUMMARY_FIRST_INSTRUCTIONS` when sources are numbered
+    pub summary_first: bool,
+    /// Documents retrieval considers (types, tag)
+    pub scope: Scope,
+    /// Snippets retrieved, the most `adaptive_k` goes up to; 0 means no limit
+    pub top_k: usize,
+    /// Scale the snippet count with the question length, see `adaptive_snippet_count`
+    pub adaptive_k: bool,
+    /// Re-ask: twice the snippets and full files, and a lower score threshold
+    pub expanded: bool,
+    /// Append `freshness_hint` to the source labels
+    pub freshness_hints: bool,
+    /// Score cutoff for the retrieved snippets
+    pub relevance: RelevanceParams,
+    /// Score added to the subjects of the session's recent answers
+    pub boosts: Boosts,
+    /// Most characters of context, to fit the model's window; capped at `MAX_CONTEXT_CHARS`
+    pub max_chars: usize,
+}
+
+/// " [updated 2024-12-02]" for a source indexed at `at`, nothing when that is unknown
+fn freshness_hint(at: Option<u64>) -> String {
+    at.map(|at| format!(" [updated {}]", crate::scrapper::processing::format_date(at / 86_400))).unwrap_or_default()
+}
+
+/// Snippets to retrieve for a question of `query_words` words: `top_k` for short questions,
+/// falling linearly to `MIN_ADAPTIVE_SNIPPETS`, and never more than fit in `budget_chars`
+pub fn adaptive_snippet_count(query_words: usize, top_k: usize, budget_chars: usize, snippet_chars: usize) -> usize {
+    let extra_words = query_words.saturating_sub(SHORT_QUERY_WORDS).min(SHORT_QUERY_WORDS);
+    let fewest = MIN_ADAPTIVE_SNIPPETS.min(top_k);
+    let count = top_k - (top_k - fewest) * extra_words / SHORT_QUERY_WORDS;
+    count.min(budget_chars / snippet_chars.max(1)).max(1)
+}
+
+/// A document whose content went into the context, as a citation names it
+#[derive(Clone, Debug, PartialEq)]
+pub struct SourceDocument {
+    /// File name, or the subject id for a subject summary
+    pub file: String,
+    /// Site id of the subject, empty when unknown
+    pub subject: String,
+    /// Pages of the retrieved snippets, empty when the whole file was used or none is recorded
+    pub pages: Vec<String>,
+}
+
+/// The user message sent to the model and how its context was assembled
+pub struct BuiltPrompt {
+    /// Context followed by the question, or just the question when nothing was retrieved
+    pub text: String,
+    pub has_context: bool,
+    /// Snippets in the context, 0 when whole files were used instead
+    pub snippets: usize,
+    pub full_files: Vec<String>,
+    /// Files replaced by a summary and their best excerpt
+    pub condensed: Vec<String>,
+    pub sources: Vec<String>,
+    /// Sources in citation order, empty unless they are numbered
+    pub numbered_sources: Vec<String>,
+    /// How each numbered source got into the context, see `describe_source`
+    pub numbered_details: Vec<String>,
+    /// When each of `sources` was indexed, for those that record it
+    pub indexed_at: HashMap<String, u64>,
+    /// Retrieval only found a weak match; the prompt asks the model to hedge
+    pub low_confidence: bool,
+    /// Subject (site id) of each retrieved source, by label and by file
+    pub source_subjects: HashMap<String, String>,
+    /// Label, score and affinity boost of each retrieved snippet, best first
+    pub scores: Vec<(String, f32, f32)>,
+    /// Chunk id of each of `scores`
+    pub snippet_ids: Vec<String>,
+    /// Every document in the context, in the order it entered
+    pub documents: Vec<SourceDocument>,
+    /// Files and snippets retrieved but left out to stay within `PromptOptions::max_chars`
+    pub left_out: usize,
+}
+
+/// Retrieve context for `query` and build the user message: files named in the query are
+/// included whole, then the best matching files (condensed with `condense_with` when far over
+/// their share of the budget), falling back to snippets when no file content is available.
+/// With `rerank_with`, `rerank::RERANK_CANDIDATES` snippets are retrieved and the model's
+/// ordering of them decides which `top_k` are kept.
+pub async fn build_prompt(rag: &RagSystem, query: &str, options: PromptOptions, condense_with: Option<&LlmClient>, rerank_with: Option<&LlmClient>) -> BuiltPrompt {
+    let PromptOptions { profile: context_profile, numbered_citations, summary_first, scope, top_k, adaptive_k, expanded, freshness_hints, relevance, boosts, max_chars } = options;
+    let max_chars = max_chars.min(MAX_CONTEXT_CHARS);
+    let mut left_out = 0;
+    let file_hint = |file: &str| if freshness_hints { freshness_hint(rag.file_indexed_at(file)) } else { String::new() };
+
+    // Sources numbered in the order they enter the context, [1] first
+    let mut numbered_sources: Vec<String> = Vec::new();
+    let mut source_label = |source: &str| -> String {
+        if !numbered_citations {
+            return source.to_string();
+        }
+        let number = match numbered_sources.iter().position(|s| s == source) {
+            Some(pos) => pos + 1,
+            None => {
+                numbered_sources.push(source.to_string());
+                numbered_sources.len()
+            }
+        };
+        format!("[{}] {}", number, source)
+    };
+
+    // 1. Detect explicit file mentions (e.g. .pdf or filename stems)
+    let mut extra_context = String::new();
+    let words: Vec<&str> = query.split_whitespace().collect();
+
+    let all_filenames = rag.get_all_filenames().unwrap_or_default();
+    let mut mentioned_targets = Vec::new();
+
+    for word in words {
+        let word_clean = word.trim_matches(|c: char| !c.is_alphanumeric() && c != '.' && c != '_' && c != '-');
+        if word_clean.len() < 4 { continue; } // Skip short common words
+
+        let word_lower = word_clean.to_lowercase();
+
+        // Check for direct match or stem match
+        for filename in &all_filenames {
+            let filename_lower = filename.to_lowercase();
+
+            // Extract just the basename (last component of path)
+            let basename = filename_lower.rsplit('/').next().unwrap_or(&filename_lower);
+
+            // Get stem without .pdf extension
+            let stem = if let Some(pos) = basename.find(".pdf") {
+                &basename[..pos]
+            } else {
+                basename
+            };
+
+            // Match against basename, stem, or if query contains stem
+            if word_lower == basename || word_lower == stem || stem.contains(&word_lower) || word_lower.contains(stem) {
+                mentioned_targets.push(filename.clone());
+            }
+        }
+    }
+
+    // Deduplicate
+    mentioned_targets.sort();
+    mentioned_targets.dedup();
+
+    // Subject of each file whose chunks were fetched, from the chunk ids
+    let mut file_subjects: HashMap<String, String> = HashMap::new();
+    for target_file in &mentioned_targets {
+        if let Ok(chunks) = rag.get_file_chunks(target_file) {
+            if let Some((id, _)) = chunks.first() {
+                // The first named file always goes in, the others while they fit
+                let file_content_size: usize = chunks.iter().map(|(_, c)| c.len()).sum();
+                if !extra_context.is_empty() && extra_context.len() + file_content_size > max_chars {
+                    tracing::info!("Skipping '{}' ({} chars) - would exceed context limit", target_file, file_content_size);
+                    left_out += 1;
+                    continue;
+                }
+                file_subjects.insert(target_file.clone(), crate::subjects::subject_of(id).to_string());
+                tracing::info!("Explicitly adding all {} chunks of '{}' to context (cleaned)", chunks.len(), target_file);
+                extra_context.push_str(&format!("\n--- START OF FILE: {}{} ---\n", source_label(target_file), file_hint(target_file)));
+                for (_id, content) in chunks {
+                    // Extract content after the double newline (where our header ends)
+                    if let Some(pos) = content.find("\n\n") {
+                        extra_context.push_str(&content[pos + 2..]);
+                    } else {
+                        extra_context.push_str(&content);
+                    }
+                }
+                extra_context.push_str(&format!("\n--- END OF FILE: {} ---\n", target_file));
+            }
+        }
+    }
+
+    // 2. Regular RAG search - find relevant documents
+    // No limit: every document may come back, the relevance cutoff and budget still apply
+    let top_k = match top_k {
+        0 => rag.count_documents().max(1),
+        top_k => top_k,
+    };
+    let top_k = if adaptive_k {
+        let budget = max_chars.saturating_sub(extra_context.len());
+        adaptive_snippet_count(query.split_whitespace().count(), top_k, budget, context_profile.snippet_chars())
+    } else {
+        top_k
+    };
+    let (top_k, relevance, full_files) = if expanded {
+        // A cutoff turned off stays off; a lowered one never reaches 0, which would turn it off
+        let floor = if relevance.filters() { (relevance.floor - EXPANDED_SCORE_DROP).max(f32::EPSILON) } else { 0.0 };
+        let widened = RelevanceParams { floor, delta: relevance.delta + EXPANDED_SCORE_DROP };
+        (top_k * 2, widened, FULL_FILES * 2)
+    } else {
+        (top_k, relevance, FULL_FILES)
+    };
+    tracing::debug!("Retrieving {} snippets, floor {:.2}, delta {:.2} (adaptive: {}, expanded: {})", top_k, relevance.floor, relevance.delta, adaptive_k, expanded);
+    let retrieved = if rerank_with.is_some() { top_k.max(super::rerank::RERANK_CANDIDATES) } else { top_k };
+    let mut snippets = rag.search_snippets(query, "user", retrieved, context_profile.snippet_chars(), relevance, &scope, &boosts).await.unwrap_or_default();
+    if let Some(llm) = rerank_with.filter(|_| snippets.len() > 1) {
+        let before: Vec<String> = snippets.iter().map(|s| s.id.clone()).collect();
+        match super::rerank::rerank(llm, query, snippets.clone()).await {
+            Ok(ranked) => {
+                tracing::debug!("Rerank before: {:?}", before);
+                tracing::debug!("Rerank after: {:?}", ranked.iter().map(|s| s.id.as_str()).collect::<Vec<_>>());
+                snippets = ranked;
+            }
+            // The retrieval order is still a ranking
+            Err(e) => tracing::warn!("Could not rerank snippets, keeping the retrieval order: {}", e),
+        }
+    }
+    snippets.truncate(top_k);
+    // Files named in the question are what was asked about, whatever the search scored
+    let low_confidence = extra_context.is_empty() && snippets.iter().any(|s| s.low_confidence);
+
+    tracing::info!("RAG search returned {} snippets for query: '{}'", snippets.len(), query);
+    for (i, snippet) in snippets.iter().enumerate() {
+        tracing::debug!("Snippet {}: source='{}', score={:.3} (affinity +{:.3})", i, snippet.source, snippet.score, snippet.boost);
+    }
+
+    // Collect unique source files from search results (excluding already mentioned ones)
+    let mut rag_source_files: Vec<String> = Vec::new();
+    for snippet in &snippets {
+        let source = &snippet.source;
+        // Check if this looks like a filename (contains . or /)
+        if (source.contains('.') || source.contains('/')) && !rag_source_files.contains(source) {
+            rag_source_files.push(source.clone());
+        }
+    }
+    rag_source_files.truncate(full_files); // Limit to the most relevant files
+
+    tracing::info!("Found {} unique source files from RAG search", rag_source_files.len());
+
+    let mut current_context_size = extra_context.len();
+
+    // Fetch complete content for each source file found via RAG (with size limit)
+    let mut rag_full_context = String::new();
+    let mut included_files: Vec<String> = Vec::new();
+    let mut condensed_files: Vec<String> = Vec::new();
+    // Each source's fair share of the budget, used to decide what gets condensed
+    let budget_share = max_chars.saturating_sub(current_context_size) / rag_source_files.len().max(1);
+
+    for (i, source_file) in rag_source_files.iter().enumerate() {
+        if current_context_size >= max_chars {
+            tracing::info!("Context limit reached ({} chars), stopping full file inclusion", current_context_size);
+            left_out += rag_source_files.len() - i;
+            break;
+        }
+
+        if let Ok(chunks) = rag.get_file_chunks(source_file) {
+            if let Some((id, _)) = chunks.first() {
+                file_subjects.insert(source_file.clone(), crate::subjects::subject_of(id).to_string());
+                // Calculate approximate size of this file
+                let file_content_size: usize = chunks.iter().map(|(_, c)| c.len()).sum();
+
+                // Far over its share: a summary plus the best excerpt instead of the whole file
+                if let Some(llm) = condense_with.filter(|_| super::condense::needs_condensing(file_content_size, budget_share)) {
+                    let texts: Vec<String> = chunks.iter()
+                        .map(|(_, c)| c.find("\n\n").map(|pos| c[pos + 2..].to_string()).unwrap_or_else(|| c.clone()))
+                        .collect();
+                    match super::condense::condense(llm, rag.summaries(), source_file, &texts).await {
+                        Ok(summary) => {
+                            tracing::info!("Condensed '{}' (~{} chars) to a {} char summary", source_file, file_content_size, summary.len());
+                            let excerpt = snippets.iter().find(|s| &s.source == source_file).map(|s| s.text.as_str()).unwrap_or("");
+                            let block = format!(
+                                "\n--- START OF FILE: {}{} (condensed) ---\nSummary:\n{}\n\nMost relevant excerpt:\n{}\n--- END OF FILE: {} ---\n",
+                                source_label(source_file), file_hint(source_file), summary, excerpt, source_file
+                            );
+                            current_context_size += block.len();
+                            rag_full_context.push_str(&block);
+                            condensed_files.push(source_file.clone());
+                            continue;
+                        }
+                        // Offline or refused: fall back to the usual inclusion rules
+                        Err(e) => tracing::warn!("Could not condense '{}': {}", source_file, e),
+                    }
+                }
+
+                // Check if adding this file would exceed the limit
+                if current_context_size + file_content_size > max_chars && !rag_full_context.is_empty() {
+                    tracing::info!("Skipping '{}' ({} chars) - would exceed context limit", source_file, file_content_size);
+                    left_out += 1;
+                    continue;
+                }
+
+                tracing::info!("Including FULL content of '{}' ({} chunks, ~{} chars) from RAG search", source_file, chunks.len(), file_content_size);
+                rag_full_context.push_str(&format!("\n--- START OF FILE: {}{} ---\n", source_label(source_file), file_hint(source_file)));
+                for (_id, content) in chunks {
+                    // Extract content after the header (double newline)
+                    if let Some(pos) = content.find("\n\n") {
+                        rag_full_context.push_str(&content[pos + 2..]);
+                    } else {
+                        rag_full_context.push_str(&content);
+                    }
+                }
+                rag_full_context.push_str(&format!("\n--- END OF FILE: {} ---\n", source_file));
+                current_context_size += file_content_size;
+                included_files.push(source_file.clone());
+            }
+        }
+    }
+
+    let mut prompt_sources: Vec<String> = Vec::new();
+    let mut context_str = String::new();
+    if !extra_context.is_empty() {
+        context_str.push_str("You have been provided with the COMPLETE content of the requested document(s) below. Use this information as your primary source.\n");
+        context_str.push_str(&extra_context);
+        if !rag_full_context.is_empty() {
+            context_str.push_str("\nAdditional relevant documents:\n");
+            context_str.push_str(&rag_full_context);
+        }
+    } else if !rag_full_context.is_empty() {
+        context_str.push_str("Relevant documents from your files (COMPLETE content):\n");
+        context_str.push_str(&rag_full_context);
+    } else if !snippets.is_empty() {
+        // Fallback: if no file chunks available, use snippets, dropping the least relevant ones
+        // (last, the search returns them best first) when they don't all fit
+        let fitting = fitting_snippets(&snippets, max_chars);
+        left_out += snippets.len() - fitting;
+        snippets.truncate(fitting);
+        context_str.push_str("Relevant context from your documents:\n");
+        context_str.push_str(&format_snippets(&snippets, context_profile, &mut |label| {
+            let hint = if freshness_hints {
+                freshness_hint(snippets.iter().find(|s| s.label == label).and_then(|s| s.indexed_at))
+            } else {
+                String::new()
+            };
+            if numbered_citations { format!("{}{}", source_label(label), hint) } else { format!("[{}]{}", label, hint) }
+        }));
+        for snippet in &snippets {
+            if !prompt_sources.contains(&snippet.label) {
+                prompt_sources.push(snippet.label.clone());
+            }
+        }
+    }
+    if low_confidence && !context_str.is_empty() {
+        context_str.push_str("\n\n");
+        context_str.push_str(LOW_CONFIDENCE_NOTE);
+    }
+    if summary_first && !numbered_sources.is_empty() {
+        context_str.push_str("\n\n");
+        context_str.push_str(SUMMARY_FIRST_INSTRUCTIONS);
+    }
+    let text = if !context_str.is_empty() {
+        format!("{}\n\n---\nUser question: {}", context_str, query)
+    } else {
+        query.to_string()
+    };
+
+    let sources_from_snippets = !prompt_sources.is_empty();
+    let sources = if sources_from_snippets { prompt_sources } else { included_files.clone() };
+    let indexed_at = sources.iter()
+        .filter_map(|source| {
+            let at = snippets.iter().find(|s| &s.label == source).and_then(|s| s.indexed_at)
+                .or_else(|| rag.file_indexed_at(source))?;
+            Some((source.clone(), at))
+        })
+        .collect();
+    let documents = source_documents(&snippets, sources_from_snippets, &mentioned_targets, &included_files, &condensed_files, &file_subjects);
+    let source_subjects = file_subjects.into_iter()
+        .chain(snippets.iter().flat_map(|s| [(s.label.clone(), s.subject.clone()), (s.source.clone(), s.subject.clone())]))
+        .collect();
+
+    BuiltPrompt {
+        text,
+        has_context: !context_str.is_empty(),
+        snippets: if sources_from_snippets { snippets.len() } else { 0 },
+        sources,
+        indexed_at,
+        low_confidence,
+        numbered_details: numbered_sources.iter()
+            .map(|source| describe_source(source, &snippets, &mentioned_targets, &included_files, &condensed_files))
+            .collect(),
+        full_files: included_files,
+        condensed: condensed_files,
+        numbered_sources,
+        source_subjects,
+        scores: snippets.iter().map(|s| (s.label.clone(), s.score, s.boost)).collect(),
+        snippet_ids: snippets.iter().map(|s| s.id.clone()).collect(),
+        documents,
+        left_out,
+    }
+}
+
+/// How many of `snippets`, best first, fit in `max_chars`; at least one
+fn fitting_snippets(snippets: &[Snippet], max_chars: usize) -> usize {
+    let mut total = 0;
+    let fitting = snippets.iter()
+        .take_while(|s| {
+            // Label, score and blank lines around each snippet
+            total += s.text.len() + s.label.len() + 32;
+            total <= max_chars
+        })
+        .count();
+    fitting.max(1)
+}
+
+/// The documents of the context: files named in the question, then the files included whole
+/// or condensed, or the files of the snippets when only snippets were used. Pages come from
+/// the snippets of each file.
+fn source_documents(
+    snippets: &[Snippet],
+    from_snippets: bool,
+    mentioned: &[String],
+    full_files: &[String],
+    condensed: &[String],
+    file_subjects: &HashMap<String, String>,
+) -> Vec<SourceDocument> {
+    let files: Vec<&String> = if from_snippets {
+        snippets.iter().map(|s| &s.source).collect()
+    } else {
+        mentioned.iter().filter(|f| file_subjects.contains_key(*f)).chain(full_files).chain(condensed).collect()
+    };
+
+    let mut documents: Vec<SourceDocument> = Vec::new();
+    for file in files {
+        if documents.iter().any(|d| &d.file == file) {
+            continue;
+        }
+        let matching: Vec<&Snippet> = snippets.iter().filter(|s| &s.source == file).collect();
+        let whole = !from_snippets && !condensed.contains(file);
+        let mut pages: Vec<String> = if whole { Vec::new() } else { matching.iter().filter_map(|s| s.page.clone()).collect() };
+        pages.sort_by_key(|p| (p.parse::<u32>().unwrap_or(u32::MAX), p.clone()));
+        pages.dedup();
+        let subject = matching.first().map(|s| s.subject.clone())
+            .or_else(|| file_subjects.get(file).cloned())
+            .unwrap_or_default();
+        documents.push(SourceDocument { file: file.clone(), subject, pages });
+    }
+    documents
+}
+
+/// A numbered source as shown in the sources footer: its kind and what the model saw of it,
+/// e.g. "📄 Calculus-T3.pdf (chunk 4/12)", "📄 guide.pdf (whole file)" or "📘 Subject summary (GRA_11673)"
+fn describe_source(source: &str, snippets: &[Snippet], mentioned: &[String], full_files: &[String], condensed: &[String]) -> String {
+    let matching: Vec<&Snippet> = snippets.iter().filter(|s| s.label == source || s.source == source).collect();
+    let doc_type = matching.first().map_or("", |s| s.doc_type.as_str());
+    if doc_type == "subject" {
+        return format!("📘 Subject summary ({})", source);
+    }
+
+    let icon = if doc_type == "transcript" { "🎧" } else { "📄" };
+    let seen = if condensed.iter().any(|f| f == source) {
+        "summary + excerpt".to_string()
+    } else if mentioned.iter().chain(full_files).any(|f| f == source) {
+        "whole file".to_string()
+    } else {
+        let chunks: Vec<(usize, usize)> = matching.iter().filter_map(|s| s.chunk).collect();
+        match chunks.first() {
+            Some((_, total)) => {
+                let numbers: Vec<String> = chunks.iter().map(|(n, _)| n.to_string()).collect();
+                let noun = if chunks.len() == 1 { "chunk" } else { "chunks" };
+                format!("{} {}/{}", noun, numbers.join(","), total)
+            }
+            None => return format!("{} {}", icon, source),
+        }
+    };
+    format!("{} {} ({})", icon, source, seen)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snippet(label: &str, text: &str, score: f32, page: Option<&str>) -> Snippet {
+        Snippet {
+            id: format!("GRA_1/resources/{}#0", label),
+            source: label.to_string(),
+            label: label.to_string(),
+            text: text.to_string(),
+            score,
+            page: page.map(str::to_string),
+            doc_type: "pdf".to_string(),
+            chunk: None,
+            indexed_at: None,
+            low_confidence: false,
+            subject: "GRA_1".to_string(),
+            boost: 0.0,
+        }
+    }
+
+    fn snippets() -> Vec<Snippet> {
+        vec![
+            snippet("tema1.pdf", "Gauss elimination", 0.81, Some("4")),
+            snippet("tema2.pdf", "Eigenvalues", 0.72, None),
+            snippet("tema1.pdf", "Pivoting", 0.65, None),
+        ]
+    }
+
+    #[test]
+    fn detailed_context_gives_each_snippet_its_score_and_page() {
+        let context = format_snippets(&snippets(), ContextProfile::Detailed, &mut |label| label.to_string());
+        assert_eq!(context, "\ntema1.pdf (score 0.81, page 4):\nGauss elimination\n\ntema2.pdf (score 0.72):\nEigenvalues\n\ntema1.pdf (score 0.65):\nPivoting\n");
+    }
+
+    #[test]
+    fn compact_context_groups_snippets_by_file_without_scores() {
+        let mut numbered = 0;
+        let context = format_snippets(&snippets(), ContextProfile::Compact, &mut |label| {
+            numbered += 1;
+            format!("[{}] {}", numbered, label)
+        });
+        // Files keep the order they first appear in, and each is labelled once
+        assert_eq!(context, "\n[1] tema1.pdf:\nGauss elimination\n...\nPivoting\n\n[2] tema2.pdf:\nEigenvalues\n");
+        assert!(ContextProfile::Compact.snippet_chars() < ContextProfile::Detailed.snippet_chars());
+    }
+
+    #[test]
+    fn cited_documents_carry_the_pages_of_their_snippets() {
+        let mut found = snippets();
+        found.push(snippet("tema1.pdf", "Row echelon form", 0.6, Some("12")));
+        found.push(snippet("tema1.pdf", "Gauss again", 0.5, Some("4")));
+
+        let documents = source_documents(&found, true, &[], &[], &[], &HashMap::new());
+        assert_eq!(documents, [
+            SourceDocument { file: "tema1.pdf".to_string(), subject: "GRA_1".to_string(), pages: vec!["4".to_string(), "12".to_string()] },
+            SourceDocument { file: "tema2.pdf".to_string(), subject: "GRA_1".to_string(), pages: Vec::new() },
+        ]);
+
+        // A file included whole cites no pages; a condensed one keeps its snippets' pages
+        let subjects = HashMap::from([("guide.pdf".to_string(), "GRA_2".to_string())]);
+        let documents = source_documents(&found, false, &["guide.pdf".to_string(), "missing.pdf".to_string()], &["tema2.pdf".to_string()], &["tema1.pdf".to_string()], &subjects);
+        assert_eq!(documents, [
+            SourceDocument { file: "guide.pdf".to_string(), subject: "GRA_2".to_string(), pages: Vec::new() },
+            SourceDocument { file: "tema2.pdf".to_string(), subject: "GRA_1".to_string(), pages: Vec::new() },
+            SourceDocument { file: "tema1.pdf".to_string(), subject: "GRA_1".to_string(), pages: vec!["4".to_string(), "12".to_string()] },
+        ]);
+    }
+}
@@ -0,0 +1,94 @@
+//! Maximal Marginal Relevance: a PDF split into many chunks tends to fill every retrieved
+//! place with near-identical passages of the same file. MMR picks the results one by one,
+//! each time the one that best balances its score against its similarity to those already
+//! picked, so other files and subjects get a place.
+
+use super::Document;
+
+/// Up to `limit` of `scored` (best first) in the order MMR picks them. `lambda` (0-1) weighs
+/// relevance against novelty: 1 keeps the order by score, lower values demote near duplicates
+/// harder. `similarity` compares two embeddings; scores are kept as they are.
+///
+/// When there are no more than `limit` results every one is kept anyway, so they stay in
+/// score order.
+pub fn mmr(scored: Vec<(Document, f32)>, limit: usize, lambda: f32, similarity: impl Fn(&[f32], &[f32]) -> f32) -> Vec<(Document, f32)> {
+    let lambda = lambda.clamp(0.0, 1.0);
+    if lambda >= 1.0 || scored.len() <= limit {
+        return scored.into_iter().take(limit).collect();
+    }
+
+    let mut remaining = scored;
+    let mut picked: Vec<(Document, f32)> = Vec::with_capacity(limit.min(remaining.len()));
+    // Highest similarity of each remaining result to the picked ones, updated after each pick
+    let mut redundancy = vec![f32::NEG_INFINITY; remaining.len()];
+    while picked.len() < limit && !remaining.is_empty() {
+        let marginal = |i: usize| {
+            let penalty = if picked.is_empty() { 0.0 } else { redundancy[i] };
+            lambda * remaining[i].1 - (1.0 - lambda) * penalty
+        };
+        let best = (0..remaining.len())
+            .max_by(|&a, &b| marginal(a).total_cmp(&marginal(b)).then(b.cmp(&a)))
+            .unwrap_or(0);
+        let chosen = remaining.remove(best);
+        redundancy.remove(best);
+        for (i, (doc, _)) in remaining.iter().enumerate() {
+            redundancy[i] = redundancy[i].max(similarity(&chosen.0.embedding, &doc.embedding));
+        }
+        picked.push(chosen);
+    }
+    picked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SimilarityMetric;
+    use crate::rag::store::similarity;
+    use std::collections::HashMap;
+
+    fn scored(id: &str, embedding: [f32; 3], score: f32) -> (Document, f32) {
+        let doc = Document { id: id.to_string(), content: String::new(), embedding: embedding.to_vec(), metadata: HashMap::new(), user_id: "user".to_string() };
+        (doc, score)
+    }
+
+    /// Three chunks of one PDF, nearly the same passage, then two other files
+    fn results() -> Vec<(Document, f32)> {
+        vec![
+            scored("tema1.pdf#0", [1.0, 0.0, 0.0], 0.90),
+            scored("tema1.pdf#1", [0.99, 0.05, 0.0], 0.89),
+            scored("tema1.pdf#2", [0.98, 0.1, 0.0], 0.88),
+            scored("tema2.pdf#0", [0.6, 0.8, 0.0], 0.80),
+            scored("apuntes.pdf#0", [0.5, 0.0, 0.85], 0.75),
+        ]
+    }
+
+    fn ids(picked: &[(Document, f32)]) -> Vec<&str> {
+        picked.iter().map(|(d, _)| d.id.as_str()).collect()
+    }
+
+    fn cosine(a: &[f32], b: &[f32]) -> f32 {
+        similarity(SimilarityMetric::Cosine, a, b)
+    }
+
+    #[test]
+    fn near_duplicates_are_demoted() {
+        let picked = mmr(results(), 3, 0.5, cosine);
+        // The further from the first pick, the smaller the penalty: apuntes.pdf overtakes tema2.pdf
+        assert_eq!(ids(&picked), ["tema1.pdf#0", "apuntes.pdf#0", "tema2.pdf#0"]);
+        // Scores are not rescaled
+        assert_eq!(picked[2].1, 0.80);
+    }
+
+    #[test]
+    fn lambda_one_keeps_the_score_order() {
+        let picked = mmr(results(), 3, 1.0, cosine);
+        assert_eq!(ids(&picked), ["tema1.pdf#0", "tema1.pdf#1", "tema1.pdf#2"]);
+    }
+
+    #[test]
+    fn results_within_the_limit_stay_in_score_order() {
+        let picked = mmr(results(), 5, 0.3, cosine);
+        assert_eq!(ids(&picked), ids(&results()));
+        assert!(mmr(Vec::new(), 3, 0.5, cosine).is_empty());
+    }
+}
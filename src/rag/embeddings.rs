@@ -21,6 +21,15 @@ macro_rules! embed_model {
 
 embed_model!("../../embeddinggemma-300m-Q4_0.gguf");
 
+/// File name of the embedded model, recorded in the index header
+pub fn model_id() -> String {
+    std::path::Path::new(MODEL_PATH)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("unknown_model")
+        .to_string()
+}
+
 struct LlamaState {
     // We keep the backend alive
     backend: Arc<LlamaBackend>,
@@ -30,14 +39,21 @@ struct LlamaState {
     _temp_file: Arc<NamedTempFile>,
 }
 
+/// The bundled embedding model (EmbeddingGemma through llama.cpp), loaded from the binary
+/// into a temporary file. Turns text into the vectors stored in the index.
 #[derive(Clone)]
 pub struct EmbeddingModel {
     state: Arc<LlamaState>,
     context_params: LlamaContextParams,
+    /// Longest input embedded, see `RagConfig::max_document_chars`
+    max_chars: usize,
 }
 
-// Approximate characters per token ratio
-const CHARS_PER_TOKEN: usize = 2;
+/// Library the embedding model runs on
+pub const BACKEND: &str = "llama.cpp";
+
+// Approximate characters per token ratio, on the low side so estimates err towards more tokens
+pub const CHARS_PER_TOKEN: usize = 2;
 const MAX_TOKENS: usize = 512; 
 const MAX_CHUNK_CHARS: usize = MAX_TOKENS * CHARS_PER_TOKEN;
 
@@ -50,7 +66,7 @@ use std::num::NonZeroU32;
 // ...
 
 impl EmbeddingModel {
-    pub fn new() -> Result<Self> {
+    pub fn new(max_chars: usize) -> Result<Self> {
         // Disable logging
         unsafe {
             llama_cpp_sys_2::llama_log_set(Some(log_callback), std::ptr::null_mut());
@@ -93,12 +109,33 @@ impl EmbeddingModel {
         Ok(Self {
             state,
             context_params,
+            max_chars,
         })
     }
 
     /// Embed text, chunking if necessary and averaging embeddings
     pub async fn embed(&self, text: &str) -> Result<Vec<f32>> {
-        let text = text.replace("\n", " ");
+        // Inference is blocking and heavy, keep it off the async runtime
+        let model = self.clone();
+        let text = text.to_string();
+        tokio::task::spawn_blocking(move || model.embed_blocking(&text)).await?
+    }
+
+    /// Embed several texts in one blocking task, in order. Fails as a whole when any text
+    /// fails, so callers that need to know which one should fall back to `embed`.
+    pub async fn embed_batch(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        let model = self.clone();
+        let texts = texts.to_vec();
+        tokio::task::spawn_blocking(move || texts.iter().map(|text| model.embed_blocking(text)).collect()).await?
+    }
+
+    fn embed_blocking(&self, text: &str) -> Result<Vec<f32>> {
+        let mut text = text.replace("\n", " ");
+        // Safety net behind the document size guard: a multi-megabyte input would run
+        // thousands of inferences for a single averaged vector
+        if let Some(original) = crate::scrapper::processing::truncate_chars(&mut text, self.max_chars) {
+            tracing::warn!("Embedding input of {} characters truncated to {}", original, self.max_chars);
+        }
         
         let chunks = if text.len() <= MAX_CHUNK_CHARS {
             vec![text.clone()]
@@ -111,18 +148,10 @@ impl EmbeddingModel {
         }
 
         // Process chunks sequentially
-        // We use tokio::task::spawn_blocking because inference is blocking and heavy
         let mut embeddings = Vec::new();
 
         for chunk in chunks {
-            let state = self.state.clone();
-            let ctx_params = self.context_params.clone();
-            let chunk_text = chunk.clone();
-            
-            let embedding = tokio::task::spawn_blocking(move || -> Result<Vec<f32>> {
-                 Self::inference(&state.backend, &state.model, &ctx_params, &chunk_text)
-            }).await??;
-            
+            let embedding = Self::inference(&self.state.backend, &self.state.model, &self.context_params, &chunk)?;
             embeddings.push(embedding);
         }
 
@@ -202,6 +231,14 @@ impl EmbeddingModel {
         Ok(embedding_slice.to_vec())
     }
 
+    /// Embed a known string and check the model produces a usable vector.
+    /// Returns the embedding dimension. Runs synchronously, meant for startup.
+    pub fn self_test(&self, expected_dim: Option<usize>) -> Result<usize> {
+        let embedding = Self::inference(&self.state.backend, &self.state.model, &self.context_params, "PoliRag embedding self-test")
+            .context("Embedding model failed to run (check the GPU/Metal backend)")?;
+        check_embedding(&embedding, expected_dim)
+    }
+
     fn chunk_text(&self, text: &str) -> Vec<String> {
         let splitter = text_splitter::TextSplitter::new(MAX_CHUNK_CHARS);
         
@@ -211,15 +248,53 @@ impl EmbeddingModel {
     }
 
     pub fn model_name(&self) -> String {
-        std::path::Path::new(MODEL_PATH)
-            .file_name()
-            .and_then(|n| n.to_str())
-            .unwrap_or("unknown_model")
-            .to_string()
+        model_id()
     }
 
-    pub fn chunking_strategy(&self) -> String {
-        format!("Semantic (TextSplitter) - {} chars", MAX_CHUNK_CHARS)
+    /// Longest text embedded in one pass; longer inputs are split and their embeddings averaged
+    pub fn window_chars(&self) -> usize {
+        MAX_CHUNK_CHARS
     }
 }
 
+/// The dimension of `embedding`, or why retrieval can't use it: empty, all zeros, not finite,
+/// or not the `expected_dim` of the indexed embeddings
+fn check_embedding(embedding: &[f32], expected_dim: Option<usize>) -> Result<usize> {
+    let norm: f32 = embedding.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if embedding.is_empty() || norm == 0.0 || !norm.is_finite() {
+        anyhow::bail!("Embedding model returned an empty or all-zero vector; retrieval will not find any context");
+    }
+
+    if let Some(expected) = expected_dim {
+        if expected != embedding.len() {
+            anyhow::bail!(
+                "Embedding dimension mismatch: model produces {} but the index holds {}. Recalculate embeddings from the RAG Info screen.",
+                embedding.len(), expected
+            );
+        }
+    }
+
+    Ok(embedding.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn self_test_rejects_unusable_embeddings() {
+        assert_eq!(check_embedding(&[0.6, 0.8, 0.0], None).unwrap(), 3);
+        assert_eq!(check_embedding(&[0.6, 0.8, 0.0], Some(3)).unwrap(), 3);
+        for unusable in [vec![], vec![0.0; 3], vec![f32::NAN, 1.0, 0.0], vec![f32::INFINITY, 0.0, 0.0]] {
+            let error = check_embedding(&unusable, None).unwrap_err().to_string();
+            assert!(error.contains("empty or all-zero"), "{:?}: {}", unusable, error);
+        }
+    }
+
+    #[test]
+    fn self_test_reports_a_dimension_the_index_does_not_hold() {
+        let error = check_embedding(&[0.6, 0.8, 0.0], Some(768)).unwrap_err().to_string();
+        assert!(error.contains("model produces 3 but the index holds 768"), "{}", error);
+        assert!(error.contains("Recalculate embeddings"));
+    }
+}
@@ -0,0 +1,284 @@
+use anyhow::{Context, Result};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use super::Document;
+
+/// Start of every file written with a header; unversioned files start straight with bincode data
+const MAGIC: &[u8; 8] = b"POLIRAG\0";
+/// Current index format. Version 0 is the unversioned layout written before the header existed;
+/// version 1 always stored embeddings as f32, version 2 tags them f32 or f16 (see `precision`).
+pub const FORMAT_VERSION: u32 = 2;
+
+/// Written at the start of every index file
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct IndexHeader {
+    pub version: u32,
+    /// Store that wrote the file: "hnsw" or "linear"
+    pub store: String,
+    /// Embedding model the vectors were computed with
+    pub embedder: String,
+    /// Unix time of the write
+    pub written_at: u64,
+}
+
+impl IndexHeader {
+    pub fn new(store: &str) -> Self {
+        let written_at = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        Self {
+            version: FORMAT_VERSION,
+            store: store.to_string(),
+            embedder: super::embeddings::model_id(),
+            written_at,
+        }
+    }
+}
+
+/// What `read` found in an index file
+pub enum Loaded<T> {
+    Current(IndexHeader, T),
+    /// An older versioned file: the caller reads it with `read_previous` and its layout for
+    /// that version, then migrates it
+    Previous(IndexHeader),
+    /// Version 0: the caller reads it with its legacy layouts and migrates it
+    Unversioned,
+}
+
+/// A document as format v1 and the unversioned layouts wrote it, with an f32 embedding
+#[derive(Deserialize)]
+pub struct DocumentV1 {
+    id: String,
+    content: String,
+    embedding: Vec<f32>,
+    metadata: HashMap<String, String>,
+    user_id: String,
+}
+
+impl From<DocumentV1> for Document {
+    fn from(doc: DocumentV1) -> Self {
+        Document { id: doc.id, content: doc.content, embedding: doc.embedding, metadata: doc.metadata, user_id: doc.user_id }
+    }
+}
+
+/// Write `header` followed by `payload`
+pub fn write(path: &Path, header: &IndexHeader, payload: &impl Serialize) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writer.write_all(MAGIC)?;
+    bincode::serialize_into(&mut writer, header)?;
+    bincode::serialize_into(&mut writer, payload)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Header of the index file at `path`, `None` when it is unversioned
+pub fn read_header(path: &Path) -> Result<Option<IndexHeader>> {
+    read_header_from(&mut BufReader::new(File::open(path)?))
+}
+
+fn read_header_from(reader: &mut impl Read) -> Result<Option<IndexHeader>> {
+    let mut magic = [0u8; 8];
+    if reader.read_exact(&mut magic).is_err() || &magic != MAGIC {
+        return Ok(None);
+    }
+    Ok(Some(bincode::deserialize_from(reader).context("Corrupt index header")?))
+}
+
+/// Read an index file, dispatching on its format version. Files written by a newer
+/// polirag are refused rather than misread, so they are never overwritten.
+pub fn read<T: DeserializeOwned>(path: &Path) -> Result<Loaded<T>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let Some(header) = read_header_from(&mut reader)? else { return Ok(Loaded::Unversioned) };
+
+    match header.version {
+        FORMAT_VERSION => {
+            let payload = bincode::deserialize_from(reader)
+                .with_context(|| format!("Failed to read index {:?} (format v{})", path, header.version))?;
+            Ok(Loaded::Current(header, payload))
+        }
+        v if v > FORMAT_VERSION => anyhow::bail!(
+            "Index {:?} uses format v{}, but this polirag only reads up to v{}. Update polirag; the file was left untouched.",
+            path, v, FORMAT_VERSION
+        ),
+        1 => Ok(Loaded::Previous(header)),
+        v => anyhow::bail!("Index {:?} has unknown format v{}", path, v),
+    }
+}
+
+/// The payload of an older versioned file, read with the layout of its version
+pub fn read_previous<T: DeserializeOwned>(path: &Path) -> Result<T> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let header = read_header_from(&mut reader)?.context("Index header disappeared")?;
+    bincode::deserialize_from(reader).with_context(|| format!("Failed to read index {:?} (format v{})", path, header.version))
+}
+
+/// Rewrite an older index file at the current version, keeping the original as `<file>.v<old>.bak`
+pub fn migrate(path: &Path, from_version: u32, header: &IndexHeader, payload: &impl Serialize) -> Result<PathBuf> {
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(format!(".v{}.bak", from_version));
+    let backup = PathBuf::from(backup);
+    // An earlier interrupted migration already saved the original
+    if !backup.exists() {
+        std::fs::copy(path, &backup).with_context(|| format!("Failed to back up {:?} before migrating it", path))?;
+    }
+    write(path, header, payload)?;
+    tracing::info!("Migrated index {:?} from format v{} to v{} (backup: {:?})", path, from_version, FORMAT_VERSION, backup);
+    Ok(backup)
+}
+
+/// Warn when the vectors were computed by another embedding model than the one now loaded
+pub fn check_embedder(path: &Path, header: &IndexHeader) {
+    let current = super::embeddings::model_id();
+    if header.embedder != current {
+        tracing::warn!("Index {:?} was embedded with '{}', but '{}' is loaded; re-embed for accurate search", path, header.embedder, current);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{HnswParams, SimilarityMetric};
+    use crate::rag::hnsw_store::HnswVectorStore;
+    use crate::rag::store::{LinearVectorStore, VectorStore};
+
+    /// A document as the v0 and v1 writers laid it out
+    #[derive(Serialize)]
+    struct FixtureDocument {
+        id: String,
+        content: String,
+        embedding: Vec<f32>,
+        metadata: HashMap<String, String>,
+        user_id: String,
+    }
+
+    #[derive(Serialize)]
+    struct FixtureLinear {
+        documents: Vec<FixtureDocument>,
+    }
+
+    /// The unversioned HNSW layout from before the metric was recorded
+    #[derive(Serialize)]
+    struct FixtureHnswV0 {
+        documents: HashMap<usize, FixtureDocument>,
+        next_id: usize,
+    }
+
+    #[derive(Serialize)]
+    struct FixtureHnswV1 {
+        documents: HashMap<usize, FixtureDocument>,
+        next_id: usize,
+        metric: SimilarityMetric,
+        params: HnswParams,
+    }
+
+    const FIXTURE: [(&str, [f32; 3]); 3] = [
+        ("algebra.pdf#0", [1.0, 0.0, 0.0]),
+        ("history.pdf#0", [0.0, 1.0, 0.0]),
+        ("physics.pdf#0", [0.0, 0.0, 1.0]),
+    ];
+
+    fn documents() -> Vec<FixtureDocument> {
+        FIXTURE.iter().map(|(id, embedding)| FixtureDocument {
+            id: id.to_string(),
+            content: format!("Contents of {}", id),
+            embedding: embedding.to_vec(),
+            metadata: HashMap::new(),
+            user_id: "user".to_string(),
+        }).collect()
+    }
+
+    fn header(version: u32, store: &str) -> IndexHeader {
+        IndexHeader { version, ..IndexHeader::new(store) }
+    }
+
+    /// Every fixture document is still there and is the best match for its own embedding
+    fn assert_searchable(store: &dyn VectorStore) {
+        assert_eq!(store.count(), FIXTURE.len());
+        for (id, embedding) in FIXTURE {
+            let results = store.search(&embedding, "user", 1, 0.0, None).unwrap();
+            assert_eq!(results[0].0.id, id);
+            assert_eq!(results[0].0.content, format!("Contents of {}", id));
+        }
+    }
+
+    fn linear_fixture(path: &Path, version: u32) {
+        let index = FixtureLinear { documents: documents() };
+        match version {
+            0 => std::fs::write(path, bincode::serialize(&index).unwrap()).unwrap(),
+            v => write(path, &header(v, "linear"), &index).unwrap(),
+        }
+    }
+
+    fn hnsw_fixture(data_path: &Path, version: u32) {
+        let documents: HashMap<usize, FixtureDocument> = documents().into_iter().enumerate().collect();
+        let next_id = documents.len();
+        match version {
+            0 => std::fs::write(data_path, bincode::serialize(&FixtureHnswV0 { documents, next_id }).unwrap()).unwrap(),
+            v => {
+                let data = FixtureHnswV1 { documents, next_id, metric: SimilarityMetric::Cosine, params: HnswParams::default() };
+                write(data_path, &header(v, "hnsw"), &data).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn linear_indexes_of_every_version_migrate() {
+        for version in [0, 1] {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("index.bin");
+            linear_fixture(&path, version);
+
+            let (found, count) = LinearVectorStore::inspect(&path.to_string_lossy()).unwrap().unwrap();
+            assert_eq!(found.map(|h| h.version), (version > 0).then_some(version));
+            assert_eq!(count, FIXTURE.len());
+
+            let store = LinearVectorStore::new(&path.to_string_lossy(), SimilarityMetric::Cosine).unwrap();
+            assert_searchable(&store);
+            assert_eq!(read_header(&path).unwrap().unwrap().version, FORMAT_VERSION);
+            assert!(dir.path().join(format!("index.bin.v{}.bak", version)).exists());
+            // The migrated file opens as the current version
+            assert_searchable(&LinearVectorStore::new(&path.to_string_lossy(), SimilarityMetric::Cosine).unwrap());
+        }
+    }
+
+    #[test]
+    fn hnsw_indexes_of_every_version_migrate() {
+        for version in [0, 1] {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("hnsw.bin");
+            let data_path = path.with_extension("data");
+            // A current store provides the graph files the old data file sits next to
+            let mut store = HnswVectorStore::new(&path.to_string_lossy(), SimilarityMetric::Cosine, HnswParams::default(), None).unwrap();
+            for doc in documents() {
+                store.add_document(Document { id: doc.id, content: doc.content, embedding: doc.embedding, metadata: doc.metadata, user_id: doc.user_id }).unwrap();
+            }
+            store.save().unwrap();
+            drop(store);
+            hnsw_fixture(&data_path, version);
+
+            let (found, count) = HnswVectorStore::inspect(&path.to_string_lossy()).unwrap().unwrap();
+            assert_eq!(found.map(|h| h.version), (version > 0).then_some(version));
+            assert_eq!(count, FIXTURE.len());
+
+            let store = HnswVectorStore::new(&path.to_string_lossy(), SimilarityMetric::Cosine, HnswParams::default(), None).unwrap();
+            assert_searchable(&store);
+            assert_eq!(read_header(&data_path).unwrap().unwrap().version, FORMAT_VERSION);
+            assert!(dir.path().join(format!("hnsw.data.v{}.bak", version)).exists());
+        }
+    }
+
+    #[test]
+    fn newer_versions_are_refused_and_left_untouched() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index.bin");
+        linear_fixture(&path, FORMAT_VERSION + 1);
+        let before = std::fs::read(&path).unwrap();
+
+        assert!(LinearVectorStore::new(&path.to_string_lossy(), SimilarityMetric::Cosine).is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), before);
+    }
+}
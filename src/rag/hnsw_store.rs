@@ -1,33 +1,197 @@
-use super::{Document, store::{VectorStore, StoreStats}};
+use super::{Document, store::{VectorStore, StoreStats, DocFilter, similarity, similarity_from_distance, distance_from_dot, is_family_member, document_stats}};
 use anyhow::{Result, Context};
+use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
 use std::path::{Path, PathBuf};
 use std::fs::File;
-use std::io::{BufReader, BufWriter};
+use std::io::BufReader;
+use std::ops::ControlFlow;
 use serde::{Serialize, Deserialize};
 use hnsw_rs::prelude::*;
 use hnsw_rs::hnswio::HnswIo;
 use hnsw_rs::api::AnnT;
 use std::sync::RwLock;
+use crate::config::{HnswParams, SimilarityMetric};
+use super::format::{self, IndexHeader, Loaded};
+use super::precision::{self, Precision};
+
+/// How much wider the search beam is for a filtered search, whose walk passes through
+/// neighbours the filter rejects
+const FILTERED_OVERSAMPLING: usize = 4;
+/// `compact` rebuilds the graph once removed points reach this share of the live ones
+const COMPACT_RATIO: f32 = 0.25;
 
 // Wrapper struct for serialization
 #[derive(Serialize, Deserialize)]
 struct StoredData {
     documents: HashMap<usize, Document>,
     next_id: usize,
+    // Metric the graph was built with; the graph must be rebuilt if the config changes
+    metric: SimilarityMetric,
+    // Build parameters of the dumped graph, compared against the config to flag a pending rebuild
+    params: HnswParams,
     // We don't serialize HNSW here, it has its own method
 }
 
+// `StoredData` as `save` writes it, the embeddings at the store's precision
+#[derive(Serialize)]
+struct EncodedData<'a> {
+    documents: precision::Encoded<'a, HashMap<usize, Document>>,
+    next_id: usize,
+    metric: SimilarityMetric,
+    params: HnswParams,
+}
+
+// Layout of format v1, and of unversioned files written once the parameters were recorded
+#[derive(Deserialize)]
+struct StoredDataV1 {
+    documents: HashMap<usize, format::DocumentV1>,
+    next_id: usize,
+    metric: SimilarityMetric,
+    params: HnswParams,
+}
+
+// Layout written before the build parameters were recorded
+#[derive(Deserialize)]
+struct MetricStoredData {
+    documents: HashMap<usize, format::DocumentV1>,
+    next_id: usize,
+    metric: SimilarityMetric,
+}
+
+// Layout written before the metric was recorded (always cosine)
+#[derive(Deserialize)]
+struct LegacyStoredData {
+    documents: HashMap<usize, format::DocumentV1>,
+    next_id: usize,
+}
+
+impl From<StoredDataV1> for StoredData {
+    fn from(data: StoredDataV1) -> Self {
+        StoredData { documents: upgrade(data.documents), next_id: data.next_id, metric: data.metric, params: data.params }
+    }
+}
+
+fn upgrade(documents: HashMap<usize, format::DocumentV1>) -> HashMap<usize, Document> {
+    documents.into_iter().map(|(id, doc)| (id, doc.into())).collect()
+}
+
+/// HNSW graph specialised for the configured similarity metric.
+/// hnsw_rs fixes the distance as a type parameter, so each metric is its own variant.
+enum HnswGraph {
+    Cosine(Hnsw<'static, f32, DistCosine>),
+    // DistDot asserts on dot > 1.0, which rounding on normalized vectors can trigger
+    Dot(Hnsw<'static, f32, DistFn<f32>>),
+    Euclidean(Hnsw<'static, f32, DistL2>),
+}
+
+fn dot_distance() -> DistFn<f32> {
+    DistFn::new(Box::new(|a: &[f32], b: &[f32]| {
+        let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+        distance_from_dot(dot)
+    }))
+}
+
+impl HnswGraph {
+    fn new(metric: SimilarityMetric, params: &HnswParams) -> Self {
+        let m = params.max_nb_connection;
+        let max_elements = params.max_elements;
+        let max_layer = params.max_layer;
+        let ef_c = params.ef_construction;
+        match metric {
+            SimilarityMetric::Cosine => HnswGraph::Cosine(Hnsw::new(m, max_elements, max_layer, ef_c, DistCosine)),
+            SimilarityMetric::Dot => HnswGraph::Dot(Hnsw::new(m, max_elements, max_layer, ef_c, dot_distance())),
+            SimilarityMetric::Euclidean => HnswGraph::Euclidean(Hnsw::new(m, max_elements, max_layer, ef_c, DistL2)),
+        }
+    }
+
+    /// Build a fresh graph holding every stored embedding
+    fn build(metric: SimilarityMetric, params: &HnswParams, documents: &HashMap<usize, Document>) -> Self {
+        let hnsw = Self::new(metric, params);
+        for (internal_id, doc) in documents {
+            hnsw.insert(&doc.embedding, *internal_id);
+        }
+        hnsw
+    }
+
+    fn load(hnswio: &'static mut HnswIo, metric: SimilarityMetric) -> Result<Self> {
+        let graph = match metric {
+            SimilarityMetric::Cosine => HnswGraph::Cosine(hnswio.load_hnsw::<f32, DistCosine>()?),
+            SimilarityMetric::Dot => HnswGraph::Dot(hnswio.load_hnsw_with_dist::<f32, DistFn<f32>>(dot_distance())?),
+            SimilarityMetric::Euclidean => HnswGraph::Euclidean(hnswio.load_hnsw::<f32, DistL2>()?),
+        };
+        Ok(graph)
+    }
+
+    fn insert(&self, data: &[f32], id: usize) {
+        match self {
+            HnswGraph::Cosine(h) => h.insert((data, id)),
+            HnswGraph::Dot(h) => h.insert((data, id)),
+            HnswGraph::Euclidean(h) => h.insert((data, id)),
+        }
+    }
+
+    fn search(&self, query: &[f32], k: usize, ef_search: usize, filter: Option<&dyn FilterT>) -> Vec<Neighbour> {
+        match self {
+            HnswGraph::Cosine(h) => h.search_filter(query, k, ef_search, filter),
+            HnswGraph::Dot(h) => h.search_filter(query, k, ef_search, filter),
+            HnswGraph::Euclidean(h) => h.search_filter(query, k, ef_search, filter),
+        }
+    }
+
+    /// Points in the graph, removed documents' included
+    fn nb_points(&self) -> usize {
+        match self {
+            HnswGraph::Cosine(h) => h.get_nb_point(),
+            HnswGraph::Dot(h) => h.get_nb_point(),
+            HnswGraph::Euclidean(h) => h.get_nb_point(),
+        }
+    }
+
+    fn file_dump(&self, directory: &Path, basename: &str) -> Result<String> {
+        match self {
+            HnswGraph::Cosine(h) => h.file_dump(directory, basename),
+            HnswGraph::Dot(h) => h.file_dump(directory, basename),
+            HnswGraph::Euclidean(h) => h.file_dump(directory, basename),
+        }
+    }
+}
+
 pub struct HnswVectorStore {
-    hnsw: RwLock<Hnsw<'static, f32, DistCosine>>,
+    hnsw: RwLock<HnswGraph>,
     documents: RwLock<HashMap<usize, Document>>, // Internal ID -> Document
     id_map: RwLock<HashMap<String, usize>>,      // External ID -> Internal ID
     next_id: RwLock<usize>,
     storage_path: PathBuf,
+    metric: SimilarityMetric,
+    params: HnswParams,     // Configured build parameters
+    built_with: HnswParams, // Parameters the current graph was actually built with
+    ef_search: Option<usize>,
+    /// How `save` writes the embeddings of the document map. The graph dump keeps its own
+    /// f32 copy of every point, tombstones included, so f16 shrinks only the map.
+    precision: Precision,
+    /// Points of removed or replaced documents still in the graph. hnsw_rs can't delete a
+    /// point, so their internal ids are tombstones: no document maps to them and searches
+    /// skip them until `compact` rebuilds the graph.
+    dead: usize,
+    /// Tombstone and embedding hash of each removed document. One added back with the same
+    /// embedding, as a sync does with unchanged chunks, revives its point: a new point next
+    /// to an identical tombstone is poorly linked and the walk can miss it. Not saved.
+    removed: HashMap<String, (usize, u64)>,
+}
+
+/// Hash of the exact values of an embedding
+fn embedding_hash(embedding: &[f32]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for value in embedding {
+        value.to_bits().hash(&mut hasher);
+    }
+    hasher.finish()
 }
 
 impl HnswVectorStore {
-    pub fn new(storage_path: &str) -> Result<Self> {
+    pub fn new(storage_path: &str, metric: SimilarityMetric, params: HnswParams, ef_search: Option<usize>) -> Result<Self> {
         let path = Path::new(storage_path);
         let _hnsw_path = path.with_extension("hnsw.graph"); // hnsw_rs appends .graph and .data
         let data_path = path.with_extension("data");
@@ -37,34 +201,44 @@ impl HnswVectorStore {
         // HnswIo usually uses basename.
         
         // We will assume if data_path exists, we can try to load.
-        let (hnsw, documents, next_id) = if data_path.exists() {
+        let (hnsw, documents, next_id, built_with) = if data_path.exists() {
             tracing::info!("Loading HNSW index from {:?}", path);
             
-            let directory = path.parent().unwrap_or(Path::new("."));
-            let basename = path.file_stem().unwrap().to_str().unwrap();
-            
-            // We need to leak HnswIo because Hnsw returned by load_hnsw takes a lifetime linked to HnswIo.
-            // Since we need Hnsw to match HnswVectorStore's 'static lifetime requirement (from VectorStore trait),
-            // we must make HnswIo live for 'static.
-            // This is a one-time leak per application run (singleton store), so it's acceptable.
-            let hnswio = Box::new(HnswIo::new(directory, basename));
-            let hnswio = Box::leak(hnswio);
-            
-            let hnsw = hnswio.load_hnsw::<f32, DistCosine>()
-                .context("Failed to load HNSW index")?;
+            let data = Self::read_stored_data(&data_path)?;
             
-            let file = File::open(&data_path)?;
-            let reader = BufReader::new(file);
-            let data: StoredData = bincode::deserialize_from(reader)?;
-            
-            (hnsw, data.documents, data.next_id)
+            if data.metric != metric {
+                // The dumped graph encodes the old distance, rebuild it from the stored embeddings
+                tracing::info!("Similarity metric changed ({:?} -> {:?}), rebuilding HNSW graph...", data.metric, metric);
+                let hnsw = HnswGraph::build(metric, &params, &data.documents);
+                (hnsw, data.documents, data.next_id, params)
+            } else {
+                let directory = path.parent().unwrap_or(Path::new("."));
+                let basename = path.file_stem().unwrap().to_str().unwrap();
+                
+                // We need to leak HnswIo because Hnsw returned by load_hnsw takes a lifetime linked to HnswIo.
+                // Since we need Hnsw to match HnswVectorStore's 'static lifetime requirement (from VectorStore trait),
+                // we must make HnswIo live for 'static.
+                // This is a one-time leak per application run (singleton store), so it's acceptable.
+                let hnswio = Box::new(HnswIo::new(directory, basename));
+                let hnswio = Box::leak(hnswio);
+                
+                let hnsw = HnswGraph::load(hnswio, metric)
+                    .context("Failed to load HNSW index")?;
+
+                if data.params != params {
+                    // Keep serving the existing graph, rebuilding is an explicit action
+                    tracing::info!("HNSW build parameters changed ({:?} -> {:?}), rebuild pending", data.params, params);
+                }
+                
+                (hnsw, data.documents, data.next_id, data.params)
+            }
         } else {
             tracing::info!("Creating new HNSW index");
-            // Parameters can be tuned. M=24, ef_construction=10000 are decent defaults.
-            let hnsw = Hnsw::new(24, 10000, 16, 200, DistCosine);
-            (hnsw, HashMap::new(), 0)
+            (HnswGraph::new(metric, &params), HashMap::new(), 0, params)
         };
 
+        let dead = hnsw.nb_points().saturating_sub(documents.len());
+
         // Rebuild reverse map
         let mut id_map = HashMap::new();
         for (internal_id, doc) in &documents {
@@ -77,32 +251,204 @@ impl HnswVectorStore {
             id_map: RwLock::new(id_map),
             next_id: RwLock::new(next_id),
             storage_path: path.to_path_buf(),
+            metric,
+            params,
+            built_with,
+            ef_search,
+            precision: Precision::F32,
+            dead,
+            removed: HashMap::new(),
         })
     }
+
+    /// Write the embeddings at `precision` from the next save on
+    pub fn with_precision(mut self, precision: Precision) -> Self {
+        self.precision = precision;
+        self
+    }
+
+    /// Read the document map. Older files are migrated to the current format first.
+    fn read_stored_data(data_path: &Path) -> Result<StoredData> {
+        let (data, outdated) = Self::read_any_version(data_path)?;
+        if let Some(version) = outdated {
+            format::migrate(data_path, version, &IndexHeader::new("hnsw"), &data)?;
+        }
+        Ok(data)
+    }
+
+    /// The document map in whatever format the file has, and its version when that is
+    /// older than the current one. Nothing is migrated.
+    fn read_any_version(data_path: &Path) -> Result<(StoredData, Option<u32>)> {
+        match format::read::<StoredData>(data_path)? {
+            Loaded::Current(header, data) => {
+                format::check_embedder(data_path, &header);
+                Ok((data, None))
+            }
+            Loaded::Previous(header) => {
+                format::check_embedder(data_path, &header);
+                let data = format::read_previous::<StoredDataV1>(data_path)?;
+                Ok((data.into(), Some(header.version)))
+            }
+            Loaded::Unversioned => Ok((Self::read_unversioned_data(data_path)?, Some(0))),
+        }
+    }
+
+    /// Header and document count of the index at `storage_path`, without loading the graph or migrating.
+    /// `None` when there is no index yet.
+    pub fn inspect(storage_path: &str) -> Result<Option<(Option<IndexHeader>, usize)>> {
+        let data_path = Path::new(storage_path).with_extension("data");
+        if !data_path.exists() {
+            return Ok(None);
+        }
+        let header = format::read_header(&data_path)?;
+        let documents = Self::read_any_version(&data_path)?.0.documents.len();
+        Ok(Some((header, documents)))
+    }
+
+    /// Documents of the index at `storage_path`, without loading the graph or migrating.
+    /// `None` when there is no index yet.
+    pub fn stored_documents(storage_path: &str) -> Result<Option<Vec<Document>>> {
+        let data_path = Path::new(storage_path).with_extension("data");
+        if !data_path.exists() {
+            return Ok(None);
+        }
+        let data = Self::read_any_version(&data_path)?.0;
+        Ok(Some(data.documents.into_values().collect()))
+    }
+
+    /// Format v0: the layouts written before the header, and before the metric and parameters were stored
+    fn read_unversioned_data(data_path: &Path) -> Result<StoredData> {
+        let file = File::open(data_path)?;
+        if let Ok(data) = bincode::deserialize_from::<_, StoredDataV1>(BufReader::new(file)) {
+            return Ok(data.into());
+        }
+
+        // Graphs dumped before the parameters were recorded always used the defaults
+        let file = File::open(data_path)?;
+        if let Ok(data) = bincode::deserialize_from::<_, MetricStoredData>(BufReader::new(file)) {
+            return Ok(StoredData {
+                documents: upgrade(data.documents),
+                next_id: data.next_id,
+                metric: data.metric,
+                params: HnswParams::default(),
+            });
+        }
+
+        let file = File::open(data_path)?;
+        let legacy: LegacyStoredData = bincode::deserialize_from(BufReader::new(file))?;
+        Ok(StoredData {
+            documents: upgrade(legacy.documents),
+            next_id: legacy.next_id,
+            metric: SimilarityMetric::Cosine,
+            params: HnswParams::default(),
+        })
+    }
+
+    /// Exact search over the documents `accepts` lets through
+    fn scan(&self, documents: &HashMap<usize, Document>, query_embedding: &[f32], top_k: usize, min_threshold: f32, accepts: &dyn Fn(&DataId) -> bool) -> Vec<(Document, f32)> {
+        let mut scored: Vec<(&Document, f32)> = documents.iter()
+            .filter(|(id, _)| accepts(id))
+            .map(|(_, doc)| (doc, similarity(self.metric, query_embedding, &doc.embedding)))
+            .filter(|(_, score)| *score > min_threshold)
+            .collect();
+        scored.sort_by(|a, b| b.1.total_cmp(&a.1));
+        if top_k > 0 {
+            scored.truncate(top_k);
+        }
+        scored.into_iter().map(|(doc, score)| (doc.clone(), score)).collect()
+    }
+
+    /// Query breadth for a search: the configured override, or max(64, 4 * top_k)
+    fn effective_ef_search(&self, top_k: usize) -> usize {
+        self.ef_search
+            .unwrap_or_else(|| (4 * top_k).max(64))
+            .max(top_k)
+    }
+
+    /// Override ef_search for subsequent queries (None restores the automatic value)
+    pub fn set_ef_search(&mut self, ef_search: Option<usize>) {
+        self.ef_search = ef_search;
+    }
+
+    /// The document map, then the graph and the vectors hnsw_rs dumps next to it
+    fn files(&self) -> [PathBuf; 3] {
+        let directory = self.storage_path.parent().unwrap_or(Path::new("."));
+        let basename = self.storage_path.file_stem().unwrap().to_str().unwrap();
+        [
+            self.storage_path.with_extension("data"),
+            directory.join(format!("{}.hnsw.graph", basename)),
+            directory.join(format!("{}.hnsw.data", basename)),
+        ]
+    }
+
+    /// Points of removed or replaced documents left in the graph
+    pub fn dead_points(&self) -> usize {
+        self.dead
+    }
+
+    /// Whether the configured build parameters differ from those of the current graph
+    pub fn rebuild_pending(&self) -> bool {
+        self.built_with != self.params
+    }
+
+    /// Graph parameters shown on the RAG Info screen
+    fn index_params(&self) -> Vec<(String, String)> {
+        let ef_search = match self.ef_search {
+            Some(ef) => ef.to_string(),
+            None => "auto (max(64, 4 x top_k))".to_string(),
+        };
+        let params = &self.built_with;
+        let mut out = vec![
+            ("M".to_string(), params.max_nb_connection.to_string()),
+            ("ef_construction".to_string(), params.ef_construction.to_string()),
+            ("Max Layer".to_string(), params.max_layer.to_string()),
+            ("Max Elements".to_string(), params.max_elements.to_string()),
+            ("ef_search".to_string(), ef_search),
+        ];
+        if self.dead > 0 {
+            out.push(("Dead Points".to_string(), format!("{} (compacted at {:.0}% of live)", self.dead, COMPACT_RATIO * 100.0)));
+        }
+        if self.rebuild_pending() {
+            out.push(("Rebuild".to_string(), "pending (config differs, press [B])".to_string()));
+        }
+        out
+    }
 }
 
 impl VectorStore for HnswVectorStore {
     fn add_document(&mut self, doc: Document) -> Result<()> {
-        let hnsw = self.hnsw.write().unwrap();
+        let hnsw = self.hnsw.read().unwrap();
         let mut documents = self.documents.write().unwrap();
         let mut id_map = self.id_map.write().unwrap();
         let mut next_id = self.next_id.write().unwrap();
 
-        // Check if exists
-        let internal_id = if let Some(&id) = id_map.get(&doc.id) {
-            // Re-using ID. 
-            // Note: hnsw_rs insert usually allows updating if ID exists, 
-            // but older points might linger in graph connectivity until rewrite/optimization.
-            id
-        } else {
-            let id = *next_id;
-            *next_id += 1;
-            id
+        // With its embedding unchanged a document keeps its point, or gets it back
+        let unchanged = match id_map.get(&doc.id) {
+            Some(old_id) => documents.get(old_id).filter(|old| old.embedding == doc.embedding).map(|_| *old_id),
+            None => self.removed.get(&doc.id).filter(|(_, hash)| *hash == embedding_hash(&doc.embedding)).map(|(old_id, _)| *old_id),
         };
+        if let Some(old_id) = unchanged {
+            if self.removed.remove(&doc.id).is_some() {
+                self.dead -= 1;
+            }
+            documents.insert(old_id, doc.clone());
+            id_map.insert(doc.id, old_id);
+            return Ok(());
+        }
+
+        // Otherwise it gets a fresh point: the old one would keep matching the old
+        // embedding, so it becomes a tombstone
+        if let Some(old_id) = id_map.get(&doc.id) {
+            documents.remove(old_id);
+            self.dead += 1;
+        }
+        self.removed.remove(&doc.id);
+        let internal_id = *next_id;
+        *next_id += 1;
 
         // Insert into HNSW
         // Tuple (data, id)
-        hnsw.insert((&doc.embedding, internal_id));
+        hnsw.insert(&doc.embedding, internal_id);
         
         // Update maps
         documents.insert(internal_id, doc.clone());
@@ -111,14 +457,34 @@ impl VectorStore for HnswVectorStore {
         Ok(())
     }
 
-    fn search(&self, query_embedding: &[f32], user_id: &str, top_k: usize, min_threshold: f32) -> Result<Vec<(Document, f32)>> {
+    fn search(&self, query_embedding: &[f32], user_id: &str, top_k: usize, min_threshold: f32, filter: Option<DocFilter>) -> Result<Vec<(Document, f32)>> {
         let hnsw = self.hnsw.read().unwrap();
         let documents = self.documents.read().unwrap();
 
-        let ef_search = top_k * 2; 
+        let ef_search = self.effective_ef_search(if filter.is_some() { top_k * FILTERED_OVERSAMPLING } else { top_k });
+
+        // A filter is checked during the graph walk, so the k neighbours all pass it. With
+        // tombstones in the graph it also keeps their points from taking the places.
+        let accepts = |id: &DataId| documents.get(id).is_some_and(|d| d.user_id == user_id && filter.is_none_or(|f| f(d)));
+        let graph_filter: Option<&dyn FilterT> = if filter.is_some() || self.dead > 0 { Some(&accepts) } else { None };
+
+        // Candidates no more than the walk's breadth are scored exactly: it costs about the same,
+        // and so few points, or a filter this sparse, can leave some of them unreachable
+        let candidates = match filter {
+            Some(_) => documents.keys().filter(|id| accepts(id)).take(ef_search + 1).count(),
+            None => documents.len(),
+        };
+        if candidates <= ef_search {
+            return Ok(self.scan(&documents, query_embedding, top_k, min_threshold, &accepts));
+        }
 
         // Search returns Vec<Neighbour>
-        let neighbors = hnsw.search(query_embedding, top_k, ef_search);
+        let neighbors = hnsw.search(query_embedding, top_k, ef_search, graph_filter);
+        if graph_filter.is_some() && neighbors.len() < top_k {
+            // The filter accepts few documents, or tombstones cut the walk short, so it didn't
+            // reach them all; scoring them one by one is cheap then
+            return Ok(self.scan(&documents, query_embedding, top_k, min_threshold, &accepts));
+        }
         
         let mut results = Vec::new();
 
@@ -126,11 +492,9 @@ impl VectorStore for HnswVectorStore {
             if let Some(doc) = documents.get(&neighbor.d_id) {
                 // Filter by user_id
                 if doc.user_id == user_id {
-                    // DistCosine in hnsw_rs: distance = 1.0 - similarity (usually)
-                    // Let's assume this based on common practice and crate name.
-                    let similarity = 1.0 - neighbor.distance;
+                    let similarity = similarity_from_distance(self.metric, neighbor.distance);
                     
-                    if similarity >= min_threshold {
+                    if similarity > min_threshold {
                         results.push((doc.clone(), similarity));
                     }
                 }
@@ -146,9 +510,12 @@ impl VectorStore for HnswVectorStore {
         Ok(results)
     }
 
-    fn get_all(&self) -> Result<Vec<Document>> {
-        let documents = self.documents.read().unwrap();
-        Ok(documents.values().cloned().collect())
+    fn for_each_document(&self, f: &mut dyn FnMut(&Document) -> ControlFlow<()>) {
+        for doc in self.documents.read().unwrap().values() {
+            if f(doc).is_break() {
+                break;
+            }
+        }
     }
 
     fn count(&self) -> usize {
@@ -161,10 +528,13 @@ impl VectorStore for HnswVectorStore {
         let mut id_map = self.id_map.write().unwrap();
         let mut next_id = self.next_id.write().unwrap();
 
-        *hnsw = Hnsw::new(24, 10000, 16, 200, DistCosine);
+        *hnsw = HnswGraph::new(self.metric, &self.params);
+        self.built_with = self.params;
         documents.clear();
         id_map.clear();
         *next_id = 0;
+        self.dead = 0;
+        self.removed.clear();
         
         // Need to save to clear files on disk too
         // We drop lock to call save which re-acquires read lock
@@ -179,23 +549,70 @@ impl VectorStore for HnswVectorStore {
         self.id_map.read().unwrap().contains_key(id)
     }
 
+    fn get_document(&self, id: &str) -> Option<Document> {
+        let internal_id = *self.id_map.read().unwrap().get(id)?;
+        self.documents.read().unwrap().get(&internal_id).cloned()
+    }
+
+    fn set_metadata(&mut self, id: &str, key: &str, value: Option<String>) -> bool {
+        let Some(internal_id) = self.id_map.read().unwrap().get(id).copied() else { return false };
+        let mut documents = self.documents.write().unwrap();
+        let Some(doc) = documents.get_mut(&internal_id) else { return false };
+        match value {
+            Some(value) => doc.metadata.insert(key.to_string(), value),
+            None => doc.metadata.remove(key),
+        };
+        true
+    }
+
     fn remove_document(&mut self, id: &str) -> Result<()> {
         let mut documents = self.documents.write().unwrap();
         let mut id_map = self.id_map.write().unwrap();
         
         if let Some(internal_id) = id_map.remove(id) {
-            documents.remove(&internal_id);
-            // Internal ID is now effectively "orphaned" in the HNSW graph.
-            // On save, we only iterate over `documents`, so it will be cleaned up.
+            // Its point stays in the graph as a tombstone until `compact`
+            if let Some(doc) = documents.remove(&internal_id) {
+                self.removed.insert(id.to_string(), (internal_id, embedding_hash(&doc.embedding)));
+            }
+            self.dead += 1;
         }
         
         Ok(())
     }
 
+    fn family_ids(&self, base_id: &str) -> Vec<String> {
+        self.id_map.read().unwrap().keys()
+            .filter(|id| is_family_member(id, base_id))
+            .cloned()
+            .collect()
+    }
+
+    fn rebuild(&mut self) -> Result<()> {
+        let documents = self.documents.read().unwrap();
+        tracing::info!("Rebuilding HNSW graph over {} documents with {:?}", documents.len(), self.params);
+        let rebuilt = HnswGraph::build(self.metric, &self.params, &documents);
+        *self.hnsw.write().unwrap() = rebuilt;
+        drop(documents);
+        self.built_with = self.params;
+        self.dead = 0;
+        self.removed.clear();
+        Ok(())
+    }
+
+    fn compact(&mut self) -> Result<bool> {
+        let live = self.documents.read().unwrap().len();
+        if self.dead == 0 || (self.dead as f32) < COMPACT_RATIO * live as f32 {
+            return Ok(false);
+        }
+        tracing::info!("Compacting HNSW graph: {} removed points next to {} live ones", self.dead, live);
+        self.rebuild()?;
+        Ok(true)
+    }
+
     fn get_documents_by_metadata(&self, key: &str, value: &str) -> Result<Vec<Document>> {
         let documents = self.documents.read().unwrap();
         let docs = documents.values()
-            .filter(|d| d.metadata.get(key).map_or(false, |v| v == value))
+            .filter(|d| d.metadata.get(key).is_some_and(|v| v == value))
             .cloned()
             .collect();
         Ok(docs)
@@ -206,7 +623,8 @@ impl VectorStore for HnswVectorStore {
         let documents = self.documents.read().unwrap();
         let next_id = *self.next_id.read().unwrap();
 
-        let data_path = self.storage_path.with_extension("data");
+        let files = self.files();
+        let data_path = &files[0];
         
         let directory = self.storage_path.parent().unwrap_or(Path::new("."));
         let basename = self.storage_path.file_stem().unwrap().to_str().unwrap();
@@ -214,31 +632,27 @@ impl VectorStore for HnswVectorStore {
         // Handle empty index case: hnsw_rs fails on empty dump, so we remove files instead
         if documents.is_empty() {
              tracing::info!("Index is empty, removing persistence files.");
-             let _ = std::fs::remove_file(&data_path);
-             let _ = std::fs::remove_file(directory.join(format!("{}.hnsw.graph", basename)));
-             let _ = std::fs::remove_file(directory.join(format!("{}.hnsw.data", basename)));
+             for file in &files {
+                 let _ = std::fs::remove_file(file);
+             }
              return Ok(());
         }
 
         tracing::info!("Dumping HNSW to dir: {:?}, basename: {}", directory, basename);
-        if !directory.exists() {
-             tracing::info!("Directory {:?} does not exist, creating...", directory);
-             std::fs::create_dir_all(directory)?;
-        }
+        crate::config::check_writable(directory)?;
+        crate::disk::check_save(directory, &files)?;
 
         hnsw.file_dump(directory, basename).context(format!("Failed to save HNSW index to {:?}/{}", directory, basename))?;
 
         // Save Data
-        let data = StoredData {
-            documents: documents.clone(),
+        let data = EncodedData {
+            documents: precision::Encoded(&*documents, self.precision),
             next_id,
+            metric: self.metric,
+            params: self.built_with,
         };
         
-        let file = File::create(&data_path)?;
-        let writer = BufWriter::new(file);
-        bincode::serialize_into(writer, &data)?;
-
-        Ok(())
+        format::write(data_path, &IndexHeader::new("hnsw"), &data)
     }
 
 
@@ -247,37 +661,376 @@ impl VectorStore for HnswVectorStore {
     }
 
     fn store_type(&self) -> String {
-        "HNSW (Approximate)".to_string()
+        format!("HNSW (Approximate, {})", self.metric.label())
     }
 
     fn get_stats(&self) -> StoreStats {
-        let documents = self.documents.read().unwrap();
-        
-        let mut docs_by_type: HashMap<String, usize> = HashMap::new();
-        let mut total_content_bytes: usize = 0;
-        let mut total_embedding_dims: usize = 0;
-        
-        for doc in documents.values() {
-            total_content_bytes += doc.content.len();
-            total_embedding_dims = doc.embedding.len();
-            
-            let doc_type = doc.metadata.get("type").cloned().unwrap_or_else(|| "unknown".to_string());
-            *docs_by_type.entry(doc_type).or_insert(0) += 1;
-        }
-        
-        let file_size_bytes = std::fs::metadata(self.storage_path.with_extension("hnsw"))
-            .map(|m| m.len())
-            .unwrap_or(0) + 
-            std::fs::metadata(self.storage_path.with_extension("data"))
-            .map(|m| m.len())
-            .unwrap_or(0);
-            
+        let sizes = self.files().map(|file| std::fs::metadata(file).map_or(0, |m| m.len()));
         StoreStats {
-            document_count: documents.len(),
-            docs_by_type,
-            total_content_bytes,
-            embedding_dimensions: total_embedding_dims,
-            file_size_bytes,
+            file_size_bytes: sizes.iter().sum(),
+            // Only the document map follows the precision, the dumped graph data stays f32
+            precision_bytes: sizes[0],
+            index_params: self.index_params(),
+            ..document_stats(self)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rag::SearchFilter;
+    use crate::rag::store::LinearVectorStore;
+    use crate::rag::sqlite_store::SqliteVectorStore;
+
+    fn doc(id: &str, embedding: &[f32]) -> Document {
+        Document {
+            id: id.to_string(),
+            content: format!("Contents of {}", id),
+            embedding: embedding.to_vec(),
+            metadata: HashMap::new(),
+            user_id: "user".to_string(),
+        }
+    }
+
+    const CORPUS: [(&str, [f32; 4]); 5] = [
+        ("algebra.pdf#0", [0.9, 0.1, 0.0, 0.0]),
+        ("algebra.pdf#1", [0.7, 0.7, 0.0, 0.0]),
+        ("history.pdf#0", [0.0, 0.2, 0.9, 0.1]),
+        ("physics.pdf#0", [0.1, 0.0, 0.1, 0.9]),
+        ("chemistry.pdf#0", [0.0, 0.9, 0.3, 0.0]),
+    ];
+
+    fn hnsw_store(dir: &Path, metric: SimilarityMetric) -> HnswVectorStore {
+        let path = dir.join("hnsw.bin").to_string_lossy().to_string();
+        let mut store = HnswVectorStore::new(&path, metric, HnswParams::default(), None).unwrap();
+        for (id, embedding) in CORPUS {
+            store.add_document(doc(id, &embedding)).unwrap();
+        }
+        store
+    }
+
+    fn top_ids(store: &dyn VectorStore, query: &[f32], top_k: usize, filter: Option<DocFilter>) -> Vec<String> {
+        store.search(query, "user", top_k, 0.0, filter).unwrap().into_iter().map(|(d, _)| d.id).collect()
+    }
+
+    #[test]
+    fn linear_and_hnsw_agree_on_the_best_match() {
+        // Five documents are under ef_search, so they are scored exactly, not walked
+        let dir = tempfile::tempdir().unwrap();
+        let hnsw = hnsw_store(dir.path(), SimilarityMetric::Cosine);
+        let mut linear = LinearVectorStore::new(&dir.path().join("linear.bin").to_string_lossy(), SimilarityMetric::Cosine).unwrap();
+        for (id, embedding) in CORPUS {
+            linear.add_document(doc(id, &embedding)).unwrap();
+        }
+
+        for query in [[1.0, 0.0, 0.0, 0.0], [0.0, 0.1, 1.0, 0.0], [0.0, 0.0, 0.0, 1.0], [0.1, 1.0, 0.2, 0.0], [0.6, 0.8, 0.0, 0.0]] {
+            let expected = top_ids(&linear, &query, 1, None);
+            assert_eq!(top_ids(&hnsw, &query, 1, None), expected, "query {:?}", query);
+        }
+    }
+
+    /// `count` unit vectors of `dimensions` from a fixed seed
+    fn unit_vectors(count: usize, dimensions: usize, mut seed: u64) -> Vec<Vec<f32>> {
+        let mut next = move || {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            ((seed >> 33) as f32 / (1u64 << 31) as f32) * 2.0 - 1.0
+        };
+        (0..count).map(|_| {
+            let v: Vec<f32> = (0..dimensions).map(|_| next()).collect();
+            let norm = v.iter().map(|x| x * x).sum::<f32>().sqrt();
+            v.into_iter().map(|x| x / norm).collect()
+        }).collect()
+    }
+
+    #[test]
+    fn graph_search_agrees_with_the_linear_store() {
+        let mut linear = LinearVectorStore::in_memory(SimilarityMetric::Cosine);
+        let embeddings = unit_vectors(500, 4, 3);
+        for (i, embedding) in embeddings.iter().enumerate() {
+            linear.add_document(doc(&format!("doc{}", i), embedding)).unwrap();
+        }
+        let queries = unit_vectors(20, 4, 33);
+
+        // hnsw_rs draws the graph's layers from an unseeded RNG and one build in a few hundred
+        // is poorly connected, so the best of three builds is checked
+        let agreeing = (0..3).map(|_| {
+            // Big enough that searches walk the graph instead of scoring every document
+            let dir = tempfile::tempdir().unwrap();
+            let hnsw = store_of(dir.path(), &embeddings);
+            assert!(hnsw.count() > hnsw.effective_ef_search(1));
+            queries.iter().filter(|q| top_ids(&hnsw, q, 1, None) == top_ids(&linear, q, 1, None)).count()
+        }).max().unwrap();
+
+        // The walk is approximate: nearly every query, not all, finds the exact best match
+        assert!(agreeing >= 18, "{} of {} queries agree", agreeing, queries.len());
+    }
+
+    /// A store of one document per embedding, `doc<i>`
+    fn store_of(dir: &Path, embeddings: &[Vec<f32>]) -> HnswVectorStore {
+        let mut store = HnswVectorStore::new(&dir.join("hnsw.bin").to_string_lossy(), SimilarityMetric::Cosine, HnswParams::default(), None).unwrap();
+        for (i, embedding) in embeddings.iter().enumerate() {
+            store.add_document(doc(&format!("doc{}", i), embedding)).unwrap();
+        }
+        store
+    }
+
+    fn contents(store: &HnswVectorStore, query: &[f32], top_k: usize) -> Vec<String> {
+        store.search(query, "user", top_k, -1.0, None).unwrap().into_iter().map(|(d, _)| d.content).collect()
+    }
+
+    #[test]
+    fn removed_and_replaced_documents_never_come_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let embeddings = unit_vectors(300, 8, 7);
+        let mut store = store_of(dir.path(), &embeddings);
+
+        // Replaced with another text and embedding: the old point still sits near its query
+        let second = unit_vectors(1, 8, 77).remove(0);
+        let mut replaced = doc("doc0", &second);
+        replaced.content = "Second version".to_string();
+        store.add_document(replaced).unwrap();
+        store.remove_document("doc2").unwrap();
+        assert_eq!(store.dead_points(), 2);
+        assert_eq!(store.count(), 299);
+        // Enough documents that searches walk the graph, which skips the tombstones
+        assert!(store.count() > store.effective_ef_search(10));
+
+        for query in [&embeddings[0], &embeddings[2], &second] {
+            let found = contents(&store, query, 10);
+            assert_eq!(found.len(), 10);
+            assert!(!found.contains(&"Contents of doc0".to_string()), "{:?}", found);
+            assert!(!found.contains(&"Contents of doc2".to_string()), "{:?}", found);
+            assert!(found.iter().filter(|c| *c == "Second version").count() <= 1);
+        }
+        assert_eq!(contents(&store, &second, 1), ["Second version"]);
+
+        // Added again unchanged, it gets its point back and is found once
+        store.add_document(doc("doc2", &embeddings[2])).unwrap();
+        assert_eq!(store.dead_points(), 1);
+        let found = top_ids(&store, &embeddings[2], 10, None);
+        assert_eq!(found[0], "doc2");
+        assert_eq!(found.iter().filter(|id| *id == "doc2").count(), 1);
+
+        // Also after a save and a reload
+        store.save().unwrap();
+        drop(store);
+        let store = HnswVectorStore::new(&dir.path().join("hnsw.bin").to_string_lossy(), SimilarityMetric::Cosine, HnswParams::default(), None).unwrap();
+        assert_eq!(store.count(), 300);
+        assert_eq!(store.dead_points(), 1);
+        assert!(!contents(&store, &embeddings[0], 10).contains(&"Contents of doc0".to_string()));
+    }
+
+    #[test]
+    fn an_unchanged_document_keeps_its_point() {
+        let dir = tempfile::tempdir().unwrap();
+        let embeddings = unit_vectors(100, 8, 11);
+        let mut store = store_of(dir.path(), &embeddings);
+
+        // Re-synced with new metadata but the same embedding
+        let mut resynced = doc("doc5", &embeddings[5]);
+        resynced.content = "Same text, new header".to_string();
+        store.add_document(resynced).unwrap();
+        assert_eq!(store.dead_points(), 0);
+        assert_eq!(store.get_document("doc5").unwrap().content, "Same text, new header");
+
+        // Removed and added back with another embedding: the old point stays dead
+        store.remove_document("doc6").unwrap();
+        store.add_document(doc("doc6", &embeddings[7])).unwrap();
+        assert_eq!(store.dead_points(), 1);
+        store.remove_document("doc6").unwrap();
+        store.add_document(doc("doc6", &embeddings[6])).unwrap();
+        assert_eq!(store.dead_points(), 2);
+        assert_eq!(store.count(), 100);
+    }
+
+    #[test]
+    fn compact_rebuilds_once_enough_points_are_dead() {
+        let dir = tempfile::tempdir().unwrap();
+        let embeddings = unit_vectors(200, 8, 9);
+        let mut store = store_of(dir.path(), &embeddings);
+        assert!(!store.compact().unwrap());
+        for i in 0..30 {
+            store.remove_document(&format!("doc{}", i)).unwrap();
+        }
+        // 30 dead points next to 170 live ones are under the ratio
+        assert!(!store.compact().unwrap());
+        assert_eq!(store.dead_points(), 30);
+
+        for i in 30..50 {
+            store.remove_document(&format!("doc{}", i)).unwrap();
+        }
+        assert!(store.compact().unwrap());
+        assert_eq!(store.dead_points(), 0);
+        assert_eq!(store.count(), 150);
+
+        // With no tombstones left the walk runs unfiltered, over a graph without the removed points
+        assert!(store.count() > store.effective_ef_search(10));
+        for query in &embeddings[..50] {
+            let found = top_ids(&store, query, 10, None);
+            assert_eq!(found.len(), 10);
+            assert!(found.iter().all(|id| id[3..].parse::<usize>().unwrap() >= 50), "{:?}", found);
+        }
+        assert_eq!(top_ids(&store, &embeddings[120], 1, None), ["doc120"]);
+    }
+
+    #[test]
+    fn clear_deletes_the_index_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = hnsw_store(dir.path(), SimilarityMetric::Cosine);
+        store.save().unwrap();
+        let files = ["hnsw.data", "hnsw.hnsw.graph", "hnsw.hnsw.data"].map(|name| dir.path().join(name));
+        assert!(files.iter().all(|f| f.exists()));
+
+        store.clear().unwrap();
+        assert_eq!(store.count(), 0);
+        assert!(files.iter().all(|f| !f.exists()), "{:?}", std::fs::read_dir(dir.path()).unwrap().collect::<Vec<_>>());
+        let reopened = HnswVectorStore::new(&dir.path().join("hnsw.bin").to_string_lossy(), SimilarityMetric::Cosine, HnswParams::default(), None).unwrap();
+        assert_eq!(reopened.count(), 0);
+    }
+
+    #[test]
+    fn stats_measure_every_dump_file_and_f16_shrinks_only_the_document_map() {
+        let embeddings = unit_vectors(300, 64, 11);
+        let saved = |precision: Precision| {
+            let dir = tempfile::tempdir().unwrap();
+            let store = store_of(dir.path(), &embeddings).with_precision(precision);
+            store.save().unwrap();
+            let sizes = store.files().map(|file| std::fs::metadata(file).unwrap().len());
+            let stats = store.get_stats();
+            assert_eq!(stats.file_size_bytes, sizes.iter().sum::<u64>());
+            assert_eq!(stats.precision_bytes, sizes[0]);
+            sizes
+        };
+        let (full, half) = (saved(Precision::F32), saved(Precision::F16));
+        // The embeddings in the map take half the bytes, the vectors dumped with the graph stay f32
+        assert!(half[0] < full[0] * 2 / 3, "{:?} against {:?}", half, full);
+        assert_eq!(half[2], full[2]);
+        assert!(full[2] > (300 * 64 * 4) as u64);
+    }
+
+    /// `count` documents with distinct embeddings from a fixed seed, one in ten of subject `rare`
+    fn subjects_corpus(count: usize) -> Vec<Document> {
+        let mut seed: u64 = 17;
+        let mut next = move || {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (seed >> 33) as f32 / (1u64 << 31) as f32
+        };
+        (0..count).map(|i| {
+            let subject = if i % 10 == 0 { "rare" } else { "common" };
+            let embedding: [f32; 4] = std::array::from_fn(|_| next());
+            let mut doc = doc(&format!("{}/file{}.pdf#0", subject, i), &embedding);
+            doc.metadata.insert("type".to_string(), if i % 3 == 0 { "pdf" } else { "srt" }.to_string());
+            doc
+        }).collect()
+    }
+
+    #[test]
+    fn filtered_search_returns_the_best_matching_documents_on_both_stores() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut hnsw = HnswVectorStore::new(&dir.path().join("hnsw.bin").to_string_lossy(), SimilarityMetric::Cosine, HnswParams::default(), None).unwrap();
+        let mut linear = LinearVectorStore::in_memory(SimilarityMetric::Cosine);
+        for doc in subjects_corpus(400) {
+            hnsw.add_document(doc.clone()).unwrap();
+            linear.add_document(doc).unwrap();
+        }
+
+        let filters = [
+            // Accepts most documents, so the search walks the graph
+            SearchFilter { subject: Some("common".to_string()), ..Default::default() },
+            SearchFilter { subject: Some("rare".to_string()), ..Default::default() },
+            SearchFilter { id_prefix: Some("rare/file1".to_string()), ..Default::default() },
+            SearchFilter { metadata: HashMap::from([("type".to_string(), "pdf".to_string())]), subject: Some("rare".to_string()), ..Default::default() },
+        ];
+        for (i, filter) in filters.iter().enumerate() {
+            let accepts = |d: &Document| filter.matches(d);
+            let accepted = linear.get_all().unwrap().into_iter().filter(|d| filter.matches(d)).count();
+            let walks = accepted > hnsw.effective_ef_search(10 * FILTERED_OVERSAMPLING);
+            assert_eq!(walks, i == 0, "{:?}", filter);
+            for query in [[1.0, 0.0, 0.0, 0.0], [0.0, 0.3, 0.0, 1.0]] {
+                let expected = top_ids(&linear, &query, 10, Some(&accepts));
+                let found = top_ids(&hnsw, &query, 10, Some(&accepts));
+                // Better matches of other subjects don't take the places
+                assert_eq!(found.len(), accepted.min(10), "{:?}", filter);
+                assert!(found.iter().all(|id| linear.get_document(id).is_some_and(|d| filter.matches(&d))));
+                if walks {
+                    // The walk is approximate, but the best match makes the top 10
+                    assert!(found.contains(&expected[0]), "{:?}", filter);
+                } else {
+                    // Few enough to be scored exactly
+                    assert_eq!(found, expected, "{:?}", filter);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn ef_search_override_is_used_but_never_below_top_k() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = hnsw_store(dir.path(), SimilarityMetric::Cosine);
+        assert_eq!(store.effective_ef_search(5), 64);
+        assert_eq!(store.effective_ef_search(50), 200);
+        store.set_ef_search(Some(16));
+        assert_eq!(store.effective_ef_search(5), 16);
+        assert_eq!(store.effective_ef_search(40), 40);
+        store.set_ef_search(None);
+        assert_eq!(store.effective_ef_search(5), 64);
+    }
+
+    #[test]
+    fn every_metric_ranks_like_the_linear_store() {
+        // Lengths from 0.5 to 3, so dot products go past 1 and still have to be told apart
+        let embeddings: Vec<Vec<f32>> = unit_vectors(200, 8, 5).into_iter().enumerate()
+            .map(|(i, v)| v.into_iter().map(|x| x * (0.5 + (i % 6) as f32 * 0.5)).collect())
+            .collect();
+        let queries: Vec<Vec<f32>> = unit_vectors(5, 8, 55).into_iter()
+            .map(|q| q.into_iter().map(|x| x * 2.0).collect())
+            .collect();
+
+        for metric in [SimilarityMetric::Cosine, SimilarityMetric::Dot, SimilarityMetric::Euclidean] {
+            let dir = tempfile::tempdir().unwrap();
+            let mut hnsw = HnswVectorStore::new(&dir.path().join("hnsw.bin").to_string_lossy(), metric, HnswParams::default(), None).unwrap();
+            let mut linear = LinearVectorStore::in_memory(metric);
+            for (i, embedding) in embeddings.iter().enumerate() {
+                hnsw.add_document(doc(&format!("doc{}", i), embedding)).unwrap();
+                linear.add_document(doc(&format!("doc{}", i), embedding)).unwrap();
+            }
+            assert!(hnsw.count() > hnsw.effective_ef_search(5));
+
+            for query in &queries {
+                // The walk may miss a match, so what it returns is checked against the full ranking
+                let ranking: Vec<String> = linear.search(query, "user", 0, f32::MIN, None).unwrap().into_iter().map(|(d, _)| d.id).collect();
+                let found = hnsw.search(query, "user", 5, f32::MIN, None).unwrap();
+                assert_eq!(found.len(), 5);
+                let positions: Vec<usize> = found.iter().map(|(d, _)| ranking.iter().position(|id| *id == d.id).unwrap()).collect();
+                assert!(positions.windows(2).all(|w| w[0] < w[1]), "{} query {:?}: ranks {:?}", metric.label(), query, positions);
+                for (d, score) in &found {
+                    let exact = similarity(metric, query, &d.embedding);
+                    assert!((score - exact).abs() < 1e-4 * exact.abs().max(1.0), "{}: {} against {}", metric.label(), score, exact);
+                }
+                if metric == SimilarityMetric::Dot {
+                    assert!(found[0].1 > 1.0);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn a_score_equal_to_the_threshold_is_left_out_by_every_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let hnsw = hnsw_store(dir.path(), SimilarityMetric::Cosine);
+        let mut linear = LinearVectorStore::in_memory(SimilarityMetric::Cosine);
+        let mut sqlite = SqliteVectorStore::new(&dir.path().join("index.sqlite"), SimilarityMetric::Cosine).unwrap();
+        for (id, embedding) in CORPUS {
+            linear.add_document(doc(id, &embedding)).unwrap();
+            sqlite.add_document(doc(id, &embedding)).unwrap();
+        }
+
+        let query = [1.0, 0.0, 0.0, 0.0];
+        let threshold = similarity(SimilarityMetric::Cosine, &query, &CORPUS[1].1);
+        for store in [&hnsw as &dyn VectorStore, &linear, &sqlite] {
+            let found: Vec<String> = store.search(&query, "user", 0, threshold, None).unwrap().into_iter().map(|(d, _)| d.id).collect();
+            assert_eq!(found, ["algebra.pdf#0"], "{}", store.store_type());
         }
     }
 }
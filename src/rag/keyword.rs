@@ -0,0 +1,411 @@
+//! Keyword index kept next to the vector store. Embeddings smear out exact tokens like course
+//! codes ("GRA_11673"), file names or surnames; BM25 over the chunk text finds them, and
+//! `fuse` merges both rankings.
+
+use std::collections::HashMap;
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::config::{Fusion, HybridParams};
+
+use super::format::{self, IndexHeader, Loaded};
+use super::store::{DocFilter, StoreStats, VectorStore};
+use super::Document;
+
+/// BM25 term frequency saturation
+const K1: f32 = 1.2;
+/// BM25 length normalization
+const B: f32 = 0.75;
+/// Tokens shorter than this are not indexed
+const MIN_TOKEN_CHARS: usize = 2;
+/// Keyword matches scoring less than this share of the best one are left out of the fusion:
+/// a chunk matching only the common words of the question is no better than a vector match
+const RELATIVE_FLOOR: f32 = 0.5;
+
+/// Folded tokens of `text` with their counts. Letters, digits and `_` make a token, so
+/// "GRA_11673" stays whole; its parts are indexed too so "11673" alone finds it.
+fn tokens(text: &str) -> HashMap<String, u32> {
+    let mut counts = HashMap::new();
+    for token in super::sentences::fold(text).split(|c: char| !c.is_alphanumeric() && c != '_') {
+        let token = token.trim_matches('_');
+        if token.chars().count() < MIN_TOKEN_CHARS {
+            continue;
+        }
+        if token.contains('_') {
+            for part in token.split('_').filter(|p| p.chars().count() >= MIN_TOKEN_CHARS) {
+                *counts.entry(part.to_string()).or_insert(0) += 1;
+            }
+        }
+        *counts.entry(token.to_string()).or_insert(0) += 1;
+    }
+    counts
+}
+
+/// Inverted index over document contents, scored with BM25
+#[derive(Serialize, Deserialize, Default)]
+pub struct KeywordIndex {
+    /// Document id in each slot, `None` once removed
+    slots: Vec<Option<String>>,
+    /// Tokens in the document of each slot
+    lengths: Vec<u32>,
+    total_tokens: u64,
+    /// Token -> slot -> occurrences
+    postings: HashMap<String, HashMap<u32, u32>>,
+    /// Rebuilt from `slots` when loaded
+    #[serde(skip)]
+    slot_of: HashMap<String, u32>,
+    #[serde(skip)]
+    free: Vec<u32>,
+}
+
+impl KeywordIndex {
+    pub fn len(&self) -> usize {
+        self.slot_of.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slot_of.is_empty()
+    }
+
+    /// Distinct tokens indexed
+    pub fn vocabulary(&self) -> usize {
+        self.postings.len()
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.slot_of.contains_key(id)
+    }
+
+    /// Index `content` under `id`, which must not be indexed yet
+    pub fn add(&mut self, id: &str, content: &str) {
+        let counts = tokens(content);
+        let slot = match self.free.pop() {
+            Some(slot) => {
+                self.slots[slot as usize] = Some(id.to_string());
+                slot
+            }
+            None => {
+                self.slots.push(Some(id.to_string()));
+                self.lengths.push(0);
+                (self.slots.len() - 1) as u32
+            }
+        };
+        let length: u32 = counts.values().sum();
+        self.lengths[slot as usize] = length;
+        self.total_tokens += length as u64;
+        for (token, count) in counts {
+            self.postings.entry(token).or_default().insert(slot, count);
+        }
+        self.slot_of.insert(id.to_string(), slot);
+    }
+
+    /// Drop `id`, whose indexed text was `content`
+    pub fn remove(&mut self, id: &str, content: &str) {
+        let Some(slot) = self.slot_of.remove(id) else { return };
+        for token in tokens(content).into_keys() {
+            if let Some(posting) = self.postings.get_mut(&token) {
+                posting.remove(&slot);
+                if posting.is_empty() {
+                    self.postings.remove(&token);
+                }
+            }
+        }
+        self.total_tokens -= self.lengths[slot as usize] as u64;
+        self.lengths[slot as usize] = 0;
+        self.slots[slot as usize] = None;
+        self.free.push(slot);
+    }
+
+    /// BM25 contribution of `count` occurrences, in the document of `slot`, of a token found
+    /// in `df` documents
+    fn term_score(&self, slot: u32, count: u32, df: usize) -> f32 {
+        let documents = self.len() as f32;
+        let average_length = (self.total_tokens as f32 / documents).max(1.0);
+        let idf = (1.0 + (documents - df as f32 + 0.5) / (df as f32 + 0.5)).ln();
+        let tf = count as f32;
+        let norm = K1 * (1.0 - B + B * self.lengths[slot as usize] as f32 / average_length);
+        idf * tf * (K1 + 1.0) / (tf + norm)
+    }
+
+    /// Ids of the documents matching tokens of `query` with their BM25 score, best first
+    pub fn search(&self, query: &str) -> Vec<(&str, f32)> {
+        let mut scores: HashMap<u32, f32> = HashMap::new();
+        for token in tokens(query).into_keys() {
+            let Some(posting) = self.postings.get(&token) else { continue };
+            for (&slot, &count) in posting {
+                *scores.entry(slot).or_insert(0.0) += self.term_score(slot, count, posting.len());
+            }
+        }
+        let mut found: Vec<(&str, f32)> = scores.into_iter()
+            .filter_map(|(slot, score)| Some((self.slots[slot as usize].as_deref()?, score)))
+            .collect();
+        found.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        found
+    }
+
+    /// BM25 score of the document `id` for `query`, 0 when it matches no token or isn't indexed
+    pub fn score(&self, query: &str, id: &str) -> f32 {
+        let Some(&slot) = self.slot_of.get(id) else { return 0.0 };
+        tokens(query).into_keys()
+            .filter_map(|token| {
+                let posting = self.postings.get(&token)?;
+                Some(self.term_score(slot, *posting.get(&slot)?, posting.len()))
+            })
+            .sum()
+    }
+
+    /// Fill `slot_of` and `free` from the loaded slots
+    fn reindex_slots(&mut self) {
+        self.slot_of.clear();
+        self.free.clear();
+        for (slot, id) in self.slots.iter().enumerate() {
+            match id {
+                Some(id) => { self.slot_of.insert(id.clone(), slot as u32); }
+                None => self.free.push(slot as u32),
+            }
+        }
+    }
+
+    fn build(store: &dyn VectorStore) -> Self {
+        let mut index = Self::default();
+        store.for_each_document(&mut |doc| {
+            index.add(&doc.id, &doc.content);
+            ControlFlow::Continue(())
+        });
+        index
+    }
+}
+
+/// A vector store with its keyword index, saved as `<index>.keywords`. Every change to the
+/// documents goes through it, so the two always hold the same documents.
+pub struct WithKeywords {
+    store: Box<dyn VectorStore>,
+    keywords: KeywordIndex,
+    path: PathBuf,
+}
+
+impl WithKeywords {
+    /// Wrap `store`, loading the keyword index saved next to `index_path`. It is rebuilt from
+    /// the documents when missing, unreadable or out of step with the store.
+    pub fn open(store: Box<dyn VectorStore>, index_path: &str) -> Self {
+        let path = Path::new(index_path).with_extension("keywords");
+        let loaded = match path.exists().then(|| format::read::<KeywordIndex>(&path)) {
+            Some(Ok(Loaded::Current(_, mut keywords))) => {
+                keywords.reindex_slots();
+                Some(keywords)
+            }
+            // Written before the current format: rebuilt, which is cheaper than a migration
+            Some(Ok(Loaded::Previous(_) | Loaded::Unversioned)) => None,
+            Some(Err(e)) => {
+                tracing::warn!("Could not read the keyword index {}: {:#}", path.display(), e);
+                None
+            }
+            None => None,
+        };
+        let keywords = match loaded {
+            Some(keywords) if keywords.len() == store.count() => keywords,
+            _ => {
+                tracing::info!("Building the keyword index over {} documents", store.count());
+                KeywordIndex::build(store.as_ref())
+            }
+        };
+        Self { store, keywords, path }
+    }
+
+    /// The `top_k` documents of `user_id` the filter accepts with the best keyword scores,
+    /// leaving out those far below the best match, see `RELATIVE_FLOOR`
+    pub fn keyword_search(&self, query: &str, user_id: &str, top_k: usize, filter: Option<DocFilter>) -> Vec<(Document, f32)> {
+        let mut found = Vec::new();
+        for (id, score) in self.keywords.search(query) {
+            if found.len() >= top_k {
+                break;
+            }
+            let Some(doc) = self.store.get_document(id) else { continue };
+            if doc.user_id != user_id || filter.is_some_and(|accepts| !accepts(&doc)) {
+                continue;
+            }
+            found.push((doc, score));
+        }
+        if let Some(best) = found.first().map(|(_, score)| *score) {
+            found.retain(|(_, score)| *score >= best * RELATIVE_FLOOR);
+        }
+        found
+    }
+
+    /// BM25 score of the document `id` for `query`
+    pub fn keyword_score(&self, query: &str, id: &str) -> f32 {
+        self.keywords.score(query, id)
+    }
+}
+
+impl VectorStore for WithKeywords {
+    fn add_document(&mut self, doc: Document) -> Result<()> {
+        // Re-embedding adds the same text again, which leaves the keywords as they are
+        match self.store.get_document(&doc.id) {
+            Some(previous) if previous.content == doc.content && self.keywords.contains(&doc.id) => {}
+            Some(previous) => {
+                self.keywords.remove(&previous.id, &previous.content);
+                self.keywords.add(&doc.id, &doc.content);
+            }
+            None => self.keywords.add(&doc.id, &doc.content),
+        }
+        self.store.add_document(doc)
+    }
+
+    fn search(&self, query_embedding: &[f32], user_id: &str, top_k: usize, min_threshold: f32, filter: Option<DocFilter>) -> Result<Vec<(Document, f32)>> {
+        self.store.search(query_embedding, user_id, top_k, min_threshold, filter)
+    }
+
+    fn for_each_document(&self, f: &mut dyn FnMut(&Document) -> ControlFlow<()>) {
+        self.store.for_each_document(f)
+    }
+
+    fn count(&self) -> usize {
+        self.store.count()
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        self.keywords = KeywordIndex::default();
+        self.store.clear()
+    }
+
+    fn save(&self) -> Result<()> {
+        self.store.save()?;
+        if let Some(dir) = self.path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            crate::disk::check_save(dir, std::slice::from_ref(&self.path))?;
+        }
+        format::write(&self.path, &IndexHeader::new("keywords"), &self.keywords)
+    }
+
+    fn storage_path(&self) -> String {
+        self.store.storage_path()
+    }
+
+    fn get_stats(&self) -> StoreStats {
+        let mut stats = self.store.get_stats();
+        stats.file_size_bytes += std::fs::metadata(&self.path).map(|m| m.len()).unwrap_or(0);
+        stats.index_params.push(("keyword terms".to_string(), self.keywords.vocabulary().to_string()));
+        stats
+    }
+
+    fn store_type(&self) -> String {
+        self.store.store_type()
+    }
+
+    fn contains(&self, id: &str) -> bool {
+        self.store.contains(id)
+    }
+
+    fn get_document(&self, id: &str) -> Option<Document> {
+        self.store.get_document(id)
+    }
+
+    fn set_metadata(&mut self, id: &str, key: &str, value: Option<String>) -> bool {
+        self.store.set_metadata(id, key, value)
+    }
+
+    // Keywords follow removals only once the store has made them, so a failed one leaves
+    // both as they were
+    fn remove_document(&mut self, id: &str) -> Result<()> {
+        let previous = self.store.get_document(id);
+        self.store.remove_document(id)?;
+        if let Some(previous) = previous {
+            self.keywords.remove(id, &previous.content);
+        }
+        Ok(())
+    }
+
+    fn remove_by_prefix(&mut self, prefix: &str) -> Result<usize> {
+        let mut removed = Vec::new();
+        self.store.for_each_document(&mut |doc| {
+            if doc.id.starts_with(prefix) {
+                removed.push((doc.id.clone(), doc.content.clone()));
+            }
+            ControlFlow::Continue(())
+        });
+        let count = self.store.remove_by_prefix(prefix)?;
+        for (id, content) in &removed {
+            self.keywords.remove(id, content);
+        }
+        Ok(count)
+    }
+
+    fn family_ids(&self, base_id: &str) -> Vec<String> {
+        self.store.family_ids(base_id)
+    }
+
+    fn get_documents_by_metadata(&self, key: &str, value: &str) -> Result<Vec<Document>> {
+        self.store.get_documents_by_metadata(key, value)
+    }
+
+    fn rebuild(&mut self) -> Result<()> {
+        self.store.rebuild()?;
+        self.keywords = KeywordIndex::build(self.store.as_ref());
+        Ok(())
+    }
+
+    fn compact(&mut self) -> Result<bool> {
+        self.store.compact()
+    }
+}
+
+/// `vector` and `keyword` results (each best first) in one list, at most `limit` long, by the
+/// configured `Fusion`. Scores stay similarities: a document only the keyword search found
+/// gets `similarity(doc)`, so the thresholds and labels downstream keep their meaning.
+/// `keyword_score` is the BM25 score of any document, for the linear fusion.
+pub fn fuse(
+    vector: Vec<(Document, f32)>,
+    keyword: Vec<(Document, f32)>,
+    params: HybridParams,
+    limit: usize,
+    similarity: impl Fn(&Document) -> f32,
+    keyword_score: impl Fn(&Document) -> f32,
+) -> Vec<(Document, f32)> {
+    // Every document once, with its similarity and its ranks in each list
+    let mut merged: Vec<(Document, f32, Option<usize>, Option<usize>)> = vector.into_iter()
+        .enumerate()
+        .map(|(rank, (doc, score))| (doc, score, Some(rank), None))
+        .collect();
+    for (rank, (doc, _)) in keyword.into_iter().enumerate() {
+        match merged.iter_mut().find(|(kept, ..)| kept.id == doc.id) {
+            Some(entry) => entry.3 = Some(rank),
+            None => {
+                let score = similarity(&doc);
+                merged.push((doc, score, None, Some(rank)));
+            }
+        }
+    }
+
+    let fused: Vec<f32> = match params.fusion {
+        Fusion::Rank => {
+            let weight = params.keyword_weight.clamp(0.0, 1.0);
+            let rank_score = |share: f32, rank: Option<usize>| {
+                rank.map_or(0.0, |rank| share / (params.rrf_k.max(0.0) + rank as f32 + 1.0))
+            };
+            merged.iter()
+                .map(|(_, _, vector_rank, keyword_rank)| rank_score(1.0 - weight, *vector_rank) + rank_score(weight, *keyword_rank))
+                .collect()
+        }
+        Fusion::Linear => {
+            let alpha = params.alpha.clamp(0.0, 1.0);
+            let bm25: Vec<f32> = merged.iter().map(|(doc, ..)| keyword_score(doc)).collect();
+            let best = bm25.iter().copied().fold(0.0, f32::max);
+            merged.iter().zip(&bm25)
+                .map(|((_, score, ..), bm25)| {
+                    let normalized = if best > 0.0 { bm25 / best } else { 0.0 };
+                    alpha * score + (1.0 - alpha) * normalized
+                })
+                .collect()
+        }
+    };
+
+    let mut fused: Vec<(Document, f32, f32)> = merged.into_iter().zip(fused)
+        .map(|((doc, score, ..), fused)| (doc, score, fused))
+        .collect();
+    // Stable: ties keep the vector order first
+    fused.sort_by(|a, b| b.2.total_cmp(&a.2));
+    fused.into_iter().take(limit).map(|(doc, score, _)| (doc, score)).collect()
+}
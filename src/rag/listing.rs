@@ -0,0 +1,199 @@
+//! Paginated listing of the indexed documents for management screens. Summaries are read from
+//! the documents in place, so neither their content nor their embedding is cloned.
+
+use super::store::VectorStore;
+use super::{Document, RagSystem, SearchFilter};
+use std::ops::ControlFlow;
+
+/// What a document list shows of one document
+#[derive(Clone, Debug, PartialEq)]
+pub struct DocumentSummary {
+    pub id: String,
+    /// `type` metadata (pdf, transcript, subject...), empty when unset
+    pub doc_type: String,
+    /// File the document is a chunk of, `None` for subject summaries
+    pub filename: Option<String>,
+    /// Bytes of indexed text
+    pub content_len: usize,
+    /// Site id of the subject, see `subjects::subject_of`
+    pub subject: String,
+}
+
+impl DocumentSummary {
+    fn of(doc: &Document) -> Self {
+        Self {
+            id: doc.id.clone(),
+            doc_type: doc.metadata.get("type").cloned().unwrap_or_default(),
+            filename: doc.metadata.get("filename").cloned(),
+            content_len: doc.content.len(),
+            subject: crate::subjects::subject_of(&doc.id).to_string(),
+        }
+    }
+}
+
+/// Order of `RagSystem::list_documents`
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub enum DocumentOrder {
+    /// As the store keeps them; stable as long as the index isn't modified
+    #[default]
+    Stored,
+    /// By id, the chunks of a file in their order (`#2` before `#10`)
+    Id,
+    /// Longest content first
+    Size,
+}
+
+/// One page of `RagSystem::list_documents`
+#[derive(Clone, Debug, Default)]
+pub struct DocumentPage {
+    pub documents: Vec<DocumentSummary>,
+    /// Documents the filter matches, on every page
+    pub total: usize,
+}
+
+/// Sort key of an id: the file, then the chunk number
+fn id_key(id: &str) -> (&str, Option<usize>) {
+    match id.rsplit_once('#') {
+        Some((base, n)) => match n.parse() {
+            Ok(n) => (base, Some(n)),
+            Err(_) => (id, None),
+        },
+        None => (id, None),
+    }
+}
+
+impl RagSystem {
+    /// Up to `limit` summaries of the documents `filter` matches, from position `offset` in
+    /// `order`. An offset past the end or a `limit` of 0 gives an empty page, still with the total.
+    pub fn list_documents(&self, offset: usize, limit: usize, filter: &SearchFilter, order: DocumentOrder) -> DocumentPage {
+        documents_page(&*self.store.read().unwrap(), offset, limit, filter, order)
+    }
+}
+
+/// `RagSystem::list_documents` over `store`
+fn documents_page(store: &dyn VectorStore, offset: usize, limit: usize, filter: &SearchFilter, order: DocumentOrder) -> DocumentPage {
+    let mut page = DocumentPage::default();
+
+    if order == DocumentOrder::Stored {
+        // Only the page is summarized, the rest is just counted
+        store.for_each_document(&mut |doc| {
+            if filter.matches(doc) {
+                if page.total >= offset && page.documents.len() < limit {
+                    page.documents.push(DocumentSummary::of(doc));
+                }
+                page.total += 1;
+            }
+            ControlFlow::Continue(())
+        });
+        return page;
+    }
+
+    let mut summaries = Vec::new();
+    store.for_each_document(&mut |doc| {
+        if filter.matches(doc) {
+            summaries.push(DocumentSummary::of(doc));
+        }
+        ControlFlow::Continue(())
+    });
+    match order {
+        DocumentOrder::Size => summaries.sort_by(|a, b| b.content_len.cmp(&a.content_len).then_with(|| id_key(&a.id).cmp(&id_key(&b.id)))),
+        _ => summaries.sort_by(|a, b| id_key(&a.id).cmp(&id_key(&b.id))),
+    }
+    page.total = summaries.len();
+    page.documents = summaries.into_iter().skip(offset).take(limit).collect();
+    page
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rag::store::LinearVectorStore;
+    use crate::rag::SimilarityMetric;
+    use std::collections::HashMap;
+
+    /// Chunks `#0..chunks` of `file`, each `#n` holding n + 1 bytes of text
+    fn store_with(files: &[(&str, usize, &str)]) -> LinearVectorStore {
+        let mut store = LinearVectorStore::in_memory(SimilarityMetric::Cosine);
+        for (file, chunks, doc_type) in files {
+            for n in 0..*chunks {
+                store.add_document(Document {
+                    id: format!("{}#{}", file, n),
+                    content: "x".repeat(n + 1),
+                    embedding: vec![1.0, 0.0],
+                    metadata: HashMap::from([("type".to_string(), doc_type.to_string())]),
+                    user_id: "user".to_string(),
+                }).unwrap();
+            }
+        }
+        store
+    }
+
+    fn ids(page: &DocumentPage) -> Vec<&str> {
+        page.documents.iter().map(|d| d.id.as_str()).collect()
+    }
+
+    #[test]
+    fn pages_cover_every_document_once() {
+        let store = store_with(&[("GRA_1/tema1.pdf", 12, "pdf"), ("GRA_2/notas.txt", 3, "txt")]);
+        let all = SearchFilter::default();
+
+        for order in [DocumentOrder::Stored, DocumentOrder::Id, DocumentOrder::Size] {
+            let mut seen = Vec::new();
+            for offset in (0..15).step_by(4) {
+                let page = documents_page(&store, offset, 4, &all, order);
+                assert_eq!(page.total, 15);
+                seen.extend(ids(&page).into_iter().map(str::to_string));
+            }
+            seen.sort();
+            seen.dedup();
+            assert_eq!(seen.len(), 15, "{:?}", order);
+        }
+    }
+
+    #[test]
+    fn chunks_are_ordered_by_number_and_size_by_length() {
+        let store = store_with(&[("GRA_1/tema1.pdf", 12, "pdf")]);
+        let all = SearchFilter::default();
+
+        let by_id = documents_page(&store, 0, 3, &all, DocumentOrder::Id);
+        assert_eq!(ids(&by_id), ["GRA_1/tema1.pdf#0", "GRA_1/tema1.pdf#1", "GRA_1/tema1.pdf#2"]);
+        let by_id = documents_page(&store, 9, 3, &all, DocumentOrder::Id);
+        assert_eq!(ids(&by_id), ["GRA_1/tema1.pdf#9", "GRA_1/tema1.pdf#10", "GRA_1/tema1.pdf#11"]);
+
+        let by_size = documents_page(&store, 0, 2, &all, DocumentOrder::Size);
+        assert_eq!(ids(&by_size), ["GRA_1/tema1.pdf#11", "GRA_1/tema1.pdf#10"]);
+        assert_eq!(by_size.documents[0].content_len, 12);
+    }
+
+    #[test]
+    fn empty_pages_still_report_the_total() {
+        let store = store_with(&[("GRA_1/tema1.pdf", 5, "pdf")]);
+        let all = SearchFilter::default();
+
+        for order in [DocumentOrder::Stored, DocumentOrder::Id, DocumentOrder::Size] {
+            let past_the_end = documents_page(&store, 5, 10, &all, order);
+            assert!(past_the_end.documents.is_empty());
+            assert_eq!(past_the_end.total, 5);
+
+            let no_limit = documents_page(&store, 0, 0, &all, order);
+            assert!(no_limit.documents.is_empty());
+            assert_eq!(no_limit.total, 5);
+
+            let last = documents_page(&store, 4, 10, &all, order);
+            assert_eq!(last.documents.len(), 1);
+        }
+    }
+
+    #[test]
+    fn the_total_counts_only_matching_documents() {
+        let store = store_with(&[("GRA_1/tema1.pdf", 4, "pdf"), ("GRA_2/notas.txt", 3, "txt")]);
+        let filter = SearchFilter { subject: Some("GRA_2".to_string()), ..Default::default() };
+
+        for order in [DocumentOrder::Stored, DocumentOrder::Id] {
+            let page = documents_page(&store, 1, 10, &filter, order);
+            assert_eq!(page.total, 3);
+            assert_eq!(page.documents.len(), 2);
+            assert!(page.documents.iter().all(|d| d.subject == "GRA_2" && d.doc_type == "txt"));
+        }
+    }
+}
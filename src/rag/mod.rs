@@ -1,25 +1,361 @@
 pub mod embeddings;
 pub mod store;
 pub mod hnsw_store;
+pub mod citations;
+pub mod context;
+pub mod condense;
+pub mod rerank;
+pub mod format;
+pub mod cache;
+pub mod relevance;
+pub mod reembed;
+pub mod affinity;
+pub mod archive;
+pub mod sentences;
+pub mod anchor;
+pub mod pasted;
+pub mod keyword;
+pub mod diversity;
+pub mod sqlite_store;
+pub mod transfer;
+pub mod listing;
+pub mod precision;
 
 use serde::{Serialize, Deserialize};
 use std::collections::{HashMap, HashSet};
-use std::sync::{Arc, Mutex};
-use crate::rag::store::VectorStore;
-use std::path::Path;
+use std::sync::{Arc, Mutex, RwLock};
+use std::ops::{ControlFlow, Range};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use crate::config::{HybridParams, RelevanceParams, SimilarityMetric};
+use crate::rag::store::{DocFilter, VectorStore};
+use std::path::{Path, PathBuf};
 
+/// Metadata key with the Unix time a document's current content was first indexed
+pub const INDEXED_AT_KEY: &str = "indexed_at";
+/// Metadata key with a hash of the indexed content, to tell a re-add from an update
+const CONTENT_HASH_KEY: &str = "content_hash";
+/// Metadata key with the user's tags, comma separated
+pub const TAGS_KEY: &str = "tags";
+/// Documents `lookup` takes excerpts from, best embedding matches first
+const LOOKUP_CANDIDATES: usize = 8;
+/// Longest excerpt `lookup` returns; one long sentence is still returned whole
+const LOOKUP_EXCERPT_CHARS: usize = 400;
+/// Changed documents an open batch keeps in memory before saving anyway, so a crash in the
+/// middle of a sync loses at most this many
+pub const AUTOSAVE_EVERY: usize = 256;
+/// Longest chunk a synced file is split into, in characters
+pub const CHUNK_CHARS: usize = 1000;
+/// Characters consecutive chunks share. Kept at 0: `anchor` rebuilds a file's text by
+/// concatenating its chunks, which only works when they don't overlap.
+pub const CHUNK_OVERLAP: usize = 0;
+
+/// An indexed text with its embedding. Chunks of a file are documents with ids `<base_id>#<n>`.
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Document {
     pub id: String,
     pub content: String,
+    #[serde(with = "precision")]
     pub embedding: Vec<f32>,
     pub metadata: HashMap<String, String>,
     pub user_id: String,
 }
 
+/// When the document's content was indexed; `None` for documents indexed before this was recorded
+pub fn indexed_at(doc: &Document) -> Option<u64> {
+    doc.metadata.get(INDEXED_AT_KEY)?.parse().ok()
+}
+
+/// Hex SHA-256 of `content`, stored as `CONTENT_HASH_KEY`
+fn content_hash(content: &str) -> String {
+    use sha2::{Digest, Sha256};
+    Sha256::digest(content.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Whether `doc` was indexed from exactly the content hashing to `hash`. Documents hashed
+/// before SHA-256 was used have a shorter hash and are compared by content instead.
+fn same_content(doc: &Document, content: &str, hash: &str) -> bool {
+    match doc.metadata.get(CONTENT_HASH_KEY) {
+        Some(stored) if stored.len() == hash.len() => stored == hash,
+        _ => doc.content == content,
+    }
+}
+
+/// Whether `prev` was indexed for `user_id` from exactly `content`, with an embedding of
+/// `dimensions` when they are known, so its embedding can be reused
+fn reusable(prev: &Document, content: &str, user_id: &str, dimensions: Option<usize>) -> bool {
+    prev.user_id == user_id
+        && same_content(prev, content, &content_hash(content))
+        && dimensions.is_none_or(|d| prev.embedding.len() == d)
+}
+
+/// Whether `prev` already holds every entry of `meta`, so writing it again changes nothing
+fn same_metadata(prev: &Document, meta: &HashMap<String, String>) -> bool {
+    meta.iter().all(|(key, value)| prev.metadata.get(key) == Some(value))
+}
+
+/// Record the content hash and `indexed_at` on `doc`. Content identical to `previous`
+/// (the document it replaces) keeps its original timestamp.
+fn stamp_indexed_at(doc: &mut Document, previous: Option<&Document>) {
+    let hash = content_hash(&doc.content);
+    let kept = previous
+        .filter(|prev| same_content(prev, &doc.content, &hash))
+        .and_then(indexed_at);
+    let at = kept.unwrap_or_else(|| {
+        std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+    });
+    doc.metadata.insert(INDEXED_AT_KEY.to_string(), at.to_string());
+    doc.metadata.insert(CONTENT_HASH_KEY.to_string(), hash);
+}
+
+/// Remove `base_id` and its chunks from `store` and add `docs` in their place. Tags belong to
+/// the file, so they carry over even when it now splits into more chunks; chunks whose content
+/// is unchanged keep when they were indexed.
+fn swap_family(store: &mut dyn VectorStore, base_id: &str, docs: Vec<Document>) -> anyhow::Result<()> {
+    let mut previous = HashMap::new();
+    for id in store.family_ids(base_id) {
+        if let Some(doc) = store.get_document(&id) {
+            previous.insert(id.clone(), doc);
+        }
+        store.remove_document(&id)?;
+    }
+    let mut family_tags: Vec<String> = previous.values().flat_map(tags).collect();
+    family_tags.sort();
+    family_tags.dedup();
+    for mut doc in docs {
+        let prev = previous.remove(&doc.id);
+        stamp_indexed_at(&mut doc, prev.as_ref());
+        if !family_tags.is_empty() {
+            doc.metadata.insert(TAGS_KEY.to_string(), family_tags.join(","));
+        }
+        store.add_document(doc)?;
+    }
+    Ok(())
+}
+
+/// Move `from` and its chunks in `store` to the base id `to`, replacing whatever `to` held.
+/// Returns how many documents moved.
+fn move_family(store: &mut dyn VectorStore, from: &str, to: &str) -> anyhow::Result<usize> {
+    for id in store.family_ids(to) {
+        store.remove_document(&id)?;
+    }
+    let ids = store.family_ids(from);
+    for id in &ids {
+        let Some(mut doc) = store.get_document(id) else { continue };
+        store.remove_document(id)?;
+        doc.id = format!("{}{}", to, &id[from.len()..]);
+        store.add_document(doc)?;
+    }
+    Ok(ids.len())
+}
+
+/// Tags the user gave the document, see `RagSystem::add_tag`
+pub fn tags(doc: &Document) -> Vec<String> {
+    doc.metadata.get(TAGS_KEY)
+        .map(|tags| tags.split(',').filter(|t| !t.is_empty()).map(str::to_string).collect())
+        .unwrap_or_default()
+}
+
+/// Tags are matched lowercase and can't contain the separator
+fn normalize_tag(tag: &str) -> String {
+    tag.trim().to_lowercase().replace(',', "-")
+}
+
+/// Documents a search is restricted to by their id and metadata; everything when left empty
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct SearchFilter {
+    /// Every key must be set to exactly this value, e.g. `type` = `pdf`
+    pub metadata: HashMap<String, String>,
+    /// Only ids starting with this
+    pub id_prefix: Option<String>,
+    /// Only documents of this subject (site id): its files and its summary
+    pub subject: Option<String>,
+}
+
+impl SearchFilter {
+    pub fn is_empty(&self) -> bool {
+        self.metadata.is_empty() && self.id_prefix.is_none() && self.subject.is_none()
+    }
+
+    pub fn matches(&self, doc: &Document) -> bool {
+        self.metadata.iter().all(|(key, value)| doc.metadata.get(key) == Some(value))
+            && self.id_prefix.as_ref().is_none_or(|prefix| doc.id.starts_with(prefix.as_str()))
+            && self.subject.as_ref().is_none_or(|subject| crate::subjects::subject_of(&doc.id) == subject)
+    }
+}
+
+/// Retrieval settings `search_snippets` reads from the config on every search
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct SearchTuning {
+    hybrid: HybridParams,
+    mmr_lambda: f32,
+}
+
+/// Which documents retrieval considers; everything when left empty
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct Scope {
+    /// Only documents whose `type` metadata is in the list
+    pub types: Option<Vec<String>>,
+    /// Only documents with this tag
+    pub tag: Option<String>,
+    /// Leave out the documents of these subjects (site ids). `search_snippets` adds the
+    /// subjects whose `retrieve` preference is off.
+    pub exclude_subjects: Vec<String>,
+    /// Only documents the filter matches
+    pub filter: SearchFilter,
+}
+
+impl Scope {
+    pub fn is_all(&self) -> bool {
+        self.types.is_none() && self.tag.is_none() && self.exclude_subjects.is_empty() && self.filter.is_empty()
+    }
+
+    fn matches(&self, doc: &Document) -> bool {
+        // Untyped documents count as "unknown", as in the index stats
+        let doc_type = doc.metadata.get("type").map_or("unknown", |t| t.as_str());
+        self.types.as_ref().is_none_or(|types| types.iter().any(|t| t == doc_type))
+            && self.tag.as_ref().is_none_or(|tag| tags(doc).contains(tag))
+            && !self.exclude_subjects.iter().any(|s| s == crate::subjects::subject_of(&doc.id))
+            && self.filter.matches(doc)
+    }
+}
+
+/// "today", "yesterday" or "3 days ago" for an `indexed_at` time
+pub fn format_age(at: u64) -> String {
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    match now.saturating_sub(at) / 86_400 {
+        0 => "today".to_string(),
+        1 => "yesterday".to_string(),
+        days => format!("{} days ago", days),
+    }
+}
+
+/// Why `RagSystem::new` failed, so startup can tell the user what to fix
+#[derive(Debug, thiserror::Error)]
+pub enum OpenError {
+    #[error("failed to load the embedding model: {0:#}")]
+    Embedder(anyhow::Error),
+    #[error("failed to open the index at {}: {cause:#}", path.display())]
+    Index { path: PathBuf, cause: anyhow::Error },
+}
+
+/// The document index: embeds documents with `EmbeddingModel`, keeps them in a `VectorStore`
+/// and retrieves the passages that answer a question
 pub struct RagSystem {
-    store: Arc<Mutex<Box<dyn VectorStore>>>,
+    /// Searches share the read lock; writers only hold the write lock for the in-memory change,
+    /// never while embedding or writing to disk, so chat keeps answering during a sync
+    store: Arc<RwLock<keyword::WithKeywords>>,
+    /// Similarity the store scores with, for documents only the keyword search found
+    metric: SimilarityMetric,
+    /// How the store writes embeddings, see `precision`
+    precision: precision::Precision,
+    /// Serializes saves, which run under the read lock and write the same files
+    save_lock: Mutex<()>,
     embedder: Arc<embeddings::EmbeddingModel>,
+    /// Dimension the embedding model produces, `None` when its self-test failed
+    model_dimensions: Option<usize>,
+    health_warning: Option<String>,
+    summaries: condense::SummaryCache,
+    /// Open batches; while any is open, family replacements leave saving to `Batch::commit`
+    open_batches: AtomicUsize,
+    /// Documents changed since the last save, see `AUTOSAVE_EVERY`
+    unsaved: AtomicUsize,
+    /// Bumped whenever the indexed documents or their embeddings change
+    index_version: AtomicU64,
+    retrieval_cache: cache::RetrievalCache,
+    /// Text pasted into the chat, searched with the index but never saved
+    pasted: RwLock<pasted::Pasted>,
+    /// Retrieval settings, see `settings`
+    settings: RwLock<Arc<Settings>>,
+}
+
+/// The retrieval settings of the config, read once rather than on every search
+struct Settings {
+    /// `Config::save_generation` when they were read; a later save reads them again
+    generation: u64,
+    rag: crate::config::RagConfig,
+    /// Subjects left out of retrieval, see `Config::retrieval_excluded_subjects`
+    excluded_subjects: Vec<String>,
+}
+
+impl Settings {
+    fn load() -> Self {
+        // Read first: a save landing during the load only makes the next use read again
+        let generation = crate::config::Config::save_generation();
+        let config = crate::config::Config::load();
+        Self { generation, excluded_subjects: config.retrieval_excluded_subjects(), rag: config.rag }
+    }
+}
+
+/// Defers index saves until `commit`, see `RagSystem::begin_batch`.
+/// Dropping it without committing (e.g. on an error) just closes the batch.
+pub struct Batch<'a> {
+    rag: &'a RagSystem,
+    open: bool,
+}
+
+impl Batch<'_> {
+    /// Close the batch, compact the index if its removals call for it, and save it once
+    pub fn commit(mut self) -> anyhow::Result<()> {
+        self.close();
+        self.rag.compact()?;
+        self.rag.save()
+    }
+
+    fn close(&mut self) {
+        if self.open {
+            self.open = false;
+            self.rag.open_batches.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+}
+
+impl Drop for Batch<'_> {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+/// A retrieved passage prepared for the LLM context
+#[derive(Clone)]
+pub struct Snippet {
+    /// Id of the chunk it was cut from, see `RagSystem::document_text`
+    pub id: String,
+    /// Subject id or filename, usable with `get_file_chunks`
+    pub source: String,
+    /// Human readable source, e.g. "Lecture 5 recording @ 00:14" for transcripts
+    pub label: String,
+    pub text: String,
+    pub score: f32,
+    /// Page number when the chunk metadata records one
+    pub page: Option<String>,
+    /// `type` metadata ("subject", "pdf", "transcript"...), "unknown" when missing
+    pub doc_type: String,
+    /// 1-based chunk number and the number of chunks of its document, for chunked documents
+    pub chunk: Option<(usize, usize)>,
+    /// When the chunk's content was indexed, see `indexed_at`
+    pub indexed_at: Option<u64>,
+    /// Kept only because nothing scored above the relevance floor, see `relevance::Cutoff::Fallback`
+    pub low_confidence: bool,
+    /// Site id of the subject the document belongs to
+    pub subject: String,
+    /// Part of `score` added by the session's subject affinity
+    pub boost: f32,
+}
+
+/// A passage quoted verbatim from an indexed document, see `RagSystem::lookup`
+#[derive(Clone, Debug)]
+pub struct Excerpt {
+    /// File name, or the subject id for a subject summary
+    pub source: String,
+    /// Site id of the subject
+    pub subject: String,
+    pub page: Option<String>,
+    /// One to three sentences, as they appear in the document
+    pub text: String,
+    /// `sentences::score` of the best sentence
+    pub keyword_score: usize,
+    /// Embedding similarity of the document to the query
+    pub score: f32,
 }
 
 /// Statistics about the RAG index
@@ -31,8 +367,69 @@ pub struct RagStats {
     pub file_size_bytes: u64,
     pub storage_path: String,
     pub store_type: String,
-    pub chunking_strategy: String,
+    /// Longest chunk synced files are split into, in characters
+    pub chunk_chars: usize,
+    pub chunk_overlap: usize,
+    /// Library running the embedding model
+    pub embedding_backend: &'static str,
     pub embedding_model: String,
+    /// Dimension the loaded model produces; `embedding_dimensions` is what the index holds
+    pub model_dimensions: Option<usize>,
+    /// Longer inputs are embedded in windows of this many characters and averaged
+    pub embedding_window_chars: usize,
+    pub retrieval: RetrievalStats,
+    pub index_params: Vec<(String, String)>,
+    /// Oldest and newest `indexed_at` in the index
+    pub indexed_range: Option<(u64, u64)>,
+    pub docs_by_subject: HashMap<String, store::SubjectStats>,
+    /// Bytes of each embedding value in the index files, 2 with `rag.half_precision`
+    pub embedding_value_bytes: usize,
+    /// Part of `file_size_bytes` whose embeddings are written with `embedding_value_bytes`
+    pub precision_file_bytes: u64,
+}
+
+/// How questions are answered from the index, as currently configured
+pub struct RetrievalStats {
+    pub similarity_metric: &'static str,
+    /// Snippets retrieved per question; with `adaptive_k` the most, for short questions
+    pub top_k: usize,
+    pub adaptive_k: bool,
+    /// Fewest snippets `adaptive_k` retrieves
+    pub adaptive_min: usize,
+    /// Best matching files included whole
+    pub full_files: usize,
+    pub relevance: RelevanceParams,
+    pub hybrid: HybridParams,
+    pub context_profile: &'static str,
+    pub answer_mode: &'static str,
+    /// Optional pipeline stages and whether each is on
+    pub features: Vec<(&'static str, bool)>,
+}
+
+impl RetrievalStats {
+    pub fn from_config(config: &crate::config::RagConfig) -> Self {
+        Self {
+            similarity_metric: config.similarity_metric.label(),
+            top_k: config.top_k(),
+            adaptive_k: config.adaptive_k,
+            adaptive_min: context::MIN_ADAPTIVE_SNIPPETS,
+            full_files: context::FULL_FILES,
+            relevance: config.relevance,
+            hybrid: config.hybrid,
+            context_profile: config.context_profile.label(),
+            answer_mode: config.answer_mode.label(),
+            features: vec![
+                ("numbered citations", config.numbered_citations || config.answer_mode == crate::config::AnswerMode::SummaryFirst),
+                ("condense long sources", config.condense_long_sources),
+                ("condense with OpenRouter", config.condense_long_sources && config.condense_remote),
+                ("rerank with the LLM", config.rerank),
+                ("rerank with OpenRouter", config.rerank && config.rerank_remote),
+                ("freshness hints", config.freshness_hints),
+                ("subject affinity", config.affinity.boost > 0.0),
+                ("diversify (MMR)", config.mmr_lambda() < 1.0),
+            ],
+        }
+    }
 }
 
 impl RagStats {
@@ -50,6 +447,75 @@ impl RagStats {
         }
     }
 
+    /// The chunking, embedding and retrieval settings as (label, value) rows.
+    /// Destructures every field so a new one can't be added without deciding whether it shows here.
+    pub fn pipeline(&self) -> Vec<(&'static str, String)> {
+        let RagStats {
+            docs_by_type: _, total_content_bytes: _, document_count: _,
+            storage_path: _, index_params: _, indexed_range: _, docs_by_subject: _,
+            file_size_bytes, precision_file_bytes, embedding_value_bytes, embedding_dimensions, store_type, chunk_chars, chunk_overlap,
+            embedding_backend, embedding_model, model_dimensions, embedding_window_chars, retrieval,
+        } = self;
+        let RetrievalStats { similarity_metric, top_k, adaptive_k, adaptive_min, full_files, relevance, hybrid, context_profile, answer_mode, features } = retrieval;
+
+        let overlap = match chunk_overlap {
+            0 => "no overlap".to_string(),
+            n => format!("{} chars overlap", n),
+        };
+        let dimensions = match (model_dimensions, embedding_dimensions) {
+            (Some(model), 0) => format!("{} dimensions", model),
+            (Some(model), indexed) if model == indexed => format!("{} dimensions", model),
+            (Some(model), indexed) => format!("{} dimensions (index holds {})", model, indexed),
+            (None, 0) => "self-test failed".to_string(),
+            (None, indexed) => format!("self-test failed, index holds {} dimensions", indexed),
+        };
+        let limit = match top_k {
+            0 => "unlimited".to_string(),
+            n => n.to_string(),
+        };
+        let snippets = if *adaptive_k {
+            let fewest = if *top_k == 0 { *adaptive_min } else { (*adaptive_min).min(*top_k) };
+            format!("{}-{} snippets (adaptive), {} whole files", fewest, limit, full_files)
+        } else {
+            format!("{} snippets, {} whole files", limit, full_files)
+        };
+        let threshold = if relevance.filters() {
+            format!("≥ {:.2}, within {:.2} of the best match", relevance.floor, relevance.delta)
+        } else {
+            "off, every match up to the limit".to_string()
+        };
+        let keywords = match hybrid.fusion {
+            _ if hybrid.keyword_weight <= 0.0 => "off, vector search only".to_string(),
+            crate::config::Fusion::Rank => format!("BM25 fused by rank, keyword weight {:.2}, k = {}", hybrid.keyword_weight.min(1.0), hybrid.rrf_k),
+            crate::config::Fusion::Linear => format!("BM25 fused by score, {:.2} × similarity + {:.2} × BM25", hybrid.alpha.clamp(0.0, 1.0), 1.0 - hybrid.alpha.clamp(0.0, 1.0)),
+        };
+        // Measured, as the HNSW graph keeps f32 vectors whatever the setting and the keyword
+        // index holds none
+        let value = if *embedding_value_bytes == 2 { "f16" } else { "f32" };
+        let precision = if precision_file_bytes == file_size_bytes {
+            format!("{}, {} on disk", value, crate::scrapper::format_bytes(*file_size_bytes))
+        } else {
+            format!("{} in {} of the {} on disk, the rest f32 or no embeddings", value,
+                crate::scrapper::format_bytes(*precision_file_bytes), crate::scrapper::format_bytes(*file_size_bytes))
+        };
+        let features: Vec<String> = features.iter()
+            .map(|(name, on)| format!("{} {}", if *on { "✓" } else { "✗" }, name))
+            .collect();
+        vec![
+            ("Store Type", store_type.clone()),
+            ("Chunking", format!("TextSplitter, up to {} chars, {}", chunk_chars, overlap)),
+            ("Embeddings", format!("{} · {} · {}", embedding_backend, embedding_model, dimensions)),
+            ("Embed Window", format!("{} chars, longer chunks are averaged", embedding_window_chars)),
+            ("Stored As", precision),
+            ("Similarity", similarity_metric.to_string()),
+            ("Retrieval", snippets),
+            ("Keywords", keywords),
+            ("Threshold", threshold),
+            ("Context", format!("{} profile, {} answers", context_profile, answer_mode)),
+            ("Features", features.join("  ")),
+        ]
+    }
+
     /// Format content size in human readable format
     pub fn format_content_size(&self) -> String {
         let bytes = self.total_content_bytes;
@@ -63,24 +529,115 @@ impl RagStats {
     }
 }
 
+impl reembed::Index for RagSystem {
+    fn store(&self) -> &RwLock<keyword::WithKeywords> {
+        &self.store
+    }
+
+    fn changed(&self) {
+        self.bump_index_version();
+    }
+
+    fn save(&self) -> anyhow::Result<()> {
+        RagSystem::save(self)
+    }
+}
+
 impl RagSystem {
     pub fn new(storage_path: &str) -> anyhow::Result<Self> {
-        let embedder = Arc::new(embeddings::EmbeddingModel::new()?);
-        
-        // Check if HNSW index exists
-        let hnsw_path = Path::new(storage_path).with_extension("hnsw");
-        
-        let mut store = hnsw_store::HnswVectorStore::new(storage_path)?;
-        
-        // Migration logic: If HNSW didn't exist but Linear store does, migrate
-        if !hnsw_path.exists() && Path::new(storage_path).exists() {
+        let settings = Arc::new(Settings::load());
+        let rag_config = &settings.rag;
+        let precision = precision::Precision::from_half(rag_config.half_precision);
+        let embedder = Arc::new(embeddings::EmbeddingModel::new(rag_config.document_char_limit()).map_err(OpenError::Embedder)?);
+        let store: Box<dyn VectorStore> = match rag_config.store_type {
+            crate::config::StoreType::Hnsw => Box::new(Self::open_hnsw(storage_path, rag_config).map_err(|cause| {
+                OpenError::Index { path: Path::new(storage_path).with_extension("data"), cause }
+            })?),
+            crate::config::StoreType::Linear => Box::new(store::LinearVectorStore::new(storage_path, rag_config.similarity_metric).map_err(|cause| {
+                OpenError::Index { path: PathBuf::from(storage_path), cause }
+            })?.with_precision(precision)),
+            crate::config::StoreType::Sqlite => Box::new(Self::open_sqlite(storage_path, rag_config).map_err(|cause| {
+                OpenError::Index { path: Path::new(storage_path).with_extension("sqlite"), cause }
+            })?),
+        };
+        tracing::info!("Opened the {} store with {} documents", rag_config.store_type.label(), store.count());
+        let store = keyword::WithKeywords::open(store, storage_path);
+
+        // Catch a misbehaving embedding backend now rather than as silently empty retrieval later
+        let indexed_dim = store.get_stats().embedding_dimensions;
+        let (model_dimensions, health_warning) = match embedder.self_test((indexed_dim > 0).then_some(indexed_dim)) {
+            Ok(dim) => {
+                tracing::info!("Embedding self-test passed ({} dimensions)", dim);
+                (Some(dim), None)
+            }
+            Err(e) => {
+                tracing::error!("Embedding self-test failed: {:#}", e);
+                (None, Some(format!("{:#}", e)))
+            }
+        };
+
+        Ok(Self {
+            store: Arc::new(RwLock::new(store)),
+            metric: rag_config.similarity_metric,
+            precision,
+            save_lock: Mutex::new(()),
+            embedder,
+            model_dimensions,
+            health_warning,
+            summaries: condense::SummaryCache::default(),
+            open_batches: AtomicUsize::new(0),
+            unsaved: AtomicUsize::new(0),
+            index_version: AtomicU64::new(0),
+            retrieval_cache: cache::RetrievalCache::default(),
+            pasted: RwLock::new(pasted::Pasted::new(rag_config.similarity_metric)),
+            settings: RwLock::new(settings),
+        })
+    }
+
+    /// The retrieval settings, read again when this process has saved the config since
+    fn settings(&self) -> Arc<Settings> {
+        let settings = self.settings.read().unwrap().clone();
+        if settings.generation == crate::config::Config::save_generation() {
+            return settings;
+        }
+        let settings = Arc::new(Settings::load());
+        *self.settings.write().unwrap() = settings.clone();
+        settings
+    }
+
+    /// Read the retrieval settings again after the config was edited outside the app
+    pub fn reload_settings(&self) {
+        *self.settings.write().unwrap() = Arc::new(Settings::load());
+    }
+
+    /// The HNSW store at `storage_path`. A linear index left there by older versions is moved
+    /// into it the first time, while no HNSW data exists yet.
+    fn open_hnsw(storage_path: &str, rag_config: &crate::config::RagConfig) -> anyhow::Result<hnsw_store::HnswVectorStore> {
+        let data_path = Path::new(storage_path).with_extension("data");
+        let needs_migration = !data_path.exists() && Path::new(storage_path).exists();
+
+        let mut store = hnsw_store::HnswVectorStore::new(
+            storage_path,
+            rag_config.similarity_metric,
+            rag_config.hnsw,
+            rag_config.ef_search,
+        )?.with_precision(precision::Precision::from_half(rag_config.half_precision));
+
+        if needs_migration {
              tracing::info!("Migrating from Linear Store to HNSW Store...");
-             match store::LinearVectorStore::new(storage_path) {
+             match store::LinearVectorStore::new(storage_path, rag_config.similarity_metric) {
                  Ok(old_store) => {
-                     let docs = old_store.get_all()?;
-                     tracing::info!("Found {} documents to migrate.", docs.len());
-                     for doc in docs {
-                         store.add_document(doc)?;
+                     tracing::info!("Found {} documents to migrate.", old_store.count());
+                     let mut failed = None;
+                     old_store.for_each_document(&mut |doc| match store.add_document(doc.clone()) {
+                         Ok(()) => ControlFlow::Continue(()),
+                         Err(e) => {
+                             failed = Some(e);
+                             ControlFlow::Break(())
+                         }
+                     });
+                     if let Some(e) = failed {
+                         return Err(e);
                      }
                      store.save()?;
                      tracing::info!("Migration complete.");
@@ -90,17 +647,81 @@ impl RagSystem {
                  }
              }
         }
+        Ok(store)
+    }
 
-        Ok(Self {
-            store: Arc::new(Mutex::new(Box::new(store))),
-            embedder,
-        })
+    /// The SQLite store at `<storage_path>.sqlite`. Until a migration has been recorded in
+    /// the database, the documents of the HNSW index, or else of the linear one, are imported
+    /// into it; the old files are left in place. The import and its record share a
+    /// transaction, so an import that fails is tried again on the next start.
+    fn open_sqlite(storage_path: &str, rag_config: &crate::config::RagConfig) -> anyhow::Result<sqlite_store::SqliteVectorStore> {
+        let path = Path::new(storage_path).with_extension("sqlite");
+        let mut store = sqlite_store::SqliteVectorStore::new(&path, rag_config.similarity_metric)?
+            .with_precision(precision::Precision::from_half(rag_config.half_precision));
+        if store.migrated()? {
+            return Ok(store);
+        }
+
+        let existing = if store.count() > 0 {
+            // Written before the record existed, by a migration that went through
+            None
+        } else if let Some(documents) = hnsw_store::HnswVectorStore::stored_documents(storage_path)? {
+            Some(("HNSW", documents))
+        } else if Path::new(storage_path).exists() {
+            let linear = store::LinearVectorStore::new(storage_path, rag_config.similarity_metric)?;
+            Some(("linear", linear.get_all()?))
+        } else {
+            None
+        };
+        match existing {
+            Some((from, documents)) => {
+                tracing::info!("Importing {} documents from the {} index into {:?}...", documents.len(), from, path);
+                let imported = store.import(from, documents)?;
+                tracing::info!("Import complete: {} documents.", imported);
+            }
+            None => {
+                store.import("none", Vec::new())?;
+            }
+        }
+        Ok(store)
+    }
+
+    /// Summaries of condensed sources, kept for the session
+    pub fn summaries(&self) -> &condense::SummaryCache {
+        &self.summaries
     }
 
-    pub async fn add_document(&self, id: &str, content: &str, user_id: &str, meta: HashMap<String, String>) -> anyhow::Result<()> {
-        let embedding = self.embedder.embed(content).await?;
+    /// Invalidate cached retrieval results
+    fn bump_index_version(&self) {
+        self.index_version.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Problem found by the startup embedding self-test, if any
+    pub fn health_warning(&self) -> Option<&str> {
+        self.health_warning.as_deref()
+    }
+
+    /// The document `id` of `user_id`, when it was indexed from exactly `content` with an
+    /// embedding of the model's dimensions, so indexing it again needs no embedding
+    fn unchanged_document(&self, id: &str, content: &str, user_id: &str) -> Option<Document> {
+        self.store.read().unwrap().get_document(id).filter(|prev| reusable(prev, content, user_id, self.model_dimensions))
+    }
+
+    /// Embed and index `content` as `id`, replacing the document with that id. Content `id`
+    /// already holds is not embedded again, and when `meta` is unchanged too nothing is
+    /// written; `force` embeds and writes it anyway. Returns whether the index changed.
+    pub async fn add_document(&self, id: &str, content: &str, user_id: &str, meta: HashMap<String, String>, force: bool) -> anyhow::Result<bool> {
+        let unchanged = if force { None } else { self.unchanged_document(id, content, user_id) };
+        let embedding = match unchanged {
+            Some(prev) if same_metadata(&prev, &meta) => {
+                tracing::debug!("Skipping unchanged document {}", id);
+                return Ok(false);
+            }
+            Some(prev) => prev.embedding,
+            None => self.embedder.embed(content).await?,
+        };
         
-        let doc = Document {
+        let mut doc = Document {
             id: id.to_string(),
             content: content.to_string(),
             embedding,
@@ -108,48 +729,243 @@ impl RagSystem {
             user_id: user_id.to_string(),
         };
 
-        let mut store = self.store.lock().unwrap();
+        let mut store = self.store.write().unwrap();
+        let previous = store.get_document(id);
+        stamp_indexed_at(&mut doc, previous.as_ref());
+        if let Some(tags) = previous.and_then(|prev| prev.metadata.get(TAGS_KEY).cloned()) {
+            doc.metadata.insert(TAGS_KEY.to_string(), tags);
+        }
         store.add_document(doc)?;
-        Ok(())
+        drop(store);
+        self.bump_index_version();
+        self.changed(1)?;
+        Ok(true)
+    }
+
+    /// Replace `base_id` and all its chunks (`base_id#*`) with `docs` in one step.
+    /// Embeddings are computed first; the removal and inserts then happen under a single
+    /// store lock, so searches never see a half-updated family. Chunks whose id and content
+    /// are unchanged keep their embedding. Saves unless a batch is open.
+    pub async fn replace_document_family(&self, base_id: &str, user_id: &str, docs: Vec<(String, String, HashMap<String, String>)>) -> anyhow::Result<usize> {
+        let mut prepared = Vec::with_capacity(docs.len());
+        let mut reused = 0;
+        for (id, content, metadata) in docs {
+            let embedding = match self.unchanged_document(&id, &content, user_id) {
+                Some(prev) => {
+                    reused += 1;
+                    prev.embedding
+                }
+                None => self.embedder.embed(&content).await?,
+            };
+            prepared.push(Document { id, content, embedding, metadata, user_id: user_id.to_string() });
+        }
+
+        let count = prepared.len();
+        if reused > 0 {
+            tracing::info!("Re-indexed {}: {} of {} chunks unchanged, not embedded again", base_id, reused, count);
+        }
+        let mut store = self.store.write().unwrap();
+        swap_family(&mut *store, base_id, prepared)?;
+        self.bump_index_version();
+        drop(store);
+        self.changed(count)?;
+        Ok(count)
+    }
+
+    /// Start a batch: family replacements skip their save until the batch is committed
+    pub fn begin_batch(&self) -> Batch<'_> {
+        self.open_batches.fetch_add(1, Ordering::SeqCst);
+        Batch { rag: self, open: true }
     }
 
     pub fn count_documents(&self) -> usize {
-        self.store.lock().unwrap().count()
+        self.store.read().unwrap().count()
+    }
+
+    /// Documents whose `key` metadata is exactly `value`, e.g. the chunks of one file
+    pub fn get_documents_by_metadata(&self, key: &str, value: &str) -> anyhow::Result<Vec<Document>> {
+        self.store.read().unwrap().get_documents_by_metadata(key, value)
     }
 
     /// Clear all documents from the index
     pub fn clear(&self) -> anyhow::Result<()> {
-        let mut store = self.store.lock().unwrap();
+        let mut store = self.store.write().unwrap();
+        self.bump_index_version();
         store.clear()
     }
 
     /// Check if a document exists in the index
     pub fn contains(&self, id: &str) -> bool {
-        self.store.lock().unwrap().contains(id)
+        self.store.read().unwrap().contains(id)
+    }
+
+    /// Embed `text` as temporary documents that retrieval searches with the index until
+    /// `clear_pasted`. Returns the source label of its snippets and its number of chunks.
+    pub async fn add_pasted(&self, text: &str, user_id: &str) -> anyhow::Result<(String, usize)> {
+        let chunks = pasted::split(text);
+        anyhow::ensure!(!chunks.is_empty(), "Nothing was pasted");
+        let embeddings = self.embedder.embed_batch(&chunks).await?;
+        let count = chunks.len();
+        let label = self.pasted.write().unwrap().add(chunks, embeddings, user_id)?;
+        self.bump_index_version();
+        Ok((label, count))
+    }
+
+    /// Drop the pasted texts, returning how many there were
+    pub fn clear_pasted(&self) -> usize {
+        let cleared = self.pasted.write().unwrap().clear();
+        if cleared > 0 {
+            self.bump_index_version();
+        }
+        cleared
+    }
+
+    /// Texts pasted since the last `clear_pasted`
+    pub fn pasted_count(&self) -> usize {
+        self.pasted.read().unwrap().pastes()
     }
 
-    /// Save the index to disk
+    /// Save the index to disk. Searches keep running meanwhile; writers wait for it to finish.
     pub fn save(&self) -> anyhow::Result<()> {
-        let store = self.store.lock().unwrap();
-        store.save()
+        let _saving = self.save_lock.lock().unwrap();
+        let store = self.store.read().unwrap();
+        let unsaved = self.unsaved.swap(0, Ordering::SeqCst);
+        store.save().inspect_err(|_| {
+            self.unsaved.fetch_add(unsaved, Ordering::SeqCst);
+        })
+    }
+
+    /// Note that `count` documents changed and save, unless a batch is open. An open batch
+    /// still saves once `AUTOSAVE_EVERY` changes pile up.
+    fn changed(&self, count: usize) -> anyhow::Result<()> {
+        let unsaved = self.unsaved.fetch_add(count, Ordering::SeqCst) + count;
+        if self.open_batches.load(Ordering::SeqCst) == 0 {
+            self.save()?;
+        } else if unsaved >= AUTOSAVE_EVERY {
+            tracing::info!("Saving the index after {} unsaved changes", unsaved);
+            self.save()?;
+        }
+        Ok(())
     }
     
     /// Remove a document from the index
     pub fn remove_document(&self, id: &str) -> anyhow::Result<()> {
-        let mut store = self.store.lock().unwrap();
+        let mut store = self.store.write().unwrap();
+        self.bump_index_version();
         store.remove_document(id)
     }
 
+    /// Tag `id` and its chunks with `tag`, and save. Returns how many documents were tagged;
+    /// errors when there is no document with that id.
+    pub fn add_tag(&self, id: &str, tag: &str) -> anyhow::Result<usize> {
+        let tag = normalize_tag(tag);
+        anyhow::ensure!(!tag.is_empty(), "Tags can't be empty");
+        self.update_tags(id, |tags| if !tags.contains(&tag) { tags.push(tag.clone()) })
+    }
+
+    /// Remove `tag` from `id` and its chunks, and save
+    pub fn remove_tag(&self, id: &str, tag: &str) -> anyhow::Result<usize> {
+        let tag = normalize_tag(tag);
+        self.update_tags(id, |tags| tags.retain(|t| *t != tag))
+    }
+
+    fn update_tags(&self, id: &str, mut update: impl FnMut(&mut Vec<String>)) -> anyhow::Result<usize> {
+        let updated = {
+            let mut store = self.store.write().unwrap();
+            let ids = store.family_ids(id);
+            anyhow::ensure!(!ids.is_empty(), "No document '{}' in the index", id);
+            for doc_id in &ids {
+                let Some(doc) = store.get_document(doc_id) else { continue };
+                let mut doc_tags = tags(&doc);
+                update(&mut doc_tags);
+                let value = (!doc_tags.is_empty()).then(|| doc_tags.join(","));
+                store.set_metadata(doc_id, TAGS_KEY, value);
+            }
+            ids.len()
+        };
+        self.bump_index_version();
+        self.save()?;
+        Ok(updated)
+    }
+
+    /// Tags of `id` and its chunks, sorted
+    pub fn document_tags(&self, id: &str) -> Vec<String> {
+        let store = self.store.read().unwrap();
+        let mut found: Vec<String> = store.family_ids(id).iter()
+            .filter_map(|doc_id| store.get_document(doc_id))
+            .flat_map(|doc| tags(&doc))
+            .collect();
+        found.sort();
+        found.dedup();
+        found
+    }
+
+    /// Every tag in the index with the number of documents (files, not chunks) carrying it
+    pub fn all_tags(&self) -> Vec<(String, usize)> {
+        let store = self.store.read().unwrap();
+        let mut families: HashMap<String, HashSet<String>> = HashMap::new();
+        store.for_each_document(&mut |doc| {
+            let base = doc.id.rsplit_once('#').map_or(doc.id.as_str(), |(base, _)| base);
+            for tag in tags(doc) {
+                families.entry(tag).or_default().insert(base.to_string());
+            }
+            ControlFlow::Continue(())
+        });
+        let mut counts: Vec<(String, usize)> = families.into_iter().map(|(tag, bases)| (tag, bases.len())).collect();
+        counts.sort();
+        counts
+    }
+
+    /// Id of the indexed file `name`, given as its path in the index or, when unambiguous,
+    /// just its file name. Other names are taken as document ids (e.g. a subject).
+    pub fn resolve_document(&self, name: &str) -> Option<String> {
+        let filenames = self.get_all_filenames().unwrap_or_default();
+        let suffix = format!("/{}", name);
+        let mut matches = filenames.iter().filter(|f| *f == name || f.ends_with(&suffix));
+        let file = match (matches.next(), matches.next()) {
+            (Some(file), None) => Some(file.clone()),
+            _ => None,
+        };
+        if let Some(file) = file {
+            let store = self.store.read().unwrap();
+            let doc = store.get_documents_by_metadata("filename", &file).ok()?.into_iter().next()?;
+            return Some(doc.id.rsplit_once('#').map_or(doc.id.clone(), |(base, _)| base.to_string()));
+        }
+        let store = self.store.read().unwrap();
+        (!store.family_ids(name).is_empty()).then(|| name.to_string())
+    }
+
+    /// Drop what removed documents left in the search structure once there is enough of it,
+    /// see `VectorStore::compact`. Does not save.
+    fn compact(&self) -> anyhow::Result<()> {
+        if self.store.write().unwrap().compact()? {
+            self.bump_index_version();
+        }
+        Ok(())
+    }
+
+    /// Rebuild the search graph with the configured parameters and persist it
+    pub fn rebuild_index(&self) -> anyhow::Result<usize> {
+        let count = {
+            let mut store = self.store.write().unwrap();
+            store.rebuild()?;
+            store.count()
+        };
+        self.bump_index_version();
+        self.save()?;
+        Ok(count)
+    }
+
     /// Get all chunks for a specific file, sorted by index
     pub fn get_file_chunks(&self, filename: &str) -> anyhow::Result<Vec<(String, String)>> {
-        let store = self.store.lock().unwrap();
+        let store = self.store.read().unwrap();
         let mut chunks = store.get_documents_by_metadata("filename", filename)?;
         
         // Sort by ID to ensure correct part order (assuming part index is in ID)
         // IDs are formatted as "subject/path#index"
         chunks.sort_by(|a, b| {
             let get_idx = |id: &str| -> usize {
-                id.split('#').last().and_then(|s| s.parse().ok()).unwrap_or(0)
+                id.split('#').next_back().and_then(|s| s.parse().ok()).unwrap_or(0)
             };
             get_idx(&a.id).cmp(&get_idx(&b.id))
         });
@@ -157,72 +973,259 @@ impl RagSystem {
         Ok(chunks.into_iter().map(|d| (d.id, d.content)).collect())
     }
 
+    /// When the most recently indexed chunk of `filename` was indexed
+    pub fn file_indexed_at(&self, filename: &str) -> Option<u64> {
+        let store = self.store.read().unwrap();
+        store.get_documents_by_metadata("filename", filename).ok()?.iter().filter_map(indexed_at).max()
+    }
+
+    /// Site id and name of the subjects with a summary in the index
+    pub fn subject_names(&self) -> Vec<(String, String)> {
+        let store = self.store.read().unwrap();
+        store.get_documents_by_metadata("type", "subject").unwrap_or_default()
+            .into_iter()
+            .map(|doc| {
+                let name = doc.metadata.get("name").cloned().unwrap_or_else(|| doc.id.clone());
+                (doc.id, name)
+            })
+            .collect()
+    }
+
+    /// Indexed documents per subject site id, see `subjects::subject_of`
+    pub fn document_counts_by_subject(&self) -> HashMap<String, usize> {
+        let store = self.store.read().unwrap();
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        store.for_each_document(&mut |doc| {
+            *counts.entry(crate::subjects::subject_of(&doc.id).to_string()).or_insert(0) += 1;
+            ControlFlow::Continue(())
+        });
+        counts
+    }
+
+    /// Base ids (`<site id>/<path>`) of the files indexed for `subject`, without the subject summary
+    pub fn indexed_files(&self, subject: &str) -> HashSet<String> {
+        let prefix = format!("{}/", subject);
+        let store = self.store.read().unwrap();
+        let mut files = HashSet::new();
+        store.for_each_document(&mut |doc| {
+            if doc.id.starts_with(&prefix) {
+                files.insert(doc.id.rsplit_once('#').map_or(doc.id.as_str(), |(base, _)| base).to_string());
+            }
+            ControlFlow::Continue(())
+        });
+        files
+    }
+
+    /// Chunks of the files indexed for `subject`, without their embeddings
+    pub fn subject_chunks(&self, subject: &str) -> Vec<Document> {
+        let prefix = format!("{}/", subject);
+        let store = self.store.read().unwrap();
+        let mut chunks = Vec::new();
+        store.for_each_document(&mut |doc| {
+            if doc.id.starts_with(&prefix) && doc.id.contains('#') {
+                chunks.push(Document {
+                    id: doc.id.clone(),
+                    content: doc.content.clone(),
+                    embedding: Vec::new(),
+                    metadata: doc.metadata.clone(),
+                    user_id: doc.user_id.clone(),
+                });
+            }
+            ControlFlow::Continue(())
+        });
+        chunks
+    }
+
+    /// Base id of the files of `subject` by the value their chunks have for the metadata `key`
+    pub fn families_by_metadata(&self, subject: &str, key: &str) -> HashMap<String, String> {
+        let prefix = format!("{}/", subject);
+        let store = self.store.read().unwrap();
+        let mut families = HashMap::new();
+        store.for_each_document(&mut |doc| {
+            if let (true, Some(value), Some((base, _))) = (doc.id.starts_with(&prefix), doc.metadata.get(key), doc.id.rsplit_once('#')) {
+                families.entry(value.clone()).or_insert_with(|| base.to_string());
+            }
+            ControlFlow::Continue(())
+        });
+        families
+    }
+
+    /// Set (or with `None` remove) metadata entries on `base_id` and its chunks. Saves unless a
+    /// batch is open. Returns how many documents were updated.
+    pub fn set_family_metadata(&self, base_id: &str, entries: &[(&str, Option<String>)]) -> anyhow::Result<usize> {
+        let updated = {
+            let mut store = self.store.write().unwrap();
+            let ids = store.family_ids(base_id);
+            for id in &ids {
+                for (key, value) in entries {
+                    store.set_metadata(id, key, value.clone());
+                }
+            }
+            ids.len()
+        };
+        self.bump_index_version();
+        self.changed(updated)?;
+        Ok(updated)
+    }
+
+    /// Move `from` and its chunks to the base id `to`, embeddings included, replacing whatever
+    /// `to` held. Saves unless a batch is open. Returns how many documents moved.
+    pub fn move_document_family(&self, from: &str, to: &str) -> anyhow::Result<usize> {
+        let moved = move_family(&mut *self.store.write().unwrap(), from, to)?;
+        self.bump_index_version();
+        self.changed(moved)?;
+        Ok(moved)
+    }
+
+    /// `base_id` and its chunks
+    pub fn family_documents(&self, base_id: &str) -> Vec<Document> {
+        let store = self.store.read().unwrap();
+        store.family_ids(base_id).iter().filter_map(|id| store.get_document(id)).collect()
+    }
+
+    /// Extracted text of the file the chunk `id` belongs to, rebuilt from its chunks, with the
+    /// range of each. A subject summary, which isn't chunked, is its own text.
+    pub fn document_text(&self, id: &str) -> anyhow::Result<anchor::DocumentText> {
+        let base_id = id.rsplit_once('#').map_or(id, |(base, _)| base);
+        let family = self.family_documents(base_id);
+        if let [doc] = family.as_slice() {
+            if doc.id == base_id {
+                let len = doc.content.chars().count();
+                return Ok(anchor::DocumentText { text: doc.content.clone(), chunks: vec![(doc.id.clone(), 0..len)] });
+            }
+        }
+        anyhow::ensure!(!family.is_empty(), "No document '{}' in the index", id);
+        anchor::reassemble(&family)
+            .ok_or_else(|| anyhow::anyhow!("{} was indexed before chunk positions were recorded; a fresh sync records them", base_id))
+    }
+
+    /// Remove `base_id` and its chunks. Saves unless a batch is open. Returns how many were removed.
+    pub fn remove_document_family(&self, base_id: &str) -> anyhow::Result<usize> {
+        let removed = {
+            let mut store = self.store.write().unwrap();
+            let ids = store.family_ids(base_id);
+            for id in &ids {
+                store.remove_document(id)?;
+            }
+            ids.len()
+        };
+        self.bump_index_version();
+        self.changed(removed)?;
+        Ok(removed)
+    }
+
+    /// Remove every document whose id starts with `prefix`, then drop what they left in the
+    /// search structure and save unless a batch is open. Returns how many were removed.
+    pub fn remove_by_prefix(&self, prefix: &str) -> anyhow::Result<usize> {
+        let removed = self.store.write().unwrap().remove_by_prefix(prefix)?;
+        self.removed(removed)
+    }
+
+    /// Remove a subject from the index: its summary and every chunk of its files. Saves like
+    /// `remove_by_prefix`; returns how many documents were removed.
+    pub fn remove_subject(&self, subject_id: &str) -> anyhow::Result<usize> {
+        let removed = {
+            let mut store = self.store.write().unwrap();
+            // The summary's id is the subject id itself, its files' are `<subject id>/<path>`
+            let mut removed = store.remove_by_prefix(&format!("{}/", subject_id))?;
+            if store.contains(subject_id) {
+                store.remove_document(subject_id)?;
+                removed += 1;
+            }
+            removed
+        };
+        self.removed(removed)
+    }
+
+    fn removed(&self, count: usize) -> anyhow::Result<usize> {
+        if count > 0 {
+            self.compact()?;
+            self.bump_index_version();
+            self.changed(count)?;
+        }
+        Ok(count)
+    }
+
+    /// Put archived documents back as they were, embeddings included, replacing the current
+    /// chunks of their files. Saves. Returns how many documents were restored.
+    pub fn restore_documents(&self, docs: Vec<Document>) -> anyhow::Result<usize> {
+        let count = docs.len();
+        {
+            let mut store = self.store.write().unwrap();
+            let bases: HashSet<String> = docs.iter()
+                .map(|doc| doc.id.rsplit_once('#').map_or(doc.id.as_str(), |(base, _)| base).to_string())
+                .collect();
+            for base in &bases {
+                for id in store.family_ids(base) {
+                    store.remove_document(&id)?;
+                }
+            }
+            for doc in docs {
+                store.add_document(doc)?;
+            }
+        }
+        self.bump_index_version();
+        self.save()?;
+        Ok(count)
+    }
+
     /// Get a list of all unique filenames in the index
     pub fn get_all_filenames(&self) -> anyhow::Result<HashSet<String>> {
-        let store = self.store.lock().unwrap();
-        let docs = store.get_all()?;
+        let store = self.store.read().unwrap();
         let mut filenames = HashSet::new();
-        for doc in docs {
+        store.for_each_document(&mut |doc| {
             if let Some(filename) = doc.metadata.get("filename") {
-                filenames.insert(filename.clone());
+                if !filenames.contains(filename) {
+                    filenames.insert(filename.clone());
+                }
             }
-        }
+            ControlFlow::Continue(())
+        });
         Ok(filenames)
     }
     
-    /// Recalculate embeddings for all documents
-    /// progress_fn receives (current, total, doc_id, metadata)
-    /// skip_ids allows avoiding redundant work for documents already indexed in this run
-    pub async fn reembed_all<F>(&self, skip_ids: &HashSet<String>, mut progress_fn: F) -> anyhow::Result<usize>
+    /// Recalculate embeddings for all documents, `reembed::BATCH_SIZE` at a time.
+    /// `skip_ids` are documents already embedded by this model in this run (e.g. just indexed).
+    /// Every `reembed::CHECKPOINT_EVERY` documents the index is saved together with a checkpoint
+    /// of the ids done, so running it again after a crash resumes from there.
+    pub async fn reembed_all<F>(&self, skip_ids: &HashSet<String>, progress_fn: F) -> anyhow::Result<usize>
     where
-        F: FnMut(usize, usize, &str, &HashMap<String, String>),
+        F: FnMut(&reembed::Progress),
     {
-        // Get all document contents
-        let docs = {
-            let store = self.store.lock().unwrap();
-            store.get_all()?
-        };
-        
-        let total = docs.len();
-        let mut reembedded = 0;
-        
-        for (i, old_doc) in docs.into_iter().enumerate() {
-            if skip_ids.contains(&old_doc.id) {
-                reembedded += 1;
-                progress_fn(i + 1, total, &old_doc.id, &old_doc.metadata);
-                continue;
-            }
-            
-            progress_fn(i + 1, total, &old_doc.id, &old_doc.metadata);
-            
-            // Recalculate embedding
-            let embedding_res = self.embedder.embed(&old_doc.content).await;
-            
-            match embedding_res {
-                Ok(embedding) => {
-                    // Update document
-                    let mut doc = old_doc.clone();
-                    doc.embedding = embedding;
-                    
-                    let mut store = self.store.lock().unwrap();
-                    store.add_document(doc)?;
-                    reembedded += 1;
-                },
+        let index_path = PathBuf::from(self.store.read().unwrap().storage_path());
+        let checkpoint_path = reembed::Checkpoint::path(&index_path);
+        let reembedded = reembed::run(self, &checkpoint_path, &self.embedder.model_name(), skip_ids, progress_fn, |texts| self.embed_each(texts)).await?;
+
+        // Every re-embedded document left its old point behind
+        self.compact()?;
+        self.save()?;
+        reembed::Checkpoint::remove(&checkpoint_path);
+
+        Ok(reembedded)
+    }
+
+    /// Embeddings of `texts` in one batch. When the batch fails they are embedded one by one
+    /// to find the text that fails; it gets `None`, the others still get their embedding.
+    async fn embed_each(&self, texts: Vec<String>) -> anyhow::Result<Vec<Option<Vec<f32>>>> {
+        if let Ok(embeddings) = self.embedder.embed_batch(&texts).await {
+            return Ok(embeddings.into_iter().map(Some).collect());
+        }
+        let mut one_by_one = Vec::with_capacity(texts.len());
+        for text in &texts {
+            match self.embedder.embed(text).await {
+                Ok(embedding) => one_by_one.push(Some(embedding)),
                 Err(e) => {
-                    tracing::error!("Failed to re-embed output document {}: {}", old_doc.id, e);
+                    tracing::error!("Failed to re-embed a document: {}", e);
+                    one_by_one.push(None);
                 }
             }
         }
-        
-        let store = self.store.lock().unwrap();
-        store.save()?;
-        
-        Ok(reembedded)
+        Ok(one_by_one)
     }
 
     /// Get comprehensive statistics about the RAG index
     pub fn get_stats(&self) -> RagStats {
-        let store = self.store.lock().unwrap();
+        let store = self.store.read().unwrap();
         let stats = store.get_stats();
         let storage_path = store.storage_path();
         let store_type = store.store_type();
@@ -235,38 +1238,174 @@ impl RagSystem {
             file_size_bytes: stats.file_size_bytes, 
             storage_path,
             store_type,
-            chunking_strategy: self.embedder.chunking_strategy(),
+            chunk_chars: CHUNK_CHARS,
+            chunk_overlap: CHUNK_OVERLAP,
+            embedding_backend: embeddings::BACKEND,
             embedding_model: self.embedder.model_name(),
+            model_dimensions: self.model_dimensions,
+            embedding_window_chars: self.embedder.window_chars(),
+            retrieval: RetrievalStats::from_config(&self.settings().rag),
+            index_params: stats.index_params,
+            indexed_range: stats.indexed_range,
+            docs_by_subject: stats.docs_by_subject,
+            embedding_value_bytes: self.precision.value_bytes(),
+            precision_file_bytes: stats.precision_bytes,
         }
     }
 
+    /// Distinct `type` values in the index, sorted
+    pub fn document_types(&self) -> Vec<String> {
+        let store = self.store.read().unwrap();
+        let mut types: Vec<String> = store.get_stats().docs_by_type.into_keys().collect();
+        types.sort();
+        types
+    }
+
     pub async fn search(&self, query: &str, user_id: &str, top_k: usize) -> anyhow::Result<Vec<(Document, f32)>> {
+        self.search_filtered(query, user_id, top_k, &SearchFilter::default()).await
+    }
+
+    /// `search` among the documents `filter` matches; the `top_k` best of them come back
+    /// even when better matches are filtered out
+    pub async fn search_filtered(&self, query: &str, user_id: &str, top_k: usize, filter: &SearchFilter) -> anyhow::Result<Vec<(Document, f32)>> {
         let query_embedding = self.embedder.embed(query).await?;
-        let store = self.store.lock().unwrap();
-        store.search(&query_embedding, user_id, top_k, 0.0)
+        let accepts = |doc: &Document| filter.matches(doc);
+        let store = self.store.read().unwrap();
+        store.search(&query_embedding, user_id, top_k, 0.0, (!filter.is_empty()).then_some(&accepts as DocFilter))
     }
     
-    /// Search and return concise snippets suitable for LLM context
-    pub async fn search_snippets(&self, query: &str, user_id: &str, top_k: usize) -> anyhow::Result<Vec<(String, String, f32)>> {
+    /// Search and return concise snippets suitable for LLM context, cut off by `relevance`
+    /// (see `relevance::select`). Only documents in `scope` are considered.
+    /// Results of recent queries are cached until the index changes.
+    /// Documents of the subjects in `boosts` score higher by their boost, see `affinity`
+    #[allow(clippy::too_many_arguments)]
+    pub async fn search_snippets(&self, query: &str, user_id: &str, top_k: usize, max_chars: usize, relevance: RelevanceParams, scope: &Scope, boosts: &affinity::Boosts) -> anyhow::Result<Vec<Snippet>> {
+        let settings = self.settings();
+        let tuning = SearchTuning { hybrid: settings.rag.hybrid, mmr_lambda: settings.rag.mmr_lambda() };
+        let mut scope = scope.clone();
+        scope.exclude_subjects.extend(settings.excluded_subjects.iter().cloned());
+        scope.exclude_subjects.sort();
+        scope.exclude_subjects.dedup();
+        let scope = &scope;
+
+        let key = cache::QueryKey::new(query, user_id, top_k, max_chars, relevance, tuning, scope, boosts);
+        let version = self.index_version.load(Ordering::SeqCst);
+        if let Some(snippets) = self.retrieval_cache.get(&key, version) {
+            tracing::debug!("RAG Search: {} snippets served from cache", snippets.len());
+            return Ok(snippets);
+        }
+
+        let snippets = self.search_snippets_uncached(query, user_id, top_k, max_chars, relevance, tuning, scope, boosts).await?;
+        self.retrieval_cache.insert(key, snippets.clone(), version);
+        Ok(snippets)
+    }
+
+    /// Answer a factual question without a language model: the best embedding matches in
+    /// `scope`, each cut to the 1-3 sentences around its best keyword match, strongest keyword
+    /// match first. Documents without any keyword of the query are left out.
+    pub async fn lookup(&self, query: &str, user_id: &str, limit: usize, scope: &Scope) -> anyhow::Result<Vec<Excerpt>> {
+        let mut scope = scope.clone();
+        scope.exclude_subjects.extend(self.settings().excluded_subjects.iter().cloned());
         let query_embedding = self.embedder.embed(query).await?;
-        
-        let candidates = {
-            let store = self.store.lock().unwrap();
-            store.search(&query_embedding, user_id, top_k * 2, 0.3)?
+        let scope_filter = |doc: &Document| scope.matches(doc);
+        let found = {
+            let store = self.store.read().unwrap();
+            store.search(&query_embedding, user_id, LOOKUP_CANDIDATES, 0.0, (!scope.is_all()).then_some(&scope_filter as DocFilter))?
         };
+
+        let terms = sentences::query_terms(query);
+        let mut excerpts: Vec<Excerpt> = Vec::new();
+        for (doc, score) in found {
+            let body = &doc.content[sentences::body_start(&doc.content)..];
+            let Some(passage) = sentences::best_passage(body, &terms, 3, LOOKUP_EXCERPT_CHARS) else { continue };
+            let text = body[passage.range].to_string();
+            if excerpts.iter().any(|e| e.text == text) {
+                continue;
+            }
+            let source = match doc.metadata.get("type").map(|t| t.as_str()) {
+                Some("subject") => doc.id.clone(),
+                _ => doc.metadata.get("filename").cloned().unwrap_or_else(|| doc.id.clone()),
+            };
+            excerpts.push(Excerpt {
+                source,
+                subject: crate::subjects::subject_of(&doc.id).to_string(),
+                page: doc.metadata.get("page").cloned(),
+                text,
+                keyword_score: passage.score,
+                score,
+            });
+        }
+        excerpts.sort_by(|a, b| b.keyword_score.cmp(&a.keyword_score).then(b.score.total_cmp(&a.score)));
+        excerpts.truncate(limit);
+        Ok(excerpts)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn search_snippets_uncached(&self, query: &str, user_id: &str, top_k: usize, max_chars: usize, relevance: RelevanceParams, tuning: SearchTuning, scope: &Scope, boosts: &affinity::Boosts) -> anyhow::Result<Vec<Snippet>> {
+        let query_embedding = self.embedder.embed(query).await?;
         
-        tracing::debug!("RAG Search: Found {} candidates (pre-filter)", candidates.len());
-        
-        if !candidates.is_empty() {
-            let top_5: Vec<f32> = candidates.iter().take(5).map(|(_,s)| *s).collect();
-            tracing::info!("RAG Search: Top 5 scores: {:?}", top_5);
+        let scope_filter = |doc: &Document| scope.matches(doc);
+        // No threshold here: the cutoff depends on the best score, and a weak best match is still the fallback
+        let (candidates, cutoff) = {
+            let store = self.store.read().unwrap();
+            let mut scored = store.search(&query_embedding, user_id, top_k * 2, 0.0, (!scope.is_all()).then_some(&scope_filter as DocFilter))?;
+            boosts.apply(&mut scored, |doc| crate::subjects::subject_of(&doc.id));
+            // Pasted text was pasted to be asked about, so the scope doesn't apply to it
+            let pasted = self.pasted.read().unwrap();
+            if !pasted.is_empty() {
+                scored = pasted::merge(scored, pasted.search(&query_embedding, user_id, top_k * 2)?, top_k * 2);
+            }
+
+            tracing::debug!("RAG Search: Found {} candidates (pre-filter)", scored.len());
+            if !scored.is_empty() {
+                let top_5: Vec<f32> = scored.iter().take(5).map(|(_, s)| *s).collect();
+                tracing::info!("RAG Search: Top 5 scores: {:?}", top_5);
+            }
+
+            // The whole candidate pool clears the cutoff, then MMR picks `top_k` of it so one
+            // file's near-identical chunks don't take every place
+            let (kept, cutoff) = relevance::select(scored, relevance, top_k * 2);
+            let mut kept = diversity::mmr(kept, top_k, tuning.mmr_lambda, |a, b| store::similarity(self.metric, a, b));
+            // Exact tokens like course codes can score low as embeddings, so keyword matches
+            // join after the cutoff
+            let hybrid = tuning.hybrid;
+            if hybrid.keyword_weight > 0.0 {
+                let matches = store.keyword_search(query, user_id, top_k, (!scope.is_all()).then_some(&scope_filter as DocFilter));
+                tracing::debug!("RAG Search: {} keyword matches", matches.len());
+                let similarity = |doc: &Document| {
+                    store::similarity(self.metric, &query_embedding, &doc.embedding) + boosts.get(crate::subjects::subject_of(&doc.id))
+                };
+                let keyword_score = |doc: &Document| store.keyword_score(query, &doc.id);
+                kept = keyword::fuse(kept, matches, hybrid, top_k, similarity, keyword_score);
+            }
+            let candidates: Vec<_> = kept.into_iter()
+                .map(|(doc, score)| {
+                    // Chunks are stored as "<base_id>#<n>"
+                    let chunk = doc.id.rsplit_once('#')
+                        .and_then(|(base_id, n)| {
+                            let total = if pasted::is_pasted(base_id) { pasted.family_len(base_id) } else { store.family_ids(base_id).len() };
+                            Some((n.parse::<usize>().ok()? + 1, total))
+                        })
+                        .filter(|(_, total)| *total > 1);
+                    (doc, score, chunk)
+                })
+                .collect();
+            (candidates, cutoff)
+        };
+
+        match cutoff {
+            relevance::Cutoff::Empty => tracing::info!("RAG Search: nothing retrieved"),
+            relevance::Cutoff::Relative(at) => tracing::info!("RAG Search: kept {} within {:.2} of the best score (cutoff {:.2})", candidates.len(), relevance.delta, at),
+            relevance::Cutoff::Floor(at) => tracing::info!("RAG Search: kept {} above the floor {:.2}", candidates.len(), at),
+            relevance::Cutoff::Fallback => tracing::info!("RAG Search: nothing above the floor {:.2}, keeping the best match as low-confidence", relevance.floor),
+            relevance::Cutoff::Off => tracing::info!("RAG Search: relevance cutoff off, kept {}", candidates.len()),
         }
+        let low_confidence = cutoff.low_confidence();
         
-        let query_lower = query.to_lowercase();
-        let query_words: Vec<String> = query_lower.split_whitespace().map(|s| s.to_string()).collect();
+        let terms = sentences::query_terms(query);
         
-        let mut snippets: Vec<(String, String, f32)> = candidates.into_iter()
-            .map(|(doc, score)| {
+        let snippets: Vec<Snippet> = candidates.into_iter()
+            .map(|(doc, score, chunk)| {
                 let source = doc.metadata.get("type")
                     .map(|t| {
                         if t == "subject" {
@@ -277,57 +1416,407 @@ impl RagSystem {
                     })
                     .unwrap_or(doc.id.clone());
                 
-                let snippet = extract_relevant_snippet(&doc.content, &query_words, 1500);
-                (source, snippet, score)
+                let snippet = extract_relevant_snippet(&doc.content, &terms, max_chars);
+                let label = if doc.metadata.get("type").map(|t| t.as_str()) == Some("transcript") {
+                    transcript_label(&doc.metadata, &snippet).unwrap_or_else(|| source.clone())
+                } else {
+                    source.clone()
+                };
+                let page = doc.metadata.get("page").cloned();
+                let doc_type = doc.metadata.get("type").cloned().unwrap_or_else(|| "unknown".to_string());
+                let indexed_at = indexed_at(&doc);
+                let subject = crate::subjects::subject_of(&doc.id).to_string();
+                let boost = boosts.get(&subject);
+                Snippet { id: doc.id.clone(), source, label, text: snippet, score, page, doc_type, chunk, indexed_at, low_confidence, subject, boost }
             })
             .collect();
-            
-        if snippets.len() > top_k {
-            snippets.truncate(top_k);
-        }
         
         Ok(snippets)
     }
 }
 
-/// Extract the most relevant snippet from content based on query words
-fn extract_relevant_snippet(content: &str, query_words: &[String], max_chars: usize) -> String {
-    let mut best_pos = 0;
-    let mut best_score = 0;
-    
-    let words: Vec<&str> = content.split_whitespace().collect();
-    let window_size = 50; 
-    
-    for i in 0..words.len().saturating_sub(window_size) {
-        let window: String = words[i..i + window_size].join(" ").to_lowercase();
-        let score: usize = query_words.iter()
-            .filter(|qw| window.contains(*qw))
-            .count();
-        
-        if score > best_score {
-            best_score = score;
-            best_pos = words[..i].iter().map(|w| w.len() + 1).sum::<usize>();
+/// "<recording> recording @ HH:MM" using the first timestamp anchor inside the snippet
+fn transcript_label(metadata: &HashMap<String, String>, snippet: &str) -> Option<String> {
+    let media = metadata.get("media")?;
+    let name = Path::new(media).file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_else(|| media.clone());
+
+    // Anchors look like "[00:14:30]"
+    let anchor = snippet.match_indices('[').find_map(|(i, _)| {
+        let candidate = snippet.get(i + 1..i + 9)?;
+        let valid = snippet[i + 9..].starts_with(']')
+            && candidate.bytes().enumerate().all(|(j, b)| if j == 2 || j == 5 { b == b':' } else { b.is_ascii_digit() });
+        valid.then(|| candidate[..5].to_string())
+    });
+
+    Some(match anchor {
+        Some(time) => format!("{} recording @ {}", name, time),
+        None => format!("{} recording", name),
+    })
+}
+
+/// The passage of `content` that best matches `terms`, whole sentences up to `max_chars`
+/// (see `sentences::best_passage`), or the start of the text when nothing matches
+fn extract_relevant_snippet(content: &str, terms: &[String], max_chars: usize) -> String {
+    let body = match &content[sentences::body_start(content)..] {
+        "" => content,
+        body => body,
+    };
+    let range = match sentences::best_passage(body, terms, usize::MAX, max_chars) {
+        Some(passage) => passage.range,
+        None => 0..body.len(),
+    };
+    let range = clip_to(body, range, terms, max_chars);
+
+    let mut snippet = body[range.clone()].trim().to_string();
+    if range.start > 0 {
+        snippet = format!("...{}", snippet);
+    }
+    if range.end < body.len() {
+        snippet.push_str("...");
+    }
+    snippet
+}
+
+/// `range` of `text` cut to at most `max_chars` at word boundaries, keeping the first
+/// occurrence of a term in view
+fn clip_to(text: &str, range: Range<usize>, terms: &[String], max_chars: usize) -> Range<usize> {
+    if range.len() <= max_chars {
+        return range;
+    }
+    let lower = text[range.clone()].to_lowercase();
+    let hit = terms.iter().filter_map(|t| lower.find(t.as_str())).min().unwrap_or(0);
+    let floor = |mut at: usize| { while !text.is_char_boundary(at) { at -= 1; } at };
+
+    let mut start = floor(range.start + hit.saturating_sub(max_chars / 4));
+    if start > range.start {
+        start = text[start..range.end].find(' ').map_or(start, |pos| start + pos + 1);
+    }
+    let mut end = floor((start + max_chars).min(range.end));
+    if end < range.end {
+        end = text[start..end].rfind(' ').map_or(end, |pos| start + pos);
+    }
+    start..end
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RagConfig;
+
+    fn doc(id: &str, embedding: Vec<f32>) -> Document {
+        Document {
+            id: id.to_string(),
+            content: format!("Contents of {}", id),
+            embedding,
+            metadata: HashMap::new(),
+            user_id: "user".to_string(),
         }
     }
-    
-    let start = best_pos.saturating_sub(50);
-    let end = (start + max_chars).min(content.len());
-    
-    let mut snippet: String = content.chars().skip(start).take(end - start).collect();
-    
-    if start > 0 {
-        if let Some(pos) = snippet.find(' ') {
-            snippet = snippet[pos + 1..].to_string();
+
+    /// A linear index at `<dir>/index.bin` holding two documents
+    fn linear_index(dir: &Path) -> String {
+        let storage_path = dir.join("index.bin").to_string_lossy().to_string();
+        let mut linear = store::LinearVectorStore::new(&storage_path, SimilarityMetric::Cosine).unwrap();
+        linear.add_document(doc("a.pdf#0", vec![1.0, 0.0])).unwrap();
+        linear.add_document(doc("b.pdf#0", vec![0.0, 1.0])).unwrap();
+        linear.save().unwrap();
+        storage_path
+    }
+
+    /// A bare store standing in for `RagSystem` in a re-embed run
+    struct Reembedding {
+        store: RwLock<keyword::WithKeywords>,
+    }
+
+    impl Reembedding {
+        fn open(storage_path: &str) -> Self {
+            let linear = store::LinearVectorStore::new(storage_path, SimilarityMetric::Cosine).unwrap();
+            Self { store: RwLock::new(keyword::WithKeywords::open(Box::new(linear), storage_path)) }
+        }
+
+        /// Ids of the documents with the embedding `reembed` gives
+        fn reembedded(&self) -> HashSet<String> {
+            let mut ids = HashSet::new();
+            self.store.read().unwrap().for_each_document(&mut |doc| {
+                if doc.embedding == [0.0, 1.0] {
+                    ids.insert(doc.id.clone());
+                }
+                ControlFlow::Continue(())
+            });
+            ids
         }
-        snippet = format!("...{}", snippet);
     }
-    
-    if end < content.len() {
-        if let Some(pos) = snippet.rfind(' ') {
-            snippet = snippet[..pos].to_string();
+
+    impl reembed::Index for Reembedding {
+        fn store(&self) -> &RwLock<keyword::WithKeywords> {
+            &self.store
+        }
+
+        fn changed(&self) {}
+
+        fn save(&self) -> anyhow::Result<()> {
+            self.store.read().unwrap().save()
         }
-        snippet = format!("{}...", snippet);
     }
-    
-    snippet.trim().to_string()
+
+    /// Re-embed `index`, recording the texts embedded, and fail the `fail_at`th batch
+    async fn reembed(index: &Reembedding, checkpoint_path: &Path, fail_at: Option<usize>, embedded: &mut Vec<String>, resumed: &mut usize) -> anyhow::Result<usize> {
+        let mut batches = 0;
+        let progress = |progress: &reembed::Progress| *resumed = progress.resumed;
+        reembed::run(index, checkpoint_path, "model", &HashSet::new(), progress, |texts| {
+            batches += 1;
+            let fail = fail_at == Some(batches);
+            if !fail {
+                embedded.extend(texts.iter().cloned());
+            }
+            async move {
+                anyhow::ensure!(!fail, "interrupted");
+                Ok(texts.iter().map(|_| Some(vec![0.0, 1.0])).collect())
+            }
+        }).await
+    }
+
+    #[tokio::test]
+    async fn an_interrupted_reembed_resumes_from_its_checkpoint() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage_path = dir.path().join("index.bin").to_string_lossy().to_string();
+        let mut linear = store::LinearVectorStore::new(&storage_path, SimilarityMetric::Cosine).unwrap();
+        for i in 0..300 {
+            linear.add_document(doc(&format!("tema{:03}.pdf#0", i), vec![1.0, 0.0])).unwrap();
+        }
+        linear.save().unwrap();
+        drop(linear);
+        let checkpoint_path = reembed::Checkpoint::path(Path::new(&storage_path));
+
+        // Stopped two batches after the first checkpoint
+        let index = Reembedding::open(&storage_path);
+        let (mut first, mut resumed) = (Vec::new(), 0);
+        let error = reembed(&index, &checkpoint_path, Some(reembed::CHECKPOINT_EVERY / reembed::BATCH_SIZE + 2), &mut first, &mut resumed).await.unwrap_err();
+        assert_eq!(error.to_string(), "interrupted");
+        assert_eq!(first.len(), reembed::CHECKPOINT_EVERY + reembed::BATCH_SIZE);
+        drop(index);
+
+        // Only what was saved with the checkpoint survives, and the checkpoint lists exactly that
+        let index = Reembedding::open(&storage_path);
+        let saved = index.reembedded();
+        assert_eq!(saved.len(), reembed::CHECKPOINT_EVERY);
+        assert_eq!(reembed::Checkpoint::load(&checkpoint_path, "model").done, saved);
+        assert!(reembed::Checkpoint::load(&checkpoint_path, "another model").done.is_empty());
+
+        let (mut second, mut resumed) = (Vec::new(), 0);
+        assert_eq!(reembed(&index, &checkpoint_path, None, &mut second, &mut resumed).await.unwrap(), 300);
+        assert_eq!(resumed, reembed::CHECKPOINT_EVERY);
+        assert_eq!(second.len(), 300 - reembed::CHECKPOINT_EVERY);
+        assert!(saved.iter().all(|id| !second.contains(&format!("Contents of {}", id))));
+        // The batch embedded after the checkpoint was lost with the crash, so it runs again
+        assert!(first[reembed::CHECKPOINT_EVERY..].iter().all(|text| second.contains(text)));
+        assert_eq!(index.reembedded().len(), 300);
+    }
+
+    #[test]
+    fn search_filter_requires_every_condition() {
+        let mut pdf = doc("GRA_1/resources/tema1.pdf#3", vec![1.0]);
+        pdf.metadata.insert("type".to_string(), "pdf".to_string());
+        let summary = doc("GRA_1#0", vec![1.0]);
+
+        assert!(SearchFilter::default().is_empty());
+        let subject = SearchFilter { subject: Some("GRA_1".to_string()), ..Default::default() };
+        assert!(subject.matches(&pdf) && subject.matches(&summary));
+        assert!(!SearchFilter { subject: Some("GRA_11".to_string()), ..Default::default() }.matches(&pdf));
+
+        let pdfs = SearchFilter { metadata: HashMap::from([("type".to_string(), "pdf".to_string())]), ..subject.clone() };
+        assert!(pdfs.matches(&pdf) && !pdfs.matches(&summary));
+        let other_folder = SearchFilter { id_prefix: Some("GRA_1/announcements/".to_string()), ..pdfs };
+        assert!(!other_folder.matches(&pdf));
+    }
+
+    #[test]
+    fn sqlite_imports_the_previous_index_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage_path = linear_index(dir.path());
+
+        let mut store = RagSystem::open_sqlite(&storage_path, &RagConfig::default()).unwrap();
+        assert_eq!(store.count(), 2);
+        store.remove_document("a.pdf#0").unwrap();
+        store.save().unwrap();
+        drop(store);
+
+        let store = RagSystem::open_sqlite(&storage_path, &RagConfig::default()).unwrap();
+        assert_eq!(store.count(), 1);
+    }
+
+    #[test]
+    fn sqlite_import_runs_again_after_an_interrupted_one() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage_path = linear_index(dir.path());
+        // What a failed import left behind: the database, without documents or the record
+        drop(sqlite_store::SqliteVectorStore::new(&Path::new(&storage_path).with_extension("sqlite"), SimilarityMetric::Cosine).unwrap());
+
+        let store = RagSystem::open_sqlite(&storage_path, &RagConfig::default()).unwrap();
+        assert_eq!(store.count(), 2);
+        assert!(store.migrated().unwrap());
+    }
+
+    /// `doc` as it was indexed once, long ago: at second 1000 of the Unix epoch
+    fn indexed(mut doc: Document) -> Document {
+        stamp_indexed_at(&mut doc, None);
+        doc.metadata.insert(INDEXED_AT_KEY.to_string(), "1000".to_string());
+        doc
+    }
+
+    #[test]
+    fn a_family_is_swapped_whole_and_keeps_its_tags() {
+        let mut store = store::LinearVectorStore::in_memory(SimilarityMetric::Cosine);
+        for id in ["S1/tema1.pdf#0", "S1/tema1.pdf#1", "S1/tema1.pdf#2", "S1/tema10.pdf#0", "S1/tema1.pdf.bak#0"] {
+            store.add_document(indexed(doc(id, vec![1.0, 0.0]))).unwrap();
+        }
+        assert!(store.set_metadata("S1/tema1.pdf#2", TAGS_KEY, Some("exam".to_string())));
+
+        // The file now has two chunks, the second of them edited
+        let mut edited = doc("S1/tema1.pdf#1", vec![0.0, 1.0]);
+        edited.content = "Edited contents".to_string();
+        swap_family(&mut store, "S1/tema1.pdf", vec![doc("S1/tema1.pdf#0", vec![1.0, 0.0]), edited]).unwrap();
+
+        let mut ids: Vec<String> = store.get_all().unwrap().into_iter().map(|d| d.id).collect();
+        ids.sort();
+        // Files whose name only starts like it are left alone
+        assert_eq!(ids, ["S1/tema1.pdf#0", "S1/tema1.pdf#1", "S1/tema1.pdf.bak#0", "S1/tema10.pdf#0"]);
+        let (first, second) = (store.get_document("S1/tema1.pdf#0").unwrap(), store.get_document("S1/tema1.pdf#1").unwrap());
+        assert_eq!(tags(&first), ["exam"]);
+        assert_eq!(tags(&second), ["exam"]);
+        assert!(tags(&store.get_document("S1/tema10.pdf#0").unwrap()).is_empty());
+        assert_eq!(indexed_at(&first), Some(1000));
+        assert_ne!(indexed_at(&second), Some(1000));
+        assert_eq!(second.embedding, [0.0, 1.0]);
+    }
+
+    #[test]
+    fn a_moved_family_keeps_its_embeddings_and_replaces_the_target() {
+        let mut store = store::LinearVectorStore::in_memory(SimilarityMetric::Cosine);
+        for (id, embedding) in [("S1/tema3.pdf#0", vec![1.0, 0.0]), ("S1/tema3.pdf#1", vec![0.0, 1.0]), ("S1/tema3_v2.pdf#0", vec![0.5, 0.5]), ("S1/tema3_v2.pdf#1", vec![0.5, 0.5]), ("S1/tema3_v2.pdf#2", vec![0.5, 0.5])] {
+            store.add_document(doc(id, embedding)).unwrap();
+        }
+
+        assert_eq!(move_family(&mut store, "S1/tema3.pdf", "S1/tema3_v2.pdf").unwrap(), 2);
+        let mut ids: Vec<String> = store.get_all().unwrap().into_iter().map(|d| d.id).collect();
+        ids.sort();
+        assert_eq!(ids, ["S1/tema3_v2.pdf#0", "S1/tema3_v2.pdf#1"]);
+        assert_eq!(store.get_document("S1/tema3_v2.pdf#1").unwrap().embedding, [0.0, 1.0]);
+        assert_eq!(move_family(&mut store, "S1/missing.pdf", "S1/tema3_v2.pdf").unwrap(), 0);
+        assert_eq!(store.count(), 0);
+    }
+
+    fn stats(retrieval: RetrievalStats) -> RagStats {
+        RagStats {
+            document_count: 2,
+            docs_by_type: HashMap::new(),
+            total_content_bytes: 2048,
+            embedding_dimensions: 384,
+            file_size_bytes: 4096,
+            storage_path: "(in memory)".to_string(),
+            store_type: "Linear Scan (Exact, cosine)".to_string(),
+            chunk_chars: CHUNK_CHARS,
+            chunk_overlap: CHUNK_OVERLAP,
+            embedding_backend: embeddings::BACKEND,
+            embedding_model: "multilingual-e5-small".to_string(),
+            model_dimensions: Some(384),
+            embedding_window_chars: 1024,
+            retrieval,
+            index_params: Vec::new(),
+            indexed_range: None,
+            docs_by_subject: HashMap::new(),
+            embedding_value_bytes: 4,
+            precision_file_bytes: 4096,
+        }
+    }
+
+    fn row(rows: &[(&'static str, String)], label: &str) -> String {
+        rows.iter().find(|(l, _)| *l == label).map(|(_, value)| value.clone()).unwrap_or_else(|| panic!("no {} row", label))
+    }
+
+    #[test]
+    fn the_pipeline_rows_describe_the_configured_retrieval() {
+        let mut retrieval = RetrievalStats::from_config(&RagConfig::default());
+        (retrieval.top_k, retrieval.adaptive_k, retrieval.adaptive_min, retrieval.full_files) = (8, true, 3, 1);
+        retrieval.relevance = RelevanceParams { floor: 0.3, delta: 0.1 };
+        retrieval.hybrid.keyword_weight = 0.0;
+        retrieval.features = vec![("freshness hints", true), ("subject affinity", false)];
+        let rows = stats(retrieval).pipeline();
+
+        let labels: Vec<&str> = rows.iter().map(|(label, _)| *label).collect();
+        assert_eq!(labels, ["Store Type", "Chunking", "Embeddings", "Embed Window", "Stored As", "Similarity", "Retrieval", "Keywords", "Threshold", "Context", "Features"]);
+        assert_eq!(row(&rows, "Chunking"), "TextSplitter, up to 1000 chars, no overlap");
+        assert_eq!(row(&rows, "Embeddings"), "llama.cpp · multilingual-e5-small · 384 dimensions");
+        assert_eq!(row(&rows, "Retrieval"), "3-8 snippets (adaptive), 1 whole files");
+        assert_eq!(row(&rows, "Keywords"), "off, vector search only");
+        assert_eq!(row(&rows, "Threshold"), "≥ 0.30, within 0.10 of the best match");
+        assert_eq!(row(&rows, "Features"), "✓ freshness hints  ✗ subject affinity");
+    }
+
+    #[test]
+    fn the_pipeline_rows_point_out_a_model_the_index_was_not_built_with() {
+        let mut retrieval = RetrievalStats::from_config(&RagConfig::default());
+        (retrieval.top_k, retrieval.adaptive_k) = (0, false);
+        retrieval.relevance.floor = 0.0;
+        let mut stats = stats(retrieval);
+        stats.model_dimensions = Some(768);
+        assert_eq!(row(&stats.pipeline(), "Embeddings"), "llama.cpp · multilingual-e5-small · 768 dimensions (index holds 384)");
+        assert_eq!(row(&stats.pipeline(), "Retrieval"), format!("unlimited snippets, {} whole files", context::FULL_FILES));
+        assert_eq!(row(&stats.pipeline(), "Threshold"), "off, every match up to the limit");
+
+        stats.model_dimensions = None;
+        assert_eq!(row(&stats.pipeline(), "Embeddings"), "llama.cpp · multilingual-e5-small · self-test failed, index holds 384 dimensions");
+    }
+
+    #[test]
+    fn only_unchanged_content_reuses_its_embedding() {
+        let prev = indexed(doc("tema1.pdf#0", vec![1.0, 0.0]));
+
+        assert!(reusable(&prev, "Contents of tema1.pdf#0", "user", Some(2)));
+        assert!(reusable(&prev, "Contents of tema1.pdf#0", "user", None));
+        assert!(!reusable(&prev, "Edited contents of tema1.pdf#0", "user", Some(2)));
+        assert!(!reusable(&prev, "Contents of tema1.pdf#0", "other", Some(2)));
+        // Embedded by a model of other dimensions
+        assert!(!reusable(&prev, "Contents of tema1.pdf#0", "user", Some(3)));
+    }
+
+    #[test]
+    fn documents_with_the_old_hash_are_compared_by_content() {
+        let mut prev = doc("tema1.pdf#0", vec![1.0, 0.0]);
+        prev.metadata.insert(CONTENT_HASH_KEY.to_string(), "00ff00ff00ff00ff".to_string());
+
+        assert!(reusable(&prev, "Contents of tema1.pdf#0", "user", None));
+        assert!(!reusable(&prev, "Edited contents of tema1.pdf#0", "user", None));
+    }
+
+    #[test]
+    fn indexed_at_survives_only_unchanged_content() {
+        let prev = indexed(doc("tema1.pdf#0", vec![1.0, 0.0]));
+        assert_eq!(prev.metadata.get(CONTENT_HASH_KEY), Some(&content_hash("Contents of tema1.pdf#0")));
+
+        let mut unchanged = doc("tema1.pdf#0", vec![1.0, 0.0]);
+        stamp_indexed_at(&mut unchanged, Some(&prev));
+        assert_eq!(indexed_at(&unchanged), Some(1000));
+
+        let mut changed = doc("tema1.pdf#0", vec![1.0, 0.0]);
+        changed.content = "Edited contents of tema1.pdf#0".to_string();
+        stamp_indexed_at(&mut changed, Some(&prev));
+        assert_ne!(indexed_at(&changed), Some(1000));
+
+        let mut new = doc("tema2.pdf#0", vec![1.0, 0.0]);
+        stamp_indexed_at(&mut new, None);
+        assert!(indexed_at(&new).is_some_and(|at| at > 1000));
+    }
+
+    #[test]
+    fn unchanged_metadata_needs_no_write() {
+        let mut prev = indexed(doc("GRA_1", vec![1.0, 0.0]));
+        prev.metadata.insert("type".to_string(), "subject".to_string());
+        prev.metadata.insert("name".to_string(), "Algebra".to_string());
+
+        let meta = |name: &str| HashMap::from([("type".to_string(), "subject".to_string()), ("name".to_string(), name.to_string())]);
+        // Keys the index adds itself, like the hash and timestamp, don't count as changes
+        assert!(same_metadata(&prev, &meta("Algebra")));
+        assert!(!same_metadata(&prev, &meta("Linear Algebra")));
+    }
 }
@@ -0,0 +1,112 @@
+//! Text pasted into the chat with `/paste`: embedded into a store that lives in memory next
+//! to the index and is searched with it, but never saved. It is gone when the chat is
+//! cleared or polirag exits.
+
+use std::collections::HashMap;
+
+use anyhow::Result;
+use text_splitter::TextSplitter;
+
+use crate::config::SimilarityMetric;
+
+use super::store::{LinearVectorStore, VectorStore};
+use super::Document;
+
+/// `type` metadata of pasted chunks
+pub const PASTED_TYPE: &str = "pasted";
+/// Added to the score of pasted chunks: they were pasted to be asked about
+pub const PASTED_BOOST: f32 = 0.05;
+/// Ids are `pasted/<n>#<chunk>`, so `subjects::subject_of` gives "pasted"
+const ID_PREFIX: &str = "pasted/";
+
+/// Whether `id` is a pasted chunk or paste
+pub fn is_pasted(id: &str) -> bool {
+    id.starts_with(ID_PREFIX)
+}
+
+/// The pasted texts of the session
+pub struct Pasted {
+    store: LinearVectorStore,
+    metric: SimilarityMetric,
+    /// Texts pasted since the last `clear`, numbering the next one
+    pastes: usize,
+}
+
+impl Pasted {
+    pub fn new(metric: SimilarityMetric) -> Self {
+        Self { store: LinearVectorStore::in_memory(metric), metric, pastes: 0 }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.store.count() == 0
+    }
+
+    pub fn pastes(&self) -> usize {
+        self.pastes
+    }
+
+    /// Add the chunks of one paste with their embeddings. Returns the source label its
+    /// snippets get: "[pasted]", then "[pasted 2]" and so on.
+    pub fn add(&mut self, chunks: Vec<String>, embeddings: Vec<Vec<f32>>, user_id: &str) -> Result<String> {
+        self.pastes += 1;
+        let label = match self.pastes {
+            1 => "[pasted]".to_string(),
+            n => format!("[pasted {}]", n),
+        };
+        for (i, (content, embedding)) in chunks.into_iter().zip(embeddings).enumerate() {
+            let metadata = HashMap::from([
+                ("type".to_string(), PASTED_TYPE.to_string()),
+                ("filename".to_string(), label.clone()),
+            ]);
+            let id = format!("{}{}#{}", ID_PREFIX, self.pastes, i);
+            self.store.add_document(Document { id, content, embedding, metadata, user_id: user_id.to_string() })?;
+        }
+        Ok(label)
+    }
+
+    /// Drop every paste, returning how many there were
+    pub fn clear(&mut self) -> usize {
+        let pastes = std::mem::take(&mut self.pastes);
+        self.store = LinearVectorStore::in_memory(self.metric);
+        pastes
+    }
+
+    /// The `top_k` pasted chunks closest to `query_embedding`, scores boosted by `PASTED_BOOST`
+    pub fn search(&self, query_embedding: &[f32], user_id: &str, top_k: usize) -> Result<Vec<(Document, f32)>> {
+        let mut found = self.store.search(query_embedding, user_id, top_k, 0.0, None)?;
+        for (_, score) in found.iter_mut() {
+            *score += PASTED_BOOST;
+        }
+        Ok(found)
+    }
+
+    /// Number of chunks of the paste `base_id`
+    pub fn family_len(&self, base_id: &str) -> usize {
+        self.store.family_ids(base_id).len()
+    }
+}
+
+/// `text` split like a synced file, without headers
+pub fn split(text: &str) -> Vec<String> {
+    TextSplitter::new(super::CHUNK_CHARS)
+        .chunks(text.trim())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Index and pasted results as one list, best first and at most `limit` long. A chunk found
+/// twice, e.g. a paragraph pasted from an indexed file, keeps its best score.
+pub fn merge(index: Vec<(Document, f32)>, pasted: Vec<(Document, f32)>, limit: usize) -> Vec<(Document, f32)> {
+    let body = |doc: &Document| doc.content[super::sentences::body_start(&doc.content)..].trim().to_string();
+    let mut merged: Vec<(Document, f32, String)> = Vec::with_capacity(index.len() + pasted.len());
+    for (doc, score) in index.into_iter().chain(pasted) {
+        let text = body(&doc);
+        match merged.iter_mut().find(|(kept, _, kept_text)| kept.id == doc.id || *kept_text == text) {
+            Some(kept) if kept.1 < score => *kept = (doc, score, text),
+            Some(_) => {}
+            None => merged.push((doc, score, text)),
+        }
+    }
+    merged.sort_by(|a, b| b.1.total_cmp(&a.1));
+    merged.into_iter().take(limit).map(|(doc, score, _)| (doc, score)).collect()
+}
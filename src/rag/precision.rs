@@ -0,0 +1,199 @@
+//! Half-precision storage of embeddings. With `rag.half_precision`, index files keep each
+//! embedding as f16, half the bytes of f32. Documents stay f32 in memory and searches score
+//! them as before; only the stored values are rounded, to about three significant digits.
+
+use half::f16;
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashMap;
+
+use super::Document;
+
+/// How a store writes embeddings to its files. Each store carries its own, set from the
+/// config when it is opened; files are read whatever they hold.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Precision {
+    #[default]
+    F32,
+    F16,
+}
+
+impl Precision {
+    /// The precision `rag.half_precision` asks for
+    pub fn from_half(half: bool) -> Self {
+        if half { Precision::F16 } else { Precision::F32 }
+    }
+
+    pub fn is_half(self) -> bool {
+        self == Precision::F16
+    }
+
+    /// Bytes each embedding value takes in the index files
+    pub fn value_bytes(self) -> usize {
+        if self.is_half() { 2 } else { 4 }
+    }
+}
+
+/// An embedding in an index file. Both are read whatever the setting, so switching it only
+/// changes how the next save writes them.
+#[derive(Serialize)]
+enum Stored<'a> {
+    F32(&'a [f32]),
+    /// IEEE 754 half-precision bits
+    F16(Vec<u16>),
+}
+
+impl<'a> Stored<'a> {
+    fn new(embedding: &'a [f32], precision: Precision) -> Self {
+        match precision {
+            Precision::F32 => Stored::F32(embedding),
+            Precision::F16 => Stored::F16(embedding.iter().map(|&x| f16::from_f32(x).to_bits()).collect()),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+enum Loaded {
+    F32(Vec<f32>),
+    F16(Vec<u16>),
+}
+
+/// `serde(with)` for `Document::embedding`. Binary formats get the tagged `Stored` form, f32
+/// unless written through `Encoded`; human-readable ones (the JSONL archives) keep a plain
+/// f32 array.
+pub fn serialize<S: Serializer>(embedding: &[f32], serializer: S) -> Result<S::Ok, S::Error> {
+    if serializer.is_human_readable() {
+        return embedding.serialize(serializer);
+    }
+    Stored::new(embedding, Precision::F32).serialize(serializer)
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<f32>, D::Error> {
+    if deserializer.is_human_readable() {
+        return Vec::deserialize(deserializer);
+    }
+    Ok(match Loaded::deserialize(deserializer)? {
+        Loaded::F32(values) => values,
+        Loaded::F16(bits) => bits.into_iter().map(|b| f16::from_bits(b).to_f32()).collect(),
+    })
+}
+
+/// Documents written with their embeddings at a precision, for the payloads of index files.
+/// Reads back as the plain collection of `Document`s.
+pub struct Encoded<'a, T: ?Sized>(pub &'a T, pub Precision);
+
+/// A document laid out as `Document` serializes, its embedding at `precision`
+struct StoredDocument<'a>(&'a Document, Precision);
+
+impl Serialize for StoredDocument<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let StoredDocument(doc, precision) = *self;
+        if serializer.is_human_readable() {
+            return doc.serialize(serializer);
+        }
+        let mut state = serializer.serialize_struct("Document", 5)?;
+        state.serialize_field("id", &doc.id)?;
+        state.serialize_field("content", &doc.content)?;
+        state.serialize_field("embedding", &Stored::new(&doc.embedding, precision))?;
+        state.serialize_field("metadata", &doc.metadata)?;
+        state.serialize_field("user_id", &doc.user_id)?;
+        state.end()
+    }
+}
+
+impl Serialize for Encoded<'_, [Document]> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.0.iter().map(|doc| StoredDocument(doc, self.1)))
+    }
+}
+
+impl<K: Serialize> Serialize for Encoded<'_, HashMap<K, Document>> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.0.iter().map(|(key, doc)| (key, StoredDocument(doc, self.1))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SimilarityMetric;
+    use crate::rag::store::{LinearVectorStore, VectorStore};
+    use std::collections::HashSet;
+
+    /// Unit vectors from a fixed seed, so every run scores the same corpus
+    fn embeddings(count: usize, dimensions: usize, mut seed: u64) -> Vec<Vec<f32>> {
+        let mut next = move || {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            ((seed >> 33) as f32 / (1u64 << 31) as f32) * 2.0 - 1.0
+        };
+        (0..count)
+            .map(|_| {
+                let v: Vec<f32> = (0..dimensions).map(|_| next()).collect();
+                let norm = v.iter().map(|x| x * x).sum::<f32>().sqrt();
+                v.into_iter().map(|x| x / norm).collect()
+            })
+            .collect()
+    }
+
+    fn doc(id: String, embedding: Vec<f32>) -> Document {
+        Document { id, content: String::new(), embedding, metadata: HashMap::new(), user_id: "user".to_string() }
+    }
+
+    /// A linear index at `path` holding `corpus`, saved at `precision` and opened again
+    fn saved(path: &std::path::Path, corpus: &[Vec<f32>], precision: Precision) -> LinearVectorStore {
+        let path = path.to_string_lossy().to_string();
+        let mut store = LinearVectorStore::new(&path, SimilarityMetric::Cosine).unwrap().with_precision(precision);
+        for (i, embedding) in corpus.iter().enumerate() {
+            store.add_document(doc(format!("doc{}", i), embedding.clone())).unwrap();
+        }
+        store.save().unwrap();
+        LinearVectorStore::new(&path, SimilarityMetric::Cosine).unwrap()
+    }
+
+    #[test]
+    fn f32_encoding_matches_the_document_layout() {
+        let docs = vec![doc("a#0".to_string(), vec![0.25, -1.0])];
+        let encoded = bincode::serialize(&Encoded(&docs[..], Precision::F32)).unwrap();
+        assert_eq!(encoded, bincode::serialize(&docs).unwrap());
+    }
+
+    #[test]
+    fn f16_embeddings_read_back_rounded() {
+        let docs = [doc("a#0".to_string(), vec![0.1234, -0.5])];
+        let encoded = bincode::serialize(&Encoded(&docs[..], Precision::F16)).unwrap();
+        let read: Vec<Document> = bincode::deserialize(&encoded).unwrap();
+        assert_eq!(read[0].id, "a#0");
+        assert!((read[0].embedding[0] - 0.1234).abs() < 1e-3);
+        assert_eq!(read[0].embedding[1], -0.5);
+    }
+
+    #[test]
+    fn stores_keep_their_own_precision() {
+        let dir = tempfile::tempdir().unwrap();
+        let corpus = embeddings(50, 64, 7);
+        saved(&dir.path().join("half.bin"), &corpus, Precision::F16);
+        saved(&dir.path().join("full.bin"), &corpus, Precision::F32);
+        let size = |name: &str| std::fs::metadata(dir.path().join(name)).unwrap().len();
+        // 64 values of 2 bytes instead of 4 per document
+        assert!(size("full.bin") - size("half.bin") >= 50 * 64 * 2);
+    }
+
+    #[test]
+    fn f16_storage_keeps_top_k_recall() {
+        let dir = tempfile::tempdir().unwrap();
+        let corpus = embeddings(500, 64, 42);
+        let queries = embeddings(25, 64, 4242);
+        let mut exact = LinearVectorStore::in_memory(SimilarityMetric::Cosine);
+        for (i, embedding) in corpus.iter().enumerate() {
+            exact.add_document(doc(format!("doc{}", i), embedding.clone())).unwrap();
+        }
+        let half = saved(&dir.path().join("index.bin"), &corpus, Precision::F16);
+
+        let top = |store: &LinearVectorStore, query: &[f32]| -> HashSet<String> {
+            store.search(query, "user", 10, -1.0, None).unwrap().into_iter().map(|(d, _)| d.id).collect()
+        };
+        let found: usize = queries.iter().map(|q| top(&half, q).intersection(&top(&exact, q)).count()).sum();
+        let recall = found as f32 / (queries.len() * 10) as f32;
+        assert!(recall >= 0.95, "recall@10 of f16 storage was {}", recall);
+    }
+}
@@ -0,0 +1,193 @@
+//! The batches of `RagSystem::reembed_all` and their checkpoints, so an interrupted run resumes
+//! where it stopped instead of embedding the whole index again.
+
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use super::keyword::WithKeywords;
+use super::store::VectorStore;
+use super::Document;
+
+/// Documents embedded per `embed_batch` call
+pub const BATCH_SIZE: usize = 16;
+/// Documents re-embedded between saves of the index and the checkpoint
+pub const CHECKPOINT_EVERY: usize = 128;
+
+/// Where a re-embed run stands, passed to the progress callback before each batch
+pub struct Progress<'a> {
+    /// Documents with a current embedding, including `resumed` and skipped ones
+    pub done: usize,
+    pub total: usize,
+    /// 1-based batch about to be embedded, out of `batches`
+    pub batch: usize,
+    pub batches: usize,
+    /// Documents an interrupted earlier run had already re-embedded
+    pub resumed: usize,
+    /// First document of the batch
+    pub id: &'a str,
+    pub metadata: &'a HashMap<String, String>,
+}
+
+/// Ids re-embedded so far by a run that hasn't finished, and the embedder it used
+#[derive(Serialize, Deserialize, Default)]
+pub struct Checkpoint {
+    embedder: String,
+    pub done: HashSet<String>,
+}
+
+impl Checkpoint {
+    /// The checkpoint next to the index at `index_path`
+    pub fn path(index_path: &Path) -> PathBuf {
+        index_path.with_extension("reembed.json")
+    }
+
+    /// The checkpoint left at `path` by an interrupted run with the same `embedder`, or an
+    /// empty one. Ids embedded by another model have to be embedded again, so those are dropped.
+    pub fn load(path: &Path, embedder: &str) -> Self {
+        let fresh = Self { embedder: embedder.to_string(), done: HashSet::new() };
+        let Ok(text) = std::fs::read_to_string(path) else { return fresh };
+        match serde_json::from_str::<Self>(&text) {
+            Ok(checkpoint) if checkpoint.embedder == embedder => {
+                tracing::info!("Resuming re-embedding: {} documents already done", checkpoint.done.len());
+                checkpoint
+            }
+            Ok(checkpoint) => {
+                tracing::warn!("Discarding re-embed checkpoint from embedder {} (now {})", checkpoint.embedder, embedder);
+                fresh
+            }
+            Err(e) => {
+                tracing::warn!("Ignoring unreadable re-embed checkpoint {:?}: {}", path, e);
+                fresh
+            }
+        }
+    }
+
+    /// Write to a temporary file first so a crash mid-write leaves the previous checkpoint
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let temp = path.with_extension("json.tmp");
+        std::fs::write(&temp, serde_json::to_vec(self)?).with_context(|| format!("Failed to write {:?}", temp))?;
+        std::fs::rename(&temp, path).with_context(|| format!("Failed to write {:?}", path))?;
+        Ok(())
+    }
+
+    /// The run finished, nothing left to resume
+    pub fn remove(path: &Path) {
+        if let Err(e) = std::fs::remove_file(path) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                tracing::warn!("Could not remove re-embed checkpoint {:?}: {}", path, e);
+            }
+        }
+    }
+}
+
+/// The index a run re-embeds, `RagSystem` outside the tests
+pub(crate) trait Index {
+    fn store(&self) -> &RwLock<WithKeywords>;
+    /// Called after each batch that replaced embeddings
+    fn changed(&self);
+    fn save(&self) -> anyhow::Result<()>;
+}
+
+/// Re-embed every document of `index` but `skip_ids` and those `checkpoint_path` lists as done
+/// by `embedder`, `BATCH_SIZE` at a time. `embed` gets the texts of a batch and returns their
+/// embeddings, `None` for a document it could not embed, which keeps its old one. Every
+/// `CHECKPOINT_EVERY` documents the index is saved, then the checkpoint. An error ends the run
+/// and leaves the last checkpoint for the next one; compacting the index, the final save and
+/// removing the checkpoint are left to the caller. Returns the documents with a current
+/// embedding.
+pub(crate) async fn run<I, E, Fut>(
+    index: &I,
+    checkpoint_path: &Path,
+    embedder: &str,
+    skip_ids: &HashSet<String>,
+    mut progress_fn: impl FnMut(&Progress),
+    mut embed: E,
+) -> anyhow::Result<usize>
+where
+    I: Index,
+    E: FnMut(Vec<String>) -> Fut,
+    Fut: Future<Output = anyhow::Result<Vec<Option<Vec<f32>>>>>,
+{
+    let mut checkpoint = Checkpoint::load(checkpoint_path, embedder);
+
+    // Only the ids are collected up front; each batch's documents are cloned when it is embedded
+    let mut total = 0;
+    let mut resumed = 0;
+    let mut pending: Vec<String> = Vec::new();
+    index.store().read().unwrap().for_each_document(&mut |doc| {
+        total += 1;
+        if checkpoint.done.contains(&doc.id) {
+            resumed += 1;
+        } else if !skip_ids.contains(&doc.id) {
+            pending.push(doc.id.clone());
+        }
+        ControlFlow::Continue(())
+    });
+    let mut reembedded = total - pending.len();
+    let batches = pending.len().div_ceil(BATCH_SIZE);
+    let mut since_checkpoint = 0;
+
+    for (i, batch_ids) in pending.chunks(BATCH_SIZE).enumerate() {
+        let batch = documents_by_id(index.store(), batch_ids);
+        if batch.is_empty() {
+            continue;
+        }
+        progress_fn(&Progress {
+            done: reembedded,
+            total,
+            batch: i + 1,
+            batches,
+            resumed,
+            id: &batch[0].id,
+            metadata: &batch[0].metadata,
+        });
+
+        let embeddings = embed(batch.iter().map(|d| d.content.clone()).collect()).await?;
+        {
+            let mut store = index.store().write().unwrap();
+            for (old_doc, embedding) in batch.iter().zip(embeddings) {
+                let Some(embedding) = embedding else {
+                    tracing::error!("{} keeps its previous embedding", old_doc.id);
+                    continue;
+                };
+                let mut doc = old_doc.clone();
+                doc.embedding = embedding;
+                store.add_document(doc)?;
+                checkpoint.done.insert(old_doc.id.clone());
+                reembedded += 1;
+            }
+        }
+        index.changed();
+
+        since_checkpoint += batch.len();
+        if since_checkpoint >= CHECKPOINT_EVERY {
+            since_checkpoint = 0;
+            // Index first: the checkpoint must never list ids whose embedding isn't on disk
+            index.save()?;
+            checkpoint.save(checkpoint_path)?;
+        }
+    }
+    Ok(reembedded)
+}
+
+/// The documents of `ids` still in `store`, from one pass over it
+fn documents_by_id(store: &RwLock<WithKeywords>, ids: &[String]) -> Vec<Document> {
+    let wanted: HashSet<&str> = ids.iter().map(|id| id.as_str()).collect();
+    let mut docs = Vec::with_capacity(ids.len());
+    store.read().unwrap().for_each_document(&mut |doc| {
+        if wanted.contains(doc.id.as_str()) {
+            docs.push(doc.clone());
+            if docs.len() == wanted.len() {
+                return ControlFlow::Break(());
+            }
+        }
+        ControlFlow::Continue(())
+    });
+    docs
+}
@@ -0,0 +1,117 @@
+//! Score cutoff for retrieved snippets, relative to the best match instead of a fixed threshold.
+//! Short questions score lower across the board, so a fixed threshold either lets every weak
+//! match through or drops the right one.
+
+use crate::config::RelevanceParams;
+
+/// Which rule decided what was kept, for the logs and the prompt
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Cutoff {
+    /// Nothing was retrieved
+    Empty,
+    /// Kept everything within `delta` of the best score, which is above the floor
+    Relative(f32),
+    /// The best score is close to the floor, so the floor itself was the cutoff
+    Floor(f32),
+    /// Nothing cleared the floor; only the best match was kept
+    Fallback,
+    /// The floor is 0: everything up to `top_k` was kept
+    Off,
+}
+
+impl Cutoff {
+    /// The kept results are weak and the answer should say so
+    pub fn low_confidence(&self) -> bool {
+        *self == Cutoff::Fallback
+    }
+}
+
+/// Keep the results of `scored` (best first) that are relevant enough, at most `top_k`.
+/// With the floor at 0 nothing is dropped for its score.
+pub fn select<T>(scored: Vec<(T, f32)>, params: RelevanceParams, top_k: usize) -> (Vec<(T, f32)>, Cutoff) {
+    let Some(best) = scored.first().map(|(_, score)| *score) else {
+        return (scored, Cutoff::Empty);
+    };
+    if !params.filters() {
+        let mut scored = scored;
+        scored.truncate(top_k);
+        return (scored, Cutoff::Off);
+    }
+
+    if best < params.floor {
+        let mut scored = scored;
+        scored.truncate(1);
+        return (scored, Cutoff::Fallback);
+    }
+
+    let relative = best - params.delta;
+    let cutoff = if relative > params.floor { Cutoff::Relative(relative) } else { Cutoff::Floor(params.floor) };
+    let threshold = relative.max(params.floor);
+    let kept = scored.into_iter()
+        .filter(|(_, score)| *score >= threshold)
+        .take(top_k)
+        .collect();
+    (kept, cutoff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PARAMS: RelevanceParams = RelevanceParams { floor: 0.3, delta: 0.1 };
+
+    fn scored(scores: &[f32]) -> Vec<(usize, f32)> {
+        scores.iter().copied().enumerate().collect()
+    }
+
+    fn kept(selected: &[(usize, f32)]) -> Vec<usize> {
+        selected.iter().map(|(i, _)| *i).collect()
+    }
+
+    #[test]
+    fn nothing_retrieved_is_empty() {
+        let (selected, cutoff) = select(Vec::<(usize, f32)>::new(), PARAMS, 5);
+        assert!(selected.is_empty());
+        assert_eq!(cutoff, Cutoff::Empty);
+        assert!(!cutoff.low_confidence());
+    }
+
+    #[test]
+    fn a_strong_best_match_drops_what_falls_too_far_behind_it() {
+        let (selected, cutoff) = select(scored(&[0.9, 0.85, 0.82, 0.6, 0.5]), PARAMS, 5);
+        // 0.6 clears the floor but is more than `delta` below the best
+        assert_eq!(kept(&selected), [0, 1, 2]);
+        assert!(matches!(cutoff, Cutoff::Relative(at) if (at - 0.8).abs() < 1e-6));
+
+        let (selected, _) = select(scored(&[0.9, 0.85, 0.82]), PARAMS, 2);
+        assert_eq!(kept(&selected), [0, 1]);
+    }
+
+    #[test]
+    fn a_best_match_near_the_floor_cuts_at_the_floor() {
+        let (selected, cutoff) = select(scored(&[0.35, 0.32, 0.3, 0.25]), PARAMS, 5);
+        // 0.25 is within `delta` of the best but below the floor; a score at the floor is kept
+        assert_eq!(kept(&selected), [0, 1, 2]);
+        assert_eq!(cutoff, Cutoff::Floor(0.3));
+        assert!(!cutoff.low_confidence());
+    }
+
+    #[test]
+    fn everything_below_the_floor_keeps_only_the_best_as_low_confidence() {
+        let (selected, cutoff) = select(scored(&[0.28, 0.27, 0.1]), PARAMS, 5);
+        assert_eq!(kept(&selected), [0]);
+        assert_eq!(cutoff, Cutoff::Fallback);
+        assert!(cutoff.low_confidence());
+    }
+
+    #[test]
+    fn a_zero_floor_keeps_everything_up_to_top_k() {
+        let off = RelevanceParams { floor: 0.0, delta: 0.1 };
+        let (selected, cutoff) = select(scored(&[0.9, 0.2, 0.01]), off, 5);
+        assert_eq!(kept(&selected), [0, 1, 2]);
+        assert_eq!(cutoff, Cutoff::Off);
+
+        let (selected, _) = select(scored(&[0.9, 0.2, 0.01]), off, 2);
+        assert_eq!(kept(&selected), [0, 1]);
+    }
+}
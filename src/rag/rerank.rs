@@ -0,0 +1,55 @@
+use anyhow::Result;
+use std::time::Duration;
+
+use super::Snippet;
+use crate::llm::{ChatMessage, LlmClient};
+
+/// Snippets retrieved for the model to reorder when reranking, before keeping `top_k`
+pub const RERANK_CANDIDATES: usize = 30;
+/// Characters of each snippet shown to the model; the start is enough to judge relevance
+const RERANK_SNIPPET_CHARS: usize = 500;
+const RERANK_MAX_TOKENS: usize = 200;
+const RERANK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Ask the chat model to order `snippets` by relevance to `query`. Snippets it leaves out keep
+/// their retrieval order after the ones it ranked.
+pub async fn rerank(llm: &LlmClient, query: &str, snippets: Vec<Snippet>) -> Result<Vec<Snippet>> {
+    if snippets.len() < 2 {
+        return Ok(snippets);
+    }
+
+    let listed: String = snippets.iter().enumerate()
+        .map(|(i, s)| format!("[{}] {}: {}\n\n", i + 1, s.label, s.text.chars().take(RERANK_SNIPPET_CHARS).collect::<String>().replace('\n', " ")))
+        .collect();
+    let prompt = vec![
+        ChatMessage::new("system", "You rank search results. Given a question and numbered passages, reply with the numbers of the passages that help answer it, most useful first, separated by commas. Reply with the numbers only."),
+        ChatMessage::new("user", format!("Question: {}\n\nPassages:\n{}", query, listed)),
+    ];
+
+    let (content, _) = tokio::time::timeout(RERANK_TIMEOUT, llm.chat_limited(&prompt, Some(RERANK_MAX_TOKENS)))
+        .await
+        .map_err(|_| anyhow::anyhow!("Reranking timed out"))??;
+
+    // Reasoning models prefix the ranking with their thinking
+    let reply = content.rsplit("</think>").next().unwrap_or("");
+    let order = parse_ranking(reply, snippets.len());
+    if order.is_empty() {
+        anyhow::bail!("No ranking in the reply: '{}'", reply.trim());
+    }
+
+    let mut slots: Vec<Option<Snippet>> = snippets.into_iter().map(Some).collect();
+    let mut ranked: Vec<Snippet> = order.iter().filter_map(|&i| slots[i].take()).collect();
+    ranked.extend(slots.into_iter().flatten());
+    Ok(ranked)
+}
+
+/// 0-based indices of the 1-based numbers in `reply`, in order, without repeats or numbers past `len`
+fn parse_ranking(reply: &str, len: usize) -> Vec<usize> {
+    let mut order = Vec::new();
+    for n in reply.split(|c: char| !c.is_ascii_digit()).filter_map(|n| n.parse::<usize>().ok()) {
+        if (1..=len).contains(&n) && !order.contains(&(n - 1)) {
+            order.push(n - 1);
+        }
+    }
+    order
+}
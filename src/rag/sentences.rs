@@ -0,0 +1,220 @@
+//! Sentence segmentation and the choice of the passage that best matches a query, shared by
+//! the snippets sent to the model (`extract_relevant_snippet`) and the verbatim excerpts of
+//! `/lookup`.
+
+use std::ops::Range;
+
+/// Lines the indexer puts at the top of a chunk, see `sync::chunk_family`
+const HEADER_PREFIXES: &[&str] = &["### DOC:", "Course:", "Subject:", "URL:", "Last modified:"];
+/// Words that end with a period without ending the sentence (Spanish, Catalan, English), lower case
+const ABBREVIATIONS: &[&str] = &[
+    "dr", "dra", "sr", "sra", "srta", "prof", "profa", "dña", "etc", "p.ej", "e.g", "i.e", "vs", "aprox", "approx",
+    "pág", "pag", "pp", "núm", "num", "nº", "art", "cap", "fig", "vol", "ej", "tel", "telf", "av", "avda", "pl",
+    "mr", "mrs", "ms", "st", "dept", "depto", "ed", "máx", "mín", "ext",
+];
+/// Query words shorter than this match nearly every sentence ("de", "el", "is")
+const MIN_TERM_CHARS: usize = 3;
+/// A line break ends a sentence after a line this short: headings and "Email: ..." style fields.
+/// Longer lines are usually text the PDF extraction wrapped.
+const SHORT_LINE_CHARS: usize = 60;
+
+/// Where the text of a chunk starts, after the header lines of the indexer
+pub fn body_start(content: &str) -> usize {
+    let mut start = 0;
+    for line in content.split_inclusive('\n') {
+        let trimmed = line.trim();
+        if !trimmed.is_empty() && !HEADER_PREFIXES.iter().any(|p| trimmed.starts_with(p)) {
+            break;
+        }
+        start += line.len();
+    }
+    start
+}
+
+/// Lower case, with the accents of vowels removed, so "informacion" matches "Información"
+pub fn fold(text: &str) -> String {
+    text.chars()
+        .flat_map(char::to_lowercase)
+        .map(|c| match c {
+            'á' | 'à' | 'â' | 'ä' => 'a',
+            'é' | 'è' | 'ê' | 'ë' => 'e',
+            'í' | 'ì' | 'î' | 'ï' => 'i',
+            'ó' | 'ò' | 'ô' | 'ö' => 'o',
+            'ú' | 'ù' | 'û' | 'ü' => 'u',
+            c => c,
+        })
+        .collect()
+}
+
+/// The words of `query` matched against sentences: folded, without surrounding punctuation,
+/// at least `MIN_TERM_CHARS` long and each once
+pub fn query_terms(query: &str) -> Vec<String> {
+    let mut terms: Vec<String> = Vec::new();
+    for word in fold(query).split_whitespace() {
+        let word = word.trim_matches(|c: char| !c.is_alphanumeric());
+        if word.chars().count() >= MIN_TERM_CHARS && !terms.iter().any(|t| t == word) {
+            terms.push(word.to_string());
+        }
+    }
+    terms
+}
+
+/// Byte ranges of the sentences of `text`, trimmed and in order.
+///
+/// A sentence ends at `.`, `!`, `?` or `…` followed by a space (closing quotes and brackets
+/// allowed in between), at `。！？` right away, at a blank line, and at a line break after a
+/// short line. A period does not end it after an abbreviation or an initial, or when the next
+/// word starts in lower case, and "3.5" or "prof@upv.es" are never split.
+pub fn split(text: &str) -> Vec<Range<usize>> {
+    let mut sentences = Vec::new();
+    let mut start = 0;
+    let mut line_start = 0;
+    let chars: Vec<(usize, char)> = text.char_indices().collect();
+
+    for (i, &(pos, c)) in chars.iter().enumerate() {
+        let mut end = pos + c.len_utf8();
+        let boundary = match c {
+            '\n' => {
+                let line = &text[line_start..pos];
+                line_start = end;
+                let blank_next = text[end..].split('\n').next().is_some_and(|l| l.trim().is_empty());
+                blank_next || line.trim().chars().count() <= SHORT_LINE_CHARS
+            }
+            '。' | '！' | '？' => true,
+            '.' | '!' | '?' | '…' => {
+                // Skip closing punctuation, then require a space or the end of the text
+                let mut next = i + 1;
+                while let Some(&(closing, c)) = chars.get(next).filter(|(_, c)| matches!(c, '"' | '\'' | '»' | '”' | '’' | ')' | ']')) {
+                    end = closing + c.len_utf8();
+                    next += 1;
+                }
+                match chars.get(next) {
+                    None => true,
+                    Some((_, c)) if !c.is_whitespace() => false,
+                    Some(_) if c != '.' => true,
+                    Some(_) => !is_abbreviation(&text[start..pos]) && !next_word_lowercase(&chars[next..]),
+                }
+            }
+            _ => false,
+        };
+        if boundary {
+            push_trimmed(&mut sentences, text, start..end);
+            start = end;
+        }
+    }
+    push_trimmed(&mut sentences, text, start..text.len());
+    sentences
+}
+
+fn push_trimmed(sentences: &mut Vec<Range<usize>>, text: &str, range: Range<usize>) {
+    let slice = &text[range.clone()];
+    let trimmed = slice.trim();
+    if !trimmed.is_empty() {
+        let offset = range.start + (slice.len() - slice.trim_start().len());
+        sentences.push(offset..offset + trimmed.len());
+    }
+}
+
+/// Whether `before` (the sentence up to a period) ends in an abbreviation or a single-letter initial
+fn is_abbreviation(before: &str) -> bool {
+    let word = before.rsplit(|c: char| c.is_whitespace() || c == '(').next().unwrap_or("");
+    let word = word.to_lowercase();
+    (word.chars().count() == 1 && word.chars().all(char::is_alphabetic)) || ABBREVIATIONS.contains(&word.as_str())
+}
+
+fn next_word_lowercase(rest: &[(usize, char)]) -> bool {
+    rest.iter().map(|(_, c)| *c).find(|c| !c.is_whitespace()).is_some_and(char::is_lowercase)
+}
+
+/// Total length of the distinct `terms` the folded `sentence` contains, so rarer long words
+/// ("email", "laboratorio") weigh more than short ones
+pub fn score(sentence: &str, terms: &[String]) -> usize {
+    let folded = fold(sentence);
+    terms.iter().filter(|t| folded.contains(t.as_str())).map(|t| t.chars().count()).sum()
+}
+
+/// The best matching sentence of `text` and its neighbours
+pub struct Passage {
+    pub range: Range<usize>,
+    /// `score` of the best sentence, 0 when nothing matched
+    pub score: usize,
+}
+
+/// The sentence of `text` that best matches `terms` (the first on a tie), grown with the
+/// following and then the preceding sentences, alternately, up to `max_sentences` and as long
+/// as the passage stays within `max_chars`. `None` when no sentence contains any term.
+pub fn best_passage(text: &str, terms: &[String], max_sentences: usize, max_chars: usize) -> Option<Passage> {
+    let sentences = split(text);
+    let (best, score) = sentences.iter()
+        .enumerate()
+        .map(|(i, range)| (i, score(&text[range.clone()], terms)))
+        .fold((0, 0), |best, current| if current.1 > best.1 { current } else { best });
+    if score == 0 {
+        return None;
+    }
+
+    let (mut first, mut last) = (best, best);
+    let fits = |(f, l): (usize, usize)| sentences[l].end - sentences[f].start <= max_chars;
+    let mut prefer_after = true;
+    while last - first + 1 < max_sentences {
+        let after = (last + 1 < sentences.len()).then_some((first, last + 1));
+        let before = first.checked_sub(1).map(|f| (f, last));
+        let (preferred, other) = if prefer_after { (after, before) } else { (before, after) };
+        // When the preferred side doesn't fit the other may still have a short sentence that does
+        match preferred.filter(|r| fits(*r)).or(other.filter(|r| fits(*r))) {
+            Some(grown) => (first, last) = grown,
+            None => break,
+        }
+        prefer_after = !prefer_after;
+    }
+    Some(Passage { range: sentences[first].start..sentences[last].end, score })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sentences(text: &str) -> Vec<&str> {
+        split(text).into_iter().map(|range| &text[range]).collect()
+    }
+
+    #[test]
+    fn periods_inside_sentences_do_not_split_them() {
+        assert_eq!(
+            sentences("La Dra. García da la clase. El examen vale el 3.5 del total, etc. y se entrega a prof@upv.es hoy. ¿Hay dudas? Sí."),
+            ["La Dra. García da la clase.", "El examen vale el 3.5 del total, etc. y se entrega a prof@upv.es hoy.", "¿Hay dudas?", "Sí."]
+        );
+        assert_eq!(sentences("Según J. R. Smith (fig. 3) funciona. Fin."), ["Según J. R. Smith (fig. 3) funciona.", "Fin."]);
+        assert_eq!(sentences("He said \"stop.\" Then left."), ["He said \"stop.\"", "Then left."]);
+        assert_eq!(sentences("第一句。第二句！"), ["第一句。", "第二句！"]);
+    }
+
+    #[test]
+    fn short_lines_end_a_sentence_and_wrapped_ones_do_not() {
+        let wrapped = "This long paragraph was wrapped by the PDF extraction in the middle of a sentence\nthat goes on here.";
+        assert_eq!(sentences(wrapped), [wrapped]);
+        assert_eq!(sentences("Email: prof@upv.es\nDespacho: 1G-2.05\n\nHorario de tutorías"), ["Email: prof@upv.es", "Despacho: 1G-2.05", "Horario de tutorías"]);
+    }
+
+    #[test]
+    fn the_passage_grows_around_the_best_sentence() {
+        let text = "Intro. The lab is on Monday. Bring the laboratorio notebook. Grades come later. Bye.";
+        let terms = query_terms("When is the laboratorio?");
+        assert_eq!(terms, ["when", "the", "laboratorio"]);
+        let passage = best_passage(text, &terms, 3, 1000).unwrap();
+        assert_eq!(&text[passage.range], "The lab is on Monday. Bring the laboratorio notebook. Grades come later.");
+        assert_eq!(passage.score, "the".len() + "laboratorio".len());
+
+        // The character limit stops the growth on either side
+        let passage = best_passage(text, &terms, 3, 40).unwrap();
+        assert_eq!(&text[passage.range], "Bring the laboratorio notebook.");
+        assert!(best_passage(text, &query_terms("horario"), 3, 1000).is_none());
+    }
+
+    #[test]
+    fn headers_and_accents_do_not_get_in_the_way() {
+        let chunk = "### DOC: guia.pdf\nSubject: BDA\n\nLa información está en la guía.";
+        assert_eq!(&chunk[body_start(chunk)..], "La información está en la guía.");
+        assert_eq!(score(&chunk[body_start(chunk)..], &query_terms("informacion guia")), "informacion".len() + "guia".len());
+    }
+}
@@ -0,0 +1,558 @@
+//! Vector store kept in a single SQLite file, `<index>.sqlite`. Every change is written at
+//! once, in its own transaction, where the linear store rewrites the whole index on save, so
+//! a crash loses nothing already changed. Opening reads only the ids: the documents and their
+//! embeddings are read the first time a search or a caller needs them. Searches are exact
+//! scans. Each row records whether its embedding is f16.
+
+use super::format::{self, IndexHeader, FORMAT_VERSION};
+use super::store::{document_stats, is_family_member, similarity, DocFilter, StoreStats, VectorStore};
+use super::precision::Precision;
+use super::Document;
+use crate::config::SimilarityMetric;
+use half::f16;
+use anyhow::{Context, Result};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::collections::{HashMap, HashSet};
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS meta (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS documents (
+        id TEXT PRIMARY KEY,
+        user_id TEXT NOT NULL,
+        content TEXT NOT NULL,
+        metadata TEXT NOT NULL,
+        embedding BLOB NOT NULL,
+        half INTEGER NOT NULL DEFAULT 0
+    );
+";
+
+/// The documents read from the file, kept in step with it from then on
+#[derive(Default)]
+struct Loaded {
+    documents: Vec<Document>,
+    /// Position of each id in `documents`
+    positions: HashMap<String, usize>,
+}
+
+impl Loaded {
+    fn upsert(&mut self, doc: Document) {
+        match self.positions.get(&doc.id) {
+            Some(&pos) => self.documents[pos] = doc,
+            None => {
+                self.positions.insert(doc.id.clone(), self.documents.len());
+                self.documents.push(doc);
+            }
+        }
+    }
+
+    /// Drop `id`, moving the last document into its place
+    fn remove(&mut self, id: &str) {
+        let Some(pos) = self.positions.remove(id) else { return };
+        self.documents.swap_remove(pos);
+        if let Some(moved) = self.documents.get(pos) {
+            self.positions.insert(moved.id.clone(), pos);
+        }
+    }
+}
+
+pub struct SqliteVectorStore {
+    /// Locked by the reads of `&self` methods; changes go through `get_mut`
+    conn: Mutex<Connection>,
+    /// Ids of every stored document, read when the store is opened
+    ids: HashSet<String>,
+    /// The documents, embeddings included, read on first use
+    loaded: OnceLock<Loaded>,
+    path: PathBuf,
+    metric: SimilarityMetric,
+    /// How rows written from now on store their embedding
+    precision: Precision,
+}
+
+impl SqliteVectorStore {
+    /// Open (or create) the database at `path`
+    pub fn new(path: &Path, metric: SimilarityMetric) -> Result<Self> {
+        let conn = Connection::open(path).with_context(|| format!("Failed to open {:?}", path))?;
+        // WAL keeps a crash during a write from corrupting what was already committed
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "synchronous", "NORMAL")?;
+        conn.execute_batch(SCHEMA)?;
+        // Databases created before embeddings could be stored as f16
+        let has_half: bool = conn.query_row("SELECT COUNT(*) FROM pragma_table_info('documents') WHERE name = 'half'", [], |row| row.get(0))?;
+        if !has_half {
+            conn.execute("ALTER TABLE documents ADD COLUMN half INTEGER NOT NULL DEFAULT 0", [])?;
+        }
+
+        match read_header(&conn)? {
+            Some(header) if header.version > FORMAT_VERSION => anyhow::bail!(
+                "Index {:?} uses format v{}, but this polirag only reads up to v{}. Update polirag; the file was left untouched.",
+                path, header.version, FORMAT_VERSION
+            ),
+            Some(header) => format::check_embedder(path, &header),
+            None => write_header(&conn)?,
+        }
+
+        let ids = {
+            let mut statement = conn.prepare("SELECT id FROM documents")?;
+            let ids = statement.query_map([], |row| row.get::<_, String>(0))?.collect::<rusqlite::Result<_>>()?;
+            ids
+        };
+
+        Ok(Self {
+            conn: Mutex::new(conn),
+            ids,
+            loaded: OnceLock::new(),
+            path: path.to_path_buf(),
+            metric,
+            precision: Precision::F32,
+        })
+    }
+
+    /// Write the embeddings at `precision` from the next write on
+    pub fn with_precision(mut self, precision: Precision) -> Self {
+        self.precision = precision;
+        self
+    }
+
+    /// Header and document count of the database at `path`, without loading the documents.
+    /// `None` when there is no database yet.
+    pub fn inspect(path: &Path) -> Result<Option<(Option<IndexHeader>, usize)>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let conn = Connection::open_with_flags(path, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .with_context(|| format!("Failed to open {:?}", path))?;
+        let header = read_header(&conn)?;
+        let count: i64 = conn.query_row("SELECT COUNT(*) FROM documents", [], |row| row.get(0))?;
+        Ok(Some((header, count as usize)))
+    }
+
+    /// Whether `import` has run on this database, see `RagSystem::open_sqlite`
+    pub fn migrated(&self) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        let found: Option<String> = conn.query_row("SELECT value FROM meta WHERE key = 'migrated'", [], |row| row.get(0)).optional()?;
+        Ok(found.is_some())
+    }
+
+    /// Add every document of `documents` in a single transaction, replacing those with the
+    /// same id, and record in it that the database was migrated from `source`. Used to import
+    /// an existing index.
+    pub fn import(&mut self, source: &str, documents: impl IntoIterator<Item = Document>) -> Result<usize> {
+        let conn = self.conn.get_mut().unwrap();
+        let transaction = conn.transaction()?;
+        let mut imported = Vec::new();
+        for doc in documents {
+            insert(&transaction, &doc, self.precision)?;
+            imported.push(doc);
+        }
+        transaction.execute("INSERT OR REPLACE INTO meta (key, value) VALUES ('migrated', ?1)", params![source])?;
+        transaction.commit()?;
+
+        let count = imported.len();
+        for doc in imported {
+            self.ids.insert(doc.id.clone());
+            if let Some(loaded) = self.loaded.get_mut() {
+                loaded.upsert(doc);
+            }
+        }
+        Ok(count)
+    }
+
+    /// SQLite's write-ahead log next to the database
+    fn wal_path(&self) -> PathBuf {
+        let mut wal = self.path.as_os_str().to_owned();
+        wal.push("-wal");
+        PathBuf::from(wal)
+    }
+
+    /// The documents, read from the file the first time
+    fn loaded(&self) -> Result<&Loaded> {
+        if let Some(loaded) = self.loaded.get() {
+            return Ok(loaded);
+        }
+        let read = read_documents(&self.conn.lock().unwrap(), &self.path)?;
+        Ok(self.loaded.get_or_init(|| read))
+    }
+
+    /// `loaded` for the methods that can't return the error, which is logged instead
+    fn loaded_or_log(&self) -> Option<&Loaded> {
+        self.loaded()
+            .inspect_err(|e| tracing::error!("Could not read the documents of {:?}: {:#}", self.path, e))
+            .ok()
+    }
+
+    /// Delete the rows of `ids` in one transaction, then drop them from memory
+    fn delete(&mut self, ids: &[String]) -> Result<()> {
+        let transaction = self.conn.get_mut().unwrap().transaction()?;
+        for id in ids {
+            transaction.execute("DELETE FROM documents WHERE id = ?1", params![id])?;
+        }
+        transaction.commit()?;
+        for id in ids {
+            self.ids.remove(id);
+            if let Some(loaded) = self.loaded.get_mut() {
+                loaded.remove(id);
+            }
+        }
+        Ok(())
+    }
+
+    /// Rewrite one metadata value of the row of `id`, which is read back from the file so
+    /// the documents needn't be loaded
+    fn write_metadata(&mut self, id: &str, key: &str, value: &Option<String>) -> Result<()> {
+        let transaction = self.conn.get_mut().unwrap().transaction()?;
+        let stored: String = transaction.query_row("SELECT metadata FROM documents WHERE id = ?1", params![id], |row| row.get(0))?;
+        let mut metadata: HashMap<String, String> = serde_json::from_str(&stored)
+            .with_context(|| format!("Corrupt metadata of '{}' in {:?}", id, self.path))?;
+        match value {
+            Some(value) => metadata.insert(key.to_string(), value.clone()),
+            None => metadata.remove(key),
+        };
+        transaction.execute("UPDATE documents SET metadata = ?1 WHERE id = ?2", params![serde_json::to_string(&metadata)?, id])?;
+        transaction.commit()?;
+        Ok(())
+    }
+}
+
+fn read_header(conn: &Connection) -> Result<Option<IndexHeader>> {
+    let header: Option<String> = conn.query_row("SELECT value FROM meta WHERE key = 'header'", [], |row| row.get(0)).optional()?;
+    header.map(|header| serde_json::from_str(&header).context("Corrupt index header")).transpose()
+}
+
+fn write_header(conn: &Connection) -> Result<()> {
+    let header = serde_json::to_string(&IndexHeader::new("sqlite"))?;
+    conn.execute("INSERT OR REPLACE INTO meta (key, value) VALUES ('header', ?1)", params![header])?;
+    Ok(())
+}
+
+fn read_documents(conn: &Connection, path: &Path) -> Result<Loaded> {
+    let mut statement = conn.prepare("SELECT id, user_id, content, metadata, embedding, half FROM documents")?;
+    let rows = statement.query_map([], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?, row.get::<_, String>(3)?, row.get::<_, Vec<u8>>(4)?, row.get::<_, bool>(5)?))
+    })?;
+    let mut loaded = Loaded::default();
+    for row in rows {
+        let (id, user_id, content, metadata, embedding, half) = row?;
+        let metadata = serde_json::from_str(&metadata).with_context(|| format!("Corrupt metadata of '{}' in {:?}", id, path))?;
+        loaded.upsert(Document { id, content, embedding: decode_embedding(&embedding, half), metadata, user_id });
+    }
+    Ok(loaded)
+}
+
+fn insert(conn: &Connection, doc: &Document, precision: Precision) -> Result<()> {
+    let half = precision.is_half();
+    conn.execute(
+        "INSERT OR REPLACE INTO documents (id, user_id, content, metadata, embedding, half) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![doc.id, doc.user_id, doc.content, serde_json::to_string(&doc.metadata)?, encode_embedding(&doc.embedding, half), half],
+    )?;
+    Ok(())
+}
+
+/// Little-endian f32s, or f16s with `half`
+fn encode_embedding(embedding: &[f32], half: bool) -> Vec<u8> {
+    if half {
+        embedding.iter().flat_map(|&x| f16::from_f32(x).to_le_bytes()).collect()
+    } else {
+        embedding.iter().flat_map(|x| x.to_le_bytes()).collect()
+    }
+}
+
+fn decode_embedding(bytes: &[u8], half: bool) -> Vec<f32> {
+    if half {
+        bytes.chunks_exact(2).map(|b| f16::from_le_bytes([b[0], b[1]]).to_f32()).collect()
+    } else {
+        bytes.chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect()
+    }
+}
+
+impl VectorStore for SqliteVectorStore {
+    fn add_document(&mut self, doc: Document) -> Result<()> {
+        // A single statement is its own transaction
+        insert(self.conn.get_mut().unwrap(), &doc, self.precision)?;
+        self.ids.insert(doc.id.clone());
+        if let Some(loaded) = self.loaded.get_mut() {
+            loaded.upsert(doc);
+        }
+        Ok(())
+    }
+
+    fn search(&self, query_embedding: &[f32], user_id: &str, top_k: usize, min_threshold: f32, filter: Option<DocFilter>) -> Result<Vec<(Document, f32)>> {
+        let mut scores: Vec<(&Document, f32)> = self.loaded()?.documents.iter()
+            .filter(|d| d.user_id == user_id && filter.is_none_or(|f| f(d)))
+            .map(|d| (d, similarity(self.metric, query_embedding, &d.embedding)))
+            .filter(|(_, score)| *score > min_threshold)
+            .collect();
+        scores.sort_by(|a, b| b.1.total_cmp(&a.1));
+        if top_k > 0 {
+            scores.truncate(top_k);
+        }
+        Ok(scores.into_iter().map(|(d, score)| (d.clone(), score)).collect())
+    }
+
+    fn for_each_document(&self, f: &mut dyn FnMut(&Document) -> ControlFlow<()>) {
+        let Some(loaded) = self.loaded_or_log() else { return };
+        for doc in &loaded.documents {
+            if f(doc).is_break() {
+                break;
+            }
+        }
+    }
+
+    fn count(&self) -> usize {
+        self.ids.len()
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        self.conn.get_mut().unwrap().execute("DELETE FROM documents", [])?;
+        self.ids.clear();
+        self.loaded = OnceLock::from(Loaded::default());
+        Ok(())
+    }
+
+    fn save(&self) -> Result<()> {
+        // The rows are written as they change; a save checks the file can still grow, then
+        // folds the write-ahead log into the database
+        if let Some(dir) = self.path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            crate::config::check_writable(dir)?;
+            crate::disk::check_save(dir, &[self.path.clone(), self.wal_path()])?;
+        }
+        let conn = self.conn.lock().unwrap();
+        write_header(&conn)?;
+        conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))?;
+        Ok(())
+    }
+
+    fn storage_path(&self) -> String {
+        self.path.to_string_lossy().to_string()
+    }
+
+    fn get_stats(&self) -> StoreStats {
+        let file_size_bytes = [self.path.clone(), self.wal_path()].iter()
+            .map(|path| std::fs::metadata(path).map_or(0, |m| m.len()))
+            .sum();
+        StoreStats { file_size_bytes, precision_bytes: file_size_bytes, ..document_stats(self) }
+    }
+
+    fn store_type(&self) -> String {
+        format!("SQLite (Exact, {})", self.metric.label())
+    }
+
+    fn contains(&self, id: &str) -> bool {
+        self.ids.contains(id)
+    }
+
+    fn get_document(&self, id: &str) -> Option<Document> {
+        if !self.ids.contains(id) {
+            return None;
+        }
+        let loaded = self.loaded_or_log()?;
+        loaded.positions.get(id).map(|&pos| loaded.documents[pos].clone())
+    }
+
+    fn set_metadata(&mut self, id: &str, key: &str, value: Option<String>) -> bool {
+        if !self.ids.contains(id) {
+            return false;
+        }
+        if let Err(e) = self.write_metadata(id, key, &value) {
+            tracing::error!("Could not write the metadata of '{}' to {:?}: {:#}", id, self.path, e);
+            return false;
+        }
+        if let Some(doc) = self.loaded.get_mut().and_then(|loaded| loaded.positions.get(id).map(|&pos| &mut loaded.documents[pos])) {
+            match value {
+                Some(value) => doc.metadata.insert(key.to_string(), value),
+                None => doc.metadata.remove(key),
+            };
+        }
+        true
+    }
+
+    fn remove_document(&mut self, id: &str) -> Result<()> {
+        if self.ids.contains(id) {
+            self.delete(&[id.to_string()])?;
+        }
+        Ok(())
+    }
+
+    fn remove_by_prefix(&mut self, prefix: &str) -> Result<usize> {
+        let ids: Vec<String> = self.ids.iter().filter(|id| id.starts_with(prefix)).cloned().collect();
+        self.delete(&ids)?;
+        Ok(ids.len())
+    }
+
+    fn family_ids(&self, base_id: &str) -> Vec<String> {
+        self.ids.iter()
+            .filter(|id| is_family_member(id, base_id))
+            .cloned()
+            .collect()
+    }
+
+    fn get_documents_by_metadata(&self, key: &str, value: &str) -> Result<Vec<Document>> {
+        Ok(self.loaded()?.documents.iter()
+            .filter(|d| d.metadata.get(key).is_some_and(|v| v == value))
+            .cloned()
+            .collect())
+    }
+
+    fn rebuild(&mut self) -> Result<()> {
+        // Nothing to rebuild, every search is an exact scan
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn doc(id: &str, embedding: Vec<f32>) -> Document {
+        Document {
+            id: id.to_string(),
+            content: format!("Contents of {}", id),
+            embedding,
+            metadata: [("filename".to_string(), id.to_string())].into(),
+            user_id: "user".to_string(),
+        }
+    }
+
+    fn ids(store: &SqliteVectorStore) -> Vec<String> {
+        let mut ids: Vec<String> = store.ids.iter().cloned().collect();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn documents_reach_the_file_as_they_are_added() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index.sqlite");
+        let mut store = SqliteVectorStore::new(&path, SimilarityMetric::Cosine).unwrap();
+        store.add_document(doc("a.pdf#0", vec![1.0, 0.0])).unwrap();
+        store.add_document(doc("b.pdf#0", vec![0.0, 1.0])).unwrap();
+        // No save: a crash from here on keeps both
+        assert_eq!(SqliteVectorStore::inspect(&path).unwrap().unwrap().1, 2);
+
+        drop(store);
+        let store = SqliteVectorStore::new(&path, SimilarityMetric::Cosine).unwrap();
+        assert_eq!(ids(&store), ["a.pdf#0", "b.pdf#0"]);
+        assert_eq!(store.get_document("b.pdf#0").unwrap().embedding, vec![0.0, 1.0]);
+        let found = store.search(&[0.9, 0.1], "user", 1, 0.0, None).unwrap();
+        assert_eq!(found[0].0.id, "a.pdf#0");
+    }
+
+    #[test]
+    fn opening_reads_the_ids_and_leaves_the_embeddings_for_the_first_search() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index.sqlite");
+        let mut store = SqliteVectorStore::new(&path, SimilarityMetric::Cosine).unwrap();
+        store.add_document(doc("S1/a.pdf#0", vec![1.0, 0.0])).unwrap();
+        store.add_document(doc("S1/a.pdf#1", vec![0.0, 1.0])).unwrap();
+        drop(store);
+
+        let mut store = SqliteVectorStore::new(&path, SimilarityMetric::Cosine).unwrap();
+        assert!(store.loaded.get().is_none());
+        assert_eq!(store.count(), 2);
+        assert!(store.contains("S1/a.pdf#1"));
+        let mut family = store.family_ids("S1/a.pdf");
+        family.sort();
+        assert_eq!(family, ["S1/a.pdf#0", "S1/a.pdf#1"]);
+        // Changes made before the documents are read are there once they are
+        assert!(store.set_metadata("S1/a.pdf#0", "tags", Some("exam".to_string())));
+        store.remove_document("S1/a.pdf#1").unwrap();
+        store.add_document(doc("S2/b.pdf#0", vec![0.6, 0.8])).unwrap();
+        assert!(store.loaded.get().is_none());
+
+        let found = store.search(&[1.0, 0.0], "user", 0, 0.0, None).unwrap();
+        assert!(store.loaded.get().is_some());
+        let found: Vec<(&str, Option<&str>)> = found.iter().map(|(d, _)| (d.id.as_str(), d.metadata.get("tags").map(String::as_str))).collect();
+        assert_eq!(found, [("S1/a.pdf#0", Some("exam")), ("S2/b.pdf#0", None)]);
+    }
+
+    #[test]
+    fn removals_reach_the_file_as_they_are_made() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index.sqlite");
+        let mut store = SqliteVectorStore::new(&path, SimilarityMetric::Cosine).unwrap();
+        for id in ["S1/a.pdf#0", "S1/a.pdf#1", "S2/b.pdf#0", "S2"] {
+            store.add_document(doc(id, vec![1.0, 0.0])).unwrap();
+        }
+        // Read the documents, so the removals have to reach them too
+        assert_eq!(store.search(&[1.0, 0.0], "user", 0, 0.0, None).unwrap().len(), 4);
+
+        store.remove_document("S2").unwrap();
+        assert_eq!(store.remove_by_prefix("S1/").unwrap(), 2);
+        assert_eq!(ids(&store), ["S2/b.pdf#0"]);
+        assert!(!store.contains("S1/a.pdf#1"));
+        assert!(store.get_document("S1/a.pdf#1").is_none());
+        assert_eq!(store.search(&[1.0, 0.0], "user", 0, 0.0, None).unwrap().len(), 1);
+
+        let store = SqliteVectorStore::new(&path, SimilarityMetric::Cosine).unwrap();
+        assert_eq!(ids(&store), ["S2/b.pdf#0"]);
+    }
+
+    #[test]
+    fn removing_and_adding_again_keeps_the_document() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index.sqlite");
+        let mut store = SqliteVectorStore::new(&path, SimilarityMetric::Cosine).unwrap();
+        store.add_document(doc("a.pdf#0", vec![1.0, 0.0])).unwrap();
+        store.remove_document("a.pdf#0").unwrap();
+        store.add_document(doc("a.pdf#0", vec![0.0, 1.0])).unwrap();
+
+        let store = SqliteVectorStore::new(&path, SimilarityMetric::Cosine).unwrap();
+        assert_eq!(store.get_document("a.pdf#0").unwrap().embedding, vec![0.0, 1.0]);
+    }
+
+    #[test]
+    fn metadata_reaches_the_file_as_it_is_set() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index.sqlite");
+        let mut store = SqliteVectorStore::new(&path, SimilarityMetric::Cosine).unwrap();
+        store.add_document(doc("a.pdf#0", vec![1.0, 0.0])).unwrap();
+        assert!(store.get_document("a.pdf#0").is_some());
+
+        assert!(store.set_metadata("a.pdf#0", "tags", Some("exam".to_string())));
+        assert!(!store.set_metadata("missing", "tags", None));
+        assert_eq!(store.get_document("a.pdf#0").unwrap().metadata.get("tags").map(String::as_str), Some("exam"));
+
+        let mut reopened = SqliteVectorStore::new(&path, SimilarityMetric::Cosine).unwrap();
+        assert_eq!(reopened.get_document("a.pdf#0").unwrap().metadata.get("tags").map(String::as_str), Some("exam"));
+        assert!(reopened.set_metadata("a.pdf#0", "tags", None));
+        let reopened = SqliteVectorStore::new(&path, SimilarityMetric::Cosine).unwrap();
+        let metadata = reopened.get_document("a.pdf#0").unwrap().metadata;
+        assert_eq!(metadata.get("tags"), None);
+        assert_eq!(metadata.get("filename").map(String::as_str), Some("a.pdf#0"));
+    }
+
+    #[test]
+    fn save_checkpoints_the_log_into_the_database() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index.sqlite");
+        let mut store = SqliteVectorStore::new(&path, SimilarityMetric::Cosine).unwrap();
+        for i in 0..50 {
+            store.add_document(doc(&format!("a.pdf#{}", i), vec![1.0, i as f32])).unwrap();
+        }
+        assert!(std::fs::metadata(store.wal_path()).unwrap().len() > 0);
+
+        store.save().unwrap();
+        assert_eq!(std::fs::metadata(store.wal_path()).unwrap().len(), 0);
+        assert_eq!(store.get_stats().file_size_bytes, std::fs::metadata(&path).unwrap().len());
+    }
+
+    #[test]
+    fn import_writes_at_once_and_records_the_migration() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index.sqlite");
+        let mut store = SqliteVectorStore::new(&path, SimilarityMetric::Cosine).unwrap();
+        assert!(!store.migrated().unwrap());
+        let imported = store.import("linear", vec![doc("a.pdf#0", vec![1.0, 0.0]), doc("b.pdf#0", vec![0.0, 1.0])]).unwrap();
+        assert_eq!(imported, 2);
+        drop(store);
+
+        let store = SqliteVectorStore::new(&path, SimilarityMetric::Cosine).unwrap();
+        assert!(store.migrated().unwrap());
+        assert_eq!(ids(&store), ["a.pdf#0", "b.pdf#0"]);
+    }
+}
@@ -1,10 +1,19 @@
 use super::Document;
-use anyhow::Result;
+use anyhow::{Context, Result};
 use std::collections::HashMap;
 use std::fs::File;
-use std::io::{BufReader, BufWriter};
-use std::path::Path;
+use std::io::BufReader;
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use serde::{Serialize, Deserialize};
+use crate::config::SimilarityMetric;
+use super::format::{self, IndexHeader, Loaded};
+use super::precision::{self, Precision};
+
+/// Restricts a search to the documents it accepts; applied before top-k so filtered-out
+/// documents don't take the places of matching ones
+pub type DocFilter<'a> = &'a dyn Fn(&Document) -> bool;
 
 /// Trait for vector storage backends
 pub trait VectorStore: Send + Sync {
@@ -12,10 +21,40 @@ pub trait VectorStore: Send + Sync {
     fn add_document(&mut self, doc: Document) -> Result<()>;
     
     /// Search for similar documents
-    fn search(&self, query_embedding: &[f32], user_id: &str, top_k: usize, min_threshold: f32) -> Result<Vec<(Document, f32)>>;
+    fn search(&self, query_embedding: &[f32], user_id: &str, top_k: usize, min_threshold: f32, filter: Option<DocFilter>) -> Result<Vec<(Document, f32)>>;
     
-    /// Get all documents (for re-embedding or migration)
-    fn get_all(&self) -> Result<Vec<Document>>;
+    /// Visit the documents in place until `f` breaks. The order is stable as long as the
+    /// store isn't modified.
+    fn for_each_document(&self, f: &mut dyn FnMut(&Document) -> ControlFlow<()>);
+
+    /// Up to `limit` documents from position `offset` in `for_each_document` order; only the
+    /// page is cloned
+    fn documents_page(&self, offset: usize, limit: usize) -> Vec<Document> {
+        let mut page = Vec::with_capacity(limit.min(self.count()));
+        let mut position = 0;
+        self.for_each_document(&mut |doc| {
+            if page.len() >= limit {
+                return ControlFlow::Break(());
+            }
+            if position >= offset {
+                page.push(doc.clone());
+            }
+            position += 1;
+            ControlFlow::Continue(())
+        });
+        page
+    }
+
+    /// Clone every document. Holds the whole corpus in memory twice: prefer
+    /// `for_each_document` or `documents_page` unless every embedding is needed at once.
+    fn get_all(&self) -> Result<Vec<Document>> {
+        let mut docs = Vec::with_capacity(self.count());
+        self.for_each_document(&mut |doc| {
+            docs.push(doc.clone());
+            ControlFlow::Continue(())
+        });
+        Ok(docs)
+    }
     
     /// Count documents
     fn count(&self) -> usize;
@@ -38,12 +77,47 @@ pub trait VectorStore: Send + Sync {
     
     /// Check if a document with the given ID exists
     fn contains(&self, id: &str) -> bool;
+
+    /// The document stored under `id`, if any
+    fn get_document(&self, id: &str) -> Option<Document>;
+
+    /// Set (or with `None` remove) one metadata value of a stored document, keeping its
+    /// embedding where it is. Does not save; false when there is no such document.
+    fn set_metadata(&mut self, id: &str, key: &str, value: Option<String>) -> bool;
     
     /// Remove a document by ID
     fn remove_document(&mut self, id: &str) -> Result<()>;
 
+    /// Remove every document whose id starts with `prefix`, e.g. `GRA_11673_2025/` for the
+    /// chunks of a subject's files. Does not save. Returns how many were removed.
+    fn remove_by_prefix(&mut self, prefix: &str) -> Result<usize> {
+        let mut ids = Vec::new();
+        self.for_each_document(&mut |doc| {
+            if doc.id.starts_with(prefix) {
+                ids.push(doc.id.clone());
+            }
+            ControlFlow::Continue(())
+        });
+        for id in &ids {
+            self.remove_document(id)?;
+        }
+        Ok(ids.len())
+    }
+
+    /// IDs of `base_id` itself and its chunks (`base_id#0`, `base_id#1`, ...)
+    fn family_ids(&self, base_id: &str) -> Vec<String>;
+
     /// Get documents by metadata key-value pair
     fn get_documents_by_metadata(&self, key: &str, value: &str) -> Result<Vec<Document>>;
+
+    /// Rebuild any search structure from the stored embeddings (does not save)
+    fn rebuild(&mut self) -> Result<()>;
+
+    /// Rebuild the search structure when removed documents take up enough of it to slow
+    /// searches down (does not save). True when it did.
+    fn compact(&mut self) -> Result<bool> {
+        Ok(false)
+    }
 }
 
 #[derive(Default)]
@@ -53,6 +127,55 @@ pub struct StoreStats {
     pub total_content_bytes: usize,
     pub embedding_dimensions: usize,
     pub file_size_bytes: u64,
+    /// Part of `file_size_bytes` whose embeddings are written at the store's precision
+    pub precision_bytes: u64,
+    /// Backend specific tuning parameters (name, value)
+    pub index_params: Vec<(String, String)>,
+    /// Oldest and newest `indexed_at` among the documents that record one
+    pub indexed_range: Option<(u64, u64)>,
+    /// Per subject site id, see `subjects::subject_of`
+    pub docs_by_subject: HashMap<String, SubjectStats>,
+}
+
+/// Share of the index taken by one subject
+#[derive(Default, Clone, Debug)]
+pub struct SubjectStats {
+    /// From the subject's summary document, when it is indexed
+    pub name: Option<String>,
+    pub chunks: usize,
+    pub content_bytes: usize,
+}
+
+/// The statistics every backend computes the same way, from one pass over its documents:
+/// counts, content size, dimensions, the indexed range and the split by subject. File size and tuning parameters
+/// are left to the backend.
+pub fn document_stats(store: &(impl VectorStore + ?Sized)) -> StoreStats {
+    let mut stats = StoreStats::default();
+    store.for_each_document(&mut |doc| {
+        stats.document_count += 1;
+        stats.total_content_bytes += doc.content.len();
+        stats.embedding_dimensions = doc.embedding.len();
+        let doc_type = doc.metadata.get("type").map_or("unknown", |t| t.as_str());
+        match stats.docs_by_type.get_mut(doc_type) {
+            Some(count) => *count += 1,
+            None => { stats.docs_by_type.insert(doc_type.to_string(), 1); }
+        }
+        let subject = crate::subjects::subject_of(&doc.id);
+        let subject = match stats.docs_by_subject.get_mut(subject) {
+            Some(subject) => subject,
+            None => stats.docs_by_subject.entry(subject.to_string()).or_default(),
+        };
+        subject.chunks += 1;
+        subject.content_bytes += doc.content.len();
+        if doc_type == "subject" {
+            subject.name = doc.metadata.get("name").cloned();
+        }
+        if let Some(at) = super::indexed_at(doc) {
+            stats.indexed_range = Some(stats.indexed_range.map_or((at, at), |(oldest, newest)| (at.min(oldest), at.max(newest))));
+        }
+        ControlFlow::Continue(())
+    });
+    stats
 }
 
 /// Simple linear scan vector store (legacy/default)
@@ -61,17 +184,64 @@ struct LinearIndex {
     documents: Vec<Document>,
 }
 
+/// `LinearIndex` as `save` writes it, the embeddings at the store's precision
+#[derive(Serialize)]
+struct EncodedIndex<'a> {
+    documents: precision::Encoded<'a, [Document]>,
+}
+
+/// Layout of format v1 and the unversioned files
+#[derive(Deserialize)]
+struct LinearIndexV1 {
+    documents: Vec<format::DocumentV1>,
+}
+
+impl LinearIndexV1 {
+    fn read_unversioned(path: &Path) -> Result<LinearIndex> {
+        let index: LinearIndexV1 = bincode::deserialize_from(BufReader::new(File::open(path)?))
+            .with_context(|| format!("Failed to read linear index {:?}", path))?;
+        Ok(index.into())
+    }
+}
+
+impl From<LinearIndexV1> for LinearIndex {
+    fn from(index: LinearIndexV1) -> Self {
+        LinearIndex { documents: index.documents.into_iter().map(Document::from).collect() }
+    }
+}
+
+/// Exact search over all documents, saved as a single file
 pub struct LinearVectorStore {
     index: LinearIndex,
     storage_path: String,
+    metric: SimilarityMetric,
+    /// How `save` writes the embeddings
+    precision: Precision,
+    /// Changed since the last save. Adds and removals only change memory, so indexing many
+    /// documents rewrites the file once, when the caller saves.
+    dirty: AtomicBool,
 }
 
 impl LinearVectorStore {
-    pub fn new(storage_path: &str) -> Result<Self> {
-        let index = if Path::new(storage_path).exists() {
-            let file = File::open(storage_path)?;
-            let reader = BufReader::new(file);
-            bincode::deserialize_from(reader).unwrap_or_default()
+    pub fn new(storage_path: &str, metric: SimilarityMetric) -> Result<Self> {
+        let path = Path::new(storage_path);
+        let index = if path.exists() {
+            match format::read::<LinearIndex>(path)? {
+                Loaded::Current(header, index) => {
+                    format::check_embedder(path, &header);
+                    index
+                }
+                Loaded::Previous(header) => {
+                    let index: LinearIndex = format::read_previous::<LinearIndexV1>(path)?.into();
+                    format::migrate(path, header.version, &IndexHeader::new("linear"), &index)?;
+                    index
+                }
+                Loaded::Unversioned => {
+                    let index = LinearIndexV1::read_unversioned(path)?;
+                    format::migrate(path, 0, &IndexHeader::new("linear"), &index)?;
+                    index
+                }
+            }
         } else {
             LinearIndex::default()
         };
@@ -79,30 +249,61 @@ impl LinearVectorStore {
         Ok(Self {
             index,
             storage_path: storage_path.to_string(),
+            metric,
+            precision: Precision::F32,
+            dirty: AtomicBool::new(false),
         })
     }
+
+    /// Write the embeddings at `precision` from the next save on
+    pub fn with_precision(mut self, precision: Precision) -> Self {
+        self.precision = precision;
+        self
+    }
+
+    /// Header and document count of the index at `storage_path`, without migrating it.
+    /// `None` when there is no index yet.
+    pub fn inspect(storage_path: &str) -> Result<Option<(Option<IndexHeader>, usize)>> {
+        let path = Path::new(storage_path);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let header = format::read_header(path)?;
+        let index = match format::read::<LinearIndex>(path)? {
+            Loaded::Current(_, index) => index,
+            Loaded::Previous(_) => format::read_previous::<LinearIndexV1>(path)?.into(),
+            Loaded::Unversioned => LinearIndexV1::read_unversioned(path)?,
+        };
+        Ok(Some((header, index.documents.len())))
+    }
+
+    /// A store that is never written to disk; `save` does nothing
+    pub fn in_memory(metric: SimilarityMetric) -> Self {
+        Self { index: LinearIndex::default(), storage_path: String::new(), metric, precision: Precision::F32, dirty: AtomicBool::new(false) }
+    }
 }
 
 impl VectorStore for LinearVectorStore {
     fn storage_path(&self) -> String {
-        self.storage_path.clone()
+        if self.storage_path.is_empty() { "(in memory)".to_string() } else { self.storage_path.clone() }
     }
 
     fn store_type(&self) -> String {
-        "Linear Scan (Exact)".to_string()
+        format!("Linear Scan (Exact, {})", self.metric.label())
     }
 
     fn add_document(&mut self, doc: Document) -> Result<()> {
         self.index.documents.retain(|d| d.id != doc.id);
         self.index.documents.push(doc);
-        self.save()
+        *self.dirty.get_mut() = true;
+        Ok(())
     }
 
-    fn search(&self, query_embedding: &[f32], user_id: &str, top_k: usize, min_threshold: f32) -> Result<Vec<(Document, f32)>> {
+    fn search(&self, query_embedding: &[f32], user_id: &str, top_k: usize, min_threshold: f32, filter: Option<DocFilter>) -> Result<Vec<(Document, f32)>> {
         let mut scores: Vec<(Document, f32)> = self.index.documents.iter()
-            .filter(|d| d.user_id == user_id)
+            .filter(|d| d.user_id == user_id && filter.is_none_or(|f| f(d)))
             .map(|d| {
-                let score = cosine_similarity(query_embedding, &d.embedding);
+                let score = similarity(self.metric, query_embedding, &d.embedding);
                 (d.clone(), score)
             })
             .filter(|(_, score)| *score > min_threshold)
@@ -119,8 +320,12 @@ impl VectorStore for LinearVectorStore {
         Ok(scores)
     }
 
-    fn get_all(&self) -> Result<Vec<Document>> {
-        Ok(self.index.documents.clone())
+    fn for_each_document(&self, f: &mut dyn FnMut(&Document) -> ControlFlow<()>) {
+        for doc in &self.index.documents {
+            if f(doc).is_break() {
+                break;
+            }
+        }
     }
 
     fn count(&self) -> usize {
@@ -129,6 +334,7 @@ impl VectorStore for LinearVectorStore {
 
     fn clear(&mut self) -> Result<()> {
         self.index.documents.clear();
+        *self.dirty.get_mut() = true;
         self.save()
     }
 
@@ -136,53 +342,114 @@ impl VectorStore for LinearVectorStore {
         self.index.documents.iter().any(|d| d.id == id)
     }
 
+    fn get_document(&self, id: &str) -> Option<Document> {
+        self.index.documents.iter().find(|d| d.id == id).cloned()
+    }
+
+    fn set_metadata(&mut self, id: &str, key: &str, value: Option<String>) -> bool {
+        let Some(doc) = self.index.documents.iter_mut().find(|d| d.id == id) else { return false };
+        match value {
+            Some(value) => doc.metadata.insert(key.to_string(), value),
+            None => doc.metadata.remove(key),
+        };
+        *self.dirty.get_mut() = true;
+        true
+    }
+
     fn remove_document(&mut self, id: &str) -> Result<()> {
+        let before = self.index.documents.len();
         self.index.documents.retain(|d| d.id != id);
-        self.save()
+        if self.index.documents.len() != before {
+            *self.dirty.get_mut() = true;
+        }
+        Ok(())
+    }
+
+    fn family_ids(&self, base_id: &str) -> Vec<String> {
+        self.index.documents.iter()
+            .filter(|d| is_family_member(&d.id, base_id))
+            .map(|d| d.id.clone())
+            .collect()
     }
 
     fn get_documents_by_metadata(&self, key: &str, value: &str) -> Result<Vec<Document>> {
         let docs = self.index.documents.iter()
-            .filter(|d| d.metadata.get(key).map_or(false, |v| v == value))
+            .filter(|d| d.metadata.get(key).is_some_and(|v| v == value))
             .cloned()
             .collect();
         Ok(docs)
     }
 
+    fn rebuild(&mut self) -> Result<()> {
+        // Nothing to rebuild, every search is an exact scan
+        Ok(())
+    }
+
     fn save(&self) -> Result<()> {
-        let file = File::create(&self.storage_path)?;
-        let writer = BufWriter::new(file);
-        bincode::serialize_into(writer, &self.index)?;
+        if self.storage_path.is_empty() || !self.dirty.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        if let Some(dir) = Path::new(&self.storage_path).parent().filter(|d| !d.as_os_str().is_empty()) {
+            crate::config::check_writable(dir)?;
+            crate::disk::check_save(dir, &[PathBuf::from(&self.storage_path)])?;
+        }
+        let encoded = EncodedIndex { documents: precision::Encoded(&self.index.documents, self.precision) };
+        format::write(Path::new(&self.storage_path), &IndexHeader::new("linear"), &encoded)?;
+        self.dirty.store(false, Ordering::SeqCst);
         Ok(())
     }
     
     fn get_stats(&self) -> StoreStats {
-        let mut docs_by_type: HashMap<String, usize> = HashMap::new();
-        let mut total_content_bytes: usize = 0;
-        let mut total_embedding_dims: usize = 0;
-        
-        for doc in &self.index.documents {
-            total_content_bytes += doc.content.len();
-            total_embedding_dims = doc.embedding.len();
-            
-            let doc_type = doc.metadata.get("type").cloned().unwrap_or_else(|| "unknown".to_string());
-            *docs_by_type.entry(doc_type).or_insert(0) += 1;
-        }
-        
         let file_size_bytes = std::fs::metadata(&self.storage_path)
             .map(|m| m.len())
             .unwrap_or(0);
-            
-        StoreStats {
-            document_count: self.index.documents.len(),
-            docs_by_type,
-            total_content_bytes,
-            embedding_dimensions: total_embedding_dims,
-            file_size_bytes,
-        }
+        StoreStats { file_size_bytes, precision_bytes: file_size_bytes, ..document_stats(self) }
+    }
+}
+
+/// `id` is `base_id` or one of its chunks, `base_id#<n>`
+pub fn is_family_member(id: &str, base_id: &str) -> bool {
+    match id.strip_prefix(base_id) {
+        Some("") => true,
+        Some(rest) => rest.strip_prefix('#').is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit())),
+        None => false,
+    }
+}
+
+/// Score two embeddings under the given metric (higher is more similar)
+pub fn similarity(metric: SimilarityMetric, a: &[f32], b: &[f32]) -> f32 {
+    match metric {
+        SimilarityMetric::Cosine => cosine_similarity(a, b),
+        SimilarityMetric::Dot => dot_product(a, b),
+        SimilarityMetric::Euclidean => 1.0 / (1.0 + euclidean_distance(a, b)),
+    }
+}
+
+/// Convert a distance reported by the HNSW graph back into the same score as `similarity`
+pub fn similarity_from_distance(metric: SimilarityMetric, distance: f32) -> f32 {
+    match metric {
+        SimilarityMetric::Cosine => 1.0 - distance,
+        SimilarityMetric::Dot if distance >= 1.0 => 1.0 - distance,
+        SimilarityMetric::Dot => 1.0 / distance - 1.0,
+        SimilarityMetric::Euclidean => 1.0 / (1.0 + distance),
     }
 }
 
+/// Graph distance of a dot product. hnsw_rs wants distances of at least 0, which `1 - dot`
+/// isn't past a dot of 1, so positive dots map into (0, 1) instead. Larger dots stay closer,
+/// so unnormalized embeddings rank as on the linear store.
+pub fn distance_from_dot(dot: f32) -> f32 {
+    if dot <= 0.0 { 1.0 - dot } else { 1.0 / (1.0 + dot) }
+}
+
+fn dot_product(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+fn euclidean_distance(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| (x - y) * (x - y)).sum::<f32>().sqrt()
+}
+
 fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
     let dot_product: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
     let norm_a: f32 = a.iter().map(|x| x * x).sum::<f32>().sqrt();
@@ -194,3 +461,124 @@ fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
         dot_product / (norm_a * norm_b)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUERY: [f32; 2] = [1.0, 0.0];
+
+    /// Vectors each metric ranks differently against `QUERY`
+    const VECTORS: [(&str, [f32; 2]); 4] = [
+        ("long_aligned", [3.0, 0.0]),
+        ("close", [0.9, 0.3]),
+        ("long_diagonal", [2.0, 2.0]),
+        ("orthogonal", [0.0, 1.0]),
+    ];
+
+    fn ranked(store: &dyn VectorStore) -> Vec<String> {
+        store.search(&QUERY, "user", VECTORS.len(), f32::MIN, None).unwrap().into_iter().map(|(d, _)| d.id).collect()
+    }
+
+    fn store(metric: SimilarityMetric) -> LinearVectorStore {
+        let mut store = LinearVectorStore::in_memory(metric);
+        for (id, embedding) in VECTORS {
+            store.add_document(Document {
+                id: id.to_string(),
+                content: String::new(),
+                embedding: embedding.to_vec(),
+                metadata: HashMap::new(),
+                user_id: "user".to_string(),
+            }).unwrap();
+        }
+        store
+    }
+
+    #[test]
+    fn cosine_ranks_by_angle() {
+        assert_eq!(ranked(&store(SimilarityMetric::Cosine)), ["long_aligned", "close", "long_diagonal", "orthogonal"]);
+    }
+
+    #[test]
+    fn dot_ranks_by_projection_length() {
+        assert_eq!(ranked(&store(SimilarityMetric::Dot)), ["long_aligned", "long_diagonal", "close", "orthogonal"]);
+    }
+
+    #[test]
+    fn euclidean_ranks_by_distance() {
+        assert_eq!(ranked(&store(SimilarityMetric::Euclidean)), ["close", "orthogonal", "long_aligned", "long_diagonal"]);
+    }
+
+    #[test]
+    fn linear_store_writes_only_on_save_and_only_when_changed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index.bin");
+        let mut store = LinearVectorStore::new(&path.to_string_lossy(), SimilarityMetric::Cosine).unwrap();
+        for (id, embedding) in VECTORS {
+            store.add_document(Document { id: id.to_string(), content: String::new(), embedding: embedding.to_vec(), metadata: HashMap::new(), user_id: "user".to_string() }).unwrap();
+        }
+        store.remove_document("orthogonal").unwrap();
+        assert!(!path.exists());
+        store.save().unwrap();
+        assert!(path.exists());
+
+        // Nothing changed since, so a save doesn't write: the deleted file stays deleted
+        std::fs::remove_file(&path).unwrap();
+        store.save().unwrap();
+        store.remove_document("missing").unwrap();
+        store.save().unwrap();
+        assert!(!path.exists());
+
+        assert!(store.set_metadata("close", "type", Some("pdf".to_string())));
+        store.save().unwrap();
+        let reopened = LinearVectorStore::new(&path.to_string_lossy(), SimilarityMetric::Cosine).unwrap();
+        assert_eq!(reopened.count(), 3);
+        assert_eq!(reopened.get_document("close").unwrap().metadata["type"], "pdf");
+
+        // Clearing saves right away
+        store.clear().unwrap();
+        assert_eq!(LinearVectorStore::new(&path.to_string_lossy(), SimilarityMetric::Cosine).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn pages_cover_the_store_in_order_and_stop_early() {
+        let store = store(SimilarityMetric::Cosine);
+        let ids = |page: Vec<Document>| page.into_iter().map(|d| d.id).collect::<Vec<_>>();
+        assert_eq!(ids(store.documents_page(0, 3)), ["long_aligned", "close", "long_diagonal"]);
+        assert_eq!(ids(store.documents_page(3, 3)), ["orthogonal"]);
+        assert!(store.documents_page(4, 3).is_empty());
+        assert!(store.documents_page(0, 0).is_empty());
+
+        let mut visited = 0;
+        store.for_each_document(&mut |_| {
+            visited += 1;
+            if visited == 2 { ControlFlow::Break(()) } else { ControlFlow::Continue(()) }
+        });
+        assert_eq!(visited, 2);
+
+        let stats = document_stats(&store);
+        assert_eq!(stats.document_count, 4);
+        assert_eq!(stats.docs_by_type.get("unknown"), Some(&4));
+    }
+
+    #[test]
+    fn graph_distances_map_back_to_the_same_scores() {
+        let (a, b) = ([0.6, 0.8], [1.0, 0.0]);
+        let cosine_distance = 1.0 - cosine_similarity(&a, &b);
+        let cosine = similarity_from_distance(SimilarityMetric::Cosine, cosine_distance);
+        assert!((cosine - similarity(SimilarityMetric::Cosine, &a, &b)).abs() < 1e-6);
+        let euclidean = similarity_from_distance(SimilarityMetric::Euclidean, euclidean_distance(&a, &b));
+        assert!((euclidean - similarity(SimilarityMetric::Euclidean, &a, &b)).abs() < 1e-6);
+        assert_eq!(similarity(SimilarityMetric::Cosine, &[0.0, 0.0], &b), 0.0);
+
+        // Dot distances stay positive and keep the order of dots past 1
+        let dots = [-2.5, -1.0, 0.0, 0.5, 1.0, 1.5, 4.0, 30.0];
+        let distances = dots.map(distance_from_dot);
+        assert!(distances.iter().all(|d| *d > 0.0));
+        assert!(distances.windows(2).all(|w| w[0] > w[1]), "{:?}", distances);
+        for (dot, distance) in dots.iter().zip(distances) {
+            let score = similarity_from_distance(SimilarityMetric::Dot, distance);
+            assert!((score - dot).abs() < 1e-4 * dot.abs().max(1.0), "{} -> {}", dot, score);
+        }
+    }
+}
@@ -0,0 +1,140 @@
+//! Export and import of the index as newline-delimited JSON, one document per line, to move
+//! it between machines or inspect it with other tools.
+
+use super::store::VectorStore;
+use super::{reembed, Document, RagSystem};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::ops::ControlFlow;
+use std::path::Path;
+
+/// One line of an export. Without its embedding, the document is re-embedded on import.
+#[derive(Serialize, Deserialize)]
+struct Record {
+    id: String,
+    content: String,
+    #[serde(default)]
+    metadata: HashMap<String, String>,
+    user_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    embedding: Option<Vec<f32>>,
+}
+
+/// What `RagSystem::import` did
+#[derive(Default, Debug)]
+pub struct ImportReport {
+    pub added: usize,
+    /// Documents whose id was already indexed
+    pub replaced: usize,
+    /// Documents without an embedding, or with one of other dimensions than the model's
+    pub reembedded: usize,
+}
+
+impl RagSystem {
+    /// Write every document to `path`, one JSON object per line. `embeddings: false` leaves
+    /// the vectors out, which makes the file several times smaller. Returns the count.
+    pub fn export(&self, path: &Path, embeddings: bool) -> anyhow::Result<usize> {
+        let file = File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+        let mut writer = BufWriter::new(file);
+        let mut count = 0;
+        let mut failed = None;
+        self.store.read().unwrap().for_each_document(&mut |doc| {
+            let record = Record {
+                id: doc.id.clone(),
+                content: doc.content.clone(),
+                metadata: doc.metadata.clone(),
+                user_id: doc.user_id.clone(),
+                embedding: embeddings.then(|| doc.embedding.clone()),
+            };
+            let written = serde_json::to_writer(&mut writer, &record)
+                .map_err(anyhow::Error::from)
+                .and_then(|()| Ok(writer.write_all(b"\n")?));
+            match written {
+                Ok(()) => {
+                    count += 1;
+                    ControlFlow::Continue(())
+                }
+                Err(e) => {
+                    failed = Some(e);
+                    ControlFlow::Break(())
+                }
+            }
+        });
+        if let Some(e) = failed {
+            return Err(e.context(format!("Failed to write {}", path.display())));
+        }
+        writer.flush()?;
+        Ok(count)
+    }
+
+    /// Add the documents exported to `path`, replacing those with the same id. Documents
+    /// without an embedding, or with one that doesn't match the model's dimensions, are
+    /// embedded again, `reembed::BATCH_SIZE` at a time. Saves once at the end.
+    pub async fn import(&self, path: &Path) -> anyhow::Result<ImportReport> {
+        let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        let dimensions = self.model_dimensions
+            .or_else(|| Some(self.store.read().unwrap().get_stats().embedding_dimensions).filter(|&d| d > 0));
+
+        let mut report = ImportReport::default();
+        let mut pending = Vec::with_capacity(reembed::BATCH_SIZE);
+        for (n, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.with_context(|| format!("Failed to read {}", path.display()))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record: Record = serde_json::from_str(&line)
+                .with_context(|| format!("Line {} of {} is not an exported document", n + 1, path.display()))?;
+            pending.push(record);
+            if pending.len() == reembed::BATCH_SIZE {
+                self.import_records(std::mem::take(&mut pending), dimensions, &mut report).await?;
+            }
+        }
+        self.import_records(pending, dimensions, &mut report).await?;
+
+        // Replaced documents may have left old points behind
+        self.compact()?;
+        self.save()?;
+        Ok(report)
+    }
+
+    async fn import_records(&self, records: Vec<Record>, dimensions: Option<usize>, report: &mut ImportReport) -> anyhow::Result<()> {
+        if records.is_empty() {
+            return Ok(());
+        }
+        let usable = |embedding: Option<&[f32]>| {
+            embedding.is_some_and(|e| !e.is_empty() && dimensions.is_none_or(|d| e.len() == d))
+        };
+        let texts: Vec<String> = records.iter()
+            .filter(|r| !usable(r.embedding.as_deref()))
+            .map(|r| r.content.clone())
+            .collect();
+        let mut embedded = if texts.is_empty() { Vec::new() } else { self.embedder.embed_batch(&texts).await? }.into_iter();
+        report.reembedded += texts.len();
+
+        let mut docs = Vec::with_capacity(records.len());
+        for record in records {
+            let embedding = match record.embedding {
+                Some(embedding) if usable(Some(&embedding)) => embedding,
+                _ => embedded.next().context("The embedding backend returned fewer embeddings than texts")?,
+            };
+            docs.push(Document { id: record.id, content: record.content, embedding, metadata: record.metadata, user_id: record.user_id });
+        }
+
+        {
+            let mut store = self.store.write().unwrap();
+            for doc in docs {
+                if store.contains(&doc.id) {
+                    report.replaced += 1;
+                } else {
+                    report.added += 1;
+                }
+                store.add_document(doc)?;
+            }
+        }
+        self.bump_index_version();
+        Ok(())
+    }
+}
@@ -0,0 +1,308 @@
+//! Print-friendly subject reports assembled from the scraped data, no LLM needed
+//! unless a study-guide section is asked for.
+
+use std::path::{Path, PathBuf};
+
+use crate::llm::{ChatMessage, LlmClient};
+use crate::{config, scrapper};
+
+/// Announcement text kept in the report; the scraped list is newest first
+const ANNOUNCEMENT_CHARS: usize = 4000;
+/// Dated lines listed under "Deadlines"
+const MAX_DEADLINES: usize = 30;
+/// Cap on the generated study guide
+const SUMMARY_MAX_TOKENS: usize = 1500;
+/// Scraped text sent along with the study-guide request
+const SUMMARY_INPUT_CHARS: usize = 12_000;
+
+pub struct ReportOptions {
+    pub out: Option<PathBuf>,
+    pub with_summary: bool,
+    pub pdf: bool,
+}
+
+struct Section {
+    title: &'static str,
+    anchor: &'static str,
+    body: String,
+}
+
+/// `polirag report <subject>`: write the subject report and print where it went
+pub async fn run_report(subject: &str, options: ReportOptions) -> anyhow::Result<()> {
+    let subject_dir = find_subject_dir(subject)?;
+    let name = scrapper::layout::display_name(&subject_dir);
+    let summary = std::fs::read_to_string(subject_dir.join("summary.md")).unwrap_or_default();
+    let scraped = summary_sections(&summary);
+    let scraped_section = |marker: &str| scraped.iter().find(|(m, _)| m == marker).map(|(_, text)| text.as_str()).unwrap_or("");
+
+    let description = scraped_section("GUIA DOCENT DESCRIPTION");
+    let guia = scraped_section("GUIA DOCENT");
+    let announcements = scraped_section("ANUNCIS");
+
+    let mut sections = vec![
+        Section {
+            title: "Description",
+            anchor: "description",
+            body: or_missing(description.to_string(), "no guia docent description was scraped. Run a full sync (not `--resources-only`)."),
+        },
+        Section {
+            title: "Professors",
+            anchor: "professors",
+            body: or_missing(scraped_section("PROFESSORS").to_string(), "no professor list was scraped. Run a full sync (not `--resources-only`)."),
+        },
+        Section {
+            title: "Recent announcements",
+            anchor: "recent-announcements",
+            body: or_missing(truncate_chars(announcements, ANNOUNCEMENT_CHARS), "no announcements were scraped for this subject."),
+        },
+        Section {
+            title: "Deadlines",
+            anchor: "deadlines",
+            body: or_missing(
+                deadlines(&[announcements, guia]).iter().map(|line| format!("- {}", line)).collect::<Vec<_>>().join("\n"),
+                "no dated lines found in the announcements or the guia docent.",
+            ),
+        },
+        Section {
+            title: "Files",
+            anchor: "files",
+            body: or_missing(file_inventory(&name), "no downloaded files for this subject."),
+        },
+    ];
+
+    if options.with_summary {
+        let body = match study_guide(&name, &summary).await {
+            Ok(guide) => guide,
+            Err(e) => {
+                eprintln!("Study guide skipped: {:#}", e);
+                format!("_Not available: the study guide could not be generated ({})._", e)
+            }
+        };
+        sections.push(Section { title: "Study guide", anchor: "study-guide", body });
+    }
+
+    let out_path = output_path(&name, options.out)?;
+    if let Some(parent) = out_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(&out_path, render_markdown(&name, &sections, false))?;
+    println!("Report written to {}", out_path.display());
+
+    if options.pdf {
+        let pdf_path = out_path.with_extension("pdf");
+        let html = render_html(&name, &render_markdown(&name, &sections, true));
+        let html_path = out_path.with_extension("html");
+        std::fs::write(&html_path, html)?;
+        let result = {
+            let (html_path, pdf_path) = (html_path.clone(), pdf_path.clone());
+            tokio::task::spawn_blocking(move || print_to_pdf(&html_path, &pdf_path)).await?
+        };
+        let _ = std::fs::remove_file(&html_path);
+        result?;
+        println!("PDF written to {}", pdf_path.display());
+    }
+
+    Ok(())
+}
+
+/// Subject folder under the scraped data directory: exact name or site id first (ignoring case), then a unique partial name match
+fn find_subject_dir(query: &str) -> anyhow::Result<PathBuf> {
+    let data_dir = config::Config::get_scraped_data_dir();
+    let mut subjects: Vec<PathBuf> = std::fs::read_dir(&data_dir)
+        .map(|entries| entries.flatten().map(|e| e.path()).filter(|p| p.is_dir()).collect())
+        .unwrap_or_default();
+    subjects.retain(|p| p.file_name().is_some_and(|n| !n.to_string_lossy().starts_with('.')));
+    subjects.sort();
+    if subjects.is_empty() {
+        anyhow::bail!("No scraped subjects in {}. Run a sync first.", data_dir.display());
+    }
+
+    let query = query.trim().to_lowercase();
+    let name_of = |p: &PathBuf| scrapper::layout::display_name(p).to_lowercase();
+    let dir_of = |p: &PathBuf| p.file_name().map(|n| n.to_string_lossy().to_lowercase()).unwrap_or_default();
+    if let Some(exact) = subjects.iter().find(|p| name_of(p) == query || dir_of(p) == query) {
+        return Ok(exact.clone());
+    }
+
+    let matches: Vec<&PathBuf> = subjects.iter().filter(|p| name_of(p).contains(&query)).collect();
+    match matches.as_slice() {
+        [single] => Ok((*single).clone()),
+        [] => anyhow::bail!("No subject matches '{}'. Scraped subjects:\n  {}", query, list_names(&subjects)),
+        _ => anyhow::bail!("'{}' matches several subjects, be more specific:\n  {}", query, list_names(&matches.into_iter().cloned().collect::<Vec<_>>())),
+    }
+}
+
+fn list_names(dirs: &[PathBuf]) -> String {
+    dirs.iter().map(|p| scrapper::layout::display_name(p)).collect::<Vec<_>>().join("\n  ")
+}
+
+/// `summary.md` split on its `--- NAME ---` markers
+fn summary_sections(summary: &str) -> Vec<(String, String)> {
+    let mut sections: Vec<(String, String)> = Vec::new();
+    for line in summary.lines() {
+        let trimmed = line.trim();
+        if let Some(marker) = trimmed.strip_prefix("--- ").and_then(|rest| rest.strip_suffix(" ---")) {
+            sections.push((marker.to_string(), String::new()));
+        } else if let Some((_, text)) = sections.last_mut() {
+            text.push_str(line);
+            text.push('\n');
+        }
+    }
+    for (_, text) in &mut sections {
+        *text = text.trim().to_string();
+    }
+    sections
+}
+
+fn or_missing(body: String, note: &str) -> String {
+    if body.trim().is_empty() {
+        format!("_Not available: {}_", note)
+    } else {
+        body
+    }
+}
+
+fn truncate_chars(text: &str, max_chars: usize) -> String {
+    match text.char_indices().nth(max_chars) {
+        Some((cut, _)) => format!("{}\n\n_(truncated)_", text[..cut].trim_end()),
+        None => text.to_string(),
+    }
+}
+
+/// Lines carrying a date like `12/03/2025`, `12-3-25` or `12.03.2025`, deduplicated in order
+fn deadlines(texts: &[&str]) -> Vec<String> {
+    let mut found: Vec<String> = Vec::new();
+    for line in texts.iter().flat_map(|t| t.lines()) {
+        let line = line.trim();
+        if line.chars().count() > 300 || !line.split(|c: char| !(c.is_ascii_digit() || matches!(c, '/' | '-' | '.'))).any(is_date) {
+            continue;
+        }
+        if !found.iter().any(|f| f == line) {
+            found.push(line.to_string());
+        }
+        if found.len() >= MAX_DEADLINES {
+            break;
+        }
+    }
+    found
+}
+
+fn is_date(token: &str) -> bool {
+    let token = token.trim_end_matches('.');
+    let Some(separator) = token.chars().find(|c| !c.is_ascii_digit()) else { return false };
+    let parts: Vec<&str> = token.split(separator).collect();
+    let [day, month, year] = parts.as_slice() else { return false };
+    let in_range = |part: &str, max: u32| part.len() <= 2 && part.parse::<u32>().is_ok_and(|n| (1..=max).contains(&n));
+    in_range(day, 31) && in_range(month, 12) && matches!(year.len(), 2 | 4) && year.chars().all(|c| c.is_ascii_digit())
+}
+
+/// Markdown table of the subject's files from the same manifest `polirag status` diffs against
+fn file_inventory(subject: &str) -> String {
+    let manifest = scrapper::changes::snapshot(&config::Config::get_scraped_data_dir());
+    let Some(files) = manifest.get(subject) else { return String::new() };
+
+    let mut paths: Vec<_> = files.iter().filter(|(path, _)| path.as_str() != "summary.md").collect();
+    if paths.is_empty() {
+        return String::new();
+    }
+    paths.sort_by(|a, b| a.0.cmp(b.0));
+
+    let total: u64 = paths.iter().map(|(_, entry)| entry.size).sum();
+    let mut table = format!("Files: {}, {} in total.\n\n| File | Size |\n|---|---:|\n", paths.len(), scrapper::format_bytes(total));
+    for (path, entry) in paths {
+        table.push_str(&format!("| {} | {} |\n", path.replace('|', "\\|"), scrapper::format_bytes(entry.size)));
+    }
+    table
+}
+
+/// LLM-written study guide over the scraped summary, using the configured provider and saved model
+async fn study_guide(subject: &str, summary: &str) -> anyhow::Result<String> {
+    if summary.trim().is_empty() {
+        anyhow::bail!("nothing was scraped for this subject");
+    }
+
+    let config = config::Config::load();
+    let mut llm = LlmClient::new(Some(config.chat_url()), None, None);
+    llm.set_headers(config.request_headers(&config.llm_provider));
+    if let Some(model) = config::Config::get_last_model() {
+        llm.set_model(&model);
+    }
+
+    let prompt = vec![
+        ChatMessage::new("system", "Write a concise study guide in Markdown for the subject below: key topics, how it is assessed, important dates and what to prioritise. Use only the given material and write in its language. Start with the first bullet or paragraph, no title."),
+        ChatMessage::new("user", format!("Subject: {}\n\n{}", subject, truncate_chars(summary, SUMMARY_INPUT_CHARS))),
+    ];
+
+    let (content, _) = llm.chat_limited(&prompt, Some(SUMMARY_MAX_TOKENS)).await?;
+    // Reasoning models prefix the answer with their thinking
+    let guide = content.rsplit("</think>").next().unwrap_or("").trim().to_string();
+    if guide.is_empty() {
+        anyhow::bail!("the model returned an empty answer");
+    }
+    Ok(guide)
+}
+
+/// `--out` may name a file or an existing directory; the default is `reports/<subject>.md` in the app data dir
+fn output_path(subject: &str, out: Option<PathBuf>) -> anyhow::Result<PathBuf> {
+    let file_name = format!("{}.md", subject.replace(['/', '\\', ':'], "-").trim());
+    Ok(match out {
+        Some(path) if path.is_dir() => path.join(file_name),
+        Some(path) => path,
+        None => config::Config::get_app_data_dir().join("reports").join(file_name),
+    })
+}
+
+/// With `heading_ids`, headings carry `{#anchor}` attributes so the HTML render's
+/// table of contents links resolve; plain Markdown viewers derive the same anchors from the titles.
+fn render_markdown(subject: &str, sections: &[Section], heading_ids: bool) -> String {
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let mut md = format!("# {}\n\n_Report generated by polirag on {}._\n\n## Contents\n\n", subject, scrapper::processing::format_date(now / 86_400));
+    for (i, section) in sections.iter().enumerate() {
+        md.push_str(&format!("{}. [{}](#{})\n", i + 1, section.title, section.anchor));
+    }
+    for section in sections {
+        let id = if heading_ids { format!(" {{#{}}}", section.anchor) } else { String::new() };
+        md.push_str(&format!("\n## {}{}\n\n{}\n", section.title, id, section.body));
+    }
+    md
+}
+
+fn render_html(subject: &str, markdown: &str) -> String {
+    use pulldown_cmark::{html, Options, Parser};
+
+    let mut body = String::new();
+    html::push_html(&mut body, Parser::new_ext(markdown, Options::ENABLE_TABLES | Options::ENABLE_HEADING_ATTRIBUTES));
+    format!(
+        "<html>
+        <head>
+            <meta charset='UTF-8'>
+            <title>{}</title>
+            <style>
+                body {{ font-family: sans-serif; margin: 40px; line-height: 1.4; }}
+                h1 {{ color: #000080; border-bottom: 2px solid #000080; padding-bottom: 10px; }}
+                h2 {{ color: #333; margin-top: 30px; border-bottom: 1px solid #ccc; page-break-after: avoid; }}
+                table {{ border-collapse: collapse; width: 100%; font-size: 0.9em; }}
+                td, th {{ border: 1px solid #ccc; padding: 4px 8px; text-align: left; }}
+                p {{ white-space: pre-wrap; }}
+            </style>
+        </head>
+        <body>{}</body>
+        </html>",
+        subject, body
+    )
+}
+
+/// Render the HTML file in headless Chrome and save it as a PDF
+fn print_to_pdf(html_path: &Path, pdf_path: &Path) -> anyhow::Result<()> {
+    use headless_chrome::{Browser, LaunchOptions};
+
+    let browser = Browser::new(LaunchOptions { headless: true, path: scrapper::browser::chrome_path(), ..Default::default() })
+        .map_err(|e| anyhow::anyhow!("Could not start Chrome for the PDF ({}); the Markdown report is still there", e))?;
+    let tab = browser.new_tab()?;
+    let file_url = scrapper::browser::file_url(html_path)?;
+    tab.navigate_to(&file_url)?;
+    tab.wait_until_navigated()?;
+    let pdf = tab.print_to_pdf(None)?;
+    std::fs::write(pdf_path, pdf)?;
+    Ok(())
+}
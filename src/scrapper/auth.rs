@@ -7,8 +7,71 @@ pub struct AuthCredentials {
     pub pin: String,
 }
 
+/// Login failures the user can act on; anything else is a network or portal problem
+#[derive(Debug, thiserror::Error)]
+pub enum LoginError {
+    #[error("Could not start the headless browser (is Chrome or Chromium installed?): {0}")]
+    BrowserUnavailable(String),
+    #[error("PoliformaT did not accept the credentials: {0}")]
+    Rejected(String),
+    /// Caught before starting the browser, see `check_format`
+    #[error("{0}")]
+    InvalidFormat(String),
+}
+
+/// Exit code of `polirag login` for `error`: 2 rejected credentials, 3 no browser, 4 network or other
+pub fn login_exit_code(error: &anyhow::Error) -> i32 {
+    match error.downcast_ref::<LoginError>() {
+        Some(LoginError::Rejected(_)) | Some(LoginError::InvalidFormat(_)) => 2,
+        Some(LoginError::BrowserUnavailable(_)) => 3,
+        None => 4,
+    }
+}
+
+/// Catch obviously malformed credentials before the 20-40s browser round trip.
+/// Usernames are a DNI (8 digits and a letter), an NIE (X/Y/Z, 7 digits and a letter)
+/// or a UPV user name; the PIN only has to be non-blank.
+pub fn check_format(creds: &AuthCredentials) -> std::result::Result<(), LoginError> {
+    let invalid = |msg: &str| Err(LoginError::InvalidFormat(msg.to_string()));
+    let username = creds.username.as_str();
+
+    if username.trim().is_empty() {
+        return invalid("Enter your DNI or UPV username");
+    }
+    if username.chars().any(char::is_whitespace) {
+        return invalid("The username can't contain spaces");
+    }
+    if !username.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-')) {
+        return invalid("The username can only contain letters, digits, '.', '_' and '-'");
+    }
+
+    let upper = username.to_ascii_uppercase();
+    let digits = upper.chars().take_while(char::is_ascii_digit).count();
+    if digits > 0 {
+        // Starts with a digit: a DNI, with or without its letter
+        let rest = &upper[digits..];
+        if digits != 8 || rest.len() > 1 || !rest.chars().all(|c| c.is_ascii_alphabetic()) {
+            return invalid("A DNI has 8 digits, optionally followed by its letter (e.g. 12345678Z)");
+        }
+    } else if upper.len() == 9 && upper.starts_with(['X', 'Y', 'Z']) && upper[1..8].chars().all(|c| c.is_ascii_digit()) {
+        if !upper.ends_with(|c: char| c.is_ascii_alphabetic()) {
+            return invalid("An NIE ends with a letter (e.g. X1234567L)");
+        }
+    } else if username.len() < 3 || username.len() > 32 {
+        return invalid("The username should be between 3 and 32 characters");
+    }
+
+    if creds.pin.trim().is_empty() {
+        return invalid("Enter your PIN");
+    }
+    if creds.pin.chars().any(char::is_control) {
+        return invalid("The PIN contains control characters; type it again");
+    }
+    Ok(())
+}
+
 // Helper function to perform headless login and extract the JSESSIONID or relevant cookies.
-pub fn headless_login(creds: &AuthCredentials) -> Result<String> {
+pub fn headless_login(creds: &AuthCredentials, base_url: &url::Url) -> Result<String> {
     tracing::info!("Starting headless login (Optimized)...");
 
     // Optimized Launch Options
@@ -16,18 +79,19 @@ pub fn headless_login(creds: &AuthCredentials) -> Result<String> {
         headless: true,
         enable_logging: false, // Reduce noise
         window_size: Some((1280, 800)), 
+        path: super::browser::chrome_path(),
         ..Default::default()
     };
     
     tracing::info!("Launching browser...");
-    let browser = Browser::new(options).context("Failed to launch headless browser")?;
+    let browser = Browser::new(options).map_err(|e| LoginError::BrowserUnavailable(e.to_string()))?;
     let tab = browser.new_tab().context("Failed to open new tab")?;
 
     // 1. Navigate to Login
     tracing::info!("Navigating to Login Portal...");
     // Direct link to the Auth portal to skip redirects if possible.
     // However, the safest is still the main entry point.
-    tab.navigate_to("https://poliformat.upv.es/portal/login")?;
+    tab.navigate_to(&super::portal_url(base_url, "/portal/login"))?;
     
     // 2. Race: Check for Button OR Input
     // We poll quickly
@@ -41,20 +105,20 @@ pub fn headless_login(creds: &AuthCredentials) -> Result<String> {
         tracing::debug!("DEBUG polling: URL={} Title={}", current_url, current_title);
 
         // Check for DNI Input (common in Poliformat) OR Username (CAS)
-        if let Ok(_) = tab.find_element("input[name='dni']") {
+        if tab.find_element("input[name='dni']").is_ok() {
             tracing::info!("FOUND: DNI Input field (PoliformaT style).");
             found_input = true;
             break;
         }
         
-        if let Ok(_) = tab.find_element("input[name='username']") {
+        if tab.find_element("input[name='username']").is_ok() {
              tracing::info!("FOUND: Username Input field (CAS style).");
              found_input = true;
              break;
         }
         
         // Sometimes the input has id="username" but name is different, or typical CAS structure
-        if let Ok(_) = tab.find_element("#username") {
+        if tab.find_element("#username").is_ok() {
              tracing::info!("FOUND: #username Input field.");
              found_input = true;
              break;
@@ -144,7 +208,7 @@ pub fn headless_login(creds: &AuthCredentials) -> Result<String> {
         let current_url = tab.get_url();
         if current_url.contains("/portal/site/") || 
            current_url.contains("/portal/pda/") ||
-           (current_url.contains("poliformat.upv.es/portal") && !current_url.contains("/login")) {
+           (current_url.starts_with(&super::portal_url(base_url, "/portal")) && !current_url.contains("/login")) {
             tracing::info!("Login successful! Detected authenticated URL: {}", current_url);
             login_success = true;
             break;
@@ -169,7 +233,7 @@ pub fn headless_login(creds: &AuthCredentials) -> Result<String> {
     if !login_success {
         let final_url = tab.get_url();
         tracing::error!("Login detection failed. Final URL: {}", final_url);
-        anyhow::bail!("Login failed: Could not detect authenticated session after 20s. Final URL: {}", final_url);
+        return Err(LoginError::Rejected(format!("no authenticated session after 20s (final URL: {})", final_url)).into());
     }
 
     tracing::info!("Session active! Extracting cookies...");
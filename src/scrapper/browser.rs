@@ -0,0 +1,96 @@
+//! What the headless browser needs from the host: where Chrome is installed, and paths
+//! in a form it accepts on every platform.
+
+use std::path::{Path, PathBuf};
+
+/// Chrome (or Chromium/Edge) executable to launch. `None` lets headless_chrome's own
+/// discovery run, which covers `$CHROME`, the `PATH` and the machine-wide registry entry.
+pub fn chrome_path() -> Option<PathBuf> {
+    if headless_chrome::browser::default_executable().is_ok() {
+        return None;
+    }
+    let found = windows_chrome_path();
+    if let Some(path) = &found {
+        tracing::info!("Using the browser at {}", path.display());
+    }
+    found
+}
+
+/// Per-user installs and the Program Files folders, which headless_chrome doesn't probe
+#[cfg(windows)]
+fn windows_chrome_path() -> Option<PathBuf> {
+    const INSTALLS: &[&str] = &[
+        r"Google\Chrome\Application\chrome.exe",
+        r"Chromium\Application\chrome.exe",
+        r"Microsoft\Edge\Application\msedge.exe",
+    ];
+
+    let from_registry = ["HKCU", "HKLM"].iter().find_map(|hive| {
+        let key = format!(r"{}\Software\Microsoft\Windows\CurrentVersion\App Paths\chrome.exe", hive);
+        let output = std::process::Command::new("reg").args(["query", &key, "/ve"]).output().ok()?;
+        // "    (Default)    REG_SZ    C:\...\chrome.exe"
+        let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+        let value = stdout.lines().find_map(|line| line.split_once("REG_SZ").map(|(_, v)| v.trim().to_string()))?;
+        Some(PathBuf::from(value))
+    });
+
+    from_registry.into_iter()
+        .chain(["LOCALAPPDATA", "PROGRAMFILES", "PROGRAMFILES(X86)"].iter()
+            .filter_map(|var| std::env::var_os(var))
+            .flat_map(|root| INSTALLS.iter().map(move |install| Path::new(&root).join(install))))
+        .find(|path| path.is_file())
+}
+
+#[cfg(not(windows))]
+fn windows_chrome_path() -> Option<PathBuf> {
+    None
+}
+
+/// `path` without the `\\?\` prefix `canonicalize` adds on Windows, which Chrome's
+/// download and file URL handling reject. Other paths are returned unchanged.
+pub fn strip_verbatim(path: &Path) -> PathBuf {
+    let text = path.to_string_lossy();
+    if let Some(unc) = text.strip_prefix(r"\\?\UNC\") {
+        PathBuf::from(format!(r"\\{}", unc))
+    } else if let Some(local) = text.strip_prefix(r"\\?\") {
+        PathBuf::from(local)
+    } else {
+        path.to_path_buf()
+    }
+}
+
+/// Absolute form of `path` for the browser, e.g. as its download directory
+pub fn absolute(path: &Path) -> std::io::Result<PathBuf> {
+    Ok(strip_verbatim(&std::fs::canonicalize(path)?))
+}
+
+/// `file://` URL of a local file, `file:///C:/...` on Windows
+pub fn file_url(path: &Path) -> anyhow::Result<String> {
+    let path = absolute(path)?;
+    url::Url::from_file_path(&path)
+        .map(String::from)
+        .map_err(|_| anyhow::anyhow!("Cannot turn {} into a file URL", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verbatim_prefixes_are_dropped_and_other_paths_kept() {
+        assert_eq!(strip_verbatim(Path::new(r"\\?\C:\Users\ana\polirag")), PathBuf::from(r"C:\Users\ana\polirag"));
+        assert_eq!(strip_verbatim(Path::new(r"\\?\UNC\server\share\polirag")), PathBuf::from(r"\\server\share\polirag"));
+        assert_eq!(strip_verbatim(Path::new("/home/ana/polirag")), PathBuf::from("/home/ana/polirag"));
+    }
+
+    #[test]
+    fn local_files_become_absolute_file_urls() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("page.html"), "<html></html>").unwrap();
+        let url = file_url(&dir.path().join(".").join("page.html")).unwrap();
+        assert!(url.starts_with("file:///"), "{}", url);
+        assert!(url.ends_with("/page.html"), "{}", url);
+        assert!(!url.contains("/./"), "{}", url);
+        assert!(file_url(&dir.path().join("missing.html")).is_err());
+    }
+}
@@ -0,0 +1,253 @@
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hasher;
+use std::io::Read;
+use std::path::Path;
+
+const REPORT_FILE: &str = "last_sync.json";
+
+/// Content hash and size of a scraped file
+#[derive(Clone, Copy, PartialEq)]
+pub struct FileEntry {
+    pub hash: u64,
+    pub size: u64,
+}
+
+/// Files of every subject directory: subject -> relative path -> entry
+pub type Manifest = BTreeMap<String, HashMap<String, FileEntry>>;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FileChange {
+    pub path: String,
+    pub old_size: Option<u64>,
+    pub new_size: Option<u64>,
+}
+
+impl FileChange {
+    /// "tema3.pdf (1.2 MB → 1.4 MB)"
+    pub fn describe(&self) -> String {
+        match (self.old_size, self.new_size) {
+            (Some(old), Some(new)) => format!("{} ({} → {})", self.path, super::format_bytes(old), super::format_bytes(new)),
+            (None, Some(size)) | (Some(size), None) => format!("{} ({})", self.path, super::format_bytes(size)),
+            (None, None) => self.path.clone(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct SubjectChanges {
+    pub subject: String,
+    pub added: Vec<FileChange>,
+    pub updated: Vec<FileChange>,
+    pub removed: Vec<FileChange>,
+}
+
+impl SubjectChanges {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.updated.is_empty() && self.removed.is_empty()
+    }
+
+    /// "+3 ~1 -0"
+    pub fn counts(&self) -> String {
+        format!("+{} ~{} -{}", self.added.len(), self.updated.len(), self.removed.len())
+    }
+}
+
+/// A subject whose sync replaced or removed more of its indexed files than the churn
+/// threshold, and whether the new versions were indexed
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ChurnDecision {
+    pub subject: String,
+    pub replaced: usize,
+    pub removed: usize,
+    /// Files of the subject in the index before the sync
+    pub indexed: usize,
+    /// `false`: the indexed versions were kept
+    pub accepted: bool,
+}
+
+impl ChurnDecision {
+    /// "12 changed and 3 removed of 20 indexed files: applied"
+    pub fn describe(&self) -> String {
+        let outcome = if self.accepted { "applied, old versions archived" } else { "kept the indexed versions" };
+        format!("{} changed and {} removed of {} indexed files: {}", self.replaced, self.removed, self.indexed, outcome)
+    }
+}
+
+/// How long a sync took and how much of it the throttle added
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default)]
+pub struct SyncTiming {
+    pub scrape_secs: u64,
+    pub index_secs: u64,
+    /// Waits between page navigations and backoffs, part of `scrape_secs`
+    pub throttle_secs: u64,
+    /// Navigations retried after landing on an error page
+    pub backoffs: usize,
+    /// Throttling was off (`polirag sync --fast`)
+    pub fast: bool,
+}
+
+impl SyncTiming {
+    /// "scraping 6m 10s (1m 2s throttled, 1 backoff) │ indexing 40s"
+    pub fn describe(&self) -> String {
+        let throttle = if self.fast {
+            "unthrottled".to_string()
+        } else {
+            let backoffs = match self.backoffs {
+                0 => String::new(),
+                1 => ", 1 backoff".to_string(),
+                n => format!(", {} backoffs", n),
+            };
+            format!("{} throttled{}", format_secs(self.throttle_secs), backoffs)
+        };
+        format!("scraping {} ({}) │ indexing {}", format_secs(self.scrape_secs), throttle, format_secs(self.index_secs))
+    }
+}
+
+/// "1h 5m", "6m 10s", "40s"
+fn format_secs(secs: u64) -> String {
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m {}s", secs / 60, secs % 60),
+        _ => format!("{}h {}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+/// What the last sync changed, saved next to the index
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct SyncReport {
+    /// Unix time the sync finished
+    pub finished_at: u64,
+    /// Only subjects with changes
+    pub subjects: Vec<SubjectChanges>,
+    /// Subjects over the churn threshold
+    #[serde(default)]
+    pub churn: Vec<ChurnDecision>,
+    /// Site ids of every enrolled subject PoliformaT listed, synced or not; `polirag clean`
+    /// takes folders of other subjects as orphaned
+    #[serde(default)]
+    pub enrolled: Vec<String>,
+    #[serde(default)]
+    pub timing: SyncTiming,
+}
+
+impl SyncReport {
+    pub fn new(before: &Manifest, after: &Manifest) -> Self {
+        let finished_at = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        Self { finished_at, subjects: diff(before, after), churn: Vec::new(), enrolled: Vec::new(), timing: SyncTiming::default() }
+    }
+
+    pub fn save(&self) -> anyhow::Result<()> {
+        let path = crate::config::Config::get_app_data_dir().join(REPORT_FILE);
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Size of the files this sync added or updated, roughly what it downloaded
+    pub fn downloaded_bytes(&self) -> u64 {
+        self.subjects.iter()
+            .flat_map(|s| s.added.iter().chain(&s.updated))
+            .filter_map(|f| f.new_size)
+            .sum()
+    }
+
+    pub fn load_last() -> Option<Self> {
+        let path = crate::config::Config::get_app_data_dir().join(REPORT_FILE);
+        let contents = std::fs::read_to_string(path).ok()?;
+        serde_json::from_str(&contents).ok()
+    }
+}
+
+/// Hash every file under the subject directories of `data_dir`.
+/// Files are streamed, so no content is kept in memory.
+pub fn snapshot(data_dir: &Path) -> Manifest {
+    let mut manifest = Manifest::new();
+    let Ok(entries) = std::fs::read_dir(data_dir) else { return manifest };
+
+    for entry in entries.flatten() {
+        let subject_dir = entry.path();
+        if !subject_dir.is_dir() || entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let subject = super::layout::display_name(&subject_dir);
+
+        let files = manifest.entry(subject).or_default();
+        for file in walkdir::WalkDir::new(&subject_dir).into_iter().filter_map(|e| e.ok()) {
+            if !file.file_type().is_file() {
+                continue;
+            }
+            let name = file.file_name().to_string_lossy();
+            // Browser download leftovers and the folder's own metadata and clean record
+            if name.starts_with('.') || name.ends_with(".crdownload") || (file.depth() == 1 && (name == super::layout::META_FILE || name == super::layout::CLEANED_FILE)) {
+                continue;
+            }
+            if let Some(entry) = hash_file(file.path()) {
+                let rel_path = super::layout::relative_id(file.path(), &subject_dir);
+                files.insert(rel_path, entry);
+            }
+        }
+    }
+
+    manifest
+}
+
+/// Hash and size of the bytes of `path`, `None` when it can't be read
+pub fn hash_file(path: &Path) -> Option<FileEntry> {
+    let mut file = std::fs::File::open(path).ok()?;
+    let mut hasher = DefaultHasher::new();
+    let mut buffer = [0u8; 64 * 1024];
+    let mut size = 0u64;
+    loop {
+        let read = file.read(&mut buffer).ok()?;
+        if read == 0 {
+            break;
+        }
+        hasher.write(&buffer[..read]);
+        size += read as u64;
+    }
+    Some(FileEntry { hash: hasher.finish(), size })
+}
+
+/// Per-subject added, updated and removed files between two snapshots
+pub fn diff(before: &Manifest, after: &Manifest) -> Vec<SubjectChanges> {
+    let empty = HashMap::new();
+    let mut subjects: Vec<&String> = before.keys().chain(after.keys()).collect();
+    subjects.sort();
+    subjects.dedup();
+
+    let mut changes = Vec::new();
+    for subject in subjects {
+        let old = before.get(subject).unwrap_or(&empty);
+        let new = after.get(subject).unwrap_or(&empty);
+        let mut subject_changes = SubjectChanges { subject: subject.clone(), ..Default::default() };
+
+        for (path, entry) in new {
+            match old.get(path) {
+                None => subject_changes.added.push(FileChange { path: path.clone(), old_size: None, new_size: Some(entry.size) }),
+                Some(previous) if previous != entry => subject_changes.updated.push(FileChange {
+                    path: path.clone(),
+                    old_size: Some(previous.size),
+                    new_size: Some(entry.size),
+                }),
+                Some(_) => {}
+            }
+        }
+        for (path, entry) in old {
+            if !new.contains_key(path) {
+                subject_changes.removed.push(FileChange { path: path.clone(), old_size: Some(entry.size), new_size: None });
+            }
+        }
+
+        if !subject_changes.is_empty() {
+            for list in [&mut subject_changes.added, &mut subject_changes.updated, &mut subject_changes.removed] {
+                list.sort_by(|a, b| a.path.cmp(&b.path));
+            }
+            changes.push(subject_changes);
+        }
+    }
+    changes
+}
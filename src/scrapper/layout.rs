@@ -0,0 +1,135 @@
+//! Where each subject lives on disk. Folders are named after the subject's site id, which is
+//! unique; the human name is kept in `subject.json` next to `summary.md` and `resources/`.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use super::Subject;
+
+/// Id, name and URL of the subject a folder belongs to
+pub const META_FILE: &str = "subject.json";
+/// What `polirag clean` removed from the folder, see `CleanedRecord`
+pub const CLEANED_FILE: &str = "cleaned.json";
+
+/// Files `polirag clean` deleted from a subject folder on purpose. A full sync takes a record
+/// with files as "resources already downloaded", so it doesn't fetch the removed archives again.
+#[derive(Serialize, Deserialize, Default)]
+pub struct CleanedRecord {
+    /// Unix time of the last clean
+    pub cleaned_at: u64,
+    /// Paths relative to the subject folder, with their sizes
+    pub files: BTreeMap<String, u64>,
+}
+
+/// Folder name for a site id. Ids look like `GRA_11673_2025`; anything unexpected becomes `_`.
+pub fn dir_name(id: &str) -> String {
+    id.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.') { c } else { '_' })
+        .collect::<String>()
+        .trim_start_matches('.')
+        .to_string()
+}
+
+pub fn subject_dir(data_dir: &Path, sub: &Subject) -> PathBuf {
+    data_dir.join(dir_name(&sub.id))
+}
+
+pub fn write_meta(dir: &Path, sub: &Subject) -> anyhow::Result<()> {
+    std::fs::write(dir.join(META_FILE), serde_json::to_string_pretty(sub)?)?;
+    Ok(())
+}
+
+/// `None` for folders written before subjects were stored by id
+pub fn read_meta(dir: &Path) -> Option<Subject> {
+    let contents = std::fs::read_to_string(dir.join(META_FILE)).ok()?;
+    serde_json::from_str(&contents).ok()
+}
+
+pub fn read_cleaned(dir: &Path) -> CleanedRecord {
+    std::fs::read_to_string(dir.join(CLEANED_FILE))
+        .ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+/// Add `files` (relative path, size) to the folder's `CleanedRecord`
+pub fn record_cleaned(dir: &Path, files: impl IntoIterator<Item = (String, u64)>, now: u64) -> anyhow::Result<()> {
+    let mut record = read_cleaned(dir);
+    record.cleaned_at = now;
+    record.files.extend(files);
+    std::fs::write(dir.join(CLEANED_FILE), serde_json::to_string_pretty(&record)?)?;
+    Ok(())
+}
+
+/// Subject name for a folder, falling back to the folder name for the old name-based layout
+pub fn display_name(dir: &Path) -> String {
+    read_meta(dir)
+        .map(|sub| sub.name)
+        .unwrap_or_else(|| dir.file_name().unwrap_or_default().to_string_lossy().to_string())
+}
+
+/// `path` relative to `base` with `/` separators on every platform, as used in document ids
+/// and `filename` metadata
+pub fn relative_id(path: &Path, base: &Path) -> String {
+    path.strip_prefix(base).unwrap_or(path)
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Folder name the old layout derived from the subject name
+fn legacy_dir_name(name: &str) -> String {
+    name.replace('/', "-").replace(':', "").trim().to_string()
+}
+
+/// Move folders from the old name-based layout to their id-based place. Only the subjects'
+/// current names can tell which folder is whose, so this runs at the start of a sync.
+/// A folder several subjects sanitize to is left alone: it can't be told apart, and the
+/// sync downloads each of them again into its own folder. Returns the number of folders moved.
+pub fn migrate_legacy_dirs(data_dir: &Path, subjects: &[Subject]) -> usize {
+    let mut moved = 0;
+    for sub in subjects {
+        let legacy_name = legacy_dir_name(&sub.name);
+        let legacy = data_dir.join(&legacy_name);
+        let target = subject_dir(data_dir, sub);
+        if legacy == target || !legacy.is_dir() || read_meta(&legacy).is_some() {
+            continue;
+        }
+
+        let sharing = subjects.iter().filter(|other| legacy_dir_name(&other.name) == legacy_name).count();
+        if sharing > 1 {
+            tracing::warn!("'{}' holds data of {} subjects with similar names; leaving it in place", legacy.display(), sharing);
+            continue;
+        }
+        if target.exists() {
+            tracing::warn!("Not migrating '{}': '{}' already exists", legacy.display(), target.display());
+            continue;
+        }
+
+        match std::fs::rename(&legacy, &target).and_then(|_| write_meta(&target, sub).map_err(std::io::Error::other)) {
+            Ok(()) => {
+                tracing::info!("Moved '{}' to '{}'", legacy.display(), target.display());
+                moved += 1;
+            }
+            Err(e) => tracing::error!("Failed to migrate '{}': {}", legacy.display(), e),
+        }
+    }
+    moved
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relative_ids_use_forward_slashes() {
+        let base = Path::new("data").join("GRA_11673_2025");
+        let file = base.join("resources").join("Tema 1").join("apuntes.pdf");
+        assert_eq!(relative_id(&file, &base), "resources/Tema 1/apuntes.pdf");
+        // Outside the base the whole path is kept
+        assert_eq!(relative_id(Path::new("other/notes.md"), &base), "other/notes.md");
+    }
+}
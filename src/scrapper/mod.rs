@@ -1,19 +1,68 @@
 pub mod auth;
 pub mod processing;
+pub mod changes;
+pub mod layout;
+pub mod browser;
+pub mod throttle;
+pub mod pages;
 
 use reqwest_cookie_store::CookieStoreMutex;
 use reqwest::Client;
 use std::sync::Arc;
 use url::Url;
 
+use pages::ResourceEntry;
+
+pub const DEFAULT_BASE_URL: &str = "https://poliformat.upv.es";
+/// Overrides `DEFAULT_BASE_URL`, e.g. to point the scraper at a mock server
+const BASE_URL_ENV: &str = "POLIFORMAT_BASE_URL";
+
+/// `path` on the PoliformaT instance at `base_url`, e.g. "/portal/login"
+pub fn portal_url(base_url: &Url, path: &str) -> String {
+    base_url.join(path).map(String::from).unwrap_or_else(|_| format!("{}{}", base_url, path))
+}
+
+/// How much of each subject a sync scrapes
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SyncMode {
+    /// Dashboard, announcements, lessons, guia docent and resources
+    #[default]
+    Full,
+    /// Only re-download the resources; the existing summary.md is kept
+    ResourcesOnly,
+}
+
+/// Session with PoliformaT: logs in through a headless browser and shares its cookies with
+/// `reqwest` for the plain HTTP requests
 pub struct PoliformatClient {
     client: Client,
     cookie_store: Arc<CookieStoreMutex>,
     base_url: Url,
 }
 
+impl Default for PoliformatClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl PoliformatClient {
+    /// Client for the real PoliformaT, or `POLIFORMAT_BASE_URL` when it is set to a valid URL
     pub fn new() -> Self {
+        let base_url = std::env::var(BASE_URL_ENV).ok()
+            .and_then(|url| match Url::parse(&url) {
+                Ok(url) => Some(url),
+                Err(e) => {
+                    tracing::warn!("Ignoring invalid {} '{}': {}", BASE_URL_ENV, url, e);
+                    None
+                }
+            })
+            .unwrap_or_else(|| Url::parse(DEFAULT_BASE_URL).unwrap());
+        Self::with_base_url(base_url)
+    }
+
+    /// Client for the PoliformaT (Sakai) instance at `base_url`
+    pub fn with_base_url(base_url: Url) -> Self {
         let cookie_store = Arc::new(CookieStoreMutex::new(cookie_store::CookieStore::default()));
 
         let client = Client::builder()
@@ -24,47 +73,92 @@ impl PoliformatClient {
             .build()
             .expect("Failed to build reqwest client");
         
-        Self { client, cookie_store, base_url: Url::parse("https://poliformat.upv.es").unwrap() }
+        Self { client, cookie_store, base_url }
     }
     
     pub fn login_headless(&self, creds: &auth::AuthCredentials) -> anyhow::Result<()> {
-        let cookie_str = auth::headless_login(creds)?;
+        let cookie_str = auth::headless_login(creds, &self.base_url)?;
         self.import_cookies(&cookie_str);
         tracing::info!("Cookies imported. Testing connection...");
         std::thread::sleep(std::time::Duration::from_millis(2000));
         Ok(())
     }
 
+    /// Drop the session cookies, e.g. before logging in with another account
+    pub fn clear_session(&self) {
+        self.cookie_store.lock().unwrap().clear();
+    }
+
+    pub fn base_url(&self) -> &Url {
+        &self.base_url
+    }
+
+    /// Add the cookies of a browser session ("name=value; name2=value2") for the instance's
+    /// host, and for its parent domain so the single sign-on (`*.upv.es`) gets them too
     pub fn import_cookies(&self, cookie_string: &str) {
         let mut store = self.cookie_store.lock().unwrap();
         let base_url = &self.base_url;
+        let secure = base_url.scheme() == "https";
+        let domains = [base_url.host_str(), parent_domain(base_url)];
         for pair in cookie_string.split(';') {
             let pair = pair.trim();
             if let Some((k, v)) = pair.split_once('=') {
-                 let c = cookie::Cookie::build((k, v)).domain("poliformat.upv.es").path("/").secure(true).build();
-                 let _ = store.parse(&c.to_string(), base_url);
-                 let c2 = cookie::Cookie::build((k, v)).domain("upv.es").path("/").secure(true).build();
-                 let _ = store.parse(&c2.to_string(), base_url);
+                for domain in domains.iter().flatten() {
+                    let c = cookie::Cookie::build((k, v)).domain(*domain).path("/").secure(secure).build();
+                    let _ = store.parse(&c.to_string(), base_url);
+                }
             }
         }
     }
-    
+
+    /// Log in again after the session expired. `login` gets the instance URL and returns the
+    /// new session's cookies, as `auth::headless_login` does. Fails when the portal still asks
+    /// for a login with them.
+    pub async fn relogin(&self, login: impl FnOnce(&Url) -> anyhow::Result<String> + Send + 'static) -> anyhow::Result<()> {
+        self.clear_session();
+        let base_url = self.base_url.clone();
+        let cookies = tokio::task::spawn_blocking(move || login(&base_url)).await??;
+        self.import_cookies(&cookies);
+        if !self.check_connection().await? {
+            return Err(auth::LoginError::Rejected("the portal still redirects to the login page".to_string()).into());
+        }
+        Ok(())
+    }
+
+    /// GET `url` with the session's cookies, following redirects
+    pub async fn fetch_page(&self, url: &str) -> anyhow::Result<Page> {
+        let resp = self.client.get(url).send().await?.error_for_status()?;
+        let url = resp.url().clone();
+        Ok(Page { url, html: resp.text().await? })
+    }
+
+    /// Whether the session is logged in: the portal doesn't send us to a login
     pub async fn check_connection(&self) -> anyhow::Result<bool> {
-        let resp = tokio::time::timeout(std::time::Duration::from_secs(5), self.client.get(self.base_url.clone()).send()).await??;
-        let url = resp.url().as_str();
-        let is_login = url.contains("login") || url.contains("est_aute") || url.contains("gateway");
-        Ok(!is_login)
+        let page = tokio::time::timeout(std::time::Duration::from_secs(5), self.fetch_page(self.base_url.as_str())).await??;
+        Ok(!page.requires_login())
+    }
+
+    /// Subjects of the portal page, read over HTTP with the session's cookies. Faster than
+    /// `get_subjects`, but without a browser the sites the portal only lists once its
+    /// "view all sites" panel is opened are missing.
+    pub async fn fetch_subjects(&self) -> anyhow::Result<Vec<Subject>> {
+        let page = self.fetch_page(&portal_url(&self.base_url, "/portal")).await?;
+        if page.requires_login() {
+            anyhow::bail!("Not logged in: {} asks for a login", page.url);
+        }
+        Ok(dedupe_subjects(pages::subject_links(&page.html, &self.base_url), &self.base_url))
     }
 
     pub async fn get_subjects(&self) -> anyhow::Result<Vec<Subject>> {
         tracing::info!("Starting Browser-based Subject Extraction...");
+        let base_url = self.base_url.clone();
         let subjects = tokio::task::spawn_blocking(move || -> anyhow::Result<Vec<Subject>> {
             use headless_chrome::{Browser, LaunchOptions};
-            let options = LaunchOptions { headless: true, window_size: Some((1280, 800)), idle_browser_timeout: std::time::Duration::from_secs(180), ..Default::default() };
+            let options = LaunchOptions { headless: true, window_size: Some((1280, 800)), idle_browser_timeout: std::time::Duration::from_secs(180), path: browser::chrome_path(), ..Default::default() };
             let browser = Browser::new(options)?;
             let tab = browser.new_tab()?;
             tab.set_default_timeout(std::time::Duration::from_secs(60));
-            tab.navigate_to("https://poliformat.upv.es/portal")?;
+            tab.navigate_to(&portal_url(&base_url, "/portal"))?;
             std::thread::sleep(std::time::Duration::from_secs(2));
             
             // Initial Login Logic (Shared)
@@ -75,7 +169,7 @@ impl PoliformatClient {
             tracing::info!("DEBUG: get_subjects URL: {}", current_url);
             tracing::info!("DEBUG: Body text len: {}", body_text.len());
             
-            if current_url.contains("login") || current_url.contains("gateway") || current_url.contains("xlogin") || body_text.contains("Identificación obligatoria") || body_text.contains("Identificarse") {
+            if pages::requires_login(&current_url, &body_text) {
                  tracing::info!("DEBUG: Login required. Starting login flow...");
                  // Try env vars first, then fall back to cached credentials
                  let env_username = std::env::var("POLIFORMAT_USER").or_else(|_| std::env::var("POLIFORMAT_DNI"));
@@ -87,7 +181,7 @@ impl PoliformatClient {
                  if let Some((u, p)) = creds {
                      // Explicitly navigate to login page to avoid button/link issues
                      tracing::info!("DEBUG: Navigating to portable/login...");
-                     if let Err(e) = tab.navigate_to("https://poliformat.upv.es/portal/login") {
+                     if let Err(e) = tab.navigate_to(&portal_url(&base_url, "/portal/login")) {
                          tracing::warn!("DEBUG: Failed to navigate to login: {}", e);
                      }
                      std::thread::sleep(std::time::Duration::from_secs(5));
@@ -129,37 +223,24 @@ impl PoliformatClient {
                 tracing::warn!("DEBUG: #sakai-view-all-sites NOT found!");
             }
 
-            let js_script = r#"
-                (function() {
-                    let subjects = [];
-                    let links = Array.from(document.querySelectorAll('a[href*="/portal/site/"]:not([href*="!gateway"])'));
-                    let seen = new Set();
-                    links.forEach(a => {
-                        let href = a.href;
-                        if (!href || seen.has(href) || href.includes("/tool/") || href.includes("~")) return;
-                        let text = (a.innerText || a.title || "").trim();
-                        if (!text || ["Home", "Inici", "Castellano", "English", "Valencià"].includes(text)) return;
-                        seen.add(href);
-                        subjects.push({ id: href, name: text, url: href });
-                    });
-                    return JSON.stringify(subjects);
-                })()
-            "#;
-            let remote_object = tab.evaluate(js_script, true)?;
-            let raw_json = remote_object.value.unwrap_or(serde_json::json!([]));
-            let raw_subjects: Vec<Subject> = serde_json::from_str(raw_json.as_str().unwrap_or("[]")).unwrap_or_default();
-            Ok(raw_subjects)
+            Ok(pages::subject_links(&tab.get_content()?, &base_url))
         }).await??;
         
-        let mut unique_subjects = subjects;
-        unique_subjects.sort_by(|a, b| a.name.cmp(&b.name));
-        unique_subjects.dedup_by(|a, b| a.id == b.id);
-        tracing::info!("Found {} unique subjects", unique_subjects.len());
+        let raw_count = subjects.len();
+        let unique_subjects = dedupe_subjects(subjects, &self.base_url);
+        tracing::info!("Found {} unique subjects ({} raw entries)", unique_subjects.len(), raw_count);
         Ok(unique_subjects)
     }
 
-    pub async fn scrape_subject_content(&self, subjects: Vec<Subject>) -> anyhow::Result<Vec<(Subject, String)>> {
+    /// Scrape every subject, pacing the page navigations with `throttle`. `log_callback` receives
+    /// human readable progress (resource listings, download sizes) and is called from the scraping thread.
+    pub async fn scrape_subject_content(&self, subjects: Vec<Subject>, mode: SyncMode, limits: crate::config::DownloadLimits, throttle: Arc<throttle::Throttle>, log_callback: impl Fn(String) + Send + 'static) -> anyhow::Result<Vec<(Subject, String)>> {
         tracing::info!("Starting Parallel Content Extraction for {} subjects...", subjects.len());
+
+        let moved = layout::migrate_legacy_dirs(&crate::config::Config::get_scraped_data_dir(), &subjects);
+        if moved > 0 {
+            log_callback(format!("📁 Moved {} subject folder(s) to id-based names", moved));
+        }
         
         // Get cached credentials
         let cached_creds = crate::config::Config::get_credentials();
@@ -172,6 +253,7 @@ impl PoliformatClient {
             }
         };
         let creds = cached_creds.map(|c| (c.username, c.pin)).or(env_creds);
+        let base_url = self.base_url.clone();
 
         let results = tokio::task::spawn_blocking(move || -> anyhow::Result<Vec<(Subject, String)>> {
             use headless_chrome::{Browser, LaunchOptions};
@@ -183,6 +265,7 @@ impl PoliformatClient {
                 headless: true, 
                 window_size: Some((1280, 800)), 
                 idle_browser_timeout: std::time::Duration::from_secs(600), // 10 min timeout
+                path: browser::chrome_path(),
                 ..Default::default() 
             })?;
             let browser = Arc::new(browser);
@@ -197,7 +280,7 @@ impl PoliformatClient {
             for (idx, sub) in subjects.into_iter().enumerate() {
                 tracing::info!("Progress: [{}/{}] Processing: {}", idx + 1, total, sub.name);
                 
-                match scrape_single_subject(&browser, &sub, creds.as_ref()) {
+                match scrape_single_subject(&browser, &base_url, &sub, creds.as_ref(), mode, &limits, &throttle, &log_callback) {
                     Ok(path) => {
                         results.lock().unwrap().push((sub, path));
                     }
@@ -219,26 +302,117 @@ impl PoliformatClient {
     }
 }
 
+/// A page fetched by `PoliformatClient::fetch_page`
+pub struct Page {
+    /// Where the redirects ended
+    pub url: Url,
+    pub html: String,
+}
+
+impl Page {
+    /// Whether the page is a login prompt instead of the one asked for
+    pub fn requires_login(&self) -> bool {
+        pages::requires_login(self.url.as_str(), &pages::text(&self.html))
+    }
+}
+
+/// Domain above the instance's host that its cookies are shared with: `upv.es` for
+/// poliformat.upv.es. None for IP addresses and hosts directly under a top-level domain.
+fn parent_domain(base_url: &Url) -> Option<&str> {
+    match base_url.host()? {
+        url::Host::Domain(host) => host.split_once('.').map(|(_, parent)| parent).filter(|parent| parent.contains('.')),
+        _ => None,
+    }
+}
+
+/// Extract the Sakai site id from a portal URL (`.../portal/site/<ID>[/...]`).
+/// Relative links resolve against `base_url`; the query (`?locale=ca`), fragment and
+/// path parameters (`;jsessionid=...`) are ignored, so every language portal maps to one id.
+fn canonical_site_id(href: &str, base_url: &Url) -> Option<String> {
+    let url = base_url.join(href.trim()).ok()?;
+    let mut segments = url.path_segments()?;
+    while let Some(segment) = segments.next() {
+        if segment == "site" {
+            return segments.next()
+                .map(|id| id.split(';').next().unwrap_or(id))
+                .filter(|id| !id.is_empty())
+                .map(|id| id.to_string());
+        }
+    }
+    None
+}
+
+/// The user's own workspace (`~user`) and special sites (`!gateway`, `!admin`) are not courses
+fn is_home_site(site_id: &str) -> bool {
+    site_id.starts_with('~') || site_id.starts_with('!')
+}
+
+/// Collapse the raw portal links into one entry per site.
+/// The same site shows up in the card grid and the "view all sites" drawer, with different
+/// language labels and trailing path segments, so identity is the site id, not the href.
+fn dedupe_subjects(raw: Vec<Subject>, base_url: &Url) -> Vec<Subject> {
+    let mut by_id: std::collections::HashMap<String, Subject> = std::collections::HashMap::new();
+
+    for sub in raw {
+        let Some(site_id) = canonical_site_id(&sub.url, base_url).or_else(|| canonical_site_id(&sub.id, base_url)) else {
+            tracing::debug!("Skipping link without site id: {}", sub.url);
+            continue;
+        };
+        if is_home_site(&site_id) {
+            continue;
+        }
+
+        let name = sub.name.trim().to_string();
+        match by_id.get_mut(&site_id) {
+            Some(existing) => {
+                // Prefer the most descriptive label among duplicates
+                if name.chars().count() > existing.name.chars().count() {
+                    existing.name = name;
+                }
+            }
+            None => {
+                let url = base_url.join(&format!("/portal/site/{}", site_id))
+                    .map(|u| u.to_string())
+                    .unwrap_or(sub.url);
+                by_id.insert(site_id.clone(), Subject { id: site_id, name, url });
+            }
+        }
+    }
+
+    let mut subjects: Vec<Subject> = by_id.into_values().collect();
+    subjects.sort_by(|a, b| a.name.cmp(&b.name));
+    subjects
+}
+
 /// Scrapes a single subject using a new tab from the shared browser
+#[allow(clippy::too_many_arguments)]
 fn scrape_single_subject(
     browser: &std::sync::Arc<headless_chrome::Browser>,
+    base_url: &Url,
     sub: &Subject,
     creds: Option<&(String, String)>,
+    mode: SyncMode,
+    limits: &crate::config::DownloadLimits,
+    throttle: &throttle::Throttle,
+    log_callback: &dyn Fn(String),
 ) -> anyhow::Result<String> {
     use headless_chrome::protocol::cdp::Browser as BrowserProtocol;
+    let full = mode == SyncMode::Full;
     
     let tab = browser.new_tab()?;
     tab.set_default_timeout(std::time::Duration::from_secs(60));
+    let mut pace = throttle.pace();
     
     // Create data directory for this subject
-    let clean_name = sub.name.replace("/", "-").replace(":", "").trim().to_string();
-    let base_path = crate::config::Config::get_scraped_data_dir().join(&clean_name);
+    let base_path = layout::subject_dir(&crate::config::Config::get_scraped_data_dir(), sub);
     std::fs::create_dir_all(&base_path)?;
+    layout::write_meta(&base_path, sub)?;
     
     // Final destination for resources - use absolute path
     let final_download_path = base_path.join("resources");
     std::fs::create_dir_all(&final_download_path)?;
-    let download_path_str = std::fs::canonicalize(&final_download_path)?
+    // Chrome rejects the \\?\ paths canonicalize returns on Windows
+    let download_path_str = browser::absolute(&final_download_path)?
         .to_string_lossy()
         .to_string();
     
@@ -256,7 +430,7 @@ fn scrape_single_subject(
     // We can skip heavy downloads like resources zip if folder is populated.
     
     // Navigate to subject
-    if tab.navigate_to(&sub.url).is_err() { 
+    if throttle.navigate(&tab, &mut pace, &sub.url).is_err() { 
         let _ = tab.close(true);
         return Ok("Navigation Failed".to_string()); 
     }
@@ -270,12 +444,10 @@ fn scrape_single_subject(
         .and_then(|v| v.as_str().map(|s| s.to_string()))
         .unwrap_or_default();
 
-    if curr_url.contains("login") || curr_url.contains("gateway") || curr_url.contains("xlogin") 
-        || body_text.contains("Identificación obligatoria") || body_text.contains("Identificarse") {
-        
+    if pages::requires_login(&curr_url, &body_text) {
         if let Some((u, p)) = creds {
             tracing::info!("Session expired for {}. Re-authenticating...", sub.name);
-            if let Err(e) = tab.navigate_to("https://poliformat.upv.es/portal/login") {
+            if let Err(e) = throttle.navigate(&tab, &mut pace, &portal_url(base_url, "/portal/login")) {
                 tracing::warn!("Failed to navigate to login: {}", e);
             }
             std::thread::sleep(std::time::Duration::from_secs(3));
@@ -301,7 +473,7 @@ fn scrape_single_subject(
             let _ = tab.wait_for_element_with_custom_timeout("#toolMenu, .Mrphs-toolsNav", std::time::Duration::from_secs(20));
             
             // Re-navigate to subject
-            let _ = tab.navigate_to(&sub.url);
+            let _ = throttle.navigate(&tab, &mut pace, &sub.url);
             std::thread::sleep(std::time::Duration::from_secs(2));
         }
     }
@@ -340,12 +512,16 @@ fn scrape_single_subject(
         })()
     "#;
     
+    if !full {
+        tracing::info!("Resources-only sync: skipping announcements, lessons and guia docent for {}", sub.name);
+    }
+
     if let Ok(ro) = tab.evaluate(tool_links_script, true) {
         if let Some(val) = ro.value {
             let map: serde_json::Value = serde_json::from_str(val.as_str().unwrap_or("{}")).unwrap_or_default();
             
-            if let Some(href) = map.get("announcements").and_then(|h| h.as_str()) {
-                let _ = tab.navigate_to(href);
+            if let Some(href) = map.get("announcements").and_then(|h| h.as_str()).filter(|_| full) {
+                let _ = throttle.navigate(&tab, &mut pace, href);
                 std::thread::sleep(std::time::Duration::from_secs(3));
                 if let Ok(ro_a) = tab.evaluate("document.querySelector('.portletBody') ? document.querySelector('.portletBody').innerText : document.body.innerText", true) {
                     let content = ro_a.value.and_then(|v| v.as_str().map(|s| s.to_string())).unwrap_or_default();
@@ -353,8 +529,8 @@ fn scrape_single_subject(
                 }
             }
 
-            if let Some(href) = map.get("lessons").and_then(|h| h.as_str()) {
-                let _ = tab.navigate_to(href);
+            if let Some(href) = map.get("lessons").and_then(|h| h.as_str()).filter(|_| full) {
+                let _ = throttle.navigate(&tab, &mut pace, href);
                 std::thread::sleep(std::time::Duration::from_secs(3));
                 if let Ok(ro_l) = tab.evaluate("document.body.innerText", true) {
                     let content = ro_l.value.and_then(|v| v.as_str().map(|s| s.to_string())).unwrap_or_default();
@@ -363,24 +539,28 @@ fn scrape_single_subject(
             }
 
             if let Some(href) = map.get("resources").and_then(|h| h.as_str()) {
-                // Check if we already have resources (more than just PDFs we create).
-                // Heuristic: If there are > 5 files, maybe we don't need to download zip.
-                // But user might want update. For now, let's skip if ANY resources exist to be safe/incremental.
-                // Or better: check if "Resources" folder inside exists or just check file count.
-                let resource_files_count = std::fs::read_dir(&final_download_path).map(|d| d.count()).unwrap_or(0);
-                
-                if resource_files_count < 2 { // Only download if almost empty
+                let wanted = resources_wanted(&base_path, mode);
+                if !full {
+                    remove_old_archives(&final_download_path);
+                }
+                if wanted {
                     tracing::info!("Downloading resources for {}...", sub.name);
-                    let _ = tab.navigate_to(href);
+                    let _ = throttle.navigate(&tab, &mut pace, href);
                     std::thread::sleep(std::time::Duration::from_secs(3));
-                    let _ = tab.evaluate("document.getElementById('selectall') ? document.getElementById('selectall').click() : null", true);
-                    std::thread::sleep(std::time::Duration::from_millis(500));
-                    let _ = tab.evaluate("document.getElementById('zipdownload-button') ? document.getElementById('zipdownload-button').click() : null", true);
-                    std::thread::sleep(std::time::Duration::from_secs(2));
-                    let _ = tab.evaluate("document.getElementById('zipDownloadButton') ? document.getElementById('zipDownloadButton').click() : null", true);
-                    
-                    // Wait for downloads to complete
-                    wait_for_downloads(&final_download_path, &sub.name);
+                    let listing = read_resource_listing(&tab);
+                    let selected = select_resources(&tab, &listing, limits, &sub.name, log_callback);
+                    if !listing.is_empty() && selected.is_empty() {
+                        log_callback(format!("  📦 {}: nothing within the download limits", sub.name));
+                    } else {
+                        let expected_bytes = log_resource_listing(&sub.name, &selected, log_callback);
+                        std::thread::sleep(std::time::Duration::from_millis(500));
+                        let _ = tab.evaluate("document.getElementById('zipdownload-button') ? document.getElementById('zipdownload-button').click() : null", true);
+                        std::thread::sleep(std::time::Duration::from_secs(2));
+                        let _ = tab.evaluate("document.getElementById('zipDownloadButton') ? document.getElementById('zipDownloadButton').click() : null", true);
+
+                        // Wait for downloads to complete
+                        wait_for_downloads(&final_download_path, &sub.name, expected_bytes, log_callback);
+                    }
                 } else {
                      tracing::info!("Skipping resource download for {} (files already exist)", sub.name);
                 }
@@ -389,9 +569,9 @@ fn scrape_single_subject(
             // Scrape Guia Docent (Teaching Guide / Syllabus PDF)
             // Strategy 1: Try finding link in menu
             
-            if let Some(href) = map.get("guiaDocent").and_then(|h| h.as_str()) {
+            if let Some(href) = map.get("guiaDocent").and_then(|h| h.as_str()).filter(|_| full) {
                 tracing::info!("Found Guia Docent link for {}", sub.name);
-                let _ = tab.navigate_to(href);
+                let _ = throttle.navigate(&tab, &mut pace, href);
                 std::thread::sleep(std::time::Duration::from_secs(4));
                 
                 // Extract page content
@@ -414,16 +594,17 @@ fn scrape_single_subject(
             }
             
             // Strategy 2: Direct URL construction (Primary or Fallback)
-            // We ALWAYS run this because it gives us the Description and Professors in a consistent format
+            // Every full sync runs this because it gives us the Description and Professors in a consistent format
             // which might be missing from the basic "Guia Docent" page in Sakai.
-            tracing::info!("Attempting Direct URL Scraping for Guia/Description/Professors: {}", sub.name);
-            
+
             // Extract numeric ID from subject ID (e.g. GRA_11673_2025_DTU -> 11673)
             let parts: Vec<&str> = sub.id.split('_').collect();
             let subject_id = if parts.len() >= 2 { parts[1] } else { "" };
             let subject_year = if parts.len() >= 3 { parts[2] } else { "2025" }; // Default to 2025 if missing
 
-            if !subject_id.is_empty() {
+            if full && !subject_id.is_empty() {
+                tracing::info!("Attempting Direct URL Scraping for Guia/Description/Professors: {}", sub.name);
+
                 let base_filename1 = format!("{} (Guia Docent).pdf", sub.name.replace("/", "-"));
                 let base_path1 = final_download_path.join(&base_filename1);
                 
@@ -432,7 +613,7 @@ fn scrape_single_subject(
                     let guia_url = format!("https://www.upv.es/pls/soalu/sic_gdoc.get_content?P_ASI={}&P_IDIOMA=c&P_VISTA=poliformat&P_TIT=&P_CACA={}", subject_id, subject_year);
                     tracing::info!("Navigating to Guia Docent HTML view: {}", guia_url);
 
-                    if let Ok(_) = tab.navigate_to(&guia_url) {
+                    if throttle.navigate(&tab, &mut pace, &guia_url).is_ok() {
                             let _ = tab.wait_until_navigated();
                             std::thread::sleep(std::time::Duration::from_secs(3));
                             
@@ -472,7 +653,7 @@ fn scrape_single_subject(
                 // Always scrape description text for summary.md even if PDF exists
                 let desc_url = format!("https://www.upv.es/pls/soalu/sic_gdoc.get_content?P_ASI={}&P_IDIOMA=c&P_VISTA=poliformat&P_TIT=&P_CACA={}&P_CONTENT=descripcion", subject_id, subject_year);
                 tracing::info!("Scraping Guia Docent Description: {}", desc_url);
-                if let Ok(_) = tab.navigate_to(&desc_url) {
+                if throttle.navigate(&tab, &mut pace, &desc_url).is_ok() {
                     let _ = tab.wait_until_navigated();
                     std::thread::sleep(std::time::Duration::from_secs(2));
                     
@@ -500,7 +681,7 @@ fn scrape_single_subject(
                 // Always scrape professors text for summary.md
                 let prof_url = format!("https://www.upv.es/pls/soalu/sic_asi.Profesores?P_OCW=&P_ASI={}&P_CACA={}&P_IDIOMA=c&P_VISTA=poliformat", subject_id, subject_year);
                 tracing::info!("Scraping Guia Docent Professors: {}", prof_url);
-                if let Ok(_) = tab.navigate_to(&prof_url) {
+                if throttle.navigate(&tab, &mut pace, &prof_url).is_ok() {
                     let _ = tab.wait_until_navigated();
                     std::thread::sleep(std::time::Duration::from_secs(2));
                     
@@ -522,15 +703,17 @@ fn scrape_single_subject(
                     }
                 }
         
-            } else {
+            } else if full {
                     tracing::warn!("Could not extract numeric ID from subject ID: {}", sub.id);
             }
         }
     }
     
-    // Write summary.md
+    // Write summary.md. A resources-only sync only has the dashboard, so it keeps the last full summary.
     let summary_path = base_path.join("summary.md");
-    if let Err(e) = std::fs::write(&summary_path, &content_accumulator) {
+    if !full && summary_path.exists() {
+        tracing::info!("Keeping existing summary for {} (resources-only sync)", sub.name);
+    } else if let Err(e) = std::fs::write(&summary_path, &content_accumulator) {
         tracing::error!("Failed to write summary.md for {}: {}", sub.name, e);
     } else {
         let summary_pdf_path = final_download_path.join("summary.pdf");
@@ -560,10 +743,10 @@ fn scrape_single_subject(
             );
             
             let temp_html_path = base_path.join("temp_summary.html");
-            if let Ok(_) = std::fs::write(&temp_html_path, html_content) {
-                let file_url = format!("file://{}", temp_html_path.canonicalize().unwrap_or(temp_html_path.clone()).to_string_lossy());
+            if std::fs::write(&temp_html_path, html_content).is_ok() {
+                let file_url = browser::file_url(&temp_html_path).unwrap_or_default();
                 
-                if let Ok(_) = tab.navigate_to(&file_url) {
+                if tab.navigate_to(&file_url).is_ok() {
                     let _ = tab.wait_until_navigated();
                     std::thread::sleep(std::time::Duration::from_millis(1000)); // Allow render
                     
@@ -593,8 +776,168 @@ fn scrape_single_subject(
     Ok(base_path.to_string_lossy().to_string())
 }
 
-/// Wait for downloads to complete by checking for .crdownload / .tmp files
-fn wait_for_downloads(download_path: &std::path::Path, subject_name: &str) {
+/// Delete previously downloaded resource archives, so a fresh download keeps its name
+/// instead of Chrome saving it as "Resources (1).zip" next to the old one
+fn remove_old_archives(download_path: &std::path::Path) {
+    let Ok(entries) = std::fs::read_dir(download_path) else { return };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.extension().is_some_and(|e| e == "zip") {
+            if let Err(e) = std::fs::remove_file(&path) {
+                tracing::warn!("Failed to remove old archive {:?}: {}", path, e);
+            }
+        }
+    }
+}
+
+/// Whether a sync downloads the resources of the subject folder `subject_dir`. A full sync
+/// only fills a resources folder that is (almost) empty, and not one `polirag clean` emptied
+/// after extracting its archives; a resources-only sync is an explicit refresh and always does.
+pub fn resources_wanted(subject_dir: &std::path::Path, mode: SyncMode) -> bool {
+    if mode == SyncMode::ResourcesOnly {
+        return true;
+    }
+    let resource_files = std::fs::read_dir(subject_dir.join("resources")).map(|d| d.count()).unwrap_or(0);
+    let cleaned = !layout::read_cleaned(subject_dir).files.is_empty();
+    resource_files < 2 && !cleaned
+}
+
+/// Split `listing` into the entries that fit `limits`, in order, and the skipped ones with
+/// the reason. Folders (sized "N items") always fit; their contents are filtered when the zip
+/// is unpacked.
+pub fn plan_resources(listing: &[ResourceEntry], limits: &crate::config::DownloadLimits) -> (Vec<ResourceEntry>, Vec<(ResourceEntry, &'static str)>) {
+    let mut budget = processing::DownloadBudget::new(limits);
+    let mut kept = Vec::new();
+    let mut skipped = Vec::new();
+    for entry in listing {
+        let admitted = match parse_size_label(&entry.size) {
+            Some(size) => budget.admit(&entry.name, size),
+            None => Ok(()),
+        };
+        match admitted {
+            Ok(()) => kept.push(entry.clone()),
+            Err(reason) => skipped.push((entry.clone(), reason)),
+        }
+    }
+    (kept, skipped)
+}
+
+/// Read file names and size labels from the resources page currently loaded in `tab`
+fn read_resource_listing(tab: &headless_chrome::Tab) -> Vec<ResourceEntry> {
+    tab.get_content().map(|html| pages::resource_listing(&html)).unwrap_or_default()
+}
+
+/// Tick the rows of the listing that fit `limits` (see `plan_resources`) and return them.
+/// Without a listing, or when nothing is over the limits, everything is selected as before.
+fn select_resources(
+    tab: &headless_chrome::Tab,
+    listing: &[ResourceEntry],
+    limits: &crate::config::DownloadLimits,
+    subject_name: &str,
+    log_callback: &dyn Fn(String),
+) -> Vec<ResourceEntry> {
+    let (kept, skipped) = plan_resources(listing, limits);
+    if skipped.is_empty() {
+        let _ = tab.evaluate("document.getElementById('selectall') ? document.getElementById('selectall').click() : null", true);
+        return kept;
+    }
+
+    for (entry, reason) in &skipped {
+        tracing::info!("Not downloading {} ({}) for {}: {}", entry.name, entry.size, subject_name, reason);
+    }
+    log_callback(format!("  ⏭️  {}: skipping {} of {} entries over the download limits", subject_name, skipped.len(), listing.len()));
+    let names: Vec<&str> = kept.iter().map(|e| e.name.as_str()).collect();
+    let select_script = format!(
+        r#"
+        (function(keep) {{
+            Array.from(document.querySelectorAll('table tr')).forEach(r => {{
+                let box = r.querySelector("input[type='checkbox'][name='selectedMembers']");
+                let title = r.querySelector('td.title a, th.title a, .title a');
+                if (!box || !title) return;
+                if (box.checked !== keep.includes(title.innerText.trim())) box.click();
+            }});
+        }})({})
+    "#,
+        serde_json::to_string(&names).unwrap_or_else(|_| "[]".to_string())
+    );
+    let _ = tab.evaluate(&select_script, true);
+    kept
+}
+
+/// Log the files about to be downloaded and return their approximate total size
+fn log_resource_listing(subject_name: &str, listing: &[ResourceEntry], log_callback: &dyn Fn(String)) -> Option<u64> {
+    if listing.is_empty() {
+        log_callback(format!("  📦 {}: downloading resources (listing unavailable)", subject_name));
+        return None;
+    }
+
+    // Folders report "N items" rather than a size and are not counted
+    let total: u64 = listing.iter().filter_map(|e| parse_size_label(&e.size)).sum();
+    let expected = (total > 0).then_some(total);
+
+    log_callback(format!(
+        "  📦 {}: {} entries{}",
+        subject_name,
+        listing.len(),
+        expected.map(|t| format!(", ~{}", format_bytes(t))).unwrap_or_default()
+    ));
+
+    const MAX_LISTED: usize = 15;
+    for entry in listing.iter().take(MAX_LISTED) {
+        if entry.size.is_empty() {
+            log_callback(format!("     • {}", entry.name));
+        } else {
+            log_callback(format!("     • {} ({})", entry.name, entry.size));
+        }
+    }
+    if listing.len() > MAX_LISTED {
+        log_callback(format!("     … and {} more", listing.len() - MAX_LISTED));
+    }
+
+    expected
+}
+
+/// Parse Sakai size labels such as "1.2 MB", "1,2 MB", "850 KB" or "12 bytes"
+fn parse_size_label(label: &str) -> Option<u64> {
+    let label = label.trim().replace(',', ".");
+    let (number, unit) = label.split_once(char::is_whitespace)?;
+    let value: f64 = number.parse().ok()?;
+    let multiplier = match unit.trim().to_uppercase().as_str() {
+        "B" | "BYTES" | "BYTE" => 1.0,
+        "KB" => 1024.0,
+        "MB" => 1024.0 * 1024.0,
+        "GB" => 1024.0 * 1024.0 * 1024.0,
+        _ => return None,
+    };
+    Some((value * multiplier) as u64)
+}
+
+pub fn format_bytes(bytes: u64) -> String {
+    if bytes < 1024 {
+        format!("{} B", bytes)
+    } else if bytes < 1024 * 1024 {
+        format!("{:.1} KB", bytes as f64 / 1024.0)
+    } else {
+        format!("{:.1} MB", bytes as f64 / (1024.0 * 1024.0))
+    }
+}
+
+/// Total size of the files in the download directory, partial downloads included
+fn downloaded_bytes(download_path: &std::path::Path) -> u64 {
+    std::fs::read_dir(download_path)
+        .map(|entries| {
+            entries.filter_map(|e| e.ok())
+                .filter_map(|e| e.metadata().ok())
+                .filter(|m| m.is_file())
+                .map(|m| m.len())
+                .sum()
+        })
+        .unwrap_or(0)
+}
+
+/// Wait for downloads to complete by checking for .crdownload / .tmp files,
+/// reporting the bytes written so far on every poll
+fn wait_for_downloads(download_path: &std::path::Path, subject_name: &str, expected_bytes: Option<u64>, log_callback: &dyn Fn(String)) {
     use std::time::{Duration, Instant};
     
     let max_wait = Duration::from_secs(120); // Wait up to 2 minutes for downloads
@@ -631,11 +974,17 @@ fn wait_for_downloads(download_path: &std::path::Path, subject_name: &str) {
                 
             if has_files {
                 tracing::info!("Downloads complete for {}", subject_name);
+                log_callback(format!("  ✓ {}: downloaded {}", subject_name, format_bytes(downloaded_bytes(download_path))));
             }
             break;
         }
         
         tracing::debug!("Downloads still in progress for {}...", subject_name);
+        let done = downloaded_bytes(download_path);
+        match expected_bytes {
+            Some(total) => log_callback(format!("  ⬇️  {}: {} / ~{}", subject_name, format_bytes(done), format_bytes(total))),
+            None => log_callback(format!("  ⬇️  {}: {}", subject_name, format_bytes(done))),
+        }
         std::thread::sleep(poll_interval);
     }
 }
@@ -643,3 +992,79 @@ fn wait_for_downloads(download_path: &std::path::Path, subject_name: &str) {
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct Subject { pub id: String, pub name: String, pub url: String }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn raw(name: &str, href: &str) -> Subject {
+        Subject { id: href.to_string(), name: name.to_string(), url: href.to_string() }
+    }
+
+    fn portal() -> Url {
+        Url::parse(DEFAULT_BASE_URL).unwrap()
+    }
+
+    #[test]
+    fn portal_links_collapse_to_one_subject_per_site() {
+        // As the card grid and the "view all sites" drawer list them
+        let links = vec![
+            raw("ALG", "https://poliformat.upv.es/portal/site/GRA_11673_2025"),
+            raw("Álgebra Lineal", "https://poliformat.upv.es/portal/site/GRA_11673_2025/tool/8f2a-resources"),
+            raw("Àlgebra", "https://poliformat.upv.es/portal/site/GRA_11673_2025/page/3c1d"),
+            raw("Física", "https://poliformat.upv.es/portal/site/GRA_11674_2025/"),
+            raw("Fisica I (Grau)", "https://poliformat.upv.es/portal/site/GRA_11674_2025"),
+            raw("My Workspace", "https://poliformat.upv.es/portal/site/~jdoe"),
+            raw("Gateway", "https://poliformat.upv.es/portal/site/!gateway"),
+            raw("Help", "https://poliformat.upv.es/portal/help/main"),
+        ];
+
+        let subjects = dedupe_subjects(links, &portal());
+        let found: Vec<(&str, &str, &str)> = subjects.iter().map(|s| (s.id.as_str(), s.name.as_str(), s.url.as_str())).collect();
+        // Sorted by name
+        assert_eq!(found, [
+            ("GRA_11674_2025", "Fisica I (Grau)", "https://poliformat.upv.es/portal/site/GRA_11674_2025"),
+            ("GRA_11673_2025", "Álgebra Lineal", "https://poliformat.upv.es/portal/site/GRA_11673_2025"),
+        ]);
+    }
+
+    #[test]
+    fn language_portals_of_one_course_are_one_subject() {
+        let links = vec![
+            raw("Àlgebra Lineal", "https://poliformat.upv.es/portal/site/GRA_11673_2025?locale=ca"),
+            raw("Álgebra Lineal", "https://poliformat.upv.es/portal/site/GRA_11673_2025?locale=es"),
+            raw("Linear Algebra", "/portal/site/GRA_11673_2025;jsessionid=4F2A9C?locale=en#top"),
+        ];
+
+        let subjects = dedupe_subjects(links, &portal());
+        assert_eq!(subjects.len(), 1);
+        assert_eq!(subjects[0].id, "GRA_11673_2025");
+        assert_eq!(subjects[0].url, "https://poliformat.upv.es/portal/site/GRA_11673_2025");
+    }
+
+    #[test]
+    fn site_ids_ignore_query_fragment_and_path_parameters() {
+        let id = |href: &str| canonical_site_id(href, &portal());
+        assert_eq!(id("/portal/site/GRA_11673_2025/tool/abc?locale=ca").as_deref(), Some("GRA_11673_2025"));
+        assert_eq!(id("https://poliformat.upv.es/portal/site/GRA_11673_2025;jsessionid=1").as_deref(), Some("GRA_11673_2025"));
+        assert_eq!(id("/portal/site/"), None);
+        assert_eq!(id("/portal/help/main"), None);
+    }
+
+    #[test]
+    fn home_and_special_sites_are_not_subjects() {
+        assert!(is_home_site("~jdoe"));
+        assert!(is_home_site("!admin"));
+        assert!(!is_home_site("GRA_11673_2025"));
+    }
+
+    #[test]
+    fn cookies_are_shared_with_the_parent_domain_only_for_named_hosts() {
+        let parent = |url: &str| parent_domain(&Url::parse(url).unwrap()).map(str::to_string);
+        assert_eq!(parent("https://poliformat.upv.es").as_deref(), Some("upv.es"));
+        assert_eq!(parent("https://poliformat.test.upv.es/portal").as_deref(), Some("test.upv.es"));
+        assert_eq!(parent("https://upv.es"), None);
+        assert_eq!(parent("http://localhost:8080"), None);
+        assert_eq!(parent("http://127.0.0.1:8080"), None);
+    }
+}
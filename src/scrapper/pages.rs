@@ -0,0 +1,118 @@
+//! Reading the PoliformaT (Sakai) pages the scraper visits: whether a page asks for a login,
+//! the subjects the portal lists and the rows of a resources table. The browser and the plain
+//! HTTP requests both go through these, so they read a page the same way.
+
+use scraper::{ElementRef, Html, Selector};
+use url::Url;
+
+use super::Subject;
+
+/// Link texts of the portal's home and language switches, which are not subjects
+const NOT_SUBJECTS: [&str; 5] = ["Home", "Inici", "Castellano", "English", "Valencià"];
+
+/// Whether the page at `url` showing `text` asks for a login instead of its content: a
+/// redirect to the login page or the single sign-on, or the portal's own login prompt
+pub fn requires_login(url: &str, text: &str) -> bool {
+    url.contains("login") || url.contains("gateway") || url.contains("est_aute")
+        || text.contains("Identificación obligatoria") || text.contains("Identificarse")
+}
+
+/// Text of the body of `html`, scripts and styles left out, roughly what a browser shows
+pub fn text(html: &str) -> String {
+    let document = Html::parse_document(html);
+    let body = Selector::parse("body").unwrap();
+    let Some(body) = document.select(&body).next() else { return String::new() };
+
+    let mut text = String::new();
+    for node in body.descendants() {
+        if let Some(chunk) = node.value().as_text() {
+            let hidden = node.ancestors().filter_map(ElementRef::wrap).any(|e| matches!(e.value().name(), "script" | "style"));
+            if !hidden {
+                text.push_str(chunk);
+            }
+        }
+    }
+    text
+}
+
+/// What a link reads as: its text, or its title when it has none
+fn label(link: &ElementRef) -> String {
+    let text = link.text().collect::<String>();
+    let text = text.trim();
+    if text.is_empty() {
+        link.value().attr("title").unwrap_or_default().trim().to_string()
+    } else {
+        text.to_string()
+    }
+}
+
+/// Subjects `html` links to: every `/portal/site/<id>` link but tools, workspaces, the
+/// gateway, home and language switches. Relative links resolve against `base_url`. A subject
+/// linked several times appears once per distinct URL; `dedupe_subjects` merges those.
+pub fn subject_links(html: &str, base_url: &Url) -> Vec<Subject> {
+    let document = Html::parse_document(html);
+    let links = Selector::parse(r#"a[href*="/portal/site/"]:not([href*="!gateway"])"#).unwrap();
+
+    let mut seen = std::collections::HashSet::new();
+    let mut subjects = Vec::new();
+    for link in document.select(&links) {
+        let Some(href) = link.value().attr("href").and_then(|href| base_url.join(href.trim()).ok()) else { continue };
+        let href = href.to_string();
+        if seen.contains(&href) || href.contains("/tool/") || href.contains('~') {
+            continue;
+        }
+        let name = label(&link);
+        if name.is_empty() || NOT_SUBJECTS.contains(&name.as_str()) {
+            continue;
+        }
+        seen.insert(href.clone());
+        subjects.push(Subject { id: href.clone(), name, url: href });
+    }
+    subjects
+}
+
+/// A row of the Sakai resources table
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResourceEntry {
+    pub name: String,
+    /// Size label as shown, e.g. "1,2 MB", or "3 items" for a folder; empty when missing
+    pub size: String,
+}
+
+/// Rows of the resources table in `html` that can be selected for download
+pub fn resource_listing(html: &str) -> Vec<ResourceEntry> {
+    let document = Html::parse_document(html);
+    let rows = Selector::parse("table tr").unwrap();
+    let checkbox = Selector::parse("input[type='checkbox'][name='selectedMembers']").unwrap();
+    let title = Selector::parse("td.title a, th.title a, .title a").unwrap();
+    let size = Selector::parse("td.size, .size").unwrap();
+    let text = |e: ElementRef| e.text().collect::<String>().trim().to_string();
+
+    document.select(&rows)
+        .filter(|row| row.select(&checkbox).next().is_some())
+        .map(|row| ResourceEntry {
+            name: row.select(&title).next().map(text).unwrap_or_default(),
+            size: row.select(&size).next().map(text).unwrap_or_default(),
+        })
+        .filter(|entry| !entry.name.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn login_is_detected_by_url_or_prompt() {
+        assert!(requires_login("https://poliformat.upv.es/portal/login", ""));
+        assert!(requires_login("https://intranet.upv.es/pls/soalu/est_aute.intraalucomp", ""));
+        assert!(requires_login("https://poliformat.upv.es/portal", "Identificación obligatoria"));
+        assert!(!requires_login("https://poliformat.upv.es/portal/site/GRA_11673_2025", "Anuncis"));
+    }
+
+    #[test]
+    fn page_text_leaves_out_scripts() {
+        let html = "<html><head><title>Inici</title></head><body><p>Benvingut</p><script>let s = 'Identificarse';</script></body></html>";
+        assert_eq!(text(html).trim(), "Benvingut");
+    }
+}
@@ -1,3 +1,6 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
 
 /// Normalize text extracted from PDFs - fix ligatures and other Unicode issues
 fn normalize_text(text: &str) -> String {
@@ -8,8 +11,7 @@ fn normalize_text(text: &str) -> String {
         .replace('\u{FB02}', "fl")   // ﬂ
         .replace('\u{FB03}', "ffi")  // ﬃ
         .replace('\u{FB04}', "ffl")  // ﬄ
-        .replace('\u{FB05}', "st")   // ﬅ (long s + t)
-        .replace('\u{FB06}', "st")   // ﬆ
+        .replace(['\u{FB05}', '\u{FB06}'], "st")   // ﬅ (long s + t), ﬆ
         // Additional ligatures
         .replace('\u{0132}', "IJ")   // Ĳ
         .replace('\u{0133}', "ij")   // ĳ
@@ -18,12 +20,9 @@ fn normalize_text(text: &str) -> String {
         .replace('\u{00C6}', "AE")   // Æ
         .replace('\u{00E6}', "ae")   // æ
         // Common symbols
-        .replace('\u{2019}', "'")    // '
-        .replace('\u{2018}', "'")    // '
-        .replace('\u{201C}', "\"")   // "
-        .replace('\u{201D}', "\"")   // "
-        .replace('\u{2013}', "-")    // – (en dash)
-        .replace('\u{2014}', "-")    // — (em dash)
+        .replace(['\u{2019}', '\u{2018}'], "'")    // ' '
+        .replace(['\u{201C}', '\u{201D}'], "\"")   // " "
+        .replace(['\u{2013}', '\u{2014}'], "-")    // – (en dash), — (em dash)
         .replace('\u{2026}', "...")  // …
         .replace('\u{00A0}', " ")    // Non-breaking space
         // Normalize whitespace
@@ -32,7 +31,91 @@ fn normalize_text(text: &str) -> String {
         .join(" ")
 }
 
-pub fn process_resources(subject_path: &std::path::Path) -> anyhow::Result<Vec<(String, String)>> {
+/// Text extracted from a downloaded resource
+pub struct ExtractedDoc {
+    /// Path relative to the subject directory
+    pub rel_path: String,
+    pub text: String,
+    /// Index metadata: always `type` ("pdf" or "transcript"), plus `media` for transcripts
+    /// and `modified` (YYYY-MM-DD) when the file's mtime is known, `truncated_from` when the size guard cut it
+    pub metadata: HashMap<String, String>,
+}
+
+impl ExtractedDoc {
+    /// "tema1.pdf was truncated to N of M characters" when the size guard cut the text
+    pub fn truncation_warning(&self) -> Option<String> {
+        let original = self.metadata.get("truncated_from")?;
+        Some(format!("{} was truncated to {} of {} characters", self.rel_path, self.text.chars().count(), original))
+    }
+}
+
+/// Cut `text` to its first `max_chars` characters.
+/// Returns the original length in characters when something was cut.
+pub fn truncate_chars(text: &mut String, max_chars: usize) -> Option<usize> {
+    let (cut, _) = text.char_indices().nth(max_chars)?;
+    let original = max_chars + text[cut..].chars().count();
+    text.truncate(cut);
+    Some(original)
+}
+
+/// "Last modified: YYYY-MM-DD\n" for chunk headers, empty when the date is unknown
+pub fn modified_line(metadata: &HashMap<String, String>) -> String {
+    metadata.get("modified")
+        .map(|date| format!("Last modified: {}\n", date))
+        .unwrap_or_default()
+}
+
+/// Running totals for one subject's resources, checked against the configured `DownloadLimits`
+pub struct DownloadBudget<'a> {
+    limits: &'a crate::config::DownloadLimits,
+    files: usize,
+    bytes: u64,
+}
+
+impl<'a> DownloadBudget<'a> {
+    pub fn new(limits: &'a crate::config::DownloadLimits) -> Self {
+        Self { limits, files: 0, bytes: 0 }
+    }
+
+    /// Count the file in if it fits, otherwise say why it is skipped
+    pub fn admit(&mut self, file_name: &str, size: u64) -> Result<(), &'static str> {
+        if !self.limits.allows_extension(file_name) {
+            return Err("type not in the allowlist");
+        }
+        if self.limits.max_files.is_some_and(|max| self.files >= max) {
+            return Err("file limit reached");
+        }
+        if self.limits.max_subject_bytes().is_some_and(|max| self.bytes + size > max) {
+            return Err("size limit reached");
+        }
+        self.files += 1;
+        self.bytes += size;
+        Ok(())
+    }
+}
+
+/// Modification date of `path` as YYYY-MM-DD (UTC)
+pub fn modified_date(path: &Path) -> Option<String> {
+    let modified = std::fs::metadata(path).ok()?.modified().ok()?;
+    let secs = modified.duration_since(std::time::UNIX_EPOCH).ok()?.as_secs();
+    Some(format_date(secs / 86_400))
+}
+
+/// Civil date for a count of days since 1970-01-01 (Howard Hinnant's algorithm)
+pub fn format_date(days: u64) -> String {
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+pub fn process_resources(subject_path: &std::path::Path, limits: &crate::config::DownloadLimits) -> anyhow::Result<Vec<ExtractedDoc>> {
     use std::fs;
     let mut extracted_docs = Vec::new();
     let resources_path = subject_path.join("resources");
@@ -42,11 +125,12 @@ pub fn process_resources(subject_path: &std::path::Path) -> anyhow::Result<Vec<(
         return Ok(extracted_docs);
     }
 
-    // 1. Unzip Logic
+    // 1. Unzip Logic, keeping only what fits the subject's download limits
+    let mut budget = DownloadBudget::new(limits);
     if let Ok(entries) = fs::read_dir(&resources_path) {
         for entry in entries.flatten() {
             let path = entry.path();
-            if path.extension().map_or(false, |e| e == "zip") {
+            if path.extension().is_some_and(|e| e == "zip") {
                 tracing::info!("Found zip: {:?}. Extracting...", path.file_name());
                 let file = fs::File::open(&path)?;
                 let mut archive = zip::ZipArchive::new(file)?;
@@ -64,6 +148,10 @@ pub fn process_resources(subject_path: &std::path::Path) -> anyhow::Result<Vec<(
 
                     if file.name().ends_with('/') {
                         fs::create_dir_all(&outpath)?;
+                    } else if let Err(reason) = budget.admit(file.name(), file.size()) {
+                        tracing::info!("Skipping {} from {:?}: {}", file.name(), path.file_name(), reason);
+                        // Drop what an earlier, less strict sync unpacked
+                        let _ = fs::remove_file(&outpath);
                     } else {
                         if let Some(p) = outpath.parent() {
                             if !p.exists() { fs::create_dir_all(p)?; }
@@ -77,52 +165,317 @@ pub fn process_resources(subject_path: &std::path::Path) -> anyhow::Result<Vec<(
     }
 
     // 2. PDF Extraction Logic
-    use std::process::Command;
-    
     // Scan both resources/ and resources/extracted/
     let dirs_to_scan = vec![resources_path.clone(), extracted_path];
     
-    let exe = std::env::current_exe()?;
-    let exe_path = exe.to_string_lossy();
+    // Without our own path PDFs are still extracted, in-process
+    let exe = std::env::current_exe()
+        .inspect_err(|e| tracing::warn!("Cannot locate the polirag executable ({}), extracting PDFs in-process", e))
+        .ok();
     
     for dir in dirs_to_scan {
         if !dir.exists() { continue; }
         for entry in walkdir::WalkDir::new(&dir).into_iter().filter_map(|e| e.ok()) {
             let path = entry.path();
-            if path.extension().map_or(false, |e| e == "pdf") {
+            if path.extension().is_some_and(|e| e == "pdf") {
                  tracing::info!("Processing PDF: {:?}", path.file_name());
                  
-                 // Run subprocess to isolate noise
-                 let output = Command::new(&*exe_path)
-                     .arg("extract-pdf")
-                     .arg(path.to_string_lossy().as_ref())
-                     .output();
-                     
-                 match output {
-                     Ok(out) => {
-                         if out.status.success() {
-                             let stdout = String::from_utf8_lossy(&out.stdout);
-                             if let Some(start) = stdout.find("<<<START_CONTENT>>>") {
-                                 if let Some(end) = stdout.find("<<<END_CONTENT>>>") {
-                                     let text = &stdout[start + 19..end];
-                                     let normalized = normalize_text(text);
-                                     if !normalized.trim().is_empty() {
-                                         let _name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
-                                         let rel_path = path.strip_prefix(subject_path).unwrap_or(path).to_string_lossy().to_string();
-                                         extracted_docs.push((rel_path, normalized));
-                                     }
-                                 }
-                             }
-                         } else {
-                             let stderr = String::from_utf8_lossy(&out.stderr);
-                             tracing::warn!("PDF extraction failed for {:?}: {}", path, stderr);
+                 if let Some(text) = extract_pdf_text(exe.as_deref(), path) {
+                     let normalized = normalize_text(&text);
+                     if !normalized.trim().is_empty() {
+                         let rel_path = super::layout::relative_id(path, subject_path);
+                         let mut metadata: HashMap<String, String> = [("type".to_string(), "pdf".to_string())].into();
+                         if let Some(date) = modified_date(path) {
+                             metadata.insert("modified".to_string(), date);
                          }
-                     },
-                     Err(e) => tracing::error!("Failed to spawn extraction subprocess: {}", e),
+                         extracted_docs.push(ExtractedDoc {
+                             rel_path,
+                             text: normalized,
+                             metadata,
+                         });
+                     }
                  }
             }
         }
     }
 
+    // 3. Subtitle Tracks (lecture recordings), extracted/ included
+    extracted_docs.extend(process_subtitles(&resources_path, subject_path));
+
+    // 4. Size guard: a huge (e.g. OCR'd) document is cut instead of being chunked and embedded whole
+    let max_chars = crate::config::Config::load().rag.document_char_limit();
+    for doc in &mut extracted_docs {
+        if let Some(original) = truncate_chars(&mut doc.text, max_chars) {
+            tracing::warn!("{} has {} characters, indexing only the first {}", doc.rel_path, original, max_chars);
+            doc.metadata.insert("truncated_from".to_string(), original.to_string());
+        }
+    }
+
     Ok(extracted_docs)
 }
+
+/// Text of the PDF at `path`. The `extract-pdf` subprocess isolates pdf_extract's noise and
+/// crashes; when it can't be spawned or its output has no content markers (e.g. `current_exe`
+/// is not a runnable polirag), the PDF is parsed in-process instead.
+fn extract_pdf_text(exe_path: Option<&Path>, path: &Path) -> Option<String> {
+    if let Some(exe_path) = exe_path {
+        // Run subprocess to isolate noise; the PDF is piped in so the child never opens paths itself
+        match extract_pdf_subprocess(exe_path, path) {
+            Ok(out) if out.status.success() => {
+                let stdout = String::from_utf8_lossy(&out.stdout);
+                let content = stdout.find("<<<START_CONTENT>>>")
+                    .and_then(|start| stdout[start..].find("<<<END_CONTENT>>>").map(|end| stdout[start + 19..start + end].to_string()));
+                match content {
+                    Some(text) => {
+                        tracing::debug!("Extracted {:?} in the subprocess", path.file_name());
+                        return Some(text);
+                    }
+                    None => tracing::warn!("No content markers in the extract-pdf output for {:?}, retrying in-process", path),
+                }
+            }
+            // The PDF itself failed to parse, the in-process parser would fail (or crash) the same way
+            Ok(out) => {
+                tracing::warn!("PDF extraction failed for {:?}: {}", path, String::from_utf8_lossy(&out.stderr));
+                return None;
+            }
+            Err(e) => tracing::warn!("Failed to spawn extraction subprocess ({}), extracting {:?} in-process", e, path),
+        }
+    }
+
+    let bytes = std::fs::read(path)
+        .inspect_err(|e| tracing::warn!("Cannot read {:?}: {}", path, e))
+        .ok()?;
+    match std::panic::catch_unwind(|| pdf_extract::extract_text_from_mem(&bytes)) {
+        Ok(Ok(text)) => {
+            tracing::info!("Extracted {:?} in-process (fallback)", path.file_name());
+            Some(text)
+        }
+        Ok(Err(e)) => {
+            tracing::warn!("In-process PDF extraction failed for {:?}: {}", path, e);
+            None
+        }
+        Err(_) => {
+            tracing::warn!("In-process PDF extraction panicked for {:?}", path);
+            None
+        }
+    }
+}
+
+/// Run `extract-pdf --stdin` on a copy of ourselves, feeding it the PDF bytes
+fn extract_pdf_subprocess(exe_path: &Path, path: &Path) -> std::io::Result<std::process::Output> {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    let bytes = std::fs::read(path)?;
+    let mut child = Command::new(exe_path)
+        .arg("extract-pdf")
+        .arg("--stdin")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // Write from another thread so a child filling its stdout pipe can't deadlock us
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let writer = std::thread::spawn(move || stdin.write_all(&bytes));
+    let output = child.wait_with_output()?;
+    // A child that exits early (e.g. on a parse panic) closes the pipe; its status says why
+    let _ = writer.join();
+    Ok(output)
+}
+
+// ============================================================================
+// SUBTITLES (.srt / .vtt)
+// ============================================================================
+
+/// Seconds of speech between "[HH:MM:SS]" anchors in a transcript
+const ANCHOR_INTERVAL_SECS: u64 = 30;
+
+/// Track languages in order of preference when a recording has several
+const PREFERRED_LANGUAGES: &[&str] = &["es", "ca", "en"];
+
+const MEDIA_EXTENSIONS: &[&str] = &["mp4", "m4v", "mkv", "webm", "mov", "avi", "mp3", "m4a", "ogg", "wav"];
+
+/// Parse the subtitle files under `dir`, keeping one track per recording
+fn process_subtitles(dir: &Path, subject_path: &Path) -> Vec<ExtractedDoc> {
+    // Recording (directory + stem without language suffix) -> candidate tracks
+    let mut tracks: HashMap<PathBuf, Vec<(PathBuf, Option<String>)>> = HashMap::new();
+
+    for entry in walkdir::WalkDir::new(dir).into_iter().filter_map(|e| e.ok()) {
+        let path = entry.path();
+        let is_subtitle = path.extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| e.eq_ignore_ascii_case("srt") || e.eq_ignore_ascii_case("vtt"));
+        if !is_subtitle { continue; }
+
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy().to_string();
+        let (base, lang) = split_language_suffix(&stem);
+        let key = path.with_file_name(base);
+        tracks.entry(key).or_default().push((path.to_path_buf(), lang));
+    }
+
+    let mut docs = Vec::new();
+    for (recording, mut candidates) in tracks {
+        let rank = |lang: &Option<String>| {
+            lang.as_deref()
+                .and_then(|l| PREFERRED_LANGUAGES.iter().position(|p| *p == l))
+                .unwrap_or(PREFERRED_LANGUAGES.len())
+        };
+        candidates.sort_by_key(|(path, lang)| (rank(lang), path.clone()));
+        let (path, lang) = &candidates[0];
+
+        let raw = match std::fs::read(path) {
+            Ok(bytes) => decode_subtitle_bytes(&bytes),
+            Err(e) => {
+                tracing::warn!("Failed to read subtitles {:?}: {}", path, e);
+                continue;
+            }
+        };
+
+        let transcript = parse_subtitles(&raw);
+        if transcript.trim().is_empty() {
+            continue;
+        }
+
+        tracing::info!("Processing subtitles: {:?} ({} tracks, using {})", path.file_name(), candidates.len(), lang.as_deref().unwrap_or("default"));
+
+        let media = find_media_file(&recording).unwrap_or_else(|| {
+            recording.file_name().unwrap_or_default().to_string_lossy().to_string()
+        });
+        let rel_path = super::layout::relative_id(path, subject_path);
+
+        let mut metadata: HashMap<String, String> = [
+            ("type".to_string(), "transcript".to_string()),
+            ("media".to_string(), media),
+        ].into();
+        if let Some(lang) = lang {
+            metadata.insert("language".to_string(), lang.clone());
+        }
+        if let Some(date) = modified_date(path) {
+            metadata.insert("modified".to_string(), date);
+        }
+
+        docs.push(ExtractedDoc { rel_path, text: transcript, metadata });
+    }
+
+    docs
+}
+
+/// Split "clase5.es" / "clase5_ca" / "clase5-en" into ("clase5", Some("es"))
+fn split_language_suffix(stem: &str) -> (String, Option<String>) {
+    const KNOWN: &[&str] = &["es", "ca", "va", "en", "fr", "de", "it", "pt", "spa", "cat", "eng", "val"];
+    if let Some(pos) = stem.rfind(['.', '_', '-']) {
+        let suffix = stem[pos + 1..].to_lowercase();
+        if KNOWN.contains(&suffix.as_str()) && pos > 0 {
+            let lang = match suffix.as_str() {
+                "spa" => "es",
+                "cat" | "val" | "va" => "ca",
+                "eng" => "en",
+                other => other,
+            };
+            return (stem[..pos].to_string(), Some(lang.to_string()));
+        }
+    }
+    (stem.to_string(), None)
+}
+
+/// The recording a subtitle track belongs to, if it was downloaded alongside it
+fn find_media_file(recording: &Path) -> Option<String> {
+    MEDIA_EXTENSIONS.iter()
+        .map(|ext| recording.with_extension(ext))
+        .find(|p| p.exists())
+        .and_then(|p| p.file_name().map(|n| n.to_string_lossy().to_string()))
+}
+
+/// Subtitles come as UTF-8 (with or without BOM) or Latin-1 from older tools
+fn decode_subtitle_bytes(bytes: &[u8]) -> String {
+    let bytes = bytes.strip_prefix(&[0xEF, 0xBB, 0xBF]).unwrap_or(bytes);
+    match std::str::from_utf8(bytes) {
+        Ok(text) => text.to_string(),
+        // Latin-1 maps every byte to the code point of the same value
+        Err(_) => bytes.iter().map(|&b| b as char).collect(),
+    }
+}
+
+/// Turn SRT/VTT cues into paragraphs, starting a new one with a "[HH:MM:SS]" anchor
+/// every `ANCHOR_INTERVAL_SECS` seconds. Cue numbers, timings, tags and repeated lines are dropped.
+fn parse_subtitles(raw: &str) -> String {
+    let raw = raw.replace("\r\n", "\n").replace('\r', "\n");
+    let mut out = String::new();
+    let mut last_anchor: Option<u64> = None;
+    let mut last_text = String::new();
+
+    for block in raw.split("\n\n") {
+        let lines: Vec<&str> = block.lines().map(str::trim).filter(|l| !l.is_empty()).collect();
+        let Some(timing_idx) = lines.iter().position(|l| l.contains("-->")) else {
+            // WEBVTT header, NOTE, STYLE and REGION blocks have no timing line
+            continue;
+        };
+        let Some(start) = lines[timing_idx].split("-->").next().and_then(parse_timestamp) else {
+            continue;
+        };
+
+        let text = lines[timing_idx + 1..].iter()
+            .map(|l| strip_cue_markup(l))
+            .filter(|l| !l.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ");
+        // Auto-generated captions repeat the previous line as they scroll
+        if text.is_empty() || text == last_text {
+            continue;
+        }
+
+        if last_anchor.is_none_or(|a| start >= a + ANCHOR_INTERVAL_SECS) {
+            if !out.is_empty() {
+                out.push_str("\n\n");
+            }
+            out.push_str(&format!("[{:02}:{:02}:{:02}] ", start / 3600, (start / 60) % 60, start % 60));
+            last_anchor = Some(start);
+        } else {
+            out.push(' ');
+        }
+        out.push_str(&text);
+        last_text = text;
+    }
+
+    normalize_paragraphs(&out)
+}
+
+/// Parse "01:02:03,456", "01:02:03.456" or "02:03.456" (plus VTT cue settings) into whole seconds
+fn parse_timestamp(s: &str) -> Option<u64> {
+    let s = s.split_whitespace().next()?;
+    let s = s.split(['.', ',']).next()?;
+    let parts: Vec<u64> = s.split(':').map(|p| p.parse().ok()).collect::<Option<_>>()?;
+    match parts.as_slice() {
+        [h, m, sec] => Some(h * 3600 + m * 60 + sec),
+        [m, sec] => Some(m * 60 + sec),
+        _ => None,
+    }
+}
+
+/// Remove <i>, <c.color>, <v Speaker>, <00:01:02.000> and {\an8} style markup
+fn strip_cue_markup(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut depth_angle = false;
+    let mut depth_brace = false;
+    for c in line.chars() {
+        match c {
+            '<' => depth_angle = true,
+            '>' if depth_angle => depth_angle = false,
+            '{' => depth_brace = true,
+            '}' if depth_brace => depth_brace = false,
+            _ if depth_angle || depth_brace => {}
+            _ => out.push(c),
+        }
+    }
+    out.replace("&amp;", "&").replace("&lt;", "<").replace("&gt;", ">").replace("&nbsp;", " ").trim().to_string()
+}
+
+/// normalize_text per paragraph, so anchors keep starting their own paragraph
+fn normalize_paragraphs(text: &str) -> String {
+    text.split("\n\n")
+        .map(normalize_text)
+        .filter(|p| !p.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
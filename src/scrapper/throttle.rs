@@ -0,0 +1,155 @@
+//! Politeness towards PoliformaT: a jittered minimum delay between the page navigations of a
+//! tab, backoff when a navigation lands on an error page, and a cap on navigations in flight.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use headless_chrome::Tab;
+
+use crate::config::ThrottleConfig;
+
+/// Text of the error pages a proxy or Chrome shows when the portal is overloaded. The login
+/// gateway is not one of them: an expired session is handled by logging in again.
+const ERROR_MARKERS: &[&str] = &[
+    "502 Bad Gateway",
+    "503 Service",
+    "504 Gateway",
+    "Gateway Time-out",
+    "Gateway Timeout",
+    "Too Many Requests",
+    "Service Unavailable",
+];
+
+/// Time the throttle added to a sync
+#[derive(Clone, Copy, Default, Debug)]
+pub struct ThrottleStats {
+    /// Waiting between navigations and backing off
+    pub delay: Duration,
+    /// Navigations that landed on an error page and were retried
+    pub backoffs: usize,
+}
+
+#[derive(Default)]
+struct State {
+    in_flight: usize,
+    stats: ThrottleStats,
+}
+
+/// Shared by every tab of a sync
+pub struct Throttle {
+    config: Option<ThrottleConfig>,
+    state: Mutex<State>,
+    slot_freed: Condvar,
+}
+
+/// Pace of one tab (one subject): when it last navigated and its current delay, which
+/// doubles on error pages
+pub struct Pace {
+    delay: Duration,
+    last: Option<Instant>,
+}
+
+impl Throttle {
+    pub fn new(config: ThrottleConfig) -> Self {
+        Self { config: Some(config), state: Mutex::default(), slot_freed: Condvar::new() }
+    }
+
+    /// No delays, backoff or cap (`--fast`)
+    pub fn disabled() -> Self {
+        Self { config: None, state: Mutex::default(), slot_freed: Condvar::new() }
+    }
+
+    pub fn pace(&self) -> Pace {
+        Pace { delay: Duration::from_millis(self.config.map_or(0, |c| c.min_delay_ms)), last: None }
+    }
+
+    pub fn stats(&self) -> ThrottleStats {
+        self.state.lock().unwrap().stats
+    }
+
+    /// Navigate `tab` to `url` once the tab's delay has passed and a navigation slot is free.
+    /// An error page (or a failed navigation) doubles the tab's delay and is retried; after
+    /// the last retry the page is left as it is, or the error returned.
+    pub fn navigate(&self, tab: &Tab, pace: &mut Pace, url: &str) -> anyhow::Result<()> {
+        let Some(config) = self.config else {
+            tab.navigate_to(url)?;
+            return Ok(());
+        };
+
+        let mut attempt = 0;
+        loop {
+            self.wait_turn(pace, config);
+            let result = {
+                let _slot = self.acquire_slot(config.max_concurrent);
+                tab.navigate_to(url).and_then(|tab| tab.wait_until_navigated()).map(|_| ())
+            };
+            pace.last = Some(Instant::now());
+
+            let failed = result.is_err() || landed_on_error(tab);
+            if !failed || attempt >= config.retries {
+                return result;
+            }
+            attempt += 1;
+            pace.delay = (pace.delay * 2).max(Duration::from_millis(500)).min(Duration::from_millis(config.max_delay_ms));
+            self.state.lock().unwrap().stats.backoffs += 1;
+            tracing::warn!("Error page at {}, retrying in {:?} ({}/{})", url, pace.delay, attempt, config.retries);
+        }
+    }
+
+    /// Sleep until `pace.delay` plus jitter has passed since the tab's last navigation
+    fn wait_turn(&self, pace: &Pace, config: ThrottleConfig) {
+        let Some(last) = pace.last else { return };
+        let target = pace.delay + jitter(config.jitter_ms);
+        let wait = target.saturating_sub(last.elapsed());
+        if !wait.is_zero() {
+            std::thread::sleep(wait);
+            self.state.lock().unwrap().stats.delay += wait;
+        }
+    }
+
+    fn acquire_slot(&self, max: usize) -> Slot<'_> {
+        let start = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        while state.in_flight >= max.max(1) {
+            state = self.slot_freed.wait(state).unwrap();
+        }
+        state.in_flight += 1;
+        state.stats.delay += start.elapsed();
+        Slot(self)
+    }
+}
+
+/// A navigation in flight; frees its slot when dropped
+struct Slot<'a>(&'a Throttle);
+
+impl Drop for Slot<'_> {
+    fn drop(&mut self) {
+        self.0.state.lock().unwrap().in_flight -= 1;
+        self.0.slot_freed.notify_one();
+    }
+}
+
+/// Random duration up to `max_ms`
+fn jitter(max_ms: u64) -> Duration {
+    if max_ms == 0 {
+        return Duration::ZERO;
+    }
+    // A fresh RandomState is randomly keyed, which is enough randomness for spacing requests
+    let random = RandomState::new().build_hasher().finish();
+    Duration::from_millis(random % (max_ms + 1))
+}
+
+/// Whether the tab shows Chrome's error page or a proxy error instead of the portal
+fn landed_on_error(tab: &Tab) -> bool {
+    if tab.get_url().starts_with("chrome-error://") {
+        return true;
+    }
+    let head = tab.evaluate("document.title + '\\n' + (document.body ? document.body.innerText.slice(0, 500) : '')", false)
+        .ok()
+        .and_then(|r| r.value)
+        .and_then(|v| v.as_str().map(|s| s.to_string()))
+        .unwrap_or_default();
+    ERROR_MARKERS.iter().any(|marker| head.contains(marker))
+}
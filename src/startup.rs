@@ -0,0 +1,210 @@
+//! Plain-text explanations for failures that stop polirag before the interface is up,
+//! and the `--force-fresh-index` escape hatch for an index that can't be opened.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+
+use crate::rag;
+
+/// A failure that keeps polirag from starting, by what the user has to fix
+#[derive(Debug, thiserror::Error)]
+pub enum StartupError {
+    /// The index files can't be read or migrated
+    #[error("could not open the index at {}: {cause:#}", path.display())]
+    Storage { path: PathBuf, cause: anyhow::Error },
+    /// The app data directory can't be written
+    #[error("cannot write to {}: {cause:#}", path.display())]
+    Permissions { path: PathBuf, cause: anyhow::Error },
+    /// The terminal can't switch to the full-screen interface
+    #[error("could not set up the terminal: {0:#}")]
+    Terminal(anyhow::Error),
+    /// The bundled embedding model failed to load
+    #[error("could not load the embedding model: {0:#}")]
+    Embedder(anyhow::Error),
+}
+
+impl StartupError {
+    /// Sort out a `RagSystem::new` failure
+    pub fn from_rag(err: anyhow::Error) -> Self {
+        match err.downcast::<rag::OpenError>() {
+            Ok(rag::OpenError::Embedder(cause)) => StartupError::Embedder(cause),
+            Ok(rag::OpenError::Index { path, cause }) => {
+                let denied = cause.chain()
+                    .filter_map(|e| e.downcast_ref::<std::io::Error>())
+                    .any(|e| e.kind() == std::io::ErrorKind::PermissionDenied);
+                if denied {
+                    let dir = path.parent().map(Path::to_path_buf).unwrap_or(path);
+                    StartupError::Permissions { path: dir, cause }
+                } else {
+                    StartupError::Storage { path, cause }
+                }
+            }
+            Err(cause) => StartupError::Storage { path: crate::config::Config::get_index_path(), cause },
+        }
+    }
+
+    /// Process exit code per category, for scripts; login uses 2 to 4
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            StartupError::Storage { .. } => 10,
+            StartupError::Permissions { .. } => 11,
+            StartupError::Terminal(_) => 12,
+            StartupError::Embedder(_) => 13,
+        }
+    }
+
+    /// What went wrong, the path involved and what to try next
+    pub fn explain(&self) -> String {
+        let (summary, details, remedies): (&str, Vec<(&str, String)>, Vec<String>) = match self {
+            StartupError::Storage { path, cause } => (
+                "polirag could not open its search index.",
+                vec![("File", path.display().to_string()), ("Reason", format!("{:#}", cause))],
+                vec![
+                    "Run `polirag index info` to see which format and model the index was written with.".to_string(),
+                    "Run `polirag --force-fresh-index` to move the index aside (nothing is deleted) and start empty; the next sync indexes everything again.".to_string(),
+                ],
+            ),
+            StartupError::Permissions { path, cause } => (
+                "polirag cannot write to its data directory.",
+                vec![("Directory", path.display().to_string()), ("Reason", format!("{:#}", cause))],
+                vec![
+                    format!("Check that your user owns the directory: `ls -ld \"{}\"`.", path.display()),
+                    "Free some disk space if the disk is full.".to_string(),
+                ],
+            ),
+            StartupError::Terminal(cause) => (
+                "polirag could not start its full-screen interface.",
+                vec![("Reason", format!("{:#}", cause))],
+                vec![
+                    "Run it from an interactive terminal, not through a pipe or a session without a TTY.".to_string(),
+                    "Use the subcommands instead, e.g. `polirag sync` or `polirag retrieve \"<question>\"`.".to_string(),
+                ],
+            ),
+            StartupError::Embedder(cause) => (
+                "polirag could not load its embedding model.",
+                vec![("Model", rag::embeddings::model_id()), ("Reason", format!("{:#}", cause))],
+                vec![
+                    "The model is unpacked to the temporary directory on startup; check that it has space (or point TMPDIR elsewhere).".to_string(),
+                    "Rebuild or reinstall polirag if the problem persists.".to_string(),
+                ],
+            ),
+        };
+
+        let mut text = format!("Error: {}\n\n", summary);
+        for (label, value) in details {
+            text.push_str(&format!("  {:<10} {}\n", format!("{}:", label), value));
+        }
+        text.push_str("\nWhat you can do:\n");
+        for remedy in remedies {
+            text.push_str(&format!("  - {}\n", remedy));
+        }
+        text
+    }
+}
+
+/// Print the explanation and exit with the category's code
+pub fn exit(err: StartupError) -> ! {
+    // Only to debug.log: errors also go to stderr, which gets the explanation instead
+    tracing::info!("Startup failed: {}", err);
+    eprint!("{}", err.explain());
+    std::process::exit(err.exit_code())
+}
+
+/// Index files next to `index_path`: `polirag.index`, `polirag.data` and the graph dump
+fn index_files(index_path: &Path) -> Vec<PathBuf> {
+    let Some(stem) = index_path.file_stem().map(|s| s.to_string_lossy().to_string()) else { return Vec::new() };
+    let directory = index_path.parent().unwrap_or(Path::new("."));
+    let Ok(entries) = std::fs::read_dir(directory) else { return Vec::new() };
+
+    let mut files: Vec<PathBuf> = entries.flatten()
+        .map(|e| e.path())
+        .filter(|p| p.is_file())
+        .filter(|p| {
+            let name = p.file_name().unwrap_or_default().to_string_lossy();
+            // Backups and earlier moved-aside copies stay where they are
+            name.starts_with(&format!("{}.", stem)) && !name.ends_with(".bak") && !name.contains(".broken-")
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+/// `--force-fresh-index`: after confirmation, rename the index files to `<file>.broken-<unix time>`
+/// so polirag starts with an empty index. Returns false when the user declined.
+pub fn force_fresh_index(index_path: &Path) -> anyhow::Result<bool> {
+    let files = index_files(index_path);
+    if files.is_empty() {
+        println!("No index files next to {}, nothing to move.", index_path.display());
+        return Ok(true);
+    }
+
+    println!("These files will be moved aside and polirag will start with an empty index:");
+    for file in &files {
+        println!("  {}", file.display());
+    }
+    let confirmed = dialoguer::Confirm::new()
+        .with_prompt("Move them aside?")
+        .default(false)
+        .interact()
+        .context("--force-fresh-index asks for confirmation, run it from an interactive terminal")?;
+    if !confirmed {
+        return Ok(false);
+    }
+
+    let stamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    for file in files {
+        let mut target = file.clone().into_os_string();
+        target.push(format!(".broken-{}", stamp));
+        std::fs::rename(&file, &target)?;
+        println!("Moved {} to {}", file.display(), PathBuf::from(target).display());
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn open_error(path: &Path, cause: anyhow::Error) -> anyhow::Error {
+        rag::OpenError::Index { path: path.to_path_buf(), cause }.into()
+    }
+
+    #[test]
+    fn a_denied_index_is_a_permissions_problem_with_its_directory() {
+        let path = Path::new("/data/polirag/polirag.index");
+        let denied = anyhow::Error::new(std::io::Error::from(std::io::ErrorKind::PermissionDenied)).context("reading the header");
+        let err = StartupError::from_rag(open_error(path, denied));
+        assert!(matches!(&err, StartupError::Permissions { path, .. } if path == Path::new("/data/polirag")));
+        assert_eq!(err.exit_code(), 11);
+        assert!(err.explain().contains("ls -ld \"/data/polirag\""));
+
+        let corrupt = StartupError::from_rag(open_error(path, anyhow::anyhow!("unknown format version 9")));
+        assert!(matches!(&corrupt, StartupError::Storage { path: p, .. } if p == path));
+        assert_eq!(corrupt.exit_code(), 10);
+        let text = corrupt.explain();
+        assert!(text.starts_with("Error: polirag could not open its search index."));
+        assert!(text.contains("unknown format version 9"));
+        assert!(text.contains("--force-fresh-index"));
+
+        let embedder = StartupError::from_rag(rag::OpenError::Embedder(anyhow::anyhow!("no space left")).into());
+        assert_eq!(embedder.exit_code(), 13);
+    }
+
+    #[test]
+    fn backups_and_earlier_copies_are_not_moved_aside() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["polirag.index", "polirag.data", "polirag.hnsw.graph", "polirag.index.bak", "polirag.data.broken-1700000000", "config.json"] {
+            std::fs::write(dir.path().join(name), b"").unwrap();
+        }
+        let names: Vec<String> = index_files(&dir.path().join("polirag.index"))
+            .iter()
+            .map(|p| p.file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(names, ["polirag.data", "polirag.hnsw.graph", "polirag.index"]);
+        assert!(index_files(&dir.path().join("missing").join("polirag.index")).is_empty());
+    }
+}
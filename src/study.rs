@@ -0,0 +1,164 @@
+//! Active recall: a question generated from one indexed chunk, the answer judged against that
+//! same chunk, and a record per subject of what was asked so later sessions move on to chunks
+//! not quizzed yet. Both prompts forbid knowledge from outside the chunk.
+
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeSet, HashMap};
+use std::hash::{BuildHasher, Hasher};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::llm::ChatMessage;
+use crate::rag::{anchor, RagSystem};
+
+const STUDY_FILE: &str = "study.json";
+/// Chunks shorter than this rarely hold a whole idea to ask about
+const MIN_CHUNK_CHARS: usize = 300;
+/// Share of letters and spaces below which a chunk is mostly numbers, tables or code
+const MIN_PROSE_SHARE: f32 = 0.6;
+
+const QUESTION_PROMPT: &str = "You write one study question for a student from an excerpt of their course material.\n\nRULES:\n1. Use ONLY the excerpt. The question must be answerable from it alone; do not rely on outside knowledge.\n2. Ask about an idea, definition, method or result that matters, not trivia such as page numbers, dates of the document or file names.\n3. Write the question in the language of the excerpt.\n4. Reply with the question only.";
+
+const EVALUATION_PROMPT: &str = "You grade a student's answer to a study question against the source excerpt it was written from.\n\nRULES:\n1. Judge ONLY against the excerpt; do not use outside knowledge. If the excerpt does not settle a point, say so instead of guessing.\n2. Start your reply with exactly one line: `VERDICT: CORRECT`, `VERDICT: PARTIAL` or `VERDICT: INCORRECT`.\n3. Then explain briefly what was right and what was missing or wrong, and quote the sentence of the excerpt that answers the question.\n4. Answer in the language of the question.";
+
+/// What was asked in one subject over all sessions
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct SubjectStudy {
+    pub asked: u32,
+    pub correct: u32,
+    /// Times each chunk was asked about, by chunk id
+    #[serde(default)]
+    pub chunks: HashMap<String, u32>,
+    /// Files questions came from
+    #[serde(default)]
+    pub topics: BTreeSet<String>,
+}
+
+/// `study.json` in the app data directory: the study record of every subject
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct StudyLog {
+    #[serde(default)]
+    pub subjects: HashMap<String, SubjectStudy>,
+}
+
+impl StudyLog {
+    /// The saved log, empty when there is none yet or it can't be read
+    pub fn load() -> Self {
+        std::fs::read_to_string(Config::get_app_data_dir().join(STUDY_FILE))
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> anyhow::Result<()> {
+        std::fs::write(Config::get_app_data_dir().join(STUDY_FILE), serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn subject(&self, subject: &str) -> SubjectStudy {
+        self.subjects.get(subject).cloned().unwrap_or_default()
+    }
+
+    /// Count a judged question about `chunk` in `subject`
+    pub fn record(&mut self, subject: &str, chunk: &StudyChunk, correct: bool) {
+        let study = self.subjects.entry(subject.to_string()).or_default();
+        study.asked += 1;
+        study.correct += correct as u32;
+        *study.chunks.entry(chunk.id.clone()).or_insert(0) += 1;
+        study.topics.insert(chunk.topic.clone());
+    }
+}
+
+/// The chunk a question is generated from
+#[derive(Clone, Debug)]
+pub struct StudyChunk {
+    pub id: String,
+    pub text: String,
+    /// Path of the file within the subject
+    pub topic: String,
+}
+
+/// Whether `text` is worth a question: long enough and mostly prose
+fn high_value(text: &str) -> bool {
+    let total = text.chars().count();
+    if total < MIN_CHUNK_CHARS {
+        return false;
+    }
+    let prose = text.chars().filter(|c| c.is_alphabetic() || c.is_whitespace()).count();
+    prose as f32 / total as f32 >= MIN_PROSE_SHARE
+}
+
+/// A random high-value chunk of `subject`, among those asked about the fewest times
+pub fn pick_chunk(rag: &RagSystem, subject: &str, study: &SubjectStudy) -> Option<StudyChunk> {
+    let candidates: Vec<(u32, StudyChunk)> = rag.subject_chunks(subject)
+        .iter()
+        .filter(|doc| high_value(anchor::chunk_text(doc)))
+        .map(|doc| {
+            let topic = doc.metadata.get("filename").cloned()
+                .unwrap_or_else(|| doc.id.rsplit_once('#').map_or(doc.id.as_str(), |(base, _)| base).to_string());
+            let chunk = StudyChunk { id: doc.id.clone(), text: anchor::chunk_text(doc).to_string(), topic };
+            (study.chunks.get(&doc.id).copied().unwrap_or(0), chunk)
+        })
+        .collect();
+    let fewest = candidates.iter().map(|(times, _)| *times).min()?;
+    let mut least_asked: Vec<StudyChunk> = candidates.into_iter()
+        .filter(|(times, _)| *times == fewest)
+        .map(|(_, chunk)| chunk)
+        .collect();
+    // A fresh RandomState is randomly keyed, which is enough randomness to pick a chunk
+    let random = RandomState::new().build_hasher().finish();
+    let index = (random % least_asked.len() as u64) as usize;
+    Some(least_asked.swap_remove(index))
+}
+
+/// Messages asking for a question about `chunk`
+pub fn question_messages(chunk: &StudyChunk) -> Vec<ChatMessage> {
+    vec![
+        ChatMessage::new("system", QUESTION_PROMPT),
+        ChatMessage::new("user", format!("Excerpt from {}:\n\n{}", chunk.topic, chunk.text)),
+    ]
+}
+
+/// Messages asking to judge `answer` to `question` against `chunk`
+pub fn evaluation_messages(chunk: &StudyChunk, question: &str, answer: &str) -> Vec<ChatMessage> {
+    vec![
+        ChatMessage::new("system", EVALUATION_PROMPT),
+        ChatMessage::new("user", format!("Source excerpt ({}):\n\n{}\n\nQuestion: {}\n\nStudent's answer: {}", chunk.topic, chunk.text, question, answer)),
+    ]
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Verdict {
+    Correct,
+    Partial,
+    Incorrect,
+}
+
+impl Verdict {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Verdict::Correct => "Correct",
+            Verdict::Partial => "Partially correct",
+            Verdict::Incorrect => "Incorrect",
+        }
+    }
+}
+
+/// The verdict line of an evaluation and the explanation after it. `None` when the model
+/// left the line out; the whole reply is the explanation then.
+pub fn parse_evaluation(reply: &str) -> (Option<Verdict>, String) {
+    let reply = reply.rsplit("</think>").next().unwrap_or("").trim();
+    let (first, rest) = reply.split_once('\n').unwrap_or((reply, ""));
+    let line = first.trim().trim_matches(|c: char| c == '*' || c == '`').to_uppercase();
+    let Some(verdict) = line.strip_prefix("VERDICT:") else {
+        return (None, reply.to_string());
+    };
+    let verdict = match verdict.trim() {
+        v if v.starts_with("CORRECT") => Verdict::Correct,
+        v if v.starts_with("PARTIAL") => Verdict::Partial,
+        v if v.starts_with("INCORRECT") => Verdict::Incorrect,
+        _ => return (None, reply.to_string()),
+    };
+    (Some(verdict), rest.trim().to_string())
+}
@@ -0,0 +1,90 @@
+//! The subjects polirag knows about, from the scraped data directory and the index, with the
+//! user's sync and retrieval choices (`Config::subjects`).
+
+use std::collections::BTreeMap;
+
+use crate::config::{Config, SubjectPrefs};
+use crate::rag::RagSystem;
+use crate::scrapper;
+
+/// A subject seen in a sync, with what the user chose for it
+#[derive(Clone, Debug)]
+pub struct KnownSubject {
+    /// PoliformaT site id, e.g. `GRA_11673_2025`
+    pub id: String,
+    pub name: String,
+    pub prefs: SubjectPrefs,
+    /// Indexed documents (summary and chunks)
+    pub documents: usize,
+}
+
+impl KnownSubject {
+    /// The alias when one is set, the subject name otherwise
+    pub fn label(&self) -> &str {
+        self.prefs.alias.as_deref().unwrap_or(&self.name)
+    }
+}
+
+/// Site id a document belongs to: ids are `<site id>` for subject summaries and
+/// `<site id>/<file>#<n>` for files
+pub fn subject_of(doc_id: &str) -> &str {
+    let id = doc_id.split('/').next().unwrap_or(doc_id);
+    id.rsplit_once('#').map_or(id, |(base, _)| base)
+}
+
+/// Subjects with a folder in the data directory, an entry in the index or saved preferences,
+/// sorted by name
+pub fn known(rag: &RagSystem) -> Vec<KnownSubject> {
+    let config = Config::load();
+    let mut names: BTreeMap<String, String> = BTreeMap::new();
+
+    if let Ok(entries) = std::fs::read_dir(Config::get_scraped_data_dir()) {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if !path.is_dir() || entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            if let Some(sub) = scrapper::layout::read_meta(&path) {
+                names.insert(sub.id, sub.name);
+            }
+        }
+    }
+    for (id, name) in rag.subject_names() {
+        names.entry(id).or_insert(name);
+    }
+    for id in config.subjects.keys() {
+        names.entry(id.clone()).or_insert_with(|| id.clone());
+    }
+
+    let counts = rag.document_counts_by_subject();
+    let mut subjects: Vec<KnownSubject> = names.into_iter()
+        .map(|(id, name)| KnownSubject {
+            prefs: config.subject_prefs(&id),
+            documents: counts.get(&id).copied().unwrap_or(0),
+            id,
+            name,
+        })
+        .collect();
+    subjects.sort_by_key(|s| s.label().to_lowercase());
+    subjects
+}
+
+/// The subject `query` names: its site id or name (ignoring case), or a unique part of its
+/// name or alias
+pub fn find<'a>(subjects: &'a [KnownSubject], query: &str) -> anyhow::Result<&'a KnownSubject> {
+    let query = query.trim().to_lowercase();
+    if let Some(exact) = subjects.iter().find(|s| s.id.to_lowercase() == query || s.name.to_lowercase() == query) {
+        return Ok(exact);
+    }
+    let matches: Vec<&KnownSubject> = subjects.iter()
+        .filter(|s| s.name.to_lowercase().contains(&query) || s.prefs.alias.as_ref().is_some_and(|a| a.to_lowercase().contains(&query)))
+        .collect();
+    match matches.as_slice() {
+        [single] => Ok(single),
+        [] => anyhow::bail!("No subject matches '{}'. Run `polirag subjects list` to see them.", query),
+        _ => anyhow::bail!(
+            "'{}' matches several subjects, be more specific:\n  {}",
+            query, matches.iter().map(|s| format!("{} ({})", s.name, s.id)).collect::<Vec<_>>().join("\n  ")
+        ),
+    }
+}
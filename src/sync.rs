@@ -0,0 +1,740 @@
+//! The sync engine: scrape PoliformaT into the data directory and index what is new.
+//! Progress is reported as `SyncEvent`s so each frontend can show it its own way.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use text_splitter::TextSplitter;
+
+use crate::{config, rag, scrapper};
+use crate::scrapper::changes::{ChurnDecision, SubjectChanges, SyncReport};
+
+/// Progress of `run` and `scan_local_data`. `Display` gives a one-line log entry.
+#[derive(Debug, Clone)]
+pub enum SyncEvent {
+    /// Free-form progress, e.g. from the scraper
+    Log(String),
+    /// A subject or file had a problem; the sync carries on
+    Warning(String),
+    /// Names of the enrolled subjects to sync, before their content is scraped
+    SubjectsFound(Vec<String>),
+    /// Subjects seen for the first time; they are synced and searched until the user turns them off
+    NewSubjects(Vec<String>),
+    /// Indexing subject `current` of `total` (1-based)
+    Indexing { current: usize, total: usize, subject: String },
+    /// A file was indexed as `chunks` chunks
+    FileIndexed { subject: String, file: String, chunks: usize },
+}
+
+impl fmt::Display for SyncEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyncEvent::Log(msg) => write!(f, "{}", msg),
+            SyncEvent::Warning(msg) => write!(f, "  ⚠️  {}", msg),
+            SyncEvent::SubjectsFound(names) => write!(f, "📚 Found {} subjects", names.len()),
+            SyncEvent::NewSubjects(names) => write!(f, "🆕 New subjects: {}", names.join(", ")),
+            SyncEvent::Indexing { current, total, subject } => write!(f, "[{}/{}] 📖 Indexing: {}", current, total, subject),
+            SyncEvent::FileIndexed { subject, file, chunks } => write!(f, "  ➕ Indexed {}/{} ({} chunks)", subject, file, chunks),
+        }
+    }
+}
+
+/// Indexed files of a subject that a sync would replace or remove, when they are more than
+/// `ChurnConfig::threshold` of them (a course reorganised or moved to a new year)
+#[derive(Debug, Clone)]
+pub struct Churn {
+    pub subject: String,
+    /// Paths in the subject folder whose content changed
+    pub replaced: Vec<String>,
+    /// Paths in the subject folder that are gone
+    pub removed: Vec<String>,
+    /// Files of the subject in the index before the sync
+    pub indexed: usize,
+}
+
+impl Churn {
+    pub fn fraction(&self) -> f32 {
+        (self.replaced.len() + self.removed.len()) as f32 / self.indexed.max(1) as f32
+    }
+}
+
+impl fmt::Display for Churn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} changed and {} removed of {} indexed files ({:.0}%)",
+            self.subject, self.replaced.len(), self.removed.len(), self.indexed, self.fraction() * 100.0)
+    }
+}
+
+/// A `Churn` waiting for the user. Send `true` to index the new versions; dropping `reply` keeps the old ones.
+#[derive(Debug)]
+pub struct ChurnPrompt {
+    pub churn: Churn,
+    pub reply: tokio::sync::oneshot::Sender<bool>,
+}
+
+/// What `run` does with a subject over the churn threshold
+#[derive(Debug, Clone, Default)]
+pub enum ChurnPolicy {
+    /// Index the new versions, archiving the old ones
+    Accept,
+    /// Keep the indexed versions (headless syncs without `--accept-churn`)
+    #[default]
+    Keep,
+    /// Ask through the channel and wait for the answer
+    Ask(tokio::sync::mpsc::UnboundedSender<ChurnPrompt>),
+}
+
+/// What `run` downloads and whether it starts over
+#[derive(Debug, Clone)]
+pub struct SyncOptions {
+    pub mode: scrapper::SyncMode,
+    pub limits: config::DownloadLimits,
+    /// Clear the index and the downloaded files first, so everything is downloaded and indexed again
+    pub fresh: bool,
+    pub churn: ChurnPolicy,
+    /// Navigate as fast as the portal answers, without `config::ThrottleConfig`'s delays
+    pub fast: bool,
+    /// Start even when `disk::check_sync` finds too little free space, with a warning
+    pub ignore_low_space: bool,
+}
+
+/// Indexed files of a subject to re-index and to drop from the index
+#[derive(Default)]
+struct Replacements {
+    reindex: HashSet<String>,
+    prune: Vec<String>,
+}
+
+/// Work out which indexed files of `sub` changed or disappeared on disk and, when they are more
+/// than `threshold` of them, ask `policy` whether to apply that. Applied replacements are
+/// archived first; if the archive can't be written the indexed versions are kept.
+async fn review_churn(
+    rag: &rag::RagSystem,
+    sub: &scrapper::Subject,
+    changes: &SubjectChanges,
+    settings: config::ChurnConfig,
+    policy: &ChurnPolicy,
+    on_event: &(dyn Fn(SyncEvent) + Sync),
+) -> (Replacements, Option<ChurnDecision>) {
+    let indexed = rag.indexed_files(&sub.id);
+    let doc_id = |path: &str| format!("{}/{}", sub.id, path);
+    let in_index = |list: &[scrapper::changes::FileChange]| -> Vec<String> {
+        list.iter().map(|c| c.path.clone()).filter(|p| indexed.contains(&doc_id(p))).collect()
+    };
+    let churn = Churn { subject: sub.name.clone(), replaced: in_index(&changes.updated), removed: in_index(&changes.removed), indexed: indexed.len() };
+    if churn.replaced.is_empty() && churn.removed.is_empty() {
+        return (Replacements::default(), None);
+    }
+
+    let mut decision = None;
+    if churn.fraction() > settings.threshold {
+        let accepted = match policy {
+            ChurnPolicy::Accept => true,
+            ChurnPolicy::Keep => false,
+            ChurnPolicy::Ask(prompts) => {
+                let (reply, answer) = tokio::sync::oneshot::channel();
+                prompts.send(ChurnPrompt { churn: churn.clone(), reply }).is_ok() && answer.await.unwrap_or(false)
+            }
+        };
+        decision = Some(ChurnDecision {
+            subject: sub.name.clone(),
+            replaced: churn.replaced.len(),
+            removed: churn.removed.len(),
+            indexed: churn.indexed,
+            accepted,
+        });
+        if !accepted {
+            on_event(SyncEvent::Warning(format!("{}; kept the indexed versions (sync with --accept-churn to apply)", churn)));
+            return (Replacements::default(), decision);
+        }
+        on_event(SyncEvent::Log(format!("♻️  {}; applying", churn)));
+    }
+
+    let old: Vec<rag::Document> = churn.replaced.iter().chain(&churn.removed).flat_map(|p| rag.family_documents(&doc_id(p))).collect();
+    match rag::archive::write(&sub.id, &old) {
+        Ok(path) => tracing::info!("Archived {} documents of {} to {:?}", old.len(), sub.name, path),
+        Err(e) => {
+            on_event(SyncEvent::Warning(format!("{}: could not archive the replaced documents, keeping them: {}", sub.name, e)));
+            if let Some(decision) = decision.as_mut() {
+                decision.accepted = false;
+            }
+            return (Replacements::default(), decision);
+        }
+    }
+    (Replacements { reindex: churn.replaced.into_iter().collect(), prune: churn.removed }, decision)
+}
+
+/// Metadata key with the hash of the bytes of the file a chunk was read from
+pub const FILE_HASH_KEY: &str = "file_hash";
+/// Metadata key with the earlier paths of a file re-uploaded under other names, one per line,
+/// oldest first; `filename` is the newest
+pub const ALIASES_KEY: &str = "aliases";
+
+/// Metadata for a chunk of an extracted resource: its type (and media for transcripts), the
+/// filename and the hash of the file
+fn chunk_metadata(extracted: &HashMap<String, String>, rel_path: &str, file_hash: Option<&str>) -> HashMap<String, String> {
+    let mut metadata = extracted.clone();
+    metadata.insert("filename".to_string(), rel_path.to_string());
+    if let Some(hash) = file_hash {
+        metadata.insert(FILE_HASH_KEY.to_string(), hash.to_string());
+    }
+    metadata
+}
+
+/// What `IndexedFiles::link` did with a file whose bytes are already indexed under another name
+enum Linked {
+    /// Recorded as the newest name of `existing`, which is still on disk
+    Alias { existing: String },
+    /// `from` is gone from disk, so its chunks moved to the new name
+    Moved { from: String },
+    /// Already recorded by an earlier sync
+    Known,
+}
+
+/// The indexed files of a subject by the hash of their bytes, so a file re-uploaded under
+/// another name ("tema3_v2_FINAL.pdf") links to the chunks already indexed instead of being
+/// embedded again and returned twice by retrieval
+struct IndexedFiles {
+    subject_id: String,
+    subject_dir: PathBuf,
+    by_hash: HashMap<String, String>,
+}
+
+impl IndexedFiles {
+    fn new(rag: &rag::RagSystem, subject_id: &str, subject_dir: &Path) -> Self {
+        Self {
+            subject_id: subject_id.to_string(),
+            subject_dir: subject_dir.to_path_buf(),
+            by_hash: rag.families_by_metadata(subject_id, FILE_HASH_KEY),
+        }
+    }
+
+    /// Hash of the bytes of `rel_path`, `None` when it can't be read
+    fn hash(&self, rel_path: &str) -> Option<String> {
+        scrapper::changes::hash_file(&self.subject_dir.join(rel_path)).map(|entry| format!("{:016x}", entry.hash))
+    }
+
+    /// `doc_id` was embedded from a file with these bytes
+    fn indexed(&mut self, hash: Option<String>, doc_id: &str) {
+        if let Some(hash) = hash {
+            self.by_hash.insert(hash, doc_id.to_string());
+        }
+    }
+
+    /// Link `rel_path` to the indexed file with the same bytes: it becomes that file's newest
+    /// name, or takes over its chunks when the old file is gone. `None` when no indexed file
+    /// has these bytes and `rel_path` has to be embedded.
+    fn link(&mut self, rag: &rag::RagSystem, rel_path: &str, hash: &str) -> anyhow::Result<Option<Linked>> {
+        let Some(existing) = self.by_hash.get(hash).cloned() else { return Ok(None) };
+        let doc_id = format!("{}/{}", self.subject_id, rel_path);
+        let Some(metadata) = rag.family_documents(&existing).into_iter().next().map(|doc| doc.metadata) else {
+            self.by_hash.remove(hash);
+            return Ok(None);
+        };
+        let existing_path = existing.strip_prefix(&format!("{}/", self.subject_id)).unwrap_or(&existing).to_string();
+        let (known, aliases) = aliases_after_link(&metadata, &existing_path, rel_path);
+        let moving = !self.subject_dir.join(&existing_path).exists();
+        if !moving && known {
+            return Ok(Some(Linked::Known));
+        }
+
+        let entries = [("filename", Some(rel_path.to_string())), (ALIASES_KEY, Some(aliases.join("\n")))];
+        if moving {
+            rag.move_document_family(&existing, &doc_id)?;
+            rag.set_family_metadata(&doc_id, &entries)?;
+            self.by_hash.insert(hash.to_string(), doc_id);
+            Ok(Some(Linked::Moved { from: existing_path }))
+        } else {
+            rag.set_family_metadata(&existing, &entries)?;
+            Ok(Some(Linked::Alias { existing: existing_path }))
+        }
+    }
+}
+
+/// Aliases of a family once `rel_path` becomes its filename: its earlier names, oldest first,
+/// ending with the current one unless that is `rel_path`. Also whether `rel_path` already was
+/// one of its names. `existing_path` names families indexed before filenames were recorded.
+fn aliases_after_link(metadata: &HashMap<String, String>, existing_path: &str, rel_path: &str) -> (bool, Vec<String>) {
+    let current = metadata.get("filename").map_or(existing_path, String::as_str);
+    let mut aliases: Vec<&str> = metadata.get(ALIASES_KEY).map(|a| a.lines().collect()).unwrap_or_default();
+    let known = current == rel_path || aliases.contains(&rel_path);
+    aliases.retain(|a| *a != rel_path && *a != current);
+    if current != rel_path {
+        aliases.push(current);
+    }
+    (known, aliases.into_iter().map(str::to_string).collect())
+}
+
+/// Log line for a file linked to the chunks of an identical one
+fn linked_log(subject: &str, rel_path: &str, linked: &Linked) -> Option<String> {
+    match linked {
+        Linked::Alias { existing } => Some(format!("  🔗 {}/{}: same file as {}, linked without re-indexing", subject, rel_path, existing)),
+        Linked::Moved { from } => Some(format!("  🔗 {}/{}: renamed from {}, moved without re-indexing", subject, rel_path, from)),
+        Linked::Known => None,
+    }
+}
+
+/// Split an extracted file into `<doc_id>#<n>` chunks, each with a header naming the file and subject
+fn chunk_family(doc_id: &str, rel_path: &str, subject: &str, extracted: &scrapper::processing::ExtractedDoc, file_hash: Option<&str>, index_modified_dates: bool) -> Vec<(String, String, HashMap<String, String>)> {
+    let splitter = TextSplitter::new(rag::CHUNK_CHARS);
+    // Ranges count characters of the text as normalized and truncated, the text that is split
+    let chunks: Vec<(Range<usize>, &str)> = splitter.chunk_char_indices(&extracted.text)
+        .map(|c| (c.char_offset..c.char_offset + c.chunk.chars().count(), c.chunk))
+        .collect();
+
+    let filename = std::path::Path::new(rel_path).file_name().and_then(|n| n.to_str()).unwrap_or(rel_path);
+
+    let modified = if index_modified_dates { scrapper::processing::modified_line(&extracted.metadata) } else { String::new() };
+
+    let mut family = Vec::new();
+    if chunks.is_empty() {
+        let pdf_text = format!("### DOC: {}\nSubject: {}\n{}\n{}", filename, subject, modified, extracted.text);
+        let mut metadata = chunk_metadata(&extracted.metadata, rel_path, file_hash);
+        metadata.extend(rag::anchor::range_metadata(0..extracted.text.chars().count()));
+        family.push((format!("{}#0", doc_id), pdf_text, metadata));
+    } else {
+        for (i, (range, chunk)) in chunks.iter().enumerate() {
+            let pdf_text = format!("### DOC: {} (Part {}/{})\nCourse: {}\n{}\n{}", filename, i + 1, chunks.len(), subject, modified, chunk);
+            let mut metadata = chunk_metadata(&extracted.metadata, rel_path, file_hash);
+            metadata.extend(rag::anchor::range_metadata(range.clone()));
+            family.push((format!("{}#{}", doc_id, i), pdf_text, metadata));
+        }
+    }
+    family
+}
+
+/// Log in if the session expired, using the saved credentials or `POLIFORMAT_USER`/`POLIFORMAT_PIN`
+async fn ensure_logged_in(poliformat: &Arc<scrapper::PoliformatClient>) -> anyhow::Result<()> {
+    if poliformat.check_connection().await.unwrap_or(false) {
+        return Ok(());
+    }
+    tracing::warn!("Not authenticated. Checking for credentials...");
+
+    // First, try cached credentials from config
+    let cached_creds = config::Config::get_credentials().map(|c| (c.username, c.pin));
+
+    // Then try environment variables
+    let env_creds = {
+        let username = std::env::var("POLIFORMAT_USER").or_else(|_| std::env::var("POLIFORMAT_DNI"));
+        let pin = std::env::var("POLIFORMAT_PIN").or_else(|_| std::env::var("POLIFORMAT_PASSWORD"));
+        if let (Ok(u), Ok(p)) = (username, pin) {
+            Some((u, p))
+        } else {
+            None
+        }
+    };
+
+    // Prefer cached credentials, fallback to env
+    let Some((u, p)) = cached_creds.or(env_creds) else {
+        tracing::error!("No credentials found in config or .env.");
+        anyhow::bail!("No credentials available. Please login via the Menu first.");
+    };
+
+    tracing::info!("Credentials found. Attempting automatic login for user: {}", u);
+    let creds = scrapper::auth::AuthCredentials {
+        username: u.clone(),
+        pin: p.clone(),
+    };
+
+    // The browser login returns the new session's cookies, which must then work
+    match poliformat.relogin(move |base_url| scrapper::auth::headless_login(&creds, base_url)).await {
+        Ok(_) => {
+            tracing::info!("Login successful!");
+            // Save credentials to config for future use
+            if let Err(e) = config::Config::save_credentials(&u, &p) {
+                tracing::warn!("Failed to cache credentials: {}", e);
+            }
+            Ok(())
+        },
+        Err(e) => {
+            tracing::error!("Auto-login failed: {}", e);
+            // Clear bad cached credentials
+            let _ = config::Config::clear_credentials();
+            anyhow::bail!("Login failed. Please login via the Menu first.");
+        }
+    }
+}
+
+/// Record subjects seen for the first time in the config (enabled) and report them, then
+/// drop the subjects whose `sync` preference is off
+fn apply_subject_prefs(subjects: Vec<scrapper::Subject>, existing_dirs: &std::collections::HashSet<String>, on_event: &dyn Fn(SyncEvent)) -> Vec<scrapper::Subject> {
+    let mut config = config::Config::load();
+    let unseen: Vec<&scrapper::Subject> = subjects.iter().filter(|s| !config.subjects.contains_key(&s.id)).collect();
+    if !unseen.is_empty() {
+        let new: Vec<String> = unseen.iter()
+            .filter(|s| !existing_dirs.contains(&scrapper::layout::dir_name(&s.id)))
+            .map(|s| s.name.clone())
+            .collect();
+        for sub in &unseen {
+            config.subjects.insert(sub.id.clone(), config::SubjectPrefs::default());
+        }
+        if let Err(e) = config.save() {
+            tracing::warn!("Failed to record new subjects: {}", e);
+        }
+        if !new.is_empty() {
+            on_event(SyncEvent::NewSubjects(new));
+        }
+    }
+
+    let (synced, skipped): (Vec<_>, Vec<_>) = subjects.into_iter().partition(|s| config.subject_prefs(&s.id).sync);
+    if !skipped.is_empty() {
+        let names: Vec<&str> = skipped.iter().map(|s| s.name.as_str()).collect();
+        on_event(SyncEvent::Log(format!("⏭️  Skipping {} disabled subjects: {}", skipped.len(), names.join(", "))));
+    }
+    synced
+}
+
+/// Scrape every enrolled subject, index the subject summaries and files that are not in the
+/// index yet, re-index the files that changed and drop the ones that are gone (see
+/// `review_churn`). Returns what changed on disk, which is also saved as the last sync report.
+pub async fn run(
+    rag: Arc<rag::RagSystem>,
+    poliformat: Arc<scrapper::PoliformatClient>,
+    options: SyncOptions,
+    on_event: impl Fn(SyncEvent) + Send + Sync + 'static,
+) -> anyhow::Result<SyncReport> {
+    let SyncOptions { mode, limits, fresh, churn, fast, ignore_low_space } = options;
+    tracing::info!("Starting Sync ({:?}, fresh: {}, fast: {})...", mode, fresh, fast);
+    let on_event = Arc::new(on_event);
+
+    // Fail before scraping (or clearing) anything if the files and index can't be written
+    config::Config::check_data_dir_writable()?;
+    // A disk filling up halfway leaves half-written zips and a truncated index, so don't start
+    if let Err(low) = crate::disk::check_sync(&config::Config::get_scraped_data_dir()) {
+        if !ignore_low_space {
+            return Err(low.into());
+        }
+        on_event(SyncEvent::Warning(format!("{}; syncing anyway", low)));
+    }
+
+    ensure_logged_in(&poliformat).await?;
+
+    // Hashes of the current files, compared with the new download at the end
+    let data_dir = config::Config::get_scraped_data_dir();
+    let before = scrapper::changes::snapshot(&data_dir);
+    // Subjects downloaded before per-subject preferences existed aren't new
+    let existing_dirs: std::collections::HashSet<String> = std::fs::read_dir(&data_dir)
+        .map(|entries| entries.flatten().map(|e| e.file_name().to_string_lossy().to_string()).collect())
+        .unwrap_or_default();
+
+    if fresh {
+        on_event(SyncEvent::Log("🗑️  Clearing old RAG index...".to_string()));
+        rag.clear()?;
+        if data_dir.exists() {
+            on_event(SyncEvent::Log("🗑️  Removing old data directory...".to_string()));
+            let _ = std::fs::remove_dir_all(&data_dir);
+        }
+    }
+
+    if !data_dir.exists() {
+        tracing::info!("Creating data directory: {:?}", data_dir);
+        std::fs::create_dir_all(&data_dir)?;
+    }
+
+    // 1. Fetch Subjects
+    let scrape_start = std::time::Instant::now();
+    on_event(SyncEvent::Log("🔍 Fetching subjects from PoliformaT...".to_string()));
+    let subjects = poliformat.get_subjects().await?;
+    let enrolled: Vec<String> = subjects.iter().map(|s| s.id.clone()).collect();
+    let subjects = apply_subject_prefs(subjects, &existing_dirs, on_event.as_ref());
+    on_event(SyncEvent::SubjectsFound(subjects.iter().map(|s| s.name.clone()).collect()));
+
+    // 2. Fetch Deep Content
+    on_event(SyncEvent::Log(format!("⏳ Scraping content for {} subjects (this may take 2-3 mins)...", subjects.len())));
+    let config = config::Config::load();
+    let throttle = Arc::new(if fast { scrapper::throttle::Throttle::disabled() } else { scrapper::throttle::Throttle::new(config.throttle) });
+    let scrape_events = on_event.clone();
+    let detailed_subjects = poliformat.scrape_subject_content(subjects, mode, limits.clone(), throttle.clone(), move |msg| scrape_events(SyncEvent::Log(msg))).await?;
+    on_event(SyncEvent::Log("✅ Downloads complete!".to_string()));
+    let scrape_secs = scrape_start.elapsed().as_secs();
+    let throttled = throttle.stats();
+
+    let index_start = std::time::Instant::now();
+    let index_modified_dates = config.rag.index_modified_dates;
+    let expired = rag::archive::prune(config.churn.archive_days);
+    if expired > 0 {
+        tracing::info!("Deleted {} expired archives", expired);
+    }
+    let downloaded = scrapper::changes::snapshot(&data_dir);
+    let changes = scrapper::changes::diff(&before, &downloaded);
+    let mut decisions = Vec::new();
+    let batch = rag.begin_batch();
+
+    let total = detailed_subjects.len();
+    for (i, (sub, dir_path)) in detailed_subjects.into_iter().enumerate() {
+        on_event(SyncEvent::Indexing { current: i + 1, total, subject: sub.name.clone() });
+
+        let summary_path = std::path::Path::new(&dir_path).join("summary.md");
+        let mut content = if summary_path.exists() {
+            std::fs::read_to_string(&summary_path).unwrap_or_default()
+        } else {
+            on_event(SyncEvent::Warning(format!("No summary found for {}, skipping", sub.name)));
+            continue;
+        };
+
+        // Append list of found resources
+        let resources_path = std::path::Path::new(&dir_path).join("resources");
+        if resources_path.exists() {
+            use std::fmt::Write;
+            let mut file_list = String::new();
+            writeln!(&mut file_list, "\n\n[Local Files]:").unwrap();
+            if let Ok(entries) = std::fs::read_dir(&resources_path) {
+                for entry in entries.flatten() {
+                    if let Ok(name) = entry.file_name().into_string() {
+                        writeln!(&mut file_list, "- {}", name).unwrap();
+                    }
+                }
+            }
+            content.push_str(&file_list);
+        }
+
+        // Unzip and extract PDFs and transcripts; already extracted files are only rescanned
+        let extracted_docs = match scrapper::processing::process_resources(std::path::Path::new(&dir_path), &limits) {
+            Ok(d) => d,
+            Err(e) => {
+                on_event(SyncEvent::Warning(format!("Error processing resources for {}: {}", sub.name, e)));
+                Vec::new()
+            }
+        };
+        for warning in extracted_docs.iter().filter_map(|d| d.truncation_warning()) {
+            on_event(SyncEvent::Warning(format!("{}: {}", sub.name, warning)));
+        }
+
+        let full_text = format!("Subject: {}\nURL: {}\n\n{}", sub.name, sub.url, content);
+
+        // Add or refresh the summary doc; an unchanged one isn't embedded again
+        let summary_changed = rag.add_document(
+            &sub.id,
+            &full_text,
+            "user",
+            [
+                ("type".to_string(), "subject".to_string()),
+                ("name".to_string(), sub.name.clone())
+            ].into(),
+            false,
+        ).await?;
+        if summary_changed {
+            tracing::info!("Indexed subject summary: {}", sub.name);
+        } else {
+            tracing::debug!("Skipping unchanged subject summary: {}", sub.name);
+        }
+
+        let subject_dir = scrapper::layout::display_name(std::path::Path::new(&dir_path));
+        let replacements = match changes.iter().find(|c| c.subject == subject_dir) {
+            Some(subject_changes) => {
+                let (replacements, decision) = review_churn(&rag, &sub, subject_changes, config.churn, &churn, on_event.as_ref()).await;
+                decisions.extend(decision);
+                replacements
+            }
+            None => Replacements::default(),
+        };
+
+        // Add PDF and transcript docs
+        let mut indexed_files = IndexedFiles::new(&rag, &sub.id, std::path::Path::new(&dir_path));
+        let mut moved = HashSet::new();
+        for extracted in extracted_docs {
+            let rel_path = extracted.rel_path.clone();
+            let doc_id = format!("{}/{}", sub.id, rel_path);
+            let reindex = replacements.reindex.contains(&rel_path);
+
+            if !rag.contains(&format!("{}#0", doc_id)) || reindex {
+                let hash = indexed_files.hash(&rel_path);
+                if let Some(linked) = hash.as_deref().filter(|_| !reindex).map(|hash| indexed_files.link(&rag, &rel_path, hash)).transpose()?.flatten() {
+                    if let Linked::Moved { from } = &linked {
+                        moved.insert(from.clone());
+                    }
+                    if let Some(log) = linked_log(&sub.name, &rel_path, &linked) {
+                        on_event(SyncEvent::Log(log));
+                    }
+                    continue;
+                }
+                let family = chunk_family(&doc_id, &rel_path, &sub.name, &extracted, hash.as_deref(), index_modified_dates);
+                let chunks = rag.replace_document_family(&doc_id, "user", family).await?;
+                indexed_files.indexed(hash, &doc_id);
+                on_event(SyncEvent::FileIndexed { subject: sub.name.clone(), file: rel_path, chunks });
+            } else {
+                tracing::debug!("Skipping existing PDF: {}", rel_path);
+            }
+        }
+
+        // After indexing, so a removed file whose bytes came back under a new name was moved instead
+        for path in replacements.prune.iter().filter(|path| !moved.contains(*path)) {
+            rag.remove_document_family(&format!("{}/{}", sub.id, path))?;
+            on_event(SyncEvent::Log(format!("  🗑️  Removed {}/{} from the index", sub.name, path)));
+        }
+
+        // Save intermittently (good for large scrapes)
+        let _ = rag.save();
+    }
+
+    tracing::info!("Saving RAG index...");
+    batch.commit()?;
+
+    let mut report = SyncReport::new(&before, &scrapper::changes::snapshot(&data_dir));
+    report.churn = decisions;
+    report.enrolled = enrolled;
+    report.timing = scrapper::changes::SyncTiming {
+        scrape_secs,
+        index_secs: index_start.elapsed().as_secs(),
+        throttle_secs: throttled.delay.as_secs(),
+        backoffs: throttled.backoffs,
+        fast,
+    };
+    if let Err(e) = report.save() {
+        tracing::warn!("Failed to save sync report: {}", e);
+    }
+
+    tracing::info!("Sync Complete.");
+    Ok(report)
+}
+
+/// Index files already in the data directory that are not in the index yet, without going
+/// online. Returns the ids of the chunks it added.
+pub async fn scan_local_data(rag: Arc<rag::RagSystem>, on_event: impl Fn(SyncEvent)) -> anyhow::Result<Vec<String>> {
+    on_event(SyncEvent::Log("🔍 Scanning local data directory...".to_string()));
+
+    let data_dir = config::Config::get_scraped_data_dir();
+    if !data_dir.exists() {
+        on_event(SyncEvent::Warning("Data directory not found.".to_string()));
+        return Ok(Vec::new());
+    }
+
+    let mut added_ids = Vec::new();
+    let mut linked_any = false;
+    let batch = rag.begin_batch();
+    let config = config::Config::load();
+    let index_modified_dates = config.rag.index_modified_dates;
+
+    // Iterate over subject directories
+    let entries = std::fs::read_dir(&data_dir)?;
+    for entry in entries.flatten() {
+        if !entry.path().is_dir() { continue; }
+
+        let path = entry.path();
+        let dir_name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+
+        // Skip hidden folders
+        if dir_name.starts_with('.') { continue; }
+
+        // Folders from before the id-based layout have no metadata; the folder name stands in for both
+        let (subject_id, subject_name) = match scrapper::layout::read_meta(&path) {
+            Some(sub) => (sub.id, sub.name),
+            None => (dir_name.clone(), dir_name.clone()),
+        };
+        if !config.subject_prefs(&subject_id).sync {
+            tracing::debug!("Skipping disabled subject {}", subject_name);
+            continue;
+        }
+
+        on_event(SyncEvent::Log(format!("Checking subject: {}", subject_name)));
+
+        // 1. Process Resources
+        let extracted_docs = match scrapper::processing::process_resources(&path, &config.downloads) {
+            Ok(d) => d,
+            Err(e) => {
+                tracing::error!("Error processing resources for {}: {}", dir_name, e);
+                Vec::new()
+            }
+        };
+        for warning in extracted_docs.iter().filter_map(|d| d.truncation_warning()) {
+            on_event(SyncEvent::Warning(warning));
+        }
+
+        // 2. Index PDFs and transcripts
+        let mut indexed_files = IndexedFiles::new(&rag, &subject_id, &path);
+        for extracted in extracted_docs {
+            let rel_path = extracted.rel_path.clone();
+            let doc_id = format!("{}/{}", subject_id, rel_path);
+
+            // Chunk 0 missing means the file is new (or only has an old unchunked entry)
+            if !rag.contains(&format!("{}#0", doc_id)) {
+                // An OLD unchunked version is part of the family and gets replaced with it
+                if rag.contains(&doc_id) {
+                    on_event(SyncEvent::Log(format!("  🗑️  Removing old unchunked entry for: {}", rel_path)));
+                }
+
+                let hash = indexed_files.hash(&rel_path);
+                if let Some(linked) = hash.as_deref().map(|hash| indexed_files.link(&rag, &rel_path, hash)).transpose()?.flatten() {
+                    if let Some(log) = linked_log(&subject_name, &rel_path, &linked) {
+                        linked_any = true;
+                        on_event(SyncEvent::Log(log));
+                    }
+                    continue;
+                }
+                let family = chunk_family(&doc_id, &rel_path, &subject_name, &extracted, hash.as_deref(), index_modified_dates);
+                let ids: Vec<String> = family.iter().map(|(id, _, _)| id.clone()).collect();
+                let chunks = rag.replace_document_family(&doc_id, "user", family).await?;
+                indexed_files.indexed(hash, &doc_id);
+                on_event(SyncEvent::FileIndexed { subject: subject_name.clone(), file: rel_path, chunks });
+                added_ids.extend(ids);
+            }
+        }
+    }
+
+    if !added_ids.is_empty() || linked_any {
+        batch.commit()?;
+    }
+
+    Ok(added_ids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extracted(text: String) -> scrapper::processing::ExtractedDoc {
+        scrapper::processing::ExtractedDoc {
+            rel_path: "resources/tema1.pdf".to_string(),
+            text,
+            metadata: HashMap::from([("type".to_string(), "pdf".to_string())]),
+        }
+    }
+
+    #[test]
+    fn chunk_ranges_point_back_into_the_extracted_text() {
+        let paragraphs = ["La normalización elimina la redundancia. ", "正規化は冗長性を取り除く。", "Emoji 🎓📚 and ñandú. "];
+        let text: String = (0..60).map(|i| format!("{}{}\n\n", i, paragraphs[i % 3].repeat(4))).collect();
+        let family: Vec<rag::Document> = chunk_family("GRA_1/resources/tema1.pdf", "resources/tema1.pdf", "GRA_1", &extracted(text.clone()), None, false)
+            .into_iter()
+            .map(|(id, content, metadata)| rag::Document { id, content, embedding: Vec::new(), metadata, user_id: "user".to_string() })
+            .collect();
+        assert!(family.len() > 3);
+
+        let rebuilt = rag::anchor::reassemble(&family).unwrap();
+        let original: Vec<char> = text.chars().collect();
+        let rebuilt_chars: Vec<char> = rebuilt.text.chars().collect();
+        for doc in &family {
+            let range = rebuilt.range_of(&doc.id).unwrap();
+            let chunk: String = rebuilt_chars[range.clone()].iter().collect();
+            assert_eq!(chunk, original[range].iter().collect::<String>());
+            assert_eq!(chunk, rag::anchor::chunk_text(doc));
+        }
+        // Only the whitespace trimmed between chunks differs
+        assert_eq!(rebuilt.text.split_whitespace().collect::<Vec<_>>(), text.split_whitespace().collect::<Vec<_>>());
+    }
+
+    fn names(filename: Option<&str>, aliases: &[&str]) -> HashMap<String, String> {
+        let mut metadata = HashMap::new();
+        if let Some(filename) = filename {
+            metadata.insert("filename".to_string(), filename.to_string());
+        }
+        if !aliases.is_empty() {
+            metadata.insert(ALIASES_KEY.to_string(), aliases.join("\n"));
+        }
+        metadata
+    }
+
+    #[test]
+    fn a_new_name_keeps_the_earlier_ones_as_aliases() {
+        let metadata = names(Some("tema3_v2.pdf"), &["tema3.pdf"]);
+        assert_eq!(aliases_after_link(&metadata, "tema3.pdf", "tema3_FINAL.pdf"), (false, vec!["tema3.pdf".to_string(), "tema3_v2.pdf".to_string()]));
+        // A name it had before comes back as the filename and leaves the aliases
+        assert_eq!(aliases_after_link(&metadata, "tema3.pdf", "tema3.pdf"), (true, vec!["tema3_v2.pdf".to_string()]));
+        assert_eq!(aliases_after_link(&metadata, "tema3.pdf", "tema3_v2.pdf"), (true, vec!["tema3.pdf".to_string()]));
+        // Chunks indexed before filenames were recorded go by their path
+        assert_eq!(aliases_after_link(&names(None, &[]), "tema3.pdf", "copia.pdf"), (false, vec!["tema3.pdf".to_string()]));
+    }
+}
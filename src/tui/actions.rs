@@ -0,0 +1,249 @@
+//! Every action of the interface that can run from anywhere: the main menu, the command
+//! palette (Ctrl+K) and the global hotkeys all dispatch through `ACTIONS`, so a feature
+//! registers its action here once.
+
+use std::collections::HashMap;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Action {
+    Menu,
+    Chat,
+    /// The sync log, without starting a sync
+    SyncLog,
+    /// Full sync from scratch, like the menu entry
+    SyncFull,
+    SyncResources,
+    RagInfo,
+    Subjects,
+    /// Study mode, resuming the session left for another screen
+    Study,
+    Login,
+    Logout,
+    Settings,
+    /// Settings with a field focused: 0 provider, 1 OpenRouter API key, 2 model
+    SettingsField(usize),
+    /// Index new local files, then recalculate every embedding
+    Reembed,
+    RebuildGraph,
+    /// Look for scraped files to delete, see `crate::clean`
+    CleanData,
+    ToggleThinking,
+    ToggleSources,
+    ClearChat,
+    /// A chat slash command. One ending in a space takes an argument, so it is put in the
+    /// input to complete instead of run.
+    Slash(&'static str),
+    Palette,
+    Quit,
+}
+
+pub struct ActionSpec {
+    /// Name used in `keybindings` of the config
+    pub id: &'static str,
+    /// What the palette shows and filters on
+    pub label: &'static str,
+    pub action: Action,
+}
+
+const fn spec(id: &'static str, label: &'static str, action: Action) -> ActionSpec {
+    ActionSpec { id, label, action }
+}
+
+pub const ACTIONS: &[ActionSpec] = &[
+    spec("mode.chat", "Go to Chat", Action::Chat),
+    spec("mode.sync", "Go to Sync log", Action::SyncLog),
+    spec("mode.rag_info", "Go to RAG Info", Action::RagInfo),
+    spec("mode.settings", "Go to Settings", Action::Settings),
+    spec("mode.subjects", "Go to Subjects", Action::Subjects),
+    spec("mode.study", "Go to Study: questions from a subject's material", Action::Study),
+    spec("mode.login", "Go to Login", Action::Login),
+    spec("mode.menu", "Go to main menu", Action::Menu),
+    spec("sync.full", "Sync: full, rebuilding the index", Action::SyncFull),
+    spec("sync.resources", "Sync: resources only", Action::SyncResources),
+    spec("index.reembed", "Index: scan new files and recalculate embeddings", Action::Reembed),
+    spec("index.rebuild", "Index: rebuild search graph", Action::RebuildGraph),
+    spec("index.clean", "Index: free disk space in the scraped data", Action::CleanData),
+    spec("settings.provider", "Settings: switch provider", Action::SettingsField(0)),
+    spec("settings.api_key", "Settings: OpenRouter API key", Action::SettingsField(1)),
+    spec("settings.model", "Settings: model", Action::SettingsField(2)),
+    spec("chat.toggle_thinking", "Chat: show/hide thinking of the last answer", Action::ToggleThinking),
+    spec("chat.toggle_sources", "Chat: show/hide the retrieved sources of the answer in view", Action::ToggleSources),
+    spec("chat.clear", "Chat: clear history", Action::ClearChat),
+    spec("chat.retry", "/retry: ask the last question again", Action::Slash("/retry")),
+    spec("chat.sources", "/sources: how the last prompt was built", Action::Slash("/sources")),
+    spec("chat.cite", "/cite: copy a citation block for the last answer", Action::Slash("/cite")),
+    spec("chat.cite_bibtex", "/cite bibtex: copy a BibTeX entry for the last answer", Action::Slash("/cite bibtex")),
+    spec("chat.view", "/view: show a snippet of the last prompt in its file", Action::Slash("/view ")),
+    spec("chat.lookup", "/lookup: quote matching sentences without the model", Action::Slash("/lookup ")),
+    spec("chat.paste", "/paste: add text to search with your documents this session", Action::Slash("/paste")),
+    spec("chat.paste_clear", "/paste clear: drop the pasted texts", Action::Slash("/paste clear")),
+    spec("chat.find", "/find: search the conversation", Action::Slash("/find ")),
+    spec("chat.model", "/model: set the chat model", Action::Slash("/model ")),
+    spec("chat.mode", "/mode: toggle summary-first answers", Action::Slash("/mode")),
+    spec("chat.ragmode_compact", "/ragmode compact: smaller context", Action::Slash("/ragmode compact")),
+    spec("chat.ragmode_detailed", "/ragmode detailed: larger context", Action::Slash("/ragmode detailed")),
+    spec("chat.topk", "/topk: snippets retrieved per question", Action::Slash("/topk ")),
+    spec("chat.threshold", "/threshold: lowest score a snippet needs", Action::Slash("/threshold ")),
+    spec("chat.types", "/types: retrieve only some document types", Action::Slash("/types ")),
+    spec("chat.scope", "/scope: retrieve only tagged documents", Action::Slash("/scope ")),
+    spec("chat.tag", "/tag: tag a document", Action::Slash("/tag ")),
+    spec("chat.subject", "/subject: show the subject affinity", Action::Slash("/subject")),
+    spec("chat.subject_clear", "/subject clear: reset the subject affinity", Action::Slash("/subject clear")),
+    spec("chat.rename", "/rename: rename the chat", Action::Slash("/rename ")),
+    spec("chat.reload", "/reload: switch to the provider and model in the config file", Action::Slash("/reload")),
+    spec("logout", "Log out / Change account", Action::Logout),
+    spec("palette", "Command palette", Action::Palette),
+    spec("quit", "Quit", Action::Quit),
+];
+
+/// Hotkeys that work from every screen; `keybindings` in the config adds to and overrides them
+const DEFAULT_BINDINGS: &[(&str, &str)] = &[
+    ("f1", "mode.chat"),
+    ("f2", "mode.sync"),
+    ("f3", "mode.rag_info"),
+    ("f4", "mode.settings"),
+    ("ctrl+k", "palette"),
+];
+
+pub fn find(id: &str) -> Option<&'static ActionSpec> {
+    ACTIONS.iter().find(|spec| spec.id == id)
+}
+
+/// "ctrl+k", "alt+s", "f5", "shift+f2" into a key code and modifiers
+fn parse_key(spec: &str) -> Option<(KeyCode, KeyModifiers)> {
+    let spec = spec.trim().to_lowercase();
+    let mut parts: Vec<&str> = spec.split('+').map(str::trim).collect();
+    let key = parts.pop()?;
+    let mut modifiers = KeyModifiers::NONE;
+    for part in parts {
+        modifiers |= match part {
+            "ctrl" | "control" => KeyModifiers::CONTROL,
+            "alt" => KeyModifiers::ALT,
+            "shift" => KeyModifiers::SHIFT,
+            _ => return None,
+        };
+    }
+    let code = match key {
+        "tab" => KeyCode::Tab,
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "pageup" => KeyCode::PageUp,
+        "pagedown" => KeyCode::PageDown,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        _ => match key.strip_prefix('f').and_then(|n| n.parse::<u8>().ok()) {
+            Some(n) if (1..=12).contains(&n) => KeyCode::F(n),
+            _ => {
+                let mut chars = key.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => KeyCode::Char(c),
+                    _ => return None,
+                }
+            }
+        },
+    };
+    Some((code, modifiers))
+}
+
+/// The code and modifiers a binding is compared on: letters lower case, only Ctrl/Alt/Shift
+fn normalize(code: KeyCode, modifiers: KeyModifiers) -> (KeyCode, KeyModifiers) {
+    let code = match code {
+        KeyCode::Char(c) => KeyCode::Char(c.to_ascii_lowercase()),
+        code => code,
+    };
+    (code, modifiers & (KeyModifiers::CONTROL | KeyModifiers::ALT | KeyModifiers::SHIFT))
+}
+
+/// The global hotkeys
+pub struct Keymap {
+    bindings: Vec<(KeyCode, KeyModifiers, String, Action)>,
+    /// Entries of the config naming an unknown key or action, left out
+    pub invalid: Vec<String>,
+}
+
+impl Keymap {
+    /// The defaults with `overrides` (key -> action id, empty to unbind) applied
+    pub fn new(overrides: &HashMap<String, String>) -> Self {
+        let mut keymap = Self { bindings: Vec::new(), invalid: Vec::new() };
+        let mut overrides: Vec<(&String, &String)> = overrides.iter().collect();
+        overrides.sort();
+        let entries = DEFAULT_BINDINGS.iter().map(|(key, id)| (key.to_string(), id.to_string()))
+            .chain(overrides.into_iter().map(|(key, id)| (key.clone(), id.clone())));
+        for (key, id) in entries {
+            let Some((code, modifiers)) = parse_key(&key) else {
+                keymap.invalid.push(format!("key '{}'", key));
+                continue;
+            };
+            let (code, modifiers) = normalize(code, modifiers);
+            keymap.bindings.retain(|(c, m, _, _)| (*c, *m) != (code, modifiers));
+            if id.trim().is_empty() {
+                continue;
+            }
+            match find(id.trim()) {
+                Some(spec) => keymap.bindings.push((code, modifiers, key.trim().to_string(), spec.action)),
+                None => keymap.invalid.push(format!("action '{}'", id)),
+            }
+        }
+        keymap
+    }
+
+    /// The action bound to `key`. While a text field has focus (`typing`) only F-keys and
+    /// Ctrl/Alt combinations count, so typed text never triggers an action.
+    pub fn lookup(&self, key: &KeyEvent, typing: bool) -> Option<Action> {
+        let (code, modifiers) = normalize(key.code, key.modifiers);
+        if typing && !matches!(code, KeyCode::F(_)) && !modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) {
+            return None;
+        }
+        self.bindings.iter().find(|(c, m, _, _)| (*c, *m) == (code, modifiers)).map(|(_, _, _, action)| *action)
+    }
+
+    /// The key bound to `action` as written in the config, e.g. "ctrl+k"
+    pub fn key_for(&self, action: Action) -> Option<&str> {
+        self.bindings.iter().find(|(_, _, _, a)| *a == action).map(|(_, _, key, _)| key.as_str())
+    }
+}
+
+/// Score of `label` for what was typed in the palette: every typed character must appear in
+/// order; runs of consecutive characters and word starts score higher. `None` if it doesn't match.
+fn fuzzy_score(query: &str, label: &str) -> Option<i32> {
+    let label: Vec<char> = label.to_lowercase().chars().collect();
+    let mut score = 0;
+    let mut next = 0;
+    let mut previous: Option<usize> = None;
+    for c in query.to_lowercase().chars().filter(|c| !c.is_whitespace()) {
+        let found = (next..label.len()).find(|&i| label[i] == c)?;
+        score += 1;
+        if previous.is_some_and(|p| p + 1 == found) {
+            score += 3;
+        }
+        if found == 0 || !label[found - 1].is_alphanumeric() {
+            score += 2;
+        }
+        previous = Some(found);
+        next = found + 1;
+    }
+    Some(score)
+}
+
+/// The command palette overlay: a query and the selected match
+#[derive(Default)]
+pub struct Palette {
+    pub query: String,
+    pub selected: usize,
+}
+
+impl Palette {
+    /// Actions matching the query, best first, in registry order when tied
+    pub fn matches(&self) -> Vec<&'static ActionSpec> {
+        let mut scored: Vec<(i32, usize, &'static ActionSpec)> = ACTIONS.iter().enumerate()
+            .filter(|(_, spec)| spec.action != Action::Palette)
+            .filter_map(|(i, spec)| Some((fuzzy_score(&self.query, spec.label)?, i, spec)))
+            .collect();
+        scored.sort_by_key(|(score, i, _)| (std::cmp::Reverse(*score), *i));
+        scored.into_iter().map(|(_, _, spec)| spec).collect()
+    }
+}
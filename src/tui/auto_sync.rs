@@ -0,0 +1,55 @@
+//! Timer for the background sync set by `auto_sync_minutes`, backing off after failures.
+
+use std::time::{Duration, Instant};
+
+/// Failed attempts double the wait, up to this many times
+const MAX_BACKOFF_DOUBLINGS: u32 = 3;
+
+pub struct AutoSync {
+    interval: Option<Duration>,
+    last_attempt: Instant,
+    failures: u32,
+    /// The running sync was started by the timer, not from the menu
+    pub running: bool,
+}
+
+impl AutoSync {
+    /// The first sync runs one interval after startup
+    pub fn new(minutes: Option<u64>) -> Self {
+        Self {
+            interval: Self::interval(minutes),
+            last_attempt: Instant::now(),
+            failures: 0,
+            running: false,
+        }
+    }
+
+    fn interval(minutes: Option<u64>) -> Option<Duration> {
+        minutes.filter(|m| *m > 0).map(|m| Duration::from_secs(m * 60))
+    }
+
+    pub fn set_minutes(&mut self, minutes: Option<u64>) {
+        self.interval = Self::interval(minutes);
+    }
+
+    /// Wait before the next attempt: the interval, doubled per consecutive failure
+    pub fn delay(&self) -> Option<Duration> {
+        self.interval.map(|interval| interval * 2u32.pow(self.failures.min(MAX_BACKOFF_DOUBLINGS)))
+    }
+
+    pub fn due(&self) -> bool {
+        !self.running && self.delay().is_some_and(|delay| self.last_attempt.elapsed() >= delay)
+    }
+
+    pub fn started(&mut self) {
+        self.running = true;
+        self.last_attempt = Instant::now();
+    }
+
+    /// Any sync finishing, from the menu or the timer, restarts the interval
+    pub fn finished(&mut self, ok: bool) {
+        self.running = false;
+        self.last_attempt = Instant::now();
+        self.failures = if ok { 0 } else { self.failures + 1 };
+    }
+}
@@ -0,0 +1,193 @@
+//! `/cite`: a citation block for the last answer, to paste into work handed in on PoliformaT
+//! so it is clear which model wrote what and from which course documents.
+
+use std::collections::HashMap;
+
+use super::PromptInfo;
+
+/// A course document the answer drew on
+pub struct CitedSource {
+    /// Subject name, or its site id when the name is unknown
+    pub subject: String,
+    pub file: String,
+    pub pages: Vec<String>,
+}
+
+pub struct Citation {
+    pub model: String,
+    pub provider: String,
+    /// YYYY-MM-DD
+    pub date: String,
+    pub question: String,
+    pub sources: Vec<CitedSource>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum CiteFormat {
+    Plain,
+    Bibtex,
+}
+
+impl CiteFormat {
+    /// `/cite` argument: nothing or "plain", "bibtex" or "bib"
+    pub fn parse(arg: &str) -> Option<Self> {
+        match arg.trim().to_lowercase().as_str() {
+            "" | "plain" | "text" => Some(CiteFormat::Plain),
+            "bibtex" | "bib" => Some(CiteFormat::Bibtex),
+            _ => None,
+        }
+    }
+}
+
+impl Citation {
+    /// The citation of the answer to `question`, whose prompt `info` describes.
+    /// `subject_names` maps site ids to subject names.
+    pub fn new(info: &PromptInfo, question: &str, subject_names: &HashMap<String, String>) -> Self {
+        let sources = info.documents.iter()
+            .map(|doc| CitedSource {
+                subject: subject_names.get(&doc.subject).cloned().unwrap_or_else(|| doc.subject.clone()),
+                file: doc.file.clone(),
+                pages: doc.pages.clone(),
+            })
+            .collect();
+        Self {
+            model: info.model.clone(),
+            provider: info.provider.label().to_string(),
+            date: info.date.clone(),
+            question: question.split_whitespace().collect::<Vec<_>>().join(" "),
+            sources,
+        }
+    }
+
+    pub fn render(&self, format: CiteFormat) -> String {
+        match format {
+            CiteFormat::Plain => self.plain(),
+            CiteFormat::Bibtex => self.bibtex(),
+        }
+    }
+
+    /// ```text
+    /// AI-generated answer (PoliRag), 2026-10-17
+    /// Model: qwen3-8b via LM Studio
+    /// Question: "What is the second normal form?"
+    /// Sources:
+    ///   - Bases de Datos: Tema3.pdf, pp. 4, 7
+    /// ```
+    pub fn plain(&self) -> String {
+        let mut out = format!("AI-generated answer (PoliRag), {}\n", self.date);
+        out.push_str(&format!("Model: {} via {}\n", self.model, self.provider));
+        out.push_str(&format!("Question: \"{}\"\n", self.question));
+        if self.sources.is_empty() {
+            out.push_str("Sources: none, answered without course documents\n");
+        } else {
+            out.push_str("Sources:\n");
+            for source in &self.sources {
+                out.push_str(&format!("  - {}\n", source.describe()));
+            }
+        }
+        out
+    }
+
+    /// A `@misc` entry with the sources in `note`, key `polirag-YYYYMMDD`
+    pub fn bibtex(&self) -> String {
+        let sources = if self.sources.is_empty() {
+            "none".to_string()
+        } else {
+            self.sources.iter().map(|s| s.describe()).collect::<Vec<_>>().join("; ")
+        };
+        let fields = [
+            ("title", format!("Answer to: {}", self.question)),
+            ("author", self.model.clone()),
+            ("howpublished", format!("PoliRag, {}", self.provider)),
+            ("year", self.date.split('-').next().unwrap_or_default().to_string()),
+            ("date", self.date.clone()),
+            ("note", format!("AI-generated. Sources: {}", sources)),
+        ];
+        let mut out = format!("@misc{{polirag-{},\n", self.date.replace('-', ""));
+        for (i, (name, value)) in fields.iter().enumerate() {
+            let comma = if i + 1 < fields.len() { "," } else { "" };
+            out.push_str(&format!("  {:<12} = {{{}}}{}\n", name, bibtex_escape(value), comma));
+        }
+        out.push_str("}\n");
+        out
+    }
+}
+
+impl CitedSource {
+    /// "Bases de Datos: Tema3.pdf, pp. 4, 7"
+    fn describe(&self) -> String {
+        let mut out = if self.subject.is_empty() { self.file.clone() } else { format!("{}: {}", self.subject, self.file) };
+        match self.pages.as_slice() {
+            [] => {}
+            [page] => out.push_str(&format!(", p. {}", page)),
+            pages => out.push_str(&format!(", pp. {}", pages.join(", "))),
+        }
+        out
+    }
+}
+
+/// Escape the characters LaTeX treats specially in a field value
+fn bibtex_escape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '{' | '}' | '%' | '&' | '#' | '_' | '$') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn citation(sources: Vec<CitedSource>) -> Citation {
+        Citation {
+            model: "qwen3-8b".to_string(),
+            provider: "LM Studio".to_string(),
+            date: "2026-10-17".to_string(),
+            question: "What is the second normal form?".to_string(),
+            sources,
+        }
+    }
+
+    fn source(subject: &str, file: &str, pages: &[&str]) -> CitedSource {
+        CitedSource { subject: subject.to_string(), file: file.to_string(), pages: pages.iter().map(|p| p.to_string()).collect() }
+    }
+
+    #[test]
+    fn plain_citation_lists_each_source_with_its_pages() {
+        let cited = citation(vec![source("Bases de Datos", "Tema3.pdf", &["4", "7"]), source("Bases de Datos", "Tema4.pdf", &["2"]), source("", "notes.md", &[])]);
+        assert_eq!(
+            cited.render(CiteFormat::Plain),
+            "AI-generated answer (PoliRag), 2026-10-17\n\
+             Model: qwen3-8b via LM Studio\n\
+             Question: \"What is the second normal form?\"\n\
+             Sources:\n  - Bases de Datos: Tema3.pdf, pp. 4, 7\n  - Bases de Datos: Tema4.pdf, p. 2\n  - notes.md\n"
+        );
+        assert!(citation(Vec::new()).plain().ends_with("Sources: none, answered without course documents\n"));
+    }
+
+    #[test]
+    fn bibtex_citation_escapes_special_characters() {
+        let cited = citation(vec![source("Bases de Datos", "tema_3.pdf", &["4"])]);
+        assert_eq!(
+            cited.render(CiteFormat::Bibtex),
+            "@misc{polirag-20261017,\n  \
+             title        = {Answer to: What is the second normal form?},\n  \
+             author       = {qwen3-8b},\n  \
+             howpublished = {PoliRag, LM Studio},\n  \
+             year         = {2026},\n  \
+             date         = {2026-10-17},\n  \
+             note         = {AI-generated. Sources: Bases de Datos: tema\\_3.pdf, p. 4}\n}\n"
+        );
+    }
+
+    #[test]
+    fn format_argument_is_parsed_loosely() {
+        assert!(CiteFormat::parse("") == Some(CiteFormat::Plain));
+        assert!(CiteFormat::parse(" BibTeX ") == Some(CiteFormat::Bibtex));
+        assert!(CiteFormat::parse("apa").is_none());
+    }
+}
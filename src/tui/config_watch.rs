@@ -0,0 +1,135 @@
+//! Picks up edits to the config file made while the TUI is running, e.g. from another terminal.
+
+use std::time::{Duration, Instant, SystemTime};
+
+use serde_json::Value;
+
+use crate::config::Config;
+
+/// How often the config file's modification time is checked
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Settings the running app only picks up through `/reload`, since they switch the model
+const CONFIRM_KEYS: &[&str] = &["llm_provider", "last_model", "models_by_provider", "openrouter_model", "openrouter_api_key", "chat_base_url", "ollama_base_url"];
+/// Settings baked into the loaded index or embedder
+const RESTART_KEYS: &[&str] = &["rag.store_type", "rag.hnsw", "rag.ef_search", "rag.similarity_metric", "rag.half_precision", "embeddings_base_url"];
+/// Written by the app itself (login), nothing to report
+const SILENT_KEYS: &[&str] = &["cached_credentials"];
+
+/// How a changed setting reaches the running app
+#[derive(Debug, PartialEq, Eq)]
+pub enum Apply {
+    /// Read on use or applied right away
+    Live,
+    /// Switches provider or model, so it waits for `/reload`
+    Confirm,
+    /// Needs a restart
+    Restart,
+    Silent,
+}
+
+pub fn classify(key: &str) -> Apply {
+    let matches = |keys: &[&str]| keys.iter().any(|k| key == *k || key.starts_with(&format!("{}.", k)));
+    if matches(SILENT_KEYS) {
+        Apply::Silent
+    } else if matches(CONFIRM_KEYS) {
+        Apply::Confirm
+    } else if matches(RESTART_KEYS) {
+        Apply::Restart
+    } else {
+        Apply::Live
+    }
+}
+
+/// Dotted paths (`rag.numbered_citations`) of the values that differ between two configs
+pub fn changed_keys(old: &Value, new: &Value) -> Vec<String> {
+    let mut changed = Vec::new();
+    collect_changes("", old, new, &mut changed);
+    changed
+}
+
+fn collect_changes(prefix: &str, old: &Value, new: &Value, changed: &mut Vec<String>) {
+    match (old, new) {
+        (Value::Object(old_map), Value::Object(new_map)) => {
+            let mut keys: Vec<&String> = old_map.keys().chain(new_map.keys()).collect();
+            keys.sort();
+            keys.dedup();
+            for key in keys {
+                let path = if prefix.is_empty() { key.clone() } else { format!("{}.{}", prefix, key) };
+                collect_changes(&path, old_map.get(key).unwrap_or(&Value::Null), new_map.get(key).unwrap_or(&Value::Null), changed);
+            }
+        }
+        _ if old != new => changed.push(prefix.to_string()),
+        _ => {}
+    }
+}
+
+pub struct ConfigWatcher {
+    last_poll: Instant,
+    modified: Option<SystemTime>,
+    generation: u64,
+    snapshot: Value,
+}
+
+impl ConfigWatcher {
+    pub fn new() -> Self {
+        Self {
+            last_poll: Instant::now(),
+            modified: Config::modified_at(),
+            generation: Config::save_generation(),
+            snapshot: serde_json::to_value(Config::load()).unwrap_or_default(),
+        }
+    }
+
+    /// Settings changed outside the app since the last poll. Changes that land together with
+    /// one of the app's own saves are taken as its own and not reported.
+    pub fn poll(&mut self) -> Option<Vec<String>> {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return None;
+        }
+        self.last_poll = Instant::now();
+
+        let modified = Config::modified_at();
+        if modified == self.modified {
+            return None;
+        }
+        self.modified = modified;
+
+        let current = serde_json::to_value(Config::load()).unwrap_or_default();
+        let changed = changed_keys(&self.snapshot, &current);
+        self.snapshot = current;
+
+        let generation = Config::save_generation();
+        let own_write = generation != self.generation;
+        self.generation = generation;
+
+        (!own_write && !changed.is_empty()).then_some(changed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn nested_changes_are_reported_by_their_dotted_path() {
+        let old = json!({"llm_provider": "LMStudio", "rag": {"top_k": 5, "hnsw": {"m": 16}}, "subjects": ["a"]});
+        let new = json!({"llm_provider": "LMStudio", "rag": {"top_k": 8, "hnsw": {"m": 16}, "numbered_citations": true}, "subjects": ["a", "b"]});
+        assert_eq!(changed_keys(&old, &new), ["rag.numbered_citations", "rag.top_k", "subjects"]);
+        assert!(changed_keys(&old, &old).is_empty());
+        // A removed key counts as a change
+        assert_eq!(changed_keys(&new, &json!({"llm_provider": "LMStudio"})), ["rag", "subjects"]);
+    }
+
+    #[test]
+    fn keys_are_classified_by_how_they_reach_the_app() {
+        assert_eq!(classify("rag.top_k"), Apply::Live);
+        assert_eq!(classify("last_model"), Apply::Confirm);
+        assert_eq!(classify("models_by_provider.OpenRouter"), Apply::Confirm);
+        assert_eq!(classify("rag.hnsw.ef_construction"), Apply::Restart);
+        assert_eq!(classify("cached_credentials.username"), Apply::Silent);
+        // A shared prefix is not a parent key
+        assert_eq!(classify("rag.hnsw_cache"), Apply::Live);
+    }
+}
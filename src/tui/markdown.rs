@@ -1,9 +1,12 @@
 use ratatui::style::{Color, Modifier, Style};
 use ratatui::text::{Line, Span};
-use pulldown_cmark::{Event, Options, Parser, Tag, TagEnd};
+use pulldown_cmark::{Event, Options, Parser, Tag, TagEnd, TextMergeStream};
+use crate::rag::citations;
 
 
-pub fn render_markdown(text: &str, max_width: usize, thinking_collapsed: bool) -> Vec<Line<'static>> {
+/// `citation_count` is the number of numbered sources behind the text (0 disables citation highlighting).
+/// `streaming` renders an answer still being received as if its open code fence and emphasis were closed.
+pub fn render_markdown(text: &str, max_width: usize, thinking_collapsed: bool, citation_count: usize, streaming: bool) -> Vec<Line<'static>> {
     let mut lines = Vec::new();
 
     // 1. Separate Thinking Block
@@ -53,13 +56,15 @@ pub fn render_markdown(text: &str, max_width: usize, thinking_collapsed: bool) -
     // ASCII tables look like paragraphs to it (just lines of text).
     // We need to wrap them in code blocks ```text ... ``` so they are preserved verbatim.
     // ALSO: Detect standard GFM tables and convert them to ASCII Art code blocks since tui-markdown doesn't support them.
-    let gfm_processed = preprocess_gfm_tables(main_content_raw, max_width);
+    let balanced = if streaming { balance_partial(main_content_raw) } else { std::borrow::Cow::Borrowed(main_content_raw) };
+    let gfm_processed = preprocess_gfm_tables(&balanced, max_width);
     let processed_content = preprocess_ascii_tables(&gfm_processed);
 
     // 4. Custom Markdown Rendering
     let mut options = Options::empty();
     options.insert(Options::ENABLE_STRIKETHROUGH);
-    let parser = Parser::new_ext(&processed_content, options);
+    // Merge text events so markers like "[1]" aren't split across spans
+    let parser = TextMergeStream::new(Parser::new_ext(&processed_content, options));
 
     let mut current_line = Vec::new();
     let mut style_stack = vec![Style::default()];
@@ -71,8 +76,8 @@ pub fn render_markdown(text: &str, max_width: usize, thinking_collapsed: bool) -
         match event {
             Event::Start(tag) => {
                 match tag {
-                    Tag::Paragraph => {
-                        if !lines.is_empty() || !current_line.is_empty() { lines.push(Line::from("")); }
+                    Tag::Paragraph if !lines.is_empty() || !current_line.is_empty() => {
+                        lines.push(Line::from(""));
                     }
                     Tag::Heading { level, .. } => {
                         if !lines.is_empty() || !current_line.is_empty() { lines.push(Line::from("")); }
@@ -125,7 +130,7 @@ pub fn render_markdown(text: &str, max_width: usize, thinking_collapsed: bool) -
                     TagEnd::Paragraph | TagEnd::Heading(_) | TagEnd::Item => {
                         let is_heading = matches!(tag, TagEnd::Heading(_));
                         if is_heading { style_stack.pop(); }
-                        let line = Line::from(current_line.drain(..).collect::<Vec<_>>());
+                        let line = Line::from(std::mem::take(&mut current_line));
                         // Simple wrapping for lines
                         for wrapped in wrap_line(line, max_width) {
                             lines.push(wrapped);
@@ -150,6 +155,8 @@ pub fn render_markdown(text: &str, max_width: usize, thinking_collapsed: bool) -
                     for line in t.lines() {
                         lines.push(Line::from(Span::styled(format!("  {}", line), Style::default().fg(Color::DarkGray))));
                     }
+                } else if citation_count > 0 {
+                    push_with_citations(&mut current_line, &t, *style_stack.last().unwrap(), citation_count);
                 } else {
                     current_line.push(Span::styled(t.into_string(), *style_stack.last().unwrap()));
                 }
@@ -165,12 +172,134 @@ pub fn render_markdown(text: &str, max_width: usize, thinking_collapsed: bool) -
     }
 
     // Clean up empty lines at start/end
-    while lines.first().map_or(false, |l| l.to_string().trim().is_empty()) { lines.remove(0); }
-    while lines.last().map_or(false, |l| l.to_string().trim().is_empty()) { lines.pop(); }
+    while lines.first().is_some_and(|l| l.to_string().trim().is_empty()) { lines.remove(0); }
+    while lines.last().is_some_and(|l| l.to_string().trim().is_empty()) { lines.pop(); }
 
     lines
 }
 
+/// Push text, highlighting valid citation markers and flagging bracketed ones that match no source
+fn push_with_citations(current_line: &mut Vec<Span<'static>>, text: &str, style: Style, citation_count: usize) {
+    let mut last = 0;
+    for marker in citations::parse_markers(text) {
+        let valid = marker.numbers.iter().all(|n| *n >= 1 && *n <= citation_count);
+        // An out of range "(3)" is most likely ordinary text, leave it alone
+        if !valid && marker.parenthesized {
+            continue;
+        }
+
+        if marker.range.start > last {
+            current_line.push(Span::styled(text[last..marker.range.start].to_string(), style));
+        }
+        let marker_style = if valid {
+            style.fg(Color::Cyan).add_modifier(Modifier::BOLD)
+        } else {
+            style.fg(Color::Red).add_modifier(Modifier::CROSSED_OUT)
+        };
+        current_line.push(Span::styled(text[marker.range.clone()].to_string(), marker_style));
+        last = marker.range.end;
+    }
+    if last < text.len() {
+        current_line.push(Span::styled(text[last..].to_string(), style));
+    }
+}
+
+/// Close what a partially received answer has left open, so the rendering doesn't flip the
+/// rest of the message into a code block or bold until the closing marker arrives: an open
+/// ``` or ~~~ fence, and `**`/`*` emphasis opened in the last paragraph
+fn balance_partial(text: &str) -> std::borrow::Cow<'_, str> {
+    let mut open_fence: Option<String> = None;
+    // Byte offset of the paragraph still being written, outside any fence
+    let mut paragraph_start = 0;
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        let trimmed = line.trim_end();
+        let indent = trimmed.len() - trimmed.trim_start().len();
+        let body = trimmed.trim_start();
+        let marker_char = body.chars().next().filter(|c| *c == '`' || *c == '~');
+        let run = marker_char.map_or(0, |c| body.chars().take_while(|x| *x == c).count());
+        offset += line.len();
+        match &open_fence {
+            Some(fence) if indent <= 3 && run >= fence.len() && body.starts_with(&fence[..1]) && body[run..].trim().is_empty() => {
+                open_fence = None;
+                paragraph_start = offset;
+            }
+            Some(_) => {}
+            None if indent <= 3 && run >= 3 => {
+                open_fence = Some(body[..run].to_string());
+            }
+            None if trimmed.is_empty() => paragraph_start = offset,
+            None => {}
+        }
+    }
+
+    if let Some(fence) = open_fence {
+        let newline = if text.ends_with('\n') { "" } else { "\n" };
+        return std::borrow::Cow::Owned(format!("{}{}{}", text, newline, fence));
+    }
+
+    let (kept, closing) = unclosed_emphasis(&text[paragraph_start..]);
+    if closing.is_empty() {
+        return std::borrow::Cow::Borrowed(text);
+    }
+    // A marker after whitespace can't close, so the closers go before the trailing spaces
+    let words = kept.trim_end();
+    std::borrow::Cow::Owned(format!("{}{}{}{}", &text[..paragraph_start], words, closing, &kept[words.len()..]))
+}
+
+/// The `**`/`*` markers `paragraph` leaves open, as the text that closes them. A trailing
+/// lone `*` after an open `**` is the first half of its closing marker and is dropped from
+/// the returned paragraph.
+fn unclosed_emphasis(paragraph: &str) -> (&str, String) {
+    let chars: Vec<(usize, char)> = paragraph.char_indices().collect();
+    let mut open: Vec<&'static str> = Vec::new();
+    let mut in_code = false;
+    // The paragraph ends in a `*` that neither opened nor closed anything
+    let mut dangling = false;
+    let mut i = 0;
+    while i < chars.len() {
+        let (_, c) = chars[i];
+        if c == '`' {
+            in_code = !in_code;
+            i += 1;
+            continue;
+        }
+        if c != '*' || in_code {
+            i += 1;
+            continue;
+        }
+        let run = chars[i..].iter().take_while(|(_, x)| *x == '*').count();
+        let before = i.checked_sub(1).map(|j| chars[j].1);
+        let after = chars.get(i + run).map(|(_, x)| *x);
+        let at_line_start = paragraph[..chars[i].0].rsplit('\n').next().is_some_and(|l| l.trim().is_empty());
+        // "* item" is a list bullet, "2 * 3" is arithmetic
+        if !(at_line_start && run == 1 && after.is_some_and(char::is_whitespace)) {
+            let can_close = before.is_some_and(|b| !b.is_whitespace());
+            let can_open = after.is_some_and(|a| !a.is_whitespace());
+            // A closing run ends the innermost markers first, "***" closes "**a *b"
+            let mut left = run;
+            while let Some(marker) = open.last().filter(|m| can_close && m.len() <= left) {
+                left -= marker.len();
+                open.pop();
+            }
+            dangling = false;
+            if left > 0 && can_open {
+                open.extend(std::iter::repeat_n("**", left / 2));
+                if left % 2 == 1 { open.push("*"); }
+            } else if left > 0 {
+                dangling = after.is_none();
+            }
+        }
+        i += run;
+    }
+
+    let mut kept = paragraph;
+    if dangling && open.last() == Some(&"**") && !paragraph.ends_with("**") {
+        kept = &paragraph[..paragraph.len() - 1];
+    }
+    (kept, open.iter().rev().copied().collect())
+}
+
 fn wrap_line(line: Line<'static>, width: usize) -> Vec<Line<'static>> {
     let mut result = Vec::new();
     let mut current_spans = Vec::new();
@@ -364,13 +493,13 @@ fn render_table_from_events(events: &[Event], max_width: usize) -> String {
             }
             Event::Text(t) => {
                 if in_cell {
-                    cell_content.push_str(&t);
+                    cell_content.push_str(t);
                 }
             }
             Event::Code(c) => {
                  if in_cell {
                      cell_content.push('`');
-                     cell_content.push_str(&c);
+                     cell_content.push_str(c);
                      cell_content.push('`');
                  }
             }
@@ -453,9 +582,8 @@ fn render_table_from_events(events: &[Event], max_width: usize) -> String {
         let mut wrapped_cells: Vec<Vec<String>> = Vec::new();
         let mut max_row_height = 0;
         
-        for i in 0..num_cols {
+        for (i, &width) in final_col_widths.iter().enumerate().take(num_cols) {
             let cell_text = row.get(i).map(|s| s.as_str()).unwrap_or("");
-            let width = final_col_widths[i];
             let wrapped = textwrap::wrap(cell_text, width);
             let lines: Vec<String> = if wrapped.is_empty() {
                 if cell_text.is_empty() { vec![String::new()] } else { vec![cell_text.to_string()] }
@@ -505,3 +633,78 @@ fn render_table_from_events(events: &[Event], max_width: usize) -> String {
     out.push('\n');
     out
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ANSWER: &str = "The **determinant** of a *square* matrix:\n\n```python\nimport numpy as np\nnp.linalg.det(a) * 2\n```\n\nIt is zero when 2 * 3 columns are **linearly *dependent***.\n\n* first bullet\n* second with `a*b` code\n";
+
+    #[test]
+    fn open_fence_is_closed() {
+        assert_eq!(balance_partial("Intro\n\n```rust\nlet x = 1;"), "Intro\n\n```rust\nlet x = 1;\n```");
+        assert_eq!(balance_partial("~~~~\ncode\n~~~\n"), "~~~~\ncode\n~~~\n~~~~");
+        assert_eq!(balance_partial("```\ncode\n```\nafter"), "```\ncode\n```\nafter");
+    }
+
+    #[test]
+    fn emphasis_in_the_last_paragraph_is_closed() {
+        assert_eq!(balance_partial("Some **bold"), "Some **bold**");
+        assert_eq!(balance_partial("Some **bold *and it"), "Some **bold *and it***");
+        // The first half of the closing marker already arrived
+        assert_eq!(balance_partial("Some **bold*"), "Some **bold**");
+        assert_eq!(balance_partial("Some **bold "), "Some **bold** ");
+        assert_eq!(balance_partial("**a *b***. More *c"), "**a *b***. More *c*");
+        // Only the paragraph being written is touched
+        assert_eq!(balance_partial("An *open\n\nNext **one"), "An *open\n\nNext **one**");
+    }
+
+    #[test]
+    fn bullets_arithmetic_and_code_are_not_emphasis() {
+        for text in ["* item\n* other", "2 * 3 = 6", "Use `a*b` here", "Done **bold** text"] {
+            assert_eq!(balance_partial(text), text);
+        }
+    }
+
+    /// `lines` without the blank lines at its end
+    fn trimmed(mut lines: Vec<Line<'static>>) -> Vec<Line<'static>> {
+        while lines.last().is_some_and(|l| l.spans.iter().all(|s| s.content.trim().is_empty())) {
+            lines.pop();
+        }
+        lines
+    }
+
+    #[test]
+    fn streamed_prefixes_never_restyle_finished_blocks() {
+        for (cut, _) in ANSWER.char_indices().skip(1) {
+            let prefix = &ANSWER[..cut];
+            // The blocks of the prefix already followed by a blank line are finished
+            let Some(end) = prefix.rfind("\n\n") else { continue };
+            let finished = trimmed(render_markdown(&ANSWER[..end + 2], 80, false, 0, false));
+            let streamed = render_markdown(prefix, 80, false, 0, true);
+            assert!(streamed.len() >= finished.len(), "cut at {}: {:?}", cut, prefix);
+            assert_eq!(streamed[..finished.len()], finished[..], "cut at {}: {:?}", cut, prefix);
+        }
+    }
+
+    /// The visible characters of `lines` with their styles, ignoring how they were wrapped
+    fn styled_chars(lines: &[Line<'static>]) -> Vec<(char, Style)> {
+        lines.iter()
+            .flat_map(|l| l.spans.iter())
+            .flat_map(|s| s.content.chars().filter(|c| !c.is_whitespace()).map(|c| (c, s.style)))
+            .collect()
+    }
+
+    #[test]
+    fn streamed_text_keeps_its_final_style() {
+        let full = styled_chars(&render_markdown(ANSWER, 80, false, 0, false));
+        for (cut, _) in ANSWER.char_indices().skip(1) {
+            let prefix = &ANSWER[..cut];
+            let streamed = styled_chars(&render_markdown(prefix, 80, false, 0, true));
+            // The word being received may still be a marker or part of one
+            let partial = prefix.rsplit(char::is_whitespace).next().unwrap_or("").chars().count();
+            let settled = streamed.len().saturating_sub(partial);
+            assert_eq!(streamed[..settled], full[..settled], "cut at {}: {:?}", cut, prefix);
+        }
+    }
+}
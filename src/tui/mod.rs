@@ -1,30 +1,66 @@
 use std::io::{self, Stdout};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::{Duration, Instant};
 
 use crossterm::{
-    event::{self, Event, KeyCode, KeyEventKind},
+    event::{self, DisableBracketedPaste, EnableBracketedPaste, Event, KeyCode, KeyEventKind},
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
 use ratatui::{
     backend::CrosstermBackend,
-    layout::{Constraint, Direction, Layout, Alignment},
+    layout::{Constraint, Direction, Layout, Alignment, Rect},
     style::{Color, Modifier, Style},
     text::{Line, Span, Text},
-    widgets::{Block, Borders, Paragraph, Scrollbar, ScrollbarOrientation, ScrollbarState, List, ListItem, ListState, Wrap},
+    widgets::{Block, Borders, Clear, Paragraph, Scrollbar, ScrollbarOrientation, ScrollbarState, List, ListItem, ListState, Wrap},
     Frame, Terminal,
 };
 use tokio::sync::mpsc;
 use futures::StreamExt;
 
 use crate::llm::ChatMessage;
+use transcript::TranscriptMessage;
 use crate::AppState;
 use crate::rag::RagStats;
+use crate::rag::context::PromptOptions;
+use crate::scrapper::SyncMode;
 
 mod markdown;
+mod config_watch;
+mod auto_sync;
+mod actions;
+mod citation;
+mod notify;
+mod preview;
+mod study;
+mod transcript;
+
+use actions::Action;
 
 const THROBBER_FRAMES: &[char] = &['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+/// Pause between retries of a request whose model is still loading
+const MODEL_LOADING_RETRY_DELAY: Duration = Duration::from_secs(3);
+/// Replaces an answer with no visible text; never sent back to the model, see `request_history`
+const EMPTY_ANSWER_MARKER: &str = "(model returned an empty answer)";
+/// Files of a churn prompt listed in the sync log; the rest are counted
+const CHURN_FILES_SHOWN: usize = 10;
+/// Excerpts `/lookup` shows
+const LOOKUP_EXCERPTS: usize = 3;
+/// Role of notes added to the transcript, like `/cite` blocks; never sent to the model
+const NOTE_ROLE: &str = "note";
+/// Added to the temperature when `retry_empty_answers` asks again
+const EMPTY_RETRY_TEMPERATURE_BOOST: f32 = 0.3;
+/// Change of the relevance floor per `[` or `]` on the Settings screen
+const MIN_SCORE_STEP: f32 = 0.02;
+/// Share of the model's context a request may fill, leaving the rest for the answer
+const REQUEST_WINDOW_PERCENT: usize = 90;
+
+const SYSTEM_PROMPT: &str = "You are a helpful assistant with access to the user's university documents (PoliformaT). Use the provided context to answer questions. breakdown\n\nIMPORTANT INSTRUCTIONS:\n1. You MUST answer in the same language as the user's message (e.g. if user asks in Catalan, answer in Catalan).\n2. You MUST cite the source document ID for every claim you make based on the context.\n3. Use the format `[doc_id]` at the end of the sentence or paragraph.\n   - Example: \"The exam is on Friday [GRA_11673_2025/guide.pdf].\"\n   - The document ID is provided in the context blocks as `[source_id]: content`.";
+
+// Used when `rag.numbered_citations` is enabled: sources arrive numbered and are cited by number
+const NUMBERED_CITATION_PROMPT: &str = "You are a helpful assistant with access to the user's university documents (PoliformaT). Use the provided context to answer questions.\n\nIMPORTANT INSTRUCTIONS:\n1. You MUST answer in the same language as the user's message (e.g. if user asks in Catalan, answer in Catalan).\n2. Every source in the context is numbered, e.g. `[1] GRA_11673_2025/guide.pdf`.\n3. Cite the number of the source for every claim you make based on the context, in square brackets at the end of the sentence.\n   - Example: \"The exam is on Friday [1].\" or \"Both lectures cover it [1][3].\"\n4. Only use numbers that appear in the context. Never invent a source number.";
+
 
 #[derive(PartialEq, Clone)]
 pub enum AppMode {
@@ -33,6 +69,12 @@ pub enum AppMode {
     RagInfo,
     Login,
     Sync,
+    /// Per-subject changes of the last sync
+    SyncChanges,
+    /// Per-subject sync and retrieval preferences
+    Subjects,
+    /// Active recall on one subject, see `study`
+    Study,
     Settings,
 }
 
@@ -44,14 +86,60 @@ pub struct TuiApp {
     pub is_connected: bool,
     
     // Chat State
-    pub messages: Vec<ChatMessage>,
+    pub messages: Vec<TranscriptMessage>,
     pub input: String,
     pub input_cursor: usize,
     pub scroll_offset: u16,
     pub follow_bottom: bool,
+    /// Line offset of each assistant message header in the rendered transcript
+    pub assistant_offsets: Vec<u16>,
+    /// Active transcript search (`/find <term>`)
+    pub search_term: Option<String>,
+    /// Line offsets of the lines matching `search_term`, refreshed on every draw
+    pub search_matches: Vec<u16>,
+    pub search_index: usize,
+    /// Scroll to the first match once the next draw has located them
+    pub search_jump_pending: bool,
     pub is_thinking: bool,
+    /// Request currently streaming into the chat, see `TranscriptMessage::request_id`
+    pub active_request: Option<u64>,
+    pub next_request_id: u64,
+    /// Set to stop the active request while it waits for the model to load
+    pub request_cancel: Option<Arc<AtomicBool>>,
+    /// Task building and streaming the active request, aborted when the user stops it
+    pub request_task: Option<tokio::task::AbortHandle>,
+    /// Retry number and limit while the server is loading the model
+    pub model_loading: Option<(u32, u32)>,
     pub throbber_frame: usize,
     pub model_name: String,
+    pub chat_title: Option<String>,
+    pub title_pending: bool,
+    /// Document types retrieval is restricted to for this session, set by `/types`
+    pub type_filter: Option<Vec<String>>,
+    /// Tag retrieval is restricted to for this session, set by `/scope tag:<name>`
+    pub tag_scope: Option<String>,
+    /// Text being captured by `/paste`, until Ctrl+D adds it as temporary context
+    pub paste_capture: Option<String>,
+    /// Texts added with `/paste` and still searched; cleared by Ctrl+L and `/paste clear`
+    pub pasted_texts: usize,
+    /// Subjects the recent answers drew on, boosted in retrieval; reset by Ctrl+L and `/subject clear`
+    pub affinity: crate::rag::affinity::SubjectAffinity,
+    /// Send the next question with expanded context (Ctrl+R)
+    pub expand_next: bool,
+    /// Send the next question at a higher temperature, after an empty answer
+    pub retry_hotter: bool,
+    /// Request of the automatic retry, which is not retried again
+    pub hot_retry_request: Option<u64>,
+    /// How the last prompt's context was assembled, shown by `/sources`
+    pub last_prompt: Option<PromptInfo>,
+    /// OpenRouter spending limits and what was spent in the current period
+    pub budget: crate::config::BudgetConfig,
+    pub budget_spend: crate::usage::Spend,
+    /// Prices of the current OpenRouter model
+    pub model_pricing: Option<crate::usage::Pricing>,
+    /// Message held back by the budget check, and the y/n prompt shown for it
+    pub pending_send: Option<(String, String)>,
+    pub budget_confirmed: bool,
     
     // RAG Info
     pub rag_stats: Option<RagStats>,
@@ -60,12 +148,25 @@ pub struct TuiApp {
     pub login_username: String,
     pub login_pin: String,
     pub login_field: usize,
+    /// Ctrl+R shows the PIN in clear text until the form is left
+    pub login_pin_visible: bool,
     pub login_error: Option<String>,
     
     // Sync State
     pub sync_logs: Vec<String>,
     pub sync_running: bool,
     pub sync_complete: bool,
+    pub sync_report: Option<crate::scrapper::changes::SyncReport>,
+    /// A subject over the churn threshold; the sync waits until Y or N answers it
+    pub churn_prompt: Option<crate::sync::ChurnPrompt>,
+    /// Mode and `fresh` of a sync held back by the free space check until Y or N answers it
+    pub low_space_sync: Option<(SyncMode, bool)>,
+    /// Selected subject and the expanded ones in the changes view
+    pub changes_state: ListState,
+    pub changes_expanded: std::collections::HashSet<usize>,
+    // Subjects screen
+    pub subjects: Vec<crate::subjects::KnownSubject>,
+    pub subjects_state: ListState,
     
     // Settings State
     pub available_models: Vec<String>,
@@ -76,6 +177,9 @@ pub struct TuiApp {
     pub settings_field: usize, // 0=Provider, 1=Model List/Input, 2=API Key
     pub openrouter_key: String,
     pub openrouter_model: String,
+    /// Reachability of the chat and embeddings servers (None while checking)
+    pub chat_server_ok: Option<bool>,
+    pub embeddings_server_ok: Option<bool>,
     
     // Global
     pub should_quit: bool,
@@ -85,10 +189,32 @@ pub struct TuiApp {
     pub status_message_time: Option<Instant>,
     pub context_limit: usize,
     pub last_request_tokens: usize,
+    pub embedder_warning: Option<String>,
+    /// Notices edits to the config file made outside the app
+    pub config_watcher: config_watch::ConfigWatcher,
+    pub auto_sync: auto_sync::AutoSync,
+    /// The config file switched provider or model; applied by `/reload`
+    pub pending_config_switch: bool,
+    /// Global hotkeys, see `actions`
+    pub keymap: actions::Keymap,
+    /// The command palette, drawn over the current screen while open
+    pub palette: Option<actions::Palette>,
+    /// `/view`: a snippet in its file, drawn over the current screen while open
+    pub preview: Option<preview::DocumentPreview>,
+    /// The study session, kept while other screens are visited until Esc leaves it
+    pub study: Option<study::StudySession>,
+    /// Requests of an abandoned study question, whose remaining output is dropped
+    pub dropped_requests: std::collections::HashSet<u64>,
     
     // Reembed State
     pub reembed_running: bool,
     pub reembed_progress: String,
+    /// Dry run of the data cleanup, deleted when `D` is pressed again
+    pub clean_plan: Option<crate::clean::CleanPlan>,
+    /// Indexed documents when the running sync started, for the "+87 docs" of its notification
+    pub sync_start_docs: usize,
+    /// Last key press, to tell whether the user is away when an answer finishes
+    pub last_key: Instant,
 }
 
 impl TuiApp {
@@ -101,41 +227,76 @@ impl TuiApp {
             mode: AppMode::Menu,
             menu_items: vec![
                 "💬 Chat with Assistant".to_string(),
+                "🎓 Study (quiz me)".to_string(),
                 "🔄 Sync Data".to_string(),
+                "📁 Sync Resources Only".to_string(),
                 "📊 View RAG Index Info".to_string(),
                 "🔐 Login to PoliformaT".to_string(),
+                "🔓 Log out / Change account".to_string(),
+                "📚 Subjects".to_string(),
                 "⚙️  Settings (Model)".to_string(),
                 "🚪 Exit".to_string()
             ],
             menu_state,
             is_connected: connected,
             
-            messages: vec![
-                ChatMessage {
-                    role: "system".to_string(),
-                    content: "You are a helpful assistant with access to the user's university documents (PoliformaT). Use the provided context to answer questions. breakdown\n\nIMPORTANT INSTRUCTIONS:\n1. You MUST answer in the same language as the user's message (e.g. if user asks in Catalan, answer in Catalan).\n2. You MUST cite the source document ID for every claim you make based on the context.\n3. Use the format `[doc_id]` at the end of the sentence or paragraph.\n   - Example: \"The exam is on Friday [GRA_11673_2025/guide.pdf].\"\n   - The document ID is provided in the context blocks as `[source_id]: content`.".to_string(),
-                    thinking_collapsed: false,
-                    render_cache: crate::llm::RenderCache::default(),
-                }
-            ],
+            messages: Vec::new(),
             input: String::new(),
             input_cursor: 0,
             scroll_offset: 0,
             follow_bottom: true,
+            assistant_offsets: Vec::new(),
+            search_term: None,
+            search_matches: Vec::new(),
+            search_index: 0,
+            search_jump_pending: false,
+            last_prompt: None,
+            budget_spend: if config.budget.is_set() {
+                crate::usage::period_spend(&crate::usage::load(), config.budget.period)
+            } else {
+                crate::usage::Spend::default()
+            },
+            budget: config.budget.clone(),
+            model_pricing: None,
+            pending_send: None,
+            budget_confirmed: false,
             is_thinking: false,
+            active_request: None,
+            next_request_id: 1,
+            request_cancel: None,
+            request_task: None,
+            model_loading: None,
             throbber_frame: 0,
             model_name,
+            chat_title: None,
+            title_pending: false,
+            type_filter: None,
+            tag_scope: None,
+            paste_capture: None,
+            pasted_texts: 0,
+            affinity: Default::default(),
+            expand_next: false,
+            retry_hotter: false,
+            hot_retry_request: None,
             
             rag_stats: None,
             
             login_username: String::new(),
             login_pin: String::new(),
             login_field: 0,
+            login_pin_visible: false,
             login_error: None,
             
             sync_logs: Vec::new(),
             sync_running: false,
             sync_complete: false,
+            sync_report: None,
+            churn_prompt: None,
+            low_space_sync: None,
+            changes_state: ListState::default(),
+            changes_expanded: std::collections::HashSet::new(),
+            subjects: Vec::new(),
+            subjects_state: ListState::default(),
             
             available_models: Vec::new(),
             model_state: ListState::default(),
@@ -146,6 +307,8 @@ impl TuiApp {
             settings_field: 0,
             openrouter_key: config.openrouter_api_key.unwrap_or_default(),
             openrouter_model: config.openrouter_model.unwrap_or_default(),
+            chat_server_ok: None,
+            embeddings_server_ok: None,
             
             should_quit: false,
             content_height: 0,
@@ -154,9 +317,21 @@ impl TuiApp {
             status_message_time: None,
             context_limit: 32768,
             last_request_tokens: 0,
+            embedder_warning: None,
+            config_watcher: config_watch::ConfigWatcher::new(),
+            auto_sync: auto_sync::AutoSync::new(crate::config::Config::load().auto_sync_minutes),
+            pending_config_switch: false,
+            keymap: actions::Keymap::new(&config.keybindings),
+            palette: None,
+            preview: None,
+            study: None,
+            dropped_requests: std::collections::HashSet::new(),
             
             reembed_running: false,
             reembed_progress: String::new(),
+            clean_plan: None,
+            sync_start_docs: 0,
+            last_key: Instant::now(),
         }
     }
 
@@ -170,6 +345,58 @@ impl TuiApp {
         self.scroll_offset = (self.scroll_offset + amount).min(max_scroll);
     }
 
+    /// Scroll to the next assistant message below the current position
+    pub fn jump_to_next_answer(&mut self) {
+        if let Some(&offset) = self.assistant_offsets.iter().find(|&&o| o > self.scroll_offset) {
+            self.scroll_offset = offset;
+            self.follow_bottom = false;
+        }
+    }
+
+    /// Scroll to the previous assistant message above the current position
+    pub fn jump_to_previous_answer(&mut self) {
+        if let Some(&offset) = self.assistant_offsets.iter().rev().find(|&&o| o < self.scroll_offset) {
+            self.scroll_offset = offset;
+            self.follow_bottom = false;
+        }
+    }
+
+    /// Index in `messages` of the answer at the top of the view, or the last one when
+    /// following the bottom
+    fn answer_in_view(&self) -> Option<usize> {
+        let answers: Vec<usize> = self.messages.iter().enumerate().filter(|(_, m)| m.role == "assistant").map(|(i, _)| i).collect();
+        if self.follow_bottom {
+            return answers.last().copied();
+        }
+        let above = self.assistant_offsets.iter().filter(|&&o| o <= self.scroll_offset).count();
+        answers.get(above.saturating_sub(1)).copied()
+    }
+
+    /// Move to the next (or previous) search match, wrapping around
+    pub fn jump_to_search_match(&mut self, forward: bool) {
+        if self.search_matches.is_empty() {
+            return;
+        }
+        let len = self.search_matches.len();
+        self.search_index = if forward { (self.search_index + 1) % len } else { (self.search_index + len - 1) % len };
+        self.scroll_to_search_match();
+    }
+
+    fn scroll_to_search_match(&mut self) {
+        if let Some(&offset) = self.search_matches.get(self.search_index) {
+            // Keep a little context above the match
+            self.scroll_offset = offset.saturating_sub(2);
+            self.follow_bottom = false;
+        }
+    }
+
+    pub fn close_search(&mut self) {
+        self.search_term = None;
+        self.search_matches.clear();
+        self.search_index = 0;
+        self.search_jump_pending = false;
+    }
+
     pub fn scroll_to_bottom(&mut self) {
         self.scroll_offset = self.content_height.saturating_sub(self.viewport_height);
         self.follow_bottom = true;
@@ -213,23 +440,104 @@ impl TuiApp {
         self.model_state.select(Some(i));
     }
     
+    /// The assistant message a request streams into, see `message_of_request`
+    fn request_message(&mut self, request_id: u64) -> &mut TranscriptMessage {
+        message_of_request(&mut self.messages, request_id)
+    }
+
+    /// Mark `request_id` as finished if it is the one the UI is waiting for
+    fn finish_request(&mut self, request_id: u64) {
+        if self.active_request == Some(request_id) {
+            self.active_request = None;
+            self.is_thinking = false;
+            self.request_cancel = None;
+            self.request_task = None;
+            self.model_loading = None;
+        }
+    }
+
+    /// Stop the active request: its task is aborted, whatever it already sent is dropped
+    /// and the partial answer is kept, marked as cancelled
+    fn cancel_request(&mut self) {
+        let Some(request_id) = self.active_request else { return };
+        if let Some(cancel) = &self.request_cancel {
+            cancel.store(true, Ordering::Relaxed);
+        }
+        if let Some(task) = &self.request_task {
+            task.abort();
+        }
+        self.dropped_requests.insert(request_id);
+        self.finish_request(request_id);
+
+        if let Some(message) = self.messages.iter_mut().find(|m| m.request_id == Some(request_id)) {
+            let mut content = message.content.trim_end().to_string();
+            // Cut off while thinking: close the block so the marker isn't hidden in it
+            if content.contains("<think>") && !content.contains("</think>") {
+                content.push_str("\n</think>");
+            }
+            content.push_str(if visible_answer(&content).trim().is_empty() { "\n[cancelled]" } else { "\n\n[cancelled]" });
+            message.content = content.trim_start().to_string();
+            message.render_cache.inner = None;
+        }
+        self.set_status(" Answer cancelled ");
+    }
+
+    /// Add an OpenRouter answer to the usage ledger and the period's running total
+    fn record_usage(&mut self, usage: &crate::llm::Usage) {
+        let entry = crate::usage::UsageEntry::new(&self.model_name, usage, self.model_pricing);
+        if let Err(e) = crate::usage::record(&entry) {
+            tracing::warn!("Failed to record usage: {}", e);
+        }
+        self.budget_spend.add(&entry);
+    }
+
+    /// Keep `message` for a y/n confirmation when its estimated cost doesn't fit the remaining budget
+    fn hold_over_budget(&mut self, message: &str) -> bool {
+        if self.active_provider != crate::config::LlmProvider::OpenRouter || !self.budget.is_set() {
+            return false;
+        }
+        let last_prompt = (self.last_request_tokens > 0).then_some(self.last_request_tokens as u64);
+        let estimate = crate::usage::estimate_request(last_prompt, message.len(), self.model_pricing);
+        match crate::usage::over_budget(&self.budget_spend, &estimate, &self.budget) {
+            Some(reason) => {
+                self.pending_send = Some((message.to_string(), format!(" ⚠ Over budget: {}. Send anyway? (y/n) ", reason)));
+                true
+            }
+            None => false,
+        }
+    }
+
     pub fn set_status(&mut self, msg: impl Into<String>) {
         self.status_message = Some(msg.into());
         self.status_message_time = Some(Instant::now());
     }
+
+    /// A text field has focus, so typed characters are not hotkeys
+    fn typing(&self) -> bool {
+        matches!(self.mode, AppMode::Chat | AppMode::Login | AppMode::Study) || (self.mode == AppMode::Settings && self.settings_input_mode)
+    }
+
+    /// "Ctrl+K Commands" with the key opening the command palette, for the hints; empty when it is unbound
+    fn palette_hint(&self, separator: &str) -> String {
+        self.keymap.key_for(Action::Palette).map_or_else(String::new, |key| format!("{} Commands{}", key, separator))
+    }
 }
 
 pub fn setup_terminal() -> io::Result<Terminal<CrosstermBackend<Stdout>>> {
     enable_raw_mode()?;
     let mut stdout = io::stdout();
-    execute!(stdout, EnterAlternateScreen)?;
+    // Leave the terminal usable for the error message
+    if let Err(e) = execute!(stdout, EnterAlternateScreen, EnableBracketedPaste) {
+        let _ = disable_raw_mode();
+        return Err(e);
+    }
     let backend = CrosstermBackend::new(stdout);
     Terminal::new(backend)
 }
 
 pub fn restore_terminal(terminal: &mut Terminal<CrosstermBackend<Stdout>>) -> io::Result<()> {
     disable_raw_mode()?;
-    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    execute!(terminal.backend_mut(), DisableBracketedPaste, LeaveAlternateScreen)?;
     terminal.show_cursor()?;
     Ok(())
 }
@@ -245,8 +553,69 @@ fn draw(frame: &mut Frame, app: &mut TuiApp) {
         AppMode::RagInfo => draw_rag_info(frame, app),
         AppMode::Login => draw_login(frame, app),
         AppMode::Sync => draw_sync(frame, app),
+        AppMode::SyncChanges => draw_sync_changes(frame, app),
+        AppMode::Subjects => draw_subjects(frame, app),
+        AppMode::Study => match app.study.as_mut() {
+            Some(session) => study::draw(frame, session, THROBBER_FRAMES[app.throbber_frame]),
+            None => draw_menu(frame, app),
+        },
         AppMode::Settings => draw_settings(frame, app),
     }
+    if let Some(open) = app.preview.as_mut() {
+        preview::draw(frame, open);
+    }
+    draw_palette(frame, app);
+}
+
+/// The command palette over the current screen: the query, then the matching actions with their hotkeys
+fn draw_palette(frame: &mut Frame, app: &TuiApp) {
+    let Some(palette) = &app.palette else { return };
+    let size = frame.area();
+    let width = size.width.saturating_sub(4).min(72);
+    let height = size.height.saturating_sub(4).min(20);
+    let area = Rect { x: size.x + (size.width - width) / 2, y: size.y + (size.height - height) / 3, width, height };
+    frame.render_widget(Clear, area);
+
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(Color::Cyan))
+        .title(" Commands ");
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+
+    let layout = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Length(1), Constraint::Length(1), Constraint::Min(1), Constraint::Length(1)])
+        .split(inner);
+
+    let query = Line::from(vec![
+        Span::styled(" > ", Style::default().fg(Color::Cyan)),
+        Span::raw(palette.query.as_str()),
+        Span::styled("▏", Style::default().fg(Color::Cyan)),
+    ]);
+    frame.render_widget(Paragraph::new(query), layout[0]);
+
+    let matches = palette.matches();
+    if matches.is_empty() {
+        frame.render_widget(Paragraph::new(" No matching command").style(Style::default().fg(Color::DarkGray)), layout[2]);
+    } else {
+        let label_width = (layout[2].width as usize).saturating_sub(12);
+        let items: Vec<ListItem> = matches.iter().map(|spec| {
+            let key = app.keymap.key_for(spec.action).unwrap_or_default();
+            ListItem::new(Line::from(vec![
+                Span::raw(format!(" {:<width$}", spec.label.chars().take(label_width).collect::<String>(), width = label_width)),
+                Span::styled(format!("{:>10}", key), Style::default().fg(Color::DarkGray)),
+            ]))
+        }).collect();
+        let mut state = ListState::default().with_selected(Some(palette.selected));
+        let list = List::new(items).highlight_style(Style::default().bg(Color::DarkGray));
+        frame.render_stateful_widget(list, layout[2], &mut state);
+    }
+
+    frame.render_widget(
+        Paragraph::new("Type to filter │ ↑/↓ Select │ Enter Run │ Esc Close").style(Style::default().fg(Color::DarkGray)).alignment(Alignment::Center),
+        layout[3],
+    );
 }
 
 fn render_logo() -> Vec<Line<'static>> {
@@ -293,6 +662,13 @@ fn draw_menu(frame: &mut Frame, app: &mut TuiApp) {
         .alignment(Alignment::Center);
     frame.render_widget(status, layout[2]);
     
+    if let Some(warning) = &app.embedder_warning {
+        let banner = Paragraph::new(format!("⚠ Embedding model problem: {}", warning))
+            .style(Style::default().fg(Color::Black).bg(Color::Yellow).add_modifier(Modifier::BOLD))
+            .alignment(Alignment::Center);
+        frame.render_widget(banner, layout[3]);
+    }
+    
     let items: Vec<ListItem> = app.menu_items
         .iter()
         .map(|i| ListItem::new(Line::from(format!("  {}", i))))
@@ -309,7 +685,7 @@ fn draw_menu(frame: &mut Frame, app: &mut TuiApp) {
         
     frame.render_stateful_widget(menu, menu_layout[1], &mut app.menu_state);
     
-    let instr = Paragraph::new("↑/↓ Navigate  │  Enter Select  │  Esc Exit")
+    let instr = Paragraph::new(format!("↑/↓ Navigate  │  Enter Select  │  {}Esc Exit", app.palette_hint("  │  ")))
         .style(Style::default().fg(Color::DarkGray))
         .alignment(Alignment::Center);
     frame.render_widget(instr, layout[5]);
@@ -321,18 +697,45 @@ fn draw_chat(frame: &mut Frame, app: &mut TuiApp) {
     let outer_block = Block::default()
         .borders(Borders::ALL)
         .border_style(Style::default().fg(Color::Cyan))
-        .title(format!(" PoliRag Chat │ {} ", app.model_name))
-        .title_bottom(Line::from(format!(" {}/{} tokens ", app.last_request_tokens, app.context_limit)).right_aligned());
+        .title({
+            let mut title = match &app.chat_title {
+                Some(title) => format!(" PoliRag Chat │ {} │ {} ", title, app.model_name),
+                None => format!(" PoliRag Chat │ {} ", app.model_name),
+            };
+            if let Some(types) = &app.type_filter {
+                title.push_str(&format!("│ types: {} ", types.join(",")));
+            }
+            if let Some(tag) = &app.tag_scope {
+                title.push_str(&format!("│ tag: {} ", tag));
+            }
+            if app.pasted_texts > 0 {
+                title.push_str(&format!("│ pasted: {} ", app.pasted_texts));
+            }
+            if app.sync_running {
+                title.push_str(&format!("│ {} syncing ", THROBBER_FRAMES[app.throbber_frame]));
+            }
+            title
+        })
+        .title_bottom(Line::from(if app.budget.is_set() {
+            format!(" {} │ {}/{} tokens ", crate::usage::describe_spend(&app.budget_spend, &app.budget), app.last_request_tokens, app.context_limit)
+        } else {
+            format!(" {}/{} tokens ", app.last_request_tokens, app.context_limit)
+        }).right_aligned());
     
     let inner_area = outer_block.inner(size);
     frame.render_widget(outer_block, size);
     
+    // The paste capture grows with its text, up to a third of the screen
+    let input_height = match &app.paste_capture {
+        Some(text) => (text.lines().count().max(1) as u16 + 2).clamp(3, (inner_area.height / 3).max(3)),
+        None => 3,
+    };
     let chunks = Layout::default()
         .direction(Direction::Vertical)
         .constraints([
             Constraint::Min(5),
             Constraint::Length(1),
-            Constraint::Length(3),
+            Constraint::Length(input_height),
         ])
         .split(inner_area);
 
@@ -342,7 +745,9 @@ fn draw_chat(frame: &mut Frame, app: &mut TuiApp) {
     let max_width = messages_area.width.saturating_sub(4) as usize;
     let mut total_height = 0;
     let mut lines: Vec<Line> = Vec::new();
+    let mut assistant_offsets = Vec::new();
     
+    let streaming_request = app.active_request;
     // Use mutable iteration to update render cache
     for msg in &mut app.messages {
         match msg.role.as_str() {
@@ -353,6 +758,7 @@ fn draw_chat(frame: &mut Frame, app: &mut TuiApp) {
                 msg_lines.push(Line::from(""));
                 msg_lines.push(Line::from(vec![
                     Span::styled(" ▶ You ", Style::default().fg(Color::Blue).add_modifier(Modifier::BOLD)),
+                    Span::styled(if msg.expanded_context { "(re-asked with expanded context)" } else { "" }, Style::default().fg(Color::DarkGray)),
                 ]));
                 msg_height += 2;
                 
@@ -365,7 +771,7 @@ fn draw_chat(frame: &mut Frame, app: &mut TuiApp) {
                 }
                 
                 if !use_cache {
-                    let rendered = markdown::render_markdown(&msg.content, max_width, false);
+                    let rendered = markdown::render_markdown(&msg.content, max_width, false, 0, false);
                     // Calculate height for this message
                     let mut rendered_height = 0;
                     for line in &rendered {
@@ -387,6 +793,8 @@ fn draw_chat(frame: &mut Frame, app: &mut TuiApp) {
             "assistant" => {
                 let mut msg_lines = Vec::new();
                 let mut msg_height = 0;
+                // Land on the "◆ Assistant" header, after the blank separator line
+                assistant_offsets.push((total_height + 1) as u16);
                 
                 msg_lines.push(Line::from(""));
                 msg_lines.push(Line::from(vec![
@@ -403,7 +811,16 @@ fn draw_chat(frame: &mut Frame, app: &mut TuiApp) {
                 }
                 
                 if !use_cache {
-                   let rendered = markdown::render_markdown(&msg.content, max_width, msg.thinking_collapsed);
+                   let citation_count = msg.citations.as_ref().map_or(0, |c| c.sources.len());
+                   // The streaming answer renders with its open fences closed; StreamDone clears the cache
+                   let streaming = msg.request_id.is_some() && msg.request_id == streaming_request;
+                   let mut rendered = markdown::render_markdown(&msg.content, max_width, msg.thinking_collapsed, citation_count, streaming);
+                   if let Some(citations) = msg.citations.as_ref().filter(|c| c.resolved) {
+                       rendered.extend(render_sources_footer(citations));
+                   }
+                   if !streaming && !msg.sources.is_empty() {
+                       rendered.extend(render_retrieved_sources(&msg.sources, msg.sources_expanded));
+                   }
                    // Calculate height
                    let mut rendered_height = 0;
                    for line in &rendered {
@@ -422,6 +839,15 @@ fn draw_chat(frame: &mut Frame, app: &mut TuiApp) {
                 lines.extend(msg_lines);
                 total_height += msg_height;
             }
+            NOTE_ROLE => {
+                lines.push(Line::from(""));
+                lines.push(Line::from(Span::styled(" ✎ Note (not sent to the model) ", Style::default().fg(Color::DarkGray).add_modifier(Modifier::BOLD))));
+                total_height += 2;
+                for line in msg.content.lines() {
+                    total_height += textwrap::wrap(line, max_width).len().max(1);
+                    lines.push(Line::from(Span::styled(format!(" {}", line), Style::default().fg(Color::Gray))));
+                }
+            }
             _ => {}
         }
     }
@@ -455,10 +881,24 @@ fn draw_chat(frame: &mut Frame, app: &mut TuiApp) {
     }
     */
     app.content_height = total_height as u16;
+    app.assistant_offsets = assistant_offsets;
+
+    if let Some(term) = app.search_term.clone() {
+        app.search_matches = highlight_matches(&mut lines, &term, max_width, app.search_index);
+        if app.search_index >= app.search_matches.len() {
+            app.search_index = 0;
+        }
+        if app.search_jump_pending {
+            app.search_jump_pending = false;
+            // Search forward from the current position, like less
+            let from = app.scroll_offset;
+            app.search_index = app.search_matches.iter().position(|&o| o >= from).unwrap_or(0);
+            app.scroll_to_search_match();
+        }
+    }
 
     let max_scroll = app.content_height.saturating_sub(app.viewport_height);
-    if app.follow_bottom { app.scroll_offset = max_scroll; }
-    else if app.scroll_offset > max_scroll { app.scroll_offset = max_scroll; }
+    if app.follow_bottom || app.scroll_offset > max_scroll { app.scroll_offset = max_scroll; }
 
     let messages = Paragraph::new(Text::from(lines))
         .wrap(Wrap { trim: false })
@@ -476,10 +916,34 @@ fn draw_chat(frame: &mut Frame, app: &mut TuiApp) {
         frame.render_stateful_widget(scrollbar, messages_area, &mut scrollbar_state);
     }
 
-    let status_text = app.status_message.clone().unwrap_or_else(|| "Esc Menu │ Ctrl+L Clear │ /model <name>".to_string());
+    let loading_text = app.model_loading.map(|(attempt, limit)| {
+        format!("{} Model loading on server… retry {}/{} │ Esc Cancel", THROBBER_FRAMES[app.throbber_frame], attempt, limit)
+    });
+    let status_text = app.pending_send.as_ref().map(|(_, prompt)| prompt.clone()).or(loading_text).or_else(|| app.status_message.clone()).unwrap_or_else(|| match &app.search_term {
+        Some(term) if app.search_matches.is_empty() => format!("🔍 \"{}\": no matches │ Esc Close", term),
+        Some(term) => format!("🔍 \"{}\" {}/{} │ n/N Next/Prev │ Esc Close", term, app.search_index + 1, app.search_matches.len()),
+        None if app.active_request.is_some() => format!("Esc Stop answer │ {}Ctrl+↑/↓ Answers │ /find <text>", app.palette_hint(" │ ")),
+        None => format!("Esc Menu │ {}Ctrl+L Clear │ Ctrl+R Re-ask │ Ctrl+↑/↓ Answers │ /find <text> │ /model <name>", app.palette_hint(" │ ")),
+    });
     let status = Paragraph::new(status_text).style(Style::default().fg(Color::DarkGray)).alignment(Alignment::Center);
     frame.render_widget(status, chunks[1]);
 
+    if let Some(text) = &app.paste_capture {
+        let input_block = Block::default()
+            .borders(Borders::TOP)
+            .border_style(Style::default().fg(Color::Magenta))
+            .title(format!(" Paste ({} chars) │ Ctrl+D Add as context │ Esc Cancel ", text.chars().count()));
+        // Keep the end of the text, where typing goes, in view
+        let visible = chunks[2].height.saturating_sub(1) as usize;
+        let lines: Vec<&str> = text.split('\n').collect();
+        let shown = lines[lines.len().saturating_sub(visible)..].join("\n");
+        frame.render_widget(Paragraph::new(shown).block(input_block).style(Style::default().fg(Color::White)), chunks[2]);
+        let last = lines.last().map_or(0, |l| l.chars().count()) as u16;
+        let row = lines.len().min(visible) as u16;
+        frame.set_cursor_position(((chunks[2].x + last).min(chunks[2].x + chunks[2].width - 1), chunks[2].y + row));
+        return;
+    }
+
     let input_block = Block::default()
         .borders(Borders::TOP)
         .border_style(if app.is_thinking { Style::default().fg(Color::Yellow) } else { Style::default().fg(Color::Cyan) })
@@ -494,6 +958,266 @@ fn draw_chat(frame: &mut Frame, app: &mut TuiApp) {
     }
 }
 
+// ============================================================================
+// CHAT TITLE
+// ============================================================================
+
+const TITLE_MAX_CHARS: usize = 48;
+
+/// The assistant message `request_id` streams into. If it was removed meanwhile, a new one
+/// is appended so the rest of the answer isn't lost.
+fn message_of_request(messages: &mut Vec<TranscriptMessage>, request_id: u64) -> &mut TranscriptMessage {
+    let pos = match messages.iter().position(|m| m.request_id == Some(request_id)) {
+        Some(pos) => pos,
+        None => {
+            tracing::warn!("Message for request {} is gone, recovering the answer in a new one", request_id);
+            messages.push(TranscriptMessage { request_id: Some(request_id), ..TranscriptMessage::new("assistant", String::new()) });
+            messages.len() - 1
+        }
+    };
+    &mut messages[pos]
+}
+
+/// Show the error that ended `request_id` in the transcript
+fn show_request_error(messages: &mut Vec<TranscriptMessage>, request_id: u64, error: &str) {
+    let message = message_of_request(messages, request_id);
+    // Nothing streamed yet: the error fills the placeholder instead of leaving an empty turn above it
+    if message.content.trim().is_empty() {
+        message.content = format!("Error: {}", error);
+        message.citations = None;
+        message.render_cache.inner = None;
+        return;
+    }
+    // Show the error right after the answer it interrupted
+    let pos = messages.iter().position(|m| m.request_id == Some(request_id)).unwrap_or(messages.len() - 1);
+    messages.insert(pos + 1, TranscriptMessage::new("assistant", format!("Error: {}", error)));
+}
+
+/// Answer text after the thinking block; empty while the model is still (or only) thinking
+fn visible_answer(content: &str) -> &str {
+    match content.rfind("</think>") {
+        Some(pos) => &content[pos + "</think>".len()..],
+        None if content.trim_start().starts_with("<think>") => "",
+        None => content,
+    }
+}
+
+/// The earlier turns of the transcript as the model sees them: notes, unfinished answers and
+/// empty answers are left out, the latter together with the question they answered, and
+/// answers lose their thinking. Questions keep what was typed, never the retrieved context.
+fn request_history(transcript: &[TranscriptMessage]) -> Vec<ChatMessage> {
+    let mut history: Vec<ChatMessage> = Vec::with_capacity(transcript.len());
+    for message in transcript {
+        if message.role == "assistant" && message.content == EMPTY_ANSWER_MARKER {
+            if history.last().is_some_and(|m| m.role == "user") {
+                history.pop();
+            }
+            continue;
+        }
+        match message.role.as_str() {
+            "user" => history.push(ChatMessage::new("user", message.content.clone())),
+            "assistant" if !visible_answer(&message.content).trim().is_empty() => {
+                history.push(ChatMessage::new("assistant", visible_answer(&message.content).trim()));
+            }
+            _ => {}
+        }
+    }
+    history
+}
+
+/// The messages of a request, built when it is dispatched and never stored: the system
+/// prompt, the earlier turns (see `request_history`) and the question with its context
+fn api_messages(system_prompt: &str, history: &[ChatMessage], prompt: &str) -> Vec<ChatMessage> {
+    let mut messages = Vec::with_capacity(history.len() + 2);
+    messages.push(ChatMessage::new("system", system_prompt));
+    messages.extend(history.iter().cloned());
+    messages.push(ChatMessage::new("user", prompt));
+    messages
+}
+
+/// After the first exchange of a conversation, ask the model for a short title in the background.
+/// Any failure falls back to the start of the first question.
+fn request_chat_title(app: &mut TuiApp, state: &Arc<AppState>, tx_llm: &mpsc::Sender<LlmResult>) {
+    if app.chat_title.is_some() || app.title_pending {
+        return;
+    }
+
+    let mut user_messages = app.messages.iter().filter(|m| m.role == "user");
+    let (question, second) = (user_messages.next(), user_messages.next());
+    let (Some(question), None) = (question, second) else { return };
+    let Some(answer) = app.messages.last().filter(|m| m.role == "assistant") else { return };
+
+    let question = question.content.clone();
+    let answer: String = answer.content.rsplit("</think>").next().unwrap_or("").chars().take(1000).collect();
+    let fallback = fallback_title(&question);
+
+    app.title_pending = true;
+    let llm = state.llm.lock().unwrap().clone();
+    let tx = tx_llm.clone();
+
+    tokio::spawn(async move {
+        let prompt = vec![
+            ChatMessage::new("system", "Write a title of at most 6 words for the conversation below, in the same language as the user's question. Reply with the title only, without quotes or punctuation at the end."),
+            ChatMessage::new("user", format!("Question: {}\n\nAnswer: {}", question, answer)),
+        ];
+
+        let request = llm.chat_limited(&prompt, Some(24));
+        let title = match tokio::time::timeout(Duration::from_secs(20), request).await {
+            Ok(Ok((content, _))) => Some(sanitize_title(&content)).filter(|t| !t.is_empty()),
+            Ok(Err(e)) => {
+                tracing::debug!("Chat title request failed: {}", e);
+                None
+            }
+            Err(_) => None,
+        };
+
+        let _ = tx.send(LlmResult::Title(title.unwrap_or(fallback))).await;
+    });
+}
+
+/// Single line title: no think block, quotes, markdown markers or "Title:" prefix, capped in length
+fn sanitize_title(raw: &str) -> String {
+    let text = raw.rsplit("</think>").next().unwrap_or(raw);
+    let line = text.lines().map(str::trim).find(|l| !l.is_empty()).unwrap_or("");
+    let line = line
+        .strip_prefix("Title:")
+        .or_else(|| line.strip_prefix("Título:"))
+        .or_else(|| line.strip_prefix("Títol:"))
+        .unwrap_or(line);
+    let line = line.trim_matches(|c: char| c.is_whitespace() || matches!(c, '"' | '\'' | '`' | '*' | '#' | '«' | '»' | '“' | '”' | '.'));
+
+    let collapsed = line.split_whitespace().collect::<Vec<_>>().join(" ");
+    truncate_title(&collapsed)
+}
+
+/// First ~40 characters of the question, used when no title could be generated
+fn fallback_title(question: &str) -> String {
+    let collapsed = question.split_whitespace().collect::<Vec<_>>().join(" ");
+    if collapsed.chars().count() <= 40 {
+        collapsed
+    } else {
+        format!("{}…", collapsed.chars().take(40).collect::<String>().trim_end())
+    }
+}
+
+fn truncate_title(title: &str) -> String {
+    if title.chars().count() <= TITLE_MAX_CHARS {
+        title.to_string()
+    } else {
+        format!("{}…", title.chars().take(TITLE_MAX_CHARS).collect::<String>().trim_end())
+    }
+}
+
+/// Complete the last type in a `/types a,b` input to the first index type it prefixes
+fn complete_types(input: &str, available: &[String]) -> Option<String> {
+    let (head, partial) = match input.rfind(',') {
+        Some(pos) => input.split_at(pos + 1),
+        None => input.split_at("/types ".len()),
+    };
+    let partial = partial.trim_start().to_lowercase();
+    let listed: Vec<&str> = head.trim_start_matches("/types").split(',').map(|t| t.trim()).collect();
+    available.iter()
+        .find(|t| t.starts_with(&partial) && **t != partial && !listed.contains(&t.as_str()))
+        .map(|t| format!("{}{}", head, t))
+}
+
+/// Highlight case-insensitive occurrences of `term` in the transcript lines and return the
+/// scroll offset of every matching line. The `current` match gets a stronger highlight.
+fn highlight_matches(lines: &mut [Line<'static>], term: &str, max_width: usize, current: usize) -> Vec<u16> {
+    let needle = term.to_lowercase();
+    let mut offsets = Vec::new();
+    let mut offset = 0usize;
+
+    for line in lines.iter_mut() {
+        let line_str = line.to_string();
+        let height = textwrap::wrap(&line_str, max_width).len().max(1);
+
+        if line_str.to_lowercase().contains(&needle) {
+            let style = if offsets.len() == current {
+                Style::default().fg(Color::Black).bg(Color::Yellow).add_modifier(Modifier::BOLD)
+            } else {
+                Style::default().fg(Color::Black).bg(Color::DarkGray)
+            };
+            let spans = std::mem::take(&mut line.spans);
+            line.spans = spans.into_iter().flat_map(|span| split_span_matches(span, &needle, style)).collect();
+            offsets.push(offset as u16);
+        }
+        offset += height;
+    }
+
+    offsets
+}
+
+fn split_span_matches(span: Span<'static>, needle: &str, match_style: Style) -> Vec<Span<'static>> {
+    let content = span.content.to_string();
+    let lower = content.to_lowercase();
+    // Lowercasing changed byte offsets (rare non-ASCII cases): highlight the whole span
+    if lower.len() != content.len() {
+        if lower.contains(needle) {
+            return vec![Span::styled(content, span.style.patch(match_style))];
+        }
+        return vec![span];
+    }
+
+    let mut parts = Vec::new();
+    let mut last = 0;
+    for (start, matched) in lower.match_indices(needle) {
+        if start > last {
+            parts.push(Span::styled(content[last..start].to_string(), span.style));
+        }
+        parts.push(Span::styled(content[start..start + matched.len()].to_string(), span.style.patch(match_style)));
+        last = start + matched.len();
+    }
+    if parts.is_empty() {
+        return vec![span];
+    }
+    if last < content.len() {
+        parts.push(Span::styled(content[last..].to_string(), span.style));
+    }
+    parts
+}
+
+/// Numbered sources under an answer, in citation order, with uncited ones dimmed
+fn render_sources_footer(citations: &crate::rag::citations::CitationMap) -> Vec<Line<'static>> {
+    let mut lines = vec![
+        Line::from(""),
+        Line::from(Span::styled(" Sources", Style::default().fg(Color::DarkGray).add_modifier(Modifier::BOLD))),
+    ];
+    for (number, source, cited) in citations.footer() {
+        let style = if cited { Style::default().fg(Color::Cyan) } else { Style::default().fg(Color::DarkGray) };
+        lines.push(Line::from(Span::styled(format!("  [{}] {}", number, source), style)));
+    }
+    if !citations.invalid.is_empty() {
+        let invalid: Vec<String> = citations.invalid.iter().map(|n| format!("[{}]", n)).collect();
+        lines.push(Line::from(Span::styled(
+            format!("  ⚠ Cited non-existent source {}", invalid.join(" ")),
+            Style::default().fg(Color::Red),
+        )));
+    }
+    lines
+}
+
+/// Collapsible list of the snippets retrieved for an answer, toggled with Ctrl+O
+fn render_retrieved_sources(sources: &[(String, f32)], expanded: bool) -> Vec<Line<'static>> {
+    let marker = if expanded { "▼" } else { "▶" };
+    let mut lines = vec![
+        Line::from(""),
+        Line::from(Span::styled(
+            format!(" {} Retrieved sources ({})", marker, sources.len()),
+            Style::default().fg(Color::DarkGray).add_modifier(Modifier::BOLD),
+        )),
+    ];
+    if expanded {
+        for (label, score) in sources {
+            lines.push(Line::from(vec![
+                Span::styled(format!("  {:.3} ", score), Style::default().fg(Color::Yellow)),
+                Span::styled(label.clone(), Style::default().fg(Color::DarkGray)),
+            ]));
+        }
+    }
+    lines
+}
+
 fn draw_rag_info(frame: &mut Frame, app: &mut TuiApp) {
     let size = frame.area();
     
@@ -517,18 +1241,63 @@ fn draw_rag_info(frame: &mut Frame, app: &mut TuiApp) {
         let mut lines = vec![
             Line::from(""),
             Line::from(vec![Span::styled("  📁 Storage Path:    ", Style::default().add_modifier(Modifier::BOLD)), Span::raw(&stats.storage_path)]),
-            Line::from(vec![Span::styled("  🗄️  Store Type:      ", Style::default().add_modifier(Modifier::BOLD)), Span::styled(&stats.store_type, Style::default().fg(Color::Cyan))]),
-            Line::from(vec![Span::styled("  ✂️  Chunking:        ", Style::default().add_modifier(Modifier::BOLD)), Span::raw(&stats.chunking_strategy)]),
-            Line::from(vec![Span::styled("  🧠 Embedding Model: ", Style::default().add_modifier(Modifier::BOLD)), Span::raw(&stats.embedding_model)]),
             Line::from(vec![Span::styled("  💾 Index Size:      ", Style::default().add_modifier(Modifier::BOLD)), Span::styled(stats.format_file_size(), Style::default().fg(Color::Green))]),
             Line::from(vec![Span::styled("  📄 Documents:       ", Style::default().add_modifier(Modifier::BOLD)), Span::styled(stats.document_count.to_string(), Style::default().fg(Color::Yellow))]),
             Line::from(vec![Span::styled("  📝 Content Size:    ", Style::default().add_modifier(Modifier::BOLD)), Span::raw(stats.format_content_size())]),
+            Line::from(vec![Span::styled("  🕒 Indexed:         ", Style::default().add_modifier(Modifier::BOLD)), Span::raw(match stats.indexed_range {
+                Some((oldest, newest)) => format!(
+                    "oldest {} ({}), newest {} ({})",
+                    crate::scrapper::processing::format_date(oldest / 86_400), crate::rag::format_age(oldest),
+                    crate::scrapper::processing::format_date(newest / 86_400), crate::rag::format_age(newest)
+                ),
+                None => "not recorded yet".to_string(),
+            })]),
             Line::from(""),
             Line::from(Span::styled("  Documents by Type:", Style::default().add_modifier(Modifier::BOLD).add_modifier(Modifier::UNDERLINED))),
         ];
         for (t, c) in &stats.docs_by_type {
             lines.push(Line::from(format!("    • {}: {}", t, c)));
         }
+        if !stats.docs_by_subject.is_empty() {
+            lines.push(Line::from(""));
+            lines.push(Line::from(Span::styled("  Documents by Subject:", Style::default().add_modifier(Modifier::BOLD).add_modifier(Modifier::UNDERLINED))));
+            // Largest first, the subjects worth disabling or cleaning to shrink the index
+            let mut subjects: Vec<_> = stats.docs_by_subject.iter().collect();
+            subjects.sort_by(|a, b| b.1.content_bytes.cmp(&a.1.content_bytes).then_with(|| a.0.cmp(b.0)));
+            for (id, subject) in subjects {
+                let name = subject.name.as_deref().unwrap_or(id);
+                lines.push(Line::from(format!(
+                    "    • {}: {} chunks, {}",
+                    name, subject.chunks, crate::scrapper::format_bytes(subject.content_bytes as u64)
+                )));
+            }
+        }
+        lines.push(Line::from(""));
+        lines.push(Line::from(Span::styled("  Retrieval Pipeline:", Style::default().add_modifier(Modifier::BOLD).add_modifier(Modifier::UNDERLINED))));
+        for (name, value) in stats.pipeline() {
+            lines.push(Line::from(vec![Span::styled(format!("    • {:<13}", format!("{}:", name)), Style::default().add_modifier(Modifier::BOLD)), Span::raw(value)]));
+        }
+        if !stats.index_params.is_empty() {
+            lines.push(Line::from(""));
+            lines.push(Line::from(Span::styled("  Index Parameters:", Style::default().add_modifier(Modifier::BOLD).add_modifier(Modifier::UNDERLINED))));
+            for (name, value) in &stats.index_params {
+                lines.push(Line::from(format!("    • {}: {}", name, value)));
+            }
+        }
+        if let Some(plan) = &app.clean_plan {
+            lines.push(Line::from(""));
+            lines.push(Line::from(Span::styled("  Disk Cleanup (dry run):", Style::default().add_modifier(Modifier::BOLD).add_modifier(Modifier::UNDERLINED))));
+            for target in crate::clean::CleanTarget::ALL {
+                let count = plan.candidates.iter().filter(|c| c.target == target).count();
+                let kept = plan.candidates.iter().filter(|c| c.target == target && !c.indexed.is_empty()).count();
+                let kept = if kept > 0 { format!(", {} still indexed and kept", kept) } else { String::new() };
+                lines.push(Line::from(format!("    • {}: {} ({} items{})", target.label(), crate::scrapper::format_bytes(plan.bytes(target)), count, kept)));
+            }
+            for note in &plan.notes {
+                lines.push(Line::from(Span::styled(format!("    {}", note), Style::default().fg(Color::DarkGray))));
+            }
+            lines.push(Line::from(Span::styled("    D deletes, Esc cancels; `polirag clean --prune-index` also removes indexed files", Style::default().fg(Color::Yellow))));
+        }
         lines
     } else {
         vec![Line::from(""), Line::from(Span::styled("  ⏳ Loading...", Style::default().fg(Color::Yellow)))]
@@ -545,7 +1314,7 @@ fn draw_rag_info(frame: &mut Frame, app: &mut TuiApp) {
     } else {
         let buttons_layout = Layout::default()
             .direction(Direction::Horizontal)
-            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+            .constraints([Constraint::Ratio(1, 4), Constraint::Ratio(1, 4), Constraint::Ratio(1, 4), Constraint::Ratio(1, 4)])
             .split(button_area);
 
         let reembed_button = Paragraph::new("  ▶ [R] Recalculate  ")
@@ -553,14 +1322,25 @@ fn draw_rag_info(frame: &mut Frame, app: &mut TuiApp) {
             .alignment(Alignment::Center);
         frame.render_widget(reembed_button, buttons_layout[0]);
 
+        let rebuild_button = Paragraph::new("  ⟳ [B] Rebuild Graph  ")
+            .style(Style::default().fg(Color::Black).bg(Color::Yellow).add_modifier(Modifier::BOLD))
+            .alignment(Alignment::Center);
+        frame.render_widget(rebuild_button, buttons_layout[1]);
+
+        let clean_label = if app.clean_plan.is_some() { "  🧹 [D] Delete Files  " } else { "  🧹 [D] Clean Data  " };
+        let clean_button = Paragraph::new(clean_label)
+            .style(Style::default().fg(Color::Black).bg(Color::Green).add_modifier(Modifier::BOLD))
+            .alignment(Alignment::Center);
+        frame.render_widget(clean_button, buttons_layout[2]);
+
         let clear_button = Paragraph::new("  🗑 [C] Clear Index  ")
             .style(Style::default().fg(Color::White).bg(Color::Red).add_modifier(Modifier::BOLD))
             .alignment(Alignment::Center);
-        frame.render_widget(clear_button, buttons_layout[1]);
+        frame.render_widget(clear_button, buttons_layout[3]);
     }
     
     let instr_text = if app.reembed_running { 
-        "Recalculating embeddings..." 
+        "Working on the index..." 
     } else { 
         "Esc Menu" 
     };
@@ -591,8 +1371,10 @@ fn draw_login(frame: &mut Frame, app: &mut TuiApp) {
     frame.render_widget(Paragraph::new(app.login_username.as_str()).block(username_block), form_layout[1]);
     
     let pin_style = if app.login_field == 1 { Style::default().fg(Color::Cyan) } else { Style::default().fg(Color::DarkGray) };
-    let pin_block = Block::default().borders(Borders::ALL).border_style(pin_style).title(" PIN/Password ");
-    frame.render_widget(Paragraph::new("*".repeat(app.login_pin.len())).block(pin_block), form_layout_pin[1]);
+    let pin_title = if app.login_pin_visible { " PIN/Password (shown) " } else { " PIN/Password " };
+    let pin_block = Block::default().borders(Borders::ALL).border_style(pin_style).title(pin_title);
+    let pin_text = if app.login_pin_visible { app.login_pin.clone() } else { "*".repeat(app.login_pin.chars().count()) };
+    frame.render_widget(Paragraph::new(pin_text).block(pin_block), form_layout_pin[1]);
     
     if let Some(error) = &app.login_error {
         frame.render_widget(Paragraph::new(error.as_str()).style(Style::default().fg(Color::Red)).alignment(Alignment::Center), layout[5]);
@@ -602,20 +1384,25 @@ fn draw_login(frame: &mut Frame, app: &mut TuiApp) {
     
     if !app.is_thinking {
         let (cursor_x, cursor_y) = if app.login_field == 0 {
-            (form_layout[1].x + app.login_username.len() as u16 + 1, form_layout[1].y + 1)
+            (form_layout[1].x + app.login_username.chars().count() as u16 + 1, form_layout[1].y + 1)
         } else {
-            (form_layout_pin[1].x + app.login_pin.len() as u16 + 1, form_layout_pin[1].y + 1)
+            (form_layout_pin[1].x + app.login_pin.chars().count() as u16 + 1, form_layout_pin[1].y + 1)
         };
         frame.set_cursor_position((cursor_x, cursor_y));
     }
     
-    frame.render_widget(Paragraph::new("Tab Switch Field │ Enter Submit │ Esc Cancel").style(Style::default().fg(Color::DarkGray)).alignment(Alignment::Center), layout[7]);
+    let reveal = if app.login_pin_visible { "Ctrl+R Hide PIN" } else { "Ctrl+R Show PIN" };
+    frame.render_widget(Paragraph::new(format!("Tab Switch Field │ {} │ Enter Submit │ Esc Cancel", reveal)).style(Style::default().fg(Color::DarkGray)).alignment(Alignment::Center), layout[7]);
 }
 
 fn draw_sync(frame: &mut Frame, app: &mut TuiApp) {
     let size = frame.area();
     
-    let title = if app.sync_running {
+    let title = if app.churn_prompt.is_some() {
+        " Sync paused: review changes ".to_string()
+    } else if app.low_space_sync.is_some() {
+        " Low disk space ".to_string()
+    } else if app.sync_running {
         format!(" Syncing... {} ", THROBBER_FRAMES[app.throbber_frame])
     } else if app.sync_complete {
         " Sync Complete ✓ ".to_string()
@@ -664,10 +1451,112 @@ fn draw_sync(frame: &mut Frame, app: &mut TuiApp) {
         .scroll((app.scroll_offset, 0));
     frame.render_widget(logs, log_area);
     
-    let instr_text = if app.sync_running { "Syncing in progress..." } else { "Press Esc to return to Menu" };
+    let instr_text = if app.churn_prompt.is_some() {
+        "Y: Apply (old versions archived) | N: Keep indexed versions | Esc: Back to Menu"
+    } else if app.low_space_sync.is_some() {
+        "Y: Sync anyway | N: Cancel | Esc: Back to Menu"
+    } else if app.sync_running {
+        "Syncing in progress... | Esc: Back to Menu (the sync keeps running)"
+    } else if app.sync_report.is_some() {
+        "C: Review Changes | Esc: Return to Menu"
+    } else {
+        "Press Esc to return to Menu"
+    };
     frame.render_widget(Paragraph::new(instr_text).style(Style::default().fg(Color::DarkGray)).alignment(Alignment::Center), layout[3]);
 }
 
+fn draw_sync_changes(frame: &mut Frame, app: &mut TuiApp) {
+    let size = frame.area();
+
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(Color::Cyan))
+        .title(" Sync Changes ");
+    let inner_area = block.inner(size);
+    frame.render_widget(block, size);
+
+    let layout = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(3), Constraint::Length(2)])
+        .margin(1)
+        .split(inner_area);
+
+    let subjects = app.sync_report.as_ref().map(|r| r.subjects.as_slice()).unwrap_or(&[]);
+    if subjects.is_empty() {
+        frame.render_widget(Paragraph::new("Nothing changed since the previous sync.").alignment(Alignment::Center), layout[0]);
+    } else {
+        // One item per subject; expanded ones list their files below the header
+        let items: Vec<ListItem> = subjects.iter().enumerate().map(|(i, subject)| {
+            let expanded = app.changes_expanded.contains(&i);
+            let mut lines = vec![Line::from(vec![
+                Span::raw(if expanded { "▾ " } else { "▸ " }),
+                Span::styled(subject.subject.clone(), Style::default().add_modifier(Modifier::BOLD)),
+                Span::styled(format!("  {}", subject.counts()), Style::default().fg(Color::DarkGray)),
+            ])];
+            if expanded {
+                let groups = [("+", &subject.added, Color::Green), ("~", &subject.updated, Color::Yellow), ("-", &subject.removed, Color::Red)];
+                for (sign, changes, color) in groups {
+                    for change in changes {
+                        lines.push(Line::from(Span::styled(format!("    {} {}", sign, change.describe()), Style::default().fg(color))));
+                    }
+                }
+            }
+            ListItem::new(lines)
+        }).collect();
+
+        let list = List::new(items)
+            .block(Block::default().borders(Borders::ALL).border_style(Style::default().fg(Color::DarkGray)))
+            .highlight_style(Style::default().bg(Color::DarkGray));
+        frame.render_stateful_widget(list, layout[0], &mut app.changes_state);
+    }
+
+    frame.render_widget(
+        Paragraph::new("↑/↓: Select | Enter: Expand/Collapse | Esc: Back").style(Style::default().fg(Color::DarkGray)).alignment(Alignment::Center),
+        layout[1],
+    );
+}
+
+fn draw_subjects(frame: &mut Frame, app: &mut TuiApp) {
+    let size = frame.area();
+
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(Color::Cyan))
+        .title(" Subjects ");
+    let inner_area = block.inner(size);
+    frame.render_widget(block, size);
+
+    let layout = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(3), Constraint::Length(2)])
+        .margin(1)
+        .split(inner_area);
+
+    if app.subjects.is_empty() {
+        frame.render_widget(Paragraph::new("No subjects yet. Run a sync first.").alignment(Alignment::Center), layout[0]);
+    } else {
+        let flag = |on: bool| if on { Span::styled("✓", Style::default().fg(Color::Green)) } else { Span::styled("✗", Style::default().fg(Color::Red)) };
+        let items: Vec<ListItem> = app.subjects.iter().map(|subject| {
+            ListItem::new(Line::from(vec![
+                Span::styled(subject.label().to_string(), Style::default().add_modifier(Modifier::BOLD)),
+                Span::styled(format!("  {}  ", subject.id), Style::default().fg(Color::DarkGray)),
+                Span::raw("[sync "), flag(subject.prefs.sync), Span::raw("] [retrieve "), flag(subject.prefs.retrieve), Span::raw("]"),
+                Span::styled(format!("  {} docs", subject.documents), Style::default().fg(Color::DarkGray)),
+            ]))
+        }).collect();
+
+        let list = List::new(items)
+            .block(Block::default().borders(Borders::ALL).border_style(Style::default().fg(Color::DarkGray)))
+            .highlight_style(Style::default().bg(Color::DarkGray));
+        frame.render_stateful_widget(list, layout[0], &mut app.subjects_state);
+    }
+
+    frame.render_widget(
+        Paragraph::new("↑/↓: Select | S: Toggle sync | R: Toggle retrieval | Esc: Back").style(Style::default().fg(Color::DarkGray)).alignment(Alignment::Center),
+        layout[1],
+    );
+}
+
 fn _draw_settings_old(frame: &mut Frame, app: &mut TuiApp) {
     let size = frame.area();
     
@@ -750,17 +1639,70 @@ fn _draw_settings_old(frame: &mut Frame, app: &mut TuiApp) {
 // ASYNC MESSAGING
 // ============================================================================
 
+/// Summary of the context sent with the last question
+#[derive(Clone)]
+pub struct PromptInfo {
+    pub profile: crate::config::ContextProfile,
+    pub snippets: usize,
+    /// Files included with their complete content
+    pub full_files: usize,
+    /// Files replaced by a summary and their best excerpt
+    pub condensed: Vec<String>,
+    pub sources: Vec<String>,
+    /// When each source was indexed, for those that record it
+    pub indexed_at: std::collections::HashMap<String, u64>,
+    /// Only a weak match was found, see `BuiltPrompt::low_confidence`
+    pub low_confidence: bool,
+    pub chars: usize,
+    /// Subject of each retrieved source, see `BuiltPrompt::source_subjects`
+    pub source_subjects: std::collections::HashMap<String, String>,
+    /// Label, score and affinity boost of each snippet
+    pub scores: Vec<(String, f32, f32)>,
+    /// Chunk of each of `scores`, for `/view`
+    pub snippet_ids: Vec<String>,
+    /// Documents in the context with their subject and pages, for `/cite`
+    pub documents: Vec<crate::rag::context::SourceDocument>,
+    /// Model and provider that answered, and the day it was asked
+    pub model: String,
+    pub provider: crate::config::LlmProvider,
+    pub date: String,
+    /// Earlier messages dropped to fit the request in `window` tokens
+    pub trimmed_turns: usize,
+    /// Retrieved files and snippets left out of the context for the same reason
+    pub left_out: usize,
+    /// Share of the model's context the request was fitted to, see `REQUEST_WINDOW_PERCENT`
+    pub window: usize,
+}
+
+/// Streaming results carry the id of the request that produced them
 enum LlmResult {
-    StreamChunk(crate::llm::StreamEvent),
-    StreamDone,
+    StreamChunk(u64, crate::llm::StreamEvent),
+    StreamDone(u64),
+    StreamError(u64, String),
     Error(String),
     ModelList(Vec<String>),
+    /// Numbered sources sent as context for the pending answer, and their footer details
+    Sources(u64, Vec<String>, Vec<String>),
+    /// Generated (or fallback) conversation title
+    Title(String),
+    /// How the context of the pending question was assembled
+    Prompt(u64, Box<PromptInfo>),
+    /// Reachability of the chat and embeddings servers
+    Connectivity { chat: bool, embeddings: bool, warn: bool },
+    /// The server is still loading the model: request, retry number and retry limit
+    ModelLoading(u64, u32, u32),
 }
 
 enum SyncResult {
     Success,
+    /// Per-subject changes, sent just before `Success`
+    Changes(crate::scrapper::changes::SyncReport),
     Error(String),
     Log(String),
+    /// Subjects seen for the first time in this sync
+    NewSubjects(Vec<String>),
+    /// The sync waits for the user to apply or keep a subject's churn
+    Churn(crate::sync::ChurnPrompt),
 }
 
 enum LoginResult {
@@ -771,6 +1713,9 @@ enum LoginResult {
 enum ReembedResult {
     Progress(String),
     Complete(usize),
+    Rebuilt(usize),
+    CleanPlan(crate::clean::CleanPlan),
+    Cleaned(crate::clean::CleanOutcome),
     Error(String),
 }
 
@@ -783,11 +1728,10 @@ pub async fn run_app(state: Arc<AppState>) -> anyhow::Result<()> {
     let config = crate::config::Config::load();
     {
         let mut llm = state.llm.lock().unwrap();
-        llm.set_auth(config.llm_provider.base_url(), config.openrouter_api_key.clone());
-        if let Some(model) = &config.openrouter_model {
-            if config.llm_provider == crate::config::LlmProvider::OpenRouter {
-                llm.set_model(model);
-            }
+        llm.set_auth(&config.chat_url(), config.openrouter_api_key.clone());
+        llm.set_headers(config.request_headers(&config.llm_provider));
+        if let Some(model) = config.model_for(&config.llm_provider) {
+            llm.set_model(&model);
         }
     }
 
@@ -795,13 +1739,16 @@ pub async fn run_app(state: Arc<AppState>) -> anyhow::Result<()> {
     let model_name = state.llm.lock().unwrap().model.clone();
     
     let mut app = TuiApp::new(model_name, connected);
+    app.embedder_warning = state.rag.health_warning().map(|w| w.to_string());
     
-    // Fetch context limit from API
-    if let Ok(ctx_len) = state.llm.lock().unwrap().fetch_context_length().await {
-        app.context_limit = ctx_len;
+    // Fetch context limit (and OpenRouter pricing) from API
+    let llm = state.llm.lock().unwrap().clone();
+    if let Ok(info) = llm.fetch_model_info().await {
+        app.context_limit = info.as_ref().and_then(|m| m.context_length).unwrap_or(32768);
+        app.model_pricing = info.and_then(|m| m.pricing).and_then(|p| crate::usage::Pricing::from_model(&p));
     }
     
-    let mut terminal = setup_terminal()?;
+    let mut terminal = setup_terminal().map_err(|e| crate::startup::StartupError::Terminal(e.into()))?;
     
     let tick_rate = Duration::from_millis(80);
     let mut last_tick = Instant::now();
@@ -816,44 +1763,127 @@ pub async fn run_app(state: Arc<AppState>) -> anyhow::Result<()> {
 
         // Check LLM results
         while let Ok(result) = rx_llm.try_recv() {
+            // Study requests stream into the session, never into the chat
+            let result = match route_study_result(&mut app, result) {
+                Some(result) => result,
+                None => continue,
+            };
             match result {
-                LlmResult::StreamChunk(event) => {
+                LlmResult::StreamChunk(request_id, event) => {
+                    if app.active_request == Some(request_id) {
+                        app.model_loading = None;
+                    }
                     match event {
                         crate::llm::StreamEvent::Content(chunk) => {
-                             if let Some(last) = app.messages.last_mut() {
-                                if last.role == "assistant" {
-                                    last.content.push_str(&chunk);
-                                    last.render_cache.inner = None;
-                                }
-                            }
+                            let message = app.request_message(request_id);
+                            message.content.push_str(&chunk);
+                            message.render_cache.inner = None;
                             app.follow_bottom = true;
                         },
                         crate::llm::StreamEvent::Usage(usage) => {
                             app.last_request_tokens = usage.total_tokens;
+                            if app.active_provider == crate::config::LlmProvider::OpenRouter {
+                                app.record_usage(&usage);
+                            }
                         }
                     }
                 }
-                LlmResult::StreamDone => {
-                    app.is_thinking = false;
+                LlmResult::StreamDone(request_id) => {
+                    app.finish_request(request_id);
+                    let mut empty_answer = false;
                     // We no longer strip think tags here so they can be toggled in UI
-                    if let Some(last) = app.messages.last_mut() {
-                         if last.role == "assistant" {
-                             last.content = last.content.trim().to_string();
-                             last.render_cache.inner = None;
-                         }
+                    if let Some(message) = app.messages.iter_mut().find(|m| m.request_id == Some(request_id)) {
+                        message.content = message.content.trim().to_string();
+                        if visible_answer(&message.content).trim().is_empty() {
+                            message.content = EMPTY_ANSWER_MARKER.to_string();
+                            message.citations = None;
+                            message.render_cache.inner = None;
+                            empty_answer = true;
+                        } else if let Some(citations) = message.citations.as_mut() {
+                            let answer = message.message.content.rsplit("</think>").next().unwrap_or("");
+                            citations.resolve(answer);
+                        }
+                        message.render_cache.inner = None;
+                    }
+                    if empty_answer {
+                        let question = app.messages.iter().rev().find(|m| m.role == "user").map(|m| m.content.clone());
+                        let retry = crate::config::Config::load().retry_empty_answers && app.hot_retry_request != Some(request_id);
+                        match question.filter(|_| retry) {
+                            Some(question) => {
+                                // Send it like a typed question, keeping whatever is in the input
+                                let typed = std::mem::replace(&mut app.input, question);
+                                let cursor = app.input_cursor;
+                                app.retry_hotter = true;
+                                handle_chat_input(&mut app, event::KeyEvent::from(KeyCode::Enter), &state, &tx_llm).await;
+                                app.input = typed;
+                                app.input_cursor = cursor;
+                                app.set_status(" Empty answer, asking again… ");
+                            }
+                            None => app.set_status(" The model returned an empty answer │ /retry to ask again "),
+                        }
+                        continue;
+                    }
+                    if notify::likely_away(app.last_key) {
+                        let answer = app.messages.iter().find(|m| m.request_id == Some(request_id)).map(|m| visible_answer(&m.content).to_string()).unwrap_or_default();
+                        notify::send("Answer ready", notify::preview(&answer));
+                    }
+                    record_affinity(&mut app, request_id);
+                    request_chat_title(&mut app, &state, &tx_llm);
+                }
+                LlmResult::Title(title) => {
+                    app.title_pending = false;
+                    // A manual /rename while the request was in flight wins
+                    if app.chat_title.is_none() {
+                        app.chat_title = Some(title);
+                    }
+                }
+                LlmResult::Connectivity { chat, embeddings, warn } => {
+                    app.chat_server_ok = Some(chat);
+                    app.embeddings_server_ok = Some(embeddings);
+                    if warn && chat != embeddings {
+                        let down = if chat { "Embeddings" } else { "Chat" };
+                        app.set_status(format!(" ⚠ {} server unreachable, check chat_base_url / embeddings_base_url in config ", down));
+                    } else if warn && !chat {
+                        app.set_status(" ⚠ Chat and embeddings servers unreachable ");
+                    }
+                }
+                LlmResult::Prompt(request_id, info) => {
+                    let sources = info.scores.iter().map(|(label, score, _)| (label.clone(), *score)).collect();
+                    app.request_message(request_id).sources = sources;
+                    if info.trimmed_turns > 0 || info.left_out > 0 {
+                        app.set_status(format!(
+                            " Trimmed to fit ~{} tokens of the model's context: {} earlier messages, {} sources left out ",
+                            info.window, info.trimmed_turns, info.left_out
+                        ));
+                    }
+                    app.last_prompt = Some(*info);
+                }
+                LlmResult::ModelLoading(request_id, attempt, limit) => {
+                    if app.active_request == Some(request_id) {
+                        app.model_loading = Some((attempt, limit));
                     }
                 }
+                LlmResult::Sources(request_id, sources, details) => {
+                    app.request_message(request_id).citations = Some(crate::rag::citations::CitationMap::new(sources, details));
+                }
+                LlmResult::StreamError(request_id, e) => {
+                    app.finish_request(request_id);
+                    show_request_error(&mut app.messages, request_id, &e);
+                    app.scroll_to_bottom();
+                }
                 LlmResult::Error(e) => {
-                    app.messages.push(ChatMessage { role: "assistant".to_string(), content: format!("Error: {}", e), thinking_collapsed: false, render_cache: crate::llm::RenderCache::default() });
-                    app.is_thinking = false;
+                    app.messages.push(TranscriptMessage::new("assistant", format!("Error: {}", e)));
                     app.scroll_to_bottom();
                 }
                 LlmResult::ModelList(models) => {
                     app.available_models = models;
                     app.models_loading = false;
                     if !app.available_models.is_empty() {
-                        // Find current model in list
-                        let idx = app.available_models.iter().position(|m| m == &app.model_name).unwrap_or(0);
+                        // Prefer the model last used with this provider, then the current one
+                        let remembered = crate::config::Config::load().model_for(&app.active_provider);
+                        let idx = remembered.iter().chain(std::iter::once(&app.model_name))
+                            .find_map(|name| app.available_models.iter().position(|m| m == name))
+                            .unwrap_or(0);
                         app.model_state.select(Some(idx));
                     }
                 }
@@ -865,18 +1895,66 @@ pub async fn run_app(state: Arc<AppState>) -> anyhow::Result<()> {
             match result {
                 SyncResult::Log(msg) => {
                     app.sync_logs.push(msg);
-                    app.scroll_to_bottom();
+                    // The sync may be running in the background while another screen scrolls
+                    if app.mode == AppMode::Sync { app.scroll_to_bottom(); }
+                }
+                SyncResult::NewSubjects(names) => {
+                    app.set_status(format!(" 🆕 New subjects: {} │ manage them in Subjects ", names.join(", ")));
+                }
+                SyncResult::Churn(prompt) => {
+                    app.sync_logs.push(format!("⚠️  {}", prompt.churn));
+                    let files = prompt.churn.replaced.iter().map(|p| ("~", p)).chain(prompt.churn.removed.iter().map(|p| ("-", p)));
+                    for (sign, path) in files.clone().take(CHURN_FILES_SHOWN) {
+                        app.sync_logs.push(format!("    {} {}", sign, path));
+                    }
+                    let more = files.count().saturating_sub(CHURN_FILES_SHOWN);
+                    if more > 0 {
+                        app.sync_logs.push(format!("    … and {} more", more));
+                    }
+                    app.sync_logs.push("  Y: index the new versions (old ones are archived) │ N: keep the indexed versions".to_string());
+                    if app.mode == AppMode::Sync {
+                        app.scroll_to_bottom();
+                    } else {
+                        app.set_status(format!(" ⚠ Sync paused │ open Sync to review the changes to {} ", prompt.churn.subject));
+                    }
+                    app.churn_prompt = Some(prompt);
+                }
+                SyncResult::Changes(report) => {
+                    for decision in &report.churn {
+                        app.sync_logs.push(format!("♻️  {}: {}", decision.subject, decision.describe()));
+                    }
+                    app.sync_logs.push(format!("⏱️  {}", report.timing.describe()));
+                    app.sync_logs.push(format!("📋 {} subjects changed, press C to review", report.subjects.len()));
+                    app.sync_report = Some(report);
                 }
                 SyncResult::Success => {
                     app.sync_logs.push("✓ Sync Complete!".to_string());
+                    let added = state.rag.count_documents() as i64 - app.sync_start_docs as i64;
+                    let changed = app.sync_report.as_ref().map_or(0, |r| r.subjects.len());
+                    notify::send("Sync complete", format!("{:+} docs, {} subjects changed", added, changed));
                     app.sync_running = false;
                     app.sync_complete = true;
                     app.is_connected = state.poliformat.check_connection().await.unwrap_or(false);
+                    if app.rag_stats.is_some() { app.rag_stats = Some(state.rag.get_stats()); }
+                    if app.auto_sync.running {
+                        let changed = app.sync_report.as_ref().map_or(0, |r| r.subjects.len());
+                        app.set_status(format!(" ⟳ Auto-sync done │ {} subjects changed ", changed));
+                    } else if app.mode != AppMode::Sync {
+                        app.set_status(" ✓ Sync complete │ open Sync in the menu for the log ");
+                    }
+                    app.auto_sync.finished(true);
                 }
                 SyncResult::Error(e) => {
                     app.sync_logs.push(format!("✗ Error: {}", e));
+                    notify::send("Sync failed", e.clone());
                     app.sync_running = false;
                     app.sync_complete = true;
+                    let automatic = app.auto_sync.running;
+                    app.auto_sync.finished(false);
+                    match app.auto_sync.delay().filter(|_| automatic) {
+                        Some(delay) => app.set_status(format!(" Auto-sync failed: {} │ next try in {} min ", e, delay.as_secs() / 60)),
+                        None => app.set_status(format!(" Sync failed: {} ", e)),
+                    }
                 }
             }
         }
@@ -890,6 +1968,7 @@ pub async fn run_app(state: Arc<AppState>) -> anyhow::Result<()> {
                     app.login_error = None;
                     app.login_username.clear();
                     app.login_pin.clear();
+                    app.login_pin_visible = false;
                     app.mode = AppMode::Menu;
                     app.set_status(" ✓ Login Successful! ");
                 }
@@ -908,8 +1987,31 @@ pub async fn run_app(state: Arc<AppState>) -> anyhow::Result<()> {
                     app.reembed_progress.clear();
                     app.rag_stats = Some(state.rag.get_stats());
                     app.set_status(format!(" ✓ Recalculated {} embeddings ", count));
+                    notify::send("Re-embed complete", format!("Recalculated {} embeddings", count));
+                }
+                ReembedResult::Rebuilt(count) => {
+                    app.reembed_running = false;
+                    app.reembed_progress.clear();
+                    app.rag_stats = Some(state.rag.get_stats());
+                    app.set_status(format!(" ✓ Rebuilt search graph over {} documents ", count));
+                }
+                ReembedResult::CleanPlan(plan) => {
+                    app.reembed_running = false;
+                    app.reembed_progress.clear();
+                    if plan.candidates.is_empty() {
+                        app.set_status(" Nothing to clean in the scraped data ");
+                    } else {
+                        app.clean_plan = Some(plan);
+                    }
+                }
+                ReembedResult::Cleaned(outcome) => {
+                    app.reembed_running = false;
+                    app.reembed_progress.clear();
+                    app.rag_stats = Some(state.rag.get_stats());
+                    app.set_status(format!(" ✓ {} ", outcome.describe()));
                 }
                 ReembedResult::Error(e) => {
+                    notify::send("Index update failed", e.clone());
                     app.reembed_running = false;
                     app.reembed_progress = format!("Error: {}", e);
                 }
@@ -919,23 +2021,66 @@ pub async fn run_app(state: Arc<AppState>) -> anyhow::Result<()> {
         let timeout = tick_rate.saturating_sub(last_tick.elapsed());
         
         if event::poll(timeout)? {
-            if let Event::Key(key) = event::read()? {
-                if key.kind == KeyEventKind::Press {
-                    match app.mode.clone() {
-                        AppMode::Menu => handle_menu_input(&mut app, key.code, &state, &tx_sync, &tx_llm).await,
-                        AppMode::Chat => handle_chat_input(&mut app, key, &state, &tx_llm).await,
-                        AppMode::RagInfo => handle_rag_info_input(&mut app, key.code, &state, &tx_reembed).await,
-                        AppMode::Login => handle_login_input(&mut app, key.code, &state, &tx_login).await,
-                        AppMode::Sync => handle_sync_input(&mut app, key.code),
-                        AppMode::Settings => handle_settings_input(&mut app, key.code, &state, &tx_llm).await,
+            match event::read()? {
+                Event::Key(key) if key.kind == KeyEventKind::Press => {
+                    app.last_key = Instant::now();
+                    let channels = Channels { state: &state, sync: &tx_sync, llm: &tx_llm, reembed: &tx_reembed };
+                    // The open palette takes every key; otherwise global hotkeys come before the screen's own keys
+                    let palette_open = app.palette.is_some();
+                    let preview_open = app.preview.is_some();
+                    let action = if palette_open {
+                        handle_palette_input(&mut app, key)
+                    } else if preview_open {
+                        preview::handle_input(&mut app.preview, key.code);
+                        None
+                    } else {
+                        app.keymap.lookup(&key, app.typing())
+                    };
+                    if let Some(action) = action {
+                        run_action(&mut app, action, &channels).await;
+                    } else if !palette_open && !preview_open {
+                        match app.mode.clone() {
+                            AppMode::Menu => handle_menu_input(&mut app, key.code, &channels).await,
+                            AppMode::Chat => handle_chat_input(&mut app, key, &state, &tx_llm).await,
+                            AppMode::RagInfo => handle_rag_info_input(&mut app, key.code, &state, &tx_reembed).await,
+                            AppMode::Login => handle_login_input(&mut app, key, &state, &tx_login).await,
+                            AppMode::Sync => handle_sync_input(&mut app, key.code, &channels),
+                            AppMode::SyncChanges => handle_sync_changes_input(&mut app, key.code),
+                            AppMode::Subjects => handle_subjects_input(&mut app, key.code),
+                            AppMode::Study => handle_study_input(&mut app, key, &state, &tx_llm),
+                            AppMode::Settings => handle_settings_input(&mut app, key.code, &state, &tx_llm).await,
+                        }
                     }
                 }
+                Event::Paste(text) => match app.palette.as_mut() {
+                    Some(palette) => palette.query.push_str(text.lines().next().unwrap_or_default()),
+                    None => handle_paste(&mut app, &text),
+                },
+                _ => {}
             }
         }
 
         if last_tick.elapsed() >= tick_rate {
-            if app.is_thinking || app.sync_running || app.models_loading || app.reembed_running { app.advance_throbber(); }
+            let studying = app.study.as_ref().is_some_and(|s| matches!(s.stage, study::Stage::Asking | study::Stage::Judging));
+            if app.is_thinking || app.sync_running || app.models_loading || app.reembed_running || studying { app.advance_throbber(); }
             
+            if let Some(changed) = app.config_watcher.poll() {
+                apply_config_changes(&mut app, &state, &changed);
+            }
+            // A damaged or legacy config found by any load, at startup or since
+            if let Some(recovery) = crate::config::Config::take_recovery() {
+                app.set_status("⚠ Settings file was recovered, see the note");
+                let content = recovery.warnings().iter().map(|w| format!("⚠ {}", w)).collect::<Vec<_>>().join("\n");
+                app.messages.push(TranscriptMessage::new(NOTE_ROLE, content));
+            }
+
+            // Waits for an answer being streamed, so it never competes with the chat
+            if app.auto_sync.due() && app.is_connected && !app.sync_running && !app.reembed_running && !app.is_thinking {
+                tracing::info!("Starting auto-sync");
+                app.auto_sync.started();
+                start_sync(&mut app, &state, &tx_sync, SyncMode::Full, false, false);
+            }
+
             // Auto-clear status message after 3 seconds
             if let Some(time) = app.status_message_time {
                 if time.elapsed() >= Duration::from_secs(3) {
@@ -954,57 +2099,102 @@ pub async fn run_app(state: Arc<AppState>) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Apply settings edited outside the app. Most are read on use; the system prompt, budget,
+/// request headers and the index's retrieval settings are refreshed here, and provider or
+/// model switches wait for `/reload`.
+fn apply_config_changes(app: &mut TuiApp, state: &Arc<AppState>, changed: &[String]) {
+    use config_watch::Apply;
+
+    let config = crate::config::Config::load();
+    let mut applied = Vec::new();
+    let mut restart = Vec::new();
+    for key in changed {
+        match config_watch::classify(key) {
+            Apply::Live => applied.push(key.as_str()),
+            Apply::Restart => restart.push(key.as_str()),
+            Apply::Confirm => app.pending_config_switch = true,
+            Apply::Silent => {}
+        }
+    }
+
+    if changed.iter().any(|k| k == "auto_sync_minutes") {
+        app.auto_sync.set_minutes(config.auto_sync_minutes);
+    }
+    if changed.iter().any(|k| k.starts_with("keybindings")) {
+        app.keymap = actions::Keymap::new(&config.keybindings);
+    }
+    if changed.iter().any(|k| k.starts_with("budget.")) {
+        app.budget_spend = crate::usage::period_spend(&crate::usage::load(), config.budget.period);
+        app.budget = config.budget.clone();
+    }
+    if changed.iter().any(|k| k.starts_with("provider_headers") || k.starts_with("openrouter_referer") || k.starts_with("openrouter_title")) {
+        state.llm.lock().unwrap().set_headers(config.request_headers(&app.active_provider));
+    }
+    if changed.iter().any(|k| ["rag", "subjects"].iter().any(|section| k == section || k.starts_with(&format!("{}.", section)))) {
+        state.rag.reload_settings();
+    }
+
+    let mut parts = Vec::new();
+    if !applied.is_empty() {
+        parts.push(format!("applied {}", applied.join(", ")));
+    }
+    if !restart.is_empty() {
+        parts.push(format!("restart to apply {}", restart.join(", ")));
+    }
+    if app.pending_config_switch {
+        parts.push("provider/model changed, /reload to switch".to_string());
+    }
+    if changed.iter().any(|k| k.starts_with("keybindings")) && !app.keymap.invalid.is_empty() {
+        parts.push(format!("ignored keybindings with unknown {}", app.keymap.invalid.join(", ")));
+    }
+    if !parts.is_empty() {
+        app.set_status(format!(" Config reloaded: {} ", parts.join(" │ ")));
+    }
+}
+
+/// `/reload`: switch to the provider and model the config file names now
+fn apply_config_switch(app: &mut TuiApp, state: &Arc<AppState>) {
+    let config = crate::config::Config::load();
+    app.active_provider = config.llm_provider.clone();
+    app.openrouter_key = config.openrouter_api_key.clone().unwrap_or_default();
+    app.openrouter_model = config.openrouter_model.clone().unwrap_or_default();
+
+    let mut llm = state.llm.lock().unwrap();
+    llm.set_auth(&config.chat_url(), config.openrouter_api_key.clone());
+    llm.set_headers(config.request_headers(&config.llm_provider));
+    if let Some(model) = config.model_for(&config.llm_provider) {
+        llm.set_model(&model);
+        app.model_name = model;
+    }
+    app.pending_config_switch = false;
+    app.set_status(format!(" Switched to the configured model: {} ", app.model_name));
+}
+
 // ============================================================================
 // INPUT HANDLERS
 // ============================================================================
 
-async fn handle_menu_input(app: &mut TuiApp, key: KeyCode, state: &Arc<AppState>, tx_sync: &mpsc::Sender<SyncResult>, tx_llm: &mpsc::Sender<LlmResult>) {
+/// Menu entries in `menu_items` order
+const MENU_ACTIONS: &[Action] = &[
+    Action::Chat,
+    Action::Study,
+    Action::SyncFull,
+    Action::SyncResources,
+    Action::RagInfo,
+    Action::Login,
+    Action::Logout,
+    Action::Subjects,
+    Action::Settings,
+    Action::Quit,
+];
+
+async fn handle_menu_input(app: &mut TuiApp, key: KeyCode, channels: &Channels<'_>) {
     match key {
         KeyCode::Up => app.previous_menu_item(),
         KeyCode::Down => app.next_menu_item(),
         KeyCode::Enter => {
-            if let Some(i) = app.menu_state.selected() {
-                match i {
-                    0 => { app.mode = AppMode::Chat; app.scroll_to_bottom(); },
-                    1 => { // Sync
-                        if !app.is_connected {
-                            app.set_status(" ✗ Not connected! Login first. ");
-                        } else {
-                            app.mode = AppMode::Sync;
-                            app.sync_logs.clear();
-                            app.sync_running = true;
-                            app.sync_complete = false;
-                            app.sync_logs.push("Starting sync...".to_string());
-                            
-                            let tx = tx_sync.clone();
-                            let rag = state.rag.clone();
-                            let poliformat = state.poliformat.clone();
-                            tokio::spawn(async move {
-                                let _ = tx.send(SyncResult::Log("Fetching subjects...".to_string())).await;
-                                match run_sync_with_logging(rag, poliformat, tx.clone()).await {
-                                    Ok(_) => { let _ = tx.send(SyncResult::Success).await; },
-                                    Err(e) => { let _ = tx.send(SyncResult::Error(e.to_string())).await; }
-                                }
-                            });
-                        }
-                    },
-                    2 => { app.rag_stats = Some(state.rag.get_stats()); app.mode = AppMode::RagInfo; },
-                    3 => { app.mode = AppMode::Login; app.login_field = 0; app.login_error = None; },
-                    4 => { // Settings
-                        app.mode = AppMode::Settings;
-                        app.models_loading = true;
-                        let tx = tx_llm.clone();
-                        let llm = state.llm.lock().unwrap().clone();
-                        tokio::spawn(async move {
-                            match llm.fetch_models().await {
-                                Ok(models) => { let _ = tx.send(LlmResult::ModelList(models)).await; },
-                                Err(e) => { let _ = tx.send(LlmResult::Error(e.to_string())).await; }
-                            }
-                        });
-                    },
-                    5 => { app.should_quit = true; },
-                    _ => {}
-                }
+            if let Some(&action) = app.menu_state.selected().and_then(|i| MENU_ACTIONS.get(i)) {
+                run_action(app, action, channels).await;
             }
         },
         KeyCode::Esc => app.should_quit = true,
@@ -1012,221 +2202,694 @@ async fn handle_menu_input(app: &mut TuiApp, key: KeyCode, state: &Arc<AppState>
     }
 }
 
-async fn handle_chat_input(app: &mut TuiApp, key: event::KeyEvent, state: &Arc<AppState>, tx_llm: &mpsc::Sender<LlmResult>) {
+/// What `run_action` needs to start background work
+struct Channels<'a> {
+    state: &'a Arc<AppState>,
+    sync: &'a mpsc::Sender<SyncResult>,
+    llm: &'a mpsc::Sender<LlmResult>,
+    reembed: &'a mpsc::Sender<ReembedResult>,
+}
+
+/// Run an action from the menu, the command palette or a global hotkey
+async fn run_action(app: &mut TuiApp, action: Action, channels: &Channels<'_>) {
+    let state = channels.state;
+    // Leaving Settings saves it, like Esc does
+    if app.mode == AppMode::Settings && !matches!(action, Action::Settings | Action::SettingsField(_) | Action::Palette) {
+        app.settings_input_mode = false;
+        save_settings(app, state, channels.llm).await;
+    }
+
+    match action {
+        Action::Menu => app.mode = AppMode::Menu,
+        Action::Chat => { app.mode = AppMode::Chat; app.scroll_to_bottom(); },
+        Action::SyncLog => { app.mode = AppMode::Sync; app.scroll_to_bottom(); },
+        Action::SyncFull | Action::SyncResources => {
+            let mode = if action == Action::SyncResources { SyncMode::ResourcesOnly } else { SyncMode::Full };
+            if app.sync_running {
+                // Back to the sync that is already running in the background
+                app.mode = AppMode::Sync;
+                app.scroll_to_bottom();
+            } else if !app.is_connected {
+                app.set_status(" ✗ Not connected! Login first. ");
+            } else {
+                app.mode = AppMode::Sync;
+                // A full sync from the menu rebuilds the index from scratch
+                start_sync(app, state, channels.sync, mode, mode == SyncMode::Full, false);
+            }
+        },
+        Action::RagInfo => { app.rag_stats = Some(state.rag.get_stats()); app.mode = AppMode::RagInfo; },
+        Action::Login => { app.mode = AppMode::Login; app.login_field = 0; app.login_error = None; },
+        Action::Logout => { // Log out, then offer the login form for another account
+            if let Err(e) = crate::config::Config::clear_credentials() {
+                app.set_status(format!(" ✗ Failed to clear credentials: {} ", e));
+                return;
+            }
+            state.poliformat.clear_session();
+            app.is_connected = false;
+            app.login_username.clear();
+            app.login_pin.clear();
+            app.login_field = 0;
+            app.login_error = None;
+            app.mode = AppMode::Login;
+            if std::env::var("POLIFORMAT_USER").is_ok() || std::env::var("POLIFORMAT_DNI").is_ok() {
+                app.set_status(" Logged out │ POLIFORMAT_USER is still set in the environment and is used by syncs ");
+            } else {
+                app.set_status(" Logged out │ saved credentials and session cleared ");
+            }
+        },
+        Action::Study => {
+            // Back to a session left for another screen, or a new one
+            if app.study.is_none() {
+                let subjects = crate::subjects::known(&state.rag).into_iter().filter(|s| s.documents > 0).collect();
+                app.study = Some(study::StudySession::new(subjects));
+            }
+            app.mode = AppMode::Study;
+        },
+        Action::Subjects => {
+            app.subjects = crate::subjects::known(&state.rag);
+            app.subjects_state.select(if app.subjects.is_empty() { None } else { Some(0) });
+            app.mode = AppMode::Subjects;
+        },
+        Action::Settings => {
+            if app.mode != AppMode::Settings {
+                open_settings(app, state, channels.llm);
+            }
+        },
+        Action::SettingsField(field) => {
+            if app.mode != AppMode::Settings {
+                open_settings(app, state, channels.llm);
+            }
+            if field == 0 || !app.active_provider.is_local() {
+                app.settings_field = field;
+                app.settings_input_mode = field > 0;
+            } else {
+                app.set_status(format!(" {} models are picked from the list │ Tab switches provider ", app.active_provider.label()));
+            }
+        },
+        Action::Reembed | Action::RebuildGraph | Action::CleanData => {
+            app.rag_stats = Some(state.rag.get_stats());
+            app.mode = AppMode::RagInfo;
+            if app.reembed_running {
+                app.set_status(" The index is already being updated ");
+            } else if action == Action::Reembed {
+                start_reembed(app, state, channels.reembed);
+            } else if action == Action::RebuildGraph {
+                start_rebuild(app, state, channels.reembed);
+            } else {
+                app.clean_plan = None;
+                start_clean(app, state, channels.reembed);
+            }
+        },
+        Action::ToggleThinking | Action::ToggleSources | Action::ClearChat => {
+            app.mode = AppMode::Chat;
+            let c = match action {
+                Action::ToggleThinking => 't',
+                Action::ToggleSources => 'o',
+                _ => 'l',
+            };
+            handle_chat_input(app, event::KeyEvent::new(KeyCode::Char(c), event::KeyModifiers::CONTROL), state, channels.llm).await;
+        },
+        Action::Slash(command) => {
+            app.mode = AppMode::Chat;
+            app.scroll_to_bottom();
+            if !app.input.trim().is_empty() {
+                app.set_status(" Send or clear the message being typed first ");
+            } else if command.ends_with(' ') {
+                // Takes an argument: leave it in the input to complete
+                app.input = command.to_string();
+                app.input_cursor = app.input.len();
+            } else if app.is_thinking {
+                app.set_status(" Wait for the answer to finish ");
+            } else {
+                app.input = command.to_string();
+                app.input_cursor = app.input.len();
+                handle_chat_input(app, event::KeyEvent::from(KeyCode::Enter), state, channels.llm).await;
+            }
+        },
+        Action::Palette => app.palette = Some(actions::Palette::default()),
+        Action::Quit => app.should_quit = true,
+    }
+}
+
+/// Open Settings and load the model list of the current provider
+fn open_settings(app: &mut TuiApp, state: &Arc<AppState>, tx_llm: &mpsc::Sender<LlmResult>) {
+    app.mode = AppMode::Settings;
+    app.models_loading = true;
+    check_connectivity(app.active_provider.clone(), tx_llm.clone(), false);
+    let tx = tx_llm.clone();
+    let llm = state.llm.lock().unwrap().clone();
+    tokio::spawn(async move {
+        match llm.fetch_models().await {
+            Ok(models) => { let _ = tx.send(LlmResult::ModelList(models)).await; },
+            Err(e) => { let _ = tx.send(LlmResult::Error(e.to_string())).await; }
+        }
+    });
+}
+
+/// Keys while the command palette is open: type to filter, ↑/↓ to select, Enter to run the
+/// selected action, Esc (or the palette key again) to close it
+fn handle_palette_input(app: &mut TuiApp, key: event::KeyEvent) -> Option<Action> {
+    let toggle = app.keymap.lookup(&key, true) == Some(Action::Palette);
+    let palette = app.palette.as_mut()?;
+    let count = palette.matches().len();
+    match key.code {
+        KeyCode::Esc => app.palette = None,
+        _ if toggle => app.palette = None,
+        KeyCode::Enter => {
+            let action = palette.matches().get(palette.selected).map(|spec| spec.action);
+            app.palette = None;
+            return action;
+        },
+        KeyCode::Up if count > 0 => palette.selected = if palette.selected == 0 { count - 1 } else { palette.selected - 1 },
+        KeyCode::Down if count > 0 => palette.selected = (palette.selected + 1) % count,
+        KeyCode::Backspace => { palette.query.pop(); palette.selected = 0; },
+        KeyCode::Char(c) if !key.modifiers.intersects(event::KeyModifiers::CONTROL | event::KeyModifiers::ALT) => {
+            palette.query.push(c);
+            palette.selected = 0;
+        },
+        _ => {}
+    }
+    None
+}
+
+async fn handle_chat_input(app: &mut TuiApp, mut key: event::KeyEvent, state: &Arc<AppState>, tx_llm: &mpsc::Sender<LlmResult>) {
+    // A message held back by the budget check: y sends it anyway, any other key returns it to the input
+    if let Some((pending, _)) = app.pending_send.take() {
+        app.input_cursor = pending.len();
+        app.input = pending;
+        if !matches!(key.code, KeyCode::Char('y') | KeyCode::Char('Y')) {
+            app.expand_next = false;
+            app.retry_hotter = false;
+            app.set_status(" Not sent ");
+            return;
+        }
+        app.budget_confirmed = true;
+        key = event::KeyEvent::from(KeyCode::Enter);
+    }
+
+    if app.paste_capture.is_some() {
+        handle_paste_capture(app, key, state).await;
+        return;
+    }
+
+    // Ctrl+R re-asks the last question with more context
+    if key.code == KeyCode::Char('r') && key.modifiers.contains(event::KeyModifiers::CONTROL) && !app.is_thinking {
+        let Some(question) = app.messages.iter().rev().find(|m| m.role == "user").map(|m| m.content.clone()) else {
+            app.set_status(" No question to re-ask ");
+            return;
+        };
+        app.input_cursor = question.len();
+        app.input = question;
+        app.expand_next = true;
+        key = event::KeyEvent::from(KeyCode::Enter);
+    }
+
+    // While a search is open and nothing is typed, n/N navigate matches and Esc closes it
+    if app.search_term.is_some() && app.input.is_empty() {
+        match key.code {
+            KeyCode::Char('n') => { app.jump_to_search_match(true); return; },
+            KeyCode::Char('N') => { app.jump_to_search_match(false); return; },
+            KeyCode::Esc => { app.close_search(); return; },
+            _ => {}
+        }
+    }
+
     match key.code {
+        KeyCode::Esc if app.active_request.is_some() => app.cancel_request(),
         KeyCode::Esc => { app.mode = AppMode::Menu; },
-        KeyCode::Enter => {
-            if !app.input.trim().is_empty() && !app.is_thinking {
-                let user_input = app.input.trim().to_string();
-                app.input.clear();
-                app.input_cursor = 0;
-                
-                if user_input.starts_with("/model") {
-                    let parts: Vec<&str> = user_input.splitn(2, ' ').collect();
-                    if parts.len() > 1 && !parts[1].trim().is_empty() {
-                        let new_model = parts[1].trim().to_string();
-                        state.llm.lock().unwrap().set_model(&new_model);
-                        app.model_name = new_model.clone();
-                        let _ = crate::config::Config::save_model(&new_model);
-                        app.set_status(format!(" Model set: {} ", new_model));
-                    } else {
-                        // Show current model if no name provided
-                        app.set_status(format!(" Current model: {} ", app.model_name));
+        KeyCode::Tab if app.input.starts_with("/types ") => {
+            if let Some(completed) = complete_types(&app.input, &state.rag.document_types()) {
+                app.input_cursor = completed.len();
+                app.input = completed;
+            }
+        },
+        KeyCode::Enter if !app.input.trim().is_empty() && !app.is_thinking => {
+            let user_input = app.input.trim().to_string();
+            app.input.clear();
+            app.input_cursor = 0;
+
+            // Ask the last question again, e.g. after an empty answer
+            let user_input = if user_input == "/retry" {
+                match app.messages.iter().rev().find(|m| m.role == "user") {
+                    Some(question) => question.content.clone(),
+                    None => {
+                        app.set_status(" No question to retry ");
+                        return;
                     }
-                    return;
                 }
+            } else {
+                user_input
+            };
+            
+            if user_input.starts_with("/model") {
+                let parts: Vec<&str> = user_input.splitn(2, ' ').collect();
+                if parts.len() > 1 && !parts[1].trim().is_empty() {
+                    let new_model = parts[1].trim().to_string();
+                    state.llm.lock().unwrap().set_model(&new_model);
+                    app.model_name = new_model.clone();
+                    let _ = crate::config::Config::save_model(&new_model);
+                    app.set_status(format!(" Model set: {} ", new_model));
+                } else {
+                    // Show current model if no name provided
+                    app.set_status(format!(" Current model: {} ", app.model_name));
+                }
+                return;
+            }
 
-                app.messages.push(ChatMessage { role: "user".to_string(), content: user_input.clone(), thinking_collapsed: false, render_cache: crate::llm::RenderCache::default() });
-                // Placeholder for assistant
-                app.messages.push(ChatMessage { role: "assistant".to_string(), content: String::new(), thinking_collapsed: false, render_cache: crate::llm::RenderCache::default() });
-                app.scroll_to_bottom();
-                app.is_thinking = true;
-                app.status_message = None;
-                
-                let tx = tx_llm.clone();
-                let rag = state.rag.clone();
-                let llm = state.llm.lock().unwrap().clone();
-                let messages = app.messages.clone();
-                
-                tokio::spawn(async move {
-                    // 1. Detect explicit file mentions (e.g. .pdf or filename stems)
-                    let mut extra_context = String::new();
-                    let words: Vec<&str> = user_input.split_whitespace().collect();
-                    
-                    let all_filenames = rag.get_all_filenames().unwrap_or_default();
-                    let mut mentioned_targets = Vec::new();
-
-                    for word in words {
-                        let word_clean = word.trim_matches(|c: char| !c.is_alphanumeric() && c != '.' && c != '_' && c != '-');
-                        if word_clean.len() < 4 { continue; } // Skip short common words
-                        
-                        let word_lower = word_clean.to_lowercase();
-                        
-                        // Check for direct match or stem match
-                        for filename in &all_filenames {
-                            let filename_lower = filename.to_lowercase();
-                            
-                            // Extract just the basename (last component of path)
-                            let basename = filename_lower.rsplit('/').next().unwrap_or(&filename_lower);
-                            
-                            // Get stem without .pdf extension
-                            let stem = if let Some(pos) = basename.find(".pdf") {
-                                &basename[..pos]
-                            } else {
-                                basename
-                            };
+            if user_input == "/reload" {
+                if app.pending_config_switch {
+                    apply_config_switch(app, state);
+                } else {
+                    app.set_status(" No pending provider or model change in the config file ");
+                }
+                return;
+            }
 
-                            // Match against basename, stem, or if query contains stem
-                            if word_lower == basename || word_lower == stem || stem.contains(&word_lower) || word_lower.contains(stem) {
-                                mentioned_targets.push(filename.clone());
-                            }
-                        }
-                    }
+            if user_input.starts_with("/find") {
+                let term = user_input.trim_start_matches("/find").trim();
+                if term.is_empty() {
+                    app.close_search();
+                    app.set_status(" Usage: /find <text> ");
+                } else {
+                    app.search_term = Some(term.to_string());
+                    app.search_index = 0;
+                    app.search_jump_pending = true;
+                }
+                return;
+            }
 
-                    // Deduplicate
-                    mentioned_targets.sort();
-                    mentioned_targets.dedup();
-
-                    for target_file in mentioned_targets {
-                        if let Ok(chunks) = rag.get_file_chunks(&target_file) {
-                            if !chunks.is_empty() {
-                                tracing::info!("Explicitly adding all {} chunks of '{}' to context (cleaned)", chunks.len(), target_file);
-                                extra_context.push_str(&format!("\n--- START OF FILE: {} ---\n", target_file));
-                                for (_id, content) in chunks {
-                                    // Extract content after the double newline (where our header ends)
-                                    if let Some(pos) = content.find("\n\n") {
-                                        extra_context.push_str(&content[pos + 2..]);
-                                    } else {
-                                        extra_context.push_str(&content);
-                                    }
-                                }
-                                extra_context.push_str(&format!("\n--- END OF FILE: {} ---\n", target_file));
-                            }
+            if user_input.starts_with("/ragmode") {
+                let profile = match user_input.trim_start_matches("/ragmode").trim() {
+                    "compact" => Some(crate::config::ContextProfile::Compact),
+                    "detailed" => Some(crate::config::ContextProfile::Detailed),
+                    _ => None,
+                };
+                let mut config = crate::config::Config::load();
+                match profile {
+                    Some(profile) => {
+                        config.rag.context_profile = profile;
+                        match config.save() {
+                            Ok(_) => app.set_status(format!(" RAG mode: {} ", profile.label())),
+                            Err(e) => app.set_status(format!(" Failed to save config: {} ", e)),
                         }
                     }
+                    None => app.set_status(format!(" RAG mode: {} │ /ragmode compact|detailed ", config.rag.context_profile.label())),
+                }
+                return;
+            }
+
+            if user_input == "/topk" || user_input.starts_with("/topk ") {
+                let rag_config = crate::config::Config::load().rag;
+                let arg = user_input.trim_start_matches("/topk").trim();
+                if arg.is_empty() {
+                    app.set_status(format!(" Retrieval: {} │ /topk <n>, 0 for no limit ", retrieval_limits(rag_config.top_k(), rag_config.relevance.floor)));
+                    return;
+                }
+                match arg.parse::<usize>() {
+                    Ok(top_k) => match crate::config::Config::save_retrieval_limits(top_k, rag_config.relevance.floor) {
+                        Ok(()) => app.set_status(format!(" Retrieval: {} ", retrieval_limits(top_k, rag_config.relevance.floor))),
+                        Err(e) => app.set_status(format!(" Failed to save config: {} ", e)),
+                    },
+                    Err(_) => app.set_status(" Usage: /topk <n>, a whole number (0 for no limit) "),
+                }
+                return;
+            }
 
-                    // 2. Regular RAG search - find relevant documents
-                    let snippets = rag.search_snippets(&user_input, "user", 20).await.unwrap_or_default();
-                    
-                    tracing::info!("RAG search returned {} snippets for query: '{}'", snippets.len(), &user_input);
-                    for (i, (source, _snippet, score)) in snippets.iter().enumerate() {
-                        tracing::debug!("Snippet {}: source='{}', score={:.3}", i, source, score);
+            if user_input == "/threshold" || user_input.starts_with("/threshold ") {
+                let rag_config = crate::config::Config::load().rag;
+                let arg = user_input.trim_start_matches("/threshold").trim();
+                if arg.is_empty() {
+                    app.set_status(format!(" Retrieval: {} │ /threshold <0-1>, 0 for no cutoff ", retrieval_limits(rag_config.top_k(), rag_config.relevance.floor)));
+                    return;
+                }
+                match arg.replace(',', ".").parse::<f32>() {
+                    Ok(min_score) if (0.0..=1.0).contains(&min_score) => match crate::config::Config::save_retrieval_limits(rag_config.top_k(), min_score) {
+                        Ok(()) => app.set_status(format!(" Retrieval: {} ", retrieval_limits(rag_config.top_k(), min_score))),
+                        Err(e) => app.set_status(format!(" Failed to save config: {} ", e)),
+                    },
+                    _ => app.set_status(" Usage: /threshold <score>, between 0 and 1 (0 for no cutoff) "),
+                }
+                return;
+            }
+
+            if user_input == "/mode" || user_input.starts_with("/mode ") {
+                let mut config = crate::config::Config::load();
+                let mode = match user_input.trim_start_matches("/mode").trim() {
+                    "summary" | "summary-first" => crate::config::AnswerMode::SummaryFirst,
+                    "standard" => crate::config::AnswerMode::Standard,
+                    // No argument toggles
+                    "" => match config.rag.answer_mode {
+                        crate::config::AnswerMode::Standard => crate::config::AnswerMode::SummaryFirst,
+                        crate::config::AnswerMode::SummaryFirst => crate::config::AnswerMode::Standard,
+                    },
+                    _ => {
+                        app.set_status(format!(" Answer mode: {} │ /mode summary|standard ", config.rag.answer_mode.label()));
+                        return;
                     }
-                    
-                    // Collect unique source files from search results (excluding already mentioned ones)
-                    let mut rag_source_files: Vec<String> = Vec::new();
-                    for (source, _snippet, _score) in &snippets {
-                        // Check if this looks like a filename (contains . or /)
-                        if (source.contains('.') || source.contains('/')) && !rag_source_files.contains(source) {
-                            rag_source_files.push(source.clone());
+                };
+                config.rag.answer_mode = mode;
+                match config.save() {
+                    Ok(_) => app.set_status(format!(" Answer mode: {} ", mode.label())),
+                    Err(e) => app.set_status(format!(" Failed to save config: {} ", e)),
+                }
+                return;
+            }
+
+            if user_input.starts_with("/sources") {
+                match &app.last_prompt {
+                    Some(info) => {
+                        let sources = if info.sources.is_empty() {
+                            "none".to_string()
+                        } else {
+                            info.sources.iter()
+                                .map(|source| match info.indexed_at.get(source) {
+                                    Some(at) => format!("{} (indexed {})", source, crate::rag::format_age(*at)),
+                                    None => source.clone(),
+                                })
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                        };
+                        let mut status = format!(
+                            " Last prompt: {} │ {} snippets, {} full files │ {} chars │ {} ",
+                            info.profile.label(), info.snippets, info.full_files, info.chars, sources
+                        );
+                        if !info.condensed.is_empty() {
+                            status.push_str(&format!("│ condensed: {} ", info.condensed.join(", ")));
                         }
-                    }
-                    rag_source_files.truncate(3); // Limit to top 3 most relevant files
-                    
-                    tracing::info!("Found {} unique source files from RAG search", rag_source_files.len());
-                    
-                    // Context size limit: ~200k chars ≈ 50k tokens to stay safely under most LLM limits
-                    const MAX_CONTEXT_CHARS: usize = 200_000;
-                    let mut current_context_size = extra_context.len();
-                    
-                    // Fetch complete content for each source file found via RAG (with size limit)
-                    let mut rag_full_context = String::new();
-                    let mut included_files: Vec<String> = Vec::new();
-                    
-                    for source_file in &rag_source_files {
-                        if current_context_size >= MAX_CONTEXT_CHARS {
-                            tracing::info!("Context limit reached ({} chars), stopping full file inclusion", current_context_size);
-                            break;
+                        if info.low_confidence {
+                            status.push_str("│ weak match only ");
                         }
-                        
-                        if let Ok(chunks) = rag.get_file_chunks(source_file) {
-                            if !chunks.is_empty() {
-                                // Calculate approximate size of this file
-                                let file_content_size: usize = chunks.iter().map(|(_, c)| c.len()).sum();
-                                
-                                // Check if adding this file would exceed the limit
-                                if current_context_size + file_content_size > MAX_CONTEXT_CHARS && !rag_full_context.is_empty() {
-                                    tracing::info!("Skipping '{}' ({} chars) - would exceed context limit", source_file, file_content_size);
-                                    continue;
-                                }
-                                
-                                tracing::info!("Including FULL content of '{}' ({} chunks, ~{} chars) from RAG search", source_file, chunks.len(), file_content_size);
-                                rag_full_context.push_str(&format!("\n--- START OF FILE: {} ---\n", source_file));
-                                for (_id, content) in chunks {
-                                    // Extract content after the header (double newline)
-                                    if let Some(pos) = content.find("\n\n") {
-                                        rag_full_context.push_str(&content[pos + 2..]);
-                                    } else {
-                                        rag_full_context.push_str(&content);
-                                    }
-                                }
-                                rag_full_context.push_str(&format!("\n--- END OF FILE: {} ---\n", source_file));
-                                current_context_size += file_content_size;
-                                included_files.push(source_file.clone());
-                            }
+                        if !info.scores.is_empty() {
+                            let scores: Vec<String> = info.scores.iter()
+                                .map(|(label, score, boost)| if *boost > 0.0 {
+                                    format!("{} {:.2} (+{:.2} affinity)", label, score, boost)
+                                } else {
+                                    format!("{} {:.2}", label, score)
+                                })
+                                .collect();
+                            status.push_str(&format!("│ scores: {} │ /view <n> shows snippet n in its file ", scores.join(", ")));
                         }
+                        app.set_status(status);
                     }
-                    
-                    let mut context_str = String::new();
-                    if !extra_context.is_empty() {
-                        context_str.push_str("You have been provided with the COMPLETE content of the requested document(s) below. Use this information as your primary source.\n");
-                        context_str.push_str(&extra_context);
-                        if !rag_full_context.is_empty() {
-                            context_str.push_str("\nAdditional relevant documents:\n");
-                            context_str.push_str(&rag_full_context);
-                        }
-                    } else if !rag_full_context.is_empty() {
-                        context_str.push_str("Relevant documents from your files (COMPLETE content):\n");
-                        context_str.push_str(&rag_full_context);
-                    } else if !snippets.is_empty() {
-                        // Fallback: if no file chunks available, use snippets
-                        context_str.push_str("Relevant context from your documents:\n");
-                        for (source, snippet, _score) in snippets {
-                            context_str.push_str(&format!("\n[{}]:\n{}\n", source, snippet));
+                    None => app.set_status(" No prompt sent yet "),
+                }
+                return;
+            }
+
+            if user_input == "/view" || user_input.starts_with("/view ") {
+                let arg = user_input.trim_start_matches("/view").trim();
+                let Some(info) = &app.last_prompt else {
+                    app.set_status(" No prompt sent yet ");
+                    return;
+                };
+                let count = info.snippet_ids.len();
+                let n = if arg.is_empty() { Some(1) } else { arg.parse::<usize>().ok() };
+                let Some(n) = n.filter(|n| (1..=count).contains(n)) else {
+                    app.set_status(format!(" /view <n>: snippet 1-{} of the last prompt, as /sources lists them ", count));
+                    return;
+                };
+                let id = info.snippet_ids[n - 1].clone();
+                let label = info.scores[n - 1].0.clone();
+                match state.rag.document_text(&id) {
+                    Ok(document) => {
+                        let chunk = document.chunks.iter().position(|(chunk, _)| *chunk == id).unwrap_or(0);
+                        let title = format!("{} │ chunk {}/{}", label, chunk + 1, document.chunks.len());
+                        app.preview = preview::DocumentPreview::new(title, document, &id);
+                    }
+                    Err(e) => app.set_status(format!(" {} ", e)),
+                }
+                return;
+            }
+
+            if user_input == "/types" || user_input.starts_with("/types ") {
+                let available = state.rag.document_types();
+                let requested: Vec<String> = user_input.trim_start_matches("/types")
+                    .split(',')
+                    .map(|t| t.trim().to_lowercase())
+                    .filter(|t| !t.is_empty())
+                    .collect();
+                if requested.is_empty() {
+                    let current = app.type_filter.as_ref().map_or("all".to_string(), |types| types.join(","));
+                    app.set_status(format!(" Types: {} │ available: {} │ /types <a,b>|all ", current, available.join(",")));
+                } else if requested == ["all"] {
+                    app.type_filter = None;
+                    app.set_status(" Retrieving all document types ");
+                } else if let Some(unknown) = requested.iter().find(|t| !available.contains(t)) {
+                    app.set_status(format!(" Unknown type '{}' │ available: {} ", unknown, available.join(",")));
+                } else {
+                    app.set_status(format!(" Retrieving only: {} ", requested.join(",")));
+                    app.type_filter = Some(requested);
+                }
+                return;
+            }
+
+            if user_input == "/scope" || user_input.starts_with("/scope ") {
+                let tags = state.rag.all_tags();
+                let listed = if tags.is_empty() {
+                    "none yet, add with /tag <name> <file>".to_string()
+                } else {
+                    tags.iter().map(|(tag, count)| format!("{} ({})", tag, count)).collect::<Vec<_>>().join(", ")
+                };
+                let arg = user_input.trim_start_matches("/scope").trim();
+                if arg.is_empty() {
+                    let current = app.tag_scope.as_ref().map_or("all".to_string(), |tag| format!("tag:{}", tag));
+                    app.set_status(format!(" Scope: {} │ tags: {} │ /scope tag:<name>|all ", current, listed));
+                } else if arg == "all" {
+                    app.tag_scope = None;
+                    app.set_status(" Retrieving from all documents ");
+                } else if let Some(tag) = arg.strip_prefix("tag:").map(|t| t.trim().to_lowercase()) {
+                    if tags.iter().any(|(t, _)| *t == tag) {
+                        app.set_status(format!(" Retrieving only documents tagged '{}' ", tag));
+                        app.tag_scope = Some(tag);
+                    } else {
+                        app.set_status(format!(" No documents tagged '{}' │ tags: {} ", tag, listed));
+                    }
+                } else {
+                    app.set_status(" Usage: /scope tag:<name> │ /scope all ");
+                }
+                return;
+            }
+
+            if user_input == "/tag" || user_input.starts_with("/tag ") {
+                let arg = user_input.trim_start_matches("/tag").trim();
+                let Some((tag, name)) = arg.split_once(char::is_whitespace) else {
+                    app.set_status(" Usage: /tag <name> <file or document id> │ again to remove it ");
+                    return;
+                };
+                let name = name.trim();
+                match state.rag.resolve_document(name) {
+                    None => app.set_status(format!(" No document '{}' in the index (or the name is ambiguous) ", name)),
+                    Some(id) => {
+                        let tag = tag.to_lowercase();
+                        let tagged = state.rag.document_tags(&id).contains(&tag);
+                        let result = if tagged { state.rag.remove_tag(&id, &tag) } else { state.rag.add_tag(&id, &tag) };
+                        match result {
+                            Ok(_) if tagged => app.set_status(format!(" Removed tag '{}' from {} ", tag, name)),
+                            Ok(_) => app.set_status(format!(" Tagged {} as '{}' │ /scope tag:{} to chat about it ", name, tag, tag)),
+                            Err(e) => app.set_status(format!(" ✗ Could not tag {}: {} ", name, e)),
                         }
                     }
-                    let full = if !context_str.is_empty() { 
-                        format!("{}\n\n---\nUser question: {}", context_str, user_input) 
-                    } else { 
-                        user_input 
-                    };
-                    
-                    tracing::info!("Final prompt length: {} chars, has context: {}", full.len(), !context_str.is_empty());
-                    
-                    let mut mk = messages;
-                    // Remove the empty assistant placeholder we added in UI thread
-                    mk.pop();
-                    
-                    if let Some(l) = mk.last_mut() { 
-                        tracing::debug!("Setting last message content (role: {})", l.role);
-                        l.content = full.clone();
+                }
+                return;
+            }
+
+            if user_input == "/subject" || user_input.starts_with("/subject ") {
+                match user_input.trim_start_matches("/subject").trim() {
+                    "clear" => {
+                        app.affinity.clear();
+                        app.set_status(" Subject affinity cleared │ the next question searches all subjects evenly ");
                     }
-                    
-                    tracing::debug!("Sending {} messages to LLM", mk.len());
-                    for (i, m) in mk.iter().enumerate() {
-                        tracing::debug!("  Msg {}: role='{}', content_len={}", i, m.role, m.content.len());
+                    "" if app.affinity.is_empty() => app.set_status(" No subject affinity yet │ it builds up from the sources of your answers "),
+                    "" => {
+                        let names: std::collections::HashMap<String, String> = state.rag.subject_names().into_iter().collect();
+                        let following: Vec<String> = app.affinity.weights().into_iter()
+                            .map(|(id, weight)| format!("{} {:.0}%", names.get(id).map_or(id, |n| n.as_str()), weight * 100.0))
+                            .collect();
+                        app.set_status(format!(" Leaning towards: {} │ /subject clear ", following.join(", ")));
                     }
-                    
-                    match llm.chat_stream(&mk).await {
-                         Ok(mut stream) => {
-                            while let Some(chunk_res) = stream.next().await {
-                                match chunk_res {
-                                    Ok(event) => {
-                                        let _ = tx.send(LlmResult::StreamChunk(event)).await;
-                                    },
-                                    Err(e) => {
-                                         let _ = tx.send(LlmResult::Error(e.to_string())).await;
-                                    }
-                                }
-                            }
-                            let _ = tx.send(LlmResult::StreamDone).await;
-                        },
-                        Err(e) => {
-                            let _ = tx.send(LlmResult::Error(e.to_string())).await;
-                        }
+                    _ => app.set_status(" Usage: /subject │ /subject clear "),
+                }
+                return;
+            }
+
+            if user_input == "/paste" || user_input.starts_with("/paste ") {
+                match user_input.trim_start_matches("/paste").trim() {
+                    "" => {
+                        app.paste_capture = Some(String::new());
+                        app.set_status(" Paste or type the text │ it is searched with your documents until /paste clear ");
+                    }
+                    "clear" => {
+                        let cleared = state.rag.clear_pasted();
+                        app.pasted_texts = 0;
+                        app.set_status(format!(" Dropped {} pasted text(s) ", cleared));
                     }
-                });
+                    _ => app.set_status(" Usage: /paste │ /paste clear "),
+                }
+                return;
             }
+
+            if user_input == "/lookup" || user_input.starts_with("/lookup ") {
+                let query = user_input.trim_start_matches("/lookup").trim();
+                if query.is_empty() {
+                    app.set_status(" Usage: /lookup <question> │ quotes matching sentences, no AI ");
+                    return;
+                }
+                let scope = crate::rag::Scope { types: app.type_filter.clone(), tag: app.tag_scope.clone(), ..Default::default() };
+                match state.rag.lookup(query, "user", LOOKUP_EXCERPTS, &scope).await {
+                    Ok(excerpts) => {
+                        let names: std::collections::HashMap<String, String> = state.rag.subject_names().into_iter().collect();
+                        app.set_status(format!(" {} excerpt(s) │ quoted from your documents, not an AI answer ", excerpts.len()));
+                        app.messages.push(TranscriptMessage::new(NOTE_ROLE, lookup_note(query, &excerpts, &names)));
+                        app.follow_bottom = true;
+                    }
+                    Err(e) => app.set_status(format!(" ✗ Lookup failed: {} ", e)),
+                }
+                return;
+            }
+
+            if user_input == "/cite" || user_input.starts_with("/cite ") {
+                let Some(format) = citation::CiteFormat::parse(user_input.trim_start_matches("/cite")) else {
+                    app.set_status(" Usage: /cite │ /cite bibtex ");
+                    return;
+                };
+                let question = app.messages.iter().rev().find(|m| m.role == "user").map(|m| m.content.clone());
+                let (Some(info), Some(question)) = (&app.last_prompt, question) else {
+                    app.set_status(" No answer to cite yet ");
+                    return;
+                };
+                let names: std::collections::HashMap<String, String> = state.rag.subject_names().into_iter().collect();
+                let block = citation::Citation::new(info, &question, &names).render(format);
+                match copy_to_clipboard(&block) {
+                    Ok(()) => app.set_status(" Citation copied to the clipboard │ also added to the chat, not sent to the model "),
+                    Err(e) => app.set_status(format!(" Could not copy the citation: {} │ it is in the chat ", e)),
+                }
+                app.messages.push(TranscriptMessage::new(NOTE_ROLE, block));
+                app.follow_bottom = true;
+                return;
+            }
+
+            if user_input.starts_with("/rename") {
+                let title = sanitize_title(user_input.trim_start_matches("/rename"));
+                if title.is_empty() {
+                    let current = app.chat_title.clone().unwrap_or_else(|| "(untitled)".to_string());
+                    app.set_status(format!(" Chat title: {} │ /rename <title> ", current));
+                } else {
+                    app.set_status(format!(" Chat renamed: {} ", title));
+                    app.chat_title = Some(title);
+                }
+                return;
+            }
+
+            if !std::mem::take(&mut app.budget_confirmed) && app.hold_over_budget(&user_input) {
+                return;
+            }
+            let expanded = std::mem::take(&mut app.expand_next);
+            // Snapshot of the earlier turns: later changes to the transcript don't reach this request
+            let history = request_history(&app.messages);
+
+            app.messages.push(TranscriptMessage { expanded_context: expanded, ..TranscriptMessage::new("user", user_input.clone()) });
+            // Placeholder for assistant, chunks of this request are routed to it by id
+            let request_id = app.next_request_id;
+            app.next_request_id += 1;
+            let hotter = std::mem::take(&mut app.retry_hotter);
+            if hotter {
+                app.hot_retry_request = Some(request_id);
+            }
+            app.messages.push(TranscriptMessage { request_id: Some(request_id), ..TranscriptMessage::new("assistant", String::new()) });
+            app.scroll_to_bottom();
+            app.is_thinking = true;
+            app.active_request = Some(request_id);
+            app.status_message = None;
+            
+            let tx = tx_llm.clone();
+            let rag = state.rag.clone();
+            let mut llm = state.llm.lock().unwrap().clone();
+            if hotter {
+                llm.set_temperature(crate::llm::DEFAULT_TEMPERATURE + EMPTY_RETRY_TEMPERATURE_BOOST);
+            }
+            let rag_config = crate::config::Config::load().rag;
+            let summary_first = rag_config.answer_mode == crate::config::AnswerMode::SummaryFirst;
+            // Summary-first cites by number so the markers map to the retrieved sources
+            let numbered_citations = rag_config.numbered_citations || summary_first;
+            let context_profile = rag_config.context_profile;
+            let top_k = rag_config.top_k();
+            let adaptive_k = rag_config.adaptive_k;
+            let freshness_hints = rag_config.freshness_hints;
+            let relevance = rag_config.relevance;
+            let boosts = app.affinity.boosts(rag_config.affinity);
+            let remote = crate::config::Config::load().llm_provider == crate::config::LlmProvider::OpenRouter;
+            let condense = rag_config.condense_long_sources && (rag_config.condense_remote || !remote);
+            let rerank = rag_config.rerank && (rag_config.rerank_remote || !remote);
+            let loading_retries = model_loading_retries();
+            let cancel = Arc::new(AtomicBool::new(false));
+            app.request_cancel = Some(cancel.clone());
+            let scope = crate::rag::Scope { types: app.type_filter.clone(), tag: app.tag_scope.clone(), ..Default::default() };
+            let provider = app.active_provider.clone();
+            let window = app.context_limit * REQUEST_WINDOW_PERCENT / 100;
+            
+            let task = tokio::spawn(async move {
+                // Summary-first numbers the sources even when numbered citations are off
+                let system_prompt = if numbered_citations { NUMBERED_CITATION_PROMPT } else { SYSTEM_PROMPT };
+                // The context gets what the system prompt, question and earlier turns leave of the
+                // window, but at least half of it: the oldest turns are dropped first
+                let fixed = [system_prompt, user_input.as_str()].into_iter()
+                    .chain(history.iter().map(|m| m.content.as_str()))
+                    .map(crate::llm::estimate_tokens)
+                    .sum::<usize>();
+                let context_tokens = window.saturating_sub(fixed).max(window / 2);
+                let built = crate::rag::context::build_prompt(&rag, &user_input, PromptOptions {
+                    profile: context_profile,
+                    numbered_citations,
+                    summary_first,
+                    scope,
+                    top_k,
+                    adaptive_k,
+                    expanded,
+                    freshness_hints,
+                    relevance,
+                    boosts,
+                    max_chars: context_tokens * crate::rag::embeddings::CHARS_PER_TOKEN,
+                }, condense.then_some(&llm), rerank.then_some(&llm)).await;
+                let full = built.text;
+                
+                tracing::info!("Final prompt length: {} chars, has context: {}", full.len(), built.has_context);
+
+                let mut mk = api_messages(system_prompt, &history, &full);
+                let trimmed_turns = crate::llm::trim_to_fit(&mut mk, window);
+
+                let _ = tx.send(LlmResult::Prompt(request_id, Box::new(PromptInfo {
+                    profile: context_profile,
+                    snippets: built.snippets,
+                    full_files: built.full_files.len(),
+                    condensed: built.condensed,
+                    sources: built.sources,
+                    indexed_at: built.indexed_at,
+                    low_confidence: built.low_confidence,
+                    chars: full.len(),
+                    source_subjects: built.source_subjects,
+                    scores: built.scores,
+                    snippet_ids: built.snippet_ids,
+                    documents: built.documents,
+                    model: llm.model.clone(),
+                    provider,
+                    date: crate::usage::today(),
+                    trimmed_turns,
+                    left_out: built.left_out,
+                    window,
+                }))).await;
+
+                if !built.numbered_sources.is_empty() {
+                    let _ = tx.send(LlmResult::Sources(request_id, built.numbered_sources, built.numbered_details)).await;
+                }
+                
+                tracing::debug!("Sending {} messages to LLM", mk.len());
+                for (i, m) in mk.iter().enumerate() {
+                    tracing::debug!("  Msg {}: role='{}', content_len={}", i, m.role, m.content.len());
+                }
+                
+                stream_request(&llm, &mk, request_id, &tx, &cancel, loading_retries).await;
+            });
+            app.request_task = Some(task.abort_handle());
         },
         KeyCode::Char(c) => { 
             if key.modifiers.contains(event::KeyModifiers::CONTROL) && c == 't' {
@@ -1240,9 +2903,32 @@ async fn handle_chat_input(app: &mut TuiApp, key: event::KeyEvent, state: &Arc<A
                          app.status_message_time = Some(Instant::now());
                      }
                  }
+            } else if key.modifiers.contains(event::KeyModifiers::CONTROL) && c == 'o' {
+                // Toggle the retrieved sources of the answer in view
+                let Some(pos) = app.answer_in_view() else {
+                    app.set_status(" No answer to show sources for ");
+                    return;
+                };
+                let answer = &mut app.messages[pos];
+                if answer.sources.is_empty() {
+                    app.set_status(" No sources were retrieved for this answer ");
+                    return;
+                }
+                answer.sources_expanded = !answer.sources_expanded;
+                answer.render_cache.inner = None;
+                let msg = format!(" Retrieved sources: {} ", if answer.sources_expanded { "SHOWN" } else { "HIDDEN" });
+                app.set_status(msg);
             } else if key.modifiers.contains(event::KeyModifiers::CONTROL) && c == 'l' {
-                // Clear chat history (keep only system message)
-                app.messages.retain(|m| m.role == "system");
+                if app.active_request.is_some() {
+                    app.set_status(" Wait for the answer to finish before clearing the chat ");
+                    return;
+                }
+                app.messages.clear();
+                app.chat_title = None;
+                app.affinity.clear();
+                state.rag.clear_pasted();
+                app.pasted_texts = 0;
+                app.close_search();
                 app.scroll_offset = 0;
                 app.follow_bottom = true;
                 app.set_status(" Chat history cleared ");
@@ -1251,31 +2937,27 @@ async fn handle_chat_input(app: &mut TuiApp, key: event::KeyEvent, state: &Arc<A
                 app.input_cursor += c.len_utf8(); 
             } 
         },
-        KeyCode::Backspace => { 
-            if !app.is_thinking && app.input_cursor > 0 { 
-                // Find char boundary before cursor
-                if let Some(prev_char_idx) = app.input[..app.input_cursor].char_indices().next_back().map(|(i, _)| i) {
-                     app.input.remove(prev_char_idx);
-                     app.input_cursor = prev_char_idx;
-                }
-            } 
+        KeyCode::Backspace if !app.is_thinking && app.input_cursor > 0 => {
+            // Find char boundary before cursor
+            if let Some(prev_char_idx) = app.input[..app.input_cursor].char_indices().next_back().map(|(i, _)| i) {
+                 app.input.remove(prev_char_idx);
+                 app.input_cursor = prev_char_idx;
+            }
         },
-        KeyCode::Left => { 
-            if app.input_cursor > 0 {
-                if let Some((prev_idx, _)) = app.input[..app.input_cursor].char_indices().next_back() {
-                    app.input_cursor = prev_idx;
-                }
+        KeyCode::Left if app.input_cursor > 0 => {
+            if let Some((prev_idx, _)) = app.input[..app.input_cursor].char_indices().next_back() {
+                app.input_cursor = prev_idx;
             }
         },
-        KeyCode::Right => { 
-            if app.input_cursor < app.input.len() { 
-                 if let Some((next_idx, _)) = app.input[app.input_cursor..].char_indices().nth(1) {
-                     app.input_cursor += next_idx;
-                 } else {
-                     app.input_cursor = app.input.len();
-                 }
-            } 
+        KeyCode::Right if app.input_cursor < app.input.len() => {
+             if let Some((next_idx, _)) = app.input[app.input_cursor..].char_indices().nth(1) {
+                 app.input_cursor += next_idx;
+             } else {
+                 app.input_cursor = app.input.len();
+             }
         },
+        KeyCode::Up if key.modifiers.contains(event::KeyModifiers::CONTROL) => { app.jump_to_previous_answer(); },
+        KeyCode::Down if key.modifiers.contains(event::KeyModifiers::CONTROL) => { app.jump_to_next_answer(); },
         KeyCode::Up => { app.scroll_up(3); },
         KeyCode::Down => { app.scroll_down(3); },
         KeyCode::PageUp => { app.scroll_up(10); },
@@ -1286,118 +2968,268 @@ async fn handle_chat_input(app: &mut TuiApp, key: event::KeyEvent, state: &Arc<A
     }
 }
 
+/// Retries allowed while the server loads the model. Only LM Studio loads models on demand;
+/// other providers fail straight away.
+fn model_loading_retries() -> u32 {
+    let config = crate::config::Config::load();
+    if config.llm_provider == crate::config::LlmProvider::LmStudio { config.model_loading_retry_limit() } else { 0 }
+}
+
+/// Stream the answer to `messages` as `request_id`, retrying while the model loads. Stops
+/// reading once `cancel` is set.
+async fn stream_request(llm: &crate::llm::LlmClient, messages: &[ChatMessage], request_id: u64, tx: &mpsc::Sender<LlmResult>, cancel: &AtomicBool, loading_retries: u32) {
+    // Retries resend the same messages, the transcript is untouched
+    let mut attempt = 0;
+    let started = loop {
+        match llm.chat_stream(messages).await {
+            Err(e) if crate::llm::is_model_loading(&e) && attempt < loading_retries => {
+                attempt += 1;
+                tracing::info!("Model still loading, retry {}/{}: {}", attempt, loading_retries, e);
+                let _ = tx.send(LlmResult::ModelLoading(request_id, attempt, loading_retries)).await;
+                if !wait_for_model(cancel).await {
+                    break Err(anyhow::anyhow!("Cancelled while the model was loading"));
+                }
+            }
+            result => break result,
+        }
+    };
+
+    match started {
+        Ok(mut stream) => {
+            while let Some(chunk_res) = stream.next().await {
+                if cancel.load(Ordering::Relaxed) {
+                    break;
+                }
+                match chunk_res {
+                    Ok(event) => {
+                        let _ = tx.send(LlmResult::StreamChunk(request_id, event)).await;
+                    },
+                    Err(e) => {
+                        let _ = tx.send(LlmResult::StreamError(request_id, e.to_string())).await;
+                    }
+                }
+            }
+            let _ = tx.send(LlmResult::StreamDone(request_id)).await;
+        },
+        Err(e) => {
+            let _ = tx.send(LlmResult::StreamError(request_id, e.to_string())).await;
+        }
+    }
+}
+
+/// Sleep until the next model-loading retry; false when the request was cancelled meanwhile
+async fn wait_for_model(cancel: &AtomicBool) -> bool {
+    let deadline = Instant::now() + MODEL_LOADING_RETRY_DELAY;
+    while Instant::now() < deadline {
+        if cancel.load(Ordering::Relaxed) {
+            return false;
+        }
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+    !cancel.load(Ordering::Relaxed)
+}
+
 async fn handle_rag_info_input(app: &mut TuiApp, key: KeyCode, state: &Arc<AppState>, tx_reembed: &mpsc::Sender<ReembedResult>) {
     if app.reembed_running { return; }
     
     match key {
+        KeyCode::Esc if app.clean_plan.is_some() => app.clean_plan = None,
         KeyCode::Esc => { app.mode = AppMode::Menu; },
+        KeyCode::Char('d') | KeyCode::Char('D') => start_clean(app, state, tx_reembed),
         KeyCode::Char('c') | KeyCode::Char('C') => {
              let _ = state.rag.clear();
              app.rag_stats = Some(state.rag.get_stats());
              app.status_message = Some("Index Cleared!".to_string());
              app.status_message_time = Some(std::time::Instant::now());
         },
-        KeyCode::Char('r') | KeyCode::Char('R') => {
-            app.reembed_running = true;
-            app.reembed_progress = "Initializing...".to_string();
-            
-            let tx = tx_reembed.clone();
-            let rag = state.rag.clone();
-            
+        KeyCode::Char('b') | KeyCode::Char('B') => start_rebuild(app, state, tx_reembed),
+        KeyCode::Char('r') | KeyCode::Char('R') => start_reembed(app, state, tx_reembed),
+        _ => {}
+    }
+}
+
+/// Look for scraped files to delete, or delete what the last look found, in the background.
+/// Files the index still refers to are kept; `polirag clean --prune-index` removes those.
+fn start_clean(app: &mut TuiApp, state: &Arc<AppState>, tx_reembed: &mpsc::Sender<ReembedResult>) {
+    app.reembed_running = true;
+    let tx = tx_reembed.clone();
+    let rag = state.rag.clone();
+
+    match app.clean_plan.take() {
+        Some(plan) => {
+            app.reembed_progress = "Deleting scraped files...".to_string();
             tokio::spawn(async move {
-                // 1. Scan for new files first
-                let _ = tx.send(ReembedResult::Progress("Scanning for new files...".to_string())).await;
-                
-                // Helper callback for scanning logs
-                let tx_clone = tx.clone();
-                let log_callback = move |msg: String| {
-                     let _ = tx_clone.try_send(ReembedResult::Progress(msg));
+                let result = tokio::task::spawn_blocking(move || crate::clean::apply(&rag, &plan, false)).await;
+                let message = match result {
+                    Ok(Ok(outcome)) => ReembedResult::Cleaned(outcome),
+                    Ok(Err(e)) => ReembedResult::Error(e.to_string()),
+                    Err(e) => ReembedResult::Error(e.to_string()),
                 };
-                
-                let skip_ids: std::collections::HashSet<String> = match crate::ops::scan_local_data(rag.clone(), log_callback).await {
-                     Ok(ids) => {
-                         if !ids.is_empty() {
-                             let _ = tx.send(ReembedResult::Progress(format!("Indexed {} new chunks.", ids.len()))).await;
-                         } else {
-                             let _ = tx.send(ReembedResult::Progress("No new files found.".to_string())).await;
-                         }
-                         ids.into_iter().collect()
-                     },
-                     Err(e) => {
-                         let _ = tx.send(ReembedResult::Progress(format!("Scan error: {}", e))).await;
-                         std::collections::HashSet::new()
-                     }
+                let _ = tx.send(message).await;
+            });
+        }
+        None => {
+            app.reembed_progress = "Looking for files to clean...".to_string();
+            let options = crate::clean::CleanOptions { targets: Vec::new(), older_than_days: crate::clean::DEFAULT_OLDER_THAN_DAYS, apply: false, prune_index: false };
+            tokio::spawn(async move {
+                let result = tokio::task::spawn_blocking(move || crate::clean::plan(&rag, &options)).await;
+                let message = match result {
+                    Ok(plan) => ReembedResult::CleanPlan(plan),
+                    Err(e) => ReembedResult::Error(e.to_string()),
                 };
-                
-                // 2. Perform Re-embedding
-                let _ = tx.send(ReembedResult::Progress("Starting re-embedding...".to_string())).await;
-                
-                let result = rag.reembed_all(&skip_ids, |current, total, id, metadata| {
-                    let display_name = if let Some(filename) = metadata.get("filename") {
-                        filename.clone()
-                    } else if let Some(name) = metadata.get("name") {
-                        name.clone()
-                    } else {
-                        // Fallback: Try to make ID/URL readable
-                        if id.starts_with("http") || id.starts_with("/") {
-                            if let Ok(url) = url::Url::parse(id) {
-                                // Try to get the last path segment or something meaningful
-                                if let Some(segments) = url.path_segments() {
-                                    if let Some(last) = segments.last() {
-                                        if !last.is_empty() {
-                                             last.to_string()
-                                        } else {
-                                             id.to_string()
-                                        }
-                                    } else {
-                                        id.to_string()
-                                    }
+                let _ = tx.send(message).await;
+            });
+        }
+    }
+}
+
+/// Rebuild the HNSW graph in the background; progress arrives as `ReembedResult`s
+fn start_rebuild(app: &mut TuiApp, state: &Arc<AppState>, tx_reembed: &mpsc::Sender<ReembedResult>) {
+    app.reembed_running = true;
+    app.reembed_progress = "Rebuilding search graph...".to_string();
+
+    let tx = tx_reembed.clone();
+    let rag = state.rag.clone();
+
+    tokio::spawn(async move {
+        // Graph construction is CPU bound, keep it off the UI runtime
+        let result = tokio::task::spawn_blocking(move || rag.rebuild_index()).await;
+        match result {
+            Ok(Ok(count)) => { let _ = tx.send(ReembedResult::Rebuilt(count)).await; },
+            Ok(Err(e)) => { let _ = tx.send(ReembedResult::Error(e.to_string())).await; },
+            Err(e) => { let _ = tx.send(ReembedResult::Error(e.to_string())).await; }
+        }
+    });
+}
+
+/// Index files downloaded but not indexed yet, then recalculate every embedding in the background
+fn start_reembed(app: &mut TuiApp, state: &Arc<AppState>, tx_reembed: &mpsc::Sender<ReembedResult>) {
+    app.reembed_running = true;
+    app.reembed_progress = "Initializing...".to_string();
+    
+    let tx = tx_reembed.clone();
+    let rag = state.rag.clone();
+    
+    tokio::spawn(async move {
+        // 1. Scan for new files first
+        let _ = tx.send(ReembedResult::Progress("Scanning for new files...".to_string())).await;
+        
+        // Helper callback for scanning logs
+        let tx_clone = tx.clone();
+        let log_callback = move |event: crate::sync::SyncEvent| {
+             let _ = tx_clone.try_send(ReembedResult::Progress(event.to_string()));
+        };
+        
+        let skip_ids: std::collections::HashSet<String> = match crate::sync::scan_local_data(rag.clone(), log_callback).await {
+             Ok(ids) => {
+                 if !ids.is_empty() {
+                     let _ = tx.send(ReembedResult::Progress(format!("Indexed {} new chunks.", ids.len()))).await;
+                 } else {
+                     let _ = tx.send(ReembedResult::Progress("No new files found.".to_string())).await;
+                 }
+                 ids.into_iter().collect()
+             },
+             Err(e) => {
+                 let _ = tx.send(ReembedResult::Progress(format!("Scan error: {}", e))).await;
+                 std::collections::HashSet::new()
+             }
+        };
+        
+        // 2. Perform Re-embedding
+        let _ = tx.send(ReembedResult::Progress("Starting re-embedding...".to_string())).await;
+        
+        let result = rag.reembed_all(&skip_ids, |progress| {
+            let (id, metadata) = (progress.id, progress.metadata);
+            let display_name = if let Some(filename) = metadata.get("filename") {
+                filename.clone()
+            } else if let Some(name) = metadata.get("name") {
+                name.clone()
+            } else {
+                // Fallback: Try to make ID/URL readable
+                if id.starts_with("http") || id.starts_with("/") {
+                    if let Ok(url) = url::Url::parse(id) {
+                        // Try to get the last path segment or something meaningful
+                        if let Some(mut segments) = url.path_segments() {
+                            if let Some(last) = segments.next_back() {
+                                if !last.is_empty() {
+                                     last.to_string()
                                 } else {
-                                    id.to_string()
+                                     id.to_string()
                                 }
                             } else {
-                                // Just show last 30 chars?
-                                if id.len() > 30 {
-                                    format!("...{}", &id[id.len()-30..])
-                                } else {
-                                    id.to_string()
-                                }
+                                id.to_string()
                             }
                         } else {
-                             if id.len() > 30 { 
-                                format!("{}...", &id[..30]) 
-                            } else { 
-                                id.to_string() 
-                            }
+                            id.to_string()
                         }
-                    };
-                    
-                    // Truncate if still too long
-                    let final_name = if display_name.len() > 40 {
-                        format!("{}...", &display_name[..40])
                     } else {
-                        display_name
-                    };
-                    
-                    let msg = format!("[{}/{}] {}", current, total, final_name);
-                    // Note: Can't await in closure, so we send synchronously via try_send
-                    let _ = tx.try_send(ReembedResult::Progress(msg));
-                }).await;
-                
-                match result {
-                    Ok(count) => { let _ = tx.send(ReembedResult::Complete(count)).await; },
-                    Err(e) => { let _ = tx.send(ReembedResult::Error(e.to_string())).await; }
+                        // Just show last 30 chars?
+                        if id.len() > 30 {
+                            format!("...{}", &id[id.len()-30..])
+                        } else {
+                            id.to_string()
+                        }
+                    }
+                } else {
+                     if id.len() > 30 { 
+                        format!("{}...", &id[..30]) 
+                    } else { 
+                        id.to_string() 
+                    }
                 }
-            });
-        },
-        _ => {}
-    }
+            };
+            
+            // Truncate if still too long
+            let final_name = if display_name.len() > 40 {
+                format!("{}...", &display_name[..40])
+            } else {
+                display_name
+            };
+            
+            let mut msg = format!("[batch {}/{}] {}/{} │ {}", progress.batch, progress.batches, progress.done, progress.total, final_name);
+            if progress.resumed > 0 {
+                msg.push_str(&format!(" │ resumed after {}", progress.resumed));
+            }
+            // Note: Can't await in closure, so we send synchronously via try_send
+            let _ = tx.try_send(ReembedResult::Progress(msg));
+        }).await;
+        
+        match result {
+            Ok(count) => { let _ = tx.send(ReembedResult::Complete(count)).await; },
+            Err(e) => { let _ = tx.send(ReembedResult::Error(e.to_string())).await; }
+        }
+    });
 }
 
-fn handle_sync_input(app: &mut TuiApp, key: KeyCode) {
+fn handle_sync_input(app: &mut TuiApp, key: KeyCode, channels: &Channels<'_>) {
     match key {
+        KeyCode::Char('y') | KeyCode::Char('Y') if app.low_space_sync.is_some() => {
+            if let Some((mode, fresh)) = app.low_space_sync.take() {
+                start_sync(app, channels.state, channels.sync, mode, fresh, true);
+            }
+        },
+        KeyCode::Char('n') | KeyCode::Char('N') if app.low_space_sync.take().is_some() => {
+            app.sync_logs.push("Sync cancelled. Free some space, e.g. with `polirag clean`, and sync again.".to_string());
+            app.scroll_to_bottom();
+        },
+        KeyCode::Char('y') | KeyCode::Char('Y') | KeyCode::Char('n') | KeyCode::Char('N') if app.churn_prompt.is_some() => {
+            let accept = matches!(key, KeyCode::Char('y') | KeyCode::Char('Y'));
+            if let Some(prompt) = app.churn_prompt.take() {
+                let _ = prompt.reply.send(accept);
+                app.sync_logs.push(if accept { "  ✓ Applying, old versions archived".to_string() } else { "  ✓ Keeping the indexed versions".to_string() });
+                app.scroll_to_bottom();
+            }
+        },
         KeyCode::Esc => {
-            if !app.sync_running { app.mode = AppMode::Menu; }
+            if app.sync_running {
+                app.set_status(" Sync keeps running in the background │ chat stays available ");
+            }
+            app.mode = AppMode::Menu;
+        },
+        KeyCode::Char('c') | KeyCode::Char('C') if !app.sync_running && app.sync_report.is_some() => {
+            app.changes_expanded.clear();
+            app.changes_state.select(Some(0));
+            app.mode = AppMode::SyncChanges;
         },
         KeyCode::Up => app.scroll_up(3),
         KeyCode::Down => app.scroll_down(3),
@@ -1407,6 +3239,254 @@ fn handle_sync_input(app: &mut TuiApp, key: KeyCode) {
     }
 }
 
+fn handle_sync_changes_input(app: &mut TuiApp, key: KeyCode) {
+    let count = app.sync_report.as_ref().map_or(0, |r| r.subjects.len());
+    let selected = app.changes_state.selected().unwrap_or(0);
+    match key {
+        KeyCode::Esc => app.mode = AppMode::Sync,
+        KeyCode::Up if count > 0 => app.changes_state.select(Some(if selected == 0 { count - 1 } else { selected - 1 })),
+        KeyCode::Down if count > 0 => app.changes_state.select(Some((selected + 1) % count)),
+        KeyCode::Enter if count > 0 && !app.changes_expanded.remove(&selected) => {
+            app.changes_expanded.insert(selected);
+        },
+        _ => {}
+    }
+}
+
+fn handle_subjects_input(app: &mut TuiApp, key: KeyCode) {
+    let count = app.subjects.len();
+    let selected = app.subjects_state.selected().unwrap_or(0);
+    match key {
+        KeyCode::Esc => app.mode = AppMode::Menu,
+        KeyCode::Up if count > 0 => app.subjects_state.select(Some(if selected == 0 { count - 1 } else { selected - 1 })),
+        KeyCode::Down if count > 0 => app.subjects_state.select(Some((selected + 1) % count)),
+        KeyCode::Char(c @ ('s' | 'S' | 'r' | 'R')) if count > 0 => {
+            let subject = &mut app.subjects[selected];
+            let mut prefs = subject.prefs.clone();
+            if c.eq_ignore_ascii_case(&'s') { prefs.sync = !prefs.sync; } else { prefs.retrieve = !prefs.retrieve; }
+            match crate::config::Config::save_subject_prefs(&subject.id, prefs.clone()) {
+                Ok(()) => {
+                    let status = format!(
+                        " {} │ sync {} │ retrieval {} ",
+                        subject.label(), if prefs.sync { "on" } else { "off" }, if prefs.retrieve { "on" } else { "off" }
+                    );
+                    subject.prefs = prefs;
+                    app.set_status(status);
+                }
+                Err(e) => app.set_status(format!(" Could not save subject preferences: {} ", e)),
+            }
+        },
+        _ => {}
+    }
+}
+
+fn handle_study_input(app: &mut TuiApp, key: event::KeyEvent, state: &Arc<AppState>, tx_llm: &mpsc::Sender<LlmResult>) {
+    let Some(session) = app.study.as_mut() else {
+        app.mode = AppMode::Menu;
+        return;
+    };
+    match study::handle_input(session, key) {
+        study::Step::Nothing => {}
+        study::Step::Ask => ask_study_question(app, state, tx_llm),
+        study::Step::Judge => judge_study_answer(app, state, tx_llm),
+        study::Step::View => {
+            let Some(chunk) = app.study.as_ref().and_then(|s| s.chunk.clone()) else { return };
+            match state.rag.document_text(&chunk.id) {
+                Ok(text) => app.preview = preview::DocumentPreview::new(chunk.topic, text, &chunk.id),
+                Err(e) => app.set_status(format!(" ✗ {} ", e)),
+            }
+        }
+        study::Step::Exit => {
+            // Whatever the pending question still streams is dropped, the chat never sees it
+            if let Some(request_id) = app.study.take().and_then(|mut session| session.abandon()) {
+                app.dropped_requests.insert(request_id);
+            }
+            app.mode = AppMode::Menu;
+        }
+    }
+}
+
+/// Pick a chunk of the session's subject and have the model write a question about it
+fn ask_study_question(app: &mut TuiApp, state: &Arc<AppState>, tx_llm: &mpsc::Sender<LlmResult>) {
+    let request_id = app.next_request_id;
+    let Some(session) = app.study.as_mut() else { return };
+    let Some(subject) = session.subject.clone() else { return };
+    let Some(chunk) = crate::study::pick_chunk(&state.rag, &subject.id, &session.log.subject(&subject.id)) else {
+        session.stage = study::Stage::Subjects;
+        app.set_status(format!(" {} has no indexed passage long enough to ask about ", subject.label()));
+        return;
+    };
+    let messages = crate::study::question_messages(&chunk);
+    let cancel = Arc::new(AtomicBool::new(false));
+    session.asking(chunk, request_id, cancel.clone());
+    app.next_request_id += 1;
+    spawn_study_request(state, tx_llm, messages, request_id, cancel);
+}
+
+/// Have the model judge the typed answer against the question's chunk
+fn judge_study_answer(app: &mut TuiApp, state: &Arc<AppState>, tx_llm: &mpsc::Sender<LlmResult>) {
+    let request_id = app.next_request_id;
+    let Some(session) = app.study.as_mut() else { return };
+    let Some(chunk) = session.chunk.as_ref() else { return };
+    let messages = crate::study::evaluation_messages(chunk, session.question(), session.answer.trim());
+    let cancel = Arc::new(AtomicBool::new(false));
+    session.judging(request_id, cancel.clone());
+    app.next_request_id += 1;
+    spawn_study_request(state, tx_llm, messages, request_id, cancel);
+}
+
+fn spawn_study_request(state: &Arc<AppState>, tx_llm: &mpsc::Sender<LlmResult>, messages: Vec<ChatMessage>, request_id: u64, cancel: Arc<AtomicBool>) {
+    let llm = state.llm.lock().unwrap().clone();
+    let tx = tx_llm.clone();
+    let loading_retries = model_loading_retries();
+    tokio::spawn(async move {
+        stream_request(&llm, &messages, request_id, &tx, &cancel, loading_retries).await;
+    });
+}
+
+/// Take `result` if it belongs to the study session or an abandoned study question; anything
+/// else is handed back for the chat
+fn route_study_result(app: &mut TuiApp, result: LlmResult) -> Option<LlmResult> {
+    let request_id = match &result {
+        LlmResult::StreamChunk(id, _) | LlmResult::StreamDone(id) | LlmResult::StreamError(id, _) | LlmResult::ModelLoading(id, ..) => *id,
+        _ => return Some(result),
+    };
+    let owned = app.study.as_ref().is_some_and(|s| s.owns(request_id));
+    if !owned && !app.dropped_requests.contains(&request_id) {
+        return Some(result);
+    }
+    // Spent either way
+    if let LlmResult::StreamChunk(_, crate::llm::StreamEvent::Usage(usage)) = &result {
+        if app.active_provider == crate::config::LlmProvider::OpenRouter {
+            app.record_usage(usage);
+        }
+    }
+    if !owned {
+        return None;
+    }
+    if let LlmResult::ModelLoading(_, attempt, limit) = result {
+        app.set_status(format!(" Model loading… retry {}/{} ", attempt, limit));
+        return None;
+    }
+    let session = app.study.as_mut()?;
+    match result {
+        LlmResult::StreamChunk(_, crate::llm::StreamEvent::Content(chunk)) => session.push(&chunk),
+        LlmResult::StreamDone(_) => session.finish(),
+        LlmResult::StreamError(_, e) => {
+            session.fail(e);
+            // The StreamDone that may still follow
+            app.dropped_requests.insert(request_id);
+        }
+        _ => {}
+    }
+    None
+}
+
+/// The `/lookup` note: each excerpt verbatim, then where it comes from
+fn lookup_note(query: &str, excerpts: &[crate::rag::Excerpt], subject_names: &std::collections::HashMap<String, String>) -> String {
+    let mut out = format!("Direct excerpts for \"{}\", quoted from your documents (not an AI answer)\n", query);
+    if excerpts.is_empty() {
+        out.push_str("\nNo sentence in the best matching documents contains the words of the question.\n");
+    }
+    for excerpt in excerpts {
+        out.push('\n');
+        for line in excerpt.text.lines().filter(|l| !l.trim().is_empty()) {
+            out.push_str(&format!("│ {}\n", line.trim()));
+        }
+        let subject = subject_names.get(&excerpt.subject).unwrap_or(&excerpt.subject);
+        let page = excerpt.page.as_ref().map(|p| format!(", p. {}", p)).unwrap_or_default();
+        out.push_str(&format!("  — {}: {}{}\n", subject, excerpt.source, page));
+    }
+    out
+}
+
+/// Copy `text` with an OSC 52 escape sequence, which the terminal puts in the system
+/// clipboard; this also works over SSH. Terminals that don't support it ignore it.
+fn copy_to_clipboard(text: &str) -> io::Result<()> {
+    use std::io::Write;
+    let mut stdout = io::stdout();
+    write!(stdout, "\x1b]52;c;{}\x07", crate::config::base64_encode(text.as_bytes()))?;
+    stdout.flush()
+}
+
+/// Feed the subjects an answer drew on into the session's affinity: the sources it cited, or
+/// every retrieved source when it cited none
+fn record_affinity(app: &mut TuiApp, request_id: u64) {
+    let Some(info) = &app.last_prompt else { return };
+    let cited: Option<Vec<String>> = app.messages.iter()
+        .find(|m| m.request_id == Some(request_id))
+        .and_then(|m| m.citations.as_ref())
+        .filter(|c| !c.cited.is_empty())
+        .map(|c| c.cited.iter().filter_map(|n| c.sources.get(n - 1).cloned()).collect());
+    let subjects: Vec<String> = cited.as_ref().unwrap_or(&info.sources).iter()
+        .filter_map(|source| info.source_subjects.get(source).cloned())
+        .collect();
+    app.affinity.record(&subjects, crate::config::Config::load().rag.affinity.decay);
+}
+
+/// Ping the chat and embeddings servers for `provider` in the background.
+/// With `warn`, a status message reports when only one of them answers.
+fn check_connectivity(provider: crate::config::LlmProvider, tx: mpsc::Sender<LlmResult>, warn: bool) {
+    let config = crate::config::Config::load().with_provider(provider);
+    let chat_url = config.chat_url();
+    let embeddings_url = config.embeddings_url();
+    tokio::spawn(async move {
+        let chat = crate::llm::LlmClient::ping(&chat_url).await;
+        let embeddings = if embeddings_url == chat_url { chat } else { crate::llm::LlmClient::ping(&embeddings_url).await };
+        let _ = tx.send(LlmResult::Connectivity { chat, embeddings, warn }).await;
+    });
+}
+
+/// Apply and save the provider and model chosen in Settings, then go back to the menu
+async fn save_settings(app: &mut TuiApp, state: &Arc<AppState>, tx_llm: &mpsc::Sender<LlmResult>) {
+    let provider = app.active_provider.clone();
+    
+    // Configure LLM
+    {
+        let mut llm = state.llm.lock().unwrap();
+        let config = crate::config::Config::load();
+        llm.set_headers(config.request_headers(&provider));
+        llm.set_auth(&config.with_provider(provider.clone()).chat_url(), Some(app.openrouter_key.clone()));
+        if provider == crate::config::LlmProvider::OpenRouter {
+            if !app.openrouter_model.is_empty() {
+               llm.set_model(&app.openrouter_model);
+               app.model_name = app.openrouter_model.clone();
+            }
+        } else if let Some(i) = app.model_state.selected() {
+            // Local server and selection made, ensure it's set
+            if let Some(model) = app.available_models.get(i) {
+                 llm.set_model(model);
+                 app.model_name = model.clone();
+            }
+        } else if let Some(model) = crate::config::Config::load().model_for(&provider) {
+            // No list loaded (local server offline): fall back to the remembered model
+            llm.set_model(&model);
+            app.model_name = model;
+        }
+    }
+
+    // Fetch context limit (and pricing) for new model, without holding the lock meanwhile
+    let llm = state.llm.lock().unwrap().clone();
+    if let Ok(info) = llm.fetch_model_info().await {
+        app.context_limit = info.as_ref().and_then(|m| m.context_length).unwrap_or(32768);
+        app.model_pricing = info.and_then(|m| m.pricing).and_then(|p| crate::usage::Pricing::from_model(&p));
+    }
+    
+    // Save config
+    let _ = crate::config::Config::save_provider_config(
+        provider, 
+        Some(app.openrouter_key.clone()), 
+        Some(app.openrouter_model.clone())
+    );
+    if !app.model_name.is_empty() {
+        let _ = crate::config::Config::save_model(&app.model_name);
+    }
+    
+    app.set_status(" Settings saved ");
+    app.mode = AppMode::Menu;
+    check_connectivity(app.active_provider.clone(), tx_llm.clone(), true);
+}
+
 async fn handle_settings_input(app: &mut TuiApp, key: KeyCode, state: &Arc<AppState>, tx_llm: &mpsc::Sender<LlmResult>) {
     // Handle text input for OpenRouter fields
     if app.settings_input_mode {
@@ -1427,50 +3507,18 @@ async fn handle_settings_input(app: &mut TuiApp, key: KeyCode, state: &Arc<AppSt
     }
 
     match key {
-        KeyCode::Esc => {
-            // Save and Exit
-            let provider = app.active_provider.clone();
-            
-            // Configure LLM
-            {
-                let mut llm = state.llm.lock().unwrap();
-                llm.set_auth(provider.base_url(), Some(app.openrouter_key.clone()));
-                if provider == crate::config::LlmProvider::OpenRouter {
-                    if !app.openrouter_model.is_empty() {
-                       llm.set_model(&app.openrouter_model);
-                       app.model_name = app.openrouter_model.clone();
-                    }
-                } else if let Some(i) = app.model_state.selected() {
-                    // If LM Studio and selection made, ensure it's set
-                    if let Some(model) = app.available_models.get(i) {
-                         llm.set_model(model);
-                         app.model_name = model.clone();
-                    }
-                }
-                
-                // Fetch context limit for new model
-                if let Ok(len) = llm.fetch_context_length().await {
-                    app.context_limit = len;
-                }
-            }
-            
-            // Save config
-            let _ = crate::config::Config::save_provider_config(
-                provider, 
-                Some(app.openrouter_key.clone()), 
-                Some(app.openrouter_model.clone())
-            );
-            
-            app.set_status(" Settings saved ");
-            app.mode = AppMode::Menu;
-        },
+        KeyCode::Esc => save_settings(app, state, tx_llm).await,
         KeyCode::Tab => {
-            // Toggle Provider
-            app.active_provider = match app.active_provider {
-                crate::config::LlmProvider::LmStudio => crate::config::LlmProvider::OpenRouter,
-                crate::config::LlmProvider::OpenRouter => crate::config::LlmProvider::LmStudio,
-            };
+            // Cycle Provider
+            app.active_provider = app.active_provider.next();
             app.settings_field = 0; // Reset focus
+
+            // Bring back the model last used with this provider
+            if app.active_provider == crate::config::LlmProvider::OpenRouter {
+                if let Some(model) = crate::config::Config::load().model_for(&app.active_provider) {
+                    app.openrouter_model = model;
+                }
+            }
             
             // Refetch models for the new provider
             app.available_models.clear();
@@ -1478,7 +3526,10 @@ async fn handle_settings_input(app: &mut TuiApp, key: KeyCode, state: &Arc<AppSt
             
             // Create a temporary client configuration
             let provider = app.active_provider.clone();
-            let base_url = provider.base_url().to_string();
+            let config = crate::config::Config::load();
+            let headers = config.request_headers(&provider);
+            let base_url = config.with_provider(provider.clone()).chat_url();
+            check_connectivity(provider.clone(), tx_llm.clone(), false);
             let api_key = if provider == crate::config::LlmProvider::OpenRouter {
                 Some(app.openrouter_key.clone()) // Use the key currently in the input field
             } else {
@@ -1488,7 +3539,8 @@ async fn handle_settings_input(app: &mut TuiApp, key: KeyCode, state: &Arc<AppSt
             let tx = tx_llm.clone();
             tokio::spawn(async move {
                 // Use a temporary client to fetch models
-                let client = crate::llm::LlmClient::new(Some(base_url), None, api_key);
+                let mut client = crate::llm::LlmClient::new(Some(base_url), None, api_key);
+                client.set_headers(headers);
                 match client.fetch_models().await {
                     Ok(models) => { let _ = tx.send(LlmResult::ModelList(models)).await; },
                     Err(e) => { let _ = tx.send(LlmResult::Error(e.to_string())).await; }
@@ -1496,41 +3548,44 @@ async fn handle_settings_input(app: &mut TuiApp, key: KeyCode, state: &Arc<AppSt
             });
         },
         KeyCode::Up => {
-            if app.active_provider == crate::config::LlmProvider::LmStudio {
+            if app.active_provider.is_local() {
                 app.previous_model();
             } else {
                 if app.settings_field > 0 { app.settings_field -= 1; }
             }
         },
         KeyCode::Down => {
-            if app.active_provider == crate::config::LlmProvider::LmStudio {
+            if app.active_provider.is_local() {
                  app.next_model();
             } else {
                 if app.settings_field < 2 { app.settings_field += 1; }
             }
         },
         KeyCode::Enter => {
-            if app.active_provider == crate::config::LlmProvider::LmStudio {
+            if app.active_provider.is_local() {
                 if let Some(i) = app.model_state.selected() {
                     if let Some(model) = app.available_models.get(i) {
                         let new_model = model.clone();
+                        let provider = app.active_provider.clone();
                         
                         // update global state
                         {
                             let mut llm = state.llm.lock().unwrap();
                             llm.set_model(&new_model);
-                            llm.set_auth(crate::config::LlmProvider::LmStudio.base_url(), None);
+                            let config = crate::config::Config::load();
+                            llm.set_headers(config.request_headers(&provider));
+                            llm.set_auth(&config.with_provider(provider.clone()).chat_url(), None);
                         }
                         
                         app.model_name = new_model.clone();
                         
-                        // Save config
-                        let _ = crate::config::Config::save_model(&new_model);
+                        // Save config (provider first, the model is remembered per provider)
                         let _ = crate::config::Config::save_provider_config(
-                            crate::config::LlmProvider::LmStudio,
+                            provider,
                             None,
                             None
                         );
+                        let _ = crate::config::Config::save_model(&new_model);
                         
                         app.set_status(format!(" Model set to: {} ", new_model));
                         app.mode = AppMode::Menu;
@@ -1543,33 +3598,130 @@ async fn handle_settings_input(app: &mut TuiApp, key: KeyCode, state: &Arc<AppSt
                 }
             }
         },
+        KeyCode::Char(c @ ('-' | '+' | '=' | '[' | ']')) => {
+            // Read per question, so the next one already uses them
+            let rag_config = crate::config::Config::load().rag;
+            let mut top_k = rag_config.top_k();
+            let mut min_score = rag_config.relevance.floor;
+            match c {
+                '-' => top_k = top_k.saturating_sub(1),
+                '+' | '=' => top_k += 1,
+                '[' => min_score -= MIN_SCORE_STEP,
+                _ => min_score += MIN_SCORE_STEP,
+            }
+            let min_score = (min_score.clamp(0.0, 1.0) * 100.0).round() / 100.0;
+            match crate::config::Config::save_retrieval_limits(top_k, min_score) {
+                Ok(()) => app.set_status(format!(" Retrieval: {} ", retrieval_limits(top_k, min_score))),
+                Err(e) => app.set_status(format!(" Could not save the retrieval settings: {} ", e)),
+            }
+        },
+        KeyCode::Char('v') | KeyCode::Char('V') => {
+            // The backends keep different files, the open store can't be swapped in place
+            let store_type = crate::config::Config::load().rag.store_type.next();
+            match crate::config::Config::save_store_type(store_type) {
+                Ok(()) if store_type == crate::config::StoreType::Sqlite => app.set_status(
+                    " Vector store set to SQLite. Restart polirag: a new database imports the current index. "
+                ),
+                Ok(()) => app.set_status(format!(
+                    " Vector store set to {}. Restart polirag, then run a full sync: the {} index starts empty. ",
+                    store_type.label(), store_type.label()
+                )),
+                Err(e) => app.set_status(format!(" Could not save the vector store: {} ", e)),
+            }
+        },
+        _ => {}
+    }
+}
+
+/// Keys while `/paste` captures text: Enter breaks the line, Ctrl+D embeds the text as
+/// temporary context and Esc drops it
+async fn handle_paste_capture(app: &mut TuiApp, key: event::KeyEvent, state: &Arc<AppState>) {
+    let Some(capture) = app.paste_capture.as_mut() else { return };
+    match key.code {
+        KeyCode::Char('d') if key.modifiers.contains(event::KeyModifiers::CONTROL) => {
+            let text = app.paste_capture.take().unwrap_or_default();
+            if text.trim().is_empty() {
+                app.set_status(" Nothing pasted ");
+                return;
+            }
+            match state.rag.add_pasted(&text, "user").await {
+                Ok((label, chunks)) => {
+                    app.pasted_texts = state.rag.pasted_count();
+                    app.set_status(format!(" Added as {} ({} chunk(s)) │ searched with your documents until /paste clear or Ctrl+L ", label, chunks));
+                }
+                Err(e) => {
+                    app.paste_capture = Some(text);
+                    app.set_status(format!(" ✗ Could not add the pasted text: {} ", e));
+                }
+            }
+        }
+        KeyCode::Esc => {
+            app.paste_capture = None;
+            app.set_status(" Paste cancelled ");
+        }
+        KeyCode::Enter => capture.push('\n'),
+        KeyCode::Tab => capture.push('\t'),
+        KeyCode::Backspace => { capture.pop(); }
+        KeyCode::Char(c) if !key.modifiers.contains(event::KeyModifiers::CONTROL) => capture.push(c),
+        _ => {}
+    }
+}
+
+/// Bracketed paste: the text arrives in one piece instead of as key presses, so it goes
+/// into whichever text field has focus. Line breaks are dropped since every field is one line,
+/// except in the `/paste` capture.
+fn handle_paste(app: &mut TuiApp, text: &str) {
+    if let Some(capture) = app.paste_capture.as_mut().filter(|_| app.mode == AppMode::Chat) {
+        capture.push_str(&text.replace("\r\n", "\n").replace('\r', "\n"));
+        return;
+    }
+    let text: String = text.chars().filter(|c| *c != '\n' && *c != '\r').collect();
+    match app.mode {
+        AppMode::Chat if !app.is_thinking => {
+            app.input.insert_str(app.input_cursor, &text);
+            app.input_cursor += text.len();
+        }
+        AppMode::Login if !app.is_thinking => {
+            if app.login_field == 0 { app.login_username.push_str(text.trim()); } else { app.login_pin.push_str(&text); }
+        }
+        AppMode::Study => {
+            if let Some(session) = app.study.as_mut() { session.paste(&text); }
+        }
+        AppMode::Settings if app.settings_input_mode => {
+            let target = if app.settings_field == 1 { &mut app.openrouter_key } else { &mut app.openrouter_model };
+            target.push_str(text.trim());
+        }
         _ => {}
     }
 }
 
-async fn handle_login_input(app: &mut TuiApp, key: KeyCode, state: &Arc<AppState>, tx_login: &mpsc::Sender<LoginResult>) {
+async fn handle_login_input(app: &mut TuiApp, key: event::KeyEvent, state: &Arc<AppState>, tx_login: &mpsc::Sender<LoginResult>) {
     if app.is_thinking { return; }
-    match key {
-        KeyCode::Esc => { app.mode = AppMode::Menu; app.login_username.clear(); app.login_pin.clear(); app.login_error = None; },
+    if key.modifiers.contains(event::KeyModifiers::CONTROL) {
+        if key.code == KeyCode::Char('r') { app.login_pin_visible = !app.login_pin_visible; }
+        return;
+    }
+    match key.code {
+        KeyCode::Esc => { app.mode = AppMode::Menu; app.login_username.clear(); app.login_pin.clear(); app.login_pin_visible = false; app.login_error = None; },
         KeyCode::Tab => { app.login_field = (app.login_field + 1) % 2; },
         KeyCode::Enter => {
-            if !app.login_username.is_empty() && !app.login_pin.is_empty() {
-                app.is_thinking = true;
-                app.login_error = None;
-                let tx = tx_login.clone();
-                let client = state.poliformat.clone();
-                let username = app.login_username.clone();
-                let pin = app.login_pin.clone();
-                tokio::task::spawn_blocking(move || {
-                    let creds = crate::scrapper::auth::AuthCredentials { username: username.clone(), pin: pin.clone() };
-                    let result = match client.login_headless(&creds) {
-                        Ok(_) => { let _ = crate::config::Config::save_credentials(&username, &pin); LoginResult::Success },
-                        Err(e) => LoginResult::Error(e.to_string()),
-                    };
-                    let rt = tokio::runtime::Handle::current();
-                    rt.block_on(async { let _ = tx.send(result).await; });
-                });
-            } else { app.login_error = Some("Please fill in both fields".to_string()); }
+            let creds = crate::scrapper::auth::AuthCredentials { username: app.login_username.clone(), pin: app.login_pin.clone() };
+            if let Err(e) = crate::scrapper::auth::check_format(&creds) {
+                app.login_error = Some(e.to_string());
+                return;
+            }
+            app.is_thinking = true;
+            app.login_error = None;
+            let tx = tx_login.clone();
+            let client = state.poliformat.clone();
+            tokio::task::spawn_blocking(move || {
+                let result = match client.login_headless(&creds) {
+                    Ok(_) => { let _ = crate::config::Config::save_credentials(&creds.username, &creds.pin); LoginResult::Success },
+                    Err(e) => LoginResult::Error(e.to_string()),
+                };
+                let rt = tokio::runtime::Handle::current();
+                rt.block_on(async { let _ = tx.send(result).await; });
+            });
         },
         KeyCode::Char(c) => { if app.login_field == 0 { app.login_username.push(c); } else { app.login_pin.push(c); } },
         KeyCode::Backspace => { if app.login_field == 0 { app.login_username.pop(); } else { app.login_pin.pop(); } },
@@ -1577,91 +3729,108 @@ async fn handle_login_input(app: &mut TuiApp, key: KeyCode, state: &Arc<AppState
     }
 }
 
+/// Run a sync in the background, reporting through `tx_sync`. With `fresh`, the index is
+/// cleared first; otherwise only what changed is re-indexed. Unless `ignore_low_space`, a data
+/// volume too full for the sync holds it back for a Y/N on the Sync screen.
+fn start_sync(app: &mut TuiApp, state: &Arc<AppState>, tx_sync: &mpsc::Sender<SyncResult>, mode: SyncMode, fresh: bool, ignore_low_space: bool) {
+    app.sync_logs.clear();
+    app.low_space_sync = None;
+    if !ignore_low_space {
+        if let Err(low) = crate::disk::check_sync(&crate::config::Config::get_scraped_data_dir()) {
+            app.sync_complete = false;
+            app.sync_logs.push(format!("⚠ {}", low));
+            if app.auto_sync.running {
+                // Nobody is there to answer, so the timer tries again later
+                app.sync_logs.push("Auto-sync skipped.".to_string());
+                app.auto_sync.finished(false);
+                app.set_status(" ⚠ Auto-sync skipped: low disk space │ open Sync in the menu for details ");
+            } else {
+                app.sync_logs.push("A sync that runs out of space leaves half-written files. Sync anyway? (y/n)".to_string());
+                app.low_space_sync = Some((mode, fresh));
+            }
+            return;
+        }
+    }
+    app.sync_running = true;
+    app.sync_start_docs = if fresh { 0 } else { state.rag.count_documents() };
+    app.sync_complete = false;
+    app.sync_logs.push("Starting sync...".to_string());
+
+    let tx = tx_sync.clone();
+    let rag = state.rag.clone();
+    let poliformat = state.poliformat.clone();
+    // Nobody is there to answer during an auto-sync, so it keeps the indexed versions
+    let ask_churn = !app.auto_sync.running;
+    tokio::spawn(async move {
+        let _ = tx.send(SyncResult::Log("Fetching subjects...".to_string())).await;
+        match run_sync_with_logging(rag, poliformat, mode, fresh, ask_churn, tx.clone()).await {
+            Ok(_) => { let _ = tx.send(SyncResult::Success).await; },
+            Err(e) => { let _ = tx.send(SyncResult::Error(e.to_string())).await; }
+        }
+    });
+}
+
 async fn run_sync_with_logging(
     rag: Arc<crate::rag::RagSystem>,
     poliformat: Arc<crate::scrapper::PoliformatClient>,
+    mode: SyncMode,
+    fresh: bool,
+    ask_churn: bool,
     tx: mpsc::Sender<SyncResult>,
 ) -> anyhow::Result<()> {
-    let _ = tx.send(SyncResult::Log("🗑️  Clearing old RAG index...".to_string())).await;
-    rag.clear()?;
-    
-    let data_dir = crate::config::Config::get_scraped_data_dir();
-    if data_dir.exists() {
-        let _ = tx.send(SyncResult::Log("🗑️  Removing old data directory...".to_string())).await;
-        let _ = std::fs::remove_dir_all(&data_dir);
-    }
-    
-    let _ = tx.send(SyncResult::Log("🔍 Fetching subjects from PoliformaT...".to_string())).await;
-    let subjects = poliformat.get_subjects().await?;
-    let total = subjects.len();
-    let _ = tx.send(SyncResult::Log(format!("📚 Found {} subjects", total))).await;
-    
-    let _ = tx.send(SyncResult::Log("📥 Starting content scrape...".to_string())).await;
-    
-    // Clone subjects for the progress tracking
-    let subject_names: Vec<String> = subjects.iter().map(|s| s.name.clone()).collect();
-    
-    // Log each subject we're about to scrape
-    for (i, name) in subject_names.iter().enumerate() {
-        let _ = tx.send(SyncResult::Log(format!("[{}/{}] Queued: {}", i + 1, total, name))).await;
+    if mode == SyncMode::ResourcesOnly {
+        // Announcements, lessons and summaries from the last full sync stay indexed
+        let _ = tx.send(SyncResult::Log("📁 Resources-only sync: keeping the existing index and summaries".to_string())).await;
     }
-    
-    let _ = tx.send(SyncResult::Log(format!("⏳ Scraping content for {} subjects (this may take 2-3 mins)...", total))).await;
-    let detailed_subjects = poliformat.scrape_subject_content(subjects).await?;
-    let _ = tx.send(SyncResult::Log("✅ Downloads complete!".to_string())).await;
-    
-    let indexing_total = detailed_subjects.len();
-    for (i, (sub, dir_path)) in detailed_subjects.iter().enumerate() {
-        let _ = tx.send(SyncResult::Log(format!("[{}/{}] 📖 Indexing: {}", i + 1, indexing_total, sub.name))).await;
-        
-        let summary_path = std::path::Path::new(&dir_path).join("summary.md");
-        let mut content = if summary_path.exists() {
-            std::fs::read_to_string(&summary_path).unwrap_or_default()
-        } else {
-            let _ = tx.send(SyncResult::Log(format!("  ⚠️  No summary found, skipping..."))).await;
-            continue;
-        };
-        
-        let resources_path = std::path::Path::new(&dir_path).join("resources");
-        let mut file_count = 0;
-        if resources_path.exists() {
-            use std::fmt::Write;
-            let mut file_list = String::new();
-            writeln!(&mut file_list, "\n\n[Local Files]:").unwrap();
-            if let Ok(entries) = std::fs::read_dir(&resources_path) {
-                for entry in entries.flatten() {
-                    if let Ok(name) = entry.file_name().into_string() {
-                        writeln!(&mut file_list, "- {}", name).unwrap();
-                        file_count += 1;
-                    }
+
+    // Events also come from the blocking scrape thread; forward them in order through an unbounded channel
+    let (event_tx, mut event_rx) = mpsc::unbounded_channel::<crate::sync::SyncEvent>();
+    let log_tx = tx.clone();
+    let forwarder = tokio::spawn(async move {
+        while let Some(event) = event_rx.recv().await {
+            if let crate::sync::SyncEvent::SubjectsFound(names) = &event {
+                let _ = log_tx.send(SyncResult::Log(event.to_string())).await;
+                for (i, name) in names.iter().enumerate() {
+                    let _ = log_tx.send(SyncResult::Log(format!("[{}/{}] Queued: {}", i + 1, names.len(), name))).await;
                 }
+                continue;
             }
-            content.push_str(&file_list);
-        }
-        
-        if file_count > 0 {
-            let _ = tx.send(SyncResult::Log(format!("  📁 Found {} resource files", file_count))).await;
-        }
-        
-        let _ = tx.send(SyncResult::Log(format!("  🔄 Processing PDFs..."))).await;
-        let extracted_docs = crate::scrapper::processing::process_resources(std::path::Path::new(&dir_path)).unwrap_or_default();
-        
-        let full_text = format!("Subject: {}\nURL: {}\n\n{}", sub.name, sub.url, content);
-        rag.add_document(&sub.id, &full_text, "user", [("type".to_string(), "subject".to_string())].into()).await?;
-        
-        if !extracted_docs.is_empty() {
-            let _ = tx.send(SyncResult::Log(format!("  📄 Indexing {} PDFs...", extracted_docs.len()))).await;
-        }
-        
-        for (rel_path, text) in extracted_docs {
-            let doc_id = format!("{}/{}", sub.id, rel_path);
-            let pdf_text = format!("Subject: {}\nFile: {}\n\n{}", sub.name, rel_path, text);
-            rag.add_document(&doc_id, &pdf_text, "user", [("type".to_string(), "pdf".to_string()), ("filename".to_string(), rel_path)].into()).await?;
+            if let crate::sync::SyncEvent::NewSubjects(names) = &event {
+                let _ = log_tx.send(SyncResult::NewSubjects(names.clone())).await;
+            }
+            let _ = log_tx.send(SyncResult::Log(event.to_string())).await;
         }
-        
-        let _ = tx.send(SyncResult::Log(format!("  ✓ Done: {}", sub.name))).await;
-    }
-    
+    });
+
+    let churn = if ask_churn {
+        let (prompt_tx, mut prompt_rx) = mpsc::unbounded_channel::<crate::sync::ChurnPrompt>();
+        let prompt_forward = tx.clone();
+        tokio::spawn(async move {
+            while let Some(prompt) = prompt_rx.recv().await {
+                let _ = prompt_forward.send(SyncResult::Churn(prompt)).await;
+            }
+        });
+        crate::sync::ChurnPolicy::Ask(prompt_tx)
+    } else {
+        crate::sync::ChurnPolicy::Keep
+    };
+    let options = crate::sync::SyncOptions {
+        mode,
+        limits: crate::config::Config::load().downloads,
+        fresh,
+        churn,
+        fast: false,
+        // start_sync already checked, or the user chose to sync anyway
+        ignore_low_space: true,
+    };
+    let result = crate::sync::run(rag.clone(), poliformat, options, move |event| {
+        let _ = event_tx.send(event);
+    }).await;
+    // The engine dropped the sender when it returned, so this ends once the last line is out
+    let _ = forwarder.await;
+    let report = result?;
+    let _ = tx.send(SyncResult::Changes(report)).await;
+
     let stats = rag.get_stats();
     let _ = tx.send(SyncResult::Log(format!("📊 Final index: {} documents, {}", stats.document_count, stats.format_file_size()))).await;
     
@@ -1670,6 +3839,16 @@ async fn run_sync_with_logging(
 
 
 
+/// "top 20, min score 0.22", with 0 shown as no limit or no cutoff
+fn retrieval_limits(top_k: usize, min_score: f32) -> String {
+    let top_k = match top_k {
+        0 => "no snippet limit".to_string(),
+        n => format!("top {}", n),
+    };
+    let min_score = if min_score > 0.0 { format!("min score {:.2}", min_score) } else { "no score cutoff".to_string() };
+    format!("{}, {}", top_k, min_score)
+}
+
 fn draw_settings(frame: &mut Frame, app: &mut TuiApp) {
     let size = frame.area();
     
@@ -1688,27 +3867,47 @@ fn draw_settings(frame: &mut Frame, app: &mut TuiApp) {
             Constraint::Length(3), // Input 1 (Model List or API Key)
             Constraint::Length(3), // Input 2 (Model Name)
             Constraint::Min(3),    // Remaining/Help
+            Constraint::Length(4), // Endpoints, vector store and retrieval
         ])
         .margin(1)
         .split(inner_area);
     
     frame.render_widget(Paragraph::new(render_logo()).alignment(Alignment::Center), layout[0]);
+
+    // Chat and embeddings endpoints with their reachability
+    let config = crate::config::Config::load().with_provider(app.active_provider.clone());
+    let indicator = |ok: Option<bool>| match ok {
+        Some(true) => Span::styled("●", Style::default().fg(Color::Green)),
+        Some(false) => Span::styled("●", Style::default().fg(Color::Red)),
+        None => Span::styled("○", Style::default().fg(Color::DarkGray)),
+    };
+    let endpoints = vec![
+        Line::from(vec![indicator(app.chat_server_ok), Span::styled(format!(" Chat: {}", config.chat_url()), Style::default().fg(Color::DarkGray))]),
+        Line::from(vec![indicator(app.embeddings_server_ok), Span::styled(format!(" Embeddings: {}", config.embeddings_url()), Style::default().fg(Color::DarkGray))]),
+        Line::from(Span::styled(format!("Vector store: {} │ V: switch", config.rag.store_type.label()), Style::default().fg(Color::DarkGray))),
+        Line::from(Span::styled(
+            format!("Retrieval: {} │ -/+: top k  [/]: min score", retrieval_limits(config.rag.top_k(), config.rag.relevance.floor)),
+            Style::default().fg(Color::DarkGray),
+        )),
+    ];
+    frame.render_widget(Paragraph::new(endpoints).alignment(Alignment::Center), layout[5]);
     
     // 1. Provider Selection
     let provider_style = if app.settings_field == 0 { Style::default().fg(Color::Yellow) } else { Style::default().fg(Color::White) };
-    let lm_style = if app.active_provider == crate::config::LlmProvider::LmStudio { Style::default().bg(Color::Blue).fg(Color::White) } else { Style::default() };
-    let or_style = if app.active_provider == crate::config::LlmProvider::OpenRouter { Style::default().bg(Color::Blue).fg(Color::White) } else { Style::default() };
+    let choice_style = |provider: crate::config::LlmProvider| if app.active_provider == provider { Style::default().bg(Color::Blue).fg(Color::White) } else { Style::default() };
     
     let provider_span = Line::from(vec![
         Span::styled(" Provider: ", provider_style),
-        Span::styled(" [ LM Studio ] ", lm_style),
+        Span::styled(" [ LM Studio ] ", choice_style(crate::config::LlmProvider::LmStudio)),
+        Span::raw("   "),
+        Span::styled(" [ Ollama ] ", choice_style(crate::config::LlmProvider::Ollama)),
         Span::raw("   "),
-        Span::styled(" [ OpenRouter ] ", or_style),
+        Span::styled(" [ OpenRouter ] ", choice_style(crate::config::LlmProvider::OpenRouter)),
     ]);
     frame.render_widget(Paragraph::new(provider_span).alignment(Alignment::Center), layout[1]);
     
     match app.active_provider {
-        crate::config::LlmProvider::LmStudio => {
+        crate::config::LlmProvider::LmStudio | crate::config::LlmProvider::Ollama => {
              let model_style = if app.settings_field == 1 { Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD) } else { Style::default().fg(Color::Green) };
              frame.render_widget(
                  Paragraph::new(format!("Current Model: {}", app.model_name)).style(model_style).alignment(Alignment::Center),
@@ -1759,10 +3958,98 @@ fn draw_settings(frame: &mut Frame, app: &mut TuiApp) {
             frame.render_widget(model_widget, layout[3]);
             
             // Instructions
-            let instr = Paragraph::new("Tab: Switch Provider | Up/Down: Select Field | Enter: Edit | Esc: Cancel/Save")
+            let instr = Paragraph::new("Tab: Switch Provider | Up/Down: Select Field | Enter: Edit | V: Vector Store | Esc: Cancel/Save")
                 .style(Style::default().fg(Color::DarkGray))
                 .alignment(Alignment::Center);
              frame.render_widget(instr, layout[4]);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An assistant message streaming the answer to `request_id`
+    fn answer(request_id: u64, content: &str) -> TranscriptMessage {
+        TranscriptMessage { request_id: Some(request_id), ..TranscriptMessage::new("assistant", content) }
+    }
+
+    fn contents(messages: &[TranscriptMessage]) -> Vec<(&str, &str)> {
+        messages.iter().map(|m| (m.role.as_str(), m.content.as_str())).collect()
+    }
+
+    #[test]
+    fn chunks_reach_the_message_of_their_request() {
+        let mut messages = vec![TranscriptMessage::new("user", "First?"), answer(1, "One"), TranscriptMessage::new("user", "Second?"), answer(2, "")];
+        message_of_request(&mut messages, 1).content.push_str(", done");
+        message_of_request(&mut messages, 2).content.push_str("Two");
+        assert_eq!(contents(&messages), [("user", "First?"), ("assistant", "One, done"), ("user", "Second?"), ("assistant", "Two")]);
+
+        // A cleared transcript gets a new message rather than losing the rest of the answer
+        let mut messages = vec![TranscriptMessage::new("user", "Third?")];
+        message_of_request(&mut messages, 3).content.push_str("Three");
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[1].request_id, Some(3));
+        assert_eq!(messages[1].content, "Three");
+    }
+
+    #[test]
+    fn an_error_fills_an_empty_answer_or_follows_a_partial_one() {
+        let mut messages = vec![answer(1, "")];
+        show_request_error(&mut messages, 1, "connection refused");
+        assert_eq!(contents(&messages), [("assistant", "Error: connection refused")]);
+
+        let mut messages = vec![answer(1, "Partial"), TranscriptMessage::new("user", "Next?"), answer(2, "")];
+        show_request_error(&mut messages, 1, "stream closed");
+        assert_eq!(contents(&messages), [("assistant", "Partial"), ("assistant", "Error: stream closed"), ("user", "Next?"), ("assistant", "")]);
+    }
+
+    fn roles_and_contents(history: &[ChatMessage]) -> Vec<(&str, &str)> {
+        history.iter().map(|m| (m.role.as_str(), m.content.as_str())).collect()
+    }
+
+    #[test]
+    fn thinking_is_not_part_of_the_visible_answer() {
+        assert_eq!(visible_answer("<think>Let me see</think>\n\nParis."), "\n\nParis.");
+        assert_eq!(visible_answer("Paris."), "Paris.");
+        // Still thinking, or thinking that never closed, shows no answer at all
+        assert_eq!(visible_answer("  <think>Let me see"), "");
+        assert!(visible_answer("<think>Let me see</think>  \n").trim().is_empty());
+    }
+
+    #[test]
+    fn the_payload_holds_typed_questions_and_visible_answers_only() {
+        let transcript = [
+            TranscriptMessage::new("user", "What is 2NF?"),
+            TranscriptMessage::new("assistant", "<think>Recall the definition</think>\n\nNo partial dependencies."),
+            TranscriptMessage::new(NOTE_ROLE, "Copied the citation"),
+            TranscriptMessage::new("user", "And 3NF?"),
+            answer(7, ""),
+        ];
+        let history = request_history(&transcript);
+        assert_eq!(roles_and_contents(&history), [("user", "What is 2NF?"), ("assistant", "No partial dependencies."), ("user", "And 3NF?")]);
+
+        let messages = api_messages("Answer from the context.", &history[..2], "Context: ...\n\nAnd 3NF?");
+        assert_eq!(
+            roles_and_contents(&messages),
+            [("system", "Answer from the context."), ("user", "What is 2NF?"), ("assistant", "No partial dependencies."), ("user", "Context: ...\n\nAnd 3NF?")]
+        );
+    }
+
+    #[test]
+    fn an_empty_answer_leaves_the_history_with_its_question() {
+        let transcript = [
+            TranscriptMessage::new("user", "First?"),
+            TranscriptMessage::new("assistant", "One."),
+            TranscriptMessage::new("user", "Second?"),
+            TranscriptMessage::new("assistant", EMPTY_ANSWER_MARKER),
+            TranscriptMessage::new("user", "Third?"),
+            TranscriptMessage::new("assistant", "Three."),
+        ];
+        assert_eq!(
+            roles_and_contents(&request_history(&transcript)),
+            [("user", "First?"), ("assistant", "One."), ("user", "Third?"), ("assistant", "Three.")]
+        );
+    }
+}
@@ -0,0 +1,46 @@
+//! Desktop notifications for work that finishes while the terminal is in the background:
+//! syncs, re-embeds and answers. They need `notifications.enabled` in the config and a build
+//! with the `notifications` feature; the status bar stays the record inside the app.
+
+use std::time::{Duration, Instant};
+
+use crate::config::Config;
+
+/// Characters of an answer shown in its notification
+const PREVIEW_CHARS: usize = 120;
+
+/// Show a notification when they are enabled. Without a notification daemon or D-Bus session
+/// nothing happens.
+pub fn send(summary: impl Into<String>, body: impl Into<String>) {
+    if Config::load().notifications.enabled {
+        show(summary.into(), body.into());
+    }
+}
+
+/// Whether the user is likely in another window: no key pressed for `notifications.idle_secs`
+pub fn likely_away(last_key: Instant) -> bool {
+    last_key.elapsed() >= Duration::from_secs(Config::load().notifications.idle_secs)
+}
+
+/// The start of `answer` on one line, for the notification body
+pub fn preview(answer: &str) -> String {
+    let collapsed = answer.split_whitespace().collect::<Vec<_>>().join(" ");
+    if collapsed.chars().count() <= PREVIEW_CHARS {
+        collapsed
+    } else {
+        format!("{}…", collapsed.chars().take(PREVIEW_CHARS).collect::<String>().trim_end())
+    }
+}
+
+#[cfg(feature = "notifications")]
+fn show(summary: String, body: String) {
+    // Reaching the daemon can block, keep it off the UI loop
+    std::thread::spawn(move || {
+        if let Err(e) = notify_rust::Notification::new().appname("PoliRag").summary(&summary).body(&body).show() {
+            tracing::debug!("Desktop notification not shown: {}", e);
+        }
+    });
+}
+
+#[cfg(not(feature = "notifications"))]
+fn show(_summary: String, _body: String) {}
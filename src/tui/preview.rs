@@ -0,0 +1,140 @@
+//! `/view`: the extracted text of a retrieved snippet's file, drawn over the current screen,
+//! scrolled to the snippet's chunk and with the chunk highlighted, to check an answer against
+//! what surrounds it.
+
+use std::ops::Range;
+
+use crossterm::event::KeyCode;
+use ratatui::{
+    layout::Rect,
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, Clear, Paragraph},
+    Frame,
+};
+
+use crate::rag::anchor::DocumentText;
+
+/// Lines shown above the chunk when the preview opens
+const CONTEXT_LINES: usize = 3;
+
+pub struct DocumentPreview {
+    title: String,
+    text: Vec<char>,
+    /// Character range of the chunk
+    highlight: Range<usize>,
+    /// First wrapped line shown; `None` until the first draw scrolls to the chunk
+    scroll: Option<usize>,
+    /// Wrapped lines and visible rows of the last draw, to keep scrolling in bounds
+    line_count: usize,
+    height: usize,
+}
+
+impl DocumentPreview {
+    /// Preview of `document` on the chunk `id`
+    pub fn new(title: String, document: DocumentText, id: &str) -> Option<Self> {
+        let highlight = document.range_of(id)?;
+        Some(Self { title, text: document.text.chars().collect(), highlight, scroll: None, line_count: 0, height: 0 })
+    }
+
+    fn scroll_by(&mut self, lines: isize) {
+        let max = self.line_count.saturating_sub(self.height);
+        let current = self.scroll.unwrap_or(0) as isize;
+        self.scroll = Some((current + lines).clamp(0, max as isize) as usize);
+    }
+}
+
+/// Keys while the preview is open; Esc or q closes it
+pub fn handle_input(preview: &mut Option<DocumentPreview>, key: KeyCode) {
+    let Some(open) = preview.as_mut() else { return };
+    let page = open.height.saturating_sub(1).max(1) as isize;
+    match key {
+        KeyCode::Esc | KeyCode::Char('q') => *preview = None,
+        KeyCode::Up | KeyCode::Char('k') => open.scroll_by(-1),
+        KeyCode::Down | KeyCode::Char('j') => open.scroll_by(1),
+        KeyCode::PageUp => open.scroll_by(-page),
+        KeyCode::PageDown | KeyCode::Char(' ') => open.scroll_by(page),
+        KeyCode::Home => open.scroll = Some(0),
+        KeyCode::End => open.scroll_by(isize::MAX / 2),
+        _ => {}
+    }
+}
+
+pub fn draw(frame: &mut Frame, preview: &mut DocumentPreview) {
+    let area = frame.area();
+    let area = Rect::new(area.x + 2, area.y + 1, area.width.saturating_sub(4), area.height.saturating_sub(2));
+    frame.render_widget(Clear, area);
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(Color::Cyan))
+        .title(format!(" {} ", preview.title))
+        .title_bottom(" ↑/↓ PgUp/PgDn Scroll │ Home/End │ Esc Close ");
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+
+    let lines = wrap(&preview.text, inner.width as usize);
+    preview.line_count = lines.len();
+    preview.height = inner.height as usize;
+    let scroll = *preview.scroll.get_or_insert_with(|| {
+        let chunk_line = lines.iter().position(|line| line.end > preview.highlight.start).unwrap_or(0);
+        chunk_line.saturating_sub(CONTEXT_LINES)
+    });
+
+    let highlight = Style::default().bg(Color::DarkGray).fg(Color::Yellow).add_modifier(Modifier::BOLD);
+    let visible: Vec<Line> = lines.iter()
+        .skip(scroll)
+        .take(preview.height)
+        .map(|line| {
+            // The part of the line inside the chunk, if any
+            let start = preview.highlight.start.clamp(line.start, line.end);
+            let end = preview.highlight.end.clamp(line.start, line.end);
+            let piece = |range: Range<usize>| preview.text[range].iter().collect::<String>();
+            Line::from(vec![
+                Span::raw(piece(line.start..start)),
+                Span::styled(piece(start..end), highlight),
+                Span::raw(piece(end..line.end)),
+            ])
+        })
+        .collect();
+    frame.render_widget(Paragraph::new(visible), inner);
+}
+
+/// Character ranges of the display lines of `text` at `width` columns: a line per line of
+/// text, broken after the last space that fits, or mid-word when none does
+fn wrap(text: &[char], width: usize) -> Vec<Range<usize>> {
+    let width = width.max(1);
+    let mut lines = Vec::new();
+    let mut start = 0;
+    while start <= text.len() {
+        let end = text[start..].iter().position(|&c| c == '\n').map_or(text.len(), |p| start + p);
+        let mut from = start;
+        while end - from > width {
+            let cut = text[from..from + width].iter()
+                .rposition(|c| c.is_whitespace())
+                .filter(|&p| p > 0)
+                .map_or(from + width, |p| from + p + 1);
+            lines.push(from..cut);
+            from = cut;
+        }
+        lines.push(from..end);
+        start = end + 1;
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str, width: usize) -> Vec<String> {
+        let chars: Vec<char> = text.chars().collect();
+        wrap(&chars, width).into_iter().map(|range| chars[range].iter().collect()).collect()
+    }
+
+    #[test]
+    fn lines_break_after_a_space_or_mid_word() {
+        assert_eq!(lines("la forma normal\n\nañadida", 10), ["la forma ", "normal", "", "añadida"]);
+        assert_eq!(lines("desnormalización", 6), ["desnor", "maliza", "ción"]);
+        assert_eq!(lines("", 10), [""]);
+    }
+}
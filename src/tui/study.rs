@@ -0,0 +1,337 @@
+//! Study mode: pick a subject, then answer questions the model writes from its indexed chunks
+//! and read the model's judgement of each answer against the chunk, see `crate::study`. The
+//! requests stream through the chat's machinery, but into the session, never the transcript.
+
+use std::collections::BTreeSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use ratatui::{
+    layout::{Alignment, Constraint, Direction, Layout},
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Wrap},
+    Frame,
+};
+
+use crate::study::{StudyChunk, StudyLog, Verdict};
+use crate::subjects::KnownSubject;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Stage {
+    /// Choosing the subject
+    Subjects,
+    /// The question is being generated
+    Asking,
+    /// Waiting for the typed answer
+    Answering,
+    /// The answer is being judged
+    Judging,
+    /// Between questions: the last evaluation or error is shown
+    Ready,
+}
+
+/// What a key asks the app to do, since starting requests needs its channels
+pub enum Step {
+    Nothing,
+    /// Generate a question for the chosen subject
+    Ask,
+    /// Judge the typed answer
+    Judge,
+    /// Show the question's chunk in its file
+    View,
+    Exit,
+}
+
+pub struct StudySession {
+    pub stage: Stage,
+    subjects: Vec<KnownSubject>,
+    subjects_state: ListState,
+    pub subject: Option<KnownSubject>,
+    pub chunk: Option<StudyChunk>,
+    question: String,
+    pub answer: String,
+    evaluation: String,
+    verdict: Option<Verdict>,
+    pub error: Option<String>,
+    /// Request streaming into the question or the evaluation
+    request: Option<u64>,
+    cancel: Option<Arc<AtomicBool>>,
+    /// This session's judged questions, correct ones and files covered
+    asked: u32,
+    correct: u32,
+    topics: BTreeSet<String>,
+    /// Saved record of every subject, updated after each judged question
+    pub log: StudyLog,
+}
+
+impl StudySession {
+    pub fn new(subjects: Vec<KnownSubject>) -> Self {
+        let mut subjects_state = ListState::default();
+        subjects_state.select((!subjects.is_empty()).then_some(0));
+        Self {
+            stage: Stage::Subjects,
+            subjects,
+            subjects_state,
+            subject: None,
+            chunk: None,
+            question: String::new(),
+            answer: String::new(),
+            evaluation: String::new(),
+            verdict: None,
+            error: None,
+            request: None,
+            cancel: None,
+            asked: 0,
+            correct: 0,
+            topics: BTreeSet::new(),
+            log: StudyLog::load(),
+        }
+    }
+
+    pub fn owns(&self, request_id: u64) -> bool {
+        self.request == Some(request_id)
+    }
+
+    pub fn question(&self) -> &str {
+        super::visible_answer(&self.question).trim()
+    }
+
+    /// A question for `chunk` is being generated by `request_id`
+    pub fn asking(&mut self, chunk: StudyChunk, request_id: u64, cancel: Arc<AtomicBool>) {
+        self.chunk = Some(chunk);
+        self.question.clear();
+        self.answer.clear();
+        self.evaluation.clear();
+        self.verdict = None;
+        self.error = None;
+        self.request = Some(request_id);
+        self.cancel = Some(cancel);
+        self.stage = Stage::Asking;
+    }
+
+    /// The answer is being judged by `request_id`
+    pub fn judging(&mut self, request_id: u64, cancel: Arc<AtomicBool>) {
+        self.evaluation.clear();
+        self.error = None;
+        self.request = Some(request_id);
+        self.cancel = Some(cancel);
+        self.stage = Stage::Judging;
+    }
+
+    /// A streamed piece of the question or the evaluation
+    pub fn push(&mut self, chunk: &str) {
+        match self.stage {
+            Stage::Asking => self.question.push_str(chunk),
+            Stage::Judging => self.evaluation.push_str(chunk),
+            _ => {}
+        }
+    }
+
+    /// The request finished: show the question, or record the judged answer
+    pub fn finish(&mut self) {
+        self.request = None;
+        self.cancel = None;
+        match self.stage {
+            Stage::Asking if self.question().is_empty() => self.fail("The model returned no question".to_string()),
+            Stage::Asking => self.stage = Stage::Answering,
+            Stage::Judging => {
+                let (verdict, explanation) = crate::study::parse_evaluation(&self.evaluation);
+                self.evaluation = explanation;
+                self.verdict = verdict;
+                self.stage = Stage::Ready;
+                let (Some(subject), Some(chunk)) = (&self.subject, &self.chunk) else { return };
+                let correct = verdict == Some(Verdict::Correct);
+                self.asked += 1;
+                self.correct += correct as u32;
+                self.topics.insert(chunk.topic.clone());
+                self.log.record(&subject.id, chunk, correct);
+                if let Err(e) = self.log.save() {
+                    tracing::warn!("Failed to save study record: {}", e);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// The request failed: a failed evaluation keeps the answer to send again
+    pub fn fail(&mut self, error: String) {
+        self.request = None;
+        self.cancel = None;
+        self.error = Some(error);
+        self.stage = if self.stage == Stage::Judging { Stage::Answering } else { Stage::Ready };
+    }
+
+    /// Stop the pending request, returning its id so what it still streams can be dropped
+    pub fn abandon(&mut self) -> Option<u64> {
+        if let Some(cancel) = self.cancel.take() {
+            cancel.store(true, Ordering::Relaxed);
+        }
+        self.request.take()
+    }
+
+    pub fn paste(&mut self, text: &str) {
+        if self.stage == Stage::Answering {
+            self.answer.push_str(text);
+        }
+    }
+}
+
+pub fn handle_input(session: &mut StudySession, key: KeyEvent) -> Step {
+    if key.code == KeyCode::Esc {
+        return Step::Exit;
+    }
+    match session.stage {
+        Stage::Subjects => match key.code {
+            KeyCode::Up | KeyCode::Down if !session.subjects.is_empty() => {
+                let len = session.subjects.len();
+                let current = session.subjects_state.selected().unwrap_or(0);
+                let next = if key.code == KeyCode::Up { (current + len - 1) % len } else { (current + 1) % len };
+                session.subjects_state.select(Some(next));
+            }
+            KeyCode::Enter => {
+                if let Some(subject) = session.subjects_state.selected().and_then(|i| session.subjects.get(i)) {
+                    session.subject = Some(subject.clone());
+                    return Step::Ask;
+                }
+            }
+            _ => {}
+        },
+        Stage::Answering => match key.code {
+            KeyCode::Enter if !session.answer.trim().is_empty() => return Step::Judge,
+            KeyCode::Backspace => { session.answer.pop(); }
+            KeyCode::Char(c) if !key.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) => session.answer.push(c),
+            _ => {}
+        },
+        Stage::Ready => match key.code {
+            KeyCode::Enter | KeyCode::Char('n') => return Step::Ask,
+            KeyCode::Char('v') if session.chunk.is_some() => return Step::View,
+            _ => {}
+        },
+        Stage::Asking | Stage::Judging => {}
+    }
+    Step::Nothing
+}
+
+pub fn draw(frame: &mut Frame, session: &mut StudySession, throbber: char) {
+    let size = frame.area();
+    let title = match &session.subject {
+        Some(subject) if session.stage != Stage::Subjects => format!(" Study: {} ", subject.label()),
+        _ => " Study ".to_string(),
+    };
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(Color::Cyan))
+        .title(title);
+    let inner = block.inner(size);
+    frame.render_widget(block, size);
+
+    if session.stage == Stage::Subjects {
+        draw_subjects(frame, session, inner);
+        return;
+    }
+
+    let layout = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Length(1), Constraint::Percentage(30), Constraint::Percentage(25), Constraint::Min(4), Constraint::Length(1)])
+        .margin(1)
+        .split(inner);
+
+    let total = session.subject.as_ref().map(|subject| session.log.subject(&subject.id)).unwrap_or_default();
+    let stats = format!(
+        "This session: {} asked · {} correct · {} topics   │   All time: {} asked · {} correct · {} topics",
+        session.asked, session.correct, session.topics.len(), total.asked, total.correct, total.topics.len(),
+    );
+    frame.render_widget(Paragraph::new(stats).style(Style::default().fg(Color::DarkGray)), layout[0]);
+
+    let dim = Style::default().fg(Color::DarkGray);
+    let question = match session.stage {
+        Stage::Asking if session.question().is_empty() => Paragraph::new(format!("{} Writing a question…", throbber)).style(dim),
+        _ => Paragraph::new(session.question().to_string()),
+    };
+    let source = session.chunk.as_ref().map(|chunk| format!(" Question · from {} ", chunk.topic)).unwrap_or_else(|| " Question ".to_string());
+    frame.render_widget(question.wrap(Wrap { trim: false }).block(Block::default().borders(Borders::ALL).border_style(dim).title(source)), layout[1]);
+
+    let answer_style = if session.stage == Stage::Answering { Style::default().fg(Color::Yellow) } else { dim };
+    let cursor = if session.stage == Stage::Answering { "▏" } else { "" };
+    frame.render_widget(
+        Paragraph::new(format!("{}{}", session.answer, cursor))
+            .wrap(Wrap { trim: false })
+            .block(Block::default().borders(Borders::ALL).border_style(answer_style).title(" Your answer ")),
+        layout[2],
+    );
+
+    let mut lines = Vec::new();
+    if let Some(error) = &session.error {
+        lines.push(Line::from(Span::styled(format!("✗ {}", error), Style::default().fg(Color::Red))));
+    }
+    match session.stage {
+        Stage::Judging => {
+            let visible = super::visible_answer(&session.evaluation);
+            if visible.trim().is_empty() {
+                lines.push(Line::from(Span::styled(format!("{} Checking your answer against the source…", throbber), dim)));
+            }
+            lines.extend(visible.lines().map(|line| Line::from(line.to_string())));
+        }
+        Stage::Ready if session.error.is_none() => {
+            let (label, color) = match session.verdict {
+                Some(Verdict::Correct) => ("✓ Correct", Color::Green),
+                Some(Verdict::Partial) => ("◐ Partially correct", Color::Yellow),
+                Some(Verdict::Incorrect) => ("✗ Incorrect", Color::Red),
+                None => ("? No verdict given", Color::DarkGray),
+            };
+            lines.push(Line::from(Span::styled(label, Style::default().fg(color).add_modifier(Modifier::BOLD))));
+            lines.extend(session.evaluation.lines().map(|line| Line::from(line.to_string())));
+            if let Some(chunk) = &session.chunk {
+                lines.push(Line::from(""));
+                lines.push(Line::from(Span::styled(format!("Source: [{}]", chunk.id), Style::default().fg(Color::Cyan))));
+            }
+        }
+        _ => {}
+    }
+    frame.render_widget(
+        Paragraph::new(lines).wrap(Wrap { trim: false }).block(Block::default().borders(Borders::ALL).border_style(dim).title(" Evaluation ")),
+        layout[3],
+    );
+
+    let help = match session.stage {
+        Stage::Answering => "Type your answer │ Enter: Submit │ Esc: Leave",
+        Stage::Ready if session.chunk.is_some() => "Enter/N: Next question │ V: View source │ Esc: Leave",
+        Stage::Ready => "Enter/N: Next question │ Esc: Leave",
+        _ => "Esc: Leave",
+    };
+    frame.render_widget(Paragraph::new(help).style(dim).alignment(Alignment::Center), layout[4]);
+}
+
+fn draw_subjects(frame: &mut Frame, session: &mut StudySession, area: ratatui::layout::Rect) {
+    let layout = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(3), Constraint::Length(2)])
+        .margin(1)
+        .split(area);
+
+    if session.subjects.is_empty() {
+        frame.render_widget(Paragraph::new("No indexed subjects yet. Run a sync first.").alignment(Alignment::Center), layout[0]);
+    } else {
+        let items: Vec<ListItem> = session.subjects.iter().map(|subject| {
+            let study = session.log.subject(&subject.id);
+            ListItem::new(Line::from(vec![
+                Span::styled(subject.label().to_string(), Style::default().add_modifier(Modifier::BOLD)),
+                Span::styled(
+                    format!("  {} asked · {} correct · {} topics", study.asked, study.correct, study.topics.len()),
+                    Style::default().fg(Color::DarkGray),
+                ),
+            ]))
+        }).collect();
+        let list = List::new(items)
+            .block(Block::default().borders(Borders::ALL).border_style(Style::default().fg(Color::DarkGray)).title(" Pick a subject "))
+            .highlight_style(Style::default().bg(Color::DarkGray));
+        frame.render_stateful_widget(list, layout[0], &mut session.subjects_state);
+    }
+
+    frame.render_widget(
+        Paragraph::new("↑/↓: Select | Enter: Start | Esc: Back").style(Style::default().fg(Color::DarkGray)).alignment(Alignment::Center),
+        layout[1],
+    );
+}
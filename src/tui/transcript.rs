@@ -0,0 +1,64 @@
+//! Messages of the chat transcript as the TUI keeps them: the message the model sees, plus
+//! what is only needed to show it. None of it is saved or sent.
+
+use std::ops::{Deref, DerefMut};
+
+use ratatui::text::Line;
+
+use crate::llm::ChatMessage;
+use crate::rag::citations::CitationMap;
+
+/// Lines rendered for a message and the width they were wrapped at
+#[derive(Clone, Default, Debug)]
+pub struct RenderCache {
+    /// Width, lines and their height
+    pub inner: Option<(usize, Vec<Line<'static>>, usize)>,
+}
+
+/// A message of the transcript. It derefs to its `ChatMessage`, so the role and content
+/// read as before.
+#[derive(Clone, Debug)]
+pub struct TranscriptMessage {
+    pub message: ChatMessage,
+    pub thinking_collapsed: bool,
+    pub render_cache: RenderCache,
+    /// Numbered sources behind an assistant answer
+    pub citations: Option<CitationMap>,
+    /// Id of the request streaming into this message
+    pub request_id: Option<u64>,
+    /// Question re-asked with relaxed retrieval, labeled in the chat
+    pub expanded_context: bool,
+    /// Label and score of each snippet retrieved for an assistant answer
+    pub sources: Vec<(String, f32)>,
+    /// Whether the retrieved sources are listed under the answer
+    pub sources_expanded: bool,
+}
+
+impl TranscriptMessage {
+    pub fn new(role: &str, content: impl Into<String>) -> Self {
+        Self {
+            message: ChatMessage::new(role, content),
+            thinking_collapsed: false,
+            render_cache: RenderCache::default(),
+            citations: None,
+            request_id: None,
+            expanded_context: false,
+            sources: Vec::new(),
+            sources_expanded: false,
+        }
+    }
+}
+
+impl Deref for TranscriptMessage {
+    type Target = ChatMessage;
+
+    fn deref(&self) -> &ChatMessage {
+        &self.message
+    }
+}
+
+impl DerefMut for TranscriptMessage {
+    fn deref_mut(&mut self) -> &mut ChatMessage {
+        &mut self.message
+    }
+}
@@ -0,0 +1,199 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use crate::config::{BudgetConfig, BudgetPeriod, Config};
+
+const LEDGER_FILE: &str = "usage.jsonl";
+/// Prompt size assumed before the first answer reports real usage: a full context of retrieved files
+const FIRST_REQUEST_PROMPT_TOKENS: u64 = 50_000;
+/// Answer length assumed when estimating a request
+const ESTIMATED_COMPLETION_TOKENS: u64 = 1_000;
+
+/// Model prices in USD per token
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Pricing {
+    pub prompt: f64,
+    pub completion: f64,
+}
+
+impl Pricing {
+    /// OpenRouter sends prices as decimal strings ("0.000003")
+    pub fn from_model(pricing: &crate::llm::ModelPricing) -> Option<Self> {
+        Some(Self {
+            prompt: pricing.prompt.parse().ok()?,
+            completion: pricing.completion.parse().ok()?,
+        })
+    }
+
+    pub fn cost(&self, prompt_tokens: u64, completion_tokens: u64) -> f64 {
+        prompt_tokens as f64 * self.prompt + completion_tokens as f64 * self.completion
+    }
+}
+
+/// One request in the ledger
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UsageEntry {
+    /// YYYY-MM-DD (UTC)
+    pub day: String,
+    pub model: String,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    /// 0 when the model's price was unknown
+    pub cost_usd: f64,
+}
+
+/// Tokens and dollars spent in a period
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Spend {
+    pub tokens: u64,
+    pub usd: f64,
+}
+
+impl Spend {
+    pub fn add(&mut self, entry: &UsageEntry) {
+        self.tokens += entry.prompt_tokens + entry.completion_tokens;
+        self.usd += entry.cost_usd;
+    }
+}
+
+/// Per-day totals for `polirag usage`
+#[derive(Clone, Debug, Default)]
+pub struct DayTotal {
+    pub requests: usize,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub usd: f64,
+}
+
+pub fn today() -> String {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    crate::scrapper::processing::format_date(secs / 86_400)
+}
+
+impl UsageEntry {
+    pub fn new(model: &str, usage: &crate::llm::Usage, pricing: Option<Pricing>) -> Self {
+        let prompt_tokens = usage.prompt_tokens as u64;
+        let completion_tokens = usage.completion_tokens as u64;
+        Self {
+            day: today(),
+            model: model.to_string(),
+            prompt_tokens,
+            completion_tokens,
+            cost_usd: pricing.map(|p| p.cost(prompt_tokens, completion_tokens)).unwrap_or(0.0),
+        }
+    }
+
+    fn in_period(&self, period: BudgetPeriod, today: &str) -> bool {
+        match period {
+            BudgetPeriod::Daily => self.day == today,
+            // Same "YYYY-MM" prefix
+            BudgetPeriod::Monthly => self.day.get(..7) == today.get(..7),
+        }
+    }
+}
+
+/// Append one entry to the ledger. A single write of one line, so a crash can at most
+/// leave a truncated last line, which `load` skips.
+pub fn record(entry: &UsageEntry) -> anyhow::Result<()> {
+    let path = Config::get_app_data_dir().join(LEDGER_FILE);
+    let mut line = serde_json::to_string(entry)?;
+    line.push('\n');
+    let mut file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+/// Every readable ledger entry, oldest first
+pub fn load() -> Vec<UsageEntry> {
+    let path = Config::get_app_data_dir().join(LEDGER_FILE);
+    let Ok(contents) = std::fs::read_to_string(path) else { return Vec::new() };
+    contents.lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect()
+}
+
+/// What the entries spent in the current day or month
+pub fn period_spend(entries: &[UsageEntry], period: BudgetPeriod) -> Spend {
+    let today = today();
+    let mut spend = Spend::default();
+    for entry in entries.iter().filter(|e| e.in_period(period, &today)) {
+        spend.add(entry);
+    }
+    spend
+}
+
+pub fn daily_totals(entries: &[UsageEntry]) -> BTreeMap<String, DayTotal> {
+    let mut days: BTreeMap<String, DayTotal> = BTreeMap::new();
+    for entry in entries {
+        let total = days.entry(entry.day.clone()).or_default();
+        total.requests += 1;
+        total.prompt_tokens += entry.prompt_tokens;
+        total.completion_tokens += entry.completion_tokens;
+        total.usd += entry.cost_usd;
+    }
+    days
+}
+
+/// Rough cost of the next request. The prompt is assumed as large as the last one
+/// (history plus retrieved context), or a full context when nothing was sent yet.
+pub fn estimate_request(last_prompt_tokens: Option<u64>, input_chars: usize, pricing: Option<Pricing>) -> Spend {
+    let prompt = last_prompt_tokens.unwrap_or(FIRST_REQUEST_PROMPT_TOKENS) + input_chars as u64 / 4;
+    Spend {
+        tokens: prompt + ESTIMATED_COMPLETION_TOKENS,
+        usd: pricing.map(|p| p.cost(prompt, ESTIMATED_COMPLETION_TOKENS)).unwrap_or(0.0),
+    }
+}
+
+/// "$0.42/$5.00 this month" (or tokens), for the chat footer
+pub fn describe_spend(spend: &Spend, budget: &BudgetConfig) -> String {
+    let amount = match (budget.max_usd, budget.max_tokens) {
+        (Some(max_usd), _) => format!("${:.2}/${:.2}", spend.usd, max_usd),
+        (None, Some(max_tokens)) => format!("{}/{} tokens", spend.tokens, max_tokens),
+        (None, None) => format!("${:.2}", spend.usd),
+    };
+    format!("{} {}", amount, budget.period.label())
+}
+
+/// Why `estimate` does not fit in what is left of the budget, if it doesn't
+pub fn over_budget(spend: &Spend, estimate: &Spend, budget: &BudgetConfig) -> Option<String> {
+    if let Some(max_usd) = budget.max_usd {
+        if spend.usd + estimate.usd > max_usd {
+            return Some(format!("~${:.3} with ${:.3} left {}", estimate.usd, (max_usd - spend.usd).max(0.0), budget.period.label()));
+        }
+    }
+    if let Some(max_tokens) = budget.max_tokens {
+        if spend.tokens + estimate.tokens > max_tokens {
+            return Some(format!("~{} tokens with {} left {}", estimate.tokens, max_tokens.saturating_sub(spend.tokens), budget.period.label()));
+        }
+    }
+    None
+}
+
+/// `polirag usage`: the ledger as a table, one row per day
+pub fn print_ledger() {
+    let entries = load();
+    if entries.is_empty() {
+        println!("No OpenRouter usage recorded yet.");
+        return;
+    }
+
+    println!("{:<12} {:>8} {:>12} {:>12} {:>10}", "Day", "Requests", "Prompt", "Completion", "Cost");
+    let mut grand_total = DayTotal::default();
+    for (day, total) in daily_totals(&entries) {
+        println!("{:<12} {:>8} {:>12} {:>12} {:>10}", day, total.requests, total.prompt_tokens, total.completion_tokens, format!("${:.4}", total.usd));
+        grand_total.requests += total.requests;
+        grand_total.prompt_tokens += total.prompt_tokens;
+        grand_total.completion_tokens += total.completion_tokens;
+        grand_total.usd += total.usd;
+    }
+    println!("{:<12} {:>8} {:>12} {:>12} {:>10}", "Total", grand_total.requests, grand_total.prompt_tokens, grand_total.completion_tokens, format!("${:.4}", grand_total.usd));
+
+    let budget = Config::load().budget;
+    if budget.is_set() {
+        println!("\nBudget: {}", describe_spend(&period_spend(&entries, budget.period), &budget));
+    }
+}
@@ -0,0 +1,65 @@
+//! The chat client against a mock OpenAI-compatible server that, like LM Studio, refuses
+//! requests while it loads the model.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use axum::Router;
+use futures::StreamExt;
+use polirag::llm::{self, ChatMessage, LlmClient, StreamEvent};
+
+/// Requests the server answers with "Model is loading" before it streams
+struct Server {
+    loading_for: usize,
+    requests: AtomicUsize,
+}
+
+async fn completions(State(server): State<Arc<Server>>) -> Response {
+    if server.requests.fetch_add(1, Ordering::SeqCst) < server.loading_for {
+        return (StatusCode::BAD_REQUEST, r#"{"error":"Model is loading"}"#).into_response();
+    }
+    let body = [
+        r#"data: {"choices":[{"delta":{"content":"Exam on "}}]}"#,
+        r#"data: {"choices":[{"delta":{"content":"June 3"}}]}"#,
+        "data: [DONE]",
+    ].map(|line| format!("{}\n\n", line)).concat();
+    ([("content-type", "text/event-stream")], body).into_response()
+}
+
+/// Serve the mock on a free port, returning its base URL
+async fn serve(loading_for: usize) -> (Arc<Server>, String) {
+    let server = Arc::new(Server { loading_for, requests: AtomicUsize::new(0) });
+    let app = Router::new().route("/v1/chat/completions", post(completions)).with_state(server.clone());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    (server, format!("http://127.0.0.1:{}/v1", port))
+}
+
+async fn answer(client: &LlmClient) -> anyhow::Result<String> {
+    let mut stream = client.chat_stream(&[ChatMessage::new("user", "When is the exam?")]).await?;
+    let mut answer = String::new();
+    while let Some(event) = stream.next().await {
+        if let StreamEvent::Content(chunk) = event? {
+            answer.push_str(&chunk);
+        }
+    }
+    Ok(answer)
+}
+
+#[tokio::test]
+async fn a_loading_model_fails_as_retryable_until_it_streams() {
+    let (server, base_url) = serve(2).await;
+    let client = LlmClient::new(Some(base_url), Some("qwen3-8b".to_string()), None);
+
+    for _ in 0..2 {
+        let error = answer(&client).await.unwrap_err();
+        assert!(llm::is_model_loading(&error), "{:#}", error);
+    }
+    assert_eq!(answer(&client).await.unwrap(), "Exam on June 3");
+    assert_eq!(server.requests.load(Ordering::SeqCst), 3);
+}
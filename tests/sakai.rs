@@ -0,0 +1,198 @@
+//! The scraper's HTTP side against a mock Sakai server. The server serves the PoliformaT pages
+//! in `tests/fixtures/sakai` behind a session cookie, sending anonymous visitors to the gateway
+//! or the login page the way the portal does.
+
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::response::{Html, IntoResponse, Redirect, Response};
+use axum::routing::get;
+use axum::Router;
+use polirag::config::DownloadLimits;
+use polirag::scrapper::{self, pages, PoliformatClient, SyncMode};
+use url::Url;
+
+const PORTAL: &str = include_str!("fixtures/sakai/portal.html");
+const GATEWAY: &str = include_str!("fixtures/sakai/gateway.html");
+const LOGIN: &str = include_str!("fixtures/sakai/login.html");
+const RESOURCES: &str = include_str!("fixtures/sakai/resources.html");
+
+/// Sessions of the mock server
+#[derive(Default)]
+struct Sakai {
+    sessions: Mutex<HashSet<String>>,
+    logins: AtomicUsize,
+}
+
+impl Sakai {
+    /// Open a session, returning the cookies a browser login leaves behind
+    fn login(&self) -> String {
+        let n = self.logins.fetch_add(1, Ordering::SeqCst);
+        let session = format!("session-{}", n);
+        self.sessions.lock().unwrap().insert(session.clone());
+        format!("JSESSIONID={}; SAKAI_LOCALE=ca_ES", session)
+    }
+
+    /// Expire every session, as the portal does after a while
+    fn expire_sessions(&self) {
+        self.sessions.lock().unwrap().clear();
+    }
+
+    fn authenticated(&self, headers: &HeaderMap) -> bool {
+        let sessions = self.sessions.lock().unwrap();
+        headers.get_all("cookie").iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(';'))
+            .filter_map(|pair| pair.trim().strip_prefix("JSESSIONID="))
+            .any(|session| sessions.contains(session))
+    }
+}
+
+async fn portal(State(sakai): State<Arc<Sakai>>, headers: HeaderMap) -> Html<&'static str> {
+    Html(if sakai.authenticated(&headers) { PORTAL } else { GATEWAY })
+}
+
+async fn resources(State(sakai): State<Arc<Sakai>>, headers: HeaderMap) -> Response {
+    if sakai.authenticated(&headers) {
+        Html(RESOURCES).into_response()
+    } else {
+        Redirect::to("/cas/login?service=portal").into_response()
+    }
+}
+
+/// Serve the mock on a free port, returning its URL
+async fn serve(sakai: Arc<Sakai>) -> Url {
+    let app = Router::new()
+        .route("/", get(|| async { Redirect::to("/portal") }))
+        .route("/portal", get(portal))
+        .route("/portal/login", get(|| async { Html(LOGIN) }))
+        .route("/cas/login", get(|| async { Html(LOGIN) }))
+        .route("/portal/site/:site/tool/resources", get(resources))
+        .with_state(sakai);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    Url::parse(&format!("http://127.0.0.1:{}", port)).unwrap()
+}
+
+fn resources_url(base_url: &Url) -> String {
+    scrapper::portal_url(base_url, "/portal/site/GRA_11673_2025/tool/resources")
+}
+
+#[tokio::test]
+async fn the_authenticated_portal_lists_each_subject_once() {
+    let sakai = Arc::new(Sakai::default());
+    let base_url = serve(sakai.clone()).await;
+    let client = PoliformatClient::with_base_url(base_url.clone());
+    client.import_cookies(&sakai.login());
+
+    let subjects = client.fetch_subjects().await.unwrap();
+    let listed: Vec<(&str, &str)> = subjects.iter().map(|s| (s.id.as_str(), s.name.as_str())).collect();
+    // Workspace, gateway, tool, language and duplicate links are left out; sorted by name
+    assert_eq!(listed, [("GRA_11680_2025", "Física"), ("ESP_20411_2025", "Treball Fi de Grau"), ("GRA_11673_2025", "Àlgebra")]);
+    assert_eq!(subjects[2].url, scrapper::portal_url(&base_url, "/portal/site/GRA_11673_2025"));
+}
+
+#[tokio::test]
+async fn redirects_to_the_login_are_detected() {
+    let sakai = Arc::new(Sakai::default());
+    let base_url = serve(sakai.clone()).await;
+    let client = PoliformatClient::with_base_url(base_url.clone());
+
+    // The portal answers anonymous visitors with its gateway page, not a redirect
+    assert!(!client.check_connection().await.unwrap());
+    assert!(client.fetch_subjects().await.is_err());
+    // A subject's tools redirect to the single sign-on
+    let page = client.fetch_page(&resources_url(&base_url)).await.unwrap();
+    assert_eq!(page.url.path(), "/cas/login");
+    assert!(page.requires_login());
+
+    client.import_cookies(&sakai.login());
+    assert!(client.check_connection().await.unwrap());
+    let page = client.fetch_page(&resources_url(&base_url)).await.unwrap();
+    assert!(!page.requires_login());
+}
+
+#[tokio::test]
+async fn downloads_follow_the_listing_the_limits_and_the_folder() {
+    let sakai = Arc::new(Sakai::default());
+    let base_url = serve(sakai.clone()).await;
+    let client = PoliformatClient::with_base_url(base_url.clone());
+    client.import_cookies(&sakai.login());
+
+    let page = client.fetch_page(&resources_url(&base_url)).await.unwrap();
+    let listing = pages::resource_listing(&page.html);
+    let names: Vec<&str> = listing.iter().map(|e| e.name.as_str()).collect();
+    // Only rows with a selection checkbox can be downloaded
+    assert_eq!(names, ["Teoria", "tema1.pdf", "practica1.zip", "classe.mp4"]);
+    assert_eq!(listing[1].size, "1,5 MB");
+
+    let capped = DownloadLimits { max_subject_mb: Some(100), ..Default::default() };
+    let (kept, skipped) = scrapper::plan_resources(&listing, &capped);
+    assert_eq!(kept.len(), 3);
+    assert_eq!(skipped, [(listing[3].clone(), "size limit reached")]);
+
+    let pdfs = DownloadLimits { extensions: vec!["pdf".to_string()], ..Default::default() };
+    let (kept, skipped) = scrapper::plan_resources(&listing, &pdfs);
+    // The folder is kept and filtered when its zip is unpacked
+    assert_eq!(kept, &listing[..2]);
+    assert!(skipped.iter().all(|(_, reason)| *reason == "type not in the allowlist"));
+
+    // A full sync only fills an (almost) empty resources folder
+    let dir = tempfile::tempdir().unwrap();
+    let subject = dir.path().join("GRA_11673_2025");
+    assert!(scrapper::resources_wanted(&subject, SyncMode::Full));
+    std::fs::create_dir_all(subject.join("resources")).unwrap();
+    std::fs::write(subject.join("resources/tema1.pdf"), b"%PDF").unwrap();
+    assert!(scrapper::resources_wanted(&subject, SyncMode::Full));
+    std::fs::write(subject.join("resources/practica1.zip"), b"PK").unwrap();
+    assert!(!scrapper::resources_wanted(&subject, SyncMode::Full));
+    assert!(scrapper::resources_wanted(&subject, SyncMode::ResourcesOnly));
+
+    // Archives `polirag clean` removed after extracting them aren't downloaded again
+    std::fs::remove_file(subject.join("resources/practica1.zip")).unwrap();
+    scrapper::layout::record_cleaned(&subject, [("resources/practica1.zip".to_string(), 2)], 0).unwrap();
+    assert!(!scrapper::resources_wanted(&subject, SyncMode::Full));
+}
+
+#[tokio::test]
+async fn an_expired_session_logs_in_again() {
+    let sakai = Arc::new(Sakai::default());
+    let base_url = serve(sakai.clone()).await;
+    let client = PoliformatClient::with_base_url(base_url.clone());
+    client.import_cookies(&sakai.login());
+    assert!(client.check_connection().await.unwrap());
+
+    sakai.expire_sessions();
+    assert!(!client.check_connection().await.unwrap());
+
+    let login = sakai.clone();
+    let expected = base_url.clone();
+    client.relogin(move |base_url| {
+        assert_eq!(base_url, &expected);
+        Ok(login.login())
+    }).await.unwrap();
+    assert!(client.check_connection().await.unwrap());
+    assert_eq!(client.fetch_subjects().await.unwrap().len(), 3);
+    assert_eq!(sakai.logins.load(Ordering::SeqCst), 2);
+
+    // Cookies that don't open a session are a rejected login
+    sakai.expire_sessions();
+    let error = client.relogin(|_| Ok("JSESSIONID=stale".to_string())).await.unwrap_err();
+    assert!(matches!(error.downcast_ref(), Some(scrapper::auth::LoginError::Rejected(_))));
+}
+
+#[tokio::test]
+async fn session_cookies_are_set_for_the_host_of_the_base_url() {
+    let sakai = Arc::new(Sakai::default());
+    let mut base_url = serve(sakai.clone()).await;
+    // A host name rather than an address, so the cookies get a domain
+    base_url.set_host(Some("localhost")).unwrap();
+    let client = PoliformatClient::with_base_url(base_url);
+
+    client.import_cookies(&sakai.login());
+    assert!(client.check_connection().await.unwrap());
+}